@@ -85,6 +85,8 @@ static int parse_secret_attributes(ErlNifEnv *env, ERL_NIF_TERM arg, ockam_vault
     }
 
     attributes->length = length;
+    attributes->exportable = 1;
+    attributes->usage = OCKAM_VAULT_SECRET_USAGE_ALL;
 
     return 0;
 }
@@ -153,11 +153,17 @@ defmodule Ockam.Vault.Software.Tests do
 
       {:ok, dh} = SoftwareVault.ecdh(handle, secret1, public2)
 
-      {:ok, dh_data} = SoftwareVault.secret_export(handle, dh)
+      # the shared secret can't be exported, check a key derived from it
+      {:error, _} = SoftwareVault.secret_export(handle, dh)
 
-      assert dh_data ==
-               <<174, 139, 240, 140, 226, 187, 236, 169, 59, 89, 38, 171, 165, 29, 32, 47, 148,
-                 161, 218, 139, 246, 23, 131, 164, 6, 109, 155, 8, 203, 90, 153, 38>>
+      buffer = %{type: :buffer, persistence: :ephemeral, length: 32}
+      {:ok, salt} = SoftwareVault.secret_import(handle, buffer, <<0::256>>)
+      {:ok, [derived]} = SoftwareVault.hkdf_sha256(handle, salt, dh, [buffer])
+      {:ok, derived_data} = SoftwareVault.secret_export(handle, derived)
+
+      assert derived_data ==
+               <<11, 13, 131, 42, 246, 200, 208, 186, 149, 7, 242, 65, 162, 31, 24, 193, 57, 63,
+                 10, 110, 140, 63, 97, 12, 154, 134, 236, 71, 132, 64, 218, 234>>
     end
   end
 
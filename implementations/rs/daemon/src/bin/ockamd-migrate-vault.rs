@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use ockam_vault_file::{FilesystemVault, StorageKey, KEK_ENV_VAR, PASSPHRASE_ENV_VAR};
+use structopt::StructOpt;
+
+/// Command-line arguments passed to `ockamd-migrate-vault`.
+#[derive(StructOpt)]
+#[structopt(
+    author = "Ockam Developers (ockam.io)",
+    about = "Encrypt the plaintext key files of an `ockamd` filesystem vault. The storage key is read from OCKAM_VAULT_KEK (hex encoded, 32 bytes) or OCKAM_VAULT_PASSPHRASE."
+)]
+struct Args {
+    /// Path on disk where the vault data is stored.
+    #[structopt(
+        parse(from_os_str),
+        long,
+        default_value = "ockamd_vault",
+        help = "Filepath on disk to the filesystem vault to encrypt"
+    )]
+    vault_path: PathBuf,
+}
+
+fn main() {
+    let args = Args::from_args();
+
+    let storage_key = match StorageKey::from_env() {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            println!(
+                "Set {} or {} to migrate the vault",
+                KEK_ENV_VAR, PASSPHRASE_ENV_VAR
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("Invalid vault storage key: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match FilesystemVault::migrate(args.vault_path, storage_key) {
+        Ok(n) => println!("Encrypted {} key files", n),
+        Err(e) => {
+            println!("Failed to migrate vault: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use ockam_vault_file::error::Error;
+use ockam_vault_file::ockam_vault::{PersistentVault, Secret};
+use ockam_vault_file::{ConflictPolicy, FilesystemVault, StorageKey, BACKUP_PASSPHRASE_ENV_VAR};
+use structopt::StructOpt;
+
+/// Command-line arguments passed to `ockamd-vault-keys`.
+#[derive(StructOpt)]
+#[structopt(
+    author = "Ockam Developers (ockam.io)",
+    about = "List and name the keys in an `ockamd` filesystem vault, and back them up. An encrypted vault is opened with the storage key from OCKAM_VAULT_KEK or OCKAM_VAULT_PASSPHRASE, backups are sealed with the passphrase from OCKAM_BACKUP_PASSPHRASE."
+)]
+struct Args {
+    /// Path on disk where the vault data is stored.
+    #[structopt(
+        parse(from_os_str),
+        long,
+        default_value = "ockamd_vault",
+        help = "Filepath on disk to the filesystem vault"
+    )]
+    vault_path: PathBuf,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// List the keys with their labels, creation times, tags and public keys
+    List,
+    /// Set the label of a key, or remove it if no label is given
+    Label {
+        /// Persistence id or current label of the key
+        key: String,
+        label: Option<String>,
+    },
+    /// Replace the tags of a key
+    Tag {
+        /// Persistence id or label of the key
+        key: String,
+        tags: Vec<String>,
+    },
+    /// Write all persistent keys with their metadata to an encrypted backup file
+    Backup {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Restore the keys from a backup file into the vault
+    Restore {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// What to do with a key whose id or label is in use: abort, skip or keep-both
+        #[structopt(long, default_value = "abort", parse(try_from_str = parse_conflict_policy))]
+        on_conflict: ConflictPolicy,
+    },
+}
+
+fn parse_conflict_policy(s: &str) -> Result<ConflictPolicy, String> {
+    match s {
+        "abort" => Ok(ConflictPolicy::Abort),
+        "skip" => Ok(ConflictPolicy::Skip),
+        "keep-both" => Ok(ConflictPolicy::KeepBoth),
+        _ => Err(format!("unknown conflict policy {}", s)),
+    }
+}
+
+fn backup_key() -> StorageKey {
+    match std::env::var(BACKUP_PASSPHRASE_ENV_VAR) {
+        Ok(passphrase) if !passphrase.is_empty() => StorageKey::passphrase(&passphrase),
+        _ => {
+            println!("Set {} to the backup passphrase", BACKUP_PASSPHRASE_ENV_VAR);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn find(vault: &FilesystemVault, key: &str) -> Box<dyn Secret> {
+    match vault
+        .get_persistent_secret(key)
+        .or_else(|_| vault.get_persistent_secret_by_label(key))
+    {
+        Ok(secret) => secret,
+        Err(_) => {
+            println!("No key {} in the vault", key);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args = Args::from_args();
+
+    let vault = match StorageKey::from_env() {
+        Ok(Some(storage_key)) => FilesystemVault::new_encrypted(args.vault_path, storage_key),
+        Ok(None) => FilesystemVault::new(args.vault_path),
+        Err(e) => {
+            println!("Invalid vault storage key: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let vault = match vault {
+        Ok(vault) => vault,
+        Err(e) => {
+            println!("Failed to open vault: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let result = match args.command {
+        Command::List => vault.list_persistent_secrets().map(|secrets| {
+            for s in secrets {
+                println!(
+                    "{}\t{}\t{:?}\t{}\t{}\t{}",
+                    s.persistence_id,
+                    s.metadata.label.unwrap_or_default(),
+                    s.attributes.stype,
+                    s.metadata.created_at,
+                    s.metadata.tags.join(","),
+                    s.public_key
+                        .map(|k| hex::encode(k.as_ref()))
+                        .unwrap_or_default()
+                );
+            }
+        }),
+        Command::Label { key, label } => {
+            let secret = find(&vault, &key);
+            vault.set_secret_label(secret.as_ref(), label.as_deref())
+        }
+        Command::Tag { key, tags } => {
+            let secret = find(&vault, &key);
+            vault.set_secret_tags(secret.as_ref(), tags)
+        }
+        Command::Backup { file } => vault
+            .backup(backup_key())
+            .and_then(|bundle| std::fs::write(&file, bundle).map_err(|_| Error::IOError.into()))
+            .map(|_| println!("Wrote backup to {:?}", file)),
+        Command::Restore { file, on_conflict } => std::fs::read(&file)
+            .map_err(|_| Error::IOError.into())
+            .and_then(|bundle| vault.restore(&bundle, backup_key(), on_conflict))
+            .map(|report| {
+                for (from, to) in report.restored {
+                    println!("restored {} as {}", from, to);
+                }
+                for id in report.unlabelled {
+                    println!("restored {} without its label, the label is in use", id);
+                }
+                for id in report.skipped {
+                    println!("skipped {}, its id or label is in use", id);
+                }
+            }),
+    };
+    if let Err(e) = result {
+        println!("Failed to access vault: {}", e);
+        std::process::exit(1);
+    }
+}
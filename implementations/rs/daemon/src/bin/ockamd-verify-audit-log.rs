@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use ockam_vault_audit::{verify_log, AuditHead};
+use structopt::StructOpt;
+
+/// Command-line arguments passed to `ockamd-verify-audit-log`.
+#[derive(StructOpt)]
+#[structopt(
+    author = "Ockam Developers (ockam.io)",
+    about = "Check that an `ockamd` audit log is complete and unmodified, and print its records."
+)]
+struct Args {
+    /// Path on disk to the audit log.
+    #[structopt(parse(from_os_str))]
+    audit_log: PathBuf,
+
+    /// Record count of a checkpoint from an earlier run or from `ockamd`'s output,
+    /// detects records removed from the end of the log. 0 the first time a log is checked.
+    #[structopt(long)]
+    records: u64,
+
+    /// Hex encoded hash of the last record of the checkpoint, required unless `--records`
+    /// is 0
+    #[structopt(long)]
+    hash: Option<String>,
+}
+
+fn main() {
+    let args = Args::from_args();
+
+    let mut checkpoint = AuditHead {
+        records: args.records,
+        ..Default::default()
+    };
+    match (args.records, args.hash) {
+        (0, None) => {}
+        (_, Some(hash)) => match hex::decode(hash) {
+            Ok(hash) if hash.len() == checkpoint.hash.len() => {
+                checkpoint.hash.copy_from_slice(&hash)
+            }
+            _ => {
+                println!("The hash must be 32 hex encoded bytes");
+                std::process::exit(1);
+            }
+        },
+        (_, None) => {
+            println!("A checkpoint needs the hash of its last record");
+            std::process::exit(1);
+        }
+    }
+
+    match verify_log(&args.audit_log, &checkpoint) {
+        Ok(records) => {
+            for r in &records {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    r.sequence,
+                    r.timestamp,
+                    r.caller,
+                    r.operation,
+                    r.secret.as_deref().unwrap_or_default()
+                );
+            }
+            match records.last() {
+                Some(last) => println!(
+                    "The log is intact, checkpoint: --records {} --hash {}",
+                    last.sequence + 1,
+                    hex::encode(last.hash)
+                ),
+                None => println!("The log is empty"),
+            }
+        }
+        Err(e) => {
+            println!("The audit log failed to verify: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
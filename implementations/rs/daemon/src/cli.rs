@@ -69,6 +69,23 @@ pub struct Args {
     )]
     vault_path: PathBuf,
 
+    /// Path to the PKCS#11 module (used with the PKCS11 vault).
+    #[structopt(
+        parse(from_os_str),
+        long,
+        required_if("vault", "PKCS11"),
+        help = "Path to the PKCS#11 module of the token. The user PIN is read from OCKAM_PKCS11_PIN"
+    )]
+    pkcs11_module: Option<PathBuf>,
+
+    /// Label of the PKCS#11 token holding the keys (used with the PKCS11 vault).
+    #[structopt(
+        long,
+        default_value = "ockamd",
+        help = "Label of the PKCS#11 token holding the keys"
+    )]
+    pkcs11_token: String,
+
     /// Start the `ockamd` process as the initiator or responder of a secure channel.
     #[structopt(
         long,
@@ -81,7 +98,7 @@ pub struct Args {
     #[structopt(
         long,
         default_value = FILENAME_KEY_DEFAULT,
-        help = "Name of the private key to use for the identity of the channel initiator"
+        help = "Persistence id or label of the private key to use for the identity of the channel initiator"
     )]
     identity_name: String,
 
@@ -97,6 +114,30 @@ pub struct Args {
     #[structopt(long, help = "The public key provided by the hub service")]
     public_key_hub: Option<String>,
 
+    /// Path on disk to a known-keys file, used to trust peers on first use.
+    #[structopt(
+        parse(from_os_str),
+        long,
+        help = "Known-keys file, peers are trusted on first use and must keep the same key afterwards"
+    )]
+    known_keys: Option<PathBuf>,
+
+    /// Name of the peer whose key is pinned in the known-keys file.
+    #[structopt(
+        long,
+        default_value = "peer",
+        help = "Name of the peer in the known-keys file, the first key it presents is pinned"
+    )]
+    known_peer: String,
+
+    /// Path on disk to the audit log of vault operations.
+    #[structopt(
+        parse(from_os_str),
+        long,
+        help = "Audit log file, every use of a persistent key in the vault is appended to it as a hash chained record"
+    )]
+    audit_log: Option<PathBuf>,
+
     /// Address used to reach the service on remote machine.
     #[structopt(
         long,
@@ -141,11 +182,16 @@ impl Default for Args {
             local_socket: SocketAddr::from_str(DEFAULT_LOCAL_SOCKET).expect("bad socket addr"),
             vault: VaultKind::Filesystem,
             vault_path: PathBuf::from("ockamd_vault"),
+            pkcs11_module: None,
+            pkcs11_token: "ockamd".into(),
             role: ChannelRole::Sink,
             service_address: None,
             identity_name: format!("1{}", FILENAME_KEY_SUFFIX),
             public_key_sink: None,
             public_key_hub: Some("default_key_vaule".into()),
+            known_keys: None,
+            known_peer: "peer".into(),
+            audit_log: None,
             addon: None,
         }
     }
@@ -195,10 +241,22 @@ impl Args {
     //     self.router_socket
     // }
 
+    pub fn vault(&self) -> VaultKind {
+        self.vault
+    }
+
     pub fn vault_path(&self) -> PathBuf {
         self.vault_path.clone()
     }
 
+    pub fn pkcs11_module(&self) -> Option<PathBuf> {
+        self.pkcs11_module.clone()
+    }
+
+    pub fn pkcs11_token(&self) -> String {
+        self.pkcs11_token.clone()
+    }
+
     pub fn public_key_sink(&self) -> Option<String> {
         self.public_key_sink.clone()
     }
@@ -207,6 +265,18 @@ impl Args {
         self.public_key_hub.clone()
     }
 
+    pub fn known_keys(&self) -> Option<PathBuf> {
+        self.known_keys.clone()
+    }
+
+    pub fn known_peer(&self) -> String {
+        self.known_peer.clone()
+    }
+
+    pub fn audit_log(&self) -> Option<PathBuf> {
+        self.audit_log.clone()
+    }
+
     pub fn service_address(&self) -> Option<String> {
         self.service_address.clone()
     }
@@ -247,8 +317,11 @@ impl FromStr for Addon {
 }
 
 /// Specifies the implementation of a Ockam vault to be used.
+#[derive(Clone, Copy, Debug)]
 pub enum VaultKind {
     Filesystem,
+    /// Keys live on a PKCS#11 token and use P-256, so both ends of a channel need it
+    Pkcs11,
 }
 
 impl FromStr for VaultKind {
@@ -257,7 +330,8 @@ impl FromStr for VaultKind {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "FILESYSTEM" => Ok(VaultKind::Filesystem),
-            _ => Err("currently, 'FILESYSTEM' is the only supported vault option".into()),
+            "PKCS11" => Ok(VaultKind::Pkcs11),
+            _ => Err("supported vault options are 'FILESYSTEM' and 'PKCS11'".into()),
         }
     }
 }
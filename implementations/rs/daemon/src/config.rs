@@ -17,6 +17,15 @@ pub enum Input {
     Stdin,
 }
 
+#[derive(Debug, Clone)]
+pub enum Vault {
+    Filesystem,
+    Pkcs11 {
+        module: PathBuf,
+        token_label: String,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub enum AddonKind {
     InfluxDb(url::Url, String),
@@ -31,10 +40,14 @@ pub struct Config {
     // router_socket: Option<SocketAddr>,
     // channel_to_sink: Option<String>,
     role: Role,
+    vault: Vault,
     vault_path: PathBuf,
     input_kind: Input,
     public_key_sink: Option<String>,
     public_key_hub: Option<String>,
+    known_keys: Option<PathBuf>,
+    known_peer: String,
+    audit_log: Option<PathBuf>,
     service_address: Option<String>,
     identity_name: String,
     addon: Option<AddonKind>,
@@ -47,6 +60,10 @@ impl Default for Config {
 }
 
 impl Config {
+    pub fn vault(&self) -> Vault {
+        self.vault.clone()
+    }
+
     pub fn vault_path(&self) -> PathBuf {
         self.vault_path.clone()
     }
@@ -83,6 +100,18 @@ impl Config {
         self.public_key_hub.clone()
     }
 
+    pub fn known_keys(&self) -> Option<PathBuf> {
+        self.known_keys.clone()
+    }
+
+    pub fn known_peer(&self) -> String {
+        self.known_peer.clone()
+    }
+
+    pub fn audit_log(&self) -> Option<PathBuf> {
+        self.audit_log.clone()
+    }
+
     pub fn role(&self) -> Role {
         self.role
     }
@@ -110,10 +139,14 @@ impl From<cli::Args> for Config {
             // channel_to_sink: args.channel_to_sink(),
             // router_socket: args.router_socket(),
             role: Role::Source,
+            vault: Vault::Filesystem,
             vault_path: args.vault_path(),
             input_kind: Input::Stdin,
             public_key_sink: args.public_key_sink(),
             public_key_hub: args.public_key_hub(),
+            known_keys: args.known_keys(),
+            known_peer: args.known_peer(),
+            audit_log: args.audit_log(),
             service_address: args.service_address(),
             identity_name: args.identity_name(),
             addon: if let Some(a) = args.addon() {
@@ -140,6 +173,16 @@ impl From<cli::Args> for Config {
             cli::ChannelRole::Router => Role::Router,
         };
 
+        cfg.vault = match args.vault() {
+            cli::VaultKind::Filesystem => Vault::Filesystem,
+            cli::VaultKind::Pkcs11 => Vault::Pkcs11 {
+                module: args
+                    .pkcs11_module()
+                    .expect("the PKCS11 vault needs a module path"),
+                token_label: args.pkcs11_token(),
+            },
+        };
+
         cfg.input_kind = match args.input_kind() {
             cli::InputKind::Stdin => Input::Stdin,
         };
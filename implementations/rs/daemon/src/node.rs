@@ -1,10 +1,10 @@
 use std::sync::mpsc::{self, Sender};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
 use std::time;
 
 use crate::cli;
-use crate::config::{Config, Role};
+use crate::config::{Config, Role, Vault};
 use crate::sink::SinkWorker;
 use crate::source::StdinWorker;
 
@@ -16,14 +16,17 @@ use ockam::kex::CipherSuite;
 use ockam::message::{Address, RouterAddress};
 use ockam::secure_channel::*;
 use ockam::system::commands::{OckamCommand, WorkerCommand};
-use ockam_kex_xx::{XXInitiator, XXNewKeyExchanger, XXResponder};
+use ockam_kex_xx::{XXInitiator, XXNewKeyExchanger, XXResponder, XXVault};
 use ockam_router::router::Router;
 use ockam_transport::tcp::TcpManager;
+use ockam_vault_audit::AuditVault;
 use ockam_vault_file::ockam_vault::types::*;
 use ockam_vault_file::ockam_vault::*;
-use ockam_vault_file::FilesystemVault;
+use ockam_vault_file::{FilesystemVault, StorageKey, FILENAME_KEY_SUFFIX};
+use ockam_vault_pkcs11::{Pkcs11Vault, PIN_ENV_VAR};
 use std::net::SocketAddr;
 use std::ops::Deref;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 pub enum OckamdWorker {
@@ -31,6 +34,15 @@ pub enum OckamdWorker {
     Sink(SinkWorker),
 }
 
+/// A vault for the key exchange, the same vault for the channels, the identity key in
+/// it and the cipher suite for its key type
+type NodeVault = (
+    Arc<dyn XXVault>,
+    Arc<dyn ChannelVault>,
+    Option<Arc<Box<dyn Secret>>>,
+    CipherSuite,
+);
+
 #[allow(dead_code)]
 pub struct Node<'a> {
     config: &'a Config,
@@ -54,7 +66,179 @@ pub fn get_console_line(wtx: Sender<OckamCommand>) {
     }
 }
 
+/// Poll the channel manager. A peer that fails a handshake or sends a bad message
+/// only affects its own channel, so the error is reported and the node keeps running.
+fn poll_channels(
+    chan_manager: &mut ChannelManager<XXInitiator, XXResponder, XXNewKeyExchanger>,
+) -> bool {
+    match chan_manager.poll() {
+        Ok(keep_going) => keep_going,
+        Err(e) => {
+            eprintln!("secure channel error: {}", e);
+            true
+        }
+    }
+}
+
 impl<'a> Node<'a> {
+    /// A source only accepts the sink key it was given, other roles pin the key of the
+    /// known peer in the known-keys file if one is configured and accept anyone
+    /// otherwise.
+    fn trust_policy(config: &Config) -> Box<dyn TrustPolicy> {
+        if let (Role::Source, Some(key)) = (config.role(), config.public_key_sink()) {
+            let key = hex::decode(key).expect("public key sink must be hex encoded");
+            return Box::new(AllowList::new(vec![PublicKey::new(key)]));
+        }
+        match config.known_keys() {
+            Some(path) => Box::new(
+                TrustOnFirstUse::new(path, &config.known_peer())
+                    .expect("failed to load known keys file"),
+            ),
+            None => Box::new(AcceptAll),
+        }
+    }
+
+    /// Open the FILESYSTEM vault and find or create the identity key. Secrets are
+    /// encrypted on disk if a storage key is set in the environment.
+    fn filesystem_vault(config: &Config) -> NodeVault {
+        let vault = match StorageKey::from_env().expect("invalid vault storage key") {
+            Some(storage_key) => FilesystemVault::new_encrypted(config.vault_path(), storage_key),
+            None => FilesystemVault::new(config.vault_path()),
+        }
+        .expect("failed to initialize vault");
+
+        // check for re-use of provided identity name from CLI args, if not in on-disk in vault
+        // generate a new one to be used. The name is either a persistence id or a label.
+
+        let identity_name = config.identity_name();
+        let identity = vault
+            .get_persistent_secret(&identity_name)
+            .or_else(|_| vault.get_persistent_secret_by_label(&identity_name));
+        let resp_key_ctx = match identity {
+            Ok(secret) => Some(secret),
+            Err(_) => {
+                // if responder, generate keypair and display static public key
+                if matches!(config.role(), Role::Sink) || matches!(config.role(), Role::Router) {
+                    let attributes = SecretAttributes {
+                        stype: SecretType::Curve25519,
+                        persistence: SecretPersistence::Persistent,
+                        length: CURVE25519_SECRET_LENGTH,
+                        exportable: false,
+                        usage: SecretUsage::ECDH,
+                    };
+                    let secret = vault
+                        .secret_generate(attributes)
+                        .expect("failed to generate secret");
+                    // a label names the new key, so it is found again on the next start
+                    if !identity_name.ends_with(FILENAME_KEY_SUFFIX) {
+                        vault
+                            .set_secret_label(secret.as_ref(), Some(&identity_name))
+                            .expect("failed to label secret");
+                    }
+                    Some(secret)
+                } else {
+                    None
+                }
+            }
+        };
+
+        Node::node_vault(
+            config,
+            vault,
+            resp_key_ctx,
+            CipherSuite::Curve25519AesGcmSha256,
+        )
+    }
+
+    /// Open the PKCS11 vault and find or create the identity key, which is labelled on
+    /// the token with the identity name. The token only holds P-256 keys. Fails without
+    /// the user PIN in the environment, or if the module can't run HKDF on the token, which
+    /// the key exchange of every channel needs.
+    fn pkcs11_vault(
+        config: &Config,
+        module: PathBuf,
+        token_label: &str,
+    ) -> Result<NodeVault, String> {
+        let pin = std::env::var(PIN_ENV_VAR)
+            .map_err(|_| format!("set {} to the user PIN of the token", PIN_ENV_VAR))?;
+        let vault = Pkcs11Vault::open(module, token_label, &pin)
+            .map_err(|e| format!("failed to open the PKCS11 token: {}", e))?;
+        if !vault.supports_hkdf() {
+            return Err(
+                "the PKCS11 module can't derive channel keys on the token, it needs the \
+                 CKM_HKDF_DERIVE and CKM_EXTRACT_KEY_FROM_KEY mechanisms"
+                    .into(),
+            );
+        }
+
+        let identity_name = config.identity_name();
+        let resp_key_ctx = match vault.find_secret_by_label(&identity_name) {
+            Ok(secret) => Some(secret),
+            Err(_) => {
+                if matches!(config.role(), Role::Sink) || matches!(config.role(), Role::Router) {
+                    let attributes = SecretAttributes {
+                        stype: SecretType::P256,
+                        persistence: SecretPersistence::Persistent,
+                        length: P256_SECRET_LENGTH,
+                        exportable: false,
+                        usage: SecretUsage::ECDH,
+                    };
+                    let secret = vault
+                        .secret_generate(attributes)
+                        .expect("failed to generate secret");
+                    vault
+                        .set_secret_label(secret.as_ref(), &identity_name)
+                        .expect("failed to label secret");
+                    Some(secret)
+                } else {
+                    None
+                }
+            }
+        };
+
+        Ok(Node::node_vault(
+            config,
+            vault,
+            resp_key_ctx,
+            CipherSuite::P256Aes128GcmSha256,
+        ))
+    }
+
+    /// Share the vault, wrapped in an `AuditVault` if an audit log is configured. The
+    /// identity key is named after the identity name in the log. The head of the log is
+    /// printed, keep it to check later that no records were removed.
+    fn node_vault<V: XXVault + 'static>(
+        config: &Config,
+        vault: V,
+        identity: Option<Box<dyn Secret>>,
+        cipher_suite: CipherSuite,
+    ) -> NodeVault {
+        let path = match config.audit_log() {
+            Some(path) => path,
+            None => {
+                let vault = Arc::new(vault);
+                return (vault.clone(), vault, identity.map(Arc::new), cipher_suite);
+            }
+        };
+        let vault = AuditVault::new(vault, &path)
+            .and_then(|vault| vault.for_caller("ockamd"))
+            .expect("failed to open audit log");
+        let head = vault.head();
+        println!(
+            "Audit log checkpoint: --records {} --hash {}",
+            head.records,
+            hex::encode(head.hash)
+        );
+        let identity = identity.map(|secret| {
+            let secret = vault
+                .wrap_secret(secret, &config.identity_name())
+                .expect("the identity name can't name a key in the audit log");
+            Arc::new(secret)
+        });
+        let vault = Arc::new(vault);
+        (vault.clone(), vault, identity, cipher_suite)
+    }
+
     pub fn create_transport(
         config: &Config,
         router_tx: Sender<OckamCommand>,
@@ -113,66 +297,42 @@ impl<'a> Node<'a> {
         let (router_tx, router_rx) = std::sync::mpsc::channel();
         let router = Router::new(router_rx);
 
-        // create the vault, using the FILESYSTEM implementation
-        let mut vault =
-            FilesystemVault::new(config.vault_path()).expect("failed to initialize vault");
-
-        // check for re-use of provided identity name from CLI args, if not in on-disk in vault
-        // generate a new one to be used
-
-        let resp_key_ctx = match vault.get_persistent_secret(&config.identity_name()) {
-            Ok(secret) => Some(Arc::new(secret)),
-            Err(_) => {
-                // if responder, generate keypair and display static public key
-                if matches!(config.role(), Role::Sink) || matches!(config.role(), Role::Router) {
-                    let attributes = SecretAttributes {
-                        stype: SecretType::Curve25519,
-                        persistence: SecretPersistence::Persistent,
-                        length: CURVE25519_SECRET_LENGTH,
-                    };
-                    Some(Arc::new(
-                        vault
-                            .secret_generate(attributes)
-                            .expect("failed to generate secret"),
-                    ))
-                } else {
-                    None
-                }
-            }
+        let (vault, channel_vault, resp_key_ctx, cipher_suite) = match config.vault() {
+            Vault::Filesystem => Node::filesystem_vault(config),
+            Vault::Pkcs11 {
+                module,
+                token_label,
+            } => Node::pkcs11_vault(config, module, &token_label)?,
         };
 
-        if matches!(config.role(), Role::Sink) && resp_key_ctx.is_some() {
-            if let Ok(resp_key) = vault.secret_public_key_get(resp_key_ctx.as_ref().unwrap()) {
-                println!("Responder public key: {}", hex::encode(resp_key.as_ref()));
-            }
-        }
-
-        if matches!(config.role(), Role::Router) && resp_key_ctx.is_some() {
-            if let Ok(resp_key) = vault.secret_public_key_get(resp_key_ctx.as_ref().unwrap()) {
-                println!("Router public key: {}", hex::encode(resp_key.as_ref()));
+        if let Some(resp_key_ctx) = resp_key_ctx.as_ref() {
+            if let Ok(resp_key) = vault.secret_public_key_get(resp_key_ctx.as_ref().as_ref()) {
+                match config.role() {
+                    Role::Sink => {
+                        println!("Responder public key: {}", hex::encode(resp_key.as_ref()))
+                    }
+                    Role::Router => {
+                        println!("Router public key: {}", hex::encode(resp_key.as_ref()))
+                    }
+                    Role::Source => {}
+                }
             }
         }
 
-        // prepare the vault for use in key exchanger and channel manager
-        let vault = Arc::new(Mutex::new(vault));
-
         // create the channel manager
         type XXChannelManager = ChannelManager<XXInitiator, XXResponder, XXNewKeyExchanger>;
         let (channel_tx, channel_rx) = mpsc::channel();
-        let new_key_exchanger = XXNewKeyExchanger::new(
-            CipherSuite::Curve25519AesGcmSha256,
-            vault.clone(),
-            vault.clone(),
-        );
+        let new_key_exchanger = XXNewKeyExchanger::new(cipher_suite, vault.clone(), vault.clone());
 
         let chan_manager = XXChannelManager::new(
             channel_rx,
             channel_tx.clone(),
             router_tx.clone(),
-            vault,
+            channel_vault,
             new_key_exchanger,
             resp_key_ctx,
             None,
+            Node::trust_policy(config),
         )
         .unwrap();
 
@@ -219,10 +379,7 @@ impl<'a> Node<'a> {
                     while self.router.poll()
                         && self.transport.poll()
                         && w.poll()
-                        && self
-                            .chan_manager
-                            .poll()
-                            .expect("channel manager poll failure")
+                        && poll_channels(&mut self.chan_manager)
                     {
                         thread::sleep(time::Duration::from_millis(1));
                     }
@@ -233,10 +390,7 @@ impl<'a> Node<'a> {
                     while self.router.poll()
                         && self.transport.poll()
                         && w.poll()
-                        && self
-                            .chan_manager
-                            .poll()
-                            .expect("channel manager poll failure")
+                        && poll_channels(&mut self.chan_manager)
                     {
                         thread::sleep(time::Duration::from_millis(1));
                     }
@@ -245,10 +399,7 @@ impl<'a> Node<'a> {
             None => {
                 while self.router.poll()
                     && self.transport.poll()
-                    && self
-                        .chan_manager
-                        .poll()
-                        .expect("channel manager poll failure")
+                    && poll_channels(&mut self.chan_manager)
                 {
                     thread::sleep(time::Duration::from_millis(1));
                 }
@@ -161,6 +161,15 @@ impl SinkWorker {
                                 true
                             }
                         }
+                        MessageType::ChannelClose | MessageType::NoSuchChannel => {
+                            println!("channel closed");
+                            self.route = None;
+                            true
+                        }
+                        MessageType::KeyAgreementFailed => {
+                            println!("key agreement failed");
+                            false
+                        }
                         _ => unimplemented!(),
                     }
                 }
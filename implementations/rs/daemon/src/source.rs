@@ -2,7 +2,6 @@ use std::sync::mpsc::{self, Receiver, Sender};
 
 use crate::config::Config;
 
-use hex::encode;
 use ockam::message::{
     Address, AddressType, Codec, Message as OckamMessage, Message, MessageType, Route,
     RouterAddress,
@@ -93,23 +92,11 @@ impl StdinWorker {
                 .unwrap();
         self.route.addresses.push(service_address);
 
-        return match RouterAddress::decode(&m.message_body) {
-            Ok((rcc, mb)) => {
-                if let Some(rpk) = self.config.public_key_sink() {
-                    if rpk == encode(mb) {
-                        println!("keys agree");
-                        return Ok(());
-                    } else {
-                        println!("keys conflict");
-                        return Err(
-                            "remote public key doesn't match expected, possible spoofing".into(),
-                        );
-                    }
-                }
-                Ok(())
-            }
+        // the channel manager's trust policy already checked the sink's public key
+        match RouterAddress::decode(&m.message_body) {
+            Ok(_) => Ok(()),
             _ => Err("receive channel: expected channel address in message body".into()),
-        };
+        }
     }
 
     pub fn poll(&mut self) -> bool {
@@ -122,6 +109,13 @@ impl StdinWorker {
                             Ok(()) => {}
                             Err(s) => panic!(s),
                         },
+                        MessageType::ChannelClose | MessageType::NoSuchChannel => {
+                            println!("channel to sink closed");
+                            self.route = Route { addresses: vec![] };
+                        }
+                        MessageType::KeyAgreementFailed => {
+                            panic!("key agreement with sink failed");
+                        }
                         _ => unimplemented!(),
                     }
                 }
@@ -44,6 +44,28 @@ ockam_vault_extern_error_t ockam_kex_xx_responder(ockam_kex_t*         kex,
                                                   ockam_vault_t        vault,
                                                   ockam_vault_secret_t identity_key);
 
+/**
+ * @brief  Create x3dh initiator.
+ * @param   kex[out]         Resulting kex.
+ * @param   vault[in]        Vault to use.
+ * @param   identity_key[in] Identity key.
+ * @return  error.
+ */
+ockam_vault_extern_error_t ockam_kex_x3dh_initiator(ockam_kex_t*         kex,
+                                                    ockam_vault_t        vault,
+                                                    ockam_vault_secret_t identity_key);
+
+/**
+ * @brief  Create x3dh responder. Its prekeys are signed with the identity key.
+ * @param   kex[out]         Resulting kex.
+ * @param   vault[in]        Vault to use.
+ * @param   identity_key[in] Identity key.
+ * @return  error.
+ */
+ockam_vault_extern_error_t ockam_kex_x3dh_responder(ockam_kex_t*         kex,
+                                                    ockam_vault_t        vault,
+                                                    ockam_vault_secret_t identity_key);
+
 /**
  * @brief  Process new portion of data.
  * @param   kex[in]              Kex object to use.
@@ -30,6 +30,8 @@ typedef enum {
     OCKAM_VAULT_SECRET_TYPE_AES_KEY,
     OCKAM_VAULT_SECRET_TYPE_CURVE25519_PRIVATEKEY,
     OCKAM_VAULT_SECRET_TYPE_P256_PRIVATEKEY,
+    OCKAM_VAULT_SECRET_TYPE_ED25519_PRIVATEKEY,
+    OCKAM_VAULT_SECRET_TYPE_CHACHA20POLY1305_KEY,
 } ockam_vault_secret_type_t;
 
 /**
@@ -41,6 +43,19 @@ typedef enum {
     OCKAM_VAULT_SECRET_PERSISTENT = 1,
 } ockam_vault_secret_persistence_t;
 
+/**
+ * @enum    ockam_vault_secret_usage_t
+ * @brief   Operations a secret may be used for, combine them with `|`.
+ */
+typedef enum {
+    OCKAM_VAULT_SECRET_USAGE_NONE = 0,
+    OCKAM_VAULT_SECRET_USAGE_SIGN = 1,
+    OCKAM_VAULT_SECRET_USAGE_ECDH = 2,
+    OCKAM_VAULT_SECRET_USAGE_AEAD = 4,
+    OCKAM_VAULT_SECRET_USAGE_HKDF = 8,
+    OCKAM_VAULT_SECRET_USAGE_ALL  = 15,
+} ockam_vault_secret_usage_t;
+
 /**
  * @struct  ockam_vault_secret_attributes_t
  * @brief   Attributes for a specific ockam vault secret.
@@ -49,6 +64,8 @@ typedef struct {
     uint32_t                         length;
     ockam_vault_secret_type_t        type;
     ockam_vault_secret_persistence_t persistence;
+    uint32_t                         exportable; /* 0 if the secret may not be exported */
+    uint32_t                         usage;      /* ockam_vault_secret_usage_t bits */
 } ockam_vault_secret_attributes_t;
 
 /**
@@ -66,6 +83,15 @@ ockam_vault_extern_error_t ockam_vault_default_init(ockam_vault_t* vault);
  */
 ockam_vault_extern_error_t ockam_vault_file_init(ockam_vault_t* vault, const unsigned char* const path);
 
+/**
+ * @brief   Fill a buffer with random bytes from the vault's entropy source.
+ * @param   vault[in]           Vault object to use for random number generation.
+ * @param   buffer[out]         Buffer to place the random bytes in.
+ * @param   buffer_size[in]     Number of random bytes to generate.
+ * @return  error.
+ */
+ockam_vault_extern_error_t ockam_vault_random_bytes_generate(ockam_vault_t vault, uint8_t* buffer, size_t buffer_size);
+
 /**
  * @brief   Compute a SHA-256 hash based on input data.
  * @param   vault[in]           Vault object to use for SHA-256.
@@ -238,6 +264,93 @@ ockam_vault_extern_error_t ockam_vault_aead_aes_gcm_decrypt(ockam_vault_t
                                                             uint8_t*             plaintext,
                                                             size_t               plaintext_size,
                                                             size_t*              plaintext_length);
+
+/**
+ * @brief   Encrypt a payload using ChaCha20-Poly1305. The 96-bit nonce is four zero bytes followed by the
+ *          little-endian nonce value, as in Noise.
+ * @param   vault[in]                       Vault object to use for encryption.
+ * @param   key[in]                         Ockam secret key to use for encryption.
+ * @param   nonce[in]                       Nonce value to use for encryption.
+ * @param   additional_data[in]             Additional data to use for encryption.
+ * @param   additional_data_length[in]      Length of the additional data.
+ * @param   plaintext[in]                   Buffer containing plaintext data to encrypt.
+ * @param   plaintext_length[in]            Length of plaintext data to encrypt.
+ * @param   ciphertext_and_tag[in]          Buffer containing the generated ciphertext and tag data.
+ * @param   ciphertext_and_tag_size[in]     Size of the ciphertext + tag buffer. Must be plaintext_size + 16.
+ * @param   ciphertext_and_tag_length[out]  Amount of data placed in the ciphertext + tag buffer.
+ * @return  error.
+ */
+ockam_vault_extern_error_t ockam_vault_aead_chacha20_poly1305_encrypt(ockam_vault_t        vault,
+                                                                      ockam_vault_secret_t key,
+                                                                      uint64_t             nonce,
+                                                                      const uint8_t*       additional_data,
+                                                                      size_t               additional_data_length,
+                                                                      const uint8_t*       plaintext,
+                                                                      size_t               plaintext_length,
+                                                                      uint8_t*             ciphertext_and_tag,
+                                                                      size_t               ciphertext_and_tag_size,
+                                                                      size_t*              ciphertext_and_tag_length);
+
+/**
+ * @brief   Decrypt a payload using ChaCha20-Poly1305.
+ * @param   vault[in]                     Vault object to use for decryption.
+ * @param   key[in]                       Ockam secret key to use for decryption.
+ * @param   nonce[in]                     Nonce value to use for decryption.
+ * @param   additional_data[in]           Additional data to use for decryption.
+ * @param   additional_data_length[in]    Length of the additional data.
+ * @param   ciphertext_and_tag[in]        The ciphertext + tag data to decrypt.
+ * @param   ciphertext_and_tag_length[in] Length of the ciphertext + tag data to decrypt.
+ * @param   plaintext[out]                Buffer to place the decrypted data in.
+ * @param   plaintext_size[in]            Size of the plaintext buffer. Must be ciphertext_tag_size - 16.
+ * @param   plaintext_length[out]         Amount of data placed in the plaintext buffer.
+ * @return  error.
+ */
+ockam_vault_extern_error_t ockam_vault_aead_chacha20_poly1305_decrypt(ockam_vault_t        vault,
+                                                                      ockam_vault_secret_t key,
+                                                                      uint64_t             nonce,
+                                                                      const uint8_t*       additional_data,
+                                                                      size_t               additional_data_length,
+                                                                      const uint8_t*       ciphertext_and_tag,
+                                                                      size_t               ciphertext_and_tag_length,
+                                                                      uint8_t*             plaintext,
+                                                                      size_t               plaintext_size,
+                                                                      size_t*              plaintext_length);
+
+/**
+ * @brief   Sign data with an ockam vault secret. Curve25519 secrets sign with XEdDSA, Ed25519 secrets produce
+ *          RFC 8032 signatures.
+ * @param   vault[in]           Vault object to use for signing.
+ * @param   secret[in]          Ockam vault secret to sign with.
+ * @param   data[in]            Buffer containing the data to sign.
+ * @param   data_length[in]     Length of the data to sign.
+ * @param   signature[out]      Buffer to place the signature in. Must be 64 bytes.
+ * @return  error.
+ */
+ockam_vault_extern_error_t ockam_vault_sign(ockam_vault_t        vault,
+                                            ockam_vault_secret_t secret,
+                                            const uint8_t*       data,
+                                            size_t               data_length,
+                                            uint8_t*             signature);
+
+/**
+ * @brief   Verify a signature. Returns an error if the signature is invalid.
+ * @param   vault[in]               Vault object to use for verification.
+ * @param   public_key_type[in]     Type of the secret the public key belongs to.
+ * @param   public_key[in]          Public key data of the signer.
+ * @param   public_key_length[in]   Length of the public key.
+ * @param   data[in]                Buffer containing the signed data.
+ * @param   data_length[in]         Length of the signed data.
+ * @param   signature[in]           The signature to verify. Must be 64 bytes.
+ * @return  error.
+ */
+ockam_vault_extern_error_t ockam_vault_verify(ockam_vault_t             vault,
+                                              ockam_vault_secret_type_t public_key_type,
+                                              const uint8_t*            public_key,
+                                              size_t                    public_key_length,
+                                              const uint8_t*            data,
+                                              size_t                    data_length,
+                                              const uint8_t*            signature);
+
 /**
  * @brief  Return persistence id of given secret.
  * @param   vault[in]               Vault object to use.
@@ -1,9 +1,12 @@
 use crate::error::Error;
 use ockam_common::error::OckamResult;
-use ockam_vault_software::ockam_vault::types::{PublicKey, SecretAttributes, SecretKey};
+use ockam_vault_software::ockam_vault::types::{
+    PersistentSecretInfo, PublicKey, SecretAttributes, SecretKey, SecretMetadata, SecretType,
+};
 use ockam_vault_software::ockam_vault::zeroize::Zeroize;
 use ockam_vault_software::ockam_vault::{
-    AsymmetricVault, HashVault, PersistentVault, Secret, SecretVault, SymmetricVault,
+    AsymmetricVault, HashVault, PersistentVault, RandomVault, Secret, SecretVault, SignerVault,
+    SymmetricVault, VerifierVault,
 };
 use ockam_vault_software::DefaultVault;
 
@@ -22,48 +25,51 @@ impl Zeroize for DefaultVaultAdapter {
 }
 
 impl SecretVault for DefaultVaultAdapter {
-    fn secret_generate(&mut self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+    fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
         self.0.secret_generate(attributes)
     }
 
     fn secret_import(
-        &mut self,
+        &self,
         secret: &[u8],
         attributes: SecretAttributes,
     ) -> OckamResult<Box<dyn Secret>> {
         self.0.secret_import(secret, attributes)
     }
 
-    fn secret_export(&mut self, context: &Box<dyn Secret>) -> OckamResult<SecretKey> {
+    fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
         self.0.secret_export(context)
     }
 
-    fn secret_attributes_get(
-        &mut self,
-        context: &Box<dyn Secret>,
-    ) -> OckamResult<SecretAttributes> {
+    fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes> {
         self.0.secret_attributes_get(context)
     }
 
-    fn secret_public_key_get(&mut self, context: &Box<dyn Secret>) -> OckamResult<PublicKey> {
+    fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
         self.0.secret_public_key_get(context)
     }
 
-    fn secret_destroy(&mut self, context: Box<dyn Secret>) -> OckamResult<()> {
+    fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
         self.0.secret_destroy(context)
     }
 }
 
+impl RandomVault for DefaultVaultAdapter {
+    fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+        self.0.random_bytes_generate(buffer)
+    }
+}
+
 impl HashVault for DefaultVaultAdapter {
     fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]> {
         self.0.sha256(data)
     }
 
     fn hkdf_sha256(
-        &mut self,
-        salt: &Box<dyn Secret>,
+        &self,
+        salt: &dyn Secret,
         info: &[u8],
-        ikm: Option<&Box<dyn Secret>>,
+        ikm: Option<&dyn Secret>,
         output_attributes: Vec<SecretAttributes>,
     ) -> OckamResult<Vec<Box<dyn Secret>>> {
         self.0.hkdf_sha256(salt, info, ikm, output_attributes)
@@ -72,8 +78,8 @@ impl HashVault for DefaultVaultAdapter {
 
 impl SymmetricVault for DefaultVaultAdapter {
     fn aead_aes_gcm_encrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         plaintext: &[u8],
         nonce: &[u8],
         aad: &[u8],
@@ -82,8 +88,8 @@ impl SymmetricVault for DefaultVaultAdapter {
     }
 
     fn aead_aes_gcm_decrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         cipher_text: &[u8],
         nonce: &[u8],
         aad: &[u8],
@@ -91,24 +97,84 @@ impl SymmetricVault for DefaultVaultAdapter {
         self.0
             .aead_aes_gcm_decrypt(context, cipher_text, nonce, aad)
     }
+
+    fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        self.0
+            .aead_chacha20_poly1305_encrypt(context, plaintext, nonce, aad)
+    }
+
+    fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        self.0
+            .aead_chacha20_poly1305_decrypt(context, cipher_text, nonce, aad)
+    }
 }
 
 impl AsymmetricVault for DefaultVaultAdapter {
     fn ec_diffie_hellman(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         peer_public_key: &[u8],
     ) -> OckamResult<Box<dyn Secret>> {
         self.0.ec_diffie_hellman(context, peer_public_key)
     }
 }
 
+impl SignerVault for DefaultVaultAdapter {
+    fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]> {
+        self.0.sign(secret_key, data)
+    }
+}
+
+impl VerifierVault for DefaultVaultAdapter {
+    fn verify(
+        &self,
+        signature: &[u8; 64],
+        public_key: &[u8],
+        public_key_type: SecretType,
+        data: &[u8],
+    ) -> OckamResult<()> {
+        self.0.verify(signature, public_key, public_key_type, data)
+    }
+}
+
 impl PersistentVault for DefaultVaultAdapter {
-    fn get_persistence_id(&self, _secret: &Box<dyn Secret>) -> OckamResult<String> {
+    fn get_persistence_id(&self, _secret: &dyn Secret) -> OckamResult<String> {
         Err(Error::VaultDoesntSupportPersistence.into())
     }
 
     fn get_persistent_secret(&self, _persistence_id: &str) -> OckamResult<Box<dyn Secret>> {
         Err(Error::VaultDoesntSupportPersistence.into())
     }
+
+    fn get_persistent_secret_by_label(&self, _label: &str) -> OckamResult<Box<dyn Secret>> {
+        Err(Error::VaultDoesntSupportPersistence.into())
+    }
+
+    fn get_secret_metadata(&self, _secret: &dyn Secret) -> OckamResult<SecretMetadata> {
+        Err(Error::VaultDoesntSupportPersistence.into())
+    }
+
+    fn set_secret_label(&self, _secret: &dyn Secret, _label: Option<&str>) -> OckamResult<()> {
+        Err(Error::VaultDoesntSupportPersistence.into())
+    }
+
+    fn set_secret_tags(&self, _secret: &dyn Secret, _tags: Vec<String>) -> OckamResult<()> {
+        Err(Error::VaultDoesntSupportPersistence.into())
+    }
+
+    fn list_persistent_secrets(&self) -> OckamResult<Vec<PersistentSecretInfo>> {
+        Err(Error::VaultDoesntSupportPersistence.into())
+    }
 }
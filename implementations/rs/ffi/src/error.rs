@@ -48,6 +48,7 @@ pub enum Error {
     InvalidPublicKey,
     VaultNotFound,
     OwnershipError,
+    InvalidSecretAttributes,
 }
 
 impl Error {
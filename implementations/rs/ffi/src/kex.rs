@@ -3,7 +3,8 @@ use crate::kex_types::*;
 use crate::mutex_storage::FfiObjectMutexStorage;
 use crate::vault::{DEFAULT_VAULTS, FILESYSTEM_VAULTS, SECRETS};
 use crate::vault_types::{FfiVaultFatPointer, FfiVaultType};
-use ockam_kex::{CipherSuite, KeyExchanger};
+use ockam_kex::{CipherSuite, KeyExchanger, NewKeyExchanger};
+use ockam_kex_x3dh::{X3dhInitiator, X3dhNewKeyExchanger, X3dhResponder, X3dhVault};
 use ockam_kex_xx::{SymmetricState, XXInitiator, XXResponder, XXVault};
 use std::ops::DerefMut;
 use std::sync::{Arc, Mutex};
@@ -13,6 +14,10 @@ lazy_static! {
         FfiObjectMutexStorage::default();
     pub(crate) static ref XX_RESPONDER: FfiObjectMutexStorage<XXResponder> =
         FfiObjectMutexStorage::default();
+    pub(crate) static ref X3DH_INITIATOR: FfiObjectMutexStorage<X3dhInitiator> =
+        FfiObjectMutexStorage::default();
+    pub(crate) static ref X3DH_RESPONDER: FfiObjectMutexStorage<X3dhResponder> =
+        FfiObjectMutexStorage::default();
 }
 
 fn call<F, R>(context: FfiKexFatPointer, callback: F) -> Result<R, FfiOckamError>
@@ -30,13 +35,34 @@ where
             let item = XX_RESPONDER.get_object(context.handle)?;
             let mut item = item.lock().unwrap();
 
+            callback(item.deref_mut())
+        }
+        FfiKexType::X3dhInitiator => {
+            let item = X3DH_INITIATOR.get_object(context.handle)?;
+            let mut item = item.lock().unwrap();
+
+            callback(item.deref_mut())
+        }
+        FfiKexType::X3dhResponder => {
+            let item = X3DH_RESPONDER.get_object(context.handle)?;
+            let mut item = item.lock().unwrap();
+
             callback(item.deref_mut())
         }
     }
 }
 
-fn cast_vault(vault: FfiVaultFatPointer) -> Result<Arc<Mutex<dyn XXVault>>, FfiOckamError> {
-    let vault: Arc<Mutex<dyn XXVault>> = match vault.vault_type {
+fn cast_vault(vault: FfiVaultFatPointer) -> Result<Arc<dyn XXVault>, FfiOckamError> {
+    let vault: Arc<dyn XXVault> = match vault.vault_type {
+        FfiVaultType::Software => DEFAULT_VAULTS.get_object(vault.handle)?,
+        FfiVaultType::Filesystem => FILESYSTEM_VAULTS.get_object(vault.handle)?,
+    };
+
+    Ok(vault)
+}
+
+fn cast_x3dh_vault(vault: FfiVaultFatPointer) -> Result<Arc<dyn X3dhVault>, FfiOckamError> {
+    let vault: Arc<dyn X3dhVault> = match vault.vault_type {
         FfiVaultType::Software => DEFAULT_VAULTS.get_object(vault.handle)?,
         FfiVaultType::Filesystem => FILESYSTEM_VAULTS.get_object(vault.handle)?,
     };
@@ -114,6 +140,69 @@ pub extern "C" fn ockam_kex_xx_responder(
     FfiOckamError::none()
 }
 
+/// Create a new X3DH kex initiator and return it
+#[no_mangle]
+pub extern "C" fn ockam_kex_x3dh_initiator(
+    context: &mut FfiKexFatPointer,
+    vault: FfiVaultFatPointer,
+    identity_key: u64,
+) -> FfiOckamError {
+    let res = || {
+        let vault = cast_x3dh_vault(vault)?;
+        let identity_key = SECRETS.get_object(identity_key)?;
+
+        let new_key_exchanger =
+            X3dhNewKeyExchanger::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), vault);
+        let initiator = new_key_exchanger.initiator(Some(identity_key));
+
+        let handle = X3DH_INITIATOR.insert_object(Arc::new(Mutex::new(initiator)))?;
+
+        Ok(FfiKexFatPointer {
+            handle,
+            kex_type: FfiKexType::X3dhInitiator,
+        })
+    };
+    let res = res();
+    *context = match res {
+        Ok(c) => c,
+        Err(err) => return err,
+    };
+
+    FfiOckamError::none()
+}
+
+/// Create a new X3DH kex responder and return it. Its prekeys are signed with the
+/// identity key.
+#[no_mangle]
+pub extern "C" fn ockam_kex_x3dh_responder(
+    context: &mut FfiKexFatPointer,
+    vault: FfiVaultFatPointer,
+    identity_key: u64,
+) -> FfiOckamError {
+    let res = || {
+        let vault = cast_x3dh_vault(vault)?;
+        let identity_key = SECRETS.get_object(identity_key)?;
+
+        let new_key_exchanger =
+            X3dhNewKeyExchanger::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), vault);
+        let responder = new_key_exchanger.responder(Some(identity_key));
+
+        let handle = X3DH_RESPONDER.insert_object(Arc::new(Mutex::new(responder)))?;
+
+        Ok(FfiKexFatPointer {
+            handle,
+            kex_type: FfiKexType::X3dhResponder,
+        })
+    };
+    let res = res();
+    *context = match res {
+        Ok(c) => c,
+        Err(err) => return err,
+    };
+
+    FfiOckamError::none()
+}
+
 #[no_mangle]
 pub extern "C" fn ockam_kex_process(
     context: FfiKexFatPointer,
@@ -173,6 +262,16 @@ pub extern "C" fn ockam_kex_finalize(
                 let item = Box::new(item);
                 item.finalize()
             }
+            FfiKexType::X3dhInitiator => {
+                let item = X3DH_INITIATOR.remove_object_sized(context.handle)?;
+                let item = Box::new(item);
+                item.finalize()
+            }
+            FfiKexType::X3dhResponder => {
+                let item = X3DH_RESPONDER.remove_object_sized(context.handle)?;
+                let item = Box::new(item);
+                item.finalize()
+            }
         }?;
 
         let mut public_key = [0u8; 65];
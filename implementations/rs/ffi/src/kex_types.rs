@@ -5,6 +5,8 @@
 pub enum FfiKexType {
     XxInitiator = 1,
     XxResponder = 2,
+    X3dhInitiator = 3,
+    X3dhResponder = 4,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -60,21 +60,6 @@ impl<T> FfiObjectMutexStorage<T> {
 }
 
 impl<T: ?Sized> FfiObjectMutexStorage<T> {
-    /// Remove object
-    pub fn remove_object(&self, handle: u64) -> Result<(), FfiOckamError> {
-        let mut storage = self.storage.write().unwrap();
-
-        let index = storage
-            .vec
-            .iter()
-            .position(|x| x.handle == handle)
-            .ok_or(Error::EntryNotFound)?;
-
-        let _ = storage.vec.remove(index);
-
-        Ok(())
-    }
-
     /// Insert object
     pub fn insert_object(&self, object: Arc<Mutex<T>>) -> Result<u64, FfiOckamError> {
         let mut storage = self.storage.write().unwrap();
@@ -44,6 +44,21 @@ impl<T> FfiObjectNoMutexStorage<T> {
         Ok(item)
     }
 
+    /// Remove object, other holders of it keep their reference
+    pub fn drop_object(&self, handle: u64) -> Result<(), FfiOckamError> {
+        let mut storage = self.storage.write().unwrap();
+
+        let index = storage
+            .vec
+            .iter()
+            .position(|x| x.handle == handle)
+            .ok_or(Error::EntryNotFound)?;
+
+        let _ = storage.vec.remove(index);
+
+        Ok(())
+    }
+
     /// Insert object
     pub fn insert_object(&self, object: T) -> Result<u64, FfiOckamError> {
         let mut storage = self.storage.write().unwrap();
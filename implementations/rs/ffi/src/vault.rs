@@ -1,55 +1,70 @@
 use crate::default_vault_adapter::DefaultVaultAdapter;
 use crate::error::{Error, FfiOckamError};
-use crate::mutex_storage::FfiObjectMutexStorage;
 use crate::nomutex_storage::FfiObjectNoMutexStorage;
 use crate::vault_types::*;
 use ockam_vault_file::FilesystemVault;
 use ockam_vault_software::ockam_vault::types::{PublicKey, SecretAttributes, SecretType};
 use ockam_vault_software::ockam_vault::{
-    AsymmetricVault, HashVault, PersistentVault, Secret, SecretVault, SymmetricVault,
+    AsymmetricVault, HashVault, PersistentVault, RandomVault, Secret, SecretVault, SignerVault,
+    SymmetricVault, VerifierVault,
 };
 use ockam_vault_software::DefaultVault;
+use std::convert::{TryFrom, TryInto};
 use std::ffi::{CStr, CString};
-use std::ops::DerefMut;
 use std::os::raw::c_char;
 use std::slice;
-use std::sync::{Arc, Mutex};
 
 pub trait FfiVault:
-    SecretVault + HashVault + SymmetricVault + AsymmetricVault + PersistentVault + Send
+    SecretVault
+    + HashVault
+    + RandomVault
+    + SymmetricVault
+    + AsymmetricVault
+    + SignerVault
+    + VerifierVault
+    + PersistentVault
+    + Send
+    + Sync
 {
 }
 
 impl<D> FfiVault for D where
-    D: SecretVault + HashVault + SymmetricVault + AsymmetricVault + PersistentVault + Send
+    D: SecretVault
+        + HashVault
+        + RandomVault
+        + SymmetricVault
+        + AsymmetricVault
+        + SignerVault
+        + VerifierVault
+        + PersistentVault
+        + Send
+        + Sync
 {
 }
 
 lazy_static! {
-    pub(crate) static ref DEFAULT_VAULTS: FfiObjectMutexStorage<DefaultVaultAdapter> =
-        FfiObjectMutexStorage::default();
-    pub(crate) static ref FILESYSTEM_VAULTS: FfiObjectMutexStorage<FilesystemVault> =
-        FfiObjectMutexStorage::default();
+    pub(crate) static ref DEFAULT_VAULTS: FfiObjectNoMutexStorage<DefaultVaultAdapter> =
+        FfiObjectNoMutexStorage::default();
+    pub(crate) static ref FILESYSTEM_VAULTS: FfiObjectNoMutexStorage<FilesystemVault> =
+        FfiObjectNoMutexStorage::default();
     pub(crate) static ref SECRETS: FfiObjectNoMutexStorage<Box<dyn Secret>> =
         FfiObjectNoMutexStorage::default();
 }
 
 fn call<F, R>(context: FfiVaultFatPointer, callback: F) -> Result<R, FfiOckamError>
 where
-    F: FnOnce(&mut dyn FfiVault) -> Result<R, FfiOckamError>,
+    F: FnOnce(&dyn FfiVault) -> Result<R, FfiOckamError>,
 {
     match context.vault_type {
         FfiVaultType::Software => {
             let item = DEFAULT_VAULTS.get_object(context.handle)?;
-            let mut item = item.lock().unwrap();
 
-            callback(item.deref_mut())
+            callback(item.as_ref())
         }
         FfiVaultType::Filesystem => {
             let item = FILESYSTEM_VAULTS.get_object(context.handle)?;
-            let mut item = item.lock().unwrap();
 
-            callback(item.deref_mut())
+            callback(item.as_ref())
         }
     }
 }
@@ -58,12 +73,11 @@ where
 #[no_mangle]
 pub extern "C" fn ockam_vault_default_init(context: &mut FfiVaultFatPointer) -> FfiOckamError {
     // TODO: handle logging
-    let handle = match DEFAULT_VAULTS.insert_object(Arc::new(Mutex::new(DefaultVaultAdapter::new(
-        DefaultVault::default(),
-    )))) {
-        Ok(handle) => handle,
-        Err(err) => return err,
-    };
+    let handle =
+        match DEFAULT_VAULTS.insert_object(DefaultVaultAdapter::new(DefaultVault::default())) {
+            Ok(handle) => handle,
+            Err(err) => return err,
+        };
 
     *context = FfiVaultFatPointer {
         handle,
@@ -86,7 +100,7 @@ pub extern "C" fn ockam_vault_file_init(
         Ok(v) => v,
         Err(_) => return Error::ErrorCreatingFilesystemVault.into(),
     };
-    let handle = match FILESYSTEM_VAULTS.insert_object(Arc::new(Mutex::new(vault))) {
+    let handle = match FILESYSTEM_VAULTS.insert_object(vault) {
         Ok(handle) => handle,
         Err(err) => return err,
     };
@@ -99,6 +113,26 @@ pub extern "C" fn ockam_vault_file_init(
     FfiOckamError::none()
 }
 
+/// Fill `buffer` with `buffer_size` random bytes
+#[no_mangle]
+pub extern "C" fn ockam_vault_random_bytes_generate(
+    context: FfiVaultFatPointer,
+    buffer: *mut u8,
+    buffer_size: u32,
+) -> FfiOckamError {
+    check_buffer!(buffer, buffer_size);
+
+    let buffer = unsafe { std::slice::from_raw_parts_mut(buffer, buffer_size as usize) };
+
+    match call(context, |v| -> Result<(), FfiOckamError> {
+        v.random_bytes_generate(buffer)?;
+        Ok(())
+    }) {
+        Ok(_) => FfiOckamError::none(),
+        Err(err) => err.into(),
+    }
+}
+
 /// Compute the SHA-256 hash on `input` and put the result in `digest`.
 /// `digest` must be 32 bytes in length
 #[no_mangle]
@@ -135,9 +169,8 @@ pub extern "C" fn ockam_vault_secret_generate(
     secret: &mut SecretKeyHandle,
     attributes: FfiSecretAttributes,
 ) -> FfiOckamError {
-    let atts = attributes.into();
     *secret = match call(context, |v| -> Result<SecretKeyHandle, FfiOckamError> {
-        let ctx = v.secret_generate(atts)?;
+        let ctx = v.secret_generate(attributes.try_into()?)?;
         Ok(SECRETS.insert_object(ctx)?)
     }) {
         Ok(h) => h,
@@ -158,11 +191,10 @@ pub extern "C" fn ockam_vault_secret_import(
 ) -> FfiOckamError {
     check_buffer!(input, input_length);
 
-    let atts = attributes.into();
     *secret = match call(context, |v| -> Result<SecretKeyHandle, FfiOckamError> {
         let secret_data = unsafe { std::slice::from_raw_parts(input, input_length as usize) };
 
-        let ctx = v.secret_import(secret_data, atts)?;
+        let ctx = v.secret_import(secret_data, attributes.try_into()?)?;
         Ok(SECRETS.insert_object(ctx)?)
     }) {
         Ok(s) => s,
@@ -184,7 +216,7 @@ pub extern "C" fn ockam_vault_secret_export(
     *output_buffer_length = 0;
     match call(context, |v| -> Result<(), FfiOckamError> {
         let ctx = SECRETS.get_object(secret)?;
-        let key = v.secret_export(ctx.as_ref())?;
+        let key = v.secret_export(ctx.as_ref().as_ref())?;
         if output_buffer_size < key.as_ref().len() as u32 {
             return Err(Error::BufferTooSmall.into());
         }
@@ -211,7 +243,7 @@ pub extern "C" fn ockam_vault_secret_publickey_get(
     *output_buffer_length = 0;
     match call(context, |v| -> Result<(), FfiOckamError> {
         let ctx = SECRETS.get_object(secret)?;
-        let key = v.secret_public_key_get(&ctx)?;
+        let key = v.secret_public_key_get(ctx.as_ref().as_ref())?;
         if output_buffer_size < key.as_ref().len() as u32 {
             return Err(Error::BufferTooSmall.into());
         }
@@ -235,7 +267,7 @@ pub extern "C" fn ockam_vault_secret_attributes_get(
 ) -> FfiOckamError {
     *attributes = match call(context, |v| -> Result<FfiSecretAttributes, FfiOckamError> {
         let ctx = SECRETS.get_object(secret)?;
-        let atts = v.secret_attributes_get(ctx.as_ref())?;
+        let atts = v.secret_attributes_get(ctx.as_ref().as_ref())?;
         Ok(atts.into())
     }) {
         Ok(a) => a,
@@ -278,7 +310,7 @@ pub extern "C" fn ockam_vault_ecdh(
         unsafe { std::slice::from_raw_parts(peer_publickey, peer_publickey_length as usize) };
     *shared_secret = match call(context, |v| -> Result<u64, FfiOckamError> {
         let ctx = SECRETS.get_object(secret)?;
-        let atts = v.secret_attributes_get(&ctx)?;
+        let atts = v.secret_attributes_get(ctx.as_ref().as_ref())?;
         let pubkey = match atts.stype {
             SecretType::Curve25519 => {
                 if peer_publickey.len() != 32 {
@@ -296,7 +328,7 @@ pub extern "C" fn ockam_vault_ecdh(
             }
             _ => Err(Error::UnknownPublicKeyType),
         }?;
-        let shared_ctx = v.ec_diffie_hellman(&ctx, pubkey.as_ref())?;
+        let shared_ctx = v.ec_diffie_hellman(ctx.as_ref().as_ref(), pubkey.as_ref())?;
         Ok(SECRETS.insert_object(shared_ctx)?)
     }) {
         Ok(s) => s,
@@ -326,11 +358,14 @@ pub extern "C" fn ockam_vault_hkdf_sha256(
             let ctx = SECRETS.get_object(unsafe { *input_key_material })?;
             Some(ctx)
         };
-        let ikm_ctx = ikm_ctx.as_deref();
+        let ikm_ctx = ikm_ctx.as_ref().map(|ctx| ctx.as_ref().as_ref());
         let array: &[FfiSecretAttributes] =
             unsafe { slice::from_raw_parts(derived_outputs_attributes, derived_outputs_count) };
 
-        let output_attributes: Vec<SecretAttributes> = array.iter().map(|x| x.into()).collect();
+        let output_attributes = array
+            .iter()
+            .map(SecretAttributes::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
 
         // TODO: Hardcoded to be empty for now because any changes
         // to the C layer requires an API change.
@@ -344,7 +379,7 @@ pub extern "C" fn ockam_vault_hkdf_sha256(
         // Instead the vault could be encapsulated in channels and key exchanges.
         // Either way, I don't want to change the API until this decision is finalized.
         let hkdf_output: Result<Vec<SecretKeyHandle>, FfiOckamError> = v
-            .hkdf_sha256(&salt_ctx, b"", ikm_ctx, output_attributes)?
+            .hkdf_sha256(salt_ctx.as_ref().as_ref(), b"", ikm_ctx, output_attributes)?
             .into_iter()
             .map(|x| SECRETS.insert_object(x))
             .collect();
@@ -388,7 +423,12 @@ pub extern "C" fn ockam_vault_aead_aes_gcm_encrypt(
         let ctx = SECRETS.get_object(secret)?;
         let mut nonce_vec = vec![0; 12 - 2];
         nonce_vec.extend_from_slice(&nonce.to_be_bytes());
-        let ciphertext = v.aead_aes_gcm_encrypt(&ctx, plaintext, &nonce_vec, additional_data)?;
+        let ciphertext = v.aead_aes_gcm_encrypt(
+            ctx.as_ref().as_ref(),
+            plaintext,
+            &nonce_vec,
+            additional_data,
+        )?;
 
         if ciphertext_and_tag_size < ciphertext.len() as u32 {
             return Err(Error::BufferTooSmall.into());
@@ -430,8 +470,12 @@ pub extern "C" fn ockam_vault_aead_aes_gcm_decrypt(
         let ctx = SECRETS.get_object(secret)?;
         let mut nonce_vec = vec![0; 12 - 2];
         nonce_vec.extend_from_slice(&nonce.to_be_bytes());
-        let plain =
-            v.aead_aes_gcm_decrypt(&ctx, ciphertext_and_tag, &nonce_vec, additional_data)?;
+        let plain = v.aead_aes_gcm_decrypt(
+            ctx.as_ref().as_ref(),
+            ciphertext_and_tag,
+            &nonce_vec,
+            additional_data,
+        )?;
         if plaintext_size < plain.len() as u32 {
             return Err(Error::BufferTooSmall.into());
         }
@@ -444,6 +488,157 @@ pub extern "C" fn ockam_vault_aead_aes_gcm_decrypt(
     }
 }
 
+/// Encrypt a payload using ChaCha20-Poly1305. The 96-bit nonce is four zero bytes
+/// followed by `nonce` in little-endian, as in Noise.
+#[no_mangle]
+pub extern "C" fn ockam_vault_aead_chacha20_poly1305_encrypt(
+    context: FfiVaultFatPointer,
+    secret: SecretKeyHandle,
+    nonce: u64,
+    additional_data: *const u8,
+    additional_data_length: u32,
+    plaintext: *const u8,
+    plaintext_length: u32,
+    ciphertext_and_tag: &mut u8,
+    ciphertext_and_tag_size: u32,
+    ciphertext_and_tag_length: &mut u32,
+) -> FfiOckamError {
+    check_buffer!(additional_data);
+    check_buffer!(plaintext);
+    *ciphertext_and_tag_length = 0;
+    let additional_data =
+        unsafe { std::slice::from_raw_parts(additional_data, additional_data_length as usize) };
+    let plaintext = unsafe { std::slice::from_raw_parts(plaintext, plaintext_length as usize) };
+    match call(context, |v| -> Result<(), FfiOckamError> {
+        let ctx = SECRETS.get_object(secret)?;
+        let mut nonce_vec = vec![0; 12 - 8];
+        nonce_vec.extend_from_slice(&nonce.to_le_bytes());
+        let ciphertext = v.aead_chacha20_poly1305_encrypt(
+            ctx.as_ref().as_ref(),
+            plaintext,
+            &nonce_vec,
+            additional_data,
+        )?;
+
+        if ciphertext_and_tag_size < ciphertext.len() as u32 {
+            return Err(Error::BufferTooSmall.into());
+        }
+        *ciphertext_and_tag_length = ciphertext.len() as u32;
+        unsafe {
+            std::ptr::copy_nonoverlapping(ciphertext.as_ptr(), ciphertext_and_tag, ciphertext.len())
+        };
+        Ok(())
+    }) {
+        Ok(_) => FfiOckamError::none(),
+        Err(err) => err.into(),
+    }
+}
+
+/// Decrypt a payload using ChaCha20-Poly1305. The nonce is laid out as for
+/// `ockam_vault_aead_chacha20_poly1305_encrypt`.
+#[no_mangle]
+pub extern "C" fn ockam_vault_aead_chacha20_poly1305_decrypt(
+    context: FfiVaultFatPointer,
+    secret: SecretKeyHandle,
+    nonce: u64,
+    additional_data: *const u8,
+    additional_data_length: u32,
+    ciphertext_and_tag: *const u8,
+    ciphertext_and_tag_length: u32,
+    plaintext: &mut u8,
+    plaintext_size: u32,
+    plaintext_length: &mut u32,
+) -> FfiOckamError {
+    check_buffer!(ciphertext_and_tag, ciphertext_and_tag_length);
+    check_buffer!(additional_data);
+    *plaintext_length = 0;
+    let additional_data =
+        unsafe { std::slice::from_raw_parts(additional_data, additional_data_length as usize) };
+    let ciphertext_and_tag = unsafe {
+        std::slice::from_raw_parts(ciphertext_and_tag, ciphertext_and_tag_length as usize)
+    };
+    match call(context, |v| -> Result<(), FfiOckamError> {
+        let ctx = SECRETS.get_object(secret)?;
+        let mut nonce_vec = vec![0; 12 - 8];
+        nonce_vec.extend_from_slice(&nonce.to_le_bytes());
+        let plain = v.aead_chacha20_poly1305_decrypt(
+            ctx.as_ref().as_ref(),
+            ciphertext_and_tag,
+            &nonce_vec,
+            additional_data,
+        )?;
+        if plaintext_size < plain.len() as u32 {
+            return Err(Error::BufferTooSmall.into());
+        }
+        *plaintext_length = plain.len() as u32;
+        unsafe { std::ptr::copy_nonoverlapping(plain.as_ptr(), plaintext, plain.len()) };
+        Ok(())
+    }) {
+        Ok(_) => FfiOckamError::none(),
+        Err(err) => err.into(),
+    }
+}
+
+/// Sign `data` with the secret and put the result in `signature`. Curve25519 secrets sign
+/// with XEdDSA, Ed25519 secrets produce RFC 8032 signatures.
+/// `signature` must be 64 bytes in length
+#[no_mangle]
+pub extern "C" fn ockam_vault_sign(
+    context: FfiVaultFatPointer,
+    secret: SecretKeyHandle,
+    data: *const u8,
+    data_length: u32,
+    signature: *mut u8,
+) -> FfiOckamError {
+    check_buffer!(data);
+    check_buffer!(signature);
+
+    let data = unsafe { std::slice::from_raw_parts(data, data_length as usize) };
+    match call(context, |v| -> Result<(), FfiOckamError> {
+        let ctx = SECRETS.get_object(secret)?;
+        let sig = v.sign(ctx.as_ref().as_ref(), data)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(sig.as_ptr(), signature, sig.len());
+        }
+        Ok(())
+    }) {
+        Ok(_) => FfiOckamError::none(),
+        Err(err) => err.into(),
+    }
+}
+
+/// Verify the 64 byte `signature` of `data` made by the key of type `public_key_type` that
+/// `public_key` belongs to. Returns an error if the signature is invalid.
+#[no_mangle]
+pub extern "C" fn ockam_vault_verify(
+    context: FfiVaultFatPointer,
+    public_key_type: u32,
+    public_key: *const u8,
+    public_key_length: u32,
+    data: *const u8,
+    data_length: u32,
+    signature: *const u8,
+) -> FfiOckamError {
+    check_buffer!(public_key, public_key_length);
+    check_buffer!(data);
+    check_buffer!(signature);
+
+    let public_key_type: SecretType = match public_key_type.try_into() {
+        Ok(t) => t,
+        Err(_) => return Error::UnknownPublicKeyType.into(),
+    };
+    let public_key = unsafe { std::slice::from_raw_parts(public_key, public_key_length as usize) };
+    let data = unsafe { std::slice::from_raw_parts(data, data_length as usize) };
+    let signature = unsafe { &*(signature as *const [u8; 64]) };
+    match call(context, |v| -> Result<(), FfiOckamError> {
+        v.verify(signature, public_key, public_key_type, data)?;
+        Ok(())
+    }) {
+        Ok(_) => FfiOckamError::none(),
+        Err(err) => err.into(),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn ockam_vault_get_persistence_id(
     context: FfiVaultFatPointer,
@@ -453,7 +648,7 @@ pub extern "C" fn ockam_vault_get_persistence_id(
 ) -> FfiOckamError {
     match call(context, |v| -> Result<(), FfiOckamError> {
         let ctx = SECRETS.get_object(secret)?;
-        let persistence_id_str = v.get_persistence_id(&ctx)?;
+        let persistence_id_str = v.get_persistence_id(ctx.as_ref().as_ref())?;
         let persistence_id_str =
             CString::new(persistence_id_str).map_err(|_| Error::InvalidString)?;
         let persistence_id_str = persistence_id_str.as_bytes_with_nul();
@@ -499,11 +694,11 @@ pub extern "C" fn ockam_vault_get_persistent_secret(
 #[no_mangle]
 pub extern "C" fn ockam_vault_deinit(context: FfiVaultFatPointer) -> FfiOckamError {
     match context.vault_type {
-        FfiVaultType::Software => match DEFAULT_VAULTS.remove_object(context.handle) {
+        FfiVaultType::Software => match DEFAULT_VAULTS.drop_object(context.handle) {
             Ok(_) => FfiOckamError::none(),
             Err(_) => Error::VaultNotFound.into(),
         },
-        FfiVaultType::Filesystem => match FILESYSTEM_VAULTS.remove_object(context.handle) {
+        FfiVaultType::Filesystem => match FILESYSTEM_VAULTS.drop_object(context.handle) {
             Ok(_) => FfiOckamError::none(),
             Err(_) => Error::VaultNotFound.into(),
         },
@@ -1,7 +1,8 @@
 #![allow(conflicting_repr_hints)]
 
+use crate::error::{Error, FfiOckamError};
 use ockam_vault_software::ockam_vault::types::*;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
 #[derive(Clone, Copy, Debug)]
 #[repr(C, u8)]
@@ -23,6 +24,8 @@ pub struct FfiSecretAttributes {
     pub(crate) length: u32,
     pub(crate) xtype: u32,
     pub(crate) persistence: u32,
+    pub(crate) exportable: u32,
+    pub(crate) usage: u32,
 }
 
 impl From<SecretAttributes> for FfiSecretAttributes {
@@ -31,23 +34,41 @@ impl From<SecretAttributes> for FfiSecretAttributes {
             length: attrs.length as u32,
             xtype: attrs.stype.to_usize() as u32,
             persistence: attrs.persistence.to_usize() as u32,
+            exportable: attrs.exportable as u32,
+            usage: attrs.usage.bits() as u32,
         }
     }
 }
 
-impl From<FfiSecretAttributes> for SecretAttributes {
-    fn from(attrs: FfiSecretAttributes) -> Self {
-        Self::from(&attrs)
+impl TryFrom<FfiSecretAttributes> for SecretAttributes {
+    type Error = FfiOckamError;
+
+    fn try_from(attrs: FfiSecretAttributes) -> Result<Self, Self::Error> {
+        Self::try_from(&attrs)
     }
 }
 
-impl From<&FfiSecretAttributes> for SecretAttributes {
-    fn from(attrs: &FfiSecretAttributes) -> Self {
-        Self {
-            stype: attrs.xtype.try_into().unwrap(),
-            persistence: attrs.persistence.try_into().unwrap(),
+impl TryFrom<&FfiSecretAttributes> for SecretAttributes {
+    type Error = FfiOckamError;
+
+    fn try_from(attrs: &FfiSecretAttributes) -> Result<Self, Self::Error> {
+        let usage = u8::try_from(attrs.usage)
+            .ok()
+            .and_then(|bits| SecretUsage::from_bits(bits).ok())
+            .ok_or(Error::InvalidSecretAttributes)?;
+        Ok(Self {
+            stype: attrs
+                .xtype
+                .try_into()
+                .map_err(|_| Error::InvalidSecretAttributes)?,
+            persistence: attrs
+                .persistence
+                .try_into()
+                .map_err(|_| Error::InvalidSecretAttributes)?,
             length: attrs.length as usize,
-        }
+            exportable: attrs.exportable != 0,
+            usage,
+        })
     }
 }
 
@@ -0,0 +1,28 @@
+use ockam_common::error::OckamError;
+
+/// Represents the failures that can occur in
+/// an Ockam IK kex
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    /// None
+    None,
+    /// The key exchanger was called in the wrong state
+    InvalidState,
+    /// The vault returned an unexpected result
+    InternalVaultError,
+    /// A handshake message was too short
+    MessageLenMismatch,
+    /// The initiator was created without the responder's static public key
+    MissingRemoteStaticKey,
+}
+
+impl Error {
+    /// Error domain
+    pub const ERROR_DOMAIN: &'static str = "KEX_IK_ERROR_DOMAIN";
+}
+
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
+    }
+}
@@ -0,0 +1,1159 @@
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unconditional_recursion,
+    unused_import_braces,
+    unused_lifetimes,
+    unused_extern_crates,
+    unused_parens,
+    while_true
+)]
+//! Implements the Noise IK handshake for Ockam channels.
+//!
+//! IK needs the initiator to know the responder's static public key up front,
+//! in exchange the handshake completes after two messages instead of XX's three:
+//!
+//! ```text
+//! <- s
+//! ...
+//! -> e, es, s, ss
+//! <- e, ee, se
+//! ```
+
+use crate::error::Error;
+use ockam_common::error::OckamResult;
+use ockam_kex::{
+    ephemeral_secret, CipherSuite, CompletedKeyExchange, KeyExchange, KeyExchanger,
+    NewKeyExchanger, AES_GCM_TAGSIZE, SHA256_SIZE,
+};
+pub use ockam_kex_xx::XXVault;
+use ockam_vault::types::{
+    AES128_SECRET_LENGTH, AES256_SECRET_LENGTH, CHACHA20POLY1305_SECRET_LENGTH,
+    CURVE25519_SECRET_LENGTH, P256_SECRET_LENGTH,
+};
+use ockam_vault::{
+    types::{PublicKey, SecretAttributes, SecretPersistence, SecretType, SecretUsage},
+    Secret,
+};
+use std::sync::Arc;
+use zeroize::Zeroize;
+
+/// Errors thrown by the IK key exchange
+pub mod error;
+
+#[derive(Debug)]
+struct KeyPair {
+    public_key: PublicKey,
+    secret_handle: Box<dyn Secret>,
+}
+
+/// Represents the IK Handshake
+pub struct SymmetricState {
+    cipher_suite: CipherSuite,
+    identity_key: Option<Arc<Box<dyn Secret>>>,
+    identity_public_key: Option<PublicKey>,
+    ephemeral_key_pair: Option<KeyPair>,
+    remote_static_public_key: Option<PublicKey>,
+    remote_ephemeral_public_key: Option<PublicKey>,
+    key: Option<Box<dyn Secret>>,
+    nonce: u64,
+    h: Option<[u8; SHA256_SIZE]>,
+    ck: Option<Box<dyn Secret>>,
+    vault: Arc<dyn XXVault>,
+}
+
+impl Zeroize for SymmetricState {
+    fn zeroize(&mut self) {
+        self.nonce.zeroize();
+        self.h.zeroize();
+    }
+}
+
+impl std::fmt::Debug for SymmetricState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "SymmetricState {{ cipher_suite: {:?}, key: {:?}, nonce: {:?}, h: {:?}, ck: {:?} }}",
+            self.cipher_suite, self.key, self.nonce, self.h, self.ck
+        )
+    }
+}
+
+impl SymmetricState {
+    fn get_secret_key_type_and_length(&self) -> (SecretType, usize) {
+        match self.cipher_suite {
+            CipherSuite::Curve25519AesGcmSha256 => {
+                (SecretType::Curve25519, CURVE25519_SECRET_LENGTH)
+            }
+            CipherSuite::P256Aes128GcmSha256 => (SecretType::P256, P256_SECRET_LENGTH),
+            CipherSuite::Curve25519ChaChaPolySha256 => {
+                (SecretType::Curve25519, CURVE25519_SECRET_LENGTH)
+            }
+        }
+    }
+
+    fn get_symmetric_key_type_and_length(&self) -> (SecretType, usize) {
+        match self.cipher_suite {
+            CipherSuite::Curve25519AesGcmSha256 => (SecretType::Aes, AES256_SECRET_LENGTH),
+            CipherSuite::P256Aes128GcmSha256 => (SecretType::Aes, AES128_SECRET_LENGTH),
+            CipherSuite::Curve25519ChaChaPolySha256 => {
+                (SecretType::ChaCha20Poly1305, CHACHA20POLY1305_SECRET_LENGTH)
+            }
+        }
+    }
+
+    fn get_public_key_size(&self) -> usize {
+        match self.cipher_suite {
+            CipherSuite::Curve25519AesGcmSha256 => 32,
+            CipherSuite::P256Aes128GcmSha256 => 65,
+            CipherSuite::Curve25519ChaChaPolySha256 => 32,
+        }
+    }
+
+    /// The nonce is 4 bytes of 0's followed by the representation of n, be for
+    /// AES-GCM and le for ChaChaPoly
+    fn get_nonce(&self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        match self.cipher_suite {
+            CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => {
+                nonce[4..].copy_from_slice(&self.nonce.to_be_bytes())
+            }
+            CipherSuite::Curve25519ChaChaPolySha256 => {
+                nonce[4..].copy_from_slice(&self.nonce.to_le_bytes())
+            }
+        }
+        nonce
+    }
+
+    /// Create a new `SymmetricState`. `remote_static_public_key` is the responder's
+    /// static public key, it must be present for the initiator.
+    pub fn new(
+        cipher_suite: CipherSuite,
+        vault: Arc<dyn XXVault>,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+        remote_static_public_key: Option<PublicKey>,
+    ) -> Self {
+        Self {
+            cipher_suite,
+            identity_key,
+            identity_public_key: None,
+            ephemeral_key_pair: None,
+            remote_static_public_key,
+            remote_ephemeral_public_key: None,
+            key: None,
+            nonce: 0,
+            h: None,
+            ck: None,
+            vault,
+        }
+    }
+
+    /// Noise EncryptAndHash, just hashes the plaintext while there is no key yet
+    fn encrypt_and_hash<B: AsRef<[u8]>>(&mut self, plaintext: B) -> OckamResult<Vec<u8>> {
+        if self.key.is_none() {
+            self.mix_hash(plaintext.as_ref())?;
+            return Ok(plaintext.as_ref().to_vec());
+        }
+        self.encrypt_and_mix_hash(plaintext)
+    }
+
+    /// Noise DecryptAndHash, just hashes the ciphertext while there is no key yet
+    fn decrypt_and_hash<B: AsRef<[u8]>>(&mut self, ciphertext: B) -> OckamResult<Vec<u8>> {
+        if self.key.is_none() {
+            self.mix_hash(ciphertext.as_ref())?;
+            return Ok(ciphertext.as_ref().to_vec());
+        }
+        self.decrypt_and_mix_hash(ciphertext)
+    }
+}
+
+impl KeyExchange for SymmetricState {
+    fn get_protocol_name(&self) -> &'static [u8] {
+        match self.cipher_suite {
+            CipherSuite::Curve25519AesGcmSha256 => b"Noise_IK_25519_AESGCM_SHA256\0\0\0\0",
+            CipherSuite::P256Aes128GcmSha256 => b"Noise_IK_P256_AES128GCM_SHA256\0\0",
+            CipherSuite::Curve25519ChaChaPolySha256 => b"Noise_IK_25519_ChaChaPoly_SHA256",
+        }
+    }
+
+    /// Create a new `HandshakeState` starting with the prologue
+    fn prologue(&mut self) -> OckamResult<()> {
+        let asymmetric_secret_info = self.get_secret_key_type_and_length();
+
+        let mut attributes = SecretAttributes {
+            stype: asymmetric_secret_info.0,
+            persistence: SecretPersistence::Persistent,
+            length: asymmetric_secret_info.1,
+            exportable: false,
+            usage: SecretUsage::ECDH,
+        };
+        // 1. Generate a static key pair for this handshake and set it to `s`
+        let vault = &self.vault;
+        let identity_key = self.identity_key.take();
+        let identity_key = match identity_key {
+            None => {
+                let static_secret_handle = vault.secret_generate(attributes)?;
+                self.identity_public_key =
+                    Some(vault.secret_public_key_get(static_secret_handle.as_ref())?);
+                Arc::new(static_secret_handle)
+            }
+            Some(ik) => {
+                self.identity_public_key = Some(vault.secret_public_key_get(ik.as_ref().as_ref())?);
+                ik
+            }
+        };
+        self.identity_key = Some(identity_key);
+
+        attributes.persistence = SecretPersistence::Ephemeral;
+        // 2. Generate an ephemeral key pair for this handshake and set it to e
+        let ephemeral_secret_handle = ephemeral_secret(vault.as_ref(), attributes)?;
+        let ephemeral_public_key = vault.secret_public_key_get(ephemeral_secret_handle.as_ref())?;
+        self.ephemeral_key_pair = Some(KeyPair {
+            public_key: ephemeral_public_key,
+            secret_handle: ephemeral_secret_handle,
+        });
+
+        // 3. Set k to empty, Set n to 0
+        self.key = None;
+        self.nonce = 0;
+
+        // 4. Set h and ck to protocol name
+        // 5. h = SHA256(h || prologue),
+        // prologue is empty
+        let mut h = [0u8; SHA256_SIZE];
+        h[..self.get_protocol_name().len()].copy_from_slice(self.get_protocol_name());
+        let attributes = SecretAttributes {
+            stype: SecretType::Buffer,
+            persistence: SecretPersistence::Ephemeral,
+            length: SHA256_SIZE,
+            exportable: true,
+            usage: SecretUsage::HKDF,
+        };
+        self.ck = Some(vault.secret_import(&h, attributes)?);
+        self.h = Some(vault.sha256(&h)?);
+
+        Ok(())
+    }
+
+    /// Perform the diffie-hellman computation
+    fn dh(&mut self, secret_handle: &dyn Secret, public_key: &[u8]) -> OckamResult<()> {
+        let ck = self.ck.take().ok_or(Error::InvalidState)?;
+
+        let vault = &self.vault;
+
+        let attributes_ck = SecretAttributes {
+            stype: SecretType::Buffer,
+            persistence: SecretPersistence::Ephemeral,
+            length: SHA256_SIZE,
+            exportable: true,
+            usage: SecretUsage::HKDF,
+        };
+
+        let symmetric_secret_info = self.get_symmetric_key_type_and_length();
+
+        let attributes_k = SecretAttributes {
+            stype: symmetric_secret_info.0,
+            persistence: SecretPersistence::Ephemeral,
+            length: symmetric_secret_info.1,
+            exportable: true,
+            usage: SecretUsage::AEAD,
+        };
+
+        let ecdh = vault.ec_diffie_hellman(secret_handle, public_key)?;
+
+        let mut hkdf_output = vault.hkdf_sha256(
+            ck.as_ref(),
+            b"",
+            Some(ecdh.as_ref()),
+            vec![attributes_ck, attributes_k],
+        )?;
+        vault.secret_destroy(ecdh)?;
+
+        if hkdf_output.len() != 2 {
+            return Err(Error::InternalVaultError.into());
+        }
+
+        if let Some(key) = self.key.take() {
+            vault.secret_destroy(key)?;
+        }
+
+        self.key = Some(hkdf_output.pop().unwrap());
+
+        vault.secret_destroy(ck)?;
+        self.ck = Some(hkdf_output.pop().unwrap());
+
+        self.nonce = 0;
+
+        Ok(())
+    }
+
+    /// mix hash step in Noise protocol
+    fn mix_hash<B: AsRef<[u8]>>(&mut self, data: B) -> OckamResult<()> {
+        let h = &self.h.ok_or(Error::InvalidState)?;
+
+        let mut input = h.to_vec();
+        input.extend_from_slice(data.as_ref());
+        let vault = &self.vault;
+        self.h = Some(vault.sha256(&input)?);
+        Ok(())
+    }
+
+    /// Encrypt and mix step in Noise protocol
+    fn encrypt_and_mix_hash<B: AsRef<[u8]>>(&mut self, plaintext: B) -> OckamResult<Vec<u8>> {
+        let h = &self.h.ok_or(Error::InvalidState)?;
+
+        let nonce = self.get_nonce();
+        let ciphertext_and_tag = {
+            let vault = &self.vault;
+            let key = self.key.as_ref().ok_or(Error::InvalidState)?;
+            match self.cipher_suite {
+                CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => vault
+                    .aead_aes_gcm_encrypt(key.as_ref(), plaintext.as_ref(), nonce.as_ref(), h)?,
+                CipherSuite::Curve25519ChaChaPolySha256 => vault.aead_chacha20_poly1305_encrypt(
+                    key.as_ref(),
+                    plaintext.as_ref(),
+                    nonce.as_ref(),
+                    h,
+                )?,
+            }
+        };
+        self.mix_hash(&ciphertext_and_tag)?;
+        self.nonce += 1;
+        Ok(ciphertext_and_tag)
+    }
+
+    /// Decrypt and mix step in Noise protocol
+    fn decrypt_and_mix_hash<B: AsRef<[u8]>>(&mut self, ciphertext: B) -> OckamResult<Vec<u8>> {
+        let h = &self.h.ok_or(Error::InvalidState)?;
+
+        let nonce = self.get_nonce();
+        let ciphertext = ciphertext.as_ref();
+        let plaintext = {
+            let vault = &self.vault;
+            let key = self.key.as_ref().ok_or(Error::InvalidState)?;
+            match self.cipher_suite {
+                CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => {
+                    vault.aead_aes_gcm_decrypt(key.as_ref(), ciphertext, nonce.as_ref(), h)?
+                }
+                CipherSuite::Curve25519ChaChaPolySha256 => vault.aead_chacha20_poly1305_decrypt(
+                    key.as_ref(),
+                    ciphertext,
+                    nonce.as_ref(),
+                    h,
+                )?,
+            }
+        };
+        self.mix_hash(ciphertext)?;
+        self.nonce += 1;
+        Ok(plaintext)
+    }
+
+    /// Split step in Noise protocol
+    fn split(&mut self) -> OckamResult<(Box<dyn Secret>, Box<dyn Secret>)> {
+        let ck = self.ck.as_ref().ok_or(Error::InvalidState)?;
+
+        let vault = &self.vault;
+        let symmetric_key_info = self.get_symmetric_key_type_and_length();
+        let attributes = SecretAttributes {
+            stype: symmetric_key_info.0,
+            persistence: SecretPersistence::Ephemeral,
+            length: symmetric_key_info.1,
+            exportable: true,
+            // channels rekey by using the key as HKDF salt
+            usage: SecretUsage::AEAD | SecretUsage::HKDF,
+        };
+        let mut hkdf_output =
+            vault.hkdf_sha256(ck.as_ref(), b"", None, vec![attributes, attributes])?;
+
+        if hkdf_output.len() != 2 {
+            return Err(Error::InternalVaultError.into());
+        }
+
+        let res1 = hkdf_output.pop().unwrap();
+        let res0 = hkdf_output.pop().unwrap();
+
+        Ok((res0, res1))
+    }
+
+    /// Set this state up to send and receive messages
+    fn finalize(
+        self,
+        encrypt_key: Box<dyn Secret>,
+        decrypt_key: Box<dyn Secret>,
+    ) -> OckamResult<CompletedKeyExchange> {
+        let h = self.h.ok_or(Error::InvalidState)?;
+
+        let local_static_secret = self.identity_key.ok_or(Error::InvalidState)?;
+
+        let remote_static_public_key = self.remote_static_public_key.ok_or(Error::InvalidState)?;
+
+        Ok(CompletedKeyExchange {
+            h,
+            encrypt_key,
+            decrypt_key,
+            local_static_secret,
+            remote_static_public_key,
+        })
+    }
+}
+
+/// Provides methods for handling the initiator role
+#[derive(Debug)]
+struct Initiator(SymmetricState);
+
+impl Initiator {
+    /// The responder's static public key is a pre-message, mix it in right after
+    /// the prologue
+    fn mix_pre_message(&mut self) -> OckamResult<()> {
+        let rs = self
+            .0
+            .remote_static_public_key
+            .clone()
+            .ok_or(Error::MissingRemoteStaticKey)?;
+        self.0.mix_hash(rs.as_ref())
+    }
+
+    /// Encode the first message to be sent, `e, es, s, ss`
+    pub fn encode_message_1<B: AsRef<[u8]>>(&mut self, payload: B) -> OckamResult<Vec<u8>> {
+        let t = &mut self.0;
+        let ephemeral_key_pair = t.ephemeral_key_pair.take().ok_or(Error::InvalidState)?;
+        let static_secret = t.identity_key.take().ok_or(Error::InvalidState)?;
+        let static_public = t.identity_public_key.clone().ok_or(Error::InvalidState)?;
+        let rs = t
+            .remote_static_public_key
+            .clone()
+            .ok_or(Error::MissingRemoteStaticKey)?;
+
+        t.mix_hash(ephemeral_key_pair.public_key.as_ref())?;
+        t.dh(ephemeral_key_pair.secret_handle.as_ref(), rs.as_ref())?;
+        let mut encrypted_s_and_tag = t.encrypt_and_mix_hash(static_public.as_ref())?;
+        t.dh(static_secret.as_ref().as_ref(), rs.as_ref())?;
+        t.identity_key = Some(static_secret);
+        let mut encrypted_payload_and_tag = t.encrypt_and_mix_hash(payload)?;
+
+        let mut output = ephemeral_key_pair.public_key.as_ref().to_vec();
+        t.ephemeral_key_pair = Some(ephemeral_key_pair);
+        output.append(&mut encrypted_s_and_tag);
+        output.append(&mut encrypted_payload_and_tag);
+        Ok(output)
+    }
+
+    /// Decode the second message in the sequence, `e, ee, se`, sent from the responder
+    pub fn decode_message_2<B: AsRef<[u8]>>(&mut self, message: B) -> OckamResult<Vec<u8>> {
+        let t = &mut self.0;
+        let public_key_size = t.get_public_key_size();
+        let message = message.as_ref();
+        if message.len() < public_key_size + AES_GCM_TAGSIZE {
+            return Err(Error::MessageLenMismatch.into());
+        }
+
+        let ephemeral_key_pair = t.ephemeral_key_pair.take().ok_or(Error::InvalidState)?;
+        let static_secret = t.identity_key.take().ok_or(Error::InvalidState)?;
+
+        let re = PublicKey::new(message[..public_key_size].to_vec());
+        t.mix_hash(re.as_ref())?;
+        t.dh(ephemeral_key_pair.secret_handle.as_ref(), re.as_ref())?;
+        t.dh(static_secret.as_ref().as_ref(), re.as_ref())?;
+        t.remote_ephemeral_public_key = Some(re);
+        t.identity_key = Some(static_secret);
+        t.ephemeral_key_pair = Some(ephemeral_key_pair);
+
+        let payload = t.decrypt_and_hash(&message[public_key_size..])?;
+        Ok(payload)
+    }
+
+    /// Setup this initiator to send and receive messages
+    /// after decoding message 2
+    pub fn finalize(mut self) -> OckamResult<CompletedKeyExchange> {
+        let keys = self.0.split()?;
+        self.0.finalize(keys.0, keys.1)
+    }
+}
+
+/// Provides methods for handling the responder role
+#[derive(Debug)]
+struct Responder(SymmetricState);
+
+impl Responder {
+    /// The responder's own static public key is a pre-message, mix it in right after
+    /// the prologue
+    fn mix_pre_message(&mut self) -> OckamResult<()> {
+        let s = self
+            .0
+            .identity_public_key
+            .clone()
+            .ok_or(Error::InvalidState)?;
+        self.0.mix_hash(s.as_ref())
+    }
+
+    /// Decode the first message sent, `e, es, s, ss`
+    pub fn decode_message_1<B: AsRef<[u8]>>(&mut self, message_1: B) -> OckamResult<Vec<u8>> {
+        let t = &mut self.0;
+        let public_key_size = t.get_public_key_size();
+        let message_1 = message_1.as_ref();
+        if message_1.len() < 2 * public_key_size + 2 * AES_GCM_TAGSIZE {
+            return Err(Error::MessageLenMismatch.into());
+        }
+
+        let static_secret = t.identity_key.take().ok_or(Error::InvalidState)?;
+
+        let mut index_l = 0;
+        let mut index_r = public_key_size;
+        let re = PublicKey::new(message_1[index_l..index_r].to_vec());
+        index_l += public_key_size;
+        index_r += public_key_size + AES_GCM_TAGSIZE;
+        let encrypted_rs_and_tag = &message_1[index_l..index_r];
+        let encrypted_payload_and_tag = &message_1[index_r..];
+
+        t.mix_hash(re.as_ref())?;
+        t.dh(static_secret.as_ref().as_ref(), re.as_ref())?;
+        let rs = PublicKey::new(t.decrypt_and_mix_hash(encrypted_rs_and_tag)?);
+        t.dh(static_secret.as_ref().as_ref(), rs.as_ref())?;
+        t.identity_key = Some(static_secret);
+        t.remote_ephemeral_public_key = Some(re);
+        t.remote_static_public_key = Some(rs);
+
+        let payload = t.decrypt_and_mix_hash(encrypted_payload_and_tag)?;
+        Ok(payload)
+    }
+
+    /// Encode the second message to be sent, `e, ee, se`
+    pub fn encode_message_2<B: AsRef<[u8]>>(&mut self, payload: B) -> OckamResult<Vec<u8>> {
+        let t = &mut self.0;
+        let ephemeral_key_pair = t.ephemeral_key_pair.take().ok_or(Error::InvalidState)?;
+        let re = t
+            .remote_ephemeral_public_key
+            .clone()
+            .ok_or(Error::InvalidState)?;
+        let rs = t
+            .remote_static_public_key
+            .clone()
+            .ok_or(Error::InvalidState)?;
+
+        t.mix_hash(ephemeral_key_pair.public_key.as_ref())?;
+        t.dh(ephemeral_key_pair.secret_handle.as_ref(), re.as_ref())?;
+        t.dh(ephemeral_key_pair.secret_handle.as_ref(), rs.as_ref())?;
+        let mut encrypted_payload_and_tag = t.encrypt_and_hash(payload)?;
+
+        let mut output = ephemeral_key_pair.public_key.as_ref().to_vec();
+        t.ephemeral_key_pair = Some(ephemeral_key_pair);
+        output.append(&mut encrypted_payload_and_tag);
+        Ok(output)
+    }
+
+    /// Setup this responder to send and receive messages
+    /// after encoding message 2
+    pub fn finalize(mut self) -> OckamResult<CompletedKeyExchange> {
+        let keys = self.0.split()?;
+        self.0.finalize(keys.1, keys.0)
+    }
+}
+
+/// The states the connection IK pattern initiator completes
+#[derive(Debug)]
+enum InitiatorState {
+    /// Run encode message 1
+    EncodeMessage1,
+    /// Run decode message 2
+    DecodeMessage2,
+    /// Finished
+    Done,
+}
+
+/// The states the connection IK pattern responder completes
+#[derive(Debug)]
+enum ResponderState {
+    /// Run decode message 1
+    DecodeMessage1,
+    /// Run encode message 2
+    EncodeMessage2,
+    /// Finished
+    Done,
+}
+
+/// Represents an IK initiator
+#[derive(Debug)]
+pub struct IKInitiator {
+    state: InitiatorState,
+    initiator: Initiator,
+    run_prologue: bool,
+}
+
+impl IKInitiator {
+    /// Create a new IK initiator, `symmetric_state` must hold the responder's
+    /// static public key
+    pub fn new(symmetric_state: SymmetricState, run_prologue: bool) -> Self {
+        IKInitiator {
+            state: InitiatorState::EncodeMessage1,
+            initiator: Initiator(symmetric_state),
+            run_prologue,
+        }
+    }
+}
+
+/// Represents an IK responder
+#[derive(Debug)]
+pub struct IKResponder {
+    state: ResponderState,
+    responder: Responder,
+    run_prologue: bool,
+}
+
+impl IKResponder {
+    /// Create a new IK responder
+    pub fn new(symmetric_state: SymmetricState, run_prologue: bool) -> Self {
+        IKResponder {
+            state: ResponderState::DecodeMessage1,
+            responder: Responder(symmetric_state),
+            run_prologue,
+        }
+    }
+}
+
+/// Represents an IK NewKeyExchanger
+pub struct IKNewKeyExchanger {
+    cipher_suite: CipherSuite,
+    remote_static_public_key: Option<PublicKey>,
+    vault_initiator: Arc<dyn XXVault>,
+    vault_responder: Arc<dyn XXVault>,
+}
+
+impl std::fmt::Debug for IKNewKeyExchanger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.cipher_suite.fmt(f)
+    }
+}
+
+impl IKNewKeyExchanger {
+    /// Create a new IKNewKeyExchanger. Initiators created by it will only be able to
+    /// talk to the responder owning `remote_static_public_key`, responders don't need it.
+    pub fn new(
+        cipher_suite: CipherSuite,
+        remote_static_public_key: Option<PublicKey>,
+        vault_initiator: Arc<dyn XXVault>,
+        vault_responder: Arc<dyn XXVault>,
+    ) -> Self {
+        Self {
+            cipher_suite,
+            remote_static_public_key,
+            vault_initiator,
+            vault_responder,
+        }
+    }
+}
+
+impl NewKeyExchanger<IKInitiator, IKResponder> for IKNewKeyExchanger {
+    /// Create a new initiator using the provided backing vault
+    fn initiator(&self, identity_key: Option<Arc<Box<dyn Secret>>>) -> IKInitiator {
+        let ss = SymmetricState::new(
+            self.cipher_suite,
+            self.vault_initiator.clone(),
+            identity_key,
+            self.remote_static_public_key.clone(),
+        );
+        IKInitiator::new(ss, true)
+    }
+
+    /// Create a new responder using the provided backing vault
+    fn responder(&self, identity_key: Option<Arc<Box<dyn Secret>>>) -> IKResponder {
+        let ss = SymmetricState::new(
+            self.cipher_suite,
+            self.vault_responder.clone(),
+            identity_key,
+            None,
+        );
+        IKResponder::new(ss, true)
+    }
+}
+
+impl KeyExchanger for IKInitiator {
+    fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+        match self.state {
+            InitiatorState::EncodeMessage1 => {
+                if self.run_prologue {
+                    self.initiator.0.prologue()?;
+                    self.initiator.mix_pre_message()?;
+                }
+                let msg = self.initiator.encode_message_1(data)?;
+                self.state = InitiatorState::DecodeMessage2;
+                Ok(msg)
+            }
+            InitiatorState::DecodeMessage2 => {
+                let msg = self.initiator.decode_message_2(data)?;
+                self.state = InitiatorState::Done;
+                Ok(msg)
+            }
+            InitiatorState::Done => Ok(vec![]),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.state, InitiatorState::Done)
+    }
+
+    fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange> {
+        match self.state {
+            InitiatorState::Done => self.initiator.finalize(),
+            _ => Err(Error::InvalidState.into()),
+        }
+    }
+}
+
+impl KeyExchanger for IKResponder {
+    fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+        match self.state {
+            ResponderState::DecodeMessage1 => {
+                if self.run_prologue {
+                    self.responder.0.prologue()?;
+                    self.responder.mix_pre_message()?;
+                }
+                let msg = self.responder.decode_message_1(data)?;
+                self.state = ResponderState::EncodeMessage2;
+                Ok(msg)
+            }
+            ResponderState::EncodeMessage2 => {
+                let msg = self.responder.encode_message_2(data)?;
+                self.state = ResponderState::Done;
+                Ok(msg)
+            }
+            ResponderState::Done => Ok(vec![]),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.state, ResponderState::Done)
+    }
+
+    fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange> {
+        match self.state {
+            ResponderState::Done => self.responder.finalize(),
+            _ => Err(Error::InvalidState.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam_vault::{SecretVault, SymmetricVault};
+    use ockam_vault_software::DefaultVault;
+
+    const INIT_STATIC: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const INIT_EPH: &str = "202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f";
+    const RESP_STATIC: &str = "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+    const RESP_EPH: &str = "4142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f60";
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn full_flow__correct_credentials__keys_should_match() {
+        let vault_initiator = Arc::new(DefaultVault::default());
+        let vault_responder = Arc::new(DefaultVault::default());
+
+        let responder_identity = vault_responder
+            .secret_generate(SecretAttributes {
+                stype: SecretType::P256,
+                persistence: SecretPersistence::Ephemeral,
+                length: P256_SECRET_LENGTH,
+                exportable: true,
+                usage: SecretUsage::ALL,
+            })
+            .unwrap();
+        let responder_public_key = vault_responder
+            .secret_public_key_get(responder_identity.as_ref())
+            .unwrap();
+
+        let key_exchanger = IKNewKeyExchanger::new(
+            CipherSuite::P256Aes128GcmSha256,
+            Some(responder_public_key),
+            vault_initiator.clone(),
+            vault_responder.clone(),
+        );
+
+        let mut initiator = key_exchanger.initiator(None);
+        let mut responder = key_exchanger.responder(Some(Arc::new(responder_identity)));
+
+        let m1 = initiator.process(&[]).unwrap();
+        let _ = responder.process(&m1).unwrap();
+        let m2 = responder.process(&[]).unwrap();
+        let _ = initiator.process(&m2).unwrap();
+        assert!(initiator.is_complete());
+        assert!(responder.is_complete());
+
+        let initiator = Box::new(initiator).finalize().unwrap();
+        let responder = Box::new(responder).finalize().unwrap();
+
+        assert_eq!(initiator.h, responder.h);
+
+        let s1 = vault_initiator
+            .secret_export(initiator.encrypt_key.as_ref())
+            .unwrap();
+        let s2 = vault_responder
+            .secret_export(responder.decrypt_key.as_ref())
+            .unwrap();
+        assert_eq!(s1, s2);
+
+        let s1 = vault_initiator
+            .secret_export(initiator.decrypt_key.as_ref())
+            .unwrap();
+        let s2 = vault_responder
+            .secret_export(responder.encrypt_key.as_ref())
+            .unwrap();
+        assert_eq!(s1, s2);
+    }
+
+    #[test]
+    fn initiator_requires_remote_static_key() {
+        let vault = Arc::new(DefaultVault::default());
+        let key_exchanger = IKNewKeyExchanger::new(
+            CipherSuite::Curve25519AesGcmSha256,
+            None,
+            vault.clone(),
+            vault,
+        );
+        let mut initiator = key_exchanger.initiator(None);
+        assert!(initiator.process(&[]).is_err());
+    }
+
+    #[test]
+    fn wrong_responder_fails() {
+        let vault_init = Arc::new(DefaultVault::default());
+        let vault_resp = Arc::new(DefaultVault::default());
+
+        // the initiator expects a responder with RESP_STATIC, but gets INIT_STATIC
+        let ss_init = mock_prologue(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault_init,
+            INIT_STATIC,
+            INIT_EPH,
+            Some(RESP_STATIC),
+        );
+        let ss_resp = mock_prologue(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault_resp,
+            INIT_STATIC,
+            RESP_EPH,
+            None,
+        );
+        let mut initiator = IKInitiator::new(ss_init, false);
+        let mut responder = IKResponder::new(ss_resp, false);
+
+        let m1 = initiator.process(&[]).unwrap();
+        assert!(responder.process(&m1).is_err());
+    }
+
+    #[test]
+    fn handshake_1() {
+        const MSG_1_PAYLOAD: &str = "";
+        const MSG_1_CIPHERTEXT: &str = "358072d6365880d1aeea329adf9121383851ed21a28e3b75e965d0d2cd16625419d6fab175300a577115c701c41ed681373f0432f81d3bf8676bd05216cd1919ba2eaa418fdd8e09ae59d7cf57869de42789c3b9ca915c2cacf009f9d0e4436e";
+        const MSG_2_PAYLOAD: &str = "";
+        const MSG_2_CIPHERTEXT: &str =
+            "64b101b1d0be5a8704bd078f9895001fc03e8e9f9522f188dd128d9846d4846623c019a124da3f096e964fe624cf65db";
+        const H: &str = "523d4c4b634988b79bb5abc6f04eff601302956583267473ea5f9d81ff99dc7e";
+
+        mock_handshake(
+            CipherSuite::Curve25519AesGcmSha256,
+            MSG_1_PAYLOAD,
+            MSG_1_CIPHERTEXT,
+            MSG_2_PAYLOAD,
+            MSG_2_CIPHERTEXT,
+            H,
+        );
+    }
+
+    #[test]
+    fn handshake_2() {
+        const MSG_1_PAYLOAD: &str = "746573745f6d73675f30";
+        const MSG_1_CIPHERTEXT: &str = "358072d6365880d1aeea329adf9121383851ed21a28e3b75e965d0d2cd16625419d6fab175300a577115c701c41ed681373f0432f81d3bf8676bd05216cd1919ba2eaa418fdd8e09ae59d7cf57869de4e6d8177aa9777fe9b843100e255aee76034f61b96b52af38660c";
+        const MSG_2_PAYLOAD: &str = "746573745f6d73675f31";
+        const MSG_2_CIPHERTEXT: &str = "64b101b1d0be5a8704bd078f9895001fc03e8e9f9522f188dd128d9846d4846658a7bb8caac509783390e5a04df4a3ca570b2bcdf65f8c1c40cd";
+        const H: &str = "d5ae390a41e4f0f2d4ea9f46ecad2826f44aa7f01fdd6e5d3a39a46c939f644e";
+
+        mock_handshake(
+            CipherSuite::Curve25519AesGcmSha256,
+            MSG_1_PAYLOAD,
+            MSG_1_CIPHERTEXT,
+            MSG_2_PAYLOAD,
+            MSG_2_CIPHERTEXT,
+            H,
+        );
+    }
+
+    #[test]
+    fn transport_messages() {
+        const MSG_3_PAYLOAD: &str = "746573745f6d73675f33";
+        const MSG_3_CIPHERTEXT: &str = "8da7416df8f2d2fbfff898100e6a790aa64f55e0e83e3c609175";
+        const MSG_4_PAYLOAD: &str = "746573745f6d73675f34";
+        const MSG_4_CIPHERTEXT: &str = "bf574509e47db38da99ed7265f1db61501b6a7f6e5e17ec18bbf";
+
+        let vault_init = Arc::new(DefaultVault::default());
+        let vault_resp = Arc::new(DefaultVault::default());
+        let (alice, bob) = mock_handshake_keys(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault_init.clone(),
+            vault_resp.clone(),
+        );
+
+        let ciphertext = vault_init
+            .aead_aes_gcm_encrypt(
+                alice.encrypt_key.as_ref(),
+                &hex::decode(MSG_3_PAYLOAD).unwrap(),
+                &[0u8; 12],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(hex::encode(&ciphertext), MSG_3_CIPHERTEXT);
+        let plaintext = vault_resp
+            .aead_aes_gcm_decrypt(bob.decrypt_key.as_ref(), &ciphertext, &[0u8; 12], &[])
+            .unwrap();
+        assert_eq!(hex::encode(plaintext), MSG_3_PAYLOAD);
+
+        let ciphertext = vault_resp
+            .aead_aes_gcm_encrypt(
+                bob.encrypt_key.as_ref(),
+                &hex::decode(MSG_4_PAYLOAD).unwrap(),
+                &[0u8; 12],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(hex::encode(&ciphertext), MSG_4_CIPHERTEXT);
+        let plaintext = vault_init
+            .aead_aes_gcm_decrypt(alice.decrypt_key.as_ref(), &ciphertext, &[0u8; 12], &[])
+            .unwrap();
+        assert_eq!(hex::encode(plaintext), MSG_4_PAYLOAD);
+    }
+
+    #[test]
+    fn handshake_chachapoly_1() {
+        const MSG_1_PAYLOAD: &str = "";
+        const MSG_1_CIPHERTEXT: &str = "358072d6365880d1aeea329adf9121383851ed21a28e3b75e965d0d2cd1662544f8445e5dc2467b1e32653192d05dee85c4781bf0dd8d33ceebb5905a7a069f09e0d3f2cad1c842930a762eb75e52827f01d2c85189d527644b3221b4c3fc5cc";
+        const MSG_2_PAYLOAD: &str = "";
+        const MSG_2_CIPHERTEXT: &str =
+            "64b101b1d0be5a8704bd078f9895001fc03e8e9f9522f188dd128d9846d48466aabfe2e5b1650bbaa88e33679893fc77";
+        const H: &str = "9da189270442085c36a743adc71190abe2f609a81be9c434acfb3bea90e33ed1";
+
+        mock_handshake(
+            CipherSuite::Curve25519ChaChaPolySha256,
+            MSG_1_PAYLOAD,
+            MSG_1_CIPHERTEXT,
+            MSG_2_PAYLOAD,
+            MSG_2_CIPHERTEXT,
+            H,
+        );
+    }
+
+    #[test]
+    fn handshake_chachapoly_2() {
+        const MSG_1_PAYLOAD: &str = "746573745f6d73675f30";
+        const MSG_1_CIPHERTEXT: &str = "358072d6365880d1aeea329adf9121383851ed21a28e3b75e965d0d2cd1662544f8445e5dc2467b1e32653192d05dee85c4781bf0dd8d33ceebb5905a7a069f09e0d3f2cad1c842930a762eb75e528270337527f958f92050deefa1892482d74328fee90d08201bba3cc";
+        const MSG_2_PAYLOAD: &str = "746573745f6d73675f31";
+        const MSG_2_CIPHERTEXT: &str = "64b101b1d0be5a8704bd078f9895001fc03e8e9f9522f188dd128d9846d48466cb4a35db52355821787bb891112ba10f4d3dfe08b27d634db8af";
+        const H: &str = "a45384e03775b01f8e02afe5af7262e461e173eedb8584746694c8f269e4f959";
+
+        mock_handshake(
+            CipherSuite::Curve25519ChaChaPolySha256,
+            MSG_1_PAYLOAD,
+            MSG_1_CIPHERTEXT,
+            MSG_2_PAYLOAD,
+            MSG_2_CIPHERTEXT,
+            H,
+        );
+    }
+
+    #[test]
+    fn transport_messages_chachapoly() {
+        const MSG_3_PAYLOAD: &str = "746573745f6d73675f33";
+        const MSG_3_CIPHERTEXT: &str = "2f6cb771c26d7603ce2d53c806ff38705df4256ad978ff6cb4cc";
+        const MSG_4_PAYLOAD: &str = "746573745f6d73675f34";
+        const MSG_4_CIPHERTEXT: &str = "97c85c70f35b989bb9fdec704f025e48ab00a3dd602fe47b99ae";
+
+        let vault_init = Arc::new(DefaultVault::default());
+        let vault_resp = Arc::new(DefaultVault::default());
+        let (alice, bob) = mock_handshake_keys(
+            CipherSuite::Curve25519ChaChaPolySha256,
+            vault_init.clone(),
+            vault_resp.clone(),
+        );
+
+        let ciphertext = vault_init
+            .aead_chacha20_poly1305_encrypt(
+                alice.encrypt_key.as_ref(),
+                &hex::decode(MSG_3_PAYLOAD).unwrap(),
+                &[0u8; 12],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(hex::encode(&ciphertext), MSG_3_CIPHERTEXT);
+        let plaintext = vault_resp
+            .aead_chacha20_poly1305_decrypt(bob.decrypt_key.as_ref(), &ciphertext, &[0u8; 12], &[])
+            .unwrap();
+        assert_eq!(hex::encode(plaintext), MSG_3_PAYLOAD);
+
+        let ciphertext = vault_resp
+            .aead_chacha20_poly1305_encrypt(
+                bob.encrypt_key.as_ref(),
+                &hex::decode(MSG_4_PAYLOAD).unwrap(),
+                &[0u8; 12],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(hex::encode(&ciphertext), MSG_4_CIPHERTEXT);
+        let plaintext = vault_init
+            .aead_chacha20_poly1305_decrypt(
+                alice.decrypt_key.as_ref(),
+                &ciphertext,
+                &[0u8; 12],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(hex::encode(plaintext), MSG_4_PAYLOAD);
+    }
+
+    fn mock_handshake(
+        cipher_suite: CipherSuite,
+        msg_1_payload: &str,
+        msg_1_ciphertext: &str,
+        msg_2_payload: &str,
+        msg_2_ciphertext: &str,
+        h: &str,
+    ) {
+        let vault_init = Arc::new(DefaultVault::default());
+        let vault_resp = Arc::new(DefaultVault::default());
+
+        let ss_init = mock_prologue(
+            cipher_suite,
+            vault_init,
+            INIT_STATIC,
+            INIT_EPH,
+            Some(RESP_STATIC),
+        );
+        let ss_resp = mock_prologue(cipher_suite, vault_resp, RESP_STATIC, RESP_EPH, None);
+        let mut initiator = Initiator(ss_init);
+        let mut responder = Responder(ss_resp);
+
+        let msg1 = initiator
+            .encode_message_1(hex::decode(msg_1_payload).unwrap())
+            .unwrap();
+        assert_eq!(hex::encode(&msg1), msg_1_ciphertext);
+
+        let payload = responder.decode_message_1(msg1).unwrap();
+        assert_eq!(hex::encode(payload), msg_1_payload);
+
+        let msg2 = responder
+            .encode_message_2(hex::decode(msg_2_payload).unwrap())
+            .unwrap();
+        assert_eq!(hex::encode(&msg2), msg_2_ciphertext);
+
+        let payload = initiator.decode_message_2(msg2).unwrap();
+        assert_eq!(hex::encode(payload), msg_2_payload);
+
+        let alice = initiator.finalize().unwrap();
+        let bob = responder.finalize().unwrap();
+        assert_eq!(hex::encode(alice.h), h);
+        assert_eq!(alice.h, bob.h);
+    }
+
+    fn mock_handshake_keys(
+        cipher_suite: CipherSuite,
+        vault_init: Arc<dyn XXVault>,
+        vault_resp: Arc<dyn XXVault>,
+    ) -> (CompletedKeyExchange, CompletedKeyExchange) {
+        let ss_init = mock_prologue(
+            cipher_suite,
+            vault_init,
+            INIT_STATIC,
+            INIT_EPH,
+            Some(RESP_STATIC),
+        );
+        let ss_resp = mock_prologue(cipher_suite, vault_resp, RESP_STATIC, RESP_EPH, None);
+        let mut initiator = IKInitiator::new(ss_init, false);
+        let mut responder = IKResponder::new(ss_resp, false);
+
+        let msg1 = initiator.process(&[]).unwrap();
+        responder.process(&msg1).unwrap();
+        let msg2 = responder.process(&[]).unwrap();
+        initiator.process(&msg2).unwrap();
+
+        (
+            Box::new(initiator).finalize().unwrap(),
+            Box::new(responder).finalize().unwrap(),
+        )
+    }
+
+    fn mock_prologue(
+        cipher_suite: CipherSuite,
+        vault: Arc<dyn XXVault>,
+        static_private: &str,
+        ephemeral_private: &str,
+        remote_static_private: Option<&str>,
+    ) -> SymmetricState {
+        let attributes = SecretAttributes {
+            stype: SecretType::Curve25519,
+            persistence: SecretPersistence::Ephemeral,
+            length: CURVE25519_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
+        };
+        // Static x25519 for this handshake, `s`
+        let static_secret_handle = vault
+            .secret_import(&hex::decode(static_private).unwrap(), attributes)
+            .unwrap();
+        let static_public_key = vault
+            .secret_public_key_get(static_secret_handle.as_ref())
+            .unwrap();
+
+        // Ephemeral x25519 for this handshake, `e`
+        let ephemeral_secret_handle = vault
+            .secret_import(&hex::decode(ephemeral_private).unwrap(), attributes)
+            .unwrap();
+        let ephemeral_public_key = vault
+            .secret_public_key_get(ephemeral_secret_handle.as_ref())
+            .unwrap();
+
+        // The responder's static public key, `rs`, known to the initiator in advance
+        let remote_static_public_key = remote_static_private.map(|rs| {
+            let handle = vault
+                .secret_import(&hex::decode(rs).unwrap(), attributes)
+                .unwrap();
+            vault.secret_public_key_get(handle.as_ref()).unwrap()
+        });
+
+        // h = SHA256(protocol_name || prologue), prologue is empty,
+        // followed by the pre-message h = SHA256(h || rs)
+        let ck = SymmetricState::new(cipher_suite, vault.clone(), None, None).get_protocol_name();
+        let h = vault.sha256(ck).unwrap();
+        let pre_message = match &remote_static_public_key {
+            Some(rs) => rs.as_ref().to_vec(),
+            None => static_public_key.as_ref().to_vec(),
+        };
+        let mut input = h.to_vec();
+        input.extend_from_slice(&pre_message);
+        let h = vault.sha256(&input).unwrap();
+
+        let attributes = SecretAttributes {
+            stype: SecretType::Buffer,
+            persistence: SecretPersistence::Ephemeral,
+            length: ck.len(),
+            exportable: true,
+            usage: SecretUsage::ALL,
+        };
+        let ck = vault.secret_import(ck, attributes).unwrap();
+        SymmetricState {
+            cipher_suite,
+            identity_public_key: Some(static_public_key),
+            ephemeral_key_pair: Some(KeyPair {
+                public_key: ephemeral_public_key,
+                secret_handle: ephemeral_secret_handle,
+            }),
+            remote_ephemeral_public_key: None,
+            remote_static_public_key,
+            identity_key: Some(Arc::new(static_secret_handle)),
+            key: None,
+            nonce: 0,
+            h: Some(h),
+            ck: Some(ck),
+            vault,
+        }
+    }
+}
@@ -13,9 +13,10 @@
 )]
 //! Handles key exchange using Noise for Ockam channels
 
+use async_trait::async_trait;
 use ockam_common::error::OckamResult;
-use ockam_vault::types::PublicKey;
-use ockam_vault::Secret;
+use ockam_vault::types::{PublicKey, SecretAttributes, SecretKey};
+use ockam_vault::{RandomVault, Secret, SecretVault};
 use std::sync::Arc;
 
 /// The maximum bytes that will be transmitted in a single message
@@ -28,6 +29,9 @@ pub const AES128_KEYSIZE: usize = 16;
 pub const AES256_KEYSIZE: usize = 32;
 /// The number of bytes in AES-GCM tag
 pub const AES_GCM_TAGSIZE: usize = 16;
+/// Attempts at drawing an ephemeral key, random bytes are not a valid P-256 key
+/// with a probability of about 2^-32
+pub const EPHEMERAL_KEY_ATTEMPTS: usize = 4;
 
 /// A KeyExchange implements these methods
 /// A KeyExchange implementation should wrap a vault instance
@@ -37,7 +41,7 @@ pub trait KeyExchange {
     /// Create a new `HandshakeState` starting with the prologue
     fn prologue(&mut self) -> OckamResult<()>;
     /// Perform the diffie-hellman computation
-    fn dh(&mut self, secret_handle: &Box<dyn Secret>, public_key: &[u8]) -> OckamResult<()>;
+    fn dh(&mut self, secret_handle: &dyn Secret, public_key: &[u8]) -> OckamResult<()>;
     /// mix hash step in Noise protocol
     fn mix_hash<B: AsRef<[u8]>>(&mut self, data: B) -> OckamResult<()>;
     /// Encrypt and mix step in Noise protocol
@@ -64,6 +68,49 @@ pub trait KeyExchanger {
     fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange>;
 }
 
+/// Async counterpart of [`KeyExchange`] for key exchanges over an async vault
+#[async_trait]
+pub trait AsyncKeyExchange {
+    /// Returns Noise protocol name
+    fn get_protocol_name(&self) -> &'static [u8];
+    /// Create a new `HandshakeState` starting with the prologue
+    async fn prologue(&mut self) -> OckamResult<()>;
+    /// Perform the diffie-hellman computation
+    async fn dh(&mut self, secret_handle: &dyn Secret, public_key: &[u8]) -> OckamResult<()>;
+    /// mix hash step in Noise protocol
+    async fn mix_hash<B: AsRef<[u8]> + Send>(&mut self, data: B) -> OckamResult<()>;
+    /// Encrypt and mix step in Noise protocol
+    async fn encrypt_and_mix_hash<B: AsRef<[u8]> + Send>(
+        &mut self,
+        plaintext: B,
+    ) -> OckamResult<Vec<u8>>;
+    /// Decrypt and mix step in Noise protocol
+    async fn decrypt_and_mix_hash<B: AsRef<[u8]> + Send>(
+        &mut self,
+        ciphertext: B,
+    ) -> OckamResult<Vec<u8>>;
+    /// Split step in Noise protocol
+    async fn split(&mut self) -> OckamResult<(Box<dyn Secret>, Box<dyn Secret>)>;
+    /// Finish the key exchange and return computed data
+    async fn finalize(
+        self,
+        encrypt_key: Box<dyn Secret>,
+        decrypt_key: Box<dyn Secret>,
+    ) -> OckamResult<CompletedKeyExchange>;
+}
+
+/// Async counterpart of [`KeyExchanger`], processing a message doesn't block the
+/// executor while the vault works
+#[async_trait]
+pub trait AsyncKeyExchanger: Send {
+    /// Handle the current step in the key exchange process
+    async fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>>;
+    /// Is the key exchange process completed yet
+    fn is_complete(&self) -> bool;
+    /// If completed, then return the data and keys needed for channels
+    async fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange>;
+}
+
 /// XX cipher suites
 #[derive(Copy, Clone, Debug)]
 pub enum CipherSuite {
@@ -71,6 +118,8 @@ pub enum CipherSuite {
     Curve25519AesGcmSha256,
     /// P256 Aes128-GCM Sha256
     P256Aes128GcmSha256,
+    /// Curve25519 ChaCha20-Poly1305 Sha256
+    Curve25519ChaChaPolySha256,
 }
 
 /// Instantiate a stateful key exchange vault instance
@@ -81,6 +130,14 @@ pub trait NewKeyExchanger<E: KeyExchanger = Self, F: KeyExchanger = Self> {
     fn responder(&self, identity_key: Option<Arc<Box<dyn Secret>>>) -> F;
 }
 
+/// Instantiate a stateful key exchange over an async vault
+pub trait AsyncNewKeyExchanger<E: AsyncKeyExchanger = Self, F: AsyncKeyExchanger = Self> {
+    /// Create a new Key Exchanger with the initiator role
+    fn initiator(&self, identity_key: Option<Arc<Box<dyn Secret>>>) -> E;
+    /// Create a new Key Exchanger with the responder role
+    fn responder(&self, identity_key: Option<Arc<Box<dyn Secret>>>) -> F;
+}
+
 /// A Completed Key Exchange elements
 #[derive(Debug)]
 pub struct CompletedKeyExchange {
@@ -96,5 +153,23 @@ pub struct CompletedKeyExchange {
     pub remote_static_public_key: PublicKey,
 }
 
+/// Create an ephemeral secret from the random bytes of `vault`, so a deterministic vault
+/// makes the key exchange reproducible
+pub fn ephemeral_secret<V: SecretVault + RandomVault + ?Sized>(
+    vault: &V,
+    attributes: SecretAttributes,
+) -> OckamResult<Box<dyn Secret>> {
+    let mut attempt = 1;
+    loop {
+        let mut bytes = vec![0u8; attributes.length];
+        vault.random_bytes_generate(&mut bytes)?;
+        let key = SecretKey::new(bytes);
+        match vault.secret_import(key.as_ref(), attributes) {
+            Err(_) if attempt < EPHEMERAL_KEY_ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
 /// Errors thrown by Key exchange
 pub mod error;
@@ -9,6 +9,13 @@ pub enum Error {
     InvalidState,
     MessageLenMismatch,
     InvalidHash,
+    /// The prekey doesn't exist, expired or was already used
+    UnknownPreKey,
+    /// The keys of a bundle or prekey store are from another curve than the cipher
+    /// suite's
+    CipherSuiteMismatch,
+    /// The payload that came with a bundle isn't signed by the bundle's identity key
+    InvalidPayloadSignature,
 }
 
 impl Error {
@@ -1,23 +1,27 @@
 use crate::error::Error;
 use ockam_common::error::{OckamError, OckamResult};
-use ockam_kex::{CompletedKeyExchange, KeyExchanger, NewKeyExchanger};
+use ockam_kex::{
+    ephemeral_secret, CipherSuite, CompletedKeyExchange, KeyExchanger, NewKeyExchanger,
+};
 use ockam_vault::types::{
-    SecretAttributes, SecretPersistence, SecretType, AES256_SECRET_LENGTH, CURVE25519_SECRET_LENGTH,
+    SecretAttributes, SecretPersistence, SecretType, SecretUsage, AES128_SECRET_LENGTH,
+    AES256_SECRET_LENGTH, CHACHA20POLY1305_SECRET_LENGTH, CURVE25519_SECRET_LENGTH,
+    P256_SECRET_LENGTH,
 };
 use ockam_vault::{
-    types::PublicKey, AsymmetricVault, HashVault, Secret, SecretVault, SignerVault, SymmetricVault,
-    VerifierVault,
-};
-use std::{
-    convert::TryFrom,
-    sync::{Arc, Mutex},
+    types::PublicKey, AsymmetricVault, HashVault, RandomVault, Secret, SecretVault, SignerVault,
+    SymmetricVault, VerifierVault,
 };
+use std::{convert::TryFrom, sync::Arc};
 use subtle::ConstantTimeEq;
 
 #[macro_use]
 extern crate arrayref;
 
 pub mod error;
+mod prekey_store;
+
+pub use prekey_store::{PreKeyStore, DEFAULT_GRACE_PERIOD, DEFAULT_ONE_TIME_PREKEY_LIFETIME};
 
 /// Represents and (X)EdDSA or ECDSA signature
 /// from Ed25519 or P-256
@@ -48,41 +52,117 @@ impl std::fmt::Debug for Signature {
     }
 }
 
-/// Represents all the keys and signature to send to an enrollee
+/// Id of a missing one-time prekey in bundles and enrollment messages, prekey ids
+/// start at 1
+const NO_ONE_TIME_PREKEY: u32 = 0;
+
+/// Represents all the keys and signature to send to an enrollee. The ids tell the
+/// responder which prekeys the enrollee used.
 #[derive(Clone, Debug)]
 pub struct PreKeyBundle {
     identity_key: PublicKey,
+    signed_prekey_id: u32,
     signed_prekey: PublicKey,
     signature_prekey: Signature,
-    one_time_prekey: PublicKey,
+    one_time_prekey: Option<(u32, PublicKey)>,
 }
 
 impl PreKeyBundle {
-    const SIZE: usize = 32 + 32 + 64 + 32;
+    /// IK, signed prekey id, SPK, signature, one-time prekey id
+    fn size_without_one_time_prekey(public_key_size: usize) -> usize {
+        public_key_size + 4 + public_key_size + 64 + 4
+    }
+
+    /// and OPK
+    fn size(public_key_size: usize) -> usize {
+        Self::size_without_one_time_prekey(public_key_size) + public_key_size
+    }
+
     /// Convert the prekey bundle to a byte array
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut output = Vec::new();
         output.extend_from_slice(self.identity_key.as_ref());
+        output.extend_from_slice(&self.signed_prekey_id.to_be_bytes());
         output.extend_from_slice(self.signed_prekey.as_ref());
         output.extend_from_slice(self.signature_prekey.0.as_ref());
-        output.extend_from_slice(self.one_time_prekey.as_ref());
+        match &self.one_time_prekey {
+            Some((id, one_time_prekey)) => {
+                output.extend_from_slice(&id.to_be_bytes());
+                output.extend_from_slice(one_time_prekey.as_ref());
+            }
+            None => output.extend_from_slice(&NO_ONE_TIME_PREKEY.to_be_bytes()),
+        }
         output
     }
+
+    /// The responder's identity key
+    pub fn identity_key(&self) -> &PublicKey {
+        &self.identity_key
+    }
+
+    /// Id of the signed prekey
+    pub fn signed_prekey_id(&self) -> u32 {
+        self.signed_prekey_id
+    }
+
+    /// Id of the one-time prekey, `None` when the responder ran out of them
+    pub fn one_time_prekey_id(&self) -> Option<u32> {
+        self.one_time_prekey.as_ref().map(|(id, _)| *id)
+    }
+}
+
+impl PreKeyBundle {
+    /// Decode a bundle with the keys of `cipher_suite` at the start of `data` and
+    /// return it with the rest of `data`
+    fn decode_prefix(data: &[u8], cipher_suite: CipherSuite) -> OckamResult<(Self, &[u8])> {
+        let public_key_size = public_key_size(cipher_suite);
+        let size_without_one_time_prekey = Self::size_without_one_time_prekey(public_key_size);
+        if data.len() < size_without_one_time_prekey {
+            return Err(Error::MessageLenMismatch.into());
+        }
+        let id_offset = size_without_one_time_prekey - 4;
+        let size = match u32::from_be_bytes(*array_ref![data, id_offset, 4]) {
+            NO_ONE_TIME_PREKEY => size_without_one_time_prekey,
+            _ => Self::size(public_key_size),
+        };
+        if data.len() < size {
+            return Err(Error::MessageLenMismatch.into());
+        }
+        Ok((Self::try_from(&data[..size])?, &data[size..]))
+    }
 }
 
 impl TryFrom<&[u8]> for PreKeyBundle {
     type Error = OckamError;
 
+    /// The sizes of the bundles with Curve25519 and P-256 keys differ, the length of
+    /// `data` tells which keys it holds
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        if data.len() != Self::SIZE {
-            return Err(Error::MessageLenMismatch.into());
-        }
-        let identity_key = PublicKey::new(array_ref![data, 0, 32].to_vec());
-        let signed_prekey = PublicKey::new(array_ref![data, 32, 32].to_vec());
-        let signature_prekey = Signature(*array_ref![data, 64, 64]);
-        let one_time_prekey = PublicKey::new(array_ref![data, 128, 32].to_vec());
+        let (n, with_one_time_prekey) = PUBLIC_KEY_SIZES
+            .iter()
+            .find_map(|&n| {
+                if data.len() == Self::size(n) {
+                    Some((n, true))
+                } else if data.len() == Self::size_without_one_time_prekey(n) {
+                    Some((n, false))
+                } else {
+                    None
+                }
+            })
+            .ok_or_else(|| Error::MessageLenMismatch.into())?;
+        let identity_key = PublicKey::new(data[..n].to_vec());
+        let signed_prekey_id = u32::from_be_bytes(*array_ref![data, n, 4]);
+        let signed_prekey = PublicKey::new(data[n + 4..2 * n + 4].to_vec());
+        let signature_prekey = Signature(*array_ref![data, 2 * n + 4, 64]);
+        let one_time_prekey_id = u32::from_be_bytes(*array_ref![data, 2 * n + 68, 4]);
+        let one_time_prekey = match (one_time_prekey_id, with_one_time_prekey) {
+            (NO_ONE_TIME_PREKEY, false) => None,
+            (NO_ONE_TIME_PREKEY, true) | (_, false) => return Err(Error::MessageLenMismatch.into()),
+            (id, true) => Some((id, PublicKey::new(data[2 * n + 72..].to_vec()))),
+        };
         Ok(Self {
             identity_key,
+            signed_prekey_id,
             signed_prekey,
             signature_prekey,
             one_time_prekey,
@@ -92,23 +172,179 @@ impl TryFrom<&[u8]> for PreKeyBundle {
 
 #[derive(Debug)]
 enum ResponderState {
+    /// Read the initiator's EIK, possibly followed by an enrollment message
+    ReceiveEnrollmentKey,
     /// Create a PreKey Bundle
-    GenerateBundle,
-    /// Expect an enrollment message from this EIK
-    SetEnrollmentKey,
+    SendBundle,
     /// Verify an enrollment message
     VerifyEnrollment,
     /// Done
     Done,
 }
 
-const CSUITE: &[u8] = b"X3DH_25519_AESGCM_SHA256\0\0\0\0\0\0\0\0";
-/// EK, Hash(EIK), IK, EdDSA, AES_GCM_TAG
-const ENROLLMENT_MSG_SIZE: usize = 32 + 32 + 32 + 64 + 16;
+/// Public key sizes of Curve25519 and uncompressed P-256 keys
+const PUBLIC_KEY_SIZES: [usize; 2] = [32, 65];
+
+/// Name of the protocol, it goes into the key derivation and the state hash
+fn protocol_name(cipher_suite: CipherSuite) -> &'static [u8] {
+    match cipher_suite {
+        CipherSuite::Curve25519AesGcmSha256 => b"X3DH_25519_AESGCM_SHA256\0\0\0\0\0\0\0\0",
+        CipherSuite::P256Aes128GcmSha256 => b"X3DH_P256_AES128GCM_SHA256\0\0\0\0\0\0",
+        CipherSuite::Curve25519ChaChaPolySha256 => b"X3DH_25519_ChaChaPoly_SHA256\0\0\0\0",
+    }
+}
+
+/// Type and length of the identity keys, prekeys and ephemeral keys
+pub(crate) fn secret_key_type_and_length(cipher_suite: CipherSuite) -> (SecretType, usize) {
+    match cipher_suite {
+        CipherSuite::Curve25519AesGcmSha256 | CipherSuite::Curve25519ChaChaPolySha256 => {
+            (SecretType::Curve25519, CURVE25519_SECRET_LENGTH)
+        }
+        CipherSuite::P256Aes128GcmSha256 => (SecretType::P256, P256_SECRET_LENGTH),
+    }
+}
+
+fn symmetric_key_type_and_length(cipher_suite: CipherSuite) -> (SecretType, usize) {
+    match cipher_suite {
+        CipherSuite::Curve25519AesGcmSha256 => (SecretType::Aes, AES256_SECRET_LENGTH),
+        CipherSuite::P256Aes128GcmSha256 => (SecretType::Aes, AES128_SECRET_LENGTH),
+        CipherSuite::Curve25519ChaChaPolySha256 => {
+            (SecretType::ChaCha20Poly1305, CHACHA20POLY1305_SECRET_LENGTH)
+        }
+    }
+}
+
+fn public_key_size(cipher_suite: CipherSuite) -> usize {
+    match cipher_suite {
+        CipherSuite::Curve25519AesGcmSha256 | CipherSuite::Curve25519ChaChaPolySha256 => 32,
+        CipherSuite::P256Aes128GcmSha256 => 65,
+    }
+}
+
+/// EK, Hash(EIK), signed prekey id, one-time prekey id
+fn enrollment_header_size(cipher_suite: CipherSuite) -> usize {
+    public_key_size(cipher_suite) + 32 + 4 + 4
+}
+
+/// header, IK, (X)EdDSA or ECDSA signature, AEAD tag
+fn enrollment_msg_size(cipher_suite: CipherSuite) -> usize {
+    enrollment_header_size(cipher_suite) + public_key_size(cipher_suite) + 64 + 16
+}
+
+/// Derive the two keys of an enrollment from the DH outputs without them leaving the
+/// vault. Starting from a zero chaining key, each output in turn is mixed in with
+/// HKDF, the last chaining key is expanded into the keys. The DH outputs and chaining
+/// keys are destroyed.
+fn derive_keys(
+    vault: &dyn X3dhVault,
+    cipher_suite: CipherSuite,
+    dhs: Vec<Box<dyn Secret>>,
+) -> OckamResult<Vec<Box<dyn Secret>>> {
+    let csuite = protocol_name(cipher_suite);
+    let ck_attributes = SecretAttributes {
+        persistence: SecretPersistence::Ephemeral,
+        stype: SecretType::Buffer,
+        length: 32,
+        exportable: false,
+        usage: SecretUsage::HKDF,
+    };
+    let mut ck = vault.secret_import(&[0u8; 32], ck_attributes)?;
+    for dh in dhs {
+        let mixed = vault.hkdf_sha256(ck.as_ref(), csuite, Some(dh.as_ref()), vec![ck_attributes]);
+        vault.secret_destroy(dh)?;
+        vault.secret_destroy(ck)?;
+        ck = mixed?.pop().ok_or_else(|| Error::InvalidState.into())?;
+    }
+    let (stype, length) = symmetric_key_type_and_length(cipher_suite);
+    let atts = SecretAttributes {
+        persistence: SecretPersistence::Persistent,
+        stype,
+        length,
+        exportable: true,
+        usage: SecretUsage::AEAD | SecretUsage::HKDF,
+    };
+    let keys = vault.hkdf_sha256(ck.as_ref(), csuite, None, vec![atts, atts]);
+    vault.secret_destroy(ck)?;
+    keys
+}
+
+/// What the responder signs with its identity key to vouch for the payload it sends
+/// along with `bundle` to the initiator whose ephemeral identity key is `eik`
+fn payload_signature_data(
+    cipher_suite: CipherSuite,
+    eik: &[u8],
+    bundle: &[u8],
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut data = protocol_name(cipher_suite).to_vec();
+    data.extend_from_slice(eik);
+    data.extend_from_slice(bundle);
+    data.extend_from_slice(payload);
+    data
+}
+
+/// The state hash binds the protocol name and the public keys of both sides: the
+/// responder's identity key, signed prekey and one-time prekey if there is one, then
+/// the initiator's EIK and EK
+fn state_hash(
+    vault: &dyn X3dhVault,
+    cipher_suite: CipherSuite,
+    public_keys: &[&[u8]],
+) -> OckamResult<[u8; 32]> {
+    let mut transcript = vault.sha256(protocol_name(cipher_suite))?.to_vec();
+    for public_key in public_keys {
+        transcript.extend_from_slice(public_key);
+    }
+    vault.sha256(&transcript)
+}
+
+fn aead_encrypt(
+    vault: &dyn X3dhVault,
+    cipher_suite: CipherSuite,
+    key: &dyn Secret,
+    plaintext: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+) -> OckamResult<Vec<u8>> {
+    match cipher_suite {
+        CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => {
+            vault.aead_aes_gcm_encrypt(key, plaintext, nonce, aad)
+        }
+        CipherSuite::Curve25519ChaChaPolySha256 => {
+            vault.aead_chacha20_poly1305_encrypt(key, plaintext, nonce, aad)
+        }
+    }
+}
+
+fn aead_decrypt(
+    vault: &dyn X3dhVault,
+    cipher_suite: CipherSuite,
+    key: &dyn Secret,
+    ciphertext: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+) -> OckamResult<Vec<u8>> {
+    match cipher_suite {
+        CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => {
+            vault.aead_aes_gcm_decrypt(key, ciphertext, nonce, aad)
+        }
+        CipherSuite::Curve25519ChaChaPolySha256 => {
+            vault.aead_chacha20_poly1305_decrypt(key, ciphertext, nonce, aad)
+        }
+    }
+}
 
 /// Vault with X3DH required functionality
 pub trait X3dhVault:
-    SecretVault + SignerVault + VerifierVault + AsymmetricVault + SymmetricVault + HashVault + Send
+    SecretVault
+    + SignerVault
+    + VerifierVault
+    + AsymmetricVault
+    + SymmetricVault
+    + HashVault
+    + RandomVault
+    + Send
+    + Sync
 {
 }
 
@@ -119,55 +355,74 @@ impl<D> X3dhVault for D where
         + AsymmetricVault
         + SymmetricVault
         + HashVault
+        + RandomVault
         + Send
+        + Sync
 {
 }
 
 /// The responder of X3DH creates a prekey bundle that can be used to establish a shared
 /// secret key with another party that can use
+///
+/// The initiator opens with its ephemeral identity key, the responder answers with a
+/// bundle of its `PreKeyStore` and a payload signed with its identity key, and the
+/// initiator finishes with the enrollment message. Initiators that got a bundle of the store from somewhere else send the
+/// enrollment message right after their ephemeral identity key instead, the exchange
+/// is then done after a single message. A responder without a store creates its own
+/// with one one-time prekey.
 pub struct X3dhResponder {
-    // Identity key and signer prekey are wrapped in Arc because they are possible shared
-    // among threads/modules
+    cipher_suite: CipherSuite,
+    // Identity key is wrapped in Arc because it is possibly shared among threads/modules
     identity_key: Option<Arc<Box<dyn Secret>>>,
-    signed_prekey: Option<Arc<Box<dyn Secret>>>,
-    one_time_prekey: Option<Box<dyn Secret>>,
+    prekey_store: Option<Arc<PreKeyStore>>,
     expected_enrollment_key: Option<PublicKey>,
+    /// Sent along with the bundle, the enrollment authenticates it
+    payload: Vec<u8>,
     state: ResponderState,
-    vault: Arc<Mutex<dyn X3dhVault>>,
+    vault: Arc<dyn X3dhVault>,
     completed_key_exchange: Option<CompletedKeyExchange>,
 }
 
 impl X3dhResponder {
-    fn new(v: Arc<Mutex<dyn X3dhVault>>, identity_key: Option<Arc<Box<dyn Secret>>>) -> Self {
+    fn new(
+        cipher_suite: CipherSuite,
+        v: Arc<dyn X3dhVault>,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+        prekey_store: Option<Arc<PreKeyStore>>,
+    ) -> Self {
         Self {
+            cipher_suite,
             identity_key,
-            signed_prekey: None,
-            one_time_prekey: None,
+            prekey_store,
             expected_enrollment_key: None,
+            payload: Vec::new(),
             completed_key_exchange: None,
-            state: ResponderState::GenerateBundle,
+            state: ResponderState::ReceiveEnrollmentKey,
             vault: v,
         }
     }
 
     fn prologue(&mut self) -> OckamResult<()> {
-        let mut vault = self.vault.lock().unwrap();
-        let p_atts = SecretAttributes {
-            persistence: SecretPersistence::Persistent,
-            stype: SecretType::Curve25519,
-            length: CURVE25519_SECRET_LENGTH,
-        };
-        let e_atts = SecretAttributes {
-            persistence: SecretPersistence::Ephemeral,
-            stype: SecretType::Curve25519,
-            length: CURVE25519_SECRET_LENGTH,
-        };
-        if self.identity_key.is_none() {
-            self.identity_key = Some(Arc::new(vault.secret_generate(p_atts)?));
+        match &self.prekey_store {
+            Some(store) => {
+                if secret_key_type_and_length(store.cipher_suite())
+                    != secret_key_type_and_length(self.cipher_suite)
+                {
+                    return Err(Error::CipherSuiteMismatch.into());
+                }
+            }
+            None => {
+                let store = PreKeyStore::new(
+                    self.cipher_suite,
+                    self.vault.clone(),
+                    self.identity_key.take(),
+                )?;
+                store.publish_one_time_prekeys(1)?;
+                self.prekey_store = Some(Arc::new(store));
+            }
         }
-        self.signed_prekey = Some(Arc::new(vault.secret_generate(p_atts)?));
-        self.one_time_prekey = Some(vault.secret_generate(e_atts)?);
         self.expected_enrollment_key = None;
+        self.payload = Vec::new();
         self.completed_key_exchange = None;
         Ok(())
     }
@@ -177,16 +432,16 @@ impl std::fmt::Debug for X3dhResponder {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            r#"X3dhResponder {{ identity_key: {:?},
-                                      signed_prekey: {:?},
-                                      one_time_prekey: {:?},
+            r#"X3dhResponder {{ cipher_suite: {:?},
+                                      identity_key: {:?},
+                                      prekey_store: {:?},
                                       expected_enrollment_key: {:?},
                                       state: {:?},
                                       vault,
                                       completed_key_exchange: {:?} }}"#,
+            self.cipher_suite,
             self.identity_key,
-            self.signed_prekey,
-            self.one_time_prekey,
+            self.prekey_store,
             self.expected_enrollment_key,
             self.state,
             self.completed_key_exchange
@@ -198,25 +453,34 @@ impl std::fmt::Debug for X3dhResponder {
 enum InitiatorState {
     GenerateEphemeralIdentityKey,
     ProcessPreKeyBundle,
+    SendEnrollment,
     Done,
 }
 
 /// The responder of X3DH receives a prekey bundle and computes the shared secret
 /// to communicate the first message to the initiator
 pub struct X3dhInitiator {
+    cipher_suite: CipherSuite,
     ephemeral_identity_key: Option<Box<dyn Secret>>,
     prekey_bundle: Option<PreKeyBundle>,
+    enrollment: Option<Vec<u8>>,
     state: InitiatorState,
-    vault: Arc<Mutex<dyn X3dhVault>>,
+    vault: Arc<dyn X3dhVault>,
     completed_key_exchange: Option<CompletedKeyExchange>,
     identity_key: Option<Arc<Box<dyn Secret>>>,
 }
 
 impl X3dhInitiator {
-    fn new(v: Arc<Mutex<dyn X3dhVault>>, identity_key: Option<Arc<Box<dyn Secret>>>) -> Self {
+    fn new(
+        cipher_suite: CipherSuite,
+        v: Arc<dyn X3dhVault>,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+    ) -> Self {
         Self {
+            cipher_suite,
             ephemeral_identity_key: None,
             prekey_bundle: None,
+            enrollment: None,
             state: InitiatorState::GenerateEphemeralIdentityKey,
             vault: v,
             completed_key_exchange: None,
@@ -225,24 +489,36 @@ impl X3dhInitiator {
     }
 
     fn prologue(&mut self) -> OckamResult<()> {
-        let mut vault = self.vault.lock().unwrap();
+        let vault = &self.vault;
+        let (stype, length) = secret_key_type_and_length(self.cipher_suite);
         let p_atts = SecretAttributes {
             persistence: SecretPersistence::Persistent,
-            stype: SecretType::Curve25519,
-            length: CURVE25519_SECRET_LENGTH,
+            stype,
+            length,
+            exportable: false,
+            usage: SecretUsage::SIGN | SecretUsage::ECDH,
         };
         self.ephemeral_identity_key = Some(vault.secret_generate(p_atts)?);
         self.prekey_bundle = None;
+        self.enrollment = None;
         self.completed_key_exchange = None;
         Ok(())
     }
+
+    /// Enroll with a bundle that was fetched before the exchange, for example from a
+    /// `PreKeyStore` served by the responder. The enrollment message then follows the
+    /// ephemeral identity key in the first message and the exchange completes with it.
+    pub fn set_prekey_bundle(&mut self, prekey_bundle: PreKeyBundle) {
+        self.prekey_bundle = Some(prekey_bundle);
+    }
 }
 
 impl std::fmt::Debug for X3dhInitiator {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            r#"X3dhInitiator {{ ephemeral_identity_key: {:?}, prekey_bundle: {:?}, state: {:?}, vault, completed_key_exchange: {:?}, identity_key: {:?} }}"#,
+            r#"X3dhInitiator {{ cipher_suite: {:?}, ephemeral_identity_key: {:?}, prekey_bundle: {:?}, state: {:?}, vault, completed_key_exchange: {:?}, identity_key: {:?} }}"#,
+            self.cipher_suite,
             self.ephemeral_identity_key,
             self.prekey_bundle,
             self.state,
@@ -252,130 +528,138 @@ impl std::fmt::Debug for X3dhInitiator {
     }
 }
 
+impl X3dhResponder {
+    /// Check an enrollment message and derive the keys from it. `payload` is what this
+    /// side sent along with the bundle, the initiator authenticates it.
+    fn verify_enrollment(&mut self, data: &[u8], payload: &[u8]) -> OckamResult<()> {
+        debug_assert!(self.expected_enrollment_key.is_some());
+        let cipher_suite = self.cipher_suite;
+        if data.len() != enrollment_msg_size(cipher_suite) {
+            return Err(Error::MessageLenMismatch.into());
+        }
+        let n = public_key_size(cipher_suite);
+        let header_size = enrollment_header_size(cipher_suite);
+        let csuite = protocol_name(cipher_suite);
+        let vault = &self.vault;
+        let store = self
+            .prekey_store
+            .as_ref()
+            .ok_or(Error::InvalidState.into())?;
+        let eik = self
+            .expected_enrollment_key
+            .as_ref()
+            .ok_or(Error::InvalidState.into())?;
+        let id = vault.sha256(eik.as_ref())?;
+        if id.ct_eq(&data[n..n + 32]).unwrap_u8() != 1 {
+            return Err(Error::InvalidHash.into());
+        }
+        let ek = PublicKey::new(data[..n].to_vec());
+        let signed_prekey =
+            store.signed_prekey(u32::from_be_bytes(*array_ref![data, n + 32, 4]))?;
+        let one_time_prekey_id = u32::from_be_bytes(*array_ref![data, n + 36, 4]);
+        let one_time_prekey = match one_time_prekey_id {
+            NO_ONE_TIME_PREKEY => None,
+            id => Some(store.one_time_prekey(id)?),
+        };
+        let local_static_secret = store.identity_key();
+
+        let mut dhs = vec![
+            vault.ec_diffie_hellman(&**signed_prekey, eik.as_ref())?,
+            vault.ec_diffie_hellman(local_static_secret.as_ref().as_ref(), ek.as_ref())?,
+            vault.ec_diffie_hellman(&**signed_prekey, ek.as_ref())?,
+        ];
+        let mut public_keys = vec![
+            vault.secret_public_key_get(local_static_secret.as_ref().as_ref())?,
+            vault.secret_public_key_get(&**signed_prekey)?,
+        ];
+        if let Some(one_time_prekey) = one_time_prekey.as_deref() {
+            dhs.push(vault.ec_diffie_hellman(&**one_time_prekey, ek.as_ref())?);
+            public_keys.push(vault.secret_public_key_get(&**one_time_prekey)?);
+        }
+        public_keys.push(eik.clone());
+        public_keys.push(ek);
+
+        let mut keyrefs = derive_keys(vault.as_ref(), cipher_suite, dhs)?;
+        let decrypt_key = keyrefs.pop().unwrap();
+        let encrypt_key = keyrefs.pop().unwrap();
+        let public_keys: Vec<&[u8]> = public_keys.iter().map(|k| k.as_ref()).collect();
+        let state_hash = state_hash(vault.as_ref(), cipher_suite, &public_keys)?;
+
+        let mut aad = data[..header_size].to_vec();
+        aad.extend_from_slice(csuite);
+        aad.extend_from_slice(&state_hash);
+        aad.extend_from_slice(payload);
+        let plaintext = aead_decrypt(
+            vault.as_ref(),
+            cipher_suite,
+            decrypt_key.as_ref(),
+            &data[header_size..],
+            &data[..12],
+            aad.as_slice(),
+        )?;
+        let ikb = PublicKey::new(plaintext[..n].to_vec());
+        let signature = array_ref![plaintext, n, 64];
+        let (stype, _) = secret_key_type_and_length(cipher_suite);
+        vault.verify(signature, eik.as_ref(), stype, &plaintext[..n])?;
+        // each one-time prekey completes a single enrollment
+        if let Some(one_time_prekey) = one_time_prekey {
+            std::mem::drop(one_time_prekey);
+            store.consume_one_time_prekey(one_time_prekey_id)?;
+        }
+
+        self.completed_key_exchange = Some(CompletedKeyExchange {
+            h: state_hash,
+            encrypt_key,
+            decrypt_key,
+            local_static_secret,
+            remote_static_public_key: ikb,
+        });
+        self.state = ResponderState::Done;
+        Ok(())
+    }
+}
+
 impl KeyExchanger for X3dhResponder {
     fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
         match self.state {
-            ResponderState::GenerateBundle => {
+            ResponderState::ReceiveEnrollmentKey => {
                 self.prologue()?;
-                let mut vault = self.vault.lock().unwrap();
-                let identity_secret_key = self
-                    .identity_key
-                    .as_ref()
-                    .ok_or(Error::InvalidState.into())?;
-                let signed_prekey = self
-                    .signed_prekey
-                    .as_ref()
-                    .ok_or(Error::InvalidState.into())?;
-                let one_time_prekey = self
-                    .one_time_prekey
-                    .as_ref()
-                    .ok_or(Error::InvalidState.into())?;
-                let signed_prekey_pub = vault.secret_public_key_get(signed_prekey)?;
-                let signature = vault.sign(identity_secret_key, signed_prekey_pub.as_ref())?;
-                let identity_key = vault.secret_public_key_get(identity_secret_key)?;
-                let one_time_prekey_pub = vault.secret_public_key_get(one_time_prekey)?;
-                let bundle = PreKeyBundle {
-                    identity_key,
-                    signed_prekey: signed_prekey_pub,
-                    signature_prekey: Signature(signature),
-                    one_time_prekey: one_time_prekey_pub,
-                };
-                self.state = ResponderState::SetEnrollmentKey;
-                Ok(bundle.to_bytes())
-            }
-            ResponderState::SetEnrollmentKey => {
-                if data.len() != 32 {
+                let n = public_key_size(self.cipher_suite);
+                if data.len() < n {
                     return Err(Error::MessageLenMismatch.into());
                 }
-                self.expected_enrollment_key =
-                    Some(PublicKey::new(array_ref![data, 0, 32].to_vec()));
-                self.state = ResponderState::VerifyEnrollment;
+                self.expected_enrollment_key = Some(PublicKey::new(data[..n].to_vec()));
+                if data.len() == n {
+                    self.state = ResponderState::SendBundle;
+                } else {
+                    // the initiator already had a bundle and enrolled right away
+                    self.verify_enrollment(&data[n..], &[])?;
+                }
                 Ok(vec![])
             }
-            ResponderState::VerifyEnrollment => {
-                debug_assert!(self.expected_enrollment_key.is_some());
-                if data.len() != ENROLLMENT_MSG_SIZE {
-                    return Err(Error::MessageLenMismatch.into());
-                }
-                let mut vault = self.vault.lock().unwrap();
-                let signed_prekey = self
-                    .signed_prekey
+            ResponderState::SendBundle => {
+                let store = self
+                    .prekey_store
                     .as_ref()
                     .ok_or(Error::InvalidState.into())?;
-                let one_time_prekey = self
-                    .one_time_prekey
+                let eik = self
+                    .expected_enrollment_key
                     .as_ref()
                     .ok_or(Error::InvalidState.into())?;
-                let eik = self.expected_enrollment_key.as_ref().unwrap();
-                let id = vault.sha256(eik.as_ref())?;
-                if id.ct_eq(&data[32..64]).unwrap_u8() != 1 {
-                    return Err(Error::InvalidHash.into());
-                }
-                let ek = PublicKey::new(array_ref![data, 0, 32].to_vec());
-                let local_static_secret =
-                    self.identity_key.take().ok_or(Error::InvalidState.into())?;
-
-                let dh1 = vault.ec_diffie_hellman(signed_prekey, eik.as_ref())?;
-                let dh2 = vault.ec_diffie_hellman(&local_static_secret, ek.as_ref())?;
-                let dh3 = vault.ec_diffie_hellman(signed_prekey, ek.as_ref())?;
-                let dh4 = vault.ec_diffie_hellman(one_time_prekey, ek.as_ref())?;
-                let mut ikm_bytes = vec![0xFFu8; 32];
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh1)?.as_ref());
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh2)?.as_ref());
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh3)?.as_ref());
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh4)?.as_ref());
-
-                let ikm = vault.secret_import(
-                    &ikm_bytes,
-                    SecretAttributes {
-                        persistence: SecretPersistence::Ephemeral,
-                        stype: SecretType::Buffer,
-                        length: ikm_bytes.len(),
-                    },
+                let mut output = store.take_bundle()?.to_bytes();
+                let signature = self.vault.sign(
+                    store.identity_key().as_ref().as_ref(),
+                    &payload_signature_data(self.cipher_suite, eik.as_ref(), &output, data),
                 )?;
-                let salt = vault.secret_import(
-                    &[0u8; 32],
-                    SecretAttributes {
-                        persistence: SecretPersistence::Ephemeral,
-                        stype: SecretType::Buffer,
-                        length: 32,
-                    },
-                )?;
-                let atts = SecretAttributes {
-                    persistence: SecretPersistence::Persistent,
-                    stype: SecretType::Aes,
-                    length: AES256_SECRET_LENGTH,
-                };
-
-                let mut keyrefs = vault.hkdf_sha256(&salt, CSUITE, Some(&ikm), vec![atts, atts])?;
-                let decrypt_key = keyrefs.pop().unwrap();
-                let encrypt_key = keyrefs.pop().unwrap();
-                let mut state_hash = vault.sha256(CSUITE)?.to_vec();
-                state_hash.append(&mut ikm_bytes);
-                let state_hash = vault.sha256(state_hash.as_slice())?;
-
-                let mut aad = data[..64].to_vec();
-                aad.extend_from_slice(CSUITE);
-                aad.extend_from_slice(&state_hash);
-                //TODO: get the channel address from the message somehow if needed
-                let plaintext = vault.aead_aes_gcm_decrypt(
-                    &decrypt_key,
-                    &data[64..],
-                    &data[..12],
-                    aad.as_slice(),
-                )?;
-                let ikb = PublicKey::new(array_ref![plaintext, 0, 32].to_vec());
-                let signature = array_ref![plaintext, 32, 64];
-                vault.verify(signature, eik.as_ref(), &plaintext[..32])?;
-
-                self.completed_key_exchange = Some(CompletedKeyExchange {
-                    h: state_hash,
-                    encrypt_key,
-                    decrypt_key,
-                    local_static_secret,
-                    remote_static_public_key: ikb,
-                });
-                self.state = ResponderState::Done;
+                output.extend_from_slice(&signature);
+                output.extend_from_slice(data);
+                self.payload = data.to_vec();
+                self.state = ResponderState::VerifyEnrollment;
+                Ok(output)
+            }
+            ResponderState::VerifyEnrollment => {
+                let payload = std::mem::take(&mut self.payload);
+                self.verify_enrollment(data, &payload)?;
                 Ok(vec![])
             }
             ResponderState::Done => Ok(vec![]),
@@ -391,124 +675,172 @@ impl KeyExchanger for X3dhResponder {
     }
 }
 
+impl X3dhInitiator {
+    /// Derive the keys from `prekey_bundle` and create the enrollment message.
+    /// `payload` came with the bundle and is authenticated by the enrollment.
+    fn enroll(&mut self, prekey_bundle: PreKeyBundle, payload: &[u8]) -> OckamResult<Vec<u8>> {
+        let cipher_suite = self.cipher_suite;
+        let csuite = protocol_name(cipher_suite);
+        let (secret_type, secret_length) = secret_key_type_and_length(cipher_suite);
+        let vault = &self.vault;
+
+        // a bundle with keys of another curve
+        if prekey_bundle.identity_key.as_ref().len() != public_key_size(cipher_suite) {
+            return Err(Error::CipherSuiteMismatch.into());
+        }
+
+        let ephemeral_identity_key = self
+            .ephemeral_identity_key
+            .as_deref()
+            .ok_or(Error::InvalidState.into())?;
+
+        // Check the prekey_bundle signature
+        vault.verify(
+            prekey_bundle.signature_prekey.as_ref(),
+            prekey_bundle.identity_key.as_ref(),
+            secret_type,
+            prekey_bundle.signed_prekey.as_ref(),
+        )?;
+        let atts = SecretAttributes {
+            persistence: SecretPersistence::Ephemeral,
+            stype: secret_type,
+            length: secret_length,
+            exportable: false,
+            usage: SecretUsage::ECDH,
+        };
+        let esk = ephemeral_secret(vault.as_ref(), atts)?;
+        let mut dhs = vec![
+            vault
+                .ec_diffie_hellman(ephemeral_identity_key, prekey_bundle.signed_prekey.as_ref())?,
+            vault.ec_diffie_hellman(esk.as_ref(), prekey_bundle.identity_key.as_ref())?,
+            vault.ec_diffie_hellman(esk.as_ref(), prekey_bundle.signed_prekey.as_ref())?,
+        ];
+        let mut public_keys: Vec<&[u8]> = vec![
+            prekey_bundle.identity_key.as_ref(),
+            prekey_bundle.signed_prekey.as_ref(),
+        ];
+        if let Some((_, one_time_prekey)) = &prekey_bundle.one_time_prekey {
+            dhs.push(vault.ec_diffie_hellman(esk.as_ref(), one_time_prekey.as_ref())?);
+            public_keys.push(one_time_prekey.as_ref());
+        }
+
+        let mut keyrefs = derive_keys(vault.as_ref(), cipher_suite, dhs)?;
+        let encrypt_key = keyrefs.pop().unwrap();
+        let decrypt_key = keyrefs.pop().unwrap();
+        let ek = vault.secret_public_key_get(esk.as_ref())?;
+        let pubkey = vault.secret_public_key_get(ephemeral_identity_key)?;
+
+        public_keys.push(pubkey.as_ref());
+        public_keys.push(ek.as_ref());
+        let state_hash = state_hash(vault.as_ref(), cipher_suite, &public_keys)?;
+
+        let mut aad = ek.as_ref().to_vec();
+        aad.extend_from_slice(&vault.sha256(pubkey.as_ref())?);
+        aad.extend_from_slice(&prekey_bundle.signed_prekey_id.to_be_bytes());
+        aad.extend_from_slice(
+            &prekey_bundle
+                .one_time_prekey_id()
+                .unwrap_or(NO_ONE_TIME_PREKEY)
+                .to_be_bytes(),
+        );
+        aad.extend_from_slice(csuite);
+        aad.extend_from_slice(&state_hash);
+        aad.extend_from_slice(payload);
+
+        let skb = if let Some(identity_key) = self.identity_key.take() {
+            identity_key
+        } else {
+            Arc::new(vault.secret_generate(SecretAttributes {
+                stype: secret_type,
+                persistence: SecretPersistence::Persistent,
+                length: secret_length,
+                exportable: false,
+                usage: SecretUsage::ECDH,
+            })?)
+        };
+        let ikb = vault.secret_public_key_get(skb.as_ref().as_ref())?;
+
+        let mut plaintext = ikb.as_ref().to_vec();
+        plaintext.extend_from_slice(&vault.sign(ephemeral_identity_key, ikb.as_ref())?);
+
+        let mut ciphertext_and_tag = aead_encrypt(
+            vault.as_ref(),
+            cipher_suite,
+            encrypt_key.as_ref(),
+            plaintext.as_slice(),
+            &ek.as_ref()[..12],
+            aad.as_slice(),
+        )?;
+        let mut output = aad[..enrollment_header_size(cipher_suite)].to_vec();
+        output.append(&mut ciphertext_and_tag);
+        self.completed_key_exchange = Some(CompletedKeyExchange {
+            h: state_hash,
+            encrypt_key,
+            decrypt_key,
+            local_static_secret: skb,
+            remote_static_public_key: prekey_bundle.identity_key,
+        });
+        Ok(output)
+    }
+}
+
 impl KeyExchanger for X3dhInitiator {
     fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
         match self.state {
             InitiatorState::GenerateEphemeralIdentityKey => {
+                let prekey_bundle = self.prekey_bundle.take();
                 self.prologue()?;
-                let mut vault = self.vault.lock().unwrap();
-                let ephemeral_identity_key = vault.secret_generate(SecretAttributes {
-                    persistence: SecretPersistence::Persistent,
-                    stype: SecretType::Curve25519,
-                    length: CURVE25519_SECRET_LENGTH,
-                })?;
-                let pubkey = vault.secret_public_key_get(&ephemeral_identity_key)?;
-                self.ephemeral_identity_key = Some(ephemeral_identity_key);
-                self.state = InitiatorState::ProcessPreKeyBundle;
-                Ok(pubkey.as_ref().to_vec())
+                let ephemeral_identity_key = self
+                    .ephemeral_identity_key
+                    .as_deref()
+                    .ok_or(Error::InvalidState.into())?;
+                let mut output = self
+                    .vault
+                    .secret_public_key_get(ephemeral_identity_key)?
+                    .as_ref()
+                    .to_vec();
+                match prekey_bundle {
+                    Some(prekey_bundle) => {
+                        output.append(&mut self.enroll(prekey_bundle, &[])?);
+                        self.state = InitiatorState::Done;
+                    }
+                    None => self.state = InitiatorState::ProcessPreKeyBundle,
+                }
+                Ok(output)
             }
             InitiatorState::ProcessPreKeyBundle => {
-                let prekey_bundle = PreKeyBundle::try_from(data)?;
-
-                let mut vault = self.vault.lock().unwrap();
-
+                let (prekey_bundle, rest) = PreKeyBundle::decode_prefix(data, self.cipher_suite)?;
+                if rest.len() < 64 {
+                    return Err(Error::MessageLenMismatch.into());
+                }
+                let (signature, payload) = rest.split_at(64);
                 let ephemeral_identity_key = self
                     .ephemeral_identity_key
-                    .as_ref()
+                    .as_deref()
                     .ok_or(Error::InvalidState.into())?;
-
-                // Check the prekey_bundle signature
-                vault.verify(
-                    prekey_bundle.signature_prekey.as_ref(),
-                    prekey_bundle.identity_key.as_ref(),
-                    prekey_bundle.signed_prekey.as_ref(),
-                )?;
-                let atts = SecretAttributes {
-                    persistence: SecretPersistence::Ephemeral,
-                    stype: SecretType::Curve25519,
-                    length: CURVE25519_SECRET_LENGTH,
-                };
-                let esk = vault.secret_generate(atts)?;
-                let dh1 = vault.ec_diffie_hellman(
-                    ephemeral_identity_key,
-                    prekey_bundle.signed_prekey.as_ref(),
-                )?;
-                let dh2 = vault.ec_diffie_hellman(&esk, prekey_bundle.identity_key.as_ref())?;
-                let dh3 = vault.ec_diffie_hellman(&esk, prekey_bundle.signed_prekey.as_ref())?;
-                let dh4 = vault.ec_diffie_hellman(&esk, prekey_bundle.one_time_prekey.as_ref())?;
-                let mut ikm_bytes = vec![0xFFu8; 32];
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh1)?.as_ref());
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh2)?.as_ref());
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh3)?.as_ref());
-                ikm_bytes.extend_from_slice(vault.secret_export(&dh4)?.as_ref());
-
-                let ikm = vault.secret_import(
-                    &ikm_bytes,
-                    SecretAttributes {
-                        persistence: SecretPersistence::Ephemeral,
-                        stype: SecretType::Buffer,
-                        length: ikm_bytes.len(),
-                    },
-                )?;
-                let salt = vault.secret_import(
-                    &[0u8; 32],
-                    SecretAttributes {
-                        persistence: SecretPersistence::Ephemeral,
-                        stype: SecretType::Buffer,
-                        length: 32,
-                    },
-                )?;
-
-                let mut atts = SecretAttributes {
-                    stype: SecretType::Aes,
-                    persistence: SecretPersistence::Persistent,
-                    length: AES256_SECRET_LENGTH,
-                };
-
-                let mut keyrefs = vault.hkdf_sha256(&salt, CSUITE, Some(&ikm), vec![atts, atts])?;
-                let encrypt_key = keyrefs.pop().unwrap();
-                let decrypt_key = keyrefs.pop().unwrap();
-                let ek = vault.secret_public_key_get(&esk)?;
-                let pubkey = vault.secret_public_key_get(ephemeral_identity_key)?;
-
-                let mut state_hash = vault.sha256(CSUITE)?.to_vec();
-                state_hash.append(&mut ikm_bytes);
-                let state_hash = vault.sha256(state_hash.as_slice())?;
-
-                let mut aad = ek.as_ref().to_vec();
-                aad.extend_from_slice(&vault.sha256(pubkey.as_ref())?);
-                aad.extend_from_slice(CSUITE);
-                aad.extend_from_slice(&state_hash);
-
-                atts.stype = SecretType::Curve25519;
-                let identity_key = self.identity_key.take();
-                let skb = if identity_key.is_none() {
-                    Arc::new(vault.secret_generate(atts)?)
-                } else {
-                    identity_key.unwrap()
-                };
-                let ikb = vault.secret_public_key_get(&skb)?;
-
-                let mut plaintext = ikb.as_ref().to_vec();
-                plaintext.extend_from_slice(&vault.sign(ephemeral_identity_key, ikb.as_ref())?);
-
-                let mut ciphertext_and_tag = vault.aead_aes_gcm_encrypt(
-                    &encrypt_key,
-                    plaintext.as_slice(),
-                    &ek.as_ref()[..12],
-                    aad.as_slice(),
-                )?;
-                let mut output = aad[..64].to_vec();
-                output.append(&mut ciphertext_and_tag);
-                self.completed_key_exchange = Some(CompletedKeyExchange {
-                    h: state_hash,
-                    encrypt_key,
-                    decrypt_key,
-                    local_static_secret: skb,
-                    remote_static_public_key: prekey_bundle.identity_key,
-                });
+                let eik = self.vault.secret_public_key_get(ephemeral_identity_key)?;
+                let (secret_type, _) = secret_key_type_and_length(self.cipher_suite);
+                let bundle_size = data.len() - rest.len();
+                self.vault
+                    .verify(
+                        array_ref![signature, 0, 64],
+                        prekey_bundle.identity_key.as_ref(),
+                        secret_type,
+                        &payload_signature_data(
+                            self.cipher_suite,
+                            eik.as_ref(),
+                            &data[..bundle_size],
+                            payload,
+                        ),
+                    )
+                    .map_err(|_| Error::InvalidPayloadSignature.into())?;
+                self.enrollment = Some(self.enroll(prekey_bundle, payload)?);
+                self.state = InitiatorState::SendEnrollment;
+                Ok(payload.to_vec())
+            }
+            InitiatorState::SendEnrollment => {
                 self.state = InitiatorState::Done;
-                Ok(output)
+                self.enrollment.take().ok_or(Error::InvalidState.into())
             }
             InitiatorState::Done => Ok(vec![]),
         }
@@ -523,41 +855,62 @@ impl KeyExchanger for X3dhInitiator {
     }
 }
 
-/// Represents an XX NewKeyExchanger
+/// Represents an X3DH NewKeyExchanger
 pub struct X3dhNewKeyExchanger {
-    vault_initiator: Arc<Mutex<dyn X3dhVault>>,
-    vault_responder: Arc<Mutex<dyn X3dhVault>>,
+    cipher_suite: CipherSuite,
+    vault_initiator: Arc<dyn X3dhVault>,
+    vault_responder: Arc<dyn X3dhVault>,
+    prekey_store: Option<Arc<PreKeyStore>>,
 }
 
 impl std::fmt::Debug for X3dhNewKeyExchanger {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "X3dhNewKeyExchanger {{ vault_initiator, vault_responder }}"
+            "X3dhNewKeyExchanger {{ cipher_suite: {:?}, vault_initiator, vault_responder }}",
+            self.cipher_suite
         )
     }
 }
 
 impl X3dhNewKeyExchanger {
-    /// Create a new XXNewKeyExchanger
+    /// Create a new X3dhNewKeyExchanger
     pub fn new(
-        vault_initiator: Arc<Mutex<dyn X3dhVault>>,
-        vault_responder: Arc<Mutex<dyn X3dhVault>>,
+        cipher_suite: CipherSuite,
+        vault_initiator: Arc<dyn X3dhVault>,
+        vault_responder: Arc<dyn X3dhVault>,
     ) -> Self {
         Self {
+            cipher_suite,
             vault_initiator,
             vault_responder,
+            prekey_store: None,
         }
     }
+
+    /// Make responders use the prekeys of `prekey_store` and its identity key instead
+    /// of generating their own. The store must have keys of the same curve.
+    pub fn set_prekey_store(&mut self, prekey_store: Arc<PreKeyStore>) {
+        self.prekey_store = Some(prekey_store);
+    }
 }
 
 impl NewKeyExchanger<X3dhInitiator, X3dhResponder> for X3dhNewKeyExchanger {
     fn initiator(&self, identity_key: Option<Arc<Box<dyn Secret>>>) -> X3dhInitiator {
-        X3dhInitiator::new(self.vault_initiator.clone(), identity_key)
+        X3dhInitiator::new(
+            self.cipher_suite,
+            self.vault_initiator.clone(),
+            identity_key,
+        )
     }
 
     fn responder(&self, identity_key: Option<Arc<Box<dyn Secret>>>) -> X3dhResponder {
-        X3dhResponder::new(self.vault_responder.clone(), identity_key)
+        X3dhResponder::new(
+            self.cipher_suite,
+            self.vault_responder.clone(),
+            identity_key,
+            self.prekey_store.clone(),
+        )
     }
 }
 
@@ -566,12 +919,47 @@ mod tests {
     use super::*;
     use ockam_vault_software::DefaultVault;
 
-    #[test]
-    fn handshake() {
-        let vault_i = Arc::new(Mutex::new(DefaultVault::default()));
-        let vault_r = Arc::new(Mutex::new(DefaultVault::default()));
-        let mut initiator = X3dhInitiator::new(vault_i.clone(), None);
-        let mut responder = X3dhResponder::new(vault_r.clone(), None);
+    const CIPHER_SUITES: [CipherSuite; 3] = [
+        CipherSuite::Curve25519AesGcmSha256,
+        CipherSuite::P256Aes128GcmSha256,
+        CipherSuite::Curve25519ChaChaPolySha256,
+    ];
+
+    /// Check that `init` encrypts what `resp` decrypts
+    fn check_keys(
+        cipher_suite: CipherSuite,
+        vault_i: &DefaultVault,
+        vault_r: &DefaultVault,
+        init: &CompletedKeyExchange,
+        resp: &CompletedKeyExchange,
+    ) {
+        assert_eq!(init.h, resp.h);
+        let ciphertext_and_tag = aead_encrypt(
+            vault_i,
+            cipher_suite,
+            init.encrypt_key.as_ref(),
+            b"Hello Alice",
+            &[1u8; 12],
+            &[],
+        )
+        .unwrap();
+        let plaintext = aead_decrypt(
+            vault_r,
+            cipher_suite,
+            resp.decrypt_key.as_ref(),
+            ciphertext_and_tag.as_slice(),
+            &[1u8; 12],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(plaintext, b"Hello Alice");
+    }
+
+    fn handshake(cipher_suite: CipherSuite) {
+        let vault_i = Arc::new(DefaultVault::default());
+        let vault_r = Arc::new(DefaultVault::default());
+        let mut initiator = X3dhInitiator::new(cipher_suite, vault_i.clone(), None);
+        let mut responder = X3dhResponder::new(cipher_suite, vault_r.clone(), None, None);
 
         assert!(initiator.prologue().is_ok());
         assert!(responder.prologue().is_ok());
@@ -579,36 +967,228 @@ mod tests {
         let res = initiator.process(&[]);
         assert!(res.is_ok());
         let eik_bytes = res.unwrap();
-        assert_eq!(eik_bytes.len(), 32);
-        let res = responder.process(&[]);
+        assert_eq!(eik_bytes.len(), public_key_size(cipher_suite));
+        let res = responder.process(eik_bytes.as_slice());
+        assert!(res.is_ok(), "{:?}", res);
+        assert!(res.unwrap().is_empty());
+        let res = responder.process(b"payload");
         assert!(res.is_ok());
         let prekey_bundle_bytes = res.unwrap();
 
         let res = initiator.process(prekey_bundle_bytes.as_slice());
         assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(res.unwrap(), b"payload");
+        assert!(!initiator.is_complete());
+        let res = initiator.process(&[]);
+        assert!(res.is_ok(), "{:?}", res);
         let final_message = res.unwrap();
+        assert_eq!(final_message.len(), enrollment_msg_size(cipher_suite));
+        assert!(initiator.is_complete());
 
-        let res = responder.process(eik_bytes.as_slice());
-        assert!(res.is_ok(), "{:?}", res);
         let res = responder.process(final_message.as_slice());
         assert!(res.is_ok(), res);
+        assert!(responder.is_complete());
 
         let init = initiator.completed_key_exchange.as_ref().unwrap();
         let resp = responder.completed_key_exchange.as_ref().unwrap();
+        check_keys(cipher_suite, &vault_i, &vault_r, init, resp);
+        check_keys(cipher_suite, &vault_r, &vault_i, resp, init);
+    }
 
-        let mut vault_ii = vault_i.lock().unwrap();
-        let ciphertext_and_tag = vault_ii
-            .aead_aes_gcm_encrypt(&init.encrypt_key, b"Hello Alice", &[1u8; 12], &[])
-            .unwrap();
-        let mut vault_rr = vault_r.lock().unwrap();
-        let plaintext = vault_rr
-            .aead_aes_gcm_decrypt(
-                &resp.decrypt_key,
-                ciphertext_and_tag.as_slice(),
-                &[1u8; 12],
-                &[],
-            )
-            .unwrap();
-        assert_eq!(plaintext, b"Hello Alice");
+    #[test]
+    fn handshake_curve25519_aes_gcm() {
+        handshake(CipherSuite::Curve25519AesGcmSha256);
+    }
+
+    #[test]
+    fn handshake_p256_aes128_gcm() {
+        handshake(CipherSuite::P256Aes128GcmSha256);
+    }
+
+    #[test]
+    fn handshake_curve25519_chacha_poly() {
+        handshake(CipherSuite::Curve25519ChaChaPolySha256);
+    }
+
+    /// Enroll with a bundle the initiator got from the store while the responder
+    /// wasn't involved
+    fn enroll(
+        new_key_exchanger: &X3dhNewKeyExchanger,
+        bundle: &PreKeyBundle,
+    ) -> OckamResult<(CompletedKeyExchange, CompletedKeyExchange)> {
+        let mut initiator = new_key_exchanger.initiator(None);
+        let mut responder = new_key_exchanger.responder(None);
+        initiator.set_prekey_bundle(bundle.clone());
+        let message = initiator.process(&[])?;
+        assert!(initiator.is_complete());
+        assert!(responder.process(&message)?.is_empty());
+        assert!(responder.is_complete());
+        Ok((
+            Box::new(initiator).finalize()?,
+            Box::new(responder).finalize()?,
+        ))
+    }
+
+    fn enrollment_with_stored_prekeys(cipher_suite: CipherSuite) {
+        let vault = Arc::new(DefaultVault::default());
+        let store = Arc::new(PreKeyStore::new(cipher_suite, vault.clone(), None).unwrap());
+        store.publish_one_time_prekeys(1).unwrap();
+        let mut new_key_exchanger =
+            X3dhNewKeyExchanger::new(cipher_suite, vault.clone(), vault.clone());
+        new_key_exchanger.set_prekey_store(store.clone());
+
+        let bundle =
+            PreKeyBundle::try_from(store.take_bundle().unwrap().to_bytes().as_slice()).unwrap();
+        assert!(bundle.one_time_prekey_id().is_some());
+        let (init, resp) = enroll(&new_key_exchanger, &bundle).unwrap();
+        assert_eq!(
+            init.remote_static_public_key,
+            vault
+                .secret_public_key_get(store.identity_key().as_ref().as_ref())
+                .unwrap()
+        );
+        check_keys(cipher_suite, &vault, &vault, &init, &resp);
+
+        // the one-time prekey is gone
+        let error = enroll(&new_key_exchanger, &bundle).unwrap_err();
+        assert_eq!(error.code(), Error::UnknownPreKey as u32);
+
+        // without one-time prekeys the signed prekey alone is used
+        let bundle = store.take_bundle().unwrap();
+        assert!(bundle.one_time_prekey_id().is_none());
+        assert_eq!(
+            bundle.to_bytes().len(),
+            PreKeyBundle::size_without_one_time_prekey(public_key_size(cipher_suite))
+        );
+        enroll(&new_key_exchanger, &bundle).unwrap();
+        enroll(&new_key_exchanger, &bundle).unwrap();
+
+        // a rotated out signed prekey works until its grace period ends
+        store.rotate_signed_prekey().unwrap();
+        enroll(&new_key_exchanger, &bundle).unwrap();
+    }
+
+    #[test]
+    fn enrollment_with_stored_curve25519_prekeys() {
+        enrollment_with_stored_prekeys(CipherSuite::Curve25519AesGcmSha256);
+    }
+
+    #[test]
+    fn enrollment_with_stored_p256_prekeys() {
+        enrollment_with_stored_prekeys(CipherSuite::P256Aes128GcmSha256);
+    }
+
+    #[test]
+    fn prekey_bundle_format() {
+        for &cipher_suite in CIPHER_SUITES.iter() {
+            let n = public_key_size(cipher_suite);
+            let size = PreKeyBundle::size(n);
+            let size_without_one_time_prekey = PreKeyBundle::size_without_one_time_prekey(n);
+            let store =
+                PreKeyStore::new(cipher_suite, Arc::new(DefaultVault::default()), None).unwrap();
+            store.publish_one_time_prekeys(1).unwrap();
+            let bytes = store.take_bundle().unwrap().to_bytes();
+            assert_eq!(bytes.len(), size);
+            let bundle = PreKeyBundle::try_from(bytes.as_slice()).unwrap();
+            assert_eq!(bundle.identity_key().as_ref().len(), n);
+            assert_eq!(bundle.to_bytes(), bytes);
+
+            assert!(PreKeyBundle::try_from(&bytes[..bytes.len() - 1]).is_err());
+            // an id without a key and a key without an id
+            assert!(PreKeyBundle::try_from(&bytes[..size_without_one_time_prekey]).is_err());
+            let mut no_id = bytes.clone();
+            no_id[size_without_one_time_prekey - 4..size_without_one_time_prekey]
+                .copy_from_slice(&NO_ONE_TIME_PREKEY.to_be_bytes());
+            assert!(PreKeyBundle::try_from(no_id.as_slice()).is_err());
+
+            // a payload may follow the bundle
+            let mut message = bytes.clone();
+            message.extend_from_slice(b"payload");
+            let (bundle, payload) = PreKeyBundle::decode_prefix(&message, cipher_suite).unwrap();
+            assert_eq!(bundle.to_bytes(), bytes);
+            assert_eq!(payload, b"payload");
+            let (_, payload) = PreKeyBundle::decode_prefix(&no_id, cipher_suite).unwrap();
+            assert_eq!(payload.len(), n);
+            assert!(PreKeyBundle::decode_prefix(&bytes[..size - 1], cipher_suite).is_err());
+        }
+    }
+
+    #[test]
+    fn mismatched_cipher_suites_fail() {
+        let vault = Arc::new(DefaultVault::default());
+        let store = Arc::new(
+            PreKeyStore::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), None).unwrap(),
+        );
+
+        // a P-256 initiator can't use a Curve25519 bundle
+        let mut initiator =
+            X3dhInitiator::new(CipherSuite::P256Aes128GcmSha256, vault.clone(), None);
+        initiator.set_prekey_bundle(store.take_bundle().unwrap());
+        let error = initiator.process(&[]).unwrap_err();
+        assert_eq!(error.code(), Error::CipherSuiteMismatch as u32);
+
+        // nor can a P-256 responder use a Curve25519 store
+        let mut responder = X3dhResponder::new(
+            CipherSuite::P256Aes128GcmSha256,
+            vault.clone(),
+            None,
+            Some(store.clone()),
+        );
+        let error = responder.process(&[0u8; 65]).unwrap_err();
+        assert_eq!(error.code(), Error::CipherSuiteMismatch as u32);
+
+        // the AEADs of suites on the same curve differ
+        let mut new_key_exchanger = X3dhNewKeyExchanger::new(
+            CipherSuite::Curve25519ChaChaPolySha256,
+            vault.clone(),
+            vault.clone(),
+        );
+        new_key_exchanger.set_prekey_store(store.clone());
+        let mut initiator =
+            X3dhInitiator::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), None);
+        let mut responder = new_key_exchanger.responder(None);
+        initiator.set_prekey_bundle(store.take_bundle().unwrap());
+        let message = initiator.process(&[]).unwrap();
+        assert!(responder.process(&message).is_err());
+
+        // and a Curve25519 initiator can't talk to a P-256 responder
+        let mut initiator =
+            X3dhInitiator::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), None);
+        let mut responder = X3dhResponder::new(CipherSuite::P256Aes128GcmSha256, vault, None, None);
+        let eik_bytes = initiator.process(&[]).unwrap();
+        assert!(responder.process(&eik_bytes).is_err());
+    }
+
+    #[test]
+    fn payload_is_authenticated() {
+        let vault = Arc::new(DefaultVault::default());
+        let mut initiator =
+            X3dhInitiator::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), None);
+        let mut responder =
+            X3dhResponder::new(CipherSuite::Curve25519AesGcmSha256, vault, None, None);
+        let eik_bytes = initiator.process(&[]).unwrap();
+        responder.process(&eik_bytes).unwrap();
+        let mut bundle = responder.process(b"payload").unwrap();
+        *bundle.last_mut().unwrap() ^= 1;
+        let error = initiator.process(&bundle).unwrap_err();
+        assert_eq!(error.code(), Error::InvalidPayloadSignature as u32);
+        assert!(!initiator.is_complete());
+    }
+
+    #[test]
+    fn payload_is_bound_to_the_initiator() {
+        let vault = Arc::new(DefaultVault::default());
+        let cipher_suite = CipherSuite::Curve25519AesGcmSha256;
+        let mut responder = X3dhResponder::new(cipher_suite, vault.clone(), None, None);
+        let mut initiator = X3dhInitiator::new(cipher_suite, vault.clone(), None);
+        responder.process(&initiator.process(&[]).unwrap()).unwrap();
+        let bundle = responder.process(b"payload").unwrap();
+
+        // the bundle and payload were signed for another ephemeral identity key
+        let mut other = X3dhInitiator::new(cipher_suite, vault, None);
+        other.process(&[]).unwrap();
+        let error = other.process(&bundle).unwrap_err();
+        assert_eq!(error.code(), Error::InvalidPayloadSignature as u32);
+        assert_eq!(initiator.process(&bundle).unwrap(), b"payload");
     }
 }
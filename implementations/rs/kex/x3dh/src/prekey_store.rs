@@ -0,0 +1,467 @@
+use crate::error::Error;
+use crate::{secret_key_type_and_length, PreKeyBundle, Signature, X3dhVault};
+use ockam_common::error::OckamResult;
+use ockam_kex::CipherSuite;
+use ockam_vault::types::{PublicKey, SecretAttributes, SecretPersistence, SecretUsage};
+use ockam_vault::Secret;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a signed prekey stays usable after it was rotated out by default, long
+/// enough for initiators that fetched a bundle with it to enroll
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long a one-time prekey stays usable after it was put in a bundle by default
+pub const DEFAULT_ONE_TIME_PREKEY_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The secret of a prekey. It is destroyed in the vault when the store and every key
+/// exchange that looked it up have dropped it.
+pub(crate) struct PreKeySecret {
+    secret: Option<Box<dyn Secret>>,
+    vault: Arc<dyn X3dhVault>,
+}
+
+impl PreKeySecret {
+    fn new(secret: Box<dyn Secret>, vault: Arc<dyn X3dhVault>) -> Arc<Self> {
+        Arc::new(Self {
+            secret: Some(secret),
+            vault,
+        })
+    }
+}
+
+impl std::fmt::Debug for PreKeySecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("PreKeySecret").field(&self.secret).finish()
+    }
+}
+
+impl Deref for PreKeySecret {
+    type Target = dyn Secret;
+
+    fn deref(&self) -> &Self::Target {
+        self.secret.as_deref().unwrap()
+    }
+}
+
+impl Drop for PreKeySecret {
+    fn drop(&mut self) {
+        if let Some(secret) = self.secret.take() {
+            // nothing can be done about a failure, the secret is unreachable either way
+            let _ = self.vault.secret_destroy(secret);
+        }
+    }
+}
+
+struct SignedPreKey {
+    id: u32,
+    secret: Arc<PreKeySecret>,
+    public_key: PublicKey,
+    signature: Signature,
+    /// When a newer signed prekey replaced this one
+    retired_at: Option<Instant>,
+}
+
+struct OneTimePreKey {
+    secret: Arc<PreKeySecret>,
+    public_key: PublicKey,
+    /// When it was put in a bundle. It isn't handed out again but stays usable until
+    /// it is consumed or its lifetime is over.
+    handed_out: Option<Instant>,
+}
+
+struct State {
+    /// The last one is current
+    signed_prekeys: Vec<SignedPreKey>,
+    one_time_prekeys: BTreeMap<u32, OneTimePreKey>,
+    next_id: u32,
+}
+
+impl State {
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+/// Prekeys of an X3DH responder. Bundles are handed out to initiators, possibly while
+/// the responder is offline, and the responder looks the prekeys up by the ids the
+/// initiator sends back. Every one-time prekey goes into a single bundle and is
+/// consumed by the first enrollment that uses it, or expires if no enrollment uses it
+/// within its lifetime. When no one-time prekeys are left bundles carry only the
+/// signed prekey.
+///
+/// Prekey secrets that expire or are consumed are destroyed once no key exchange
+/// holds them anymore.
+///
+/// The store only lives in memory and its prekeys are ephemeral secrets, so none are
+/// left behind in a persistent vault. After a restart the bundles handed out before
+/// refer to unknown prekeys and initiators have to take new ones.
+pub struct PreKeyStore {
+    cipher_suite: CipherSuite,
+    vault: Arc<dyn X3dhVault>,
+    identity_key: Arc<Box<dyn Secret>>,
+    grace_period: Duration,
+    one_time_prekey_lifetime: Duration,
+    state: Mutex<State>,
+}
+
+impl std::fmt::Debug for PreKeyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("PreKeyStore")
+            .field("cipher_suite", &self.cipher_suite)
+            .field("identity_key", &self.identity_key)
+            .field("grace_period", &self.grace_period)
+            .field("one_time_prekey_lifetime", &self.one_time_prekey_lifetime)
+            .field("signed_prekeys", &state.signed_prekeys.len())
+            .field("one_time_prekeys", &state.one_time_prekeys.len())
+            .finish()
+    }
+}
+
+impl PreKeyStore {
+    /// Create a store with the keys of `cipher_suite` signing its prekeys with
+    /// `identity_key`, or with a new ephemeral identity key if it is `None`, and
+    /// generate the first signed prekey
+    pub fn new(
+        cipher_suite: CipherSuite,
+        vault: Arc<dyn X3dhVault>,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+    ) -> OckamResult<Self> {
+        let (stype, length) = secret_key_type_and_length(cipher_suite);
+        let identity_key = match identity_key {
+            Some(identity_key) => identity_key,
+            None => Arc::new(vault.secret_generate(SecretAttributes {
+                persistence: SecretPersistence::Ephemeral,
+                stype,
+                length,
+                exportable: false,
+                usage: SecretUsage::SIGN | SecretUsage::ECDH,
+            })?),
+        };
+        let store = Self {
+            cipher_suite,
+            vault,
+            identity_key,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            one_time_prekey_lifetime: DEFAULT_ONE_TIME_PREKEY_LIFETIME,
+            state: Mutex::new(State {
+                signed_prekeys: Vec::new(),
+                one_time_prekeys: BTreeMap::new(),
+                next_id: 1,
+            }),
+        };
+        store.rotate_signed_prekey()?;
+        Ok(store)
+    }
+
+    /// Set how long a rotated out signed prekey stays usable
+    pub fn set_grace_period(&mut self, grace_period: Duration) {
+        self.grace_period = grace_period;
+    }
+
+    /// Set how long a one-time prekey stays usable after it was put in a bundle
+    pub fn set_one_time_prekey_lifetime(&mut self, lifetime: Duration) {
+        self.one_time_prekey_lifetime = lifetime;
+    }
+
+    /// The cipher suite whose keys the store generates
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
+    /// The identity key that signs the prekeys
+    pub fn identity_key(&self) -> Arc<Box<dyn Secret>> {
+        self.identity_key.clone()
+    }
+
+    /// Replace the current signed prekey with a new one and return its id. The old one
+    /// keeps working for the grace period.
+    pub fn rotate_signed_prekey(&self) -> OckamResult<u32> {
+        let (stype, length) = secret_key_type_and_length(self.cipher_suite);
+        let secret = self.vault.secret_generate(SecretAttributes {
+            persistence: SecretPersistence::Ephemeral,
+            stype,
+            length,
+            exportable: false,
+            usage: SecretUsage::ECDH,
+        })?;
+        let public_key = self.vault.secret_public_key_get(secret.as_ref())?;
+        let signature = self
+            .vault
+            .sign(self.identity_key.as_ref().as_ref(), public_key.as_ref())?;
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if let Some(current) = state.signed_prekeys.last_mut() {
+            current.retired_at = Some(now);
+        }
+        let id = state.next_id();
+        state.signed_prekeys.push(SignedPreKey {
+            id,
+            secret: PreKeySecret::new(secret, self.vault.clone()),
+            public_key,
+            signature: Signature(signature),
+            retired_at: None,
+        });
+        self.remove_expired(&mut state);
+        Ok(id)
+    }
+
+    /// Generate `count` one-time prekeys for future bundles and return their ids
+    pub fn publish_one_time_prekeys(&self, count: usize) -> OckamResult<Vec<u32>> {
+        let (stype, length) = secret_key_type_and_length(self.cipher_suite);
+        let mut prekeys = Vec::with_capacity(count);
+        for _ in 0..count {
+            let secret = self.vault.secret_generate(SecretAttributes {
+                persistence: SecretPersistence::Ephemeral,
+                stype,
+                length,
+                exportable: false,
+                usage: SecretUsage::ECDH,
+            })?;
+            let public_key = self.vault.secret_public_key_get(secret.as_ref())?;
+            prekeys.push((secret, public_key));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        Ok(prekeys
+            .into_iter()
+            .map(|(secret, public_key)| {
+                let id = state.next_id();
+                state.one_time_prekeys.insert(
+                    id,
+                    OneTimePreKey {
+                        secret: PreKeySecret::new(secret, self.vault.clone()),
+                        public_key,
+                        handed_out: None,
+                    },
+                );
+                id
+            })
+            .collect())
+    }
+
+    /// Number of one-time prekeys that can still go into bundles
+    pub fn available_one_time_prekeys(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .one_time_prekeys
+            .values()
+            .filter(|p| p.handed_out.is_none())
+            .count()
+    }
+
+    /// Number of one-time prekeys the store keeps, the handed out ones that weren't
+    /// consumed and didn't expire included
+    pub fn one_time_prekeys(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        self.remove_expired(&mut state);
+        state.one_time_prekeys.len()
+    }
+
+    /// Create a bundle with the current signed prekey and a one-time prekey that
+    /// wasn't handed out before, if there is one
+    pub fn take_bundle(&self) -> OckamResult<PreKeyBundle> {
+        let identity_key = self
+            .vault
+            .secret_public_key_get(self.identity_key.as_ref().as_ref())?;
+        let mut state = self.state.lock().unwrap();
+        self.remove_expired(&mut state);
+        let one_time_prekey = state
+            .one_time_prekeys
+            .iter_mut()
+            .find(|(_, p)| p.handed_out.is_none())
+            .map(|(id, p)| {
+                p.handed_out = Some(Instant::now());
+                (*id, p.public_key.clone())
+            });
+        let current = state
+            .signed_prekeys
+            .last()
+            .ok_or_else(|| Error::InvalidState.into())?;
+        Ok(PreKeyBundle {
+            identity_key,
+            signed_prekey_id: current.id,
+            signed_prekey: current.public_key.clone(),
+            signature_prekey: current.signature,
+            one_time_prekey,
+        })
+    }
+
+    /// The signed prekey with `id` if it is current or within its grace period
+    pub(crate) fn signed_prekey(&self, id: u32) -> OckamResult<Arc<PreKeySecret>> {
+        let mut state = self.state.lock().unwrap();
+        self.remove_expired(&mut state);
+        state
+            .signed_prekeys
+            .iter()
+            .find(|p| p.id == id)
+            .map(|p| p.secret.clone())
+            .ok_or_else(|| Error::UnknownPreKey.into())
+    }
+
+    /// The one-time prekey with `id` if it wasn't consumed yet and didn't expire
+    pub(crate) fn one_time_prekey(&self, id: u32) -> OckamResult<Arc<PreKeySecret>> {
+        let mut state = self.state.lock().unwrap();
+        self.remove_expired(&mut state);
+        state
+            .one_time_prekeys
+            .get(&id)
+            .map(|p| p.secret.clone())
+            .ok_or_else(|| Error::UnknownPreKey.into())
+    }
+
+    /// Remove the one-time prekey with `id` after an enrollment used it. Fails if
+    /// another enrollment consumed it first.
+    pub(crate) fn consume_one_time_prekey(&self, id: u32) -> OckamResult<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .one_time_prekeys
+            .remove(&id)
+            .map(|_| ())
+            .ok_or_else(|| Error::UnknownPreKey.into())
+    }
+
+    /// Drop the rotated out signed prekeys past their grace period and the handed out
+    /// one-time prekeys past their lifetime
+    fn remove_expired(&self, state: &mut State) {
+        let grace_period = self.grace_period;
+        state
+            .signed_prekeys
+            .retain(|p| !matches!(p.retired_at, Some(t) if t.elapsed() >= grace_period));
+        let lifetime = self.one_time_prekey_lifetime;
+        state
+            .one_time_prekeys
+            .retain(|_, p| !matches!(p.handed_out, Some(t) if t.elapsed() >= lifetime));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam_vault::SecretVault;
+    use ockam_vault_software::{DefaultVault, DefaultVaultSecret};
+
+    fn store() -> PreKeyStore {
+        store_with_vault(Arc::new(DefaultVault::default()))
+    }
+
+    fn store_with_vault(vault: Arc<DefaultVault>) -> PreKeyStore {
+        PreKeyStore::new(CipherSuite::Curve25519AesGcmSha256, vault, None).unwrap()
+    }
+
+    /// Another handle to the same vault entry, it outlives the prekey secret
+    fn same_entry(secret: &PreKeySecret) -> Box<dyn Secret> {
+        Box::new(*DefaultVaultSecret::downcast_secret(&**secret).unwrap())
+    }
+
+    #[test]
+    fn one_time_prekeys_are_handed_out_once() {
+        let store = store();
+        let ids = store.publish_one_time_prekeys(3).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(store.available_one_time_prekeys(), 3);
+
+        let bundles: Vec<_> = (0..4).map(|_| store.take_bundle().unwrap()).collect();
+        let handed_out: Vec<_> = bundles
+            .iter()
+            .filter_map(|b| b.one_time_prekey_id())
+            .collect();
+        assert_eq!(handed_out, ids);
+        assert!(bundles[3].one_time_prekey_id().is_none());
+        assert_eq!(store.available_one_time_prekeys(), 0);
+
+        // handed out prekeys stay usable until consumed
+        assert!(store.one_time_prekey(ids[0]).is_ok());
+        store.consume_one_time_prekey(ids[0]).unwrap();
+        assert!(store.one_time_prekey(ids[0]).is_err());
+        let error = store.consume_one_time_prekey(ids[0]).unwrap_err();
+        assert_eq!(error.code(), Error::UnknownPreKey as u32);
+    }
+
+    #[test]
+    fn rotated_signed_prekeys_expire_after_grace_period() {
+        let mut store = store();
+        let first = store.take_bundle().unwrap().signed_prekey_id();
+        let second = store.rotate_signed_prekey().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(store.take_bundle().unwrap().signed_prekey_id(), second);
+        assert!(store.signed_prekey(first).is_ok());
+
+        store.set_grace_period(Duration::from_secs(0));
+        let error = store.signed_prekey(first).unwrap_err();
+        assert_eq!(error.code(), Error::UnknownPreKey as u32);
+        // the current one never expires
+        assert!(store.signed_prekey(second).is_ok());
+    }
+
+    #[test]
+    fn handed_out_one_time_prekeys_expire() {
+        let mut store = store();
+        let ids = store.publish_one_time_prekeys(2).unwrap();
+        let bundle = store.take_bundle().unwrap();
+        assert_eq!(bundle.one_time_prekey_id(), Some(ids[0]));
+        assert_eq!(store.one_time_prekeys(), 2);
+
+        store.set_one_time_prekey_lifetime(Duration::from_secs(0));
+        let error = store.one_time_prekey(ids[0]).unwrap_err();
+        assert_eq!(error.code(), Error::UnknownPreKey as u32);
+        // the one that wasn't handed out yet doesn't expire
+        assert_eq!(store.one_time_prekeys(), 1);
+        assert_eq!(
+            store.take_bundle().unwrap().one_time_prekey_id(),
+            Some(ids[1])
+        );
+    }
+
+    #[test]
+    fn generated_keys_are_ephemeral() {
+        let vault = Arc::new(DefaultVault::default());
+        let store = store_with_vault(vault.clone());
+        let id = store.publish_one_time_prekeys(1).unwrap()[0];
+        let signed_prekey_id = store.take_bundle().unwrap().signed_prekey_id();
+        let keys = vec![
+            store.identity_key(),
+            Arc::new(same_entry(&store.signed_prekey(signed_prekey_id).unwrap())),
+            Arc::new(same_entry(&store.one_time_prekey(id).unwrap())),
+        ];
+        for key in keys {
+            let attributes = vault.secret_attributes_get(key.as_ref().as_ref()).unwrap();
+            assert_eq!(attributes.persistence, SecretPersistence::Ephemeral);
+        }
+    }
+
+    #[test]
+    fn secrets_are_destroyed_after_the_last_holder_drops_them() {
+        let vault = Arc::new(DefaultVault::default());
+        let store = store_with_vault(vault.clone());
+        let id = store.publish_one_time_prekeys(1).unwrap()[0];
+        store.take_bundle().unwrap();
+
+        // a key exchange is using the prekey while another one consumes it
+        let held = store.one_time_prekey(id).unwrap();
+        let handle = same_entry(&held);
+        store.consume_one_time_prekey(id).unwrap();
+        assert!(vault.secret_public_key_get(handle.as_ref()).is_ok());
+        std::mem::drop(held);
+        assert!(vault.secret_public_key_get(handle.as_ref()).is_err());
+
+        // the same goes for expired signed prekeys
+        let mut store = store;
+        let first = store.take_bundle().unwrap().signed_prekey_id();
+        let held = store.signed_prekey(first).unwrap();
+        let handle = same_entry(&held);
+        store.set_grace_period(Duration::from_secs(0));
+        store.rotate_signed_prekey().unwrap();
+        assert!(store.signed_prekey(first).is_err());
+        assert!(vault.secret_public_key_get(handle.as_ref()).is_ok());
+        std::mem::drop(held);
+        assert!(vault.secret_public_key_get(handle.as_ref()).is_err());
+    }
+}
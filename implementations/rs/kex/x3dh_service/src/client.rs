@@ -0,0 +1,57 @@
+use crate::error::Error;
+use ockam::message::{Route, RouterAddress};
+use ockam::service::ServiceClient;
+use ockam::system::commands::OckamCommand;
+use ockam_common::error::OckamResult;
+use ockam_kex_x3dh::PreKeyBundle;
+use std::convert::TryFrom;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// Error domains of the service, errors from them keep their domain on the client
+const SERVICE_ERROR_DOMAINS: &[&str] = &[
+    Error::ERROR_DOMAIN,
+    ockam_kex_x3dh::error::Error::ERROR_DOMAIN,
+    "VAULT_TRAITS_ERROR_DOMAIN",
+    "VAULT_SOFTWARE_ERROR_DOMAIN",
+    "VAULT_FILESYSTEM_ERROR_DOMAIN",
+    "VAULT_PKCS11_ERROR_DOMAIN",
+    "VAULT_AUDIT_ERROR_DOMAIN",
+    "VAULT_SERVICE_ERROR_DOMAIN",
+];
+
+/// Fetches prekey bundles from a `PreKeyBundleWorker`. A request blocks until the
+/// bundle arrives, so the client must not be used on the thread that polls the router
+/// of its node.
+///
+/// The bundle is signed by the responder's identity key, check that it is the key of
+/// the expected responder before enrolling.
+#[derive(Debug)]
+pub struct PreKeyBundleClient {
+    client: ServiceClient,
+}
+
+impl PreKeyBundleClient {
+    /// Create a client at `addr` that fetches bundles from the worker at the end of
+    /// `route` and register it with the router
+    pub fn new(addr: RouterAddress, route: Route, router_tx: Sender<OckamCommand>) -> Self {
+        let mut client = ServiceClient::new(addr, route, router_tx);
+        client.set_error_domains(SERVICE_ERROR_DOMAINS);
+        Self { client }
+    }
+
+    /// Set how long a request waits for the bundle
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.client.set_timeout(timeout);
+    }
+
+    pub fn sender(&self) -> Sender<OckamCommand> {
+        self.client.sender()
+    }
+
+    /// Ask the worker for a bundle
+    pub fn fetch(&self) -> OckamResult<PreKeyBundle> {
+        let bundle = self.client.call(vec![])?;
+        PreKeyBundle::try_from(bundle.as_slice()).map_err(|_| Error::InvalidResponse.into())
+    }
+}
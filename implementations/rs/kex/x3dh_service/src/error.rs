@@ -0,0 +1,23 @@
+use ockam_common::error::OckamError;
+
+/// Represents the failures that can occur in
+/// an Ockam X3DH prekey bundle service
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    None,
+    /// The response isn't a prekey bundle
+    InvalidResponse,
+    /// The worker answered too many requests recently
+    RateLimited,
+}
+
+impl Error {
+    /// Error domain
+    pub const ERROR_DOMAIN: &'static str = "KEX_X3DH_SERVICE_ERROR_DOMAIN";
+}
+
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
+    }
+}
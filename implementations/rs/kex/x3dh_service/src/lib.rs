@@ -0,0 +1,169 @@
+//! A prekey bundle service lets X3DH initiators enroll with a responder that is
+//! offline. The `PreKeyBundleWorker` runs next to the responder's `PreKeyStore` and
+//! hands out its bundles to `PreKeyBundleClient`s through the request and response
+//! messages of `ockam::service`, the bundles are in the `PreKeyBundle::to_bytes`
+//! format.
+
+pub use client::PreKeyBundleClient;
+pub use ockam::service::DEFAULT_TIMEOUT;
+pub use ockam_kex_x3dh;
+pub use worker::PreKeyBundleWorker;
+
+mod client;
+pub mod error;
+mod worker;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam::message::RouterAddress;
+    use ockam::service::error::Error as ServiceError;
+    use ockam_kex::{CipherSuite, KeyExchanger, NewKeyExchanger};
+    use ockam_kex_x3dh::{PreKeyStore, X3dhNewKeyExchanger};
+    use ockam_router::local::{LocalNetwork, LocalNode};
+    use ockam_vault::SecretVault;
+    use ockam_vault_software::DefaultVault;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const SERVICE: &str = "b0b0b0b0";
+    const CLIENT: &str = "a1a1a1a1";
+
+    /// Poll the nodes and the bundle worker until the network is dropped
+    fn start(nodes: Vec<LocalNode>, mut worker: PreKeyBundleWorker) -> LocalNetwork {
+        LocalNetwork::start(nodes, move || {
+            move || {
+                worker.poll();
+            }
+        })
+    }
+
+    /// A responder node with a bundle worker and an initiator node with a client
+    fn setup(store: Arc<PreKeyStore>) -> (PreKeyBundleClient, PreKeyBundleWorker, Vec<LocalNode>) {
+        let responder = LocalNode::new("127.0.0.1:4300");
+        let initiator = LocalNode::new("127.0.0.1:4400");
+        let worker = PreKeyBundleWorker::new(
+            RouterAddress::worker_router_address_from_str(SERVICE).unwrap(),
+            responder.router_tx.clone(),
+            store,
+        );
+        let client = PreKeyBundleClient::new(
+            RouterAddress::worker_router_address_from_str(CLIENT).unwrap(),
+            responder.route_to(SERVICE),
+            initiator.router_tx.clone(),
+        );
+        (client, worker, vec![responder, initiator])
+    }
+
+    fn enroll_with_fetched_bundles(cipher_suite: CipherSuite) {
+        let vault = Arc::new(DefaultVault::default());
+        let store = Arc::new(PreKeyStore::new(cipher_suite, vault.clone(), None).unwrap());
+        let published = store.publish_one_time_prekeys(2).unwrap();
+        let (client, worker, nodes) = setup(store.clone());
+        let _network = start(nodes, worker);
+
+        let mut new_key_exchanger =
+            X3dhNewKeyExchanger::new(cipher_suite, vault.clone(), vault.clone());
+        new_key_exchanger.set_prekey_store(store.clone());
+        let identity_key = vault
+            .secret_public_key_get(store.identity_key().as_ref().as_ref())
+            .unwrap();
+
+        let mut handed_out = Vec::new();
+        for _ in 0..3 {
+            let bundle = client.fetch().unwrap();
+            assert_eq!(bundle.identity_key(), &identity_key);
+            handed_out.extend(bundle.one_time_prekey_id());
+
+            // the responder only sees the enrollment
+            let mut initiator = new_key_exchanger.initiator(None);
+            let mut responder = new_key_exchanger.responder(None);
+            initiator.set_prekey_bundle(bundle);
+            let enrollment = initiator.process(&[]).unwrap();
+            responder.process(&enrollment).unwrap();
+            assert!(initiator.is_complete() && responder.is_complete());
+            let init = Box::new(initiator).finalize().unwrap();
+            let resp = Box::new(responder).finalize().unwrap();
+            assert_eq!(init.h, resp.h);
+        }
+        assert_eq!(handed_out, published);
+    }
+
+    #[test]
+    fn enroll_with_fetched_curve25519_bundles() {
+        enroll_with_fetched_bundles(CipherSuite::Curve25519AesGcmSha256);
+    }
+
+    #[test]
+    fn enroll_with_fetched_p256_bundles() {
+        enroll_with_fetched_bundles(CipherSuite::P256Aes128GcmSha256);
+    }
+
+    #[test]
+    fn worker_refills_one_time_prekeys() {
+        let store = Arc::new(
+            PreKeyStore::new(
+                CipherSuite::Curve25519AesGcmSha256,
+                Arc::new(DefaultVault::default()),
+                None,
+            )
+            .unwrap(),
+        );
+        let (client, mut worker, nodes) = setup(store.clone());
+        worker.set_refill(1, 3, 4);
+        let _network = start(nodes, worker);
+
+        // the second refill only has room for one prekey, then no more are published
+        // while the handed out ones are kept
+        let bundles: Vec<_> = (0..5).map(|_| client.fetch().unwrap()).collect();
+        assert!(bundles[..4]
+            .iter()
+            .all(|b| b.one_time_prekey_id().is_some()));
+        assert!(bundles[4].one_time_prekey_id().is_none());
+        assert_eq!(store.available_one_time_prekeys(), 0);
+        assert_eq!(store.one_time_prekeys(), 4);
+    }
+
+    #[test]
+    fn worker_rate_limits_requests() {
+        let store = Arc::new(
+            PreKeyStore::new(
+                CipherSuite::Curve25519AesGcmSha256,
+                Arc::new(DefaultVault::default()),
+                None,
+            )
+            .unwrap(),
+        );
+        store.publish_one_time_prekeys(10).unwrap();
+        let (client, mut worker, nodes) = setup(store.clone());
+        worker.set_rate_limit(2, Duration::from_secs(60 * 60));
+        let _network = start(nodes, worker);
+
+        client.fetch().unwrap();
+        client.fetch().unwrap();
+        let error = client.fetch().unwrap_err();
+        assert_eq!(error.code(), error::Error::RateLimited as u32);
+        assert_eq!(error.domain(), error::Error::ERROR_DOMAIN);
+        assert_eq!(store.available_one_time_prekeys(), 8);
+    }
+
+    #[test]
+    fn no_service() {
+        let (mut client, worker, nodes) = setup(Arc::new(
+            PreKeyStore::new(
+                CipherSuite::Curve25519AesGcmSha256,
+                Arc::new(DefaultVault::default()),
+                None,
+            )
+            .unwrap(),
+        ));
+        client.set_timeout(Duration::from_millis(100));
+        // the worker isn't polled
+        std::mem::drop(worker);
+        let _nodes = nodes;
+
+        let error = client.fetch().unwrap_err();
+        assert_eq!(error.code(), ServiceError::Timeout as u32);
+        assert_eq!(error.domain(), ServiceError::ERROR_DOMAIN);
+    }
+}
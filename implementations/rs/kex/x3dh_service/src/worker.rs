@@ -0,0 +1,139 @@
+use crate::error::Error;
+use ockam::message::RouterAddress;
+use ockam::service::ServiceWorker;
+use ockam::system::commands::OckamCommand;
+use ockam_common::error::OckamResult;
+use ockam_kex_x3dh::PreKeyStore;
+use std::collections::VecDeque;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A worker that hands out prekey bundles of a `PreKeyStore` to initiators, so they
+/// can enroll with a responder that wasn't online when they asked. Each request gets
+/// a bundle with a different one-time prekey until the store runs out of them.
+///
+/// When the number of one-time prekeys that can still be handed out drops below the
+/// refill threshold the worker publishes a new batch, up to a limit on the prekeys the
+/// store keeps. Anyone who can reach the worker can ask for bundles, a rate limit or
+/// requiring requests to come through a secure channel keeps them from using up the
+/// one-time prekeys.
+///
+/// The store is kept in memory, bundles handed out before the worker's node restarted
+/// can't be used to enroll anymore.
+pub struct PreKeyBundleWorker {
+    worker: ServiceWorker,
+    store: Arc<PreKeyStore>,
+    refill: Option<Refill>,
+    rate_limit: Option<RateLimit>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Refill {
+    threshold: usize,
+    batch: usize,
+    limit: usize,
+}
+
+/// Answers at most `max_requests` in any `period`
+#[derive(Debug)]
+struct RateLimit {
+    max_requests: usize,
+    period: Duration,
+    answered: VecDeque<Instant>,
+}
+
+impl RateLimit {
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        while matches!(self.answered.front(), Some(t) if now.duration_since(*t) >= self.period) {
+            self.answered.pop_front();
+        }
+        if self.answered.len() >= self.max_requests {
+            return false;
+        }
+        self.answered.push_back(now);
+        true
+    }
+}
+
+impl std::fmt::Debug for PreKeyBundleWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PreKeyBundleWorker")
+            .field("worker", &self.worker)
+            .field("store", &self.store)
+            .field("refill", &self.refill)
+            .field("rate_limit", &self.rate_limit)
+            .finish()
+    }
+}
+
+impl PreKeyBundleWorker {
+    /// Create the worker at `addr` and register it with the router
+    pub fn new(
+        addr: RouterAddress,
+        router_tx: Sender<OckamCommand>,
+        store: Arc<PreKeyStore>,
+    ) -> Self {
+        Self {
+            worker: ServiceWorker::new(addr, router_tx),
+            store,
+            refill: None,
+            rate_limit: None,
+        }
+    }
+
+    /// Publish `batch` one-time prekeys whenever fewer than `threshold` are left to
+    /// hand out, without the store keeping more than `limit` of them. The handed out
+    /// ones count until they are consumed or expire.
+    pub fn set_refill(&mut self, threshold: usize, batch: usize, limit: usize) {
+        self.refill = Some(Refill {
+            threshold,
+            batch,
+            limit,
+        });
+    }
+
+    /// Refuse requests with `RateLimited` once `max_requests` were answered within
+    /// `period`
+    pub fn set_rate_limit(&mut self, max_requests: usize, period: Duration) {
+        self.rate_limit = Some(RateLimit {
+            max_requests,
+            period,
+            answered: VecDeque::with_capacity(max_requests),
+        });
+    }
+
+    /// Refuse requests that didn't arrive through a secure channel, so only peers
+    /// the channel's trust policy accepts get bundles
+    pub fn set_channel_required(&mut self, channel_required: bool) {
+        self.worker.set_channel_required(channel_required);
+    }
+
+    pub fn sender(&self) -> Sender<OckamCommand> {
+        self.worker.sender()
+    }
+
+    /// Answer the requests that arrived since the last poll
+    pub fn poll(&mut self) -> bool {
+        let store = &self.store;
+        let refill = self.refill;
+        let rate_limit = &mut self.rate_limit;
+        self.worker.poll(|_, _| {
+            if rate_limit.as_mut().is_some_and(|r| !r.allow()) {
+                return Err(Error::RateLimited.into());
+            }
+            bundle(store, refill)
+        })
+    }
+}
+
+fn bundle(store: &PreKeyStore, refill: Option<Refill>) -> OckamResult<Vec<u8>> {
+    if let Some(refill) = refill {
+        if store.available_one_time_prekeys() < refill.threshold {
+            let room = refill.limit.saturating_sub(store.one_time_prekeys());
+            store.publish_one_time_prekeys(refill.batch.min(room))?;
+        }
+    }
+    Ok(store.take_bundle()?.to_bytes())
+}
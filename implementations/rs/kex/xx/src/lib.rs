@@ -1,18 +1,21 @@
 use crate::error::Error;
 use crate::ResponderState::DecodeMessage1;
+use async_trait::async_trait;
 use ockam_common::error::OckamResult;
 use ockam_kex::{
     CipherSuite, CompletedKeyExchange, KeyExchange, KeyExchanger, NewKeyExchanger, AES_GCM_TAGSIZE,
-    SHA256_SIZE,
+    EPHEMERAL_KEY_ATTEMPTS, SHA256_SIZE,
 };
+use ockam_vault::asynchronous as async_vault;
 use ockam_vault::types::{
-    AES128_SECRET_LENGTH, AES256_SECRET_LENGTH, CURVE25519_SECRET_LENGTH, P256_SECRET_LENGTH,
+    AES128_SECRET_LENGTH, AES256_SECRET_LENGTH, CHACHA20POLY1305_SECRET_LENGTH,
+    CURVE25519_SECRET_LENGTH, P256_SECRET_LENGTH,
 };
 use ockam_vault::{
-    types::{PublicKey, SecretAttributes, SecretPersistence, SecretType},
-    AsymmetricVault, HashVault, Secret, SecretVault, SymmetricVault,
+    types::{PublicKey, SecretAttributes, SecretKey, SecretPersistence, SecretType, SecretUsage},
+    AsymmetricVault, HashVault, RandomVault, Secret, SecretVault, SymmetricVault,
 };
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use zeroize::Zeroize;
 
 pub mod error;
@@ -24,12 +27,42 @@ struct KeyPair {
 }
 
 /// Vault with XX required functionality
-pub trait XXVault: SecretVault + HashVault + AsymmetricVault + SymmetricVault + Send {}
+pub trait XXVault:
+    SecretVault + HashVault + AsymmetricVault + SymmetricVault + RandomVault + Send + Sync
+{
+}
+
+impl<D> XXVault for D where
+    D: SecretVault + HashVault + AsymmetricVault + SymmetricVault + RandomVault + Send + Sync
+{
+}
+
+/// Vault with XX required functionality, for key exchanges that don't block the executor
+/// while the vault works. Every `XXVault` is also an `AsyncXXVault`.
+pub trait AsyncXXVault:
+    async_vault::AsyncSecretVault
+    + async_vault::AsyncHashVault
+    + async_vault::AsyncAsymmetricVault
+    + async_vault::AsyncSymmetricVault
+    + async_vault::AsyncRandomVault
+    + Send
+    + Sync
+{
+}
 
-impl<D> XXVault for D where D: SecretVault + HashVault + AsymmetricVault + SymmetricVault + Send {}
+impl<D: ?Sized> AsyncXXVault for D where
+    D: async_vault::AsyncSecretVault
+        + async_vault::AsyncHashVault
+        + async_vault::AsyncAsymmetricVault
+        + async_vault::AsyncSymmetricVault
+        + async_vault::AsyncRandomVault
+        + Send
+        + Sync
+{
+}
 
-/// Represents the XX Handshake
-pub struct SymmetricState {
+/// Represents the XX Handshake, run over a sync `XXVault` or an `AsyncXXVault`
+pub struct SymmetricState<V: ?Sized = dyn XXVault> {
     cipher_suite: CipherSuite,
     identity_key: Option<Arc<Box<dyn Secret>>>,
     identity_public_key: Option<PublicKey>,
@@ -40,17 +73,17 @@ pub struct SymmetricState {
     nonce: u16,
     h: Option<[u8; SHA256_SIZE]>,
     ck: Option<Box<dyn Secret>>,
-    vault: Arc<Mutex<dyn XXVault>>,
+    vault: Arc<V>,
 }
 
-impl Zeroize for SymmetricState {
+impl<V: ?Sized> Zeroize for SymmetricState<V> {
     fn zeroize(&mut self) {
         self.nonce.zeroize();
         self.h.zeroize();
     }
 }
 
-impl std::fmt::Debug for SymmetricState {
+impl<V: ?Sized> std::fmt::Debug for SymmetricState<V> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
@@ -60,13 +93,24 @@ impl std::fmt::Debug for SymmetricState {
     }
 }
 
-impl SymmetricState {
+impl<V: ?Sized> SymmetricState<V> {
+    fn protocol_name(&self) -> &'static [u8] {
+        match self.cipher_suite {
+            CipherSuite::Curve25519AesGcmSha256 => b"Noise_XX_25519_AESGCM_SHA256\0\0\0\0",
+            CipherSuite::P256Aes128GcmSha256 => b"Noise_XX_P256_AES128GCM_SHA256\0\0",
+            CipherSuite::Curve25519ChaChaPolySha256 => b"Noise_XX_25519_ChaChaPoly_SHA256",
+        }
+    }
+
     fn get_secret_key_type_and_length(&self) -> (SecretType, usize) {
         match self.cipher_suite {
             CipherSuite::Curve25519AesGcmSha256 => {
                 (SecretType::Curve25519, CURVE25519_SECRET_LENGTH)
             }
             CipherSuite::P256Aes128GcmSha256 => (SecretType::P256, P256_SECRET_LENGTH),
+            CipherSuite::Curve25519ChaChaPolySha256 => {
+                (SecretType::Curve25519, CURVE25519_SECRET_LENGTH)
+            }
         }
     }
 
@@ -74,6 +118,9 @@ impl SymmetricState {
         match self.cipher_suite {
             CipherSuite::Curve25519AesGcmSha256 => (SecretType::Aes, AES256_SECRET_LENGTH),
             CipherSuite::P256Aes128GcmSha256 => (SecretType::Aes, AES128_SECRET_LENGTH),
+            CipherSuite::Curve25519ChaChaPolySha256 => {
+                (SecretType::ChaCha20Poly1305, CHACHA20POLY1305_SECRET_LENGTH)
+            }
         }
     }
 
@@ -81,12 +128,28 @@ impl SymmetricState {
         match self.cipher_suite {
             CipherSuite::Curve25519AesGcmSha256 => 32,
             CipherSuite::P256Aes128GcmSha256 => 65,
+            CipherSuite::Curve25519ChaChaPolySha256 => 32,
         }
     }
 
-    pub fn new(
+    /// AES-GCM takes the nonce big-endian in the last bytes, ChaChaPoly
+    /// little-endian after 4 zero bytes as Noise specifies
+    fn get_nonce(&self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        match self.cipher_suite {
+            CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => {
+                nonce[10..].copy_from_slice(&self.nonce.to_be_bytes())
+            }
+            CipherSuite::Curve25519ChaChaPolySha256 => {
+                nonce[4..].copy_from_slice(&(self.nonce as u64).to_le_bytes())
+            }
+        }
+        nonce
+    }
+
+    fn with_vault(
         cipher_suite: CipherSuite,
-        vault: Arc<Mutex<dyn XXVault>>,
+        vault: Arc<V>,
         identity_key: Option<Arc<Box<dyn Secret>>>,
     ) -> Self {
         Self {
@@ -105,392 +168,42 @@ impl SymmetricState {
     }
 }
 
-impl KeyExchange for SymmetricState {
-    fn get_protocol_name(&self) -> &'static [u8] {
-        match self.cipher_suite {
-            CipherSuite::Curve25519AesGcmSha256 => b"Noise_XX_25519_AESGCM_SHA256\0\0\0\0",
-            CipherSuite::P256Aes128GcmSha256 => b"Noise_XX_P256_AES128GCM_SHA256\0\0",
-        }
-    }
-
-    /// Create a new `HandshakeState` starting with the prologue
-    fn prologue(&mut self) -> OckamResult<()> {
-        let asymmetric_secret_info = self.get_secret_key_type_and_length();
-
-        let mut attributes = SecretAttributes {
-            stype: asymmetric_secret_info.0,
-            persistence: SecretPersistence::Persistent,
-            length: asymmetric_secret_info.1,
-        };
-        // 1. Generate a static key pair for this handshake and set it to `s`
-        let mut vault = self.vault.lock().unwrap();
-        let identity_key = self.identity_key.take();
-        let identity_key = match identity_key {
-            None => {
-                let static_secret_handle = vault.secret_generate(attributes)?;
-                self.identity_public_key =
-                    Some(vault.secret_public_key_get(&static_secret_handle)?);
-                Arc::new(static_secret_handle)
-            }
-            Some(ik) => {
-                self.identity_public_key = Some(vault.secret_public_key_get(&ik)?);
-                ik
-            }
-        };
-        self.identity_key = Some(identity_key);
-
-        attributes.persistence = SecretPersistence::Ephemeral;
-        // 2. Generate an ephemeral key pair for this handshake and set it to e
-        let ephemeral_secret_handle = vault.secret_generate(attributes)?;
-        let ephemeral_public_key = vault.secret_public_key_get(&ephemeral_secret_handle)?;
-        self.ephemeral_key_pair = Some(KeyPair {
-            public_key: ephemeral_public_key,
-            secret_handle: ephemeral_secret_handle,
-        });
-
-        // 3. Set k to empty, Set n to 0
-        // let nonce = 0;
-        self.key = None;
-        self.nonce = 0;
-
-        // 4. Set h and ck to protocol name
-        // 5. h = SHA256(h || prologue),
-        // prologue is empty
-        // mix_hash(xx, NULL, 0);
-        let mut h = [0u8; SHA256_SIZE];
-        h[..self.get_protocol_name().len()].copy_from_slice(self.get_protocol_name());
-        let attributes = SecretAttributes {
-            stype: SecretType::Buffer,
-            persistence: SecretPersistence::Ephemeral,
-            length: SHA256_SIZE,
-        };
-        self.ck = Some(vault.secret_import(&h, attributes)?);
-        self.h = Some(vault.sha256(&h)?);
-
-        Ok(())
-    }
-
-    /// Perform the diffie-hellman computation
-    fn dh(&mut self, secret_handle: &Box<dyn Secret>, public_key: &[u8]) -> OckamResult<()> {
-        let ck = self.ck.take().ok_or_else(|| Error::InvalidState.into())?;
-
-        let mut vault = self.vault.lock().unwrap();
-
-        let attributes_ck = SecretAttributes {
-            stype: SecretType::Buffer,
-            persistence: SecretPersistence::Ephemeral,
-            length: SHA256_SIZE,
-        };
-
-        let symmetric_secret_info = self.get_symmetric_key_type_and_length();
-
-        let attributes_k = SecretAttributes {
-            stype: symmetric_secret_info.0,
-            persistence: SecretPersistence::Ephemeral,
-            length: symmetric_secret_info.1,
-        };
-
-        let ecdh = vault.ec_diffie_hellman(secret_handle, public_key)?;
-
-        let mut hkdf_output =
-            vault.hkdf_sha256(&ck, b"", Some(&ecdh), vec![attributes_ck, attributes_k])?;
-
-        if hkdf_output.len() != 2 {
-            return Err(Error::InternalVaultError.into());
-        }
-
-        let key = self.key.take();
-        if key.is_some() {
-            vault.secret_destroy(key.unwrap())?;
-        }
-
-        self.key = Some(hkdf_output.pop().unwrap());
-
-        vault.secret_destroy(ck)?;
-        self.ck = Some(hkdf_output.pop().unwrap());
-
-        self.nonce = 0;
-
-        Ok(())
-    }
-
-    /// mix hash step in Noise protocol
-    fn mix_hash<B: AsRef<[u8]>>(&mut self, data: B) -> OckamResult<()> {
-        let h = &self.h.ok_or_else(|| Error::InvalidState.into())?;
-
-        let mut input = h.to_vec();
-        input.extend_from_slice(data.as_ref());
-        let vault = self.vault.lock().unwrap();
-        self.h = Some(vault.sha256(&input)?);
-        Ok(())
-    }
-
-    /// Encrypt and mix step in Noise protocol
-    fn encrypt_and_mix_hash<B: AsRef<[u8]>>(&mut self, plaintext: B) -> OckamResult<Vec<u8>> {
-        let h = &self.h.ok_or_else(|| Error::InvalidState.into())?;
-
-        let mut nonce = [0u8; 12];
-        nonce[10..].copy_from_slice(&self.nonce.to_be_bytes());
-        let ciphertext_and_tag = {
-            let mut vault = self.vault.lock().unwrap();
-            let key = self.key.as_ref().ok_or(Error::InvalidState.into())?;
-            vault.aead_aes_gcm_encrypt(key, plaintext.as_ref(), nonce.as_ref(), h)?
-        };
-        self.mix_hash(&ciphertext_and_tag)?;
-        self.nonce += 1;
-        Ok(ciphertext_and_tag)
-    }
-
-    /// Decrypt and mix step in Noise protocol
-    fn decrypt_and_mix_hash<B: AsRef<[u8]>>(&mut self, ciphertext: B) -> OckamResult<Vec<u8>> {
-        let h = &self.h.ok_or_else(|| Error::InvalidState.into())?;
-
-        let mut nonce = [0u8; 12];
-        nonce[10..].copy_from_slice(&self.nonce.to_be_bytes());
-        let ciphertext = ciphertext.as_ref();
-        let plaintext = {
-            let mut vault = self.vault.lock().unwrap();
-            let key = self.key.as_ref().ok_or(Error::InvalidState.into())?;
-            vault.aead_aes_gcm_decrypt(key, ciphertext, nonce.as_ref(), h)?
-        };
-        self.mix_hash(ciphertext)?;
-        self.nonce += 1;
-        Ok(plaintext)
-    }
-
-    /// Split step in Noise protocol
-    fn split(&mut self) -> OckamResult<(Box<dyn Secret>, Box<dyn Secret>)> {
-        let ck = self.ck.as_ref().ok_or_else(|| Error::InvalidState.into())?;
-
-        let mut vault = self.vault.lock().unwrap();
-        let symmetric_key_info = self.get_symmetric_key_type_and_length();
-        let attributes = SecretAttributes {
-            stype: symmetric_key_info.0,
-            persistence: SecretPersistence::Ephemeral,
-            length: symmetric_key_info.1,
-        };
-        let mut hkdf_output = vault.hkdf_sha256(ck, b"", None, vec![attributes, attributes])?;
-
-        if hkdf_output.len() != 2 {
-            return Err(Error::InternalVaultError.into());
-        }
-
-        let res1 = hkdf_output.pop().unwrap();
-        let res0 = hkdf_output.pop().unwrap();
-
-        Ok((res0, res1))
+impl SymmetricState {
+    pub fn new(
+        cipher_suite: CipherSuite,
+        vault: Arc<dyn XXVault>,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+    ) -> Self {
+        Self::with_vault(cipher_suite, vault, identity_key)
     }
+}
 
-    /// Set this state up to send and receive messages
-    fn finalize(
-        self,
-        encrypt_key: Box<dyn Secret>,
-        decrypt_key: Box<dyn Secret>,
-    ) -> OckamResult<CompletedKeyExchange> {
-        let h = self.h.ok_or_else(|| Error::InvalidState.into())?;
-
-        let local_static_secret = self
-            .identity_key
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        let remote_static_public_key = self
-            .remote_static_public_key
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        Ok(CompletedKeyExchange {
-            h,
-            encrypt_key,
-            decrypt_key,
-            local_static_secret,
-            remote_static_public_key,
-        })
+impl SymmetricState<dyn AsyncXXVault> {
+    /// Create the handshake state over an async vault
+    pub fn new_async(
+        cipher_suite: CipherSuite,
+        vault: Arc<dyn AsyncXXVault>,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+    ) -> Self {
+        Self::with_vault(cipher_suite, vault, identity_key)
     }
 }
 
 /// Provides methods for handling the initiator role
-#[derive(Debug)]
-struct Initiator(SymmetricState);
-
-impl Initiator {
-    /// Encode the first message to be sent
-    pub fn encode_message_1<B: AsRef<[u8]>>(&mut self, payload: B) -> OckamResult<Vec<u8>> {
-        let ephemeral_public_key = self
-            .0
-            .ephemeral_key_pair
-            .as_ref()
-            .ok_or_else(|| Error::InvalidState.into())?
-            .public_key
-            .clone();
-
-        let payload = payload.as_ref();
-        self.0.mix_hash(ephemeral_public_key.as_ref())?;
-        self.0.mix_hash(payload)?;
-
-        let mut output = ephemeral_public_key.as_ref().to_vec();
-        output.extend_from_slice(payload);
-        Ok(output)
-    }
-
-    /// Decode the second message in the sequence, sent from the responder
-    pub fn decode_message_2<B: AsRef<[u8]>>(&mut self, message: B) -> OckamResult<Vec<u8>> {
-        let t = &mut self.0;
-        let public_key_size = t.get_public_key_size();
-        let message = message.as_ref();
-        if message.len() < 2 * public_key_size + AES_GCM_TAGSIZE {
-            return Err(Error::MessageLenMismatch.into());
-        }
-
-        let ephemeral_key_pair = t
-            .ephemeral_key_pair
-            .take()
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        let ephemeral_secret_handle = &ephemeral_key_pair.secret_handle;
-
-        let mut index_l = 0;
-        let mut index_r = public_key_size;
-        let re = &message[..index_r];
-        let re = PublicKey::new(re.to_vec());
-        index_l += public_key_size;
-        index_r += public_key_size + AES_GCM_TAGSIZE;
-        let encrypted_rs_and_tag = &message[index_l..index_r];
-        let encrypted_payload_and_tag = &message[index_r..];
-
-        t.mix_hash(re.as_ref())?;
-        t.dh(ephemeral_secret_handle, re.as_ref())?;
-        t.remote_ephemeral_public_key = Some(re);
-        let rs = t.decrypt_and_mix_hash(encrypted_rs_and_tag)?;
-        let rs = PublicKey::new(rs);
-        t.dh(ephemeral_secret_handle, rs.as_ref())?;
-        t.remote_static_public_key = Some(rs);
-
-        t.ephemeral_key_pair = Some(ephemeral_key_pair);
-        let payload = t.decrypt_and_mix_hash(encrypted_payload_and_tag)?;
-        Ok(payload)
-    }
-
-    /// Encode the final message to be sent
-    pub fn encode_message_3<B: AsRef<[u8]>>(&mut self, payload: B) -> OckamResult<Vec<u8>> {
-        let t = &mut self.0;
-        let static_secret = t
-            .identity_key
-            .take()
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        let static_public = t
-            .identity_public_key
-            .clone()
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        let remote_ephemeral_public_key = t
-            .remote_ephemeral_public_key
-            .clone()
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        let mut encrypted_s_and_tag = t.encrypt_and_mix_hash(static_public.as_ref())?;
-        t.dh(&static_secret, remote_ephemeral_public_key.as_ref())?;
-        t.identity_key = Some(static_secret);
-        let mut encrypted_payload_and_tag = t.encrypt_and_mix_hash(payload)?;
-        encrypted_s_and_tag.append(&mut encrypted_payload_and_tag);
-        Ok(encrypted_s_and_tag)
-    }
-
-    /// Setup this initiator to send and receive messages
-    /// after encoding message 3
-    pub fn finalize(mut self) -> OckamResult<CompletedKeyExchange> {
-        let keys = self.0.split()?;
-        self.0.finalize(keys.1, keys.0)
+struct Initiator<V: ?Sized>(SymmetricState<V>);
+
+impl<V: ?Sized> std::fmt::Debug for Initiator<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Initiator").field(&self.0).finish()
     }
 }
 
 /// Provides methods for handling the responder role
-#[derive(Debug)]
-struct Responder(SymmetricState);
-
-impl Responder {
-    /// Decode the first message sent
-    pub fn decode_message_1<B: AsRef<[u8]>>(&mut self, message_1: B) -> OckamResult<Vec<u8>> {
-        let public_key_size = self.0.get_public_key_size();
-        let message_1 = message_1.as_ref();
-        if message_1.len() < public_key_size {
-            return Err(Error::MessageLenMismatch.into());
-        }
-
-        let re = &message_1[..public_key_size];
-        let re = PublicKey::new(re.to_vec());
-        self.0.mix_hash(re.as_ref())?;
-        self.0.mix_hash(&message_1[public_key_size..])?;
-        self.0.remote_ephemeral_public_key = Some(re);
-        Ok(message_1[public_key_size..].to_vec())
-    }
-
-    /// Encode the second message to be sent
-    pub fn encode_message_2<B: AsRef<[u8]>>(&mut self, payload: B) -> OckamResult<Vec<u8>> {
-        let t = &mut self.0;
-        let static_secret = t
-            .identity_key
-            .take()
-            .ok_or_else(|| Error::InvalidState.into())?;
-        let static_public = t
-            .identity_public_key
-            .clone()
-            .ok_or_else(|| Error::InvalidState.into())?;
-        let ephemeral_key_pair = t
-            .ephemeral_key_pair
-            .take()
-            .ok_or_else(|| Error::InvalidState.into())?;
-        let remote_ephemeral_public_key = t
-            .remote_ephemeral_public_key
-            .take()
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        t.mix_hash(ephemeral_key_pair.public_key.as_ref())?;
-        t.dh(
-            &ephemeral_key_pair.secret_handle,
-            remote_ephemeral_public_key.as_ref(),
-        )?;
-
-        let mut encrypted_s_and_tag = t.encrypt_and_mix_hash(static_public.as_ref())?;
-        t.dh(&static_secret, remote_ephemeral_public_key.as_ref())?;
-        t.remote_ephemeral_public_key = Some(remote_ephemeral_public_key);
-        t.identity_key = Some(static_secret);
-        let mut encrypted_payload_and_tag = t.encrypt_and_mix_hash(payload)?;
-
-        let mut output = ephemeral_key_pair.public_key.as_ref().to_vec();
-        t.ephemeral_key_pair = Some(ephemeral_key_pair);
-        output.append(&mut encrypted_s_and_tag);
-        output.append(&mut encrypted_payload_and_tag);
-        Ok(output)
-    }
-
-    /// Decode the final message received for the handshake
-    pub fn decode_message_3<B: AsRef<[u8]>>(&mut self, message_3: B) -> OckamResult<Vec<u8>> {
-        let t = &mut self.0;
-        let public_key_size = t.get_public_key_size();
-        let message_3 = message_3.as_ref();
-        if message_3.len() < public_key_size + AES_GCM_TAGSIZE {
-            return Err(Error::MessageLenMismatch.into());
-        }
-
-        let ephemeral_key_pair = t
-            .ephemeral_key_pair
-            .take()
-            .ok_or_else(|| Error::InvalidState.into())?;
-
-        let rs = t.decrypt_and_mix_hash(&message_3[..public_key_size + AES_GCM_TAGSIZE])?;
-        let rs = PublicKey::new(rs);
-        t.dh(&ephemeral_key_pair.secret_handle, rs.as_ref())?;
-        t.ephemeral_key_pair = Some(ephemeral_key_pair);
-        let payload = t.decrypt_and_mix_hash(&message_3[public_key_size + AES_GCM_TAGSIZE..])?;
-        t.remote_static_public_key = Some(rs);
-        Ok(payload)
-    }
-
-    /// Setup this responder to send and receive messages
-    /// after decoding message 3
-    pub fn finalize(mut self) -> OckamResult<CompletedKeyExchange> {
-        let keys = self.0.split()?;
-        self.0.finalize(keys.0, keys.1)
+struct Responder<V: ?Sized>(SymmetricState<V>);
+
+impl<V: ?Sized> std::fmt::Debug for Responder<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Responder").field(&self.0).finish()
     }
 }
 
@@ -521,15 +234,24 @@ enum ResponderState {
 }
 
 /// Represents an XX initiator
-#[derive(Debug)]
-pub struct XXInitiator {
+pub struct XXInitiator<V: ?Sized = dyn XXVault> {
     state: InitiatorState,
-    initiator: Initiator,
+    initiator: Initiator<V>,
     run_prologue: bool,
 }
 
-impl XXInitiator {
-    pub fn new(symmetric_state: SymmetricState, run_prologue: bool) -> Self {
+impl<V: ?Sized> std::fmt::Debug for XXInitiator<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("XXInitiator")
+            .field("state", &self.state)
+            .field("initiator", &self.initiator)
+            .field("run_prologue", &self.run_prologue)
+            .finish()
+    }
+}
+
+impl<V: ?Sized> XXInitiator<V> {
+    pub fn new(symmetric_state: SymmetricState<V>, run_prologue: bool) -> Self {
         XXInitiator {
             state: InitiatorState::EncodeMessage1,
             initiator: Initiator(symmetric_state),
@@ -539,13 +261,13 @@ impl XXInitiator {
 }
 
 /// Represents an XX NewKeyExchanger
-pub struct XXNewKeyExchanger {
+pub struct XXNewKeyExchanger<V: ?Sized = dyn XXVault> {
     cipher_suite: CipherSuite,
-    vault_initiator: Arc<Mutex<dyn XXVault>>,
-    vault_responder: Arc<Mutex<dyn XXVault>>,
+    vault_initiator: Arc<V>,
+    vault_responder: Arc<V>,
 }
 
-impl std::fmt::Debug for XXNewKeyExchanger {
+impl<V: ?Sized> std::fmt::Debug for XXNewKeyExchanger<V> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.cipher_suite.fmt(f)
     }
@@ -555,8 +277,23 @@ impl XXNewKeyExchanger {
     /// Create a new XXNewKeyExchanger
     pub fn new(
         cipher_suite: CipherSuite,
-        vault_initiator: Arc<Mutex<dyn XXVault>>,
-        vault_responder: Arc<Mutex<dyn XXVault>>,
+        vault_initiator: Arc<dyn XXVault>,
+        vault_responder: Arc<dyn XXVault>,
+    ) -> Self {
+        Self {
+            cipher_suite,
+            vault_initiator,
+            vault_responder,
+        }
+    }
+}
+
+impl XXNewKeyExchanger<dyn AsyncXXVault> {
+    /// Create a new XXNewKeyExchanger for key exchanges over async vaults
+    pub fn new_async(
+        cipher_suite: CipherSuite,
+        vault_initiator: Arc<dyn AsyncXXVault>,
+        vault_responder: Arc<dyn AsyncXXVault>,
     ) -> Self {
         Self {
             cipher_suite,
@@ -596,16 +333,63 @@ impl NewKeyExchanger<XXInitiator, XXResponder> for XXNewKeyExchanger {
     }
 }
 
+impl ockam_kex::AsyncNewKeyExchanger<XXInitiator<dyn AsyncXXVault>, XXResponder<dyn AsyncXXVault>>
+    for XXNewKeyExchanger<dyn AsyncXXVault>
+{
+    /// Create a new initiator using the provided backing vault
+    fn initiator(
+        &self,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+    ) -> XXInitiator<dyn AsyncXXVault> {
+        let ss = SymmetricState::with_vault(
+            self.cipher_suite,
+            self.vault_initiator.clone(),
+            identity_key,
+        );
+        XXInitiator {
+            state: InitiatorState::EncodeMessage1,
+            initiator: Initiator(ss),
+            run_prologue: true,
+        }
+    }
+
+    /// Create a new responder using the provided backing vault
+    fn responder(
+        &self,
+        identity_key: Option<Arc<Box<dyn Secret>>>,
+    ) -> XXResponder<dyn AsyncXXVault> {
+        let ss = SymmetricState::with_vault(
+            self.cipher_suite,
+            self.vault_responder.clone(),
+            identity_key,
+        );
+        XXResponder {
+            state: ResponderState::DecodeMessage1,
+            responder: Responder(ss),
+            run_prologue: true,
+        }
+    }
+}
+
 /// Represents an XX responder
-#[derive(Debug)]
-pub struct XXResponder {
+pub struct XXResponder<V: ?Sized = dyn XXVault> {
     state: ResponderState,
-    responder: Responder,
+    responder: Responder<V>,
     run_prologue: bool,
 }
 
-impl XXResponder {
-    pub fn new(symmetric_state: SymmetricState, run_prologue: bool) -> Self {
+impl<V: ?Sized> std::fmt::Debug for XXResponder<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("XXResponder")
+            .field("state", &self.state)
+            .field("responder", &self.responder)
+            .field("run_prologue", &self.run_prologue)
+            .finish()
+    }
+}
+
+impl<V: ?Sized> XXResponder<V> {
+    pub fn new(symmetric_state: SymmetricState<V>, run_prologue: bool) -> Self {
         XXResponder {
             state: DecodeMessage1,
             responder: Responder(symmetric_state),
@@ -614,29 +398,646 @@ impl XXResponder {
     }
 }
 
-impl KeyExchanger for XXInitiator {
-    fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
-        match self.state {
-            InitiatorState::EncodeMessage1 => {
-                if self.run_prologue {
-                    self.initiator.0.prologue()?;
+/// Expands the handshake steps for one kind of vault. The steps are shared by the
+/// sync key exchange, which calls an `XXVault` directly, and the async one, which
+/// awaits an `AsyncXXVault`.
+macro_rules! xx_handshake {
+    ($vault:ty, [$($async:tt)?], [$($await:tt)*]) => {
+        impl SymmetricState<$vault> {
+            /// Create an ephemeral secret from the random bytes of the vault, so a
+            /// deterministic vault makes the handshake reproducible
+            $($async)? fn ephemeral_secret(
+                &self,
+                attributes: SecretAttributes,
+            ) -> OckamResult<Box<dyn Secret>> {
+                let mut attempt = 1;
+                loop {
+                    let mut bytes = vec![0u8; attributes.length];
+                    self.vault.random_bytes_generate(&mut bytes)$($await)*?;
+                    let key = SecretKey::new(bytes);
+                    // random bytes are not a valid P-256 key with a tiny probability
+                    match self.vault.secret_import(key.as_ref(), attributes)$($await)* {
+                        Err(_) if attempt < EPHEMERAL_KEY_ATTEMPTS => attempt += 1,
+                        result => return result,
+                    }
                 }
-                let msg = self.initiator.encode_message_1(data)?;
-                self.state = InitiatorState::DecodeMessage2;
-                Ok(msg)
             }
-            InitiatorState::DecodeMessage2 => {
-                let msg = self.initiator.decode_message_2(data)?;
-                self.state = InitiatorState::EncodeMessage3;
-                Ok(msg)
+
+            /// Create a new `HandshakeState` starting with the prologue
+            $($async)? fn prologue(&mut self) -> OckamResult<()> {
+                let asymmetric_secret_info = self.get_secret_key_type_and_length();
+
+                let mut attributes = SecretAttributes {
+                    stype: asymmetric_secret_info.0,
+                    persistence: SecretPersistence::Persistent,
+                    length: asymmetric_secret_info.1,
+                    exportable: false,
+                    usage: SecretUsage::ECDH,
+                };
+                // 1. Generate a static key pair for this handshake and set it to `s`
+                let vault = &self.vault;
+                let identity_key = self.identity_key.take();
+                let identity_key = match identity_key {
+                    None => {
+                        let static_secret_handle = vault.secret_generate(attributes)$($await)*?;
+                        self.identity_public_key =
+                            Some(vault.secret_public_key_get(static_secret_handle.as_ref())$($await)*?);
+                        Arc::new(static_secret_handle)
+                    }
+                    Some(ik) => {
+                        self.identity_public_key =
+                            Some(vault.secret_public_key_get(ik.as_ref().as_ref())$($await)*?);
+                        ik
+                    }
+                };
+                self.identity_key = Some(identity_key);
+
+                attributes.persistence = SecretPersistence::Ephemeral;
+                // 2. Generate an ephemeral key pair for this handshake and set it to e
+                let ephemeral_secret_handle = self.ephemeral_secret(attributes)$($await)*?;
+                let ephemeral_public_key = vault
+                    .secret_public_key_get(ephemeral_secret_handle.as_ref())
+                    $($await)*?;
+                self.ephemeral_key_pair = Some(KeyPair {
+                    public_key: ephemeral_public_key,
+                    secret_handle: ephemeral_secret_handle,
+                });
+
+                // 3. Set k to empty, Set n to 0
+                // let nonce = 0;
+                self.key = None;
+                self.nonce = 0;
+
+                // 4. Set h and ck to protocol name
+                // 5. h = SHA256(h || prologue),
+                // prologue is empty
+                // mix_hash(xx, NULL, 0);
+                let mut h = [0u8; SHA256_SIZE];
+                h[..self.protocol_name().len()].copy_from_slice(self.protocol_name());
+                let attributes = SecretAttributes {
+                    stype: SecretType::Buffer,
+                    persistence: SecretPersistence::Ephemeral,
+                    length: SHA256_SIZE,
+                    exportable: true,
+                    usage: SecretUsage::HKDF,
+                };
+                self.ck = Some(vault.secret_import(&h, attributes)$($await)*?);
+                self.h = Some(vault.sha256(&h)$($await)*?);
+
+                Ok(())
             }
-            InitiatorState::EncodeMessage3 => {
-                let msg = self.initiator.encode_message_3(data)?;
-                self.state = InitiatorState::Done;
-                Ok(msg)
+
+            /// Perform the diffie-hellman computation
+            $($async)? fn dh(
+                &mut self,
+                secret_handle: &dyn Secret,
+                public_key: &[u8],
+            ) -> OckamResult<()> {
+                let ck = self.ck.take().ok_or_else(|| Error::InvalidState.into())?;
+
+                let vault = &self.vault;
+
+                let attributes_ck = SecretAttributes {
+                    stype: SecretType::Buffer,
+                    persistence: SecretPersistence::Ephemeral,
+                    length: SHA256_SIZE,
+                    exportable: true,
+                    usage: SecretUsage::HKDF,
+                };
+
+                let symmetric_secret_info = self.get_symmetric_key_type_and_length();
+
+                let attributes_k = SecretAttributes {
+                    stype: symmetric_secret_info.0,
+                    persistence: SecretPersistence::Ephemeral,
+                    length: symmetric_secret_info.1,
+                    exportable: true,
+                    usage: SecretUsage::AEAD,
+                };
+
+                let ecdh = vault.ec_diffie_hellman(secret_handle, public_key)$($await)*?;
+
+                let mut hkdf_output = vault
+                    .hkdf_sha256(ck.as_ref(), b"", Some(ecdh.as_ref()), vec![attributes_ck, attributes_k])
+                    $($await)*?;
+
+                if hkdf_output.len() != 2 {
+                    return Err(Error::InternalVaultError.into());
+                }
+
+                let key = self.key.take();
+                if key.is_some() {
+                    vault.secret_destroy(key.unwrap())$($await)*?;
+                }
+
+                self.key = Some(hkdf_output.pop().unwrap());
+
+                vault.secret_destroy(ck)$($await)*?;
+                self.ck = Some(hkdf_output.pop().unwrap());
+
+                self.nonce = 0;
+
+                Ok(())
+            }
+
+            /// mix hash step in Noise protocol
+            $($async)? fn mix_hash<B: AsRef<[u8]> + Send>(&mut self, data: B) -> OckamResult<()> {
+                let h = &self.h.ok_or_else(|| Error::InvalidState.into())?;
+
+                let mut input = h.to_vec();
+                input.extend_from_slice(data.as_ref());
+                let vault = &self.vault;
+                self.h = Some(vault.sha256(&input)$($await)*?);
+                Ok(())
+            }
+
+            /// Encrypt and mix step in Noise protocol
+            $($async)? fn encrypt_and_mix_hash<B: AsRef<[u8]> + Send>(
+                &mut self,
+                plaintext: B,
+            ) -> OckamResult<Vec<u8>> {
+                let h = &self.h.ok_or_else(|| Error::InvalidState.into())?;
+
+                let nonce = self.get_nonce();
+                let ciphertext_and_tag = {
+                    let vault = &self.vault;
+                    let key = self.key.as_deref().ok_or(Error::InvalidState.into())?;
+                    match self.cipher_suite {
+                        CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => {
+                            vault
+                                .aead_aes_gcm_encrypt(key, plaintext.as_ref(), nonce.as_ref(), h)
+                                $($await)*?
+                        }
+                        CipherSuite::Curve25519ChaChaPolySha256 => {
+                            vault
+                                .aead_chacha20_poly1305_encrypt(
+                                    key,
+                                    plaintext.as_ref(),
+                                    nonce.as_ref(),
+                                    h,
+                                )
+                                $($await)*?
+                        }
+                    }
+                };
+                self.mix_hash(&ciphertext_and_tag)$($await)*?;
+                self.nonce += 1;
+                Ok(ciphertext_and_tag)
+            }
+
+            /// Decrypt and mix step in Noise protocol
+            $($async)? fn decrypt_and_mix_hash<B: AsRef<[u8]> + Send>(
+                &mut self,
+                ciphertext: B,
+            ) -> OckamResult<Vec<u8>> {
+                let h = &self.h.ok_or_else(|| Error::InvalidState.into())?;
+
+                let nonce = self.get_nonce();
+                let ciphertext = ciphertext.as_ref();
+                let plaintext = {
+                    let vault = &self.vault;
+                    let key = self.key.as_deref().ok_or(Error::InvalidState.into())?;
+                    match self.cipher_suite {
+                        CipherSuite::Curve25519AesGcmSha256 | CipherSuite::P256Aes128GcmSha256 => {
+                            vault
+                                .aead_aes_gcm_decrypt(key, ciphertext, nonce.as_ref(), h)
+                                $($await)*?
+                        }
+                        CipherSuite::Curve25519ChaChaPolySha256 => {
+                            vault
+                                .aead_chacha20_poly1305_decrypt(key, ciphertext, nonce.as_ref(), h)
+                                $($await)*?
+                        }
+                    }
+                };
+                self.mix_hash(ciphertext)$($await)*?;
+                self.nonce += 1;
+                Ok(plaintext)
+            }
+
+            /// Split step in Noise protocol
+            $($async)? fn split(&mut self) -> OckamResult<(Box<dyn Secret>, Box<dyn Secret>)> {
+                let ck = self.ck.as_deref().ok_or_else(|| Error::InvalidState.into())?;
+
+                let vault = &self.vault;
+                let symmetric_key_info = self.get_symmetric_key_type_and_length();
+                let attributes = SecretAttributes {
+                    stype: symmetric_key_info.0,
+                    persistence: SecretPersistence::Ephemeral,
+                    length: symmetric_key_info.1,
+                    exportable: true,
+                    // channels rekey by using the key as HKDF salt
+                    usage: SecretUsage::AEAD | SecretUsage::HKDF,
+                };
+                let mut hkdf_output = vault
+                    .hkdf_sha256(ck, b"", None, vec![attributes, attributes])
+                    $($await)*?;
+
+                if hkdf_output.len() != 2 {
+                    return Err(Error::InternalVaultError.into());
+                }
+
+                let res1 = hkdf_output.pop().unwrap();
+                let res0 = hkdf_output.pop().unwrap();
+
+                Ok((res0, res1))
+            }
+
+            /// Set this state up to send and receive messages
+            $($async)? fn finalize(
+                self,
+                encrypt_key: Box<dyn Secret>,
+                decrypt_key: Box<dyn Secret>,
+            ) -> OckamResult<CompletedKeyExchange> {
+                let h = self.h.ok_or_else(|| Error::InvalidState.into())?;
+
+                let local_static_secret = self
+                    .identity_key
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                let remote_static_public_key = self
+                    .remote_static_public_key
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                Ok(CompletedKeyExchange {
+                    h,
+                    encrypt_key,
+                    decrypt_key,
+                    local_static_secret,
+                    remote_static_public_key,
+                })
             }
-            InitiatorState::Done => Ok(vec![]),
         }
+
+        impl Initiator<$vault> {
+            /// Encode the first message to be sent
+            pub $($async)? fn encode_message_1<B: AsRef<[u8]> + Send>(
+                &mut self,
+                payload: B,
+            ) -> OckamResult<Vec<u8>> {
+                let ephemeral_public_key = self
+                    .0
+                    .ephemeral_key_pair
+                    .as_ref()
+                    .ok_or_else(|| Error::InvalidState.into())?
+                    .public_key
+                    .clone();
+
+                let payload = payload.as_ref();
+                self.0.mix_hash(ephemeral_public_key.as_ref())$($await)*?;
+                self.0.mix_hash(payload)$($await)*?;
+
+                let mut output = ephemeral_public_key.as_ref().to_vec();
+                output.extend_from_slice(payload);
+                Ok(output)
+            }
+
+            /// Decode the second message in the sequence, sent from the responder
+            pub $($async)? fn decode_message_2<B: AsRef<[u8]> + Send>(
+                &mut self,
+                message: B,
+            ) -> OckamResult<Vec<u8>> {
+                let t = &mut self.0;
+                let public_key_size = t.get_public_key_size();
+                let message = message.as_ref();
+                if message.len() < 2 * public_key_size + AES_GCM_TAGSIZE {
+                    return Err(Error::MessageLenMismatch.into());
+                }
+
+                let ephemeral_key_pair = t
+                    .ephemeral_key_pair
+                    .take()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                let ephemeral_secret_handle = ephemeral_key_pair.secret_handle.as_ref();
+
+                let mut index_l = 0;
+                let mut index_r = public_key_size;
+                let re = &message[..index_r];
+                let re = PublicKey::new(re.to_vec());
+                index_l += public_key_size;
+                index_r += public_key_size + AES_GCM_TAGSIZE;
+                let encrypted_rs_and_tag = &message[index_l..index_r];
+                let encrypted_payload_and_tag = &message[index_r..];
+
+                t.mix_hash(re.as_ref())$($await)*?;
+                t.dh(ephemeral_secret_handle, re.as_ref())$($await)*?;
+                t.remote_ephemeral_public_key = Some(re);
+                let rs = t.decrypt_and_mix_hash(encrypted_rs_and_tag)$($await)*?;
+                let rs = PublicKey::new(rs);
+                t.dh(ephemeral_secret_handle, rs.as_ref())$($await)*?;
+                t.remote_static_public_key = Some(rs);
+
+                t.ephemeral_key_pair = Some(ephemeral_key_pair);
+                let payload = t.decrypt_and_mix_hash(encrypted_payload_and_tag)$($await)*?;
+                Ok(payload)
+            }
+
+            /// Encode the final message to be sent
+            pub $($async)? fn encode_message_3<B: AsRef<[u8]> + Send>(
+                &mut self,
+                payload: B,
+            ) -> OckamResult<Vec<u8>> {
+                let t = &mut self.0;
+                let static_secret = t
+                    .identity_key
+                    .take()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                let static_public = t
+                    .identity_public_key
+                    .clone()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                let remote_ephemeral_public_key = t
+                    .remote_ephemeral_public_key
+                    .clone()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                let mut encrypted_s_and_tag =
+                    t.encrypt_and_mix_hash(static_public.as_ref())$($await)*?;
+                t.dh(static_secret.as_ref().as_ref(), remote_ephemeral_public_key.as_ref())
+                    $($await)*?;
+                t.identity_key = Some(static_secret);
+                let mut encrypted_payload_and_tag = t.encrypt_and_mix_hash(payload)$($await)*?;
+                encrypted_s_and_tag.append(&mut encrypted_payload_and_tag);
+                Ok(encrypted_s_and_tag)
+            }
+
+            /// Setup this initiator to send and receive messages
+            /// after encoding message 3
+            pub $($async)? fn finalize(mut self) -> OckamResult<CompletedKeyExchange> {
+                let keys = self.0.split()$($await)*?;
+                self.0.finalize(keys.1, keys.0)$($await)*
+            }
+        }
+
+        impl Responder<$vault> {
+            /// Decode the first message sent
+            pub $($async)? fn decode_message_1<B: AsRef<[u8]> + Send>(
+                &mut self,
+                message_1: B,
+            ) -> OckamResult<Vec<u8>> {
+                let public_key_size = self.0.get_public_key_size();
+                let message_1 = message_1.as_ref();
+                if message_1.len() < public_key_size {
+                    return Err(Error::MessageLenMismatch.into());
+                }
+
+                let re = &message_1[..public_key_size];
+                let re = PublicKey::new(re.to_vec());
+                self.0.mix_hash(re.as_ref())$($await)*?;
+                self.0.mix_hash(&message_1[public_key_size..])$($await)*?;
+                self.0.remote_ephemeral_public_key = Some(re);
+                Ok(message_1[public_key_size..].to_vec())
+            }
+
+            /// Encode the second message to be sent
+            pub $($async)? fn encode_message_2<B: AsRef<[u8]> + Send>(
+                &mut self,
+                payload: B,
+            ) -> OckamResult<Vec<u8>> {
+                let t = &mut self.0;
+                let static_secret = t
+                    .identity_key
+                    .take()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+                let static_public = t
+                    .identity_public_key
+                    .clone()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+                let ephemeral_key_pair = t
+                    .ephemeral_key_pair
+                    .take()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+                let remote_ephemeral_public_key = t
+                    .remote_ephemeral_public_key
+                    .take()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                t.mix_hash(ephemeral_key_pair.public_key.as_ref())$($await)*?;
+                t.dh(
+                    ephemeral_key_pair.secret_handle.as_ref(),
+                    remote_ephemeral_public_key.as_ref(),
+                )
+                $($await)*?;
+
+                let mut encrypted_s_and_tag =
+                    t.encrypt_and_mix_hash(static_public.as_ref())$($await)*?;
+                t.dh(static_secret.as_ref().as_ref(), remote_ephemeral_public_key.as_ref())
+                    $($await)*?;
+                t.remote_ephemeral_public_key = Some(remote_ephemeral_public_key);
+                t.identity_key = Some(static_secret);
+                let mut encrypted_payload_and_tag = t.encrypt_and_mix_hash(payload)$($await)*?;
+
+                let mut output = ephemeral_key_pair.public_key.as_ref().to_vec();
+                t.ephemeral_key_pair = Some(ephemeral_key_pair);
+                output.append(&mut encrypted_s_and_tag);
+                output.append(&mut encrypted_payload_and_tag);
+                Ok(output)
+            }
+
+            /// Decode the final message received for the handshake
+            pub $($async)? fn decode_message_3<B: AsRef<[u8]> + Send>(
+                &mut self,
+                message_3: B,
+            ) -> OckamResult<Vec<u8>> {
+                let t = &mut self.0;
+                let public_key_size = t.get_public_key_size();
+                let message_3 = message_3.as_ref();
+                if message_3.len() < public_key_size + AES_GCM_TAGSIZE {
+                    return Err(Error::MessageLenMismatch.into());
+                }
+
+                let ephemeral_key_pair = t
+                    .ephemeral_key_pair
+                    .take()
+                    .ok_or_else(|| Error::InvalidState.into())?;
+
+                let rs = t
+                    .decrypt_and_mix_hash(&message_3[..public_key_size + AES_GCM_TAGSIZE])
+                    $($await)*?;
+                let rs = PublicKey::new(rs);
+                t.dh(ephemeral_key_pair.secret_handle.as_ref(), rs.as_ref())$($await)*?;
+                t.ephemeral_key_pair = Some(ephemeral_key_pair);
+                let payload = t
+                    .decrypt_and_mix_hash(&message_3[public_key_size + AES_GCM_TAGSIZE..])
+                    $($await)*?;
+                t.remote_static_public_key = Some(rs);
+                Ok(payload)
+            }
+
+            /// Setup this responder to send and receive messages
+            /// after decoding message 3
+            pub $($async)? fn finalize(mut self) -> OckamResult<CompletedKeyExchange> {
+                let keys = self.0.split()$($await)*?;
+                self.0.finalize(keys.0, keys.1)$($await)*
+            }
+        }
+
+        impl XXInitiator<$vault> {
+            /// Run the next step of the handshake
+            $($async)? fn step(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+                match self.state {
+                    InitiatorState::EncodeMessage1 => {
+                        if self.run_prologue {
+                            self.initiator.0.prologue()$($await)*?;
+                        }
+                        let msg = self.initiator.encode_message_1(data)$($await)*?;
+                        self.state = InitiatorState::DecodeMessage2;
+                        Ok(msg)
+                    }
+                    InitiatorState::DecodeMessage2 => {
+                        let msg = self.initiator.decode_message_2(data)$($await)*?;
+                        self.state = InitiatorState::EncodeMessage3;
+                        Ok(msg)
+                    }
+                    InitiatorState::EncodeMessage3 => {
+                        let msg = self.initiator.encode_message_3(data)$($await)*?;
+                        self.state = InitiatorState::Done;
+                        Ok(msg)
+                    }
+                    InitiatorState::Done => Ok(vec![]),
+                }
+            }
+
+            /// Return the keys of a finished handshake
+            $($async)? fn complete(self) -> OckamResult<CompletedKeyExchange> {
+                match self.state {
+                    InitiatorState::Done => self.initiator.finalize()$($await)*,
+                    _ => Err(Error::InvalidState.into()),
+                }
+            }
+        }
+
+
+        impl XXResponder<$vault> {
+            /// Run the next step of the handshake
+            $($async)? fn step(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+                match self.state {
+                    ResponderState::DecodeMessage1 => {
+                        if self.run_prologue {
+                            self.responder.0.prologue()$($await)*?;
+                        }
+                        let msg = self.responder.decode_message_1(data)$($await)*?;
+                        self.state = ResponderState::EncodeMessage2;
+                        Ok(msg)
+                    }
+                    ResponderState::EncodeMessage2 => {
+                        let msg = self.responder.encode_message_2(data)$($await)*?;
+                        self.state = ResponderState::DecodeMessage3;
+                        Ok(msg)
+                    }
+                    ResponderState::DecodeMessage3 => {
+                        let msg = self.responder.decode_message_3(data)$($await)*?;
+                        self.state = ResponderState::Done;
+                        Ok(msg)
+                    }
+                    ResponderState::Done => Ok(vec![]),
+                }
+            }
+
+            /// Return the keys of a finished handshake
+            $($async)? fn complete(self) -> OckamResult<CompletedKeyExchange> {
+                match self.state {
+                    ResponderState::Done => self.responder.finalize()$($await)*,
+                    _ => Err(Error::InvalidState.into()),
+                }
+            }
+        }
+
+    };
+}
+
+xx_handshake!(dyn XXVault, [], []);
+xx_handshake!(dyn AsyncXXVault, [async], [.await]);
+
+impl KeyExchange for SymmetricState {
+    fn get_protocol_name(&self) -> &'static [u8] {
+        self.protocol_name()
+    }
+
+    fn prologue(&mut self) -> OckamResult<()> {
+        Self::prologue(self)
+    }
+
+    fn dh(&mut self, secret_handle: &dyn Secret, public_key: &[u8]) -> OckamResult<()> {
+        Self::dh(self, secret_handle, public_key)
+    }
+
+    fn mix_hash<B: AsRef<[u8]>>(&mut self, data: B) -> OckamResult<()> {
+        Self::mix_hash(self, data.as_ref())
+    }
+
+    fn encrypt_and_mix_hash<B: AsRef<[u8]>>(&mut self, plaintext: B) -> OckamResult<Vec<u8>> {
+        Self::encrypt_and_mix_hash(self, plaintext.as_ref())
+    }
+
+    fn decrypt_and_mix_hash<B: AsRef<[u8]>>(&mut self, ciphertext: B) -> OckamResult<Vec<u8>> {
+        Self::decrypt_and_mix_hash(self, ciphertext.as_ref())
+    }
+
+    fn split(&mut self) -> OckamResult<(Box<dyn Secret>, Box<dyn Secret>)> {
+        Self::split(self)
+    }
+
+    fn finalize(
+        self,
+        encrypt_key: Box<dyn Secret>,
+        decrypt_key: Box<dyn Secret>,
+    ) -> OckamResult<CompletedKeyExchange> {
+        Self::finalize(self, encrypt_key, decrypt_key)
+    }
+}
+
+#[async_trait]
+impl ockam_kex::AsyncKeyExchange for SymmetricState<dyn AsyncXXVault> {
+    fn get_protocol_name(&self) -> &'static [u8] {
+        self.protocol_name()
+    }
+
+    async fn prologue(&mut self) -> OckamResult<()> {
+        Self::prologue(self).await
+    }
+
+    async fn dh(&mut self, secret_handle: &dyn Secret, public_key: &[u8]) -> OckamResult<()> {
+        Self::dh(self, secret_handle, public_key).await
+    }
+
+    async fn mix_hash<B: AsRef<[u8]> + Send>(&mut self, data: B) -> OckamResult<()> {
+        Self::mix_hash(self, data).await
+    }
+
+    async fn encrypt_and_mix_hash<B: AsRef<[u8]> + Send>(
+        &mut self,
+        plaintext: B,
+    ) -> OckamResult<Vec<u8>> {
+        Self::encrypt_and_mix_hash(self, plaintext).await
+    }
+
+    async fn decrypt_and_mix_hash<B: AsRef<[u8]> + Send>(
+        &mut self,
+        ciphertext: B,
+    ) -> OckamResult<Vec<u8>> {
+        Self::decrypt_and_mix_hash(self, ciphertext).await
+    }
+
+    async fn split(&mut self) -> OckamResult<(Box<dyn Secret>, Box<dyn Secret>)> {
+        Self::split(self).await
+    }
+
+    async fn finalize(
+        self,
+        encrypt_key: Box<dyn Secret>,
+        decrypt_key: Box<dyn Secret>,
+    ) -> OckamResult<CompletedKeyExchange> {
+        Self::finalize(self, encrypt_key, decrypt_key).await
+    }
+}
+
+impl KeyExchanger for XXInitiator {
+    fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+        self.step(data)
     }
 
     fn is_complete(&self) -> bool {
@@ -644,36 +1045,28 @@ impl KeyExchanger for XXInitiator {
     }
 
     fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange> {
-        match self.state {
-            InitiatorState::Done => self.initiator.finalize(),
-            _ => Err(Error::InvalidState.into()),
-        }
+        self.complete()
+    }
+}
+
+#[async_trait]
+impl ockam_kex::AsyncKeyExchanger for XXInitiator<dyn AsyncXXVault> {
+    async fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+        self.step(data).await
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.state, InitiatorState::Done)
+    }
+
+    async fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange> {
+        self.complete().await
     }
 }
 
 impl KeyExchanger for XXResponder {
     fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
-        match self.state {
-            ResponderState::DecodeMessage1 => {
-                if self.run_prologue {
-                    self.responder.0.prologue()?;
-                }
-                let msg = self.responder.decode_message_1(data)?;
-                self.state = ResponderState::EncodeMessage2;
-                Ok(msg)
-            }
-            ResponderState::EncodeMessage2 => {
-                let msg = self.responder.encode_message_2(data)?;
-                self.state = ResponderState::DecodeMessage3;
-                Ok(msg)
-            }
-            ResponderState::DecodeMessage3 => {
-                let msg = self.responder.decode_message_3(data)?;
-                self.state = ResponderState::Done;
-                Ok(msg)
-            }
-            ResponderState::Done => Ok(vec![]),
-        }
+        self.step(data)
     }
 
     fn is_complete(&self) -> bool {
@@ -681,23 +1074,213 @@ impl KeyExchanger for XXResponder {
     }
 
     fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange> {
-        match self.state {
-            ResponderState::Done => self.responder.finalize(),
-            _ => Err(Error::InvalidState.into()),
-        }
+        self.complete()
+    }
+}
+
+#[async_trait]
+impl ockam_kex::AsyncKeyExchanger for XXResponder<dyn AsyncXXVault> {
+    async fn process(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+        self.step(data).await
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.state, ResponderState::Done)
+    }
+
+    async fn finalize(self: Box<Self>) -> OckamResult<CompletedKeyExchange> {
+        self.complete().await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::executor::block_on;
+    use ockam_vault::types::SecretKey;
     use ockam_vault_software::DefaultVault;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+
+    /// Async vault that isn't ready the first time any call is polled, like a vault
+    /// on the other end of a route
+    #[derive(Default)]
+    struct PendingVault {
+        vault: DefaultVault,
+        pending: AtomicUsize,
+    }
+
+    impl PendingVault {
+        fn pending(&self) -> YieldOnce<'_> {
+            YieldOnce(&self.pending, false)
+        }
+    }
+
+    /// Returns `Pending` once, counting it, then `Ready`
+    struct YieldOnce<'a>(&'a AtomicUsize, bool);
+
+    impl Future for YieldOnce<'_> {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.1 {
+                return Poll::Ready(());
+            }
+            self.1 = true;
+            self.0.fetch_add(1, Ordering::SeqCst);
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[async_trait]
+    impl async_vault::AsyncSecretVault for PendingVault {
+        async fn secret_generate(
+            &self,
+            attributes: SecretAttributes,
+        ) -> OckamResult<Box<dyn Secret>> {
+            self.pending().await;
+            SecretVault::secret_generate(&self.vault, attributes)
+        }
+
+        async fn secret_import(
+            &self,
+            secret: &[u8],
+            attributes: SecretAttributes,
+        ) -> OckamResult<Box<dyn Secret>> {
+            self.pending().await;
+            SecretVault::secret_import(&self.vault, secret, attributes)
+        }
+
+        async fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+            self.pending().await;
+            SecretVault::secret_export(&self.vault, context)
+        }
+
+        async fn secret_attributes_get(
+            &self,
+            context: &dyn Secret,
+        ) -> OckamResult<SecretAttributes> {
+            self.pending().await;
+            SecretVault::secret_attributes_get(&self.vault, context)
+        }
+
+        async fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
+            self.pending().await;
+            SecretVault::secret_public_key_get(&self.vault, context)
+        }
+
+        async fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+            self.pending().await;
+            SecretVault::secret_destroy(&self.vault, context)
+        }
+    }
+
+    #[async_trait]
+    impl async_vault::AsyncHashVault for PendingVault {
+        async fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]> {
+            self.pending().await;
+            HashVault::sha256(&self.vault, data)
+        }
+
+        async fn hkdf_sha256(
+            &self,
+            salt: &dyn Secret,
+            info: &[u8],
+            ikm: Option<&dyn Secret>,
+            output_attributes: Vec<SecretAttributes>,
+        ) -> OckamResult<Vec<Box<dyn Secret>>> {
+            self.pending().await;
+            HashVault::hkdf_sha256(&self.vault, salt, info, ikm, output_attributes)
+        }
+    }
+
+    #[async_trait]
+    impl async_vault::AsyncRandomVault for PendingVault {
+        async fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+            self.pending().await;
+            RandomVault::random_bytes_generate(&self.vault, buffer)
+        }
+    }
+
+    #[async_trait]
+    impl async_vault::AsyncAsymmetricVault for PendingVault {
+        async fn ec_diffie_hellman(
+            &self,
+            context: &dyn Secret,
+            peer_public_key: &[u8],
+        ) -> OckamResult<Box<dyn Secret>> {
+            self.pending().await;
+            AsymmetricVault::ec_diffie_hellman(&self.vault, context, peer_public_key)
+        }
+    }
+
+    #[async_trait]
+    impl async_vault::AsyncSymmetricVault for PendingVault {
+        async fn aead_aes_gcm_encrypt(
+            &self,
+            context: &dyn Secret,
+            plaintext: &[u8],
+            nonce: &[u8],
+            aad: &[u8],
+        ) -> OckamResult<Vec<u8>> {
+            self.pending().await;
+            SymmetricVault::aead_aes_gcm_encrypt(&self.vault, context, plaintext, nonce, aad)
+        }
+
+        async fn aead_aes_gcm_decrypt(
+            &self,
+            context: &dyn Secret,
+            cipher_text: &[u8],
+            nonce: &[u8],
+            aad: &[u8],
+        ) -> OckamResult<Vec<u8>> {
+            self.pending().await;
+            SymmetricVault::aead_aes_gcm_decrypt(&self.vault, context, cipher_text, nonce, aad)
+        }
+
+        async fn aead_chacha20_poly1305_encrypt(
+            &self,
+            context: &dyn Secret,
+            plaintext: &[u8],
+            nonce: &[u8],
+            aad: &[u8],
+        ) -> OckamResult<Vec<u8>> {
+            self.pending().await;
+            SymmetricVault::aead_chacha20_poly1305_encrypt(
+                &self.vault,
+                context,
+                plaintext,
+                nonce,
+                aad,
+            )
+        }
+
+        async fn aead_chacha20_poly1305_decrypt(
+            &self,
+            context: &dyn Secret,
+            cipher_text: &[u8],
+            nonce: &[u8],
+            aad: &[u8],
+        ) -> OckamResult<Vec<u8>> {
+            self.pending().await;
+            SymmetricVault::aead_chacha20_poly1305_decrypt(
+                &self.vault,
+                context,
+                cipher_text,
+                nonce,
+                aad,
+            )
+        }
+    }
 
     #[allow(non_snake_case)]
     #[test]
     fn full_flow__correct_credentials__keys_should_match() {
-        let vault_initiator = Arc::new(Mutex::new(DefaultVault::default()));
-        let vault_responder = Arc::new(Mutex::new(DefaultVault::default()));
+        let vault_initiator = Arc::new(DefaultVault::default());
+        let vault_responder = Arc::new(DefaultVault::default());
         let key_exchanger = XXNewKeyExchanger::new(
             CipherSuite::P256Aes128GcmSha256,
             vault_initiator.clone(),
@@ -719,19 +1302,108 @@ mod tests {
         let responder = Box::new(responder);
         let responder = responder.finalize().unwrap();
 
-        let mut vault_in = vault_initiator.lock().unwrap();
-        let mut vault_re = vault_responder.lock().unwrap();
+        assert_eq!(initiator.h, responder.h);
+
+        let s1 = vault_initiator
+            .secret_export(initiator.encrypt_key.as_ref())
+            .unwrap();
+        let s2 = vault_responder
+            .secret_export(responder.decrypt_key.as_ref())
+            .unwrap();
+
+        assert_eq!(s1, s2);
+
+        let s1 = vault_initiator
+            .secret_export(initiator.decrypt_key.as_ref())
+            .unwrap();
+        let s2 = vault_responder
+            .secret_export(responder.encrypt_key.as_ref())
+            .unwrap();
+
+        assert_eq!(s1, s2);
+    }
+
+    #[test]
+    fn full_flow_async_vault() {
+        use ockam_kex::{AsyncKeyExchanger, AsyncNewKeyExchanger};
+
+        let vault_initiator = Arc::new(DefaultVault::default());
+        let vault_responder = Arc::new(DefaultVault::default());
+        let key_exchanger = XXNewKeyExchanger::new_async(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault_initiator.clone(),
+            vault_responder.clone(),
+        );
+
+        let (initiator, responder) = block_on(async {
+            let mut initiator = AsyncNewKeyExchanger::initiator(&key_exchanger, None);
+            let mut responder = AsyncNewKeyExchanger::responder(&key_exchanger, None);
+
+            let m1 = initiator.process(&[]).await.unwrap();
+            let _ = responder.process(&m1).await.unwrap();
+            let m2 = responder.process(&[]).await.unwrap();
+            let _ = initiator.process(&m2).await.unwrap();
+            let m3 = initiator.process(&[]).await.unwrap();
+            let _ = responder.process(&m3).await.unwrap();
+            assert!(initiator.is_complete() && responder.is_complete());
+
+            (
+                Box::new(initiator).finalize().await.unwrap(),
+                Box::new(responder).finalize().await.unwrap(),
+            )
+        });
 
         assert_eq!(initiator.h, responder.h);
 
-        let s1 = vault_in.secret_export(&initiator.encrypt_key).unwrap();
-        let s2 = vault_re.secret_export(&responder.decrypt_key).unwrap();
+        let s1 = vault_initiator
+            .secret_export(initiator.encrypt_key.as_ref())
+            .unwrap();
+        let s2 = vault_responder
+            .secret_export(responder.decrypt_key.as_ref())
+            .unwrap();
 
         assert_eq!(s1, s2);
+    }
 
-        let s1 = vault_in.secret_export(&initiator.decrypt_key).unwrap();
-        let s2 = vault_re.secret_export(&responder.encrypt_key).unwrap();
+    #[test]
+    fn full_flow_pending_vault() {
+        use ockam_kex::{AsyncKeyExchanger, AsyncNewKeyExchanger};
 
+        let vault_initiator = Arc::new(PendingVault::default());
+        let vault_responder = Arc::new(DefaultVault::default());
+        let key_exchanger = XXNewKeyExchanger::new_async(
+            CipherSuite::Curve25519ChaChaPolySha256,
+            vault_initiator.clone(),
+            vault_responder.clone(),
+        );
+
+        let (initiator, responder) = block_on(async {
+            let mut initiator = AsyncNewKeyExchanger::initiator(&key_exchanger, None);
+            let mut responder = AsyncNewKeyExchanger::responder(&key_exchanger, None);
+
+            let m1 = initiator.process(&[]).await.unwrap();
+            let _ = responder.process(&m1).await.unwrap();
+            let m2 = responder.process(&[]).await.unwrap();
+            let _ = initiator.process(&m2).await.unwrap();
+            let m3 = initiator.process(&[]).await.unwrap();
+            let _ = responder.process(&m3).await.unwrap();
+
+            (
+                Box::new(initiator).finalize().await.unwrap(),
+                Box::new(responder).finalize().await.unwrap(),
+            )
+        });
+
+        // every vault call of the initiator had to be polled again
+        assert!(vault_initiator.pending.load(Ordering::SeqCst) > 0);
+        assert_eq!(initiator.h, responder.h);
+        let s1 = vault_initiator
+            .vault
+            .secret_export(initiator.encrypt_key.as_ref())
+            .unwrap();
+        let s2 = vault_responder
+            .secret_export(responder.decrypt_key.as_ref())
+            .unwrap();
         assert_eq!(s1, s2);
     }
 
@@ -741,15 +1413,13 @@ mod tests {
             93, 247, 43, 103, 185, 101, 173, 209, 22, 143, 10, 108, 117, 109, 242, 28, 32, 79, 126,
             100, 252, 104, 43, 230, 163, 171, 75, 104, 44, 141, 182, 75,
         ];
-        let vault = Arc::new(Mutex::new(DefaultVault::default()));
+        let vault = Arc::new(DefaultVault::default());
         let mut state =
             SymmetricState::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), None);
-        let res = state.prologue();
+        let res = KeyExchange::prologue(&mut state);
         assert!(res.is_ok());
         assert_eq!(state.h.unwrap(), exp_h);
-
-        let mut vault = vault.lock().unwrap();
-        let ck = vault.secret_export(&state.ck.unwrap()).unwrap();
+        let ck = vault.secret_export(state.ck.unwrap().as_ref()).unwrap();
 
         assert_eq!(ck.as_ref(), *b"Noise_XX_25519_AESGCM_SHA256\0\0\0\0");
         assert_eq!(state.nonce, 0);
@@ -772,6 +1442,7 @@ mod tests {
         const MSG_3_PAYLOAD: &str = "";
 
         mock_handshake(
+            CipherSuite::Curve25519AesGcmSha256,
             INIT_STATIC,
             INIT_EPH,
             RESP_STATIC,
@@ -802,6 +1473,69 @@ mod tests {
         const MSG_3_CIPHERTEXT: &str = "e610eadc4b00c17708bf223f29a66f02342fbedf6c0044736544b9271821ae40232c55cd96d1350af861f6a04978f7d5e070c07602c6b84d25a331242a71c50ae31dd4c164267fd48bd2";
 
         mock_handshake(
+            CipherSuite::Curve25519AesGcmSha256,
+            INIT_STATIC,
+            INIT_EPH,
+            RESP_STATIC,
+            RESP_EPH,
+            MSG_1_PAYLOAD,
+            MSG_1_CIPHERTEXT,
+            MSG_2_PAYLOAD,
+            MSG_2_CIPHERTEXT,
+            MSG_3_PAYLOAD,
+            MSG_3_CIPHERTEXT,
+        );
+    }
+
+    #[test]
+    fn handshake_chachapoly_1() {
+        const INIT_STATIC: &str =
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        const INIT_EPH: &str = "202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f";
+        const RESP_STATIC: &str =
+            "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+        const RESP_EPH: &str = "4142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f60";
+        const MSG_1_CIPHERTEXT: &str =
+            "358072d6365880d1aeea329adf9121383851ed21a28e3b75e965d0d2cd166254";
+        const MSG_1_PAYLOAD: &str = "";
+        const MSG_2_CIPHERTEXT: &str = "64b101b1d0be5a8704bd078f9895001fc03e8e9f9522f188dd128d9846d484663414af878d3e46a2f58911a816d6e8346d4ea17a6f2a0bb4ef4ed56c133cff4560a34e36ea82109f26cf2e5a5caf992b608d55c747f615e5a3425a7a19eefb8f";
+        const MSG_2_PAYLOAD: &str = "";
+        const MSG_3_CIPHERTEXT: &str = "87f864c11ba449f46a0a4f4e2eacbb7b0457784f4fca1937f572c93603e9c4d97e5ea11b16f3968710b23a3be3202dc1b5e1ce3c963347491e74f5c0768a9b42";
+        const MSG_3_PAYLOAD: &str = "";
+
+        mock_handshake(
+            CipherSuite::Curve25519ChaChaPolySha256,
+            INIT_STATIC,
+            INIT_EPH,
+            RESP_STATIC,
+            RESP_EPH,
+            MSG_1_PAYLOAD,
+            MSG_1_CIPHERTEXT,
+            MSG_2_PAYLOAD,
+            MSG_2_CIPHERTEXT,
+            MSG_3_PAYLOAD,
+            MSG_3_CIPHERTEXT,
+        );
+    }
+
+    #[test]
+    fn handshake_chachapoly_2() {
+        const INIT_STATIC: &str =
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        const RESP_STATIC: &str =
+            "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+        const INIT_EPH: &str = "202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f";
+        const RESP_EPH: &str = "4142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f60";
+        const MSG_1_PAYLOAD: &str = "746573745f6d73675f30";
+        const MSG_1_CIPHERTEXT: &str =
+            "358072d6365880d1aeea329adf9121383851ed21a28e3b75e965d0d2cd166254746573745f6d73675f30";
+        const MSG_2_PAYLOAD: &str = "746573745f6d73675f31";
+        const MSG_2_CIPHERTEXT: &str = "64b101b1d0be5a8704bd078f9895001fc03e8e9f9522f188dd128d9846d484663414af878d3e46a2f58911a816d6e8346d4ea17a6f2a0bb4ef4ed56c133cff4572e7a2ba5123ac30618b3d205f5c2d17f50cbca216483ac56bcc78e33bf520303278db641e5e731b2e3a";
+        const MSG_3_PAYLOAD: &str = "746573745f6d73675f32";
+        const MSG_3_CIPHERTEXT: &str = "87f864c11ba449f46a0a4f4e2eacbb7b0457784f4fca1937f572c93603e9c4d9f27e318e43ba630594c4d08eeb3b36d97c7377a2f4f9144b2f0c8095ad92140505b2ab53eff244b14138";
+
+        mock_handshake(
+            CipherSuite::Curve25519ChaChaPolySha256,
             INIT_STATIC,
             INIT_EPH,
             RESP_STATIC,
@@ -831,11 +1565,21 @@ mod tests {
         const MSG_3_CIPHERTEXT: &str = "e610eadc4b00c17708bf223f29a66f02342fbedf6c0044736544b9271821ae40e70144cecd9d265dffdc5bb8e051c3f83db32a425e04d8f510c58a43325fbc56";
         const MSG_3_PAYLOAD: &str = "";
 
-        let vault_init = Arc::new(Mutex::new(DefaultVault::default()));
-        let vault_resp = Arc::new(Mutex::new(DefaultVault::default()));
+        let vault_init = Arc::new(DefaultVault::default());
+        let vault_resp = Arc::new(DefaultVault::default());
 
-        let ss_init = mock_prologue(vault_init.clone(), INIT_STATIC, INIT_EPH);
-        let ss_resp = mock_prologue(vault_resp.clone(), RESP_STATIC, RESP_EPH);
+        let ss_init = mock_prologue(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault_init.clone(),
+            INIT_STATIC,
+            INIT_EPH,
+        );
+        let ss_resp = mock_prologue(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault_resp.clone(),
+            RESP_STATIC,
+            RESP_EPH,
+        );
         let mut initiator = XXInitiator {
             state: InitiatorState::EncodeMessage1,
             initiator: Initiator(ss_init),
@@ -882,31 +1626,47 @@ mod tests {
         assert!(res.is_ok());
         let bob = res.unwrap();
         assert_eq!(alice.h, bob.h);
-        let mut vault_in = vault_init.lock().unwrap();
-        let res =
-            vault_in.aead_aes_gcm_encrypt(&alice.encrypt_key, b"hello bob", &[0u8; 12], &alice.h);
+        let res = vault_init.aead_aes_gcm_encrypt(
+            alice.encrypt_key.as_ref(),
+            b"hello bob",
+            &[0u8; 12],
+            &alice.h,
+        );
 
         assert!(res.is_ok());
         let ciphertext = res.unwrap();
-        let mut vault_re = vault_resp.lock().unwrap();
 
-        let res = vault_re.aead_aes_gcm_decrypt(&bob.decrypt_key, &ciphertext, &[0u8; 12], &bob.h);
+        let res = vault_resp.aead_aes_gcm_decrypt(
+            bob.decrypt_key.as_ref(),
+            &ciphertext,
+            &[0u8; 12],
+            &bob.h,
+        );
         assert!(res.is_ok());
         let plaintext = res.unwrap();
         assert_eq!(plaintext, b"hello bob");
 
-        let res =
-            vault_re.aead_aes_gcm_encrypt(&bob.encrypt_key, b"hello alice", &[1u8; 12], &bob.h);
+        let res = vault_resp.aead_aes_gcm_encrypt(
+            bob.encrypt_key.as_ref(),
+            b"hello alice",
+            &[1u8; 12],
+            &bob.h,
+        );
         assert!(res.is_ok());
         let ciphertext = res.unwrap();
-        let res =
-            vault_in.aead_aes_gcm_decrypt(&alice.decrypt_key, &ciphertext, &[1u8; 12], &alice.h);
+        let res = vault_init.aead_aes_gcm_decrypt(
+            alice.decrypt_key.as_ref(),
+            &ciphertext,
+            &[1u8; 12],
+            &alice.h,
+        );
         assert!(res.is_ok());
         let plaintext = res.unwrap();
         assert_eq!(plaintext, b"hello alice");
     }
 
     fn mock_handshake(
+        cipher_suite: CipherSuite,
         init_static: &str,
         init_eph: &str,
         resp_static: &str,
@@ -918,11 +1678,11 @@ mod tests {
         msg_3_payload: &str,
         msg_3_ciphertext: &str,
     ) {
-        let vault_init = Arc::new(Mutex::new(DefaultVault::default()));
-        let vault_resp = Arc::new(Mutex::new(DefaultVault::default()));
+        let vault_init = Arc::new(DefaultVault::default());
+        let vault_resp = Arc::new(DefaultVault::default());
 
-        let ss_init = mock_prologue(vault_init.clone(), init_static, init_eph);
-        let ss_resp = mock_prologue(vault_resp.clone(), resp_static, resp_eph);
+        let ss_init = mock_prologue(cipher_suite, vault_init.clone(), init_static, init_eph);
+        let ss_resp = mock_prologue(cipher_suite, vault_resp.clone(), resp_static, resp_eph);
         let mut initiator = Initiator(ss_init);
         let mut responder = Responder(ss_resp);
 
@@ -956,7 +1716,8 @@ mod tests {
     }
 
     fn mock_prologue(
-        vault_mutex: Arc<Mutex<dyn XXVault>>,
+        cipher_suite: CipherSuite,
+        vault: Arc<dyn XXVault>,
         static_private: &str,
         ephemeral_private: &str,
     ) -> SymmetricState {
@@ -964,20 +1725,23 @@ mod tests {
             stype: SecretType::Curve25519,
             persistence: SecretPersistence::Ephemeral,
             length: CURVE25519_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         // Static x25519 for this handshake, `s`
-        let mut vault = vault_mutex.lock().unwrap();
         let static_secret_handle = vault
             .secret_import(&hex::decode(static_private).unwrap(), attributes)
             .unwrap();
-        let static_public_key = vault.secret_public_key_get(&static_secret_handle).unwrap();
+        let static_public_key = vault
+            .secret_public_key_get(static_secret_handle.as_ref())
+            .unwrap();
 
         // Ephemeral x25519 for this handshake, `e`
         let ephemeral_secret_handle = vault
             .secret_import(&hex::decode(ephemeral_private).unwrap(), attributes)
             .unwrap();
         let ephemeral_public_key = vault
-            .secret_public_key_get(&ephemeral_secret_handle)
+            .secret_public_key_get(ephemeral_secret_handle.as_ref())
             .unwrap();
 
         // 3. Set k to empty, Set n to 0
@@ -987,19 +1751,20 @@ mod tests {
         // 5. h = SHA256(h || prologue),
         // prologue is empty
         // mix_hash(xx, NULL, 0);
-        let h = vault
-            .sha256(b"Noise_XX_25519_AESGCM_SHA256\0\0\0\0")
-            .unwrap();
-        let ck = *b"Noise_XX_25519_AESGCM_SHA256\0\0\0\0";
+        let protocol_name = SymmetricState::new(cipher_suite, vault.clone(), None).protocol_name();
+        let h = vault.sha256(protocol_name).unwrap();
+        let ck = protocol_name;
 
         let attributes = SecretAttributes {
             stype: SecretType::Buffer,
             persistence: SecretPersistence::Ephemeral,
             length: ck.len(),
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         let ck = vault.secret_import(&ck[..], attributes).unwrap();
         SymmetricState {
-            cipher_suite: CipherSuite::Curve25519AesGcmSha256,
+            cipher_suite,
             identity_public_key: Some(static_public_key),
             ephemeral_key_pair: Some(KeyPair {
                 public_key: ephemeral_public_key,
@@ -1012,7 +1777,7 @@ mod tests {
             nonce,
             h: Some(h),
             ck: Some(ck),
-            vault: vault_mutex.clone(),
+            vault,
         }
     }
 }
@@ -2,6 +2,7 @@
 pub mod message;
 pub mod profile;
 pub mod secure_channel;
+pub mod service;
 pub mod system;
 
 pub use ockam_common as common;
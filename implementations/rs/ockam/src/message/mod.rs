@@ -51,6 +51,8 @@ pub enum MessageType {
     KeyAgreementM1 = 3,
     KeyAgreementM2 = 4,
     KeyAgreementM3 = 5,
+    ChannelClose = 6,
+    KeyAgreementFailed = 7,
     NoSuchChannel = 9,
     None = 255,
 }
@@ -225,6 +227,9 @@ impl TryFrom<u8> for MessageType {
             3 => Ok(MessageType::KeyAgreementM1),
             4 => Ok(MessageType::KeyAgreementM2),
             5 => Ok(MessageType::KeyAgreementM3),
+            6 => Ok(MessageType::ChannelClose),
+            7 => Ok(MessageType::KeyAgreementFailed),
+            9 => Ok(MessageType::NoSuchChannel),
             _ => Err("Unknown message type".to_string()),
         }
     }
@@ -8,6 +8,28 @@ pub enum Error {
     InvalidInternalState,
     InvalidArgument,
     BareError,
+    /// The event identifier isn't the hash of the event
+    InvalidEventIdentifier,
+    /// The event isn't signed by its own key
+    InvalidSelfSignature,
+    /// The event isn't signed by the key of the previous event
+    InvalidPreviousSignature,
+    /// An event doesn't link to the event before it
+    BrokenEventChain,
+    /// An event follows the revocation of the profile
+    EventAfterRevocation,
+    /// The profile identifier doesn't match the first key of the profile
+    InvalidProfileIdentifier,
+    /// A profile's change history diverges from the one that is already known
+    ProfileFork,
+    /// The profile has no private keys, it was imported or revoked
+    ReadOnlyProfile,
+    /// Reading or writing a stored profile failed
+    IOError,
+    /// No profile with the identifier is stored
+    ProfileNotFound,
+    /// A stored private key doesn't belong to its event
+    InvalidPrivateKey,
 }
 
 impl Error {
@@ -15,8 +37,8 @@ impl Error {
     pub const ERROR_DOMAIN: &'static str = "PROFILE_ERROR_DOMAIN";
 }
 
-impl Into<OckamError> for Error {
-    fn into(self) -> OckamError {
-        OckamError::new(self as u32, Error::ERROR_DOMAIN)
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
     }
 }
@@ -2,26 +2,41 @@ use ockam_vault::{HashVault, SecretVault, SignerVault, VerifierVault};
 
 pub mod error;
 pub mod profile;
+pub mod profile_binary_model;
 pub mod profile_event;
 pub mod profile_event_binary_model;
 pub mod profile_manager;
+pub mod profile_store;
 
-pub trait ProfileVault: SecretVault + SignerVault + VerifierVault + HashVault + Send {}
+pub trait ProfileVault:
+    SecretVault + SignerVault + VerifierVault + HashVault + Send + Sync
+{
+}
 
-impl<D> ProfileVault for D where D: SecretVault + SignerVault + VerifierVault + HashVault + Send {}
+impl<D> ProfileVault for D where
+    D: SecretVault + SignerVault + VerifierVault + HashVault + Send + Sync
+{
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::profile::profile::{ProfileEventAttributeKey, ProfileEventAttributes};
+    use crate::profile::error::Error;
+    use crate::profile::profile::{Profile, ProfileEventAttributeKey, ProfileEventAttributes};
+    use crate::profile::profile_binary_model::SignedProfileEventBinaryModel;
+    use crate::profile::profile_event::ProfileEvent;
+    use crate::profile::profile_event_binary_model::ProfileEventBinaryModel;
     use crate::profile::profile_manager::ProfileManager;
+    use crate::profile::ProfileVault;
+    use ockam_vault::types::SecretType;
+    use ockam_vault::VerifierVault;
     use ockam_vault_software::DefaultVault;
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
 
     #[allow(non_snake_case)]
     #[test]
     fn test() {
         let vault = DefaultVault::default();
-        let vault = Arc::new(Mutex::new(vault));
+        let vault = Arc::new(vault);
         let manager = ProfileManager::new();
 
         let mut attributes = ProfileEventAttributes::new();
@@ -61,4 +76,189 @@ mod tests {
             .revoke_profile(profile, Some(attributes.clone()))
             .unwrap();
     }
+
+    fn friendly_name(name: &str) -> ProfileEventAttributes {
+        let mut attributes = ProfileEventAttributes::new();
+        attributes.insert(
+            ProfileEventAttributeKey::FRIENDLY_NAME.to_string(),
+            name.to_string(),
+        );
+        attributes.insert(
+            ProfileEventAttributeKey::CREATION_DATE.to_string(),
+            "1605000000".to_string(),
+        );
+        attributes
+    }
+
+    /// A copy of `event` without its private key
+    fn public_copy(event: &ProfileEvent, vault: &dyn ProfileVault) -> ProfileEvent {
+        ProfileEvent::from_binary_model(&event.to_binary_model().unwrap(), vault).unwrap()
+    }
+
+    #[test]
+    fn export_and_import() {
+        let manager = ProfileManager::new();
+        let vault = Arc::new(DefaultVault::default());
+        let mut alice = manager
+            .create_profile(Some(friendly_name("Alice")), vault.clone())
+            .unwrap();
+        manager
+            .rotate_profile(&mut alice, Some(friendly_name("Alice")))
+            .unwrap();
+        manager.verify_profile(&alice).unwrap();
+
+        let exported = manager.export_profile(&alice).unwrap();
+        // the encoding is canonical
+        assert_eq!(manager.export_profile(&alice).unwrap(), exported);
+
+        let bob_vault = Arc::new(DefaultVault::default());
+        let mut remote = manager
+            .import_profile(&exported, bob_vault.clone())
+            .unwrap();
+        assert_eq!(remote.identifier(), alice.identifier());
+        assert_eq!(remote.events().len(), 2);
+        for (local, remote) in alice.events().iter().zip(remote.events().iter()) {
+            assert_eq!(local.identifier(), remote.identifier());
+            assert_eq!(local.model_binary(), remote.model_binary());
+            assert_eq!(local.attributes(), remote.attributes());
+            assert!(remote.private_key().is_none());
+        }
+        assert_eq!(manager.export_profile(&remote).unwrap(), exported);
+
+        // attestations check out against the imported key
+        let signature = manager.attest_profile(&alice, b"nonce").unwrap();
+        let public_key = manager.get_profile_public_key(&remote).unwrap().unwrap();
+        bob_vault
+            .verify(&signature, &public_key, SecretType::Curve25519, b"nonce")
+            .unwrap();
+
+        // remote profiles are read-only
+        let error = manager.rotate_profile(&mut remote, None).unwrap_err();
+        assert_eq!(error.code(), Error::ReadOnlyProfile as u32);
+        let error = manager.attest_profile(&remote, b"nonce").unwrap_err();
+        assert_eq!(error.code(), Error::ReadOnlyProfile as u32);
+    }
+
+    #[test]
+    fn tampered_exports_are_rejected() {
+        let manager = ProfileManager::new();
+        let vault = Arc::new(DefaultVault::default());
+        let mut alice = manager.create_profile(None, vault.clone()).unwrap();
+        manager.rotate_profile(&mut alice, None).unwrap();
+        let exported = manager.export_profile(&alice).unwrap();
+        let import = |data: &[u8]| {
+            manager
+                .import_profile(data, vault.clone())
+                .map(|_| ())
+                .unwrap_err()
+                .code()
+        };
+
+        // the export ends with the signature by the first key of the second event,
+        // preceded by its own signature, its option tag and its length
+        let mut data = exported.clone();
+        *data.last_mut().unwrap() ^= 1;
+        assert_eq!(import(&data), Error::InvalidPreviousSignature as u32);
+        let mut data = exported.clone();
+        let self_signature = data.len() - 64 - 2 - 1;
+        data[self_signature] ^= 1;
+        assert_eq!(import(&data), Error::InvalidSelfSignature as u32);
+
+        // an identifier that belongs to another key
+        let bob = manager.create_profile(None, vault.clone()).unwrap();
+        let mut data = exported.clone();
+        let at = data
+            .windows(bob.identifier().len())
+            .position(|w| w == alice.identifier().as_bytes())
+            .unwrap();
+        data[at..at + bob.identifier().len()].copy_from_slice(bob.identifier().as_bytes());
+        assert_eq!(import(&data), Error::InvalidProfileIdentifier as u32);
+
+        assert_eq!(
+            import(&exported[..exported.len() - 1]),
+            Error::BareError as u32
+        );
+    }
+
+    #[test]
+    fn forks_are_rejected() {
+        let manager = ProfileManager::new();
+        let vault = Arc::new(DefaultVault::default());
+        let mut alice = manager.create_profile(None, vault.clone()).unwrap();
+        let mut remote = manager
+            .import_profile(&manager.export_profile(&alice).unwrap(), vault.clone())
+            .unwrap();
+
+        // a second change of the first event that the remote side never sees
+        let fork = ProfileEvent::new(
+            false,
+            friendly_name("Mallory"),
+            Some(&alice.events()[0]),
+            vault.clone(),
+        )
+        .unwrap();
+        manager.rotate_profile(&mut alice, None).unwrap();
+        manager
+            .update_profile(&mut remote, &manager.export_profile(&alice).unwrap())
+            .unwrap();
+        assert_eq!(remote.events().len(), 2);
+
+        // both branches in a single history
+        let events = vec![
+            public_copy(&alice.events()[0], vault.as_ref()),
+            public_copy(&alice.events()[1], vault.as_ref()),
+            public_copy(&fork, vault.as_ref()),
+        ];
+        let forked = Profile::new(alice.identifier().to_string(), events, vault.clone());
+        let error = manager.verify_profile(&forked).unwrap_err();
+        assert_eq!(error.code(), Error::BrokenEventChain as u32);
+
+        // the other branch on its own is a valid history, but not the known one
+        let events = vec![public_copy(&alice.events()[0], vault.as_ref()), fork];
+        let forked = Profile::new(alice.identifier().to_string(), events, vault.clone());
+        manager.verify_profile(&forked).unwrap();
+        let error = manager
+            .update_profile(&mut remote, &manager.export_profile(&forked).unwrap())
+            .unwrap_err();
+        assert_eq!(error.code(), Error::ProfileFork as u32);
+        assert_eq!(
+            remote.events()[1].identifier(),
+            alice.events()[1].identifier()
+        );
+    }
+
+    #[test]
+    fn nothing_follows_a_revocation() {
+        let manager = ProfileManager::new();
+        let vault = Arc::new(DefaultVault::default());
+        let mut alice = manager.create_profile(None, vault.clone()).unwrap();
+        let mut remote = manager
+            .import_profile(&manager.export_profile(&alice).unwrap(), vault.clone())
+            .unwrap();
+        alice.revoke(ProfileEventAttributes::new()).unwrap();
+        let revoked = manager.export_profile(&alice).unwrap();
+        manager.update_profile(&mut remote, &revoked).unwrap();
+        assert!(manager.get_profile_public_key(&remote).unwrap().is_none());
+
+        // an event claiming to follow the revocation
+        let revocation = alice.events().last().unwrap();
+        let event = ProfileEventBinaryModel::new(
+            1,
+            alice.events()[0].public_key().clone(),
+            ProfileEventAttributes::new(),
+            Some(revocation.identifier().to_string()),
+            None,
+        );
+        let event = SignedProfileEventBinaryModel::new(event, Some(vec![0u8; 64]), None);
+        let mut events: Vec<_> = alice
+            .events()
+            .iter()
+            .map(|e| public_copy(e, vault.as_ref()))
+            .collect();
+        events.push(ProfileEvent::from_binary_model(&event, vault.as_ref()).unwrap());
+        let profile = Profile::new(alice.identifier().to_string(), events, vault.clone());
+        let error = manager.verify_profile(&profile).unwrap_err();
+        assert_eq!(error.code(), Error::EventAfterRevocation as u32);
+        manager.delete_profile(alice).unwrap();
+    }
 }
@@ -1,12 +1,14 @@
 use crate::profile::error::Error;
+use crate::profile::profile_binary_model::ProfileBinaryModel;
 use crate::profile::profile_event::ProfileEvent;
 use crate::profile::ProfileVault;
 use ockam_common::error::OckamResult;
 use ockam_vault::Secret;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
-pub type ProfileEventAttributes = HashMap<String, String>;
+/// Attributes are sorted by key so that events have a single encoding
+pub type ProfileEventAttributes = BTreeMap<String, String>;
 
 #[non_exhaustive]
 pub struct ProfileEventAttributeKey;
@@ -19,7 +21,7 @@ impl ProfileEventAttributeKey {
 pub struct Profile {
     identifier: String,
     events: Vec<ProfileEvent>,
-    vault: Arc<Mutex<dyn ProfileVault>>,
+    vault: Arc<dyn ProfileVault>,
 }
 
 impl Profile {
@@ -29,7 +31,7 @@ impl Profile {
     pub fn events(&self) -> &Vec<ProfileEvent> {
         &self.events
     }
-    pub fn vault(&self) -> &Arc<Mutex<dyn ProfileVault>> {
+    pub fn vault(&self) -> &Arc<dyn ProfileVault> {
         &self.vault
     }
 }
@@ -38,7 +40,7 @@ impl Profile {
     pub(crate) fn new(
         identifier: String,
         events: Vec<ProfileEvent>,
-        vault: Arc<Mutex<dyn ProfileVault>>,
+        vault: Arc<dyn ProfileVault>,
     ) -> Self {
         Profile {
             identifier,
@@ -47,6 +49,24 @@ impl Profile {
         }
     }
 
+    /// The identifier of a profile whose first key is `public_key`
+    pub(crate) fn identifier_for(
+        public_key: &[u8],
+        vault: &dyn ProfileVault,
+    ) -> OckamResult<String> {
+        let hash = vault.sha256(public_key)?;
+        Ok(format!("P_ID.{}", hex::encode(hash)))
+    }
+
+    /// The last event if this side holds its private key
+    fn last_local_event(&self) -> OckamResult<&ProfileEvent> {
+        let event = self.events.last().ok_or(Error::InvalidInternalState)?;
+        if event.private_key().is_none() {
+            return Err(Error::ReadOnlyProfile.into());
+        }
+        Ok(event)
+    }
+
     pub(crate) fn public_key(&self) -> OckamResult<Option<Vec<u8>>> {
         let event: &ProfileEvent;
         if let Some(e) = self.events.last() {
@@ -59,12 +79,7 @@ impl Profile {
     }
 
     pub(crate) fn rotate(&mut self, attributes: ProfileEventAttributes) -> OckamResult<()> {
-        let event: &ProfileEvent;
-        if let Some(e) = self.events.last() {
-            event = e;
-        } else {
-            return Err(Error::InvalidInternalState.into());
-        }
+        let event = self.last_local_event()?;
 
         let new_event = ProfileEvent::new(false, attributes, Some(event), self.vault.clone())?;
 
@@ -74,12 +89,7 @@ impl Profile {
     }
 
     pub(crate) fn revoke(&mut self, attributes: ProfileEventAttributes) -> OckamResult<()> {
-        let event: &ProfileEvent;
-        if let Some(e) = self.events.last() {
-            event = e;
-        } else {
-            return Err(Error::InvalidInternalState.into());
-        }
+        let event = self.last_local_event()?;
 
         let new_event = ProfileEvent::new(true, attributes, Some(event), self.vault.clone())?;
 
@@ -89,27 +99,22 @@ impl Profile {
     }
 
     pub(crate) fn attest(&self, nonce: &[u8]) -> OckamResult<[u8; 64]> {
-        let event: &ProfileEvent;
-        if let Some(e) = self.events.last() {
-            event = e;
-        } else {
-            return Err(Error::InvalidInternalState.into());
-        }
+        let event = self.last_local_event()?;
 
-        let private_key: &Box<dyn Secret>;
+        let private_key: &dyn Secret;
         if let Some(key) = event.private_key() {
-            private_key = key;
+            private_key = key.as_ref();
         } else {
             return Err(Error::InvalidInternalState.into());
         }
 
-        let mut vault = self.vault.lock().unwrap();
+        let vault = &self.vault;
 
         Ok(vault.sign(private_key, nonce)?)
     }
 
     pub(crate) fn delete(&mut self) -> OckamResult<()> {
-        let mut vault = self.vault.lock().unwrap();
+        let vault = &self.vault;
 
         while let Some(mut event) = self.events.pop() {
             if let Some(private_key) = event.take_private_key() {
@@ -119,4 +124,94 @@ impl Profile {
 
         Ok(())
     }
+
+    /// Encode the public parts of the profile to send it to peers
+    pub(crate) fn export(&self) -> OckamResult<Vec<u8>> {
+        serde_bare::to_vec(&self.to_binary_model()?).map_err(|_| Error::BareError.into())
+    }
+
+    /// Decode and verify a profile a peer exported. The profile is read-only, it has
+    /// none of the private keys.
+    pub(crate) fn import(data: &[u8], vault: Arc<dyn ProfileVault>) -> OckamResult<Self> {
+        let model: ProfileBinaryModel =
+            serde_bare::from_slice(data).map_err(|_| Error::BareError)?;
+        Self::from_binary_model(model, vault)
+    }
+
+    pub(crate) fn to_binary_model(&self) -> OckamResult<ProfileBinaryModel> {
+        let events = self
+            .events
+            .iter()
+            .map(|e| e.to_binary_model())
+            .collect::<OckamResult<Vec<_>>>()?;
+        Ok(ProfileBinaryModel::new(1, self.identifier.clone(), events))
+    }
+
+    /// Recreate and verify a profile without private keys
+    pub(crate) fn from_binary_model(
+        model: ProfileBinaryModel,
+        vault: Arc<dyn ProfileVault>,
+    ) -> OckamResult<Self> {
+        if model.version() != 1 {
+            return Err(Error::InvalidArgument.into());
+        }
+        let identifier = model.identifier().to_string();
+        let events = model
+            .take_events()
+            .iter()
+            .map(|e| ProfileEvent::from_binary_model(e, vault.as_ref()))
+            .collect::<OckamResult<Vec<_>>>()?;
+        let profile = Profile::new(identifier, events, vault);
+        profile.verify()?;
+
+        Ok(profile)
+    }
+
+    pub(crate) fn events_mut(&mut self) -> &mut Vec<ProfileEvent> {
+        &mut self.events
+    }
+
+    /// Check the change history: every event is signed by its own key and the key
+    /// before it, links to the event before it and nothing follows a revocation. The
+    /// identifier must belong to the first key.
+    pub(crate) fn verify(&self) -> OckamResult<()> {
+        let vault = self.vault.as_ref();
+        let first = self.events.first().ok_or(Error::InvalidInternalState)?;
+        let mut previous_event = None;
+        for event in self.events.iter() {
+            event.verify(previous_event, vault)?;
+            previous_event = Some(event);
+        }
+
+        let public_key = first
+            .public_key()
+            .as_ref()
+            .ok_or(Error::InvalidInternalState)?;
+        if self.identifier != Self::identifier_for(public_key, vault)? {
+            return Err(Error::InvalidProfileIdentifier.into());
+        }
+
+        Ok(())
+    }
+
+    /// Replace the events of a remote profile with a newer export of it. The new
+    /// history must continue the known one.
+    pub(crate) fn update(&mut self, data: &[u8]) -> OckamResult<()> {
+        let profile = Self::import(data, self.vault.clone())?;
+        if profile.identifier != self.identifier {
+            return Err(Error::InvalidProfileIdentifier.into());
+        }
+        if profile.events.len() < self.events.len()
+            || self
+                .events
+                .iter()
+                .zip(profile.events.iter())
+                .any(|(known, new)| known.identifier() != new.identifier())
+        {
+            return Err(Error::ProfileFork.into());
+        }
+        self.events = profile.events;
+
+        Ok(())
+    }
 }
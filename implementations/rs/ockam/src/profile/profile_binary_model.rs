@@ -0,0 +1,75 @@
+use crate::profile::profile_event_binary_model::ProfileEventBinaryModel;
+use serde::{Deserialize, Serialize};
+
+/// Public parts of a profile event. The event identifier is the hash of the
+/// encoded `event`, the signatures sign that hash.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignedProfileEventBinaryModel {
+    event: ProfileEventBinaryModel,
+    self_signature: Option<Vec<u8>>,
+    previous_self_signature: Option<Vec<u8>>,
+}
+
+impl SignedProfileEventBinaryModel {
+    pub fn event(&self) -> &ProfileEventBinaryModel {
+        &self.event
+    }
+    pub fn self_signature(&self) -> &Option<Vec<u8>> {
+        &self.self_signature
+    }
+    pub fn previous_self_signature(&self) -> &Option<Vec<u8>> {
+        &self.previous_self_signature
+    }
+}
+
+impl SignedProfileEventBinaryModel {
+    pub(crate) fn new(
+        event: ProfileEventBinaryModel,
+        self_signature: Option<Vec<u8>>,
+        previous_self_signature: Option<Vec<u8>>,
+    ) -> Self {
+        SignedProfileEventBinaryModel {
+            event,
+            self_signature,
+            previous_self_signature,
+        }
+    }
+}
+
+/// A profile as it is sent to peers, the events are in the order they happened
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProfileBinaryModel {
+    version: u8,
+    identifier: String,
+    events: Vec<SignedProfileEventBinaryModel>,
+}
+
+impl ProfileBinaryModel {
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+    pub fn events(&self) -> &Vec<SignedProfileEventBinaryModel> {
+        &self.events
+    }
+}
+
+impl ProfileBinaryModel {
+    pub(crate) fn new(
+        version: u8,
+        identifier: String,
+        events: Vec<SignedProfileEventBinaryModel>,
+    ) -> Self {
+        ProfileBinaryModel {
+            version,
+            identifier,
+            events,
+        }
+    }
+
+    pub(crate) fn take_events(self) -> Vec<SignedProfileEventBinaryModel> {
+        self.events
+    }
+}
@@ -1,11 +1,15 @@
 use crate::profile::error::Error;
 use crate::profile::profile::ProfileEventAttributes;
+use crate::profile::profile_binary_model::SignedProfileEventBinaryModel;
 use crate::profile::profile_event_binary_model::ProfileEventBinaryModel;
 use crate::profile::ProfileVault;
 use ockam_common::error::OckamResult;
-use ockam_vault::types::{SecretAttributes, SecretPersistence, SecretType};
+use ockam_vault::types::{
+    SecretAttributes, SecretPersistence, SecretType, SecretUsage, CURVE25519_SECRET_LENGTH,
+};
 use ockam_vault::Secret;
-use std::sync::{Arc, Mutex};
+use std::convert::TryFrom;
+use std::sync::Arc;
 
 pub struct ProfileEvent {
     version: u8,
@@ -59,26 +63,35 @@ impl ProfileEvent {
         self.private_key.take()
     }
 
+    pub(crate) fn set_private_key(&mut self, private_key: Box<dyn Secret>) {
+        self.private_key = Some(private_key);
+    }
+
     pub fn new(
         is_revoke: bool,
         attributes: ProfileEventAttributes,
         previous_event: Option<&ProfileEvent>,
-        vault: Arc<Mutex<dyn ProfileVault>>,
+        vault: Arc<dyn ProfileVault>,
     ) -> OckamResult<Self> {
-        let mut vault = vault.lock().unwrap();
+        let vault = &vault;
 
-        let keys = (|| {
+        let keys = (|| -> OckamResult<_> {
             if is_revoke {
                 Ok((None, None))
             } else {
                 let attributes = SecretAttributes {
                     stype: SecretType::Curve25519,
                     persistence: SecretPersistence::Persistent,
-                    length: 0,
+                    length: CURVE25519_SECRET_LENGTH,
+                    exportable: false,
+                    usage: SecretUsage::SIGN,
                 };
 
                 let private_key = vault.secret_generate(attributes)?;
-                let public_key = vault.secret_public_key_get(&private_key)?.as_ref().to_vec();
+                let public_key = vault
+                    .secret_public_key_get(private_key.as_ref())?
+                    .as_ref()
+                    .to_vec();
 
                 Ok((Some(private_key), Some(public_key)))
             }
@@ -96,19 +109,18 @@ impl ProfileEvent {
             prev_event_id.clone(),
             None,
         );
-        let model_binary: Vec<u8> =
-            serde_bare::to_vec(&model).map_err(|_| Error::BareError.into())?;
+        let model_binary: Vec<u8> = serde_bare::to_vec(&model).map_err(|_| Error::BareError)?;
         let identifier = vault.sha256(&model_binary)?;
         let self_signature = match &keys.0 {
-            Some(s) => Some(vault.sign(s, &identifier)?),
+            Some(s) => Some(vault.sign(s.as_ref(), &identifier)?),
             None => None,
         };
 
         let previous_self_signature = match previous_event {
             Some(event) => {
-                let private_key: &Box<dyn Secret>;
+                let private_key: &dyn Secret;
                 if let Some(key) = event.private_key() {
-                    private_key = key;
+                    private_key = key.as_ref();
                 } else {
                     return Err(Error::InvalidInternalState.into());
                 }
@@ -117,7 +129,7 @@ impl ProfileEvent {
             None => None,
         };
 
-        let identifier = format!("E_ID.{}", hex::encode(&identifier));
+        let identifier = Self::event_identifier(&identifier);
 
         Ok(ProfileEvent {
             version: 1,
@@ -133,3 +145,113 @@ impl ProfileEvent {
         })
     }
 }
+
+impl ProfileEvent {
+    fn event_identifier(hash: &[u8]) -> String {
+        format!("E_ID.{}", hex::encode(hash))
+    }
+
+    /// The public parts of the event
+    pub(crate) fn to_binary_model(&self) -> OckamResult<SignedProfileEventBinaryModel> {
+        let event: ProfileEventBinaryModel =
+            serde_bare::from_slice(&self.model_binary).map_err(|_| Error::BareError)?;
+        Ok(SignedProfileEventBinaryModel::new(
+            event,
+            self.self_signature.map(|s| s.to_vec()),
+            self.previous_self_signature.map(|s| s.to_vec()),
+        ))
+    }
+
+    /// Recreate an event of a remote profile, it has no private key. The signatures
+    /// aren't checked here, see `verify`.
+    pub(crate) fn from_binary_model(
+        model: &SignedProfileEventBinaryModel,
+        vault: &dyn ProfileVault,
+    ) -> OckamResult<Self> {
+        let signature = |s: &Option<Vec<u8>>| -> OckamResult<Option<[u8; 64]>> {
+            match s {
+                Some(s) => Ok(Some(
+                    <[u8; 64]>::try_from(s.as_slice()).map_err(|_| Error::InvalidArgument)?,
+                )),
+                None => Ok(None),
+            }
+        };
+        let event = model.event();
+        let model_binary = serde_bare::to_vec(event).map_err(|_| Error::BareError)?;
+        let identifier = Self::event_identifier(&vault.sha256(&model_binary)?);
+
+        Ok(ProfileEvent {
+            version: event.version(),
+            identifier,
+            model_binary,
+            attributes: event.attributes().clone(),
+            public_key: event.public_key().clone(),
+            prev_event_id: event.prev_event_id().clone(),
+            next_event_id: event.next_event_id().clone(),
+            private_key: None,
+            self_signature: signature(model.self_signature())?,
+            previous_self_signature: signature(model.previous_self_signature())?,
+        })
+    }
+
+    /// Check that the identifier is the hash of the event, that the event is signed by
+    /// its own key and, unless it is the first event, that it follows `previous_event`
+    /// and is signed by its key. Revocations have no key of their own and nothing may
+    /// follow them.
+    pub(crate) fn verify(
+        &self,
+        previous_event: Option<&ProfileEvent>,
+        vault: &dyn ProfileVault,
+    ) -> OckamResult<()> {
+        let hash = vault.sha256(&self.model_binary)?;
+        let model: ProfileEventBinaryModel =
+            serde_bare::from_slice(&self.model_binary).map_err(|_| Error::BareError)?;
+        if self.identifier != Self::event_identifier(&hash)
+            || self.version != model.version()
+            || &self.public_key != model.public_key()
+            || &self.attributes != model.attributes()
+            || &self.prev_event_id != model.prev_event_id()
+        {
+            return Err(Error::InvalidEventIdentifier.into());
+        }
+
+        match (previous_event, &self.prev_event_id) {
+            (None, None) => {
+                // there is nothing to revoke
+                if self.public_key.is_none() {
+                    return Err(Error::BrokenEventChain.into());
+                }
+            }
+            (Some(previous_event), Some(id)) if *id == previous_event.identifier => {
+                if previous_event.public_key.is_none() {
+                    return Err(Error::EventAfterRevocation.into());
+                }
+            }
+            _ => return Err(Error::BrokenEventChain.into()),
+        }
+
+        match (&self.public_key, &self.self_signature) {
+            (Some(public_key), Some(signature)) => vault
+                .verify(signature, public_key, SecretType::Curve25519, &hash)
+                .map_err(|_| Error::InvalidSelfSignature)?,
+            (None, None) => {}
+            _ => return Err(Error::InvalidSelfSignature.into()),
+        }
+
+        match (previous_event, &self.previous_self_signature) {
+            (Some(previous_event), Some(signature)) => {
+                let public_key = previous_event
+                    .public_key
+                    .as_ref()
+                    .ok_or(Error::InvalidInternalState)?;
+                vault
+                    .verify(signature, public_key, SecretType::Curve25519, &hash)
+                    .map_err(|_| Error::InvalidPreviousSignature)?
+            }
+            (None, None) => {}
+            _ => return Err(Error::InvalidPreviousSignature.into()),
+        }
+
+        Ok(())
+    }
+}
@@ -10,6 +10,24 @@ pub struct ProfileEventBinaryModel {
     next_event_id: Option<String>,
 }
 
+impl ProfileEventBinaryModel {
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+    pub fn public_key(&self) -> &Option<Vec<u8>> {
+        &self.public_key
+    }
+    pub fn attributes(&self) -> &ProfileEventAttributes {
+        &self.attributes
+    }
+    pub fn prev_event_id(&self) -> &Option<String> {
+        &self.prev_event_id
+    }
+    pub fn next_event_id(&self) -> &Option<String> {
+        &self.next_event_id
+    }
+}
+
 impl ProfileEventBinaryModel {
     pub(crate) fn new(
         version: u8,
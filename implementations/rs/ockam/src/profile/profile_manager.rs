@@ -3,7 +3,7 @@ use crate::profile::profile::{Profile, ProfileEventAttributes};
 use crate::profile::profile_event::ProfileEvent;
 use crate::profile::ProfileVault;
 use ockam_common::error::OckamResult;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 pub struct ProfileManager {}
 
@@ -15,16 +15,14 @@ impl ProfileManager {
     pub fn create_profile(
         &self,
         attributes: Option<ProfileEventAttributes>,
-        vault: Arc<Mutex<dyn ProfileVault>>,
+        vault: Arc<dyn ProfileVault>,
     ) -> OckamResult<Profile> {
         let attributes = attributes.unwrap_or(ProfileEventAttributes::new());
         let event = ProfileEvent::new(false, attributes, None, vault.clone())?;
 
         let identifier: String;
         if let Some(public_key) = event.public_key() {
-            let vault = vault.lock().unwrap();
-            let hash = vault.sha256(&public_key)?;
-            identifier = format!("P_ID.{}", hex::encode(&hash));
+            identifier = Profile::identifier_for(public_key, vault.as_ref())?;
         } else {
             return Err(Error::InvalidInternalState.into());
         }
@@ -65,4 +63,24 @@ impl ProfileManager {
     pub fn delete_profile(&self, mut profile: Profile) -> OckamResult<()> {
         profile.delete()
     }
+
+    pub fn export_profile(&self, profile: &Profile) -> OckamResult<Vec<u8>> {
+        profile.export()
+    }
+
+    pub fn import_profile(
+        &self,
+        data: &[u8],
+        vault: Arc<dyn ProfileVault>,
+    ) -> OckamResult<Profile> {
+        Profile::import(data, vault)
+    }
+
+    pub fn update_profile(&self, profile: &mut Profile, data: &[u8]) -> OckamResult<()> {
+        profile.update(data)
+    }
+
+    pub fn verify_profile(&self, profile: &Profile) -> OckamResult<()> {
+        profile.verify()
+    }
 }
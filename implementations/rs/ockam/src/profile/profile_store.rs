@@ -0,0 +1,334 @@
+use crate::profile::error::Error;
+use crate::profile::profile::Profile;
+use crate::profile::profile_binary_model::ProfileBinaryModel;
+use crate::profile::ProfileVault;
+use ockam_common::error::OckamResult;
+use ockam_vault::PersistentVault;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const FILENAME_PROFILE_SUFFIX: &str = ".profile";
+const TEMP_SUFFIX: &str = ".tmp";
+const PROFILE_ID_PREFIX: &str = "P_ID.";
+
+/// A stored profile, its public parts and where the vault keeps the private key of
+/// every event
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredProfileBinaryModel {
+    version: u8,
+    profile: ProfileBinaryModel,
+    persistence_ids: Vec<Option<String>>,
+}
+
+/// Keeps the change histories of profiles on disk so that they survive restarts
+/// together with their keys in a persistent vault, usually in a directory next to
+/// the vault's. The store doesn't see the changes the `ProfileManager` makes, save a
+/// profile after every change to it. Profiles are loaded by identifier.
+/// The files hold no secrets, only the persistence ids of the keys.
+pub struct ProfileStore<V: ProfileVault + PersistentVault> {
+    path: PathBuf,
+    vault: Arc<V>,
+}
+
+impl<V: ProfileVault + PersistentVault> std::fmt::Debug for ProfileStore<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProfileStore {{ path: {:?}, vault }}", self.path)
+    }
+}
+
+impl<V: ProfileVault + PersistentVault + 'static> ProfileStore<V> {
+    /// Create a store of the profiles whose keys are in `vault` in the directory
+    /// `path`, it is created if it doesn't exist
+    pub fn new(path: PathBuf, vault: Arc<V>) -> OckamResult<Self> {
+        fs::create_dir_all(&path).map_err(|_| Error::IOError)?;
+        Ok(Self { path, vault })
+    }
+
+    /// Save `profile`, replacing the previous version of it
+    pub fn save(&self, profile: &Profile) -> OckamResult<()> {
+        let path = self.profile_path(profile.identifier())?;
+        let persistence_ids = profile
+            .events()
+            .iter()
+            .map(|e| match e.private_key() {
+                Some(private_key) => self
+                    .vault
+                    .get_persistence_id(private_key.as_ref())
+                    .map(Some),
+                None => Ok(None),
+            })
+            .collect::<OckamResult<Vec<_>>>()?;
+        let model = StoredProfileBinaryModel {
+            version: 1,
+            profile: profile.to_binary_model()?,
+            persistence_ids,
+        };
+        let data = serde_bare::to_vec(&model).map_err(|_| Error::BareError)?;
+        write_atomic(&path, &data)
+    }
+
+    /// Load the profile with `identifier` with the private keys of its events, its
+    /// change history is verified
+    pub fn load(&self, identifier: &str) -> OckamResult<Profile> {
+        let path = self.profile_path(identifier)?;
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::ProfileNotFound.into())
+            }
+            Err(_) => return Err(Error::IOError.into()),
+        };
+        let model: StoredProfileBinaryModel =
+            serde_bare::from_slice(&data).map_err(|_| Error::BareError)?;
+        if model.version != 1 || model.profile.identifier() != identifier {
+            return Err(Error::InvalidArgument.into());
+        }
+
+        let vault: Arc<dyn ProfileVault> = self.vault.clone();
+        let mut profile = Profile::from_binary_model(model.profile, vault)?;
+        if model.persistence_ids.len() != profile.events().len() {
+            return Err(Error::InvalidArgument.into());
+        }
+        for (event, persistence_id) in profile
+            .events_mut()
+            .iter_mut()
+            .zip(model.persistence_ids.iter())
+        {
+            let persistence_id = match persistence_id {
+                Some(id) => id,
+                None => continue,
+            };
+            let private_key = self.vault.get_persistent_secret(persistence_id)?;
+            let public_key = self.vault.secret_public_key_get(private_key.as_ref())?;
+            if event.public_key().as_deref() != Some(public_key.as_ref()) {
+                return Err(Error::InvalidPrivateKey.into());
+            }
+            event.set_private_key(private_key);
+        }
+
+        Ok(profile)
+    }
+
+    /// Remove the profile with `identifier` from the store, its keys stay in the vault
+    pub fn remove(&self, identifier: &str) -> OckamResult<()> {
+        match fs::remove_file(self.profile_path(identifier)?) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::ProfileNotFound.into())
+            }
+            Err(_) => Err(Error::IOError.into()),
+        }
+    }
+
+    /// Identifiers of the stored profiles
+    pub fn identifiers(&self) -> OckamResult<Vec<String>> {
+        let mut identifiers = vec![];
+        for entry in self.path.read_dir().map_err(|_| Error::IOError)? {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            let file_name = entry.file_name();
+            let identifier = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(FILENAME_PROFILE_SUFFIX));
+            match identifier {
+                Some(identifier) if is_profile_identifier(identifier) => {
+                    identifiers.push(identifier.to_string())
+                }
+                _ => continue,
+            }
+        }
+        identifiers.sort();
+        Ok(identifiers)
+    }
+
+    /// Identifiers name files, anything but `P_ID.` and a hex hash is refused
+    fn profile_path(&self, identifier: &str) -> OckamResult<PathBuf> {
+        if !is_profile_identifier(identifier) {
+            return Err(Error::InvalidArgument.into());
+        }
+        Ok(self
+            .path
+            .join(format!("{}{}", identifier, FILENAME_PROFILE_SUFFIX)))
+    }
+}
+
+fn is_profile_identifier(identifier: &str) -> bool {
+    match identifier.strip_prefix(PROFILE_ID_PREFIX) {
+        Some(hash) => hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+/// Replace `path` with `data` through a synced temporary file so that a crash leaves
+/// the old or the new version, the directory is synced to persist the rename
+fn write_atomic(path: &Path, data: &[u8]) -> OckamResult<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(TEMP_SUFFIX);
+    let temp = PathBuf::from(temp);
+
+    let result = File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|_| Error::IOError.into())
+        .and_then(|_| sync_dir(path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> OckamResult<()> {
+    let dir = path.parent().ok_or(Error::IOError)?;
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|_| Error::IOError.into())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> OckamResult<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::profile::{ProfileEventAttributeKey, ProfileEventAttributes};
+    use crate::profile::profile_manager::ProfileManager;
+    use ockam_vault::types::SecretType;
+    use ockam_vault::VerifierVault;
+    use ockam_vault_file::FilesystemVault;
+
+    /// A vault directory and a profile directory next to it, removed when dropped
+    struct TestDirs(PathBuf);
+
+    impl TestDirs {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("ockam_profile_store_{}", name));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+
+        fn vault(&self) -> PathBuf {
+            self.0.join("vault")
+        }
+
+        fn profiles(&self) -> PathBuf {
+            self.0.join("profiles")
+        }
+    }
+
+    impl Drop for TestDirs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn load_error<V: ProfileVault + PersistentVault + 'static>(
+        store: &ProfileStore<V>,
+        identifier: &str,
+    ) -> u32 {
+        store.load(identifier).map(|_| ()).unwrap_err().code()
+    }
+
+    #[test]
+    fn profiles_survive_restarts() {
+        let dirs = TestDirs::new("restart");
+        let (vault_dir, profile_dir) = (dirs.vault(), dirs.profiles());
+        let manager = ProfileManager::new();
+        let mut attributes = ProfileEventAttributes::new();
+        attributes.insert(
+            ProfileEventAttributeKey::FRIENDLY_NAME.to_string(),
+            "Alice".to_string(),
+        );
+
+        let (identifier, exported) = {
+            let vault = Arc::new(FilesystemVault::new(vault_dir.clone()).unwrap());
+            let store = ProfileStore::new(profile_dir.clone(), vault.clone()).unwrap();
+            let mut alice = manager
+                .create_profile(Some(attributes.clone()), vault)
+                .unwrap();
+            store.save(&alice).unwrap();
+            manager
+                .rotate_profile(&mut alice, Some(attributes.clone()))
+                .unwrap();
+            store.save(&alice).unwrap();
+            (
+                alice.identifier().to_string(),
+                manager.export_profile(&alice).unwrap(),
+            )
+        };
+
+        let vault = Arc::new(FilesystemVault::new(vault_dir).unwrap());
+        let store = ProfileStore::new(profile_dir, vault.clone()).unwrap();
+        assert_eq!(store.identifiers().unwrap(), vec![identifier.clone()]);
+        let mut alice = store.load(&identifier).unwrap();
+        assert_eq!(manager.export_profile(&alice).unwrap(), exported);
+        assert_eq!(alice.events()[1].attributes(), &attributes);
+        assert!(alice.events().iter().all(|e| e.private_key().is_some()));
+
+        // the reloaded profile signs with the keys from the vault
+        let signature = manager.attest_profile(&alice, b"nonce").unwrap();
+        let public_key = manager.get_profile_public_key(&alice).unwrap().unwrap();
+        vault
+            .verify(&signature, &public_key, SecretType::Curve25519, b"nonce")
+            .unwrap();
+        manager.rotate_profile(&mut alice, None).unwrap();
+        manager.verify_profile(&alice).unwrap();
+        store.save(&alice).unwrap();
+        assert_eq!(store.load(&identifier).unwrap().events().len(), 3);
+
+        store.remove(&identifier).unwrap();
+        assert_eq!(
+            load_error(&store, &identifier),
+            Error::ProfileNotFound as u32
+        );
+        manager.delete_profile(alice).unwrap();
+    }
+
+    #[test]
+    fn keys_must_match_their_events() {
+        let dirs = TestDirs::new("keys");
+        let (vault_dir, profile_dir) = (dirs.vault(), dirs.profiles());
+        let manager = ProfileManager::new();
+        let vault = Arc::new(FilesystemVault::new(vault_dir).unwrap());
+        let store = ProfileStore::new(profile_dir.clone(), vault.clone()).unwrap();
+        let alice = manager.create_profile(None, vault.clone()).unwrap();
+        let bob = manager.create_profile(None, vault).unwrap();
+        store.save(&alice).unwrap();
+        store.save(&bob).unwrap();
+
+        // alice's history with bob's key
+        let path = |p: &Profile| profile_dir.join(format!("{}.profile", p.identifier()));
+        let mut model: StoredProfileBinaryModel =
+            serde_bare::from_slice(&fs::read(path(&alice)).unwrap()).unwrap();
+        let bob_model: StoredProfileBinaryModel =
+            serde_bare::from_slice(&fs::read(path(&bob)).unwrap()).unwrap();
+        model.persistence_ids = bob_model.persistence_ids;
+        fs::write(path(&alice), serde_bare::to_vec(&model).unwrap()).unwrap();
+        assert_eq!(
+            load_error(&store, alice.identifier()),
+            Error::InvalidPrivateKey as u32
+        );
+
+        // deleted keys can't be loaded
+        let identifier = bob.identifier().to_string();
+        manager.delete_profile(bob).unwrap();
+        assert!(store.load(&identifier).is_err());
+
+        // identifiers can't point outside of the store
+        assert_eq!(
+            load_error(&store, "P_ID.../vault/0"),
+            Error::InvalidArgument as u32
+        );
+        manager.delete_profile(alice).unwrap();
+    }
+}
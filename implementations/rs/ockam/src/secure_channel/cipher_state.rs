@@ -0,0 +1,485 @@
+use crate::secure_channel::error::Error;
+use crate::secure_channel::replay_window::ReplayWindow;
+use crate::secure_channel::ChannelVault;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::{SecretPersistence, SecretType};
+use ockam_vault::Secret;
+
+/// The info string used when deriving the next key of a channel direction
+const REKEY_INFO: &[u8] = b"OCKAM_SECURE_CHANNEL_REKEY";
+/// Size of the payload header, a be u32 key generation followed by a be u64 nonce
+pub const PAYLOAD_HEADER_SIZE: usize = 12;
+/// How many key generations a receiver is willing to skip ahead in one message
+pub const MAX_GENERATION_SKIP: u32 = 8;
+
+/// Thresholds after which the sending side of a channel switches to a new key.
+/// Whichever threshold is reached first triggers the rekey.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RekeyPolicy {
+    /// Maximum number of messages encrypted with one key
+    pub max_messages: u64,
+    /// Maximum number of plaintext bytes encrypted with one key
+    pub max_bytes: u64,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_messages: 1 << 20,
+            max_bytes: 1 << 30,
+        }
+    }
+}
+
+/// One direction of an established channel: the current key, its generation and the
+/// 64 bit message counter.
+///
+/// The counter is never reset by a rekey, so a (generation, nonce) pair is unique for
+/// the lifetime of the channel. Payloads carry both values in a 12 byte header which
+/// lets the receiver follow the sender's key changes deterministically.
+///
+/// On the receiving side every accepted nonce goes through a replay window. The key of
+/// the previous generation is kept as well so payloads reordered across a rekey can
+/// still be decrypted while they are inside the window.
+pub(crate) struct CipherState {
+    key: Box<dyn Secret>,
+    previous_key: Option<Box<dyn Secret>>,
+    generation: u32,
+    nonce: u64,
+    messages: u64,
+    bytes: u64,
+    window: ReplayWindow,
+}
+
+impl std::fmt::Debug for CipherState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "CipherState {{ generation: {:?}, nonce: {:?} }}",
+            self.generation, self.nonce
+        )
+    }
+}
+
+impl CipherState {
+    pub fn new(key: Box<dyn Secret>) -> Self {
+        Self {
+            key,
+            previous_key: None,
+            generation: 0,
+            nonce: 0,
+            messages: 0,
+            bytes: 0,
+            window: ReplayWindow::default(),
+        }
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Use up the counter as if `u64::MAX` messages had been sent
+    #[cfg(test)]
+    pub(crate) fn exhaust(&mut self) {
+        self.nonce = u64::MAX;
+    }
+
+    /// Encrypt `plaintext` and return the header followed by the ciphertext and tag.
+    /// Rekeys first if `policy` says the current key has been used enough.
+    pub fn encrypt(
+        &mut self,
+        vault: &dyn ChannelVault,
+        policy: &RekeyPolicy,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        if self.nonce == u64::MAX {
+            return Err(Error::NonceExhausted.into());
+        }
+        if self.messages >= policy.max_messages || self.bytes >= policy.max_bytes {
+            if self.generation == u32::MAX {
+                return Err(Error::NonceExhausted.into());
+            }
+            let key = Self::rekey(vault, self.key.as_ref())?;
+            let old_key = std::mem::replace(&mut self.key, key);
+            vault.secret_destroy(old_key)?;
+            self.generation += 1;
+            self.messages = 0;
+            self.bytes = 0;
+        }
+
+        let mut payload = Self::encode_header(self.generation, self.nonce);
+        let mut ciphertext_and_tag = Self::aead_encrypt(
+            vault,
+            self.key.as_ref(),
+            plaintext,
+            &Self::nonce_64_to_96(self.nonce),
+            aad,
+        )?;
+        payload.append(&mut ciphertext_and_tag);
+
+        self.nonce += 1;
+        self.messages += 1;
+        self.bytes = self.bytes.saturating_add(plaintext.len() as u64);
+        Ok(payload)
+    }
+
+    /// Decrypt a payload produced by the peer's `encrypt`, following the peer to a newer
+    /// key generation if the payload authenticates under it. Replayed payloads and
+    /// payloads too far behind the newest one are rejected with `Error::ReplayRejected`.
+    pub fn decrypt(
+        &mut self,
+        vault: &dyn ChannelVault,
+        aad: &[u8],
+        payload: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let (generation, nonce) = Self::decode_header(payload)?;
+        let ciphertext = &payload[PAYLOAD_HEADER_SIZE..];
+        let nonce_96 = Self::nonce_64_to_96(nonce);
+
+        if !self.window.check(nonce) {
+            return Err(Error::ReplayRejected.into());
+        }
+
+        if generation < self.generation {
+            let previous_key = match &self.previous_key {
+                Some(k) if generation + 1 == self.generation => k,
+                _ => return Err(Error::StaleKeyGeneration.into()),
+            };
+            let plaintext =
+                Self::aead_decrypt(vault, previous_key.as_ref(), ciphertext, &nonce_96, aad)?;
+            self.window.mark(nonce);
+            return Ok(plaintext);
+        }
+        if generation == self.generation {
+            let plaintext =
+                Self::aead_decrypt(vault, self.key.as_ref(), ciphertext, &nonce_96, aad)?;
+            self.window.mark(nonce);
+            self.nonce = self.window.highest();
+            return Ok(plaintext);
+        }
+        if generation - self.generation > MAX_GENERATION_SKIP {
+            return Err(Error::StaleKeyGeneration.into());
+        }
+
+        // Derive the candidate keys but only keep them if the payload authenticates,
+        // otherwise a forged header could push us onto keys the sender never used
+        let mut candidates: Vec<Box<dyn Secret>> = vec![];
+        for _ in self.generation..generation {
+            let key = Self::rekey(vault, candidates.last().unwrap_or(&self.key).as_ref())?;
+            candidates.push(key);
+        }
+        let result = Self::aead_decrypt(
+            vault,
+            candidates.last().unwrap().as_ref(),
+            ciphertext,
+            &nonce_96,
+            aad,
+        );
+        match result {
+            Ok(plaintext) => {
+                let key = candidates.pop().unwrap();
+                let old_key = std::mem::replace(&mut self.key, key);
+                let previous_key = match candidates.pop() {
+                    Some(k) => {
+                        vault.secret_destroy(old_key)?;
+                        k
+                    }
+                    None => old_key,
+                };
+                if let Some(k) = self.previous_key.replace(previous_key) {
+                    vault.secret_destroy(k)?;
+                }
+                for c in candidates {
+                    vault.secret_destroy(c)?;
+                }
+                self.generation = generation;
+                self.window.mark(nonce);
+                self.nonce = self.window.highest();
+                Ok(plaintext)
+            }
+            Err(e) => {
+                for c in candidates {
+                    vault.secret_destroy(c)?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Destroy the keys held by this state
+    pub fn destroy(self, vault: &dyn ChannelVault) -> OckamResult<()> {
+        if let Some(k) = self.previous_key {
+            vault.secret_destroy(k)?;
+        }
+        vault.secret_destroy(self.key)
+    }
+
+    /// Noise style REKEY, the next key is HKDF(salt = current key, info = REKEY_INFO)
+    fn rekey(vault: &dyn ChannelVault, key: &dyn Secret) -> OckamResult<Box<dyn Secret>> {
+        let mut attributes = vault.secret_attributes_get(key)?;
+        attributes.persistence = SecretPersistence::Ephemeral;
+        let mut keys = vault.hkdf_sha256(key, REKEY_INFO, None, vec![attributes])?;
+        keys.pop().ok_or_else(|| Error::InvalidState.into())
+    }
+
+    /// The cipher follows the type of the key the handshake produced
+    fn aead_encrypt(
+        vault: &dyn ChannelVault,
+        key: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        match vault.secret_attributes_get(key)?.stype {
+            SecretType::ChaCha20Poly1305 => {
+                vault.aead_chacha20_poly1305_encrypt(key, plaintext, nonce, aad)
+            }
+            _ => vault.aead_aes_gcm_encrypt(key, plaintext, nonce, aad),
+        }
+    }
+
+    fn aead_decrypt(
+        vault: &dyn ChannelVault,
+        key: &dyn Secret,
+        ciphertext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        match vault.secret_attributes_get(key)?.stype {
+            SecretType::ChaCha20Poly1305 => {
+                vault.aead_chacha20_poly1305_decrypt(key, ciphertext, nonce, aad)
+            }
+            _ => vault.aead_aes_gcm_decrypt(key, ciphertext, nonce, aad),
+        }
+    }
+
+    fn encode_header(generation: u32, nonce: u64) -> Vec<u8> {
+        let mut header = Vec::with_capacity(PAYLOAD_HEADER_SIZE);
+        header.extend_from_slice(&generation.to_be_bytes());
+        header.extend_from_slice(&nonce.to_be_bytes());
+        header
+    }
+
+    fn decode_header(payload: &[u8]) -> OckamResult<(u32, u64)> {
+        if payload.len() < PAYLOAD_HEADER_SIZE {
+            return Err(Error::RecvError.into());
+        }
+        let mut generation = [0u8; 4];
+        generation.copy_from_slice(&payload[..4]);
+        let mut nonce = [0u8; 8];
+        nonce.copy_from_slice(&payload[4..PAYLOAD_HEADER_SIZE]);
+        Ok((u32::from_be_bytes(generation), u64::from_be_bytes(nonce)))
+    }
+
+    /// The AEAD nonce is 4 bytes of 0's followed by the be representation of the counter
+    pub fn nonce_64_to_96(n64: u64) -> [u8; 12] {
+        let mut n: [u8; 12] = [0; 12];
+        n[4..].copy_from_slice(&n64.to_be_bytes());
+        n
+    }
+
+    pub fn nonce_from_96(n: &[u8; 12]) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&n[4..]);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam_vault::types::{
+        SecretAttributes, SecretUsage, AES256_SECRET_LENGTH, CHACHA20POLY1305_SECRET_LENGTH,
+    };
+    use ockam_vault::SecretVault;
+    use ockam_vault_software::DefaultVault;
+
+    fn cipher_states(vault: &DefaultVault) -> (CipherState, CipherState) {
+        cipher_states_of_type(vault, SecretType::Aes, AES256_SECRET_LENGTH)
+    }
+
+    fn cipher_states_of_type(
+        vault: &DefaultVault,
+        stype: SecretType,
+        length: usize,
+    ) -> (CipherState, CipherState) {
+        let attributes = SecretAttributes {
+            stype,
+            persistence: SecretPersistence::Ephemeral,
+            length,
+            exportable: true,
+            usage: SecretUsage::ALL,
+        };
+        let key = [7u8; 32];
+        let send = vault.secret_import(&key, attributes).unwrap();
+        let recv = vault.secret_import(&key, attributes).unwrap();
+        (CipherState::new(send), CipherState::new(recv))
+    }
+
+    #[test]
+    fn nonce_conversion() {
+        let n = CipherState::nonce_64_to_96(0x0102_0304_0506_0708);
+        assert_eq!(n, [0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(CipherState::nonce_from_96(&n), 0x0102_0304_0506_0708);
+    }
+
+    #[test]
+    fn rekey_on_message_count() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states(&vault);
+        let policy = RekeyPolicy {
+            max_messages: 3,
+            max_bytes: u64::MAX,
+        };
+        for i in 0..10u8 {
+            let payload = send.encrypt(&vault, &policy, b"aad", &[i; 5]).unwrap();
+            let plaintext = recv.decrypt(&vault, b"aad", &payload).unwrap();
+            assert_eq!(plaintext, vec![i; 5]);
+        }
+        assert_eq!(send.generation(), 3);
+        assert_eq!(recv.generation(), 3);
+        assert_eq!(send.nonce(), 10);
+    }
+
+    #[test]
+    fn chacha20_poly1305_keys() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states_of_type(
+            &vault,
+            SecretType::ChaCha20Poly1305,
+            CHACHA20POLY1305_SECRET_LENGTH,
+        );
+        let policy = RekeyPolicy {
+            max_messages: 2,
+            max_bytes: u64::MAX,
+        };
+        for i in 0..5u8 {
+            let payload = send.encrypt(&vault, &policy, b"aad", &[i; 5]).unwrap();
+            assert_eq!(payload.len(), PAYLOAD_HEADER_SIZE + 5 + 16);
+            let plaintext = recv.decrypt(&vault, b"aad", &payload).unwrap();
+            assert_eq!(plaintext, vec![i; 5]);
+        }
+        assert_eq!(send.generation(), 2);
+        assert_eq!(recv.generation(), 2);
+
+        // An AES-GCM key with the same bytes cannot open the payloads
+        let (mut send, mut recv) = cipher_states_of_type(
+            &vault,
+            SecretType::ChaCha20Poly1305,
+            CHACHA20POLY1305_SECRET_LENGTH,
+        );
+        let (mut aes_send, mut aes_recv) = cipher_states(&vault);
+        let payload = send
+            .encrypt(&vault, &RekeyPolicy::default(), &[], b"hello")
+            .unwrap();
+        assert!(aes_recv.decrypt(&vault, &[], &payload).is_err());
+        let payload = aes_send
+            .encrypt(&vault, &RekeyPolicy::default(), &[], b"hello")
+            .unwrap();
+        assert!(recv.decrypt(&vault, &[], &payload).is_err());
+    }
+
+    #[test]
+    fn rekey_on_byte_count() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states(&vault);
+        let policy = RekeyPolicy {
+            max_messages: u64::MAX,
+            max_bytes: 100,
+        };
+        for _ in 0..4 {
+            let payload = send.encrypt(&vault, &policy, &[], &[0u8; 60]).unwrap();
+            recv.decrypt(&vault, &[], &payload).unwrap();
+        }
+        assert_eq!(send.generation(), 1);
+        assert_eq!(recv.generation(), 1);
+    }
+
+    #[test]
+    fn receiver_skips_lost_generations() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states(&vault);
+        let policy = RekeyPolicy {
+            max_messages: 1,
+            max_bytes: u64::MAX,
+        };
+        let mut payload = vec![];
+        for _ in 0..4 {
+            payload = send.encrypt(&vault, &policy, &[], b"hello").unwrap();
+        }
+        assert_eq!(recv.decrypt(&vault, &[], &payload).unwrap(), b"hello");
+        assert_eq!(recv.generation(), 3);
+    }
+
+    #[test]
+    fn forged_generation_is_not_followed() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states(&vault);
+        let mut payload = send
+            .encrypt(&vault, &RekeyPolicy::default(), &[], b"hello")
+            .unwrap();
+        payload[3] = 1;
+        assert!(recv.decrypt(&vault, &[], &payload).is_err());
+        assert_eq!(recv.generation(), 0);
+    }
+
+    #[test]
+    fn replayed_payload_is_rejected() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states(&vault);
+        let payload = send
+            .encrypt(&vault, &RekeyPolicy::default(), &[], b"hello")
+            .unwrap();
+        recv.decrypt(&vault, &[], &payload).unwrap();
+        let err = recv.decrypt(&vault, &[], &payload).unwrap_err();
+        assert_eq!(err.code(), Error::ReplayRejected as u32);
+    }
+
+    #[test]
+    fn reordering_across_rekey_is_tolerated() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states(&vault);
+        let policy = RekeyPolicy {
+            max_messages: 2,
+            max_bytes: u64::MAX,
+        };
+        let payloads: Vec<Vec<u8>> = (0..4u8)
+            .map(|i| send.encrypt(&vault, &policy, &[], &[i]).unwrap())
+            .collect();
+        assert_eq!(recv.decrypt(&vault, &[], &payloads[0]).unwrap(), [0]);
+        assert_eq!(recv.decrypt(&vault, &[], &payloads[3]).unwrap(), [3]);
+        assert_eq!(recv.decrypt(&vault, &[], &payloads[1]).unwrap(), [1]);
+        assert_eq!(recv.decrypt(&vault, &[], &payloads[2]).unwrap(), [2]);
+        assert!(recv.decrypt(&vault, &[], &payloads[1]).is_err());
+    }
+
+    #[test]
+    fn forged_payload_does_not_move_window() {
+        let vault = DefaultVault::default();
+        let (mut send, mut recv) = cipher_states(&vault);
+        let mut payload = send
+            .encrypt(&vault, &RekeyPolicy::default(), &[], b"hello")
+            .unwrap();
+        let valid = payload.clone();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(recv.decrypt(&vault, &[], &payload).is_err());
+        assert!(recv.decrypt(&vault, &[], &valid).is_ok());
+    }
+
+    #[test]
+    fn exhausted_counter_refuses_to_send() {
+        let vault = DefaultVault::default();
+        let (mut send, _) = cipher_states(&vault);
+        send.nonce = u64::MAX;
+        let err = send
+            .encrypt(&vault, &RekeyPolicy::default(), &[], b"hello")
+            .unwrap_err();
+        assert_eq!(err.code(), Error::NonceExhausted as u32);
+    }
+}
@@ -18,6 +18,21 @@ pub enum Error {
     CantSend,
     /// Receive error
     RecvError,
+    /// The channel's message counter or key generations are used up
+    NonceExhausted,
+    /// A payload was encrypted under a key generation the receiver no longer holds
+    StaleKeyGeneration,
+    /// A payload was rejected by the replay window, it was either already received or
+    /// is too old to tell
+    ReplayRejected,
+    /// The trust policy rejected the peer's static public key
+    UntrustedPeer,
+    /// The trust policy's key store could not be read or written
+    TrustStoreError,
+    /// No channel exists for the given address
+    NoSuchChannel,
+    /// A close message failed to authenticate or carried data
+    InvalidCloseMessage,
 }
 
 impl Error {
@@ -25,14 +40,14 @@ impl Error {
     pub const ERROR_DOMAIN: &'static str = "OCKAM_SECURE_CHANNEL_ERROR_DOMAIN";
 }
 
-impl Into<OckamError> for Error {
-    fn into(self) -> OckamError {
-        OckamError::new(self as u32, Error::ERROR_DOMAIN)
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
     }
 }
 
 impl From<SendError<OckamCommand>> for Error {
     fn from(_: SendError<OckamCommand>) -> Self {
-        Error::CantSend.into()
+        Error::CantSend
     }
 }
@@ -0,0 +1,150 @@
+use crate::message::Message;
+use std::time::{Duration, Instant};
+
+/// How long a key agreement waits for the next handshake message before it
+/// retransmits its last one, and how often it does so before giving up.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HandshakePolicy {
+    /// Time to wait for a reply to the last handshake message
+    pub timeout: Duration,
+    /// Number of retransmissions before the handshake fails
+    pub max_retransmits: u32,
+}
+
+impl Default for HandshakePolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            max_retransmits: 3,
+        }
+    }
+}
+
+/// What the timer of a handshake decided
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum HandshakeTimer {
+    /// Nothing to do yet
+    Waiting,
+    /// Send the last handshake message again
+    Retransmit,
+    /// Out of retransmissions
+    Failed,
+}
+
+/// Handshake progress of one channel.
+///
+/// Keeps the last handshake message this side sent so it can be sent again, either
+/// because the reply did not arrive in time or because the peer repeated its own
+/// message, which means ours got lost. The message is kept after the handshake is
+/// done as well, until the peer proves it finished by sending a payload.
+#[derive(Debug, Default)]
+pub(crate) struct HandshakeState {
+    /// The last handshake message sent
+    last_sent: Option<Message>,
+    /// Set while a reply to `last_sent` is expected
+    sent_at: Option<Instant>,
+    retransmits: u32,
+    /// Body of the M1 that created a responder channel, to recognize duplicates
+    m1: Option<Vec<u8>>,
+    /// Body of the last handshake message the peer sent, to recognize duplicates
+    last_received: Option<Vec<u8>>,
+}
+
+impl HandshakeState {
+    /// Remember a handshake message that was just sent, `expect_reply` starts the timer
+    pub fn sent(&mut self, m: &Message, expect_reply: bool) {
+        self.last_sent = Some(m.clone());
+        self.sent_at = if expect_reply {
+            Some(Instant::now())
+        } else {
+            None
+        };
+        self.retransmits = 0;
+    }
+
+    /// The reply arrived, stop the timer
+    pub fn replied(&mut self) {
+        self.sent_at = None;
+    }
+
+    /// The peer finished its side, nothing will have to be sent again
+    pub fn finished(&mut self) {
+        self.last_sent = None;
+        self.sent_at = None;
+    }
+
+    /// The message to send again when the peer repeats itself
+    pub fn last_sent(&self) -> Option<&Message> {
+        self.last_sent.as_ref()
+    }
+
+    /// Remember the body of a handshake message that was just processed
+    pub fn received(&mut self, m: &[u8]) {
+        self.last_received = Some(m.to_vec());
+    }
+
+    /// Whether the peer repeated the last handshake message it sent
+    pub fn is_last_received(&self, m: &[u8]) -> bool {
+        self.last_received.as_deref() == Some(m)
+    }
+
+    pub fn set_m1(&mut self, m1: &[u8]) {
+        self.m1 = Some(m1.to_vec());
+    }
+
+    pub fn is_m1(&self, m1: &[u8]) -> bool {
+        self.m1.as_deref() == Some(m1)
+    }
+
+    /// Check the timer, on `Retransmit` it is restarted
+    pub fn poll(&mut self, policy: &HandshakePolicy) -> HandshakeTimer {
+        match self.sent_at {
+            Some(t) if t.elapsed() >= policy.timeout => {
+                if self.retransmits >= policy.max_retransmits {
+                    return HandshakeTimer::Failed;
+                }
+                self.retransmits += 1;
+                self.sent_at = Some(Instant::now());
+                HandshakeTimer::Retransmit
+            }
+            _ => HandshakeTimer::Waiting,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retransmits_then_fails() {
+        let policy = HandshakePolicy {
+            timeout: Duration::from_millis(0),
+            max_retransmits: 2,
+        };
+        let mut state = HandshakeState::default();
+        assert_eq!(state.poll(&policy), HandshakeTimer::Waiting);
+
+        state.sent(&Message::default(), true);
+        assert_eq!(state.poll(&policy), HandshakeTimer::Retransmit);
+        assert_eq!(state.poll(&policy), HandshakeTimer::Retransmit);
+        assert_eq!(state.poll(&policy), HandshakeTimer::Failed);
+
+        state.sent(&Message::default(), true);
+        state.replied();
+        assert_eq!(state.poll(&policy), HandshakeTimer::Waiting);
+        assert!(state.last_sent().is_some());
+        state.finished();
+        assert!(state.last_sent().is_none());
+    }
+
+    #[test]
+    fn waits_for_timeout() {
+        let mut state = HandshakeState::default();
+        state.sent(&Message::default(), true);
+        assert_eq!(
+            state.poll(&HandshakePolicy::default()),
+            HandshakeTimer::Waiting
+        );
+    }
+}
@@ -25,26 +25,48 @@ while_true
 use crate::message::{Address, AddressType, Codec, Message, MessageType, Route, RouterAddress};
 use crate::system::commands::OckamCommand::Router;
 use crate::system::commands::{ChannelCommand, OckamCommand, RouterCommand};
+use cipher_state::CipherState;
+pub use cipher_state::RekeyPolicy;
 use core::marker::PhantomData;
 use error::*;
+pub use handshake::HandshakePolicy;
+use handshake::{HandshakeState, HandshakeTimer};
 use ockam_common::error::OckamResult;
+#[cfg(feature = "ockam-kex-x3dh")]
+use ockam_kex::CipherSuite;
 use ockam_kex::{CompletedKeyExchange, KeyExchanger, NewKeyExchanger};
-use ockam_kex_xx::XXVault;
+#[cfg(feature = "ockam-kex-x3dh")]
+use ockam_kex_x3dh::{PreKeyStore, X3dhInitiator, X3dhNewKeyExchanger, X3dhResponder, X3dhVault};
 use ockam_vault::types::PublicKey;
-use ockam_vault::Secret;
-use rand::{thread_rng, Rng};
+use ockam_vault::{HashVault, RandomVault, Secret, SecretVault, SymmetricVault};
 use std::{
+    cell::RefCell,
     collections::BTreeMap,
+    rc::Rc,
     sync::{
         mpsc::{Receiver, Sender},
-        Arc, Mutex,
+        Arc,
     },
+    time::{Duration, Instant},
 };
 
 /// A channel address of zero indicates to the channel manager that
 /// a new channel is being initiated
 pub const CHANNEL_ZERO: &str = "00000000";
 
+/// Vault with the functionality channels need once the key exchange is done, to
+/// encrypt payloads, derive new keys and pick channel addresses
+pub trait ChannelVault:
+    SecretVault + HashVault + SymmetricVault + RandomVault + Send + Sync
+{
+}
+
+impl<D> ChannelVault for D where
+    D: SecretVault + HashVault + SymmetricVault + RandomVault + Send + Sync
+{
+}
+
+#[derive(Clone, Copy)]
 enum ExchangerRole {
     Initiator,
     Responder,
@@ -57,16 +79,21 @@ pub struct ChannelManager<
     R: KeyExchanger + 'static,
     E: NewKeyExchanger<I, R>,
 > {
-    channels: BTreeMap<String, Arc<Mutex<Channel>>>,
+    channels: BTreeMap<String, Rc<RefCell<Channel>>>,
     rx: Receiver<OckamCommand>,
     tx: Sender<OckamCommand>,
     router_tx: Sender<OckamCommand>,
-    vault: Arc<Mutex<dyn XXVault>>,
+    vault: Arc<dyn ChannelVault>,
     new_key_exchanger: E,
     phantom_i: PhantomData<I>,
     phantom_r: PhantomData<R>,
     resp_key_ctx: Option<Arc<Box<dyn Secret>>>,
     init_key_ctx: Option<Arc<Box<dyn Secret>>>,
+    rekey_policy: RekeyPolicy,
+    trust_policy: Box<dyn TrustPolicy>,
+    idle_timeout: Option<Duration>,
+    handshake_policy: HandshakePolicy,
+    peers: ChannelPeers,
 }
 
 impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> std::fmt::Debug
@@ -82,15 +109,18 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> std::fmt::Debug
 }
 
 impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<I, R, E> {
-    /// Create a new Channel Manager
+    /// Create a new Channel Manager. Every peer that completes a key exchange is
+    /// checked against `trust_policy` before its channel can be used.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         rx: Receiver<OckamCommand>,
         tx: Sender<OckamCommand>,
         router_tx: Sender<OckamCommand>,
-        vault: Arc<Mutex<dyn XXVault>>,
+        vault: Arc<dyn ChannelVault>,
         new_key_exchanger: E,
         resp_key_ctx: Option<Arc<Box<dyn Secret>>>,
         init_key_ctx: Option<Arc<Box<dyn Secret>>>,
+        trust_policy: Box<dyn TrustPolicy>,
     ) -> OckamResult<Self> {
         // register ChannelManager with the router as the handler for all Channel address types
         if let Err(_error) = router_tx.send(Router(RouterCommand::Register(
@@ -112,10 +142,40 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
             phantom_r: PhantomData,
             resp_key_ctx,
             init_key_ctx,
+            rekey_policy: RekeyPolicy::default(),
+            trust_policy,
+            idle_timeout: None,
+            handshake_policy: HandshakePolicy::default(),
+            peers: ChannelPeers::default(),
         })
     }
 
-    /// Check for work to be done and do it
+    /// Set the thresholds after which channels switch to a new key.
+    /// Only the sending side consults the policy, receivers follow the key generation
+    /// carried in each payload.
+    pub fn set_rekey_policy(&mut self, rekey_policy: RekeyPolicy) {
+        self.rekey_policy = rekey_policy;
+    }
+
+    /// Close established channels that have not sent or received a payload for
+    /// `idle_timeout`. Both ends are told about the close. `None`, the default, keeps
+    /// channels open until they are closed explicitly.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Set how long handshakes wait for the peer and how often they retransmit
+    pub fn set_handshake_policy(&mut self, handshake_policy: HandshakePolicy) {
+        self.handshake_policy = handshake_policy;
+    }
+
+    /// The peers of the established channels, kept current as channels open and close
+    pub fn channel_peers(&self) -> ChannelPeers {
+        self.peers.clone()
+    }
+
+    /// Check for work to be done and do it. Messages that can't be sent or received
+    /// are logged and dropped.
     pub fn poll(&mut self) -> OckamResult<bool> {
         let keep_going = true;
         let mut got_message = true;
@@ -129,15 +189,28 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
                     )) => {
                         self.initiate_new_channel(route, return_address)?;
                     }
+                    OckamCommand::Channel(ChannelCommand::Close(address)) => {
+                        // the peer or the idle timeout may have closed it already
+                        if let Some(channel) = self.channels.get(&address.as_string()).cloned() {
+                            self.close_channel(&mut channel.borrow_mut(), true, false)?;
+                        }
+                    }
                     OckamCommand::Channel(ChannelCommand::Stop) => {
-                        self.channels.clear();
+                        for channel in self.unique_channels() {
+                            self.close_channel(&mut channel.borrow_mut(), false, false)?;
+                        }
                         break;
                     }
+                    // a bad message is dropped, it must not hold up the ones behind it
                     OckamCommand::Channel(ChannelCommand::SendMessage(m)) => {
-                        self.handle_send(m)?;
+                        if let Err(e) = self.handle_send(m) {
+                            log::warn!("dropped message sent to a channel: {}", e);
+                        }
                     }
                     OckamCommand::Channel(ChannelCommand::ReceiveMessage(m)) => {
-                        self.handle_recv(m)?;
+                        if let Err(e) = self.handle_recv(m) {
+                            log::warn!("dropped message received on a channel: {}", e);
+                        }
                     }
                     _ => return Err(Error::InvalidParam.into()),
                 },
@@ -146,9 +219,153 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
                 }
             }
         }
+        self.poll_handshakes()?;
+        self.close_idle_channels()?;
         Ok(keep_going)
     }
 
+    /// Retransmit handshake messages that were not answered in time and give up on
+    /// handshakes that ran out of retransmissions. An initiator's worker is told about
+    /// the failure with a `KeyAgreementFailed` message.
+    fn poll_handshakes(&mut self) -> OckamResult<()> {
+        for channel in self.unique_channels() {
+            let mut channel = channel.borrow_mut();
+            match channel.handshake.poll(&self.handshake_policy) {
+                HandshakeTimer::Waiting => {}
+                HandshakeTimer::Retransmit => {
+                    if let Some(m) = channel.handshake.last_sent() {
+                        self.router_tx
+                            .send(Router(RouterCommand::SendMessage(m.clone())))
+                            .unwrap();
+                    }
+                }
+                HandshakeTimer::Failed => {
+                    if channel.pending.is_some() {
+                        self.notify_worker(&channel, MessageType::KeyAgreementFailed);
+                    }
+                    self.close_channel(&mut channel, false, false)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the last handshake message of a channel again, the peer repeated its own
+    /// message so ours was lost
+    fn resend_handshake(&self, channel: &Channel) {
+        if let Some(m) = channel.handshake.last_sent() {
+            self.router_tx
+                .send(Router(RouterCommand::SendMessage(m.clone())))
+                .unwrap();
+        }
+    }
+
+    /// Every channel once, the map holds each under both of its addresses
+    fn unique_channels(&self) -> Vec<Rc<RefCell<Channel>>> {
+        let mut channels: Vec<Rc<RefCell<Channel>>> = vec![];
+        for c in self.channels.values() {
+            if !channels.iter().any(|u| Rc::ptr_eq(u, c)) {
+                channels.push(c.clone());
+            }
+        }
+        channels
+    }
+
+    fn close_idle_channels(&mut self) -> OckamResult<()> {
+        let idle_timeout = match self.idle_timeout {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        for channel in self.unique_channels() {
+            let mut channel = channel.borrow_mut();
+            if channel.is_complete() && channel.last_activity.elapsed() >= idle_timeout {
+                self.close_channel(&mut channel, true, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove both addresses of a channel and destroy its keys. If `notify_peer` is
+    /// set an authenticated close message is sent to the other end first, if
+    /// `notify_worker` is set the local worker gets a `ChannelClose` message with the
+    /// channel's cleartext address as return route. The channel is removed even if
+    /// the close message can't be encrypted, e.g. once its nonces are used up.
+    fn close_channel(
+        &mut self,
+        channel: &mut Channel,
+        notify_peer: bool,
+        notify_worker: bool,
+    ) -> OckamResult<()> {
+        let vault = self.vault.as_ref();
+        if notify_peer {
+            let h = channel.h;
+            if let Some(cipher) = channel.send.as_mut() {
+                match cipher.encrypt(vault, &self.rekey_policy, &h, &[]) {
+                    Ok(close) => {
+                        let m = Message {
+                            onward_route: channel.route.clone(),
+                            return_route: Route {
+                                addresses: vec![RouterAddress::from_address(
+                                    channel.as_ciphertext_address(),
+                                )
+                                .unwrap()],
+                            },
+                            message_type: MessageType::ChannelClose,
+                            message_body: close,
+                        };
+                        self.router_tx
+                            .send(Router(RouterCommand::SendMessage(m)))
+                            .unwrap();
+                    }
+                    Err(e) => log::warn!("closing a channel without telling its peer: {}", e),
+                }
+            }
+        }
+
+        self.channels
+            .remove(&channel.as_cleartext_address().as_string());
+        self.channels
+            .remove(&channel.as_ciphertext_address().as_string());
+        self.peers.remove(&channel.as_cleartext_address());
+        if let Some(cipher) = channel.send.take() {
+            cipher.destroy(vault)?;
+        }
+        if let Some(cipher) = channel.recv.take() {
+            cipher.destroy(vault)?;
+        }
+
+        if notify_worker {
+            self.notify_worker(channel, MessageType::ChannelClose);
+        }
+        Ok(())
+    }
+
+    /// Tell the worker that uses `channel` about it. Initiators know their worker, for
+    /// responders the worker listening on the channel zero worker address is told.
+    fn notify_worker(&self, channel: &Channel, message_type: MessageType) {
+        let onward_route = match &channel.pending {
+            Some(p) => p.onward_route.clone(),
+            None => Route {
+                addresses: vec![
+                    RouterAddress::worker_router_address_from_str(CHANNEL_ZERO).unwrap()
+                ],
+            },
+        };
+        let m = Message {
+            onward_route,
+            return_route: Route {
+                addresses: vec![
+                    RouterAddress::from_address(channel.as_cleartext_address()).unwrap()
+                ],
+            },
+            message_type,
+            message_body: vec![],
+        };
+        self.router_tx
+            .send(Router(RouterCommand::ReceiveMessage(m)))
+            .unwrap();
+    }
+
     fn handle_send(&mut self, mut m: Message) -> OckamResult<()> {
         if m.onward_route.addresses.is_empty() {
             return Err(Error::CantSend.into());
@@ -159,7 +376,7 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
             .get_mut(&this_channel_address.address.as_string())
         {
             Some(channel) => {
-                let mut channel = channel.lock().unwrap();
+                let mut channel = channel.borrow_mut();
                 if this_channel_address.address == channel.as_cleartext_address() {
                     // messages coming in on the cleartext channel need to be encrypted,
                     // wrapped in an outer message, and sent on their way
@@ -172,22 +389,14 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
                     Message::encode(&m, &mut encoded_mb).unwrap();
 
                     // encrypt it
-                    let mut encrypted_mb: Vec<u8> = vec![];
-                    u16::encode(&channel.nonce, &mut encrypted_mb)
-                        .or_else(|_| Err(Error::CantSend.into()))?;
-
-                    let cke = channel.completed_key_exchange.as_ref().unwrap();
-                    let nonce = Channel::nonce_16_to_96(channel.nonce);
-                    let mut vault = self.vault.lock().unwrap();
-                    let mut ciphertext_and_tag = vault.aead_aes_gcm_encrypt(
-                        &cke.encrypt_key,
-                        &encoded_mb,
-                        &nonce,
-                        &cke.h,
-                    )?;
-                    channel.nonce += 1;
-
-                    encrypted_mb.append(&mut ciphertext_and_tag);
+                    let h = channel.h;
+                    let cipher = match channel.send.as_mut() {
+                        Some(c) => c,
+                        None => return Err(Error::InvalidState.into()),
+                    };
+                    let encrypted_mb =
+                        cipher.encrypt(self.vault.as_ref(), &self.rekey_policy, &h, &encoded_mb)?;
+                    channel.last_activity = Instant::now();
 
                     // construct the new message
                     let new_m = Message {
@@ -209,14 +418,11 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
 
                     Ok(())
                 } else {
-                    println!(
-                        "got unexapected send on ciphertext address: {:?}",
-                        m.message_type
-                    );
-                    Err(Error::NotImplemented.into())
+                    // only the channel itself sends on the ciphertext address
+                    Err(Error::CantSend.into())
                 }
             }
-            _ => Err(Error::NotImplemented.into()),
+            _ => Err(Error::NoSuchChannel.into()),
         };
     }
 
@@ -231,20 +437,29 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
         // If it's 0, we expect the message to be M1 of a key exchange
         // Respond accordingly
         let mut recv_address_str = m.onward_route.addresses[0].address.as_string();
+        let mut created = false;
         if recv_address_str == CHANNEL_ZERO {
-            if let Some((_clear, cipher)) = self.create_channel(ExchangerRole::Responder) {
-                recv_address_str = cipher;
-            } else {
-                return Err(Error::InvalidState.into());
+            if !matches!(m.message_type, MessageType::KeyAgreementM1) {
+                return Err(Error::InvalidParam.into());
+            }
+            // A repeated M1 means our M2 was lost, answer it from the existing channel
+            let duplicate = self
+                .unique_channels()
+                .into_iter()
+                .find(|c| c.borrow().handshake.is_m1(&m.message_body));
+            if let Some(channel) = duplicate {
+                self.resend_handshake(&channel.borrow());
+                return Ok(());
             }
+            let (_clear, cipher) = self.create_channel(ExchangerRole::Responder)?;
+            recv_address_str = cipher;
+            created = true;
         }
         match self.channels.get_mut(&recv_address_str) {
             Some(channel) => {
                 let channel = channel.clone();
 
-                let ch = channel.lock().unwrap();
-                let clear_address = ch.as_cleartext_address();
-                std::mem::drop(ch);
+                let clear_address = channel.borrow().as_cleartext_address();
                 if recv_address_str == clear_address.as_string() {
                     // if the message is received on the cleartext address, tunnel it
                     self.router_tx
@@ -254,37 +469,77 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
                 }
 
                 return match m.message_type {
-                    MessageType::KeyAgreementM1 => {
-                        self.handle_m1_recv(channel, m)?;
-                        Ok(())
+                    MessageType::KeyAgreementM1
+                    | MessageType::KeyAgreementM2
+                    | MessageType::KeyAgreementM3 => {
+                        let result = self.handle_handshake_recv(channel.clone(), m);
+                        if result.is_err() && created {
+                            // Nothing was sent for a bad M1, so no timer would ever
+                            // remove the channel
+                            self.close_channel(&mut channel.borrow_mut(), false, false)?;
+                        }
+                        result
                     }
-                    MessageType::KeyAgreementM2 => {
-                        self.handle_m2_recv(channel, m)?;
+                    MessageType::Payload => {
+                        self.handle_payload_recv(channel, m)?;
                         Ok(())
                     }
-                    MessageType::KeyAgreementM3 => {
-                        self.handle_m3_recv(channel, m)?;
+                    MessageType::ChannelClose => {
+                        self.handle_close_recv(channel, m)?;
                         Ok(())
                     }
-                    MessageType::Payload => {
-                        self.handle_payload_recv(channel, m)?;
+                    MessageType::NoSuchChannel => {
+                        // Unauthenticated, so only pass it on. The worker can close the
+                        // channel or leave it to the idle timeout.
+                        self.notify_worker(&channel.borrow(), MessageType::NoSuchChannel);
                         Ok(())
                     }
-                    _ => {
-                        debug_assert!(false);
-                        Err(Error::NotImplemented.into())
-                    }
+                    _ => Err(Error::InvalidParam.into()),
                 };
             }
             None => {
-                debug_assert!(false, "unknown channel address");
+                // Tell the sender the channel is gone, never answer messages that are
+                // themselves about closed channels
+                if m.return_route.addresses.is_empty()
+                    || matches!(
+                        m.message_type,
+                        MessageType::NoSuchChannel | MessageType::ChannelClose
+                    )
+                {
+                    return Ok(());
+                }
+                let reply = Message {
+                    onward_route: m.return_route,
+                    return_route: Route {
+                        addresses: vec![m.onward_route.addresses[0].clone()],
+                    },
+                    message_type: MessageType::NoSuchChannel,
+                    message_body: vec![],
+                };
+                self.router_tx
+                    .send(Router(RouterCommand::SendMessage(reply)))
+                    .unwrap();
             }
         }
         Ok(())
     }
 
-    fn handle_payload_recv(&self, channel: Arc<Mutex<Channel>>, m: Message) -> OckamResult<()> {
-        let channel = channel.lock().unwrap();
+    fn handle_close_recv(&mut self, channel: Rc<RefCell<Channel>>, m: Message) -> OckamResult<()> {
+        let mut channel = channel.borrow_mut();
+        let h = channel.h;
+        let cipher = match channel.recv.as_mut() {
+            Some(c) => c,
+            None => return Err(Error::InvalidState.into()),
+        };
+        let body = cipher.decrypt(self.vault.as_ref(), &h, &m.message_body)?;
+        if !body.is_empty() {
+            return Err(Error::InvalidCloseMessage.into());
+        }
+        self.close_channel(&mut channel, false, true)
+    }
+
+    fn handle_payload_recv(&self, channel: Rc<RefCell<Channel>>, m: Message) -> OckamResult<()> {
+        let mut channel = channel.borrow_mut();
 
         match &m.onward_route.addresses[0].address {
             Address::ChannelAddress(ca) => {
@@ -296,12 +551,15 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
         }
 
         // unwrap the payload and decode the message (payload *should* be an encrypted Message)
-        let (nonce, encrypted_msg) = u16::decode(&m.message_body).unwrap();
-        let nonce_96 = Channel::nonce_16_to_96(nonce);
-        let kex = channel.completed_key_exchange.as_ref().unwrap();
-        let mut vault = self.vault.lock().unwrap();
-        let encoded_msg =
-            vault.aead_aes_gcm_decrypt(&kex.decrypt_key, encrypted_msg, &nonce_96, &kex.h)?;
+        let h = channel.h;
+        let cipher = match channel.recv.as_mut() {
+            Some(c) => c,
+            None => return Err(Error::InvalidState.into()),
+        };
+        let encoded_msg = cipher.decrypt(self.vault.as_ref(), &h, &m.message_body)?;
+        channel.last_activity = Instant::now();
+        // the peer finished its handshake, it won't repeat handshake messages anymore
+        channel.handshake.finished();
         let (mut decoded_msg, _) = Message::decode(&encoded_msg).unwrap();
         decoded_msg.return_route.addresses.insert(
             0,
@@ -315,152 +573,214 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
         Ok(())
     }
 
-    fn handle_m1_recv(&self, channel: Arc<Mutex<Channel>>, m: Message) -> OckamResult<()> {
-        let channel = &mut *channel.lock().unwrap();
-        let cleartext_address = channel.as_cleartext_address();
+    /// Feed a handshake message to the key exchange of `channel` and send the reply it
+    /// produces. The key exchange decides how many messages it takes, the channel is
+    /// done once `KeyExchanger::is_complete` says so. Responders send their replies as
+    /// `KeyAgreementM2`, initiators as `KeyAgreementM3`. An initiator that can't trust
+    /// its peer removes the channel and tells its worker with `KeyAgreementFailed`.
+    fn handle_handshake_recv(
+        &mut self,
+        channel: Rc<RefCell<Channel>>,
+        m: Message,
+    ) -> OckamResult<()> {
+        let channel = &mut *channel.borrow_mut();
+        if channel.handshake.is_last_received(&m.message_body) {
+            // A repeated message means our reply was lost
+            self.resend_handshake(channel);
+            return Ok(());
+        }
         let ciphertext_address = channel.as_ciphertext_address();
-        let agreement = match &mut channel.agreement {
-            Some(e) => e.as_mut(),
-            None => {
-                return Err(Error::InvalidState.into());
-            }
+        let is_m1 = matches!(m.message_type, MessageType::KeyAgreementM1);
+        let data = if is_m1 {
+            // send cleartext channel address as payload of the responder's first reply
+            let cleartext_router_addr =
+                RouterAddress::from_address(channel.as_cleartext_address()).unwrap();
+            let mut cleartext_addr_encoded = vec![];
+            RouterAddress::encode(&cleartext_router_addr, &mut cleartext_addr_encoded).unwrap();
+            cleartext_addr_encoded
+        } else {
+            vec![]
         };
-
-        // send cleartext channel address as payload
-        let cleartext_router_addr = RouterAddress::from_address(cleartext_address).unwrap();
-        let mut cleartext_addr_encoded = vec![];
-        RouterAddress::encode(&cleartext_router_addr, &mut cleartext_addr_encoded).unwrap();
-
-        agreement.process(&m.message_body)?;
-        let m2 = agreement.process(&cleartext_addr_encoded)?;
-
-        let new_m = Message {
-            onward_route: m.return_route.clone(),
-            return_route: Route {
-                addresses: vec![RouterAddress::from_address(ciphertext_address).unwrap()],
-            },
-            message_type: MessageType::KeyAgreementM2,
-            message_body: m2,
+        let (payload, reply) = {
+            let agreement = match &mut channel.agreement {
+                Some(e) => e.as_mut(),
+                // duplicate, an earlier message already completed the channel
+                None => return Ok(()),
+            };
+            let payload = agreement.process(&m.message_body)?;
+            let reply = if agreement.is_complete() {
+                None
+            } else {
+                Some(agreement.process(&data)?)
+            };
+            (payload, reply)
         };
-        self.router_tx
-            .send(Router(RouterCommand::SendMessage(new_m)))
-            .unwrap();
-        Ok(())
-    }
+        channel.handshake.received(&m.message_body);
+        if is_m1 {
+            channel.handshake.set_m1(&m.message_body);
+        }
+        let complete = channel.agreement.as_ref().unwrap().is_complete();
 
-    fn handle_m2_recv(&self, channel: Arc<Mutex<Channel>>, m: Message) -> OckamResult<()> {
-        let mut channel = &mut *channel.lock().unwrap();
-        let mut agreement = match channel.agreement.take() {
-            Some(e) => e,
-            None => {
-                return Err(Error::InvalidState.into());
+        // The peer is checked before the last message goes out
+        let mut static_public_key = None;
+        if complete {
+            let agreement = channel.agreement.take().unwrap();
+            match channel.role {
+                ExchangerRole::Initiator => {
+                    match self.finalize_trusted(channel, agreement, &m.return_route) {
+                        Ok(Some(k)) => static_public_key = Some(k),
+                        failed => {
+                            // the worker waiting for the channel would never hear of it
+                            self.notify_worker(channel, MessageType::KeyAgreementFailed);
+                            self.close_channel(channel, false, false)?;
+                            return failed.and(Err(Error::UntrustedPeer.into()));
+                        }
+                    }
+                }
+                ExchangerRole::Responder => {
+                    self.complete_responder(channel, agreement, m.return_route.clone())?;
+                }
             }
-        };
+        }
 
-        let return_route = m.return_route.clone();
-        let channel_cleartext_addr_encoded = agreement.process(&m.message_body)?;
-        let m3 = agreement.process(&[])?;
-        let m = Message {
-            onward_route: return_route.clone(),
-            return_route: Route {
-                addresses: vec![m.onward_route.addresses[0].clone()],
-            },
-            message_type: MessageType::KeyAgreementM3,
-            message_body: m3,
-        };
-        self.router_tx
-            .send(Router(RouterCommand::SendMessage(m)))
-            .unwrap();
-        let completed_key_exchange = agreement.finalize()?;
-        let mut static_public_key = completed_key_exchange
-            .remote_static_public_key
-            .as_ref()
-            .to_vec();
-        channel.completed_key_exchange = Some(completed_key_exchange);
-        channel.route = return_route;
+        match reply {
+            Some(reply) => {
+                let message_type = match channel.role {
+                    ExchangerRole::Initiator => MessageType::KeyAgreementM3,
+                    ExchangerRole::Responder => MessageType::KeyAgreementM2,
+                };
+                let new_m = Message {
+                    onward_route: m.return_route,
+                    return_route: Route {
+                        addresses: vec![RouterAddress::from_address(ciphertext_address).unwrap()],
+                    },
+                    message_type,
+                    message_body: reply,
+                };
+                channel.handshake.sent(&new_m, !complete);
+                self.router_tx
+                    .send(Router(RouterCommand::SendMessage(new_m)))
+                    .unwrap();
+            }
+            None => channel.handshake.finished(),
+        }
 
         // let the worker know the key exchange is done
+        if let Some(mut static_public_key) = static_public_key {
+            match channel.pending.clone() {
+                Some(mut p) => {
+                    // send the remote public key and remote channel cleartext address as
+                    // the message body
+                    p.message_body = payload;
+                    p.message_body.append(&mut static_public_key);
+                    self.router_tx
+                        .send(Router(RouterCommand::ReceiveMessage(p)))
+                        .unwrap();
+                }
+                None => {
+                    return Err(Error::NotImplemented.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the responder side of a key exchange and let the local worker know.
+    /// Fails with `UntrustedPeer` if the trust policy rejected the initiator, the
+    /// channel is gone in that case.
+    fn complete_responder(
+        &mut self,
+        channel: &mut Channel,
+        agreement: Box<dyn KeyExchanger>,
+        return_route: Route,
+    ) -> OckamResult<()> {
+        if channel.is_complete() {
+            return Ok(());
+        }
+        // key agreement has finished, now can process any pending messages
+        let remote_static_public_key =
+            match self.finalize_trusted(channel, agreement, &return_route)? {
+                Some(k) => k,
+                None => return Err(Error::UntrustedPeer.into()),
+            };
         let pending = channel.pending.clone();
         match pending {
             Some(mut p) => {
-                // send the remote public key and remote channel cleartext address as the message
-                // body
-                p.message_body = channel_cleartext_addr_encoded;
-                p.message_body.append(&mut static_public_key);
+                p.return_route = channel.route.clone();
+                p.return_route.addresses.insert(
+                    0,
+                    RouterAddress::from_address(channel.as_cleartext_address()).unwrap(),
+                );
+                // add the channel's remote public key as the message body
+                p.message_body = remote_static_public_key;
+
                 self.router_tx
                     .send(Router(RouterCommand::ReceiveMessage(p)))
                     .unwrap();
+                channel.pending = None;
             }
-            None => {
-                return Err(Error::NotImplemented.into());
+            _ => {
+                let mut return_route = channel.route.clone();
+                return_route.addresses.insert(
+                    0,
+                    RouterAddress::from_address(channel.as_cleartext_address()).unwrap(),
+                );
+                let new_m = Message {
+                    onward_route: Route {
+                        addresses: vec![RouterAddress::worker_router_address_from_str(
+                            CHANNEL_ZERO,
+                        )
+                        .unwrap()],
+                    },
+                    return_route,
+                    message_type: MessageType::None,
+                    message_body: vec![],
+                };
+                self.router_tx
+                    .send(Router(RouterCommand::ReceiveMessage(new_m)))
+                    .unwrap();
             }
         }
         Ok(())
     }
 
-    fn handle_m3_recv(&self, channel: Arc<Mutex<Channel>>, m: Message) -> OckamResult<()> {
-        let mut channel = channel.lock().unwrap();
-        let mut agreement = match channel.agreement.take() {
-            Some(e) => e,
-            None => {
-                return Err(Error::InvalidState.into());
-            }
-        };
-
-        let return_route = m.return_route.clone();
-        // For now ignore anything returned from M3
-        let _ = agreement.process(&m.message_body)?;
-        debug_assert!(agreement.is_complete());
-        if channel.completed_key_exchange.is_none() {
-            // key agreement has finished, now can process any pending messages
-            let pending = channel.pending.clone();
-            let completed_key_exchange = agreement.finalize()?;
+    /// Finalize a key exchange and ask the trust policy about the peer. On success the
+    /// channel takes over the keys and the peer's static public key is returned. If the
+    /// peer is rejected, or the policy fails, the keys are destroyed and the channel is
+    /// removed.
+    fn finalize_trusted(
+        &mut self,
+        channel: &mut Channel,
+        agreement: Box<dyn KeyExchanger>,
+        route: &Route,
+    ) -> OckamResult<Option<Vec<u8>>> {
+        let completed_key_exchange = agreement.finalize()?;
+        let trusted = self
+            .trust_policy
+            .check(route, &completed_key_exchange.remote_static_public_key);
+        if let Ok(true) = trusted {
             let remote_static_public_key = completed_key_exchange
                 .remote_static_public_key
                 .as_ref()
                 .to_vec();
-            channel.completed_key_exchange = Some(completed_key_exchange);
-            channel.route = return_route;
-            let pending = channel.pending.clone();
-            match pending {
-                Some(mut p) => {
-                    p.return_route = channel.route.clone();
-                    p.return_route.addresses.insert(
-                        0,
-                        RouterAddress::from_address(channel.as_cleartext_address()).unwrap(),
-                    );
-                    // add the channel's remote public key as the message body
-                    p.message_body = remote_static_public_key;
-
-                    self.router_tx
-                        .send(Router(RouterCommand::ReceiveMessage(p)))
-                        .unwrap();
-                    channel.pending = None;
-                }
-                _ => {
-                    let mut return_route = channel.route.clone();
-                    return_route.addresses.insert(
-                        0,
-                        RouterAddress::from_address(channel.as_cleartext_address()).unwrap(),
-                    );
-                    let new_m = Message {
-                        onward_route: Route {
-                            addresses: vec![RouterAddress::worker_router_address_from_str(
-                                CHANNEL_ZERO,
-                            )
-                            .unwrap()],
-                        },
-                        return_route,
-                        message_type: MessageType::None,
-                        message_body: vec![],
-                    };
-                    self.router_tx
-                        .send(Router(RouterCommand::ReceiveMessage(new_m)))
-                        .unwrap();
-                }
-            }
+            channel.complete(completed_key_exchange);
+            channel.route = route.clone();
+            self.peers.insert(
+                &channel.as_cleartext_address(),
+                PublicKey::new(remote_static_public_key.clone()),
+            );
+            return Ok(Some(remote_static_public_key));
         }
-        Ok(())
+
+        self.channels
+            .remove(&channel.as_cleartext_address().as_string());
+        self.channels
+            .remove(&channel.as_ciphertext_address().as_string());
+        self.vault
+            .secret_destroy(completed_key_exchange.encrypt_key)?;
+        self.vault
+            .secret_destroy(completed_key_exchange.decrypt_key)?;
+        trusted.map(|_| None)
     }
 
     /// Initiates key exchange to create new secure channel over supplied route.
@@ -475,15 +795,10 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
         let pending_return = RouterAddress::from_address(return_address).unwrap();
 
         // Generate 2 channel addresses, one each for clear and cipher text
-        let mut clear_address = String::from(CHANNEL_ZERO);
-        let mut cipher_address = String::from(CHANNEL_ZERO);
-        if let Some((clear, cipher)) = self.create_channel(ExchangerRole::Initiator) {
-            clear_address = clear;
-            cipher_address = cipher;
-        }
+        let (clear_address, cipher_address) = self.create_channel(ExchangerRole::Initiator)?;
 
         let channel = self.channels.get_mut(&cipher_address).unwrap();
-        let mut channel = &mut *channel.lock().unwrap();
+        let channel = &mut *channel.borrow_mut();
         let agreement = match &mut channel.agreement {
             Some(e) => e.as_mut(),
             None => {
@@ -513,54 +828,96 @@ impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> ChannelManager<
             message_type: MessageType::KeyAgreementM1,
             message_body: ka_m1,
         };
+        channel.handshake.sent(&m, true);
         self.router_tx
             .send(Router(RouterCommand::SendMessage(m)))
-            .map_err(|e| Error::from(e).into())?;
+            .map_err(Error::from)?;
         Ok(Address::channel_address_from_string(&clear_address).unwrap())
     }
 
-    fn create_channel(&mut self, role: ExchangerRole) -> Option<(String, String)> {
-        let mut rng = thread_rng();
-        let clear_u32 = rng.gen::<u32>();
-        let cipher_u32 = rng.gen::<u32>();
-        let channel = match role {
-            ExchangerRole::Initiator => Arc::new(Mutex::new(Channel::new(
-                clear_u32,
-                cipher_u32,
-                Box::new(self.new_key_exchanger.initiator(self.init_key_ctx.clone())),
-            ))),
-            ExchangerRole::Responder => Arc::new(Mutex::new(Channel::new(
-                clear_u32,
-                cipher_u32,
-                Box::new(self.new_key_exchanger.responder(self.resp_key_ctx.clone())),
-            ))),
+    /// Channel addresses come from the vault so they use the same entropy source as the keys
+    fn create_channel(&mut self, role: ExchangerRole) -> OckamResult<(String, String)> {
+        let mut random = [0u8; 8];
+        self.vault.random_bytes_generate(&mut random)?;
+        let clear_u32 = u32::from_le_bytes([random[0], random[1], random[2], random[3]]);
+        let cipher_u32 = u32::from_le_bytes([random[4], random[5], random[6], random[7]]);
+        let agreement: Box<dyn KeyExchanger> = match role {
+            ExchangerRole::Initiator => {
+                Box::new(self.new_key_exchanger.initiator(self.init_key_ctx.clone()))
+            }
+            ExchangerRole::Responder => {
+                Box::new(self.new_key_exchanger.responder(self.resp_key_ctx.clone()))
+            }
         };
+        let channel = Rc::new(RefCell::new(Channel::new(
+            clear_u32, cipher_u32, role, agreement,
+        )));
         let clear_address = Address::ChannelAddress(clear_u32.to_le_bytes().to_vec());
         let cipher_address = Address::ChannelAddress(cipher_u32.to_le_bytes().to_vec());
         self.channels
             .insert(clear_address.as_string(), channel.clone());
         self.channels.insert(cipher_address.as_string(), channel);
-        Some((clear_address.as_string(), cipher_address.as_string()))
+        Ok((clear_address.as_string(), cipher_address.as_string()))
+    }
+}
+
+#[cfg(feature = "ockam-kex-x3dh")]
+impl ChannelManager<X3dhInitiator, X3dhResponder, X3dhNewKeyExchanger> {
+    /// Create a Channel Manager whose channels use X3DH with `cipher_suite`. Responders
+    /// hand out the prekeys of `prekey_store`, which also provides their identity key,
+    /// or create a store with a single one-time prekey for every channel if it is
+    /// `None`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_x3dh<V: ChannelVault + X3dhVault + 'static>(
+        rx: Receiver<OckamCommand>,
+        tx: Sender<OckamCommand>,
+        router_tx: Sender<OckamCommand>,
+        cipher_suite: CipherSuite,
+        vault: Arc<V>,
+        prekey_store: Option<Arc<PreKeyStore>>,
+        resp_key_ctx: Option<Arc<Box<dyn Secret>>>,
+        init_key_ctx: Option<Arc<Box<dyn Secret>>>,
+        trust_policy: Box<dyn TrustPolicy>,
+    ) -> OckamResult<Self> {
+        let mut new_key_exchanger =
+            X3dhNewKeyExchanger::new(cipher_suite, vault.clone(), vault.clone());
+        if let Some(prekey_store) = prekey_store {
+            new_key_exchanger.set_prekey_store(prekey_store);
+        }
+        Self::new(
+            rx,
+            tx,
+            router_tx,
+            vault,
+            new_key_exchanger,
+            resp_key_ctx,
+            init_key_ctx,
+            trust_policy,
+        )
     }
 }
 
 struct Channel {
-    completed_key_exchange: Option<CompletedKeyExchange>,
+    h: [u8; 32],
+    send: Option<CipherState>,
+    recv: Option<CipherState>,
     remote_public_key: Option<PublicKey>,
     cleartext_address: u32,
     ciphertext_address: u32,
+    role: ExchangerRole,
     agreement: Option<Box<dyn KeyExchanger>>,
-    nonce: u16,
     route: Route,
     pending: Option<Message>,
+    last_activity: Instant,
+    handshake: HandshakeState,
 }
 
 impl std::fmt::Debug for Channel {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            "Channel {{ completed_key_exchange: {:?}, id: {:?}, nonce: {:?}, agreement }}",
-            self.completed_key_exchange, self.cleartext_address, self.nonce
+            "Channel {{ id: {:?}, send: {:?}, recv: {:?}, agreement }}",
+            self.cleartext_address, self.send, self.recv
         )
     }
 }
@@ -569,17 +926,22 @@ impl Channel {
     pub fn new(
         cleartext_address: u32,
         ciphertext_address: u32,
+        role: ExchangerRole,
         agreement: Box<dyn KeyExchanger>,
     ) -> Self {
         Self {
             cleartext_address,
             ciphertext_address,
+            role,
             agreement: Some(agreement),
-            completed_key_exchange: None,
-            nonce: 0,
+            h: [0u8; 32],
+            send: None,
+            recv: None,
             route: Route { addresses: vec![] },
             pending: None,
             remote_public_key: None,
+            last_activity: Instant::now(),
+            handshake: HandshakeState::default(),
         }
     }
 
@@ -591,121 +953,987 @@ impl Channel {
         Address::ChannelAddress(self.ciphertext_address.to_le_bytes().to_vec())
     }
 
-    pub fn nonce_16_to_96(n16: u16) -> [u8; 12] {
-        // the nonce value is an le u16, whereas the nonce
-        // byte array is 10 bytes of 0's follow by the be
-        // representation of the nonce
-        let mut n: [u8; 12] = [0; 12];
-        let b = n16.to_be_bytes();
-        n[10] = b[0];
-        n[11] = b[1];
-        n
+    pub fn is_complete(&self) -> bool {
+        self.send.is_some()
     }
 
-    pub fn nonce_from_96(n: &[u8; 12]) -> u16 {
-        let bytes: [u8; 2] = [n[10], n[11]];
-        u16::from_be_bytes(bytes)
+    /// Take over the keys of a finished key exchange
+    pub fn complete(&mut self, completed_key_exchange: CompletedKeyExchange) {
+        self.h = completed_key_exchange.h;
+        self.send = Some(CipherState::new(completed_key_exchange.encrypt_key));
+        self.recv = Some(CipherState::new(completed_key_exchange.decrypt_key));
+        self.remote_public_key = Some(completed_key_exchange.remote_static_public_key);
+        self.last_activity = Instant::now();
     }
 }
 
+mod cipher_state;
+mod handshake;
+mod peers;
+pub use peers::ChannelPeers;
+mod replay_window;
+mod trust_policy;
+pub use trust_policy::*;
+
 /// Represents the errors that occur within a channel
 pub mod error;
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use ockam_kex::xx::{XXInitiator, XXNewKeyExchanger, XXResponder};
-//     use ockam_kex::CipherSuite;
-//     use ockam_message::message::AddressType;
-//     use ockam_vault::software::DefaultVault;
-//     use std::sync::mpsc::channel;
-//
-//     type XXInitiatorChannelManager = ChannelManager<XXInitiator, XXResponder, XXNewKeyExchanger>;
-//     type XXResponderChannelManager = ChannelManager<XXInitiator, XXResponder, XXNewKeyExchanger>;
-//
-//     #[test]
-//     fn new_channel_initiator() {
-//         let (tx_router, rx_router) = channel();
-//         let (tx_channel, rx_channel) = channel();
-//
-//         let new_key_exchanger = XXNewKeyExchanger::new(CipherSuite::Curve25519AesGcmSha256);
-//         let vault = Arc::new(Mutex::new(DefaultVault::default()));
-//
-//         let mut router = ockam_router::router::Router::new(rx_router);
-//         let mut channel = XXInitiatorChannelManager::new(
-//             tx_channel.clone(),
-//             rx_channel,
-//             tx_router.clone(),
-//             vault.clone(),
-//             new_key_exchanger,
-//         );
-//
-//         tx_router
-//             .send(OckamCommand::Router(RouterCommand::Register(
-//                 AddressType::Channel,
-//                 tx_channel.clone(),
-//             )))
-//             .unwrap();
-//
-//         let message = Message {
-//             onward_route: Route {
-//                 addresses:
-// vec![RouterAddress::channel_router_address_from_str("deadbeef").unwrap()],             },
-//             return_route: Route { addresses: vec![] },
-//             message_type: MessageType::Payload,
-//             message_body: b"Hello Bob".to_vec(),
-//         };
-//
-//         tx_router
-//             .send(OckamCommand::Router(RouterCommand::SendMessage(message)))
-//             .unwrap();
-//         assert!(router.poll());
-//         let res = channel.poll();
-//         assert!(res.is_ok());
-//         assert!(res.unwrap());
-//     }
-//
-//     #[test]
-//     fn new_channel_responder() {
-//         let (tx_router, rx_router) = channel();
-//         let (tx_channel, rx_channel) = channel();
-//
-//         let new_key_exchanger = XXNewKeyExchanger::new(CipherSuite::Curve25519AesGcmSha256);
-//         let vault = Arc::new(Mutex::new(DefaultVault::default()));
-//
-//         let mut router = ockam_router::router::Router::new(rx_router);
-//         let mut channel = XXResponderChannelManager::new(
-//             tx_channel.clone(),
-//             rx_channel,
-//             tx_router.clone(),
-//             vault.clone(),
-//             new_key_exchanger,
-//         );
-//
-//         tx_router
-//             .send(OckamCommand::Router(RouterCommand::Register(
-//                 AddressType::Channel,
-//                 tx_channel.clone(),
-//             )))
-//             .unwrap();
-//
-//         let message = Message {
-//             onward_route: Route {
-//                 addresses: vec![RouterAddress::channel_router_address_from_str("00").unwrap()],
-//             },
-//             return_route: Route { addresses: vec![] },
-//             message_type: MessageType::KeyAgreementM1,
-//             message_body: vec![
-//                 79, 30, 59, 197, 255, 25, 84, 22, 3, 63, 63, 45, 98, 206, 16, 137, 39, 108, 13,
-//                 171, 237, 191, 172, 115, 63, 124, 209, 114, 59, 97, 28, 82,
-//             ],
-//         };
-//
-//         tx_router
-//             .send(OckamCommand::Router(RouterCommand::SendMessage(message)))
-//             .unwrap();
-//         assert!(router.poll());
-//         let res = channel.poll();
-//         assert!(res.is_ok());
-//         assert!(res.unwrap());
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam_common::error::OckamResult;
+    use ockam_kex::CipherSuite;
+    use ockam_kex_ik::{IKInitiator, IKNewKeyExchanger, IKResponder};
+    use ockam_kex_x3dh::{PreKeyStore, X3dhInitiator, X3dhNewKeyExchanger, X3dhResponder};
+    use ockam_kex_xx::{XXInitiator, XXNewKeyExchanger, XXResponder};
+    use ockam_vault::types::{
+        SecretAttributes, SecretKey, SecretPersistence, SecretType, SecretUsage,
+    };
+    use ockam_vault::zeroize::Zeroize;
+    use ockam_vault::{AsymmetricVault, HashVault, RandomVault, SecretVault, SymmetricVault};
+    use ockam_vault_software::DefaultVault;
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::mpsc::channel;
+
+    const WORKER: &str = "aabbccdd";
+
+    /// A channel manager with a fake router, messages addressed to another node's
+    /// udp address are handed to that node as if they went through a transport
+    struct TestNode<I: KeyExchanger + 'static, R: KeyExchanger + 'static, E: NewKeyExchanger<I, R>> {
+        address: RouterAddress,
+        manager: ChannelManager<I, R, E>,
+        channel_tx: Sender<OckamCommand>,
+        router_rx: Receiver<OckamCommand>,
+        inbox: Vec<Message>,
+    }
+
+    impl<I: KeyExchanger, R: KeyExchanger, E: NewKeyExchanger<I, R>> TestNode<I, R, E> {
+        fn new(
+            address: &str,
+            new_key_exchanger: E,
+            vault: Arc<dyn ChannelVault>,
+            resp_key_ctx: Option<Arc<Box<dyn Secret>>>,
+            trust_policy: Box<dyn TrustPolicy>,
+        ) -> Self {
+            let (router_tx, router_rx) = channel();
+            let (channel_tx, channel_rx) = channel();
+            let manager = ChannelManager::new(
+                channel_rx,
+                channel_tx.clone(),
+                router_tx,
+                vault,
+                new_key_exchanger,
+                resp_key_ctx,
+                None,
+                trust_policy,
+            )
+            .unwrap();
+            Self {
+                address: RouterAddress::udp_router_address_from_str(address).unwrap(),
+                manager,
+                channel_tx,
+                router_rx,
+                inbox: vec![],
+            }
+        }
+
+        /// Poll the manager and collect what it asked the router to do, returns
+        /// messages that leave this node
+        fn step(&mut self) -> Vec<Message> {
+            self.manager.poll().unwrap();
+            let mut outgoing = vec![];
+            while let Ok(c) = self.router_rx.try_recv() {
+                match c {
+                    Router(RouterCommand::SendMessage(m))
+                    | Router(RouterCommand::ReceiveMessage(m)) => {
+                        match m.onward_route.addresses[0].a_type {
+                            AddressType::Udp => outgoing.push(m),
+                            AddressType::Channel => self
+                                .channel_tx
+                                .send(OckamCommand::Channel(ChannelCommand::ReceiveMessage(m)))
+                                .unwrap(),
+                            _ => self.inbox.push(m),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            outgoing
+        }
+
+        /// Hand a message that arrived from another node to the channel manager
+        fn deliver(&mut self, mut m: Message, from: &RouterAddress) {
+            m.onward_route.addresses.remove(0);
+            m.return_route.addresses.insert(0, from.clone());
+            self.channel_tx
+                .send(OckamCommand::Channel(ChannelCommand::ReceiveMessage(m)))
+                .unwrap();
+        }
+
+        /// Have the channel manager handle a message from another node right away,
+        /// `poll` only logs the errors
+        fn receive(&mut self, mut m: Message, from: &RouterAddress) -> OckamResult<()> {
+            m.onward_route.addresses.remove(0);
+            m.return_route.addresses.insert(0, from.clone());
+            self.manager.handle_recv(m)
+        }
+
+        fn send_from_worker(&mut self, onward_route: Route, body: &[u8]) {
+            let m = Message {
+                onward_route,
+                return_route: Route {
+                    addresses: vec![RouterAddress::worker_router_address_from_str(WORKER).unwrap()],
+                },
+                message_type: MessageType::Payload,
+                message_body: body.to_vec(),
+            };
+            self.channel_tx
+                .send(OckamCommand::Channel(ChannelCommand::SendMessage(m)))
+                .unwrap();
+        }
+    }
+
+    /// Run both nodes until neither has anything left to do
+    fn run<I1, R1, E1, I2, R2, E2>(a: &mut TestNode<I1, R1, E1>, b: &mut TestNode<I2, R2, E2>)
+    where
+        I1: KeyExchanger,
+        R1: KeyExchanger,
+        E1: NewKeyExchanger<I1, R1>,
+        I2: KeyExchanger,
+        R2: KeyExchanger,
+        E2: NewKeyExchanger<I2, R2>,
+    {
+        for _ in 0..10 {
+            for m in a.step() {
+                b.deliver(m, &a.address);
+            }
+            for m in b.step() {
+                a.deliver(m, &b.address);
+            }
+        }
+    }
+
+    /// Initiate a channel from `a` to `b` and return a's cleartext address and the
+    /// route to b's worker through the channel
+    fn establish<I1, R1, E1, I2, R2, E2>(
+        a: &mut TestNode<I1, R1, E1>,
+        b: &mut TestNode<I2, R2, E2>,
+    ) -> Route
+    where
+        I1: KeyExchanger,
+        R1: KeyExchanger,
+        E1: NewKeyExchanger<I1, R1>,
+        I2: KeyExchanger,
+        R2: KeyExchanger,
+        E2: NewKeyExchanger<I2, R2>,
+    {
+        let route = Route {
+            addresses: vec![
+                b.address.clone(),
+                RouterAddress::channel_router_address_from_str(CHANNEL_ZERO).unwrap(),
+            ],
+        };
+        a.channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Initiate(
+                route,
+                Address::worker_address_from_string(WORKER).unwrap(),
+                None,
+            )))
+            .unwrap();
+        run(a, b);
+
+        let notification = a.inbox.pop().expect("initiator was not notified");
+        assert!(matches!(notification.message_type, MessageType::None));
+        let responder_notification = b.inbox.pop().expect("responder was not notified");
+        assert!(matches!(
+            responder_notification.message_type,
+            MessageType::None
+        ));
+
+        Route {
+            addresses: vec![
+                notification.return_route.addresses[0].clone(),
+                RouterAddress::worker_router_address_from_str(WORKER).unwrap(),
+            ],
+        }
+    }
+
+    fn xx_node(address: &str) -> TestNode<XXInitiator, XXResponder, XXNewKeyExchanger> {
+        xx_node_with_policy(address, Box::new(AcceptAll))
+    }
+
+    fn xx_node_with_policy(
+        address: &str,
+        trust_policy: Box<dyn TrustPolicy>,
+    ) -> TestNode<XXInitiator, XXResponder, XXNewKeyExchanger> {
+        let vault = Arc::new(DefaultVault::default());
+        let new_key_exchanger = XXNewKeyExchanger::new(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault.clone(),
+            vault.clone(),
+        );
+        TestNode::new(address, new_key_exchanger, vault, None, trust_policy)
+    }
+
+    fn xx_node_with_suite(
+        address: &str,
+        cipher_suite: CipherSuite,
+    ) -> TestNode<XXInitiator, XXResponder, XXNewKeyExchanger> {
+        let vault = Arc::new(DefaultVault::default());
+        let new_key_exchanger = XXNewKeyExchanger::new(cipher_suite, vault.clone(), vault.clone());
+        TestNode::new(address, new_key_exchanger, vault, None, Box::new(AcceptAll))
+    }
+
+    /// A software vault whose random bytes count up from 1, so channel addresses are
+    /// predictable
+    #[derive(Default)]
+    struct DeterministicVault {
+        vault: DefaultVault,
+        counter: AtomicU8,
+    }
+
+    impl Zeroize for DeterministicVault {
+        fn zeroize(&mut self) {
+            self.vault.zeroize()
+        }
+    }
+
+    impl RandomVault for DeterministicVault {
+        fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+            for b in buffer.iter_mut() {
+                *b = self.counter.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+            }
+            Ok(())
+        }
+    }
+
+    /// Implement vault traits for `DeterministicVault` by forwarding to its software vault
+    macro_rules! forward_to_vault {
+        ($($trait:ident { $(fn $name:ident(&self $(, $arg:ident: $ty:ty)*) -> $ret:ty;)* })*) => {
+            $(
+                impl $trait for DeterministicVault {
+                    $(
+                        fn $name(&self $(, $arg: $ty)*) -> $ret {
+                            self.vault.$name($($arg),*)
+                        }
+                    )*
+                }
+            )*
+        };
+    }
+
+    forward_to_vault! {
+        SecretVault {
+            fn secret_generate(&self, attributes: SecretAttributes)
+                -> OckamResult<Box<dyn Secret>>;
+            fn secret_import(&self, secret: &[u8], attributes: SecretAttributes)
+                -> OckamResult<Box<dyn Secret>>;
+            fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey>;
+            fn secret_attributes_get(&self, context: &dyn Secret)
+                -> OckamResult<SecretAttributes>;
+            fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey>;
+            fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()>;
+        }
+        HashVault {
+            fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]>;
+            fn hkdf_sha256(
+                &self,
+                salt: &dyn Secret,
+                info: &[u8],
+                ikm: Option<&dyn Secret>,
+                output_attributes: Vec<SecretAttributes>
+            ) -> OckamResult<Vec<Box<dyn Secret>>>;
+        }
+        AsymmetricVault {
+            fn ec_diffie_hellman(&self, context: &dyn Secret, peer_public_key: &[u8])
+                -> OckamResult<Box<dyn Secret>>;
+        }
+        SymmetricVault {
+            fn aead_aes_gcm_encrypt(
+                &self,
+                context: &dyn Secret,
+                plaintext: &[u8],
+                nonce: &[u8],
+                aad: &[u8]
+            ) -> OckamResult<Vec<u8>>;
+            fn aead_aes_gcm_decrypt(
+                &self,
+                context: &dyn Secret,
+                cipher_text: &[u8],
+                nonce: &[u8],
+                aad: &[u8]
+            ) -> OckamResult<Vec<u8>>;
+            fn aead_chacha20_poly1305_encrypt(
+                &self,
+                context: &dyn Secret,
+                plaintext: &[u8],
+                nonce: &[u8],
+                aad: &[u8]
+            ) -> OckamResult<Vec<u8>>;
+            fn aead_chacha20_poly1305_decrypt(
+                &self,
+                context: &dyn Secret,
+                cipher_text: &[u8],
+                nonce: &[u8],
+                aad: &[u8]
+            ) -> OckamResult<Vec<u8>>;
+        }
+    }
+
+    fn initiate<I1, R1, E1, I2, R2, E2>(a: &mut TestNode<I1, R1, E1>, b: &TestNode<I2, R2, E2>)
+    where
+        I1: KeyExchanger,
+        R1: KeyExchanger,
+        E1: NewKeyExchanger<I1, R1>,
+        I2: KeyExchanger,
+        R2: KeyExchanger,
+        E2: NewKeyExchanger<I2, R2>,
+    {
+        let route = Route {
+            addresses: vec![
+                b.address.clone(),
+                RouterAddress::channel_router_address_from_str(CHANNEL_ZERO).unwrap(),
+            ],
+        };
+        a.channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Initiate(
+                route,
+                Address::worker_address_from_string(WORKER).unwrap(),
+                None,
+            )))
+            .unwrap();
+    }
+
+    #[test]
+    fn initiator_rejects_untrusted_responder() {
+        let mut alice = xx_node_with_policy(
+            "127.0.0.1:4050",
+            Box::new(AllowList::new(vec![PublicKey::new(vec![7u8; 32])])),
+        );
+        let mut bob = xx_node("127.0.0.1:4051");
+        initiate(&mut alice, &bob);
+        let m1 = alice.step();
+        bob.deliver(m1[0].clone(), &alice.address);
+        let m2 = bob.step();
+        let error = alice.receive(m2[0].clone(), &bob.address).unwrap_err();
+        assert_eq!(error.domain(), Error::ERROR_DOMAIN);
+        assert_eq!(error.code(), Error::UntrustedPeer as u32);
+        // no M3 went out, the channel is gone and the worker knows
+        assert!(alice.step().is_empty());
+        assert!(alice.manager.channels.is_empty());
+        let m = alice.inbox.pop().unwrap();
+        assert!(matches!(m.message_type, MessageType::KeyAgreementFailed));
+        assert_eq!(
+            m.onward_route.addresses[0],
+            RouterAddress::worker_router_address_from_str(WORKER).unwrap()
+        );
+        assert!(alice.inbox.is_empty());
+    }
+
+    #[test]
+    fn responder_rejects_untrusted_initiator() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node_with_policy("127.0.0.1:4051", Box::new(AllowList::default()));
+        initiate(&mut alice, &bob);
+        let m1 = alice.step();
+        bob.deliver(m1[0].clone(), &alice.address);
+        let m2 = bob.step();
+        alice.deliver(m2[0].clone(), &bob.address);
+        let m3 = alice.step();
+        let error = bob.receive(m3[0].clone(), &alice.address).unwrap_err();
+        assert_eq!(error.domain(), Error::ERROR_DOMAIN);
+        assert_eq!(error.code(), Error::UntrustedPeer as u32);
+        assert!(bob.step().is_empty());
+        assert!(bob.inbox.is_empty());
+        assert!(bob.manager.channels.is_empty());
+    }
+
+    #[test]
+    fn xx_channel_carries_payloads() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+
+        for i in 0..3u8 {
+            alice.send_from_worker(route.clone(), &[i; 4]);
+            run(&mut alice, &mut bob);
+            let m = bob.inbox.pop().unwrap();
+            assert_eq!(m.message_body, vec![i; 4]);
+        }
+    }
+
+    #[test]
+    fn xx_chachapoly_channel_carries_payloads() {
+        let mut alice =
+            xx_node_with_suite("127.0.0.1:4050", CipherSuite::Curve25519ChaChaPolySha256);
+        let mut bob = xx_node_with_suite("127.0.0.1:4051", CipherSuite::Curve25519ChaChaPolySha256);
+        let route = establish(&mut alice, &mut bob);
+
+        for i in 0..3u8 {
+            alice.send_from_worker(route.clone(), &[i; 4]);
+            run(&mut alice, &mut bob);
+            let m = bob.inbox.pop().unwrap();
+            assert_eq!(m.message_body, vec![i; 4]);
+        }
+    }
+
+    #[test]
+    fn channel_addresses_come_from_the_vault() {
+        let vault = Arc::new(DeterministicVault::default());
+        let new_key_exchanger = XXNewKeyExchanger::new(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault.clone(),
+            vault.clone(),
+        );
+        let mut alice: TestNode<XXInitiator, XXResponder, XXNewKeyExchanger> = TestNode::new(
+            "127.0.0.1:4050",
+            new_key_exchanger,
+            vault,
+            None,
+            Box::new(AcceptAll),
+        );
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+
+        let clear = Address::ChannelAddress(vec![1, 2, 3, 4]).as_string();
+        let cipher = Address::ChannelAddress(vec![5, 6, 7, 8]).as_string();
+        assert!(alice.manager.channels.contains_key(&clear));
+        assert!(alice.manager.channels.contains_key(&cipher));
+
+        alice.send_from_worker(route, b"hello");
+        run(&mut alice, &mut bob);
+        assert_eq!(bob.inbox.pop().unwrap().message_body, b"hello");
+    }
+
+    #[test]
+    fn handshakes_with_a_deterministic_vault_repeat() {
+        let first_message = || {
+            let vault = Arc::new(DeterministicVault::default());
+            let new_key_exchanger = XXNewKeyExchanger::new(
+                CipherSuite::Curve25519AesGcmSha256,
+                vault.clone(),
+                vault.clone(),
+            );
+            let mut alice: TestNode<XXInitiator, XXResponder, XXNewKeyExchanger> = TestNode::new(
+                "127.0.0.1:4050",
+                new_key_exchanger,
+                vault,
+                None,
+                Box::new(AcceptAll),
+            );
+            let bob = xx_node("127.0.0.1:4051");
+            initiate(&mut alice, &bob);
+            alice.step().remove(0).message_body
+        };
+        // the ephemeral key in M1 comes from the vault's random bytes as well
+        assert_eq!(first_message(), first_message());
+    }
+
+    #[test]
+    fn close_notifies_peer() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+        let peers = alice.manager.channel_peers();
+        assert!(peers.peer(&route.addresses[0].address).is_some());
+
+        alice
+            .channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Close(
+                route.addresses[0].address.clone(),
+            )))
+            .unwrap();
+        run(&mut alice, &mut bob);
+
+        assert!(alice.manager.channels.is_empty());
+        assert!(alice.inbox.is_empty());
+        assert!(peers.peer(&route.addresses[0].address).is_none());
+        assert!(bob.manager.channels.is_empty());
+        let m = bob.inbox.pop().unwrap();
+        assert!(matches!(m.message_type, MessageType::ChannelClose));
+
+        // closing it again does nothing
+        alice
+            .channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Close(
+                route.addresses[0].address.clone(),
+            )))
+            .unwrap();
+        run(&mut alice, &mut bob);
+        assert!(alice.inbox.is_empty() && bob.inbox.is_empty());
+    }
+
+    #[test]
+    fn idle_channels_are_closed() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+        alice.send_from_worker(route.clone(), b"ping");
+        run(&mut alice, &mut bob);
+        assert_eq!(bob.inbox.pop().unwrap().message_body, b"ping");
+
+        alice
+            .manager
+            .set_idle_timeout(Some(Duration::from_millis(0)));
+        run(&mut alice, &mut bob);
+
+        assert!(alice.manager.channels.is_empty());
+        assert!(bob.manager.channels.is_empty());
+        let m = alice.inbox.pop().unwrap();
+        assert!(matches!(m.message_type, MessageType::ChannelClose));
+        assert_eq!(m.return_route.addresses[0], route.addresses[0]);
+        let m = bob.inbox.pop().unwrap();
+        assert!(matches!(m.message_type, MessageType::ChannelClose));
+    }
+
+    #[test]
+    fn exhausted_idle_channels_are_closed() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+        let channel = alice.manager.unique_channels().remove(0);
+        channel.borrow_mut().send.as_mut().unwrap().exhaust();
+
+        // the close can't be encrypted, the channel goes away anyway
+        alice
+            .manager
+            .set_idle_timeout(Some(Duration::from_millis(0)));
+        run(&mut alice, &mut bob);
+
+        assert!(alice.manager.channels.is_empty());
+        let m = alice.inbox.pop().unwrap();
+        assert!(matches!(m.message_type, MessageType::ChannelClose));
+        assert_eq!(m.return_route.addresses[0], route.addresses[0]);
+        assert!(bob.inbox.is_empty());
+        assert_eq!(bob.manager.channels.len(), 2);
+    }
+
+    #[test]
+    fn unknown_channel_is_reported() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+
+        // bob forgets the channel without telling alice
+        bob.channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Stop))
+            .unwrap();
+        bob.step();
+        assert!(bob.manager.channels.is_empty());
+
+        alice.send_from_worker(route.clone(), b"anyone there?");
+        run(&mut alice, &mut bob);
+        assert!(bob.inbox.is_empty());
+        let m = alice.inbox.pop().unwrap();
+        assert!(matches!(m.message_type, MessageType::NoSuchChannel));
+        assert_eq!(m.return_route.addresses[0], route.addresses[0]);
+    }
+
+    #[test]
+    fn close_must_authenticate() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+
+        // a payload relabeled as a close decrypts, but isn't empty
+        alice.send_from_worker(route.clone(), b"not a close");
+        let mut m = alice.step().pop().unwrap();
+        m.message_type = MessageType::ChannelClose;
+        let error = bob.receive(m.clone(), &alice.address).unwrap_err();
+        assert_eq!(error.code(), Error::InvalidCloseMessage as u32);
+
+        m.message_body[cipher_state::PAYLOAD_HEADER_SIZE] ^= 1;
+        assert!(bob.receive(m, &alice.address).is_err());
+        assert_eq!(bob.manager.channels.len(), 2);
+    }
+
+    #[test]
+    fn bad_message_does_not_stop_the_queue() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+
+        alice.send_from_worker(route.clone(), b"tampered");
+        alice.send_from_worker(route, b"intact");
+        let mut sent = alice.step();
+        sent[0].message_body[cipher_state::PAYLOAD_HEADER_SIZE] ^= 1;
+        for m in sent {
+            bob.deliver(m, &alice.address);
+        }
+        assert!(bob.step().is_empty());
+        assert_eq!(bob.inbox.pop().unwrap().message_body, b"intact");
+        assert!(bob.inbox.is_empty());
+    }
+
+    fn short_handshake_policy(max_retransmits: u32) -> HandshakePolicy {
+        HandshakePolicy {
+            timeout: Duration::from_millis(20),
+            max_retransmits,
+        }
+    }
+
+    fn wait_for_timeout() {
+        std::thread::sleep(Duration::from_millis(30));
+    }
+
+    #[test]
+    fn lost_m1_is_retransmitted() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        alice
+            .manager
+            .set_handshake_policy(short_handshake_policy(3));
+        initiate(&mut alice, &bob);
+        let lost = alice.step();
+        assert_eq!(lost.len(), 1);
+
+        wait_for_timeout();
+        let m1 = alice.step();
+        assert_eq!(m1.len(), 1);
+        assert_eq!(m1[0].message_body, lost[0].message_body);
+        bob.deliver(m1[0].clone(), &alice.address);
+        run(&mut alice, &mut bob);
+        assert!(matches!(
+            alice.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+        assert!(matches!(
+            bob.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+    }
+
+    #[test]
+    fn duplicate_m1_repeats_m2() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        initiate(&mut alice, &bob);
+        let m1 = alice.step();
+        bob.deliver(m1[0].clone(), &alice.address);
+        let lost = bob.step();
+        assert_eq!(bob.manager.channels.len(), 2);
+
+        bob.deliver(m1[0].clone(), &alice.address);
+        let m2 = bob.step();
+        assert_eq!(m2.len(), 1);
+        assert_eq!(m2[0].message_body, lost[0].message_body);
+        assert_eq!(bob.manager.channels.len(), 2);
+
+        alice.deliver(m2[0].clone(), &bob.address);
+        run(&mut alice, &mut bob);
+        assert!(matches!(
+            alice.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+        assert!(matches!(
+            bob.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+    }
+
+    #[test]
+    fn garbage_m1_leaves_no_channel() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        initiate(&mut alice, &bob);
+        let m1 = alice.step().pop().unwrap();
+
+        for body in [vec![], vec![1u8; 5], vec![2u8; 31]] {
+            let mut garbage = m1.clone();
+            garbage.message_body = body;
+            assert!(bob.receive(garbage, &alice.address).is_err());
+        }
+        let mut garbage = m1.clone();
+        garbage.message_type = MessageType::Payload;
+        assert!(bob.receive(garbage, &alice.address).is_err());
+        assert!(bob.manager.channels.is_empty());
+
+        bob.deliver(m1, &alice.address);
+        run(&mut alice, &mut bob);
+        assert_eq!(bob.manager.channels.len(), 2);
+    }
+
+    #[test]
+    fn lost_m3_is_recovered() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let mut bob = xx_node("127.0.0.1:4051");
+        bob.manager.set_handshake_policy(short_handshake_policy(3));
+        initiate(&mut alice, &bob);
+        let m1 = alice.step();
+        bob.deliver(m1[0].clone(), &alice.address);
+        let m2 = bob.step();
+        alice.deliver(m2[0].clone(), &bob.address);
+        let lost = alice.step();
+        assert!(matches!(lost[0].message_type, MessageType::KeyAgreementM3));
+        let notification = alice.inbox.pop().unwrap();
+
+        // bob repeats M2, alice answers with the same M3
+        wait_for_timeout();
+        let m2 = bob.step();
+        assert!(matches!(m2[0].message_type, MessageType::KeyAgreementM2));
+        alice.deliver(m2[0].clone(), &bob.address);
+        let m3 = alice.step();
+        assert_eq!(m3[0].message_body, lost[0].message_body);
+        bob.deliver(m3[0].clone(), &alice.address);
+        bob.step();
+        assert!(matches!(
+            bob.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+
+        // and a late duplicate M3 is ignored
+        bob.deliver(m3[0].clone(), &alice.address);
+        assert!(bob.step().is_empty());
+        assert!(bob.inbox.is_empty());
+
+        let route = Route {
+            addresses: vec![
+                notification.return_route.addresses[0].clone(),
+                RouterAddress::worker_router_address_from_str(WORKER).unwrap(),
+            ],
+        };
+        alice.send_from_worker(route, b"made it");
+        run(&mut alice, &mut bob);
+        assert_eq!(bob.inbox.pop().unwrap().message_body, b"made it");
+    }
+
+    #[test]
+    fn initiator_is_told_when_handshake_fails() {
+        let mut alice = xx_node("127.0.0.1:4050");
+        let bob = xx_node("127.0.0.1:4051");
+        alice
+            .manager
+            .set_handshake_policy(short_handshake_policy(1));
+        initiate(&mut alice, &bob);
+        assert_eq!(alice.step().len(), 1);
+        wait_for_timeout();
+        assert_eq!(alice.step().len(), 1);
+        wait_for_timeout();
+        assert!(alice.step().is_empty());
+
+        assert!(alice.manager.channels.is_empty());
+        let m = alice.inbox.pop().unwrap();
+        assert!(matches!(m.message_type, MessageType::KeyAgreementFailed));
+        assert_eq!(
+            m.onward_route.addresses[0],
+            RouterAddress::worker_router_address_from_str(WORKER).unwrap()
+        );
+    }
+
+    #[test]
+    fn ik_channel_completes_in_one_round_trip() {
+        let vault_alice = Arc::new(DefaultVault::default());
+        let vault_bob = Arc::new(DefaultVault::default());
+        let (bob_identity, bob_public_key) = {
+            let vault = &vault_bob;
+            let identity = vault
+                .secret_generate(SecretAttributes {
+                    stype: SecretType::Curve25519,
+                    persistence: SecretPersistence::Ephemeral,
+                    length: 32,
+                    exportable: false,
+                    usage: SecretUsage::ECDH,
+                })
+                .unwrap();
+            let public_key = vault.secret_public_key_get(identity.as_ref()).unwrap();
+            (identity, public_key)
+        };
+
+        let mut alice = TestNode::new(
+            "127.0.0.1:4050",
+            IKNewKeyExchanger::new(
+                CipherSuite::Curve25519AesGcmSha256,
+                Some(bob_public_key.clone()),
+                vault_alice.clone(),
+                vault_alice.clone(),
+            ),
+            vault_alice,
+            None,
+            Box::new(AllowList::new(vec![bob_public_key.clone()])),
+        );
+        let mut bob: TestNode<IKInitiator, IKResponder, IKNewKeyExchanger> = TestNode::new(
+            "127.0.0.1:4051",
+            IKNewKeyExchanger::new(
+                CipherSuite::Curve25519AesGcmSha256,
+                None,
+                vault_bob.clone(),
+                vault_bob.clone(),
+            ),
+            vault_bob,
+            Some(Arc::new(bob_identity)),
+            Box::new(AcceptAll),
+        );
+
+        // M1 out, M2 back, no M3
+        let route = Route {
+            addresses: vec![
+                bob.address.clone(),
+                RouterAddress::channel_router_address_from_str(CHANNEL_ZERO).unwrap(),
+            ],
+        };
+        alice
+            .channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Initiate(
+                route,
+                Address::worker_address_from_string(WORKER).unwrap(),
+                None,
+            )))
+            .unwrap();
+        let m1 = alice.step();
+        assert_eq!(m1.len(), 1);
+        bob.deliver(m1[0].clone(), &alice.address);
+        let m2 = bob.step();
+        assert_eq!(m2.len(), 1);
+        assert!(matches!(m2[0].message_type, MessageType::KeyAgreementM2));
+        assert_eq!(bob.inbox.len(), 1);
+        alice.deliver(m2[0].clone(), &bob.address);
+        assert!(alice.step().is_empty());
+
+        let notification = alice.inbox.pop().unwrap();
+        assert!(notification.message_body.ends_with(bob_public_key.as_ref()));
+        let route = Route {
+            addresses: vec![
+                notification.return_route.addresses[0].clone(),
+                RouterAddress::worker_router_address_from_str(WORKER).unwrap(),
+            ],
+        };
+        alice.send_from_worker(route, b"hello bob");
+        run(&mut alice, &mut bob);
+        assert_eq!(bob.inbox.pop().unwrap().message_body, b"hello bob");
+    }
+
+    fn x3dh_node(address: &str) -> TestNode<X3dhInitiator, X3dhResponder, X3dhNewKeyExchanger> {
+        x3dh_node_with_suite(address, CipherSuite::Curve25519AesGcmSha256)
+    }
+
+    fn x3dh_node_with_suite(
+        address: &str,
+        cipher_suite: CipherSuite,
+    ) -> TestNode<X3dhInitiator, X3dhResponder, X3dhNewKeyExchanger> {
+        let vault = Arc::new(DefaultVault::default());
+        let new_key_exchanger =
+            X3dhNewKeyExchanger::new(cipher_suite, vault.clone(), vault.clone());
+        TestNode::new(address, new_key_exchanger, vault, None, Box::new(AcceptAll))
+    }
+
+    #[test]
+    fn x3dh_channel_carries_payloads() {
+        let mut alice = x3dh_node("127.0.0.1:4050");
+        let mut bob = x3dh_node("127.0.0.1:4051");
+        let route = establish(&mut alice, &mut bob);
+
+        for i in 0..3u8 {
+            alice.send_from_worker(route.clone(), &[i; 4]);
+            run(&mut alice, &mut bob);
+            let m = bob.inbox.pop().unwrap();
+            assert_eq!(m.message_body, vec![i; 4]);
+
+            let reply_route = m.return_route;
+            bob.send_from_worker(reply_route, &[i; 8]);
+            run(&mut alice, &mut bob);
+            assert_eq!(alice.inbox.pop().unwrap().message_body, vec![i; 8]);
+        }
+    }
+
+    #[test]
+    fn x3dh_p256_channel_carries_payloads() {
+        let mut alice = x3dh_node_with_suite("127.0.0.1:4050", CipherSuite::P256Aes128GcmSha256);
+        let mut bob = x3dh_node_with_suite("127.0.0.1:4051", CipherSuite::P256Aes128GcmSha256);
+        let route = establish(&mut alice, &mut bob);
+
+        for i in 0..3u8 {
+            alice.send_from_worker(route.clone(), &[i; 4]);
+            run(&mut alice, &mut bob);
+            let m = bob.inbox.pop().unwrap();
+            assert_eq!(m.message_body, vec![i; 4]);
+        }
+    }
+
+    #[test]
+    fn x3dh_channel_uses_the_prekey_store() {
+        let vault = Arc::new(DefaultVault::default());
+        let store = Arc::new(
+            PreKeyStore::new(CipherSuite::Curve25519AesGcmSha256, vault.clone(), None).unwrap(),
+        );
+        store.publish_one_time_prekeys(1).unwrap();
+        let identity_key = vault
+            .secret_public_key_get(store.identity_key().as_ref().as_ref())
+            .unwrap();
+        let mut alice = x3dh_node("127.0.0.1:4050");
+        let mut new_key_exchanger = X3dhNewKeyExchanger::new(
+            CipherSuite::Curve25519AesGcmSha256,
+            vault.clone(),
+            vault.clone(),
+        );
+        new_key_exchanger.set_prekey_store(store.clone());
+        let mut bob: TestNode<X3dhInitiator, X3dhResponder, X3dhNewKeyExchanger> = TestNode::new(
+            "127.0.0.1:4051",
+            new_key_exchanger,
+            vault,
+            None,
+            Box::new(AcceptAll),
+        );
+
+        // three messages, the bundle goes out as M2 and the enrollment as M3
+        initiate(&mut alice, &bob);
+        let m1 = alice.step();
+        bob.deliver(m1[0].clone(), &alice.address);
+        let m2 = bob.step();
+        assert!(matches!(m2[0].message_type, MessageType::KeyAgreementM2));
+        alice.deliver(m2[0].clone(), &bob.address);
+        let m3 = alice.step();
+        assert!(matches!(m3[0].message_type, MessageType::KeyAgreementM3));
+        bob.deliver(m3[0].clone(), &alice.address);
+        assert!(bob.step().is_empty());
+        assert_eq!(store.available_one_time_prekeys(), 0);
+
+        // the initiator learns the responder's cleartext address and identity key
+        let notification = alice.inbox.pop().unwrap();
+        assert!(notification.message_body.ends_with(identity_key.as_ref()));
+        let (address, _) = RouterAddress::decode(&notification.message_body).unwrap();
+        assert!(matches!(address.a_type, AddressType::Channel));
+        assert!(matches!(
+            bob.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+
+        // without one-time prekeys the signed prekey alone is used
+        let route = establish(&mut alice, &mut bob);
+        alice.send_from_worker(route, b"hello bob");
+        run(&mut alice, &mut bob);
+        assert_eq!(bob.inbox.pop().unwrap().message_body, b"hello bob");
+    }
+
+    #[test]
+    fn lost_x3dh_bundle_is_retransmitted() {
+        let mut alice = x3dh_node("127.0.0.1:4050");
+        let mut bob = x3dh_node("127.0.0.1:4051");
+        alice
+            .manager
+            .set_handshake_policy(short_handshake_policy(3));
+
+        initiate(&mut alice, &bob);
+        let m1 = alice.step();
+        bob.deliver(m1[0].clone(), &alice.address);
+        // the bundle gets lost, the repeated M1 brings it back
+        assert_eq!(bob.step().len(), 1);
+        wait_for_timeout();
+        let m1 = alice.step();
+        assert_eq!(m1.len(), 1);
+        bob.deliver(m1[0].clone(), &alice.address);
+        let m2 = bob.step();
+        assert_eq!(m2.len(), 1);
+        assert!(matches!(m2[0].message_type, MessageType::KeyAgreementM2));
+        alice.deliver(m2[0].clone(), &bob.address);
+        run(&mut alice, &mut bob);
+
+        assert_eq!(bob.manager.unique_channels().len(), 1);
+        assert!(matches!(
+            alice.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+        assert!(matches!(
+            bob.inbox.pop().unwrap().message_type,
+            MessageType::None
+        ));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::message::Address;
+use ockam_vault::types::PublicKey;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// The static public keys of the peers of a channel manager's established channels,
+/// by the cleartext address of each channel.
+///
+/// The channel manager adds a channel once its peer is trusted and removes it when the
+/// channel closes. Workers on the same node use it to tell who sent a message that
+/// arrived through a channel, which unlike a notification message can't be forged by
+/// a remote node.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelPeers {
+    peers: Arc<RwLock<BTreeMap<String, PublicKey>>>,
+}
+
+impl ChannelPeers {
+    /// The static public key of the peer at the other end of the channel with the
+    /// cleartext `address`, `None` if there is no such channel or it was closed
+    pub fn peer(&self, address: &Address) -> Option<PublicKey> {
+        self.peers
+            .read()
+            .unwrap()
+            .get(&address.as_string())
+            .cloned()
+    }
+
+    pub(crate) fn insert(&self, address: &Address, peer: PublicKey) {
+        self.peers
+            .write()
+            .unwrap()
+            .insert(address.as_string(), peer);
+    }
+
+    pub(crate) fn remove(&self, address: &Address) {
+        self.peers.write().unwrap().remove(&address.as_string());
+    }
+}
@@ -0,0 +1,111 @@
+/// Number of nonces below the highest accepted nonce that are still tracked
+pub const REPLAY_WINDOW_SIZE: u64 = 64;
+
+/// Sliding window replay filter in the style of IPsec/DTLS.
+///
+/// Tracks the highest nonce accepted so far and a bitmap of which of the
+/// `REPLAY_WINDOW_SIZE` nonces below it were seen. Nonces ahead of the window
+/// are accepted and slide it forward, nonces inside it are accepted once and
+/// nonces behind it are rejected.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ReplayWindow {
+    highest: u64,
+    /// Bit `i` is set when nonce `highest - i` has been accepted
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    /// Returns true if `nonce` may be accepted. Does not change the window, call
+    /// `mark` once the payload has been authenticated.
+    pub fn check(&self, nonce: u64) -> bool {
+        if self.bitmap == 0 || nonce > self.highest {
+            return true;
+        }
+        let offset = self.highest - nonce;
+        if offset >= REPLAY_WINDOW_SIZE {
+            return false;
+        }
+        self.bitmap & (1 << offset) == 0
+    }
+
+    /// Record `nonce` as accepted
+    pub fn mark(&mut self, nonce: u64) {
+        if self.bitmap == 0 {
+            self.highest = nonce;
+            self.bitmap = 1;
+        } else if nonce > self.highest {
+            let shift = nonce - self.highest;
+            self.bitmap = if shift >= REPLAY_WINDOW_SIZE {
+                1
+            } else {
+                (self.bitmap << shift) | 1
+            };
+            self.highest = nonce;
+        } else {
+            let offset = self.highest - nonce;
+            if offset < REPLAY_WINDOW_SIZE {
+                self.bitmap |= 1 << offset;
+            }
+        }
+    }
+
+    /// The highest nonce accepted so far
+    pub fn highest(&self) -> u64 {
+        self.highest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(window: &mut ReplayWindow, nonce: u64) -> bool {
+        if window.check(nonce) {
+            window.mark(nonce);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let mut window = ReplayWindow::default();
+        assert!(accept(&mut window, 0));
+        assert!(!accept(&mut window, 0));
+        assert!(accept(&mut window, 1));
+        assert!(!accept(&mut window, 1));
+        assert!(!accept(&mut window, 0));
+    }
+
+    #[test]
+    fn tolerates_reordering_within_window() {
+        let mut window = ReplayWindow::default();
+        assert!(accept(&mut window, 10));
+        assert!(accept(&mut window, 7));
+        assert!(accept(&mut window, 9));
+        assert!(!accept(&mut window, 7));
+        assert!(accept(&mut window, 8));
+        assert!(accept(&mut window, 10 + REPLAY_WINDOW_SIZE - 1));
+        assert!(accept(&mut window, 11));
+        assert!(!accept(&mut window, 10));
+        assert_eq!(window.highest(), 10 + REPLAY_WINDOW_SIZE - 1);
+    }
+
+    #[test]
+    fn rejects_nonces_behind_window() {
+        let mut window = ReplayWindow::default();
+        assert!(accept(&mut window, 1000));
+        assert!(!accept(&mut window, 1000 - REPLAY_WINDOW_SIZE));
+        assert!(accept(&mut window, 1000 - REPLAY_WINDOW_SIZE + 1));
+        assert!(accept(&mut window, 5000));
+        assert!(!accept(&mut window, 1001));
+    }
+
+    #[test]
+    fn check_does_not_mark() {
+        let window = ReplayWindow::default();
+        assert!(window.check(3));
+        assert!(window.check(3));
+    }
+}
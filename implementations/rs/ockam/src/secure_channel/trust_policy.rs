@@ -0,0 +1,198 @@
+use crate::message::Route;
+use crate::secure_channel::error::Error;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::PublicKey;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Decides whether a peer that finished a key exchange may use the channel.
+///
+/// The channel manager consults the policy with the peer's static public key
+/// before the channel is handed to any worker. Rejecting the key aborts the
+/// handshake and destroys the channel's keys.
+pub trait TrustPolicy: Send {
+    /// Return `Ok(true)` if the peer reached over `route` may authenticate with
+    /// `remote_static_public_key`
+    fn check(&mut self, route: &Route, remote_static_public_key: &PublicKey) -> OckamResult<bool>;
+}
+
+/// Accepts every peer, this is what channels did before trust policies existed
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AcceptAll;
+
+impl TrustPolicy for AcceptAll {
+    fn check(
+        &mut self,
+        _route: &Route,
+        _remote_static_public_key: &PublicKey,
+    ) -> OckamResult<bool> {
+        Ok(true)
+    }
+}
+
+/// Accepts only peers whose static public key is in a fixed list
+#[derive(Clone, Debug, Default)]
+pub struct AllowList {
+    keys: Vec<PublicKey>,
+}
+
+impl AllowList {
+    /// Create a policy that accepts any of `keys`
+    pub fn new(keys: Vec<PublicKey>) -> Self {
+        Self { keys }
+    }
+
+    /// Add a key to the list
+    pub fn allow(&mut self, key: PublicKey) {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+    }
+}
+
+impl TrustPolicy for AllowList {
+    fn check(&mut self, _route: &Route, remote_static_public_key: &PublicKey) -> OckamResult<bool> {
+        Ok(self.keys.contains(remote_static_public_key))
+    }
+}
+
+/// Trust on first use.
+///
+/// The first key a named peer presents is remembered in a known-keys file and every
+/// later handshake must present the same key. The file holds one
+/// `<peer> <hex public key>` entry per line and can be shared by the policies of
+/// several peers.
+///
+/// The name is given by the caller, the route a handshake arrives over can't
+/// identify a peer since anyone can claim any return route. A policy therefore pins
+/// a single key: used by a responder it accepts only the first initiator that ever
+/// connected, so give it to initiators, or to responders that serve exactly one peer.
+#[derive(Debug)]
+pub struct TrustOnFirstUse {
+    path: PathBuf,
+    peer: String,
+    known_keys: BTreeMap<String, Vec<u8>>,
+}
+
+impl TrustOnFirstUse {
+    /// Load the known keys from `path` and pin the key of `peer`, which can't be
+    /// empty or contain whitespace. The file is created on first use if it doesn't
+    /// exist yet.
+    pub fn new<P: AsRef<Path>>(path: P, peer: &str) -> OckamResult<Self> {
+        if peer.is_empty() || peer.chars().any(char::is_whitespace) {
+            return Err(Error::InvalidParam.into());
+        }
+        let path = path.as_ref().to_path_buf();
+        let mut known_keys = BTreeMap::new();
+        if path.exists() {
+            let file = File::open(&path).map_err(|_| Error::TrustStoreError)?;
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|_| Error::TrustStoreError)?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next().map(hex::decode), parts.next()) {
+                    (Some(peer), Some(Ok(key)), None) => {
+                        known_keys.insert(peer.to_string(), key);
+                    }
+                    _ => return Err(Error::TrustStoreError.into()),
+                }
+            }
+        }
+        Ok(Self {
+            path,
+            peer: peer.to_string(),
+            known_keys,
+        })
+    }
+
+    /// The key remembered for `peer`, if any
+    pub fn known_key(&self, peer: &str) -> Option<&[u8]> {
+        self.known_keys.get(peer).map(|k| k.as_slice())
+    }
+}
+
+impl TrustPolicy for TrustOnFirstUse {
+    fn check(&mut self, _route: &Route, remote_static_public_key: &PublicKey) -> OckamResult<bool> {
+        if let Some(key) = self.known_keys.get(&self.peer) {
+            return Ok(key.as_slice() == remote_static_public_key.as_ref());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|_| Error::TrustStoreError)?;
+        writeln!(
+            file,
+            "{} {}",
+            self.peer,
+            hex::encode(remote_static_public_key.as_ref())
+        )
+        .map_err(|_| Error::TrustStoreError)?;
+        self.known_keys.insert(
+            self.peer.clone(),
+            remote_static_public_key.as_ref().to_vec(),
+        );
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::RouterAddress;
+
+    fn route(udp: &str) -> Route {
+        Route {
+            addresses: vec![
+                RouterAddress::udp_router_address_from_str(udp).unwrap(),
+                RouterAddress::channel_router_address_from_str("01020304").unwrap(),
+            ],
+        }
+    }
+
+    #[test]
+    fn allow_list() {
+        let mut policy = AllowList::new(vec![PublicKey::new(vec![1u8; 32])]);
+        let r = route("127.0.0.1:4050");
+        assert!(policy.check(&r, &PublicKey::new(vec![1u8; 32])).unwrap());
+        assert!(!policy.check(&r, &PublicKey::new(vec![2u8; 32])).unwrap());
+        policy.allow(PublicKey::new(vec![2u8; 32]));
+        assert!(policy.check(&r, &PublicKey::new(vec![2u8; 32])).unwrap());
+        assert!(AcceptAll.check(&r, &PublicKey::new(vec![3u8; 32])).unwrap());
+    }
+
+    #[test]
+    fn trust_on_first_use() {
+        let dir = std::env::temp_dir().join(format!("ockam_tofu_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("known_keys");
+        let key_a = PublicKey::new(vec![1u8; 32]);
+        let key_b = PublicKey::new(vec![2u8; 32]);
+
+        let mut alice = TrustOnFirstUse::new(&path, "alice").unwrap();
+        let mut bob = TrustOnFirstUse::new(&path, "bob").unwrap();
+        assert!(alice.check(&route("127.0.0.1:4050"), &key_a).unwrap());
+        assert!(alice.check(&route("127.0.0.1:4050"), &key_a).unwrap());
+        assert!(!alice.check(&route("127.0.0.1:4050"), &key_b).unwrap());
+        // the route doesn't matter, only the key
+        assert!(!alice.check(&route("127.0.0.1:4051"), &key_b).unwrap());
+        assert!(alice.check(&route("127.0.0.1:4051"), &key_a).unwrap());
+        assert!(bob.check(&route("127.0.0.1:4051"), &key_b).unwrap());
+
+        // the keys survive a reload
+        let mut policy = TrustOnFirstUse::new(&path, "bob").unwrap();
+        assert_eq!(policy.known_key("alice"), Some(key_a.as_ref()));
+        assert!(!policy.check(&route("127.0.0.1:4051"), &key_a).unwrap());
+        assert!(policy.check(&route("127.0.0.1:4051"), &key_b).unwrap());
+
+        assert!(TrustOnFirstUse::new(&path, "").is_err());
+        assert!(TrustOnFirstUse::new(&path, "a b").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
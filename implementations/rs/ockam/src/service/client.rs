@@ -0,0 +1,199 @@
+use crate::message::{AddressType, Message, MessageType, Route, RouterAddress};
+use crate::secure_channel::CHANNEL_ZERO;
+use crate::service::error::Error;
+use crate::service::{decode, encode, Request, Response};
+use crate::system::commands::{ChannelCommand, OckamCommand, RouterCommand, WorkerCommand};
+use ockam_common::error::OckamResult;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// How long a call waits for the response by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends requests to a `ServiceWorker` on another node. A call blocks until the
+/// response arrives, so the client must not be used on the thread that polls the
+/// router of its node.
+///
+/// The client registers its address with the router, so the node can run other
+/// workers next to it. Messages to the client that aren't responses are dropped.
+#[derive(Debug)]
+pub struct ServiceClient {
+    addr: RouterAddress,
+    router_tx: Sender<OckamCommand>,
+    tx: Sender<OckamCommand>,
+    rx: Mutex<Receiver<OckamCommand>>,
+    /// Route to the service, `None` until the secure channel is established
+    route: RwLock<Option<Route>>,
+    service_addr: Option<RouterAddress>,
+    error_domains: &'static [&'static str],
+    next_id: AtomicU64,
+    timeout: RwLock<Duration>,
+}
+
+impl ServiceClient {
+    /// Create a client at `addr` that reaches the service at the end of `route`
+    pub fn new(addr: RouterAddress, route: Route, router_tx: Sender<OckamCommand>) -> Self {
+        Self::register(addr, Some(route), None, router_tx)
+    }
+
+    /// Create a client at `addr` that opens a secure channel along `onward_route` and
+    /// reaches the service at `service_addr` through it. Calls wait for the channel.
+    pub fn through_channel(
+        addr: RouterAddress,
+        mut onward_route: Route,
+        service_addr: RouterAddress,
+        router_tx: Sender<OckamCommand>,
+        channel_tx: Sender<OckamCommand>,
+    ) -> Self {
+        let client = Self::register(addr, None, Some(service_addr), router_tx);
+        onward_route
+            .addresses
+            .push(RouterAddress::channel_router_address_from_str(CHANNEL_ZERO).unwrap());
+        channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Initiate(
+                onward_route,
+                client.addr.address.clone(),
+                None,
+            )))
+            .expect("failed to initiate channel to the service");
+        client
+    }
+
+    fn register(
+        addr: RouterAddress,
+        route: Option<Route>,
+        service_addr: Option<RouterAddress>,
+        router_tx: Sender<OckamCommand>,
+    ) -> Self {
+        debug_assert!(matches!(addr.a_type, AddressType::Worker));
+
+        let (tx, rx) = mpsc::channel();
+        router_tx
+            .send(OckamCommand::Router(RouterCommand::RegisterWorker(
+                addr.clone(),
+                tx.clone(),
+            )))
+            .expect("service client registration failed");
+
+        Self {
+            addr,
+            router_tx,
+            tx,
+            rx: Mutex::new(rx),
+            route: RwLock::new(route),
+            service_addr,
+            error_domains: &[],
+            next_id: AtomicU64::new(1),
+            timeout: RwLock::new(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Set how long a call waits for the channel and the response
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.timeout.write().unwrap() = timeout;
+    }
+
+    /// Set the error domains of the service. Errors from them are returned as they
+    /// are, errors from other domains become `RemoteError`.
+    pub fn set_error_domains(&mut self, error_domains: &'static [&'static str]) {
+        self.error_domains = error_domains;
+    }
+
+    pub fn sender(&self) -> Sender<OckamCommand> {
+        self.tx.clone()
+    }
+
+    /// Send a request with `body` and wait for the body of the response
+    pub fn call(&self, body: Vec<u8>) -> OckamResult<Vec<u8>> {
+        let rx = self.rx.lock().unwrap();
+        let deadline = Instant::now() + *self.timeout.read().unwrap();
+
+        let route = loop {
+            if let Some(route) = self.route.read().unwrap().clone() {
+                break route;
+            }
+            // only channel notifications are expected before the route is known
+            self.receive(&rx, deadline)?;
+        };
+        let id = self.send_request(route, body)?;
+
+        loop {
+            // responses to calls that timed out or weren't waited for arrive late,
+            // skip them
+            if let Some(msg) = self.receive(&rx, deadline)? {
+                match decode::<Response>(&msg.message_body) {
+                    Ok(response) if response.id == id => {
+                        return response.reply.into_result(self.error_domains)
+                    }
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    /// Send a request with `body` without waiting for the response. Fails with
+    /// `ChannelClosed` if the channel to the service isn't established.
+    pub fn send(&self, body: Vec<u8>) -> OckamResult<()> {
+        let route = self.route.read().unwrap().clone();
+        match route {
+            Some(route) => self.send_request(route, body).map(|_| ()),
+            None => Err(Error::ChannelClosed.into()),
+        }
+    }
+
+    fn send_request(&self, route: Route, body: Vec<u8>) -> OckamResult<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.router_tx
+            .send(OckamCommand::Router(RouterCommand::SendMessage(Message {
+                onward_route: route,
+                return_route: Route {
+                    addresses: vec![self.addr.clone()],
+                },
+                message_type: MessageType::Payload,
+                message_body: encode(&Request { id, body })?,
+            })))
+            .map_err(|_| Error::RouterStopped)?;
+        Ok(id)
+    }
+
+    /// Wait for the next message to the client. Channel notifications update the
+    /// route and aren't returned.
+    fn receive(
+        &self,
+        rx: &Receiver<OckamCommand>,
+        deadline: Instant,
+    ) -> OckamResult<Option<Message>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let msg = match rx.recv_timeout(timeout) {
+            Ok(OckamCommand::Worker(WorkerCommand::ReceiveMessage(msg))) => msg,
+            Ok(_) => return Ok(None),
+            Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout.into()),
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::RouterStopped.into()),
+        };
+        match msg.message_type {
+            MessageType::Payload => Ok(Some(msg)),
+            MessageType::None => {
+                // the channel is established, its cleartext address leads to the service
+                if let (Some(service_addr), Some(channel_addr)) =
+                    (&self.service_addr, msg.return_route.addresses.first())
+                {
+                    *self.route.write().unwrap() = Some(Route {
+                        addresses: vec![channel_addr.clone(), service_addr.clone()],
+                    });
+                }
+                Ok(None)
+            }
+            MessageType::ChannelClose
+            | MessageType::NoSuchChannel
+            | MessageType::KeyAgreementFailed => {
+                if self.service_addr.is_some() {
+                    *self.route.write().unwrap() = None;
+                }
+                Err(Error::ChannelClosed.into())
+            }
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use ockam_common::error::OckamError;
+
+/// Represents the failures that can occur in
+/// an Ockam request/response service
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    None,
+    /// A request or response could not be encoded or decoded
+    BareError,
+    /// No response arrived in time
+    Timeout,
+    /// The secure channel to the service couldn't be established or was closed
+    ChannelClosed,
+    /// The router of the node stopped
+    RouterStopped,
+    /// The service failed with an error from an unknown domain
+    RemoteError,
+    /// The service only answers requests that arrive through a secure channel
+    ChannelRequired,
+}
+
+impl Error {
+    /// Error domain
+    pub const ERROR_DOMAIN: &'static str = "SERVICE_ERROR_DOMAIN";
+}
+
+#[allow(clippy::from_over_into)]
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
+    }
+}
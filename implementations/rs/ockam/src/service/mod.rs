@@ -0,0 +1,179 @@
+//! Request/response services let a node use a worker on another node. A
+//! `ServiceWorker` answers the requests of `ServiceClient`s on the return route of
+//! each request, what a request means is up to the service built on top of them.
+//! Requests and responses are BARE encoded envelopes around the service's own bodies,
+//! the client matches responses to requests by their id.
+
+pub use client::{ServiceClient, DEFAULT_TIMEOUT};
+pub use worker::ServiceWorker;
+
+mod client;
+pub mod error;
+mod worker;
+
+use error::Error;
+use ockam_common::error::{OckamError, OckamResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Request message body, `id` is echoed in the response
+#[derive(Serialize, Deserialize, Debug)]
+struct Request {
+    id: u64,
+    body: Vec<u8>,
+}
+
+/// Result of a request
+#[derive(Serialize, Deserialize, Debug)]
+enum Reply {
+    Body(Vec<u8>),
+    Error { code: u32, domain: String },
+}
+
+/// Response message body
+#[derive(Serialize, Deserialize, Debug)]
+struct Response {
+    id: u64,
+    reply: Reply,
+}
+
+impl Reply {
+    fn from_result(result: OckamResult<Vec<u8>>) -> Self {
+        match result {
+            Ok(body) => Reply::Body(body),
+            Err(error) => Reply::Error {
+                code: error.code(),
+                domain: error.domain().to_string(),
+            },
+        }
+    }
+
+    /// Turn an error reply back into the error if its domain is one of `domains`
+    fn into_result(self, domains: &[&'static str]) -> OckamResult<Vec<u8>> {
+        match self {
+            Reply::Body(body) => Ok(body),
+            Reply::Error { code, domain } => {
+                if domain == Error::ERROR_DOMAIN {
+                    return Err(OckamError::new(code, Error::ERROR_DOMAIN));
+                }
+                match domains.iter().find(|d| **d == domain) {
+                    Some(domain) => Err(OckamError::new(code, domain)),
+                    None => Err(Error::RemoteError.into()),
+                }
+            }
+        }
+    }
+}
+
+fn encode<T: Serialize>(body: &T) -> OckamResult<Vec<u8>> {
+    serde_bare::to_vec(body).map_err(|_| Error::BareError.into())
+}
+
+fn decode<T: DeserializeOwned>(body: &[u8]) -> OckamResult<T> {
+    serde_bare::from_slice(body).map_err(|_| Error::BareError.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Message, Route, RouterAddress};
+    use crate::system::commands::{OckamCommand, RouterCommand, WorkerCommand};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    const DOMAIN: &str = "TEST_SERVICE_ERROR_DOMAIN";
+
+    fn worker_address(address: &str) -> RouterAddress {
+        RouterAddress::worker_router_address_from_str(address).unwrap()
+    }
+
+    /// Take the next message a worker or client sent to its router
+    fn sent(router_rx: &Receiver<OckamCommand>) -> Message {
+        loop {
+            match router_rx.recv().unwrap() {
+                OckamCommand::Router(RouterCommand::SendMessage(m)) => return m,
+                OckamCommand::Router(RouterCommand::RegisterWorker(..)) => continue,
+                cmd => panic!("unexpected command {:?}", cmd),
+            }
+        }
+    }
+
+    fn deliver(tx: &Sender<OckamCommand>, m: Message) {
+        tx.send(OckamCommand::Worker(WorkerCommand::ReceiveMessage(m)))
+            .unwrap();
+    }
+
+    /// A client with its router, it sends to the worker at 01010101
+    fn client(domains: &'static [&'static str]) -> (ServiceClient, Receiver<OckamCommand>) {
+        let (router_tx, router_rx) = channel();
+        let mut client = ServiceClient::new(
+            worker_address("02020202"),
+            Route {
+                addresses: vec![worker_address("01010101")],
+            },
+            router_tx,
+        );
+        client.set_error_domains(domains);
+        (client, router_rx)
+    }
+
+    /// Pass one request of the client to a worker that echoes the body and fails on an
+    /// empty one. The request arrives from `first_hop`.
+    fn call(
+        domains: &'static [&'static str],
+        body: &[u8],
+        first_hop: RouterAddress,
+        channel_required: bool,
+    ) -> OckamResult<Vec<u8>> {
+        let (client, client_router_rx) = client(domains);
+        let (worker_router_tx, worker_router_rx) = channel();
+        let mut worker = ServiceWorker::new(worker_address("01010101"), worker_router_tx);
+        worker.set_channel_required(channel_required);
+
+        thread::scope(|scope| {
+            let call = scope.spawn(|| client.call(body.to_vec()));
+            let mut request = sent(&client_router_rx);
+            request.return_route.addresses.insert(0, first_hop);
+            deliver(&worker.sender(), request);
+            assert!(worker.poll(|_, body| {
+                if body.is_empty() {
+                    Err(OckamError::new(7, DOMAIN))
+                } else {
+                    Ok(body)
+                }
+            }));
+            let mut response = sent(&worker_router_rx);
+            response.onward_route.addresses.remove(0);
+            deliver(&client.sender(), response);
+            call.join().unwrap()
+        })
+    }
+
+    #[test]
+    fn requests_and_errors() {
+        let udp = RouterAddress::udp_router_address_from_str("127.0.0.1:4000").unwrap();
+        assert_eq!(
+            call(&[DOMAIN], b"ping", udp.clone(), false).unwrap(),
+            b"ping"
+        );
+
+        let error = call(&[DOMAIN], b"", udp.clone(), false).unwrap_err();
+        assert_eq!((error.code(), error.domain()), (7, DOMAIN));
+
+        // errors from domains the client doesn't know are hidden
+        let error = call(&[], b"", udp, false).unwrap_err();
+        assert_eq!(error.domain(), Error::ERROR_DOMAIN);
+        assert_eq!(error.code(), Error::RemoteError as u32);
+    }
+
+    #[test]
+    fn channel_required() {
+        let udp = RouterAddress::udp_router_address_from_str("127.0.0.1:4000").unwrap();
+        let error = call(&[], b"ping", udp, true).unwrap_err();
+        assert_eq!(error.domain(), Error::ERROR_DOMAIN);
+        assert_eq!(error.code(), Error::ChannelRequired as u32);
+
+        let channel = RouterAddress::channel_router_address_from_str("0a0a0a0a").unwrap();
+        assert_eq!(call(&[], b"ping", channel, true).unwrap(), b"ping");
+    }
+}
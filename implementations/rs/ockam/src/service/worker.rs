@@ -0,0 +1,119 @@
+use crate::message::{AddressType, Message, MessageType, Route, RouterAddress};
+use crate::service::error::Error;
+use crate::service::{decode, encode, Reply, Request, Response};
+use crate::system::commands::{OckamCommand, RouterCommand, WorkerCommand};
+use ockam_common::error::OckamResult;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+/// A worker that answers the requests of `ServiceClient`s on the return route of each
+/// request, the handler passed to `poll` decides what the answer is. Messages that
+/// aren't requests to the worker are dropped.
+#[derive(Debug)]
+pub struct ServiceWorker {
+    addr: RouterAddress,
+    router_tx: Sender<OckamCommand>,
+    rx: Receiver<OckamCommand>,
+    tx: Sender<OckamCommand>,
+    channel_required: bool,
+}
+
+impl ServiceWorker {
+    /// Create the worker at `addr` and register it with the router
+    pub fn new(addr: RouterAddress, router_tx: Sender<OckamCommand>) -> Self {
+        debug_assert!(matches!(addr.a_type, AddressType::Worker));
+
+        let (tx, rx) = mpsc::channel();
+        router_tx
+            .send(OckamCommand::Router(RouterCommand::RegisterWorker(
+                addr.clone(),
+                tx.clone(),
+            )))
+            .expect("service worker registration failed");
+
+        Self {
+            addr,
+            router_tx,
+            rx,
+            tx,
+            channel_required: false,
+        }
+    }
+
+    /// Refuse requests that didn't arrive through a secure channel with
+    /// `ChannelRequired`
+    pub fn set_channel_required(&mut self, channel_required: bool) {
+        self.channel_required = channel_required;
+    }
+
+    pub fn address(&self) -> &RouterAddress {
+        &self.addr
+    }
+
+    pub fn sender(&self) -> Sender<OckamCommand> {
+        self.tx.clone()
+    }
+
+    /// Answer the requests that arrived since the last poll with `handler`. It gets the
+    /// return route of a request, which starts with the channel the request arrived
+    /// through if there is one, and the request body. Returns false once the worker
+    /// is stopped.
+    pub fn poll<F>(&mut self, mut handler: F) -> bool
+    where
+        F: FnMut(&Route, Vec<u8>) -> OckamResult<Vec<u8>>,
+    {
+        loop {
+            match self.rx.try_recv() {
+                Ok(OckamCommand::Worker(WorkerCommand::ReceiveMessage(msg))) => {
+                    // channel notifications need no answer
+                    if !matches!(msg.message_type, MessageType::Payload)
+                        || msg.onward_route.addresses.first() != Some(&self.addr)
+                    {
+                        continue;
+                    }
+                    let request: Request = match decode(&msg.message_body) {
+                        Ok(request) => request,
+                        Err(_) => continue,
+                    };
+                    let result = if self.channel_required && !through_channel(&msg) {
+                        Err(Error::ChannelRequired.into())
+                    } else {
+                        handler(&msg.return_route, request.body)
+                    };
+                    let response = Response {
+                        id: request.id,
+                        reply: Reply::from_result(result),
+                    };
+                    if self.respond(msg.return_route, &response).is_err() {
+                        return false;
+                    }
+                }
+                Ok(OckamCommand::Worker(WorkerCommand::Stop)) => return false,
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+
+    fn respond(&self, route: Route, response: &Response) -> OckamResult<()> {
+        self.router_tx
+            .send(OckamCommand::Router(RouterCommand::SendMessage(Message {
+                onward_route: route,
+                return_route: Route {
+                    addresses: vec![self.addr.clone()],
+                },
+                message_type: MessageType::Payload,
+                message_body: encode(response)?,
+            })))
+            .map_err(|_| Error::RouterStopped.into())
+    }
+}
+
+/// The channel manager puts the channel in front of the return route of the messages
+/// it decrypts
+fn through_channel(msg: &Message) -> bool {
+    msg.return_route
+        .addresses
+        .first()
+        .is_some_and(|a| matches!(a.a_type, AddressType::Channel))
+}
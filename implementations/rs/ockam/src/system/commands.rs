@@ -26,6 +26,9 @@ pub enum TransportCommand {
 pub enum RouterCommand {
     Stop,
     Register(AddressType, std::sync::mpsc::Sender<OckamCommand>),
+    // messages to this worker address go to the sender instead of the
+    // sender registered for AddressType::Worker
+    RegisterWorker(RouterAddress, std::sync::mpsc::Sender<OckamCommand>),
     SendMessage(Message),
     ReceiveMessage(Message),
 }
@@ -39,6 +42,7 @@ pub enum ChannelCommand {
                                                              * address */
     SendMessage(Message),
     ReceiveMessage(Message),
+    Close(Address), // either address of the channel
     Stop,
 }
 
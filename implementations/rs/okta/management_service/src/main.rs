@@ -9,7 +9,7 @@ mod objects;
 use colored::Colorize;
 use isahc::prelude::*;
 use objects::UsersInGroup;
-use rand::RngCore;
+use ockam_vault_software::{ockam_vault::RandomVault, DefaultVault};
 use serde::Deserialize;
 use std::{
     cell::RefCell,
@@ -234,8 +234,9 @@ async fn main() {
 
     let mut okta_data = OktaData::default();
     let mut nonce = [0u8; 16];
-    rand::thread_rng().fill_bytes(&mut okta_data.state);
-    rand::thread_rng().fill_bytes(&mut nonce);
+    let mut vault = DefaultVault::default();
+    vault.random_bytes_generate(&mut okta_data.state).unwrap();
+    vault.random_bytes_generate(&mut nonce).unwrap();
 
     okta_data.redirect_uri = format!("http://localhost:{}/authorization-code/callback", cfg.port);
 
@@ -1,4 +1,7 @@
 #![allow(unused)]
+#[cfg(any(test, feature = "local"))]
+pub mod local;
+
 pub mod router {
     use ockam::message::*;
     use ockam::system::commands::{
@@ -12,6 +15,7 @@ pub mod router {
 
     pub struct Router {
         registry: Vec<Option<std::sync::mpsc::Sender<OckamCommand>>>,
+        workers: Vec<(RouterAddress, std::sync::mpsc::Sender<OckamCommand>)>,
         rx: std::sync::mpsc::Receiver<OckamCommand>,
     }
 
@@ -24,6 +28,7 @@ pub mod router {
         pub fn new(rx: std::sync::mpsc::Receiver<OckamCommand>) -> Router {
             Router {
                 registry: vec![Option::None; 256],
+                workers: vec![],
                 rx,
             }
         }
@@ -53,6 +58,11 @@ pub mod router {
                             got = true;
                             self.registry[a_type as usize] = Option::Some(tx);
                         }
+                        OckamCommand::Router(RouterCommand::RegisterWorker(address, tx)) => {
+                            got = true;
+                            self.workers.retain(|(a, _)| *a != address);
+                            self.workers.push((address, tx));
+                        }
                         OckamCommand::Router(RouterCommand::ReceiveMessage(m)) => {
                             got = true;
                             self.route(m, Direction::Incoming);
@@ -77,9 +87,15 @@ pub mod router {
             let destination_address = m.onward_route.addresses[0].clone();
             let address_type = destination_address.a_type;
             let at = address_type as usize;
-            let handler_tx = match &self.registry[at] {
-                Some(a) => a,
-                None => return Err("no handler".to_string()),
+            // workers registered by address come before the worker of the address type
+            let worker_tx = self
+                .workers
+                .iter()
+                .find(|(a, _)| *a == destination_address)
+                .map(|(_, tx)| tx);
+            let handler_tx = match (worker_tx, &self.registry[at]) {
+                (Some(a), _) | (None, Some(a)) => a,
+                (None, None) => return Err("no handler".to_string()),
             };
             match address_type {
                 AddressType::Worker => match direction {
@@ -114,6 +130,76 @@ pub mod router {
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::sync::mpsc::Receiver;
+
+        fn message_to(address: &RouterAddress) -> Message {
+            Message {
+                onward_route: Route {
+                    addresses: vec![address.clone()],
+                },
+                return_route: Route { addresses: vec![] },
+                message_type: MessageType::Payload,
+                message_body: vec![],
+            }
+        }
+
+        fn received(rx: &Receiver<OckamCommand>) -> Option<RouterAddress> {
+            match rx.try_recv() {
+                Ok(OckamCommand::Worker(WorkerCommand::ReceiveMessage(m))) => {
+                    Some(m.onward_route.addresses[0].clone())
+                }
+                _ => None,
+            }
+        }
+
+        #[test]
+        fn workers_registered_by_address() {
+            let (router_tx, router_rx) = channel();
+            let mut router = Router::new(router_rx);
+            let service = RouterAddress::worker_router_address_from_str("01010101").unwrap();
+            let client = RouterAddress::worker_router_address_from_str("02020202").unwrap();
+            let other = RouterAddress::worker_router_address_from_str("03030303").unwrap();
+            let (worker_tx, worker_rx) = channel();
+            let (service_tx, service_rx) = channel();
+            let (client_tx, client_rx) = channel();
+            router_tx
+                .send(OckamCommand::Router(RouterCommand::Register(
+                    AddressType::Worker,
+                    worker_tx,
+                )))
+                .unwrap();
+            router_tx
+                .send(OckamCommand::Router(RouterCommand::RegisterWorker(
+                    service.clone(),
+                    service_tx,
+                )))
+                .unwrap();
+            router_tx
+                .send(OckamCommand::Router(RouterCommand::RegisterWorker(
+                    client.clone(),
+                    client_tx,
+                )))
+                .unwrap();
+            for address in &[&service, &client, &other] {
+                router_tx
+                    .send(OckamCommand::Router(RouterCommand::ReceiveMessage(
+                        message_to(address),
+                    )))
+                    .unwrap();
+            }
+            router.poll();
+
+            assert_eq!(received(&service_rx), Some(service));
+            assert_eq!(received(&client_rx), Some(client));
+            // the worker of the address type gets the rest
+            assert_eq!(received(&worker_rx), Some(other));
+            assert!(received(&service_rx).is_none() && received(&worker_rx).is_none());
+        }
+    }
 }
 
 // #[cfg(test)]
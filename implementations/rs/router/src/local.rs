@@ -0,0 +1,113 @@
+//! Nodes that run in one process and hand their udp messages to each other as if a
+//! transport carried them, for tests of workers that talk across nodes.
+
+use crate::router::Router;
+use ockam::message::{AddressType, Route, RouterAddress};
+use ockam::system::commands::{OckamCommand, RouterCommand, TransportCommand};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A node of a `LocalNetwork`, its router is polled once the network is started
+#[derive(Debug)]
+pub struct LocalNode {
+    pub address: RouterAddress,
+    pub router_tx: Sender<OckamCommand>,
+    router_rx: Receiver<OckamCommand>,
+    transport_rx: Receiver<OckamCommand>,
+}
+
+impl LocalNode {
+    /// Create a node whose udp address is `address`
+    pub fn new(address: &str) -> Self {
+        let (router_tx, router_rx) = channel();
+        let (transport_tx, transport_rx) = channel();
+        router_tx
+            .send(OckamCommand::Router(RouterCommand::Register(
+                AddressType::Udp,
+                transport_tx,
+            )))
+            .unwrap();
+        Self {
+            address: RouterAddress::udp_router_address_from_str(address).unwrap(),
+            router_tx,
+            router_rx,
+            transport_rx,
+        }
+    }
+
+    /// Route to `worker` on this node
+    pub fn route_to(&self, worker: &str) -> Route {
+        Route {
+            addresses: vec![
+                self.address.clone(),
+                RouterAddress::worker_router_address_from_str(worker).unwrap(),
+            ],
+        }
+    }
+}
+
+/// Polls the routers of its nodes on a thread until it is dropped
+#[derive(Debug)]
+pub struct LocalNetwork {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LocalNetwork {
+    /// Start polling `nodes`. `setup` runs on the polling thread and returns what else
+    /// to poll after the routers, like workers and channel managers.
+    pub fn start<S, P>(nodes: Vec<LocalNode>, setup: S) -> Self
+    where
+        S: FnOnce() -> P + Send + 'static,
+        P: FnMut(),
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let keep_running = running.clone();
+        let thread = thread::spawn(move || {
+            let peers: Vec<_> = nodes
+                .iter()
+                .map(|node| (node.address.clone(), node.router_tx.clone()))
+                .collect();
+            let mut nodes: Vec<_> = nodes
+                .into_iter()
+                .map(|node| (node.address, Router::new(node.router_rx), node.transport_rx))
+                .collect();
+            let mut poll = setup();
+
+            while keep_running.load(Ordering::Relaxed) {
+                for (address, router, transport_rx) in nodes.iter_mut() {
+                    router.poll();
+                    while let Ok(OckamCommand::Transport(TransportCommand::SendMessage(mut m))) =
+                        transport_rx.try_recv()
+                    {
+                        let hop = m.onward_route.addresses.remove(0);
+                        m.return_route.addresses.insert(0, address.clone());
+                        // messages to unknown nodes are lost
+                        if let Some((_, peer_tx)) = peers.iter().find(|(a, _)| *a == hop) {
+                            let _ = peer_tx
+                                .send(OckamCommand::Router(RouterCommand::ReceiveMessage(m)));
+                        }
+                    }
+                }
+                poll();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        Self {
+            running,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for LocalNetwork {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
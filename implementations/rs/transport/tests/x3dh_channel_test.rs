@@ -0,0 +1,236 @@
+use ockam::kex::CipherSuite;
+use ockam::message::{Address, AddressType, Message, MessageType, Route, RouterAddress};
+use ockam::secure_channel::{AcceptAll, AllowList, ChannelManager, TrustPolicy, CHANNEL_ZERO};
+use ockam::system::commands::{
+    ChannelCommand, OckamCommand, RouterCommand, TransportCommand, WorkerCommand,
+};
+use ockam_kex_x3dh::{PreKeyStore, X3dhInitiator, X3dhNewKeyExchanger, X3dhResponder};
+use ockam_router::router::Router;
+use ockam_transport::tcp::TcpManager;
+use ockam_vault::SecretVault;
+use ockam_vault_software::DefaultVault;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const INITIATOR_WORKER: &str = "aabbccdd";
+const RESPONDER_WORKER: &str = "00112233";
+
+type X3dhChannelManager = ChannelManager<X3dhInitiator, X3dhResponder, X3dhNewKeyExchanger>;
+
+/// A node with a router, a tcp transport, X3DH channels and a worker inbox
+pub struct TestNode {
+    router: Router,
+    router_tx: Sender<OckamCommand>,
+    transport: TcpManager,
+    transport_tx: Sender<OckamCommand>,
+    channels: X3dhChannelManager,
+    channel_tx: Sender<OckamCommand>,
+    worker_rx: Receiver<OckamCommand>,
+}
+
+impl TestNode {
+    pub fn new(
+        listen_addr: Option<SocketAddr>,
+        cipher_suite: CipherSuite,
+        vault: Arc<DefaultVault>,
+        prekey_store: Option<Arc<PreKeyStore>>,
+        trust_policy: Box<dyn TrustPolicy>,
+    ) -> Self {
+        let (router_tx, router_rx) = mpsc::channel();
+        let router = Router::new(router_rx);
+
+        let (transport_tx, transport_rx) = mpsc::channel();
+        let transport = TcpManager::new(
+            transport_rx,
+            transport_tx.clone(),
+            router_tx.clone(),
+            listen_addr,
+            None,
+        )
+        .expect("failed to create tcp transport manager");
+
+        let (channel_tx, channel_rx) = mpsc::channel();
+        let channels = X3dhChannelManager::new_x3dh(
+            channel_rx,
+            channel_tx.clone(),
+            router_tx.clone(),
+            cipher_suite,
+            vault,
+            prekey_store,
+            None,
+            None,
+            trust_policy,
+        )
+        .expect("failed to create channel manager");
+
+        let (worker_tx, worker_rx) = mpsc::channel();
+        router_tx
+            .send(OckamCommand::Router(RouterCommand::Register(
+                AddressType::Worker,
+                worker_tx,
+            )))
+            .unwrap();
+
+        Self {
+            router,
+            router_tx,
+            transport,
+            transport_tx,
+            channels,
+            channel_tx,
+            worker_rx,
+        }
+    }
+
+    /// Poll every part of the node once and return what reached the worker
+    pub fn poll(&mut self) -> Vec<Message> {
+        self.router.poll();
+        self.transport.poll();
+        self.router.poll();
+        self.channels.poll().expect("channel manager failed");
+        self.router.poll();
+        let mut inbox = vec![];
+        while let Ok(OckamCommand::Worker(WorkerCommand::ReceiveMessage(m))) =
+            self.worker_rx.try_recv()
+        {
+            inbox.push(m);
+        }
+        inbox
+    }
+
+    pub fn send(&self, onward_route: Route, return_address: &str, body: &[u8]) {
+        let m = Message {
+            onward_route,
+            return_route: Route {
+                addresses: vec![
+                    RouterAddress::worker_router_address_from_str(return_address).unwrap(),
+                ],
+            },
+            message_type: MessageType::Payload,
+            message_body: body.to_vec(),
+        };
+        self.router_tx
+            .send(OckamCommand::Router(RouterCommand::SendMessage(m)))
+            .unwrap();
+    }
+
+    pub fn stop(&self) {
+        self.transport_tx
+            .send(OckamCommand::Transport(TransportCommand::Stop))
+            .unwrap();
+        self.channel_tx
+            .send(OckamCommand::Channel(ChannelCommand::Stop))
+            .unwrap();
+    }
+}
+
+/// Poll both nodes until one of them has something for its worker
+fn run(initiator: &mut TestNode, responder: &mut TestNode) -> (Vec<Message>, Vec<Message>) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        let initiator_inbox = initiator.poll();
+        let responder_inbox = responder.poll();
+        if !initiator_inbox.is_empty() || !responder_inbox.is_empty() {
+            return (initiator_inbox, responder_inbox);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("nothing arrived in time");
+}
+
+fn x3dh_channel(cipher_suite: CipherSuite, responder_address: &str) {
+    let responder_vault = Arc::new(DefaultVault::default());
+    let store = Arc::new(PreKeyStore::new(cipher_suite, responder_vault.clone(), None).unwrap());
+    store.publish_one_time_prekeys(1).unwrap();
+    let responder_identity = responder_vault
+        .secret_public_key_get(store.identity_key().as_ref().as_ref())
+        .unwrap();
+
+    let listen_addr = SocketAddr::from_str(responder_address).unwrap();
+    let mut responder = TestNode::new(
+        Some(listen_addr),
+        cipher_suite,
+        responder_vault,
+        Some(store.clone()),
+        Box::new(AcceptAll),
+    );
+    // the initiator only talks to the owner of the prekey store
+    let mut initiator = TestNode::new(
+        None,
+        cipher_suite,
+        Arc::new(DefaultVault::default()),
+        None,
+        Box::new(AllowList::new(vec![responder_identity.clone()])),
+    );
+    initiator
+        .transport
+        .connect(listen_addr)
+        .expect("failed to connect, is the listener running?");
+
+    // key exchange over tcp, the prekey bundle comes from the store
+    let route = Route {
+        addresses: vec![
+            RouterAddress::tcp_router_address_from_str(responder_address).unwrap(),
+            RouterAddress::channel_router_address_from_str(CHANNEL_ZERO).unwrap(),
+        ],
+    };
+    initiator
+        .channel_tx
+        .send(OckamCommand::Channel(ChannelCommand::Initiate(
+            route,
+            Address::worker_address_from_string(INITIATOR_WORKER).unwrap(),
+            None,
+        )))
+        .unwrap();
+    let (mut initiator_inbox, mut responder_inbox) = (vec![], vec![]);
+    while initiator_inbox.is_empty() || responder_inbox.is_empty() {
+        let (i, r) = run(&mut initiator, &mut responder);
+        initiator_inbox.extend(i);
+        responder_inbox.extend(r);
+    }
+    let notification = initiator_inbox.pop().unwrap();
+    assert!(matches!(notification.message_type, MessageType::None));
+    assert!(notification
+        .message_body
+        .ends_with(responder_identity.as_ref()));
+    assert!(matches!(
+        responder_inbox.pop().unwrap().message_type,
+        MessageType::None
+    ));
+    assert_eq!(store.available_one_time_prekeys(), 0);
+
+    // payloads both ways through the channel
+    let route = Route {
+        addresses: vec![
+            notification.return_route.addresses[0].clone(),
+            RouterAddress::worker_router_address_from_str(RESPONDER_WORKER).unwrap(),
+        ],
+    };
+    initiator.send(route, INITIATOR_WORKER, b"hello");
+    let (_, mut responder_inbox) = run(&mut initiator, &mut responder);
+    let m = responder_inbox.pop().unwrap();
+    assert!(matches!(m.message_type, MessageType::Payload));
+    assert_eq!(m.message_body, b"hello");
+
+    responder.send(m.return_route, RESPONDER_WORKER, b"hello back");
+    let (mut initiator_inbox, _) = run(&mut initiator, &mut responder);
+    let m = initiator_inbox.pop().unwrap();
+    assert_eq!(m.message_body, b"hello back");
+
+    initiator.stop();
+    responder.stop();
+}
+
+#[test]
+fn test_x3dh_channel() {
+    x3dh_channel(CipherSuite::Curve25519AesGcmSha256, "127.0.0.1:4062");
+}
+
+#[test]
+fn test_x3dh_p256_channel() {
+    x3dh_channel(CipherSuite::P256Aes128GcmSha256, "127.0.0.1:4063");
+}
@@ -0,0 +1,30 @@
+use ockam_common::error::OckamError;
+
+/// Represents the failures that can occur in
+/// an Ockam Audit Vault
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    None,
+    SecretFromAnotherVault,
+    /// The audit log could not be opened, read or appended to
+    IOError,
+    /// A record is malformed or the log ends in the middle of a record
+    InvalidRecord,
+    /// A record is out of sequence or its hash doesn't match, the log was edited
+    BrokenChain,
+    /// The log doesn't contain the checkpoint, records were removed from its end
+    CheckpointMismatch,
+    /// A caller tag or secret name is empty or contains control characters
+    InvalidTag,
+}
+
+impl Error {
+    /// Error domain
+    pub const ERROR_DOMAIN: &'static str = "VAULT_AUDIT_ERROR_DOMAIN";
+}
+
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
+    }
+}
@@ -0,0 +1,583 @@
+use crate::error::*;
+use crate::log::AuditLog;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::{
+    PersistentSecretInfo, PublicKey, SecretAttributes, SecretKey, SecretMetadata,
+    SecretPersistence, SecretType,
+};
+use ockam_vault::{
+    AsymmetricVault, HashVault, PersistentVault, RandomVault, Secret, SecretVault, SignerVault,
+    SymmetricVault, VerifierVault,
+};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroize;
+
+pub use log::{verify_log, AuditHead, AuditRecord};
+pub use ockam_vault;
+
+pub mod error;
+mod log;
+
+/// Audit vault secret, wraps a secret of the inner vault with the name it has in the log
+#[derive(Debug)]
+pub struct AuditVaultSecret {
+    inner: Box<dyn Secret>,
+    name: String,
+}
+
+impl AuditVaultSecret {
+    pub fn downcast_secret(context: &dyn Secret) -> OckamResult<&Self> {
+        context
+            .downcast_ref::<AuditVaultSecret>()
+            .map_err(|_| Error::SecretFromAnotherVault.into())
+    }
+
+    /// Name of the secret in the audit log
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Zeroize for AuditVaultSecret {
+    fn zeroize(&mut self) {
+        self.inner.zeroize();
+    }
+}
+
+impl Secret for AuditVaultSecret {}
+
+/// An AuditVault wraps another vault and records every operation that uses a
+/// persistent secret, such as an identity key, in a tamper-evident log file, see
+/// `verify_log`.
+///
+/// A record holds the operation, the name of the secret, its attributes, the time and
+/// the caller tag of the vault, see `for_caller`. Uses of a secret are recorded before
+/// they run, so an operation is never performed unrecorded, new secrets after they are
+/// created, and destroyed again if that fails. Secrets found through `PersistentVault`
+/// are named by their persistence id, other secrets by a number. Ephemeral secrets,
+/// like the handshake and session keys of a channel, are only recorded when they are
+/// exported, otherwise every message would add a record. Reading public keys and
+/// attributes and operations without a secret are not recorded either. Records are
+/// appended one at a time, operations run in parallel when the inner vault allows it.
+#[derive(Debug)]
+pub struct AuditVault<V> {
+    shared: Arc<SharedAuditVault<V>>,
+    caller: String,
+}
+
+/// The inner vault and the log, shared by the vaults of all callers
+#[derive(Debug)]
+struct SharedAuditVault<V> {
+    inner: V,
+    log: Mutex<AuditLog>,
+    next_id: AtomicUsize,
+}
+
+impl<V: SecretVault> AuditVault<V> {
+    /// Wrap `inner`, appending to the log at `path`. An existing log has to verify.
+    /// Records are tagged with the caller `unknown`.
+    pub fn new(inner: V, path: &Path) -> OckamResult<Self> {
+        Ok(Self {
+            shared: Arc::new(SharedAuditVault {
+                inner,
+                log: Mutex::new(AuditLog::open(path)?),
+                next_id: AtomicUsize::new(0),
+            }),
+            caller: String::from("unknown"),
+        })
+    }
+
+    /// A vault with the same inner vault and log whose records are tagged with
+    /// `caller`. The secrets of either vault can be used with the other.
+    pub fn for_caller(&self, caller: &str) -> OckamResult<Self> {
+        log::check_tag(caller)?;
+        Ok(Self {
+            shared: self.shared.clone(),
+            caller: caller.to_string(),
+        })
+    }
+
+    /// The position of the last record, keep it elsewhere to detect a truncated log
+    pub fn head(&self) -> AuditHead {
+        self.shared.log.lock().unwrap().head()
+    }
+
+    /// Wrap a secret created in the inner vault before it was wrapped, `name` identifies
+    /// it in the log
+    pub fn wrap_secret(&self, secret: Box<dyn Secret>, name: &str) -> OckamResult<Box<dyn Secret>> {
+        log::check_tag(name)?;
+        Ok(Box::new(AuditVaultSecret {
+            inner: secret,
+            name: name.to_string(),
+        }))
+    }
+
+    fn inner(&self) -> &V {
+        &self.shared.inner
+    }
+
+    fn wrap(&self, inner: Box<dyn Secret>) -> Box<dyn Secret> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        Box::new(AuditVaultSecret {
+            inner,
+            name: format!("#{}", id),
+        })
+    }
+
+    /// Record that `operation` created `inner` and return it wrapped. The secret is
+    /// destroyed if it can't be recorded.
+    fn record_new(&self, operation: &str, inner: Box<dyn Secret>) -> OckamResult<Box<dyn Secret>> {
+        let secret = self.wrap(inner);
+        if let Err(e) = self.record(operation, secret.as_ref()) {
+            if let Ok(secret) = secret.downcast::<AuditVaultSecret>() {
+                let _ = self.inner().secret_destroy(secret.inner);
+            }
+            return Err(e);
+        }
+        Ok(secret)
+    }
+
+    /// Record that `operation` uses `secret` if it is persistent and return the inner
+    /// secret
+    fn record<'a>(&self, operation: &str, secret: &'a dyn Secret) -> OckamResult<&'a dyn Secret> {
+        self.record_if(operation, secret, false)
+    }
+
+    /// Record that `operation` uses `secret`, ephemeral secrets only if `always` is set,
+    /// and return the inner secret
+    fn record_if<'a>(
+        &self,
+        operation: &str,
+        secret: &'a dyn Secret,
+        always: bool,
+    ) -> OckamResult<&'a dyn Secret> {
+        let secret = AuditVaultSecret::downcast_secret(secret)?;
+        let attributes = self.inner().secret_attributes_get(secret.inner.as_ref())?;
+        if !always && attributes.persistence == SecretPersistence::Ephemeral {
+            return Ok(secret.inner.as_ref());
+        }
+        self.shared.log.lock().unwrap().append(
+            &self.caller,
+            operation,
+            Some(&secret.name),
+            Some(attributes),
+        )?;
+        Ok(secret.inner.as_ref())
+    }
+}
+
+impl<V: SecretVault> Zeroize for AuditVault<V> {
+    fn zeroize(&mut self) {
+        // the inner vault is zeroized by the last vault that shares it
+        if let Some(shared) = Arc::get_mut(&mut self.shared) {
+            shared.inner.zeroize();
+        }
+    }
+}
+
+impl<V: SecretVault> SecretVault for AuditVault<V> {
+    fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+        let secret = self.inner().secret_generate(attributes)?;
+        self.record_new("secret_generate", secret)
+    }
+
+    fn secret_import(
+        &self,
+        secret: &[u8],
+        attributes: SecretAttributes,
+    ) -> OckamResult<Box<dyn Secret>> {
+        let secret = self.inner().secret_import(secret, attributes)?;
+        self.record_new("secret_import", secret)
+    }
+
+    fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+        let context = self.record_if("secret_export", context, true)?;
+        self.inner().secret_export(context)
+    }
+
+    fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes> {
+        let context = AuditVaultSecret::downcast_secret(context)?;
+        self.inner().secret_attributes_get(context.inner.as_ref())
+    }
+
+    fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
+        let context = AuditVaultSecret::downcast_secret(context)?;
+        self.inner().secret_public_key_get(context.inner.as_ref())
+    }
+
+    fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+        self.record("secret_destroy", context.as_ref())?;
+        let context = context
+            .downcast::<AuditVaultSecret>()
+            .map_err(|_| Error::SecretFromAnotherVault)?;
+        self.inner().secret_destroy(context.inner)
+    }
+}
+
+impl<V: SecretVault + AsymmetricVault> AsymmetricVault for AuditVault<V> {
+    fn ec_diffie_hellman(
+        &self,
+        context: &dyn Secret,
+        peer_public_key: &[u8],
+    ) -> OckamResult<Box<dyn Secret>> {
+        let context = self.record("ec_diffie_hellman", context)?;
+        let secret = self.inner().ec_diffie_hellman(context, peer_public_key)?;
+        Ok(self.wrap(secret))
+    }
+}
+
+impl<V: SecretVault + SymmetricVault> SymmetricVault for AuditVault<V> {
+    fn aead_aes_gcm_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let context = self.record("aead_aes_gcm_encrypt", context)?;
+        self.inner()
+            .aead_aes_gcm_encrypt(context, plaintext, nonce, aad)
+    }
+
+    fn aead_aes_gcm_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let context = self.record("aead_aes_gcm_decrypt", context)?;
+        self.inner()
+            .aead_aes_gcm_decrypt(context, cipher_text, nonce, aad)
+    }
+
+    fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let context = self.record("aead_chacha20_poly1305_encrypt", context)?;
+        self.inner()
+            .aead_chacha20_poly1305_encrypt(context, plaintext, nonce, aad)
+    }
+
+    fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let context = self.record("aead_chacha20_poly1305_decrypt", context)?;
+        self.inner()
+            .aead_chacha20_poly1305_decrypt(context, cipher_text, nonce, aad)
+    }
+}
+
+impl<V: SecretVault + HashVault> HashVault for AuditVault<V> {
+    fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]> {
+        self.inner().sha256(data)
+    }
+
+    fn hkdf_sha256(
+        &self,
+        salt: &dyn Secret,
+        info: &[u8],
+        ikm: Option<&dyn Secret>,
+        output_attributes: Vec<SecretAttributes>,
+    ) -> OckamResult<Vec<Box<dyn Secret>>> {
+        let salt = self.record("hkdf_sha256", salt)?;
+        let ikm = match ikm {
+            Some(ikm) => Some(self.record("hkdf_sha256", ikm)?),
+            None => None,
+        };
+        let secrets = self
+            .inner()
+            .hkdf_sha256(salt, info, ikm, output_attributes)?;
+        Ok(secrets.into_iter().map(|s| self.wrap(s)).collect())
+    }
+}
+
+impl<V: SecretVault + SignerVault> SignerVault for AuditVault<V> {
+    fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]> {
+        let secret_key = self.record("sign", secret_key)?;
+        self.inner().sign(secret_key, data)
+    }
+}
+
+impl<V: SecretVault + VerifierVault> VerifierVault for AuditVault<V> {
+    fn verify(
+        &self,
+        signature: &[u8; 64],
+        public_key: &[u8],
+        public_key_type: SecretType,
+        data: &[u8],
+    ) -> OckamResult<()> {
+        self.inner()
+            .verify(signature, public_key, public_key_type, data)
+    }
+}
+
+impl<V: SecretVault + RandomVault> RandomVault for AuditVault<V> {
+    fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+        self.inner().random_bytes_generate(buffer)
+    }
+}
+
+impl<V: SecretVault + PersistentVault> PersistentVault for AuditVault<V> {
+    fn get_persistence_id(&self, secret: &dyn Secret) -> OckamResult<String> {
+        let secret = AuditVaultSecret::downcast_secret(secret)?;
+        self.inner().get_persistence_id(secret.inner.as_ref())
+    }
+
+    fn get_persistent_secret(&self, persistence_id: &str) -> OckamResult<Box<dyn Secret>> {
+        let inner = self.inner().get_persistent_secret(persistence_id)?;
+        Ok(Box::new(AuditVaultSecret {
+            inner,
+            name: persistence_id.to_string(),
+        }))
+    }
+
+    fn get_persistent_secret_by_label(&self, label: &str) -> OckamResult<Box<dyn Secret>> {
+        let inner = self.inner().get_persistent_secret_by_label(label)?;
+        let name = self.inner().get_persistence_id(inner.as_ref())?;
+        Ok(Box::new(AuditVaultSecret { inner, name }))
+    }
+
+    fn get_secret_metadata(&self, secret: &dyn Secret) -> OckamResult<SecretMetadata> {
+        let secret = AuditVaultSecret::downcast_secret(secret)?;
+        self.inner().get_secret_metadata(secret.inner.as_ref())
+    }
+
+    fn set_secret_label(&self, secret: &dyn Secret, label: Option<&str>) -> OckamResult<()> {
+        let secret = self.record("set_secret_label", secret)?;
+        self.inner().set_secret_label(secret, label)
+    }
+
+    fn set_secret_tags(&self, secret: &dyn Secret, tags: Vec<String>) -> OckamResult<()> {
+        let secret = self.record("set_secret_tags", secret)?;
+        self.inner().set_secret_tags(secret, tags)
+    }
+
+    fn list_persistent_secrets(&self) -> OckamResult<Vec<PersistentSecretInfo>> {
+        self.inner().list_persistent_secrets()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam_vault::types::{SecretPersistence, SecretUsage};
+    use ockam_vault_software::DefaultVault;
+    use std::path::PathBuf;
+
+    fn fresh_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ockam_vault_audit_{}.log", name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn attributes() -> SecretAttributes {
+        SecretAttributes {
+            stype: SecretType::Curve25519,
+            persistence: SecretPersistence::Persistent,
+            length: 32,
+            exportable: false,
+            usage: SecretUsage::SIGN | SecretUsage::ECDH,
+        }
+    }
+
+    #[test]
+    fn operations_are_recorded() {
+        let path = fresh_log("operations_are_recorded");
+        let vault = AuditVault::new(DefaultVault::default(), &path)
+            .unwrap()
+            .for_caller("test")
+            .unwrap();
+        assert!(vault.for_caller("a\tb").is_err());
+
+        let identity = vault.secret_generate(attributes()).unwrap();
+        let public_key = vault.secret_public_key_get(identity.as_ref()).unwrap();
+        let signature = vault.sign(identity.as_ref(), b"data").unwrap();
+        vault
+            .verify(
+                &signature,
+                public_key.as_ref(),
+                SecretType::Curve25519,
+                b"data",
+            )
+            .unwrap();
+        let peer = vault.secret_generate(attributes()).unwrap();
+        let peer_public_key = vault.secret_public_key_get(peer.as_ref()).unwrap();
+        vault
+            .ec_diffie_hellman(identity.as_ref(), peer_public_key.as_ref())
+            .unwrap();
+        // refused by the inner vault, but recorded
+        assert!(vault.secret_export(identity.as_ref()).is_err());
+        vault.secret_destroy(peer).unwrap();
+
+        let records = verify_log(&path, &vault.head()).unwrap();
+        let operations: Vec<(&str, &str)> = records
+            .iter()
+            .map(|r| (r.operation.as_str(), r.secret.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                ("secret_generate", "#1"),
+                ("sign", "#1"),
+                ("secret_generate", "#2"),
+                ("ec_diffie_hellman", "#1"),
+                ("secret_export", "#1"),
+                ("secret_destroy", "#2"),
+            ]
+        );
+        assert!(records.iter().all(|r| r.caller == "test"));
+        assert_eq!(records[1].attributes, Some(attributes()));
+    }
+
+    #[test]
+    fn ephemeral_secrets_are_recorded_on_export_only() {
+        let path = fresh_log("ephemeral_secrets_are_recorded_on_export_only");
+        let vault = AuditVault::new(DefaultVault::default(), &path).unwrap();
+        let identity = vault.secret_generate(attributes()).unwrap();
+        let ephemeral = vault
+            .secret_generate(SecretAttributes {
+                persistence: SecretPersistence::Ephemeral,
+                exportable: true,
+                ..attributes()
+            })
+            .unwrap();
+
+        let public_key = vault.secret_public_key_get(ephemeral.as_ref()).unwrap();
+        let shared = vault
+            .ec_diffie_hellman(identity.as_ref(), public_key.as_ref())
+            .unwrap();
+        vault.sign(ephemeral.as_ref(), b"data").unwrap();
+        vault.secret_export(ephemeral.as_ref()).unwrap();
+        vault.secret_destroy(ephemeral).unwrap();
+        vault.secret_destroy(shared).unwrap();
+
+        let records = verify_log(&path, &vault.head()).unwrap();
+        let operations: Vec<(&str, &str)> = records
+            .iter()
+            .map(|r| (r.operation.as_str(), r.secret.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                ("secret_generate", "#1"),
+                ("ec_diffie_hellman", "#1"),
+                ("secret_export", "#2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn wrapped_secrets() {
+        let path = fresh_log("wrapped_secrets");
+        let inner = DefaultVault::default();
+        let identity = inner.secret_generate(attributes()).unwrap();
+        let vault = AuditVault::new(inner, &path).unwrap();
+        // secrets of the inner vault have to be wrapped first
+        assert!(vault.sign(identity.as_ref(), b"data").is_err());
+        let identity = vault.wrap_secret(identity, "identity").unwrap();
+        vault.sign(identity.as_ref(), b"data").unwrap();
+        let head = vault.head();
+        drop(vault);
+
+        // the log continues when the vault is opened again
+        let vault = AuditVault::new(DefaultVault::default(), &path).unwrap();
+        vault.secret_generate(attributes()).unwrap();
+        let records = verify_log(&path, &head).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].secret.as_deref(), Some("identity"));
+        assert_eq!(records[0].caller, "unknown");
+    }
+
+    #[test]
+    fn callers_share_the_log() {
+        let path = fresh_log("callers_share_the_log");
+        let vault = AuditVault::new(DefaultVault::default(), &path).unwrap();
+        let alice = vault.for_caller("alice").unwrap();
+        let bob = vault.for_caller("bob").unwrap();
+
+        let secret = alice.secret_generate(attributes()).unwrap();
+        bob.sign(secret.as_ref(), b"data").unwrap();
+        alice.sign(secret.as_ref(), b"data").unwrap();
+        bob.secret_generate(attributes()).unwrap();
+
+        let records = verify_log(&path, &vault.head()).unwrap();
+        let callers: Vec<(&str, &str)> = records
+            .iter()
+            .map(|r| (r.caller.as_str(), r.secret.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            callers,
+            vec![
+                ("alice", "#1"),
+                ("bob", "#1"),
+                ("alice", "#1"),
+                ("bob", "#2")
+            ]
+        );
+    }
+
+    /// A vault that can't read the attributes of its secrets and counts the secrets
+    /// it destroyed
+    #[derive(Default)]
+    struct UnreadableVault {
+        inner: DefaultVault,
+        destroyed: AtomicUsize,
+    }
+
+    impl Zeroize for UnreadableVault {
+        fn zeroize(&mut self) {}
+    }
+
+    impl SecretVault for UnreadableVault {
+        fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+            self.inner.secret_generate(attributes)
+        }
+
+        fn secret_import(
+            &self,
+            secret: &[u8],
+            attributes: SecretAttributes,
+        ) -> OckamResult<Box<dyn Secret>> {
+            self.inner.secret_import(secret, attributes)
+        }
+
+        fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+            self.inner.secret_export(context)
+        }
+
+        fn secret_attributes_get(&self, _context: &dyn Secret) -> OckamResult<SecretAttributes> {
+            Err(Error::None.into())
+        }
+
+        fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
+            self.inner.secret_public_key_get(context)
+        }
+
+        fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+            self.destroyed.fetch_add(1, Ordering::Relaxed);
+            self.inner.secret_destroy(context)
+        }
+    }
+
+    #[test]
+    fn unrecorded_secrets_are_destroyed() {
+        let path = fresh_log("unrecorded_secrets_are_destroyed");
+        let vault = AuditVault::new(UnreadableVault::default(), &path).unwrap();
+
+        assert!(vault.secret_generate(attributes()).is_err());
+        assert!(vault.secret_import(&[1u8; 32], attributes()).is_err());
+        assert_eq!(vault.inner().destroyed.load(Ordering::Relaxed), 2);
+        assert!(verify_log(&path, &AuditHead::default()).unwrap().is_empty());
+    }
+}
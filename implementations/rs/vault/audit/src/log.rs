@@ -0,0 +1,306 @@
+use crate::error::Error;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::SecretAttributes;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HASH_LENGTH: usize = 32;
+const FIELD_COUNT: usize = 8;
+/// Stands in for the secret and attributes of operations that don't use a secret
+const NO_VALUE: &str = "-";
+
+/// Position of the last record in a log. Kept outside the log, it lets `verify_log`
+/// detect records removed from the end, which the hash chain alone can't.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AuditHead {
+    /// Number of records
+    pub records: u64,
+    /// Hash of the last record, zero for an empty log
+    pub hash: [u8; HASH_LENGTH],
+}
+
+/// One vault operation in the audit log
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditRecord {
+    /// Position in the log, starting at 0
+    pub sequence: u64,
+    /// Seconds since the UNIX epoch
+    pub timestamp: u64,
+    /// Caller tag set on the vault when the operation ran
+    pub caller: String,
+    /// Name of the vault trait function
+    pub operation: String,
+    /// Name of the secret the operation used
+    pub secret: Option<String>,
+    /// Attributes of the secret
+    pub attributes: Option<SecretAttributes>,
+    /// Hash of the previous record
+    pub previous: [u8; HASH_LENGTH],
+    /// SHA-256 of this record up to the hash
+    pub hash: [u8; HASH_LENGTH],
+}
+
+impl AuditRecord {
+    /// The record without its hash, one tab separated line
+    fn body(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.sequence,
+            self.timestamp,
+            self.caller,
+            self.operation,
+            self.secret.as_deref().unwrap_or(NO_VALUE),
+            self.attributes
+                .map(|a| hex::encode(a.to_bytes()))
+                .unwrap_or_else(|| NO_VALUE.to_string()),
+            hex::encode(self.previous),
+        )
+    }
+
+    fn parse(line: &str) -> OckamResult<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != FIELD_COUNT {
+            return Err(Error::InvalidRecord.into());
+        }
+        let number = |s: &str| s.parse::<u64>().map_err(|_| Error::InvalidRecord);
+        let optional = |s: &str| Some(s.to_string()).filter(|s| s != NO_VALUE);
+        let attributes = match fields[5] {
+            NO_VALUE => None,
+            attributes => {
+                let bytes = hex::decode(attributes).map_err(|_| Error::InvalidRecord)?;
+                let bytes =
+                    <[u8; 8]>::try_from(bytes.as_slice()).map_err(|_| Error::InvalidRecord)?;
+                Some(SecretAttributes::try_from(bytes).map_err(|_| Error::InvalidRecord)?)
+            }
+        };
+        Ok(Self {
+            sequence: number(fields[0])?,
+            timestamp: number(fields[1])?,
+            caller: fields[2].to_string(),
+            operation: fields[3].to_string(),
+            secret: optional(fields[4]),
+            attributes,
+            previous: parse_hash(fields[6])?,
+            hash: parse_hash(fields[7])?,
+        })
+    }
+}
+
+fn parse_hash(s: &str) -> OckamResult<[u8; HASH_LENGTH]> {
+    let bytes = hex::decode(s).map_err(|_| Error::InvalidRecord)?;
+    <[u8; HASH_LENGTH]>::try_from(bytes.as_slice()).map_err(|_| Error::InvalidRecord.into())
+}
+
+fn hash(body: &str) -> [u8; HASH_LENGTH] {
+    let mut hash = [0u8; HASH_LENGTH];
+    hash.copy_from_slice(&Sha256::digest(body.as_bytes()));
+    hash
+}
+
+/// Tags and names end up in tab separated lines
+pub(crate) fn check_tag(tag: &str) -> OckamResult<()> {
+    if tag.is_empty() || tag == NO_VALUE || tag.chars().any(char::is_control) {
+        return Err(Error::InvalidTag.into());
+    }
+    Ok(())
+}
+
+/// Read the log at `path` and check that every record is in sequence and chained to
+/// the one before it, and that the log contains the record `checkpoint` points to.
+/// Returns the records.
+///
+/// The chain is unkeyed, whoever can write the log can also cut records off its end
+/// or replace it with a shorter chain. Only a checkpoint kept where the log's writer
+/// can't change it, like the head of an earlier verification, detects that. The
+/// default head only fits a log that is verified for the first time.
+pub fn verify_log(path: &Path, checkpoint: &AuditHead) -> OckamResult<Vec<AuditRecord>> {
+    let data = std::fs::read_to_string(path).map_err(|_| Error::IOError)?;
+    if !data.is_empty() && !data.ends_with('\n') {
+        return Err(Error::InvalidRecord.into());
+    }
+    let mut records: Vec<AuditRecord> = vec![];
+    for line in data.lines() {
+        let record = AuditRecord::parse(line)?;
+        let previous = records.last().map(|r| r.hash).unwrap_or_default();
+        if record.sequence != records.len() as u64
+            || record.previous != previous
+            || record.hash != hash(&record.body())
+        {
+            return Err(Error::BrokenChain.into());
+        }
+        records.push(record);
+    }
+    let matches = match checkpoint.records {
+        0 => true,
+        n => records
+            .get(n as usize - 1)
+            .is_some_and(|r| r.hash == checkpoint.hash),
+    };
+    if !matches {
+        return Err(Error::CheckpointMismatch.into());
+    }
+    Ok(records)
+}
+
+/// An append-only, hash chained log file. Each record is a tab separated line ending in
+/// the hex encoded SHA-256 of the rest of the line, which includes the hash of the
+/// record before it. Records are synced to disk before the operation they describe runs.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    file: File,
+    head: AuditHead,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, creating it if it doesn't exist. An existing log
+    /// has to verify.
+    pub fn open(path: &Path) -> OckamResult<Self> {
+        let head = if path.exists() {
+            verify_log(path, &AuditHead::default())?
+                .last()
+                .map(|r| AuditHead {
+                    records: r.sequence + 1,
+                    hash: r.hash,
+                })
+                .unwrap_or_default()
+        } else {
+            AuditHead::default()
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|_| Error::IOError)?;
+        Ok(Self { file, head })
+    }
+
+    pub fn head(&self) -> AuditHead {
+        self.head
+    }
+
+    pub fn append(
+        &mut self,
+        caller: &str,
+        operation: &str,
+        secret: Option<&str>,
+        attributes: Option<SecretAttributes>,
+    ) -> OckamResult<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut record = AuditRecord {
+            sequence: self.head.records,
+            timestamp,
+            caller: caller.to_string(),
+            operation: operation.to_string(),
+            secret: secret.map(str::to_string),
+            attributes,
+            previous: self.head.hash,
+            hash: [0u8; HASH_LENGTH],
+        };
+        let body = record.body();
+        record.hash = hash(&body);
+        let line = format!("{}\t{}\n", body, hex::encode(record.hash));
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|_| Error::IOError)?;
+        self.head = AuditHead {
+            records: record.sequence + 1,
+            hash: record.hash,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam_vault::types::{SecretPersistence, SecretType, SecretUsage};
+    use std::path::PathBuf;
+
+    fn fresh_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ockam_vault_audit_{}.log", name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn write_log(path: &Path) -> AuditHead {
+        let attributes = SecretAttributes {
+            stype: SecretType::Curve25519,
+            persistence: SecretPersistence::Persistent,
+            length: 32,
+            exportable: false,
+            usage: SecretUsage::ECDH,
+        };
+        let mut log = AuditLog::open(path).unwrap();
+        log.append("ockamd", "secret_generate", Some("1.key"), Some(attributes))
+            .unwrap();
+        log.append(
+            "ockamd",
+            "ec_diffie_hellman",
+            Some("1.key"),
+            Some(attributes),
+        )
+        .unwrap();
+        log.append("ockamd", "random_bytes_generate", None, None)
+            .unwrap();
+        log.head()
+    }
+
+    #[test]
+    fn append_verify_reopen() {
+        let path = fresh_log("append_verify_reopen");
+        let head = write_log(&path);
+        assert_eq!(head.records, 3);
+        let records = verify_log(&path, &head).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].operation, "ec_diffie_hellman");
+        assert_eq!(records[1].secret.as_deref(), Some("1.key"));
+        assert_eq!(records[1].previous, records[0].hash);
+        assert_eq!(records[2].attributes, None);
+
+        // a reopened log continues the chain
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.head(), head);
+        log.append("other", "sign", Some("2.key"), None).unwrap();
+        let records = verify_log(&path, &head).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].previous, head.hash);
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let path = fresh_log("tampering_is_detected");
+        let head = write_log(&path);
+        let data = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+
+        let code = |data: String| {
+            std::fs::write(&path, data).unwrap();
+            verify_log(&path, &head).unwrap_err().code()
+        };
+        // an edited field
+        let edited = data.replacen("ec_diffie_hellman", "secret_attributes", 1);
+        assert_eq!(code(edited), Error::BrokenChain as u32);
+        // a removed record
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        assert_eq!(code(removed), Error::BrokenChain as u32);
+        // records removed from the end
+        let truncated = format!("{}\n{}\n", lines[0], lines[1]);
+        assert_eq!(code(truncated), Error::CheckpointMismatch as u32);
+        // a partly written record
+        assert_eq!(
+            code(data[..data.len() - 10].to_string()),
+            Error::InvalidRecord as u32
+        );
+        // an edited log doesn't open
+        std::fs::write(&path, data.replacen("ockamd", "someone", 1)).unwrap();
+        assert!(AuditLog::open(&path).is_err());
+    }
+}
@@ -0,0 +1,601 @@
+use crate::error::Error;
+use crate::sealing::{Sealer, StorageKey};
+use crate::{id_to_metadata_path, id_to_path, metadata, FilesystemVault, FILENAME_KEY_SUFFIX};
+use ockam_common::error::OckamResult;
+use ockam_vault::types::{SecretAttributes, SecretKey, SecretMetadata, SecretPersistence};
+use ockam_vault::{Secret, SecretVault};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::RwLockWriteGuard;
+use zeroize::Zeroize;
+
+/// Environment variable holding the passphrase backup bundles are sealed with
+pub const BACKUP_PASSPHRASE_ENV_VAR: &str = "OCKAM_BACKUP_PASSPHRASE";
+
+const BACKUP_VERSION: u8 = 1;
+
+/// A persistent secret with its attributes and metadata, as stored in a backup
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BackupSecret {
+    /// Persistence id the secret had in the vault it was backed up from
+    persistence_id: String,
+    attributes: SecretAttributes,
+    key: SecretKey,
+    metadata: SecretMetadata,
+}
+
+impl Drop for BackupSecret {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// The persistent secrets of a vault, sealed into a single bundle to move them
+/// to another machine. Only sealed bundles leave the crate.
+///
+/// A bundle is sealed like an encrypted key file, with AES-256-GCM under a key derived
+/// from a passphrase with scrypt, so it can't be read or modified without the
+/// passphrase. Bundles hold non-exportable secrets too, treat them like the vault itself.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct VaultBackup {
+    secrets: Vec<BackupSecret>,
+}
+
+/// What `FilesystemVault::restore` does with a secret whose id or label is already
+/// used in the vault
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Restore nothing and fail with `RestoreConflict`
+    Abort,
+    /// Leave the secret out
+    Skip,
+    /// Restore the secret under a new id, without its label if the label is taken
+    KeepBoth,
+}
+
+/// Outcome of `FilesystemVault::restore`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RestoreReport {
+    /// Persistence ids from the backup with the persistence ids they were restored as
+    pub restored: Vec<(String, String)>,
+    /// Persistence ids from the backup that were left out because of a conflict
+    pub skipped: Vec<String>,
+    /// Persistence ids from the backup that were restored without their label
+    pub unlabelled: Vec<String>,
+}
+
+impl VaultBackup {
+    /// Seal the backup into a bundle with `key`
+    pub fn seal(&self, key: StorageKey) -> OckamResult<Vec<u8>> {
+        let mut plaintext = self.encode();
+        let sealed = Sealer::new(key).seal_backup(&plaintext);
+        plaintext.zeroize();
+        sealed
+    }
+
+    /// Open a bundle sealed with `key`
+    pub fn open(bundle: &[u8], key: StorageKey) -> OckamResult<Self> {
+        let mut plaintext = Sealer::new(key).open_backup(bundle)?;
+        let backup = Self::decode(&plaintext);
+        plaintext.zeroize();
+        backup
+    }
+
+    /// Import every secret into `vault`, ids and metadata are not kept.
+    /// Returns the secrets in the order of the backup.
+    fn restore_into<V: SecretVault>(&self, vault: &V) -> OckamResult<Vec<Box<dyn Secret>>> {
+        let mut restored = Vec::with_capacity(self.secrets.len());
+        for secret in &self.secrets {
+            match vault.secret_import(secret.key.as_ref(), secret.attributes) {
+                Ok(context) => restored.push(context),
+                Err(e) => {
+                    for context in restored {
+                        let _ = vault.secret_destroy(context);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Encode as version | count (u32) | secrets, a secret is the persistence id |
+    /// attributes (8 bytes) | key | metadata, prefixed with their u32 length but
+    /// for the attributes
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![BACKUP_VERSION];
+        out.extend_from_slice(&(self.secrets.len() as u32).to_be_bytes());
+        for secret in &self.secrets {
+            encode_bytes(&mut out, secret.persistence_id.as_bytes());
+            out.extend_from_slice(&secret.attributes.to_bytes());
+            encode_bytes(&mut out, secret.key.as_ref());
+            encode_bytes(&mut out, &metadata::encode(&secret.metadata));
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> OckamResult<Self> {
+        let mut reader = Reader(data);
+        if reader.take(1)?[0] != BACKUP_VERSION {
+            return Err(Error::InvalidBackup.into());
+        }
+        let count = reader.u32()?;
+        let mut secrets = vec![];
+        for _ in 0..count {
+            let persistence_id = String::from_utf8(reader.bytes()?.to_vec())
+                .map_err(|_| Error::InvalidBackup.into())?;
+            let mut attributes = [0u8; 8];
+            attributes.copy_from_slice(reader.take(8)?);
+            let attributes =
+                SecretAttributes::try_from(attributes).map_err(|_| Error::InvalidBackup.into())?;
+            let key = SecretKey::new(reader.bytes()?.to_vec());
+            let metadata =
+                metadata::decode(reader.bytes()?).map_err(|_| Error::InvalidBackup.into())?;
+            secrets.push(BackupSecret {
+                persistence_id,
+                attributes,
+                key,
+                metadata,
+            });
+        }
+        if !reader.0.is_empty() {
+            return Err(Error::InvalidBackup.into());
+        }
+        Ok(Self { secrets })
+    }
+}
+
+fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> OckamResult<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(Error::InvalidBackup.into());
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> OckamResult<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn bytes(&mut self) -> OckamResult<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+/// Open the bundle sealed with `key` and import every secret into `vault`, ids and
+/// metadata are not kept. Returns the secrets in the order of the backup, nothing is
+/// imported if one of them fails.
+pub fn restore_backup_into<V: SecretVault>(
+    bundle: &[u8],
+    key: StorageKey,
+    vault: &V,
+) -> OckamResult<Vec<Box<dyn Secret>>> {
+    VaultBackup::open(bundle, key)?.restore_into(vault)
+}
+
+/// The secrets and metadata of a vault, locked for a whole restore so other calls don't
+/// see half of it
+struct Restoring<'a> {
+    metadata: RwLockWriteGuard<'a, BTreeMap<usize, SecretMetadata>>,
+    map: RwLockWriteGuard<'a, BTreeMap<usize, Box<dyn Secret>>>,
+}
+
+fn parse_persistence_id(persistence_id: &str) -> OckamResult<usize> {
+    persistence_id
+        .strip_suffix(FILENAME_KEY_SUFFIX)
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| Error::InvalidBackup.into())
+}
+
+impl FilesystemVault {
+    /// Seal every persistent secret with its attributes and metadata into a bundle with
+    /// `key`. The keys are read from the key files, so non-exportable secrets are
+    /// included, a sealed backup is the only way they leave the vault.
+    pub fn backup(&self, key: StorageKey) -> OckamResult<Vec<u8>> {
+        self.collect_backup()?.seal(key)
+    }
+
+    fn collect_backup(&self) -> OckamResult<VaultBackup> {
+        let all = self.metadata.read().unwrap().clone();
+        let mut secrets = Vec::with_capacity(all.len());
+        for (id, metadata) in all {
+            let (key, attributes) = self.read_key_file(id)?;
+            secrets.push(BackupSecret {
+                persistence_id: format!("{}{}", id, FILENAME_KEY_SUFFIX),
+                attributes,
+                key,
+                metadata,
+            });
+        }
+        Ok(VaultBackup { secrets })
+    }
+
+    /// Restore the secrets of the bundle sealed with `key` as persistent secrets with
+    /// their metadata. A secret keeps its persistence id unless the id or its label is
+    /// already used in the vault, `policy` decides what happens then. The whole backup
+    /// is checked before anything is written and a restore that fails halfway is rolled
+    /// back.
+    pub fn restore(
+        &self,
+        bundle: &[u8],
+        key: StorageKey,
+        policy: ConflictPolicy,
+    ) -> OckamResult<RestoreReport> {
+        let backup = VaultBackup::open(bundle, key)?;
+        self.restore_backup(&backup, policy)
+    }
+
+    fn restore_backup(
+        &self,
+        backup: &VaultBackup,
+        policy: ConflictPolicy,
+    ) -> OckamResult<RestoreReport> {
+        let mut ids = BTreeSet::new();
+        for secret in &backup.secrets {
+            if !ids.insert(parse_persistence_id(&secret.persistence_id)?)
+                || !matches!(secret.attributes.persistence, SecretPersistence::Persistent)
+            {
+                return Err(Error::InvalidBackup.into());
+            }
+            if let Some(label) = &secret.metadata.label {
+                metadata::check_label(label).map_err(|_| Error::InvalidBackup.into())?;
+            }
+            for tag in &secret.metadata.tags {
+                metadata::check_tag(tag).map_err(|_| Error::InvalidBackup.into())?;
+            }
+        }
+
+        let mut maps = Restoring {
+            metadata: self.metadata.write().unwrap(),
+            map: self.map.write().unwrap(),
+        };
+        let mut labels: BTreeSet<String> = maps
+            .metadata
+            .values()
+            .filter_map(|m| m.label.clone())
+            .collect();
+        let mut report = RestoreReport::default();
+        // (secret, id to restore as, keep the label)
+        let mut planned = vec![];
+        for secret in &backup.secrets {
+            let id = parse_persistence_id(&secret.persistence_id)?;
+            let id_taken = maps.map.contains_key(&id) || self.path.join(id_to_path(id)).exists();
+            let label_taken = secret.metadata.label.iter().any(|l| labels.contains(l));
+            if !id_taken && !label_taken {
+                if let Some(label) = &secret.metadata.label {
+                    labels.insert(label.clone());
+                }
+                planned.push((secret, Some(id), true));
+                continue;
+            }
+            match policy {
+                ConflictPolicy::Abort => return Err(Error::RestoreConflict.into()),
+                ConflictPolicy::Skip => report.skipped.push(secret.persistence_id.clone()),
+                ConflictPolicy::KeepBoth => {
+                    if label_taken {
+                        report.unlabelled.push(secret.persistence_id.clone());
+                    } else if let Some(label) = &secret.metadata.label {
+                        labels.insert(label.clone());
+                    }
+                    planned.push((secret, if id_taken { None } else { Some(id) }, !label_taken));
+                }
+            }
+        }
+
+        // ids from the backup are taken first, so new ids don't land on them
+        for (_, id, _) in &planned {
+            if let Some(id) = id {
+                self.next_id.fetch_max(*id, Ordering::Relaxed);
+            }
+        }
+        let mut restored = vec![];
+        for (secret, id, keep_label) in planned {
+            if let Err(e) = self.restore_secret(&mut maps, secret, id, keep_label, &mut restored) {
+                self.roll_back(&mut maps, &restored);
+                return Err(e);
+            }
+            report.restored.push((
+                secret.persistence_id.clone(),
+                format!("{}{}", restored[restored.len() - 1], FILENAME_KEY_SUFFIX),
+            ));
+        }
+        Ok(report)
+    }
+
+    /// Add `secret` to the vault as `id` or a new id, the id is pushed to `restored` as
+    /// soon as the secret is in the vault
+    fn restore_secret(
+        &self,
+        maps: &mut Restoring,
+        secret: &BackupSecret,
+        id: Option<usize>,
+        keep_label: bool,
+        restored: &mut Vec<usize>,
+    ) -> OckamResult<()> {
+        let context = self
+            .v
+            .secret_import(secret.key.as_ref(), secret.attributes)?;
+        let id = match id {
+            Some(id) => id,
+            None => self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+        };
+        maps.map.insert(id, context);
+        restored.push(id);
+        self.write_key_file(id, secret.key.as_ref(), secret.attributes)?;
+        let mut metadata = secret.metadata.clone();
+        if !keep_label {
+            metadata.label = None;
+        }
+        self.write_metadata(id, &metadata)?;
+        maps.metadata.insert(id, metadata);
+        Ok(())
+    }
+
+    /// Remove the secrets of a failed restore, their ids were free before it
+    fn roll_back(&self, maps: &mut Restoring, restored: &[usize]) {
+        for id in restored {
+            for path in &[id_to_path(*id), id_to_metadata_path(*id)] {
+                let _ = fs::remove_file(self.path.join(path));
+            }
+            maps.metadata.remove(id);
+            if let Some(context) = maps.map.remove(id) {
+                let _ = self.v.secret_destroy(context);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TestDir;
+    use crate::KdfParams;
+    use ockam_vault::types::{SecretType, SecretUsage};
+    use ockam_vault::PersistentVault;
+    use ockam_vault_software::DefaultVault;
+
+    fn test_passphrase(passphrase: &str) -> StorageKey {
+        StorageKey::Passphrase(
+            passphrase.to_string(),
+            KdfParams {
+                log_n: 4,
+                r: 8,
+                p: 1,
+            },
+        )
+    }
+
+    fn attributes(exportable: bool) -> SecretAttributes {
+        SecretAttributes {
+            stype: SecretType::Curve25519,
+            persistence: SecretPersistence::Persistent,
+            length: 32,
+            exportable,
+            usage: SecretUsage::ECDH,
+        }
+    }
+
+    fn labelled_vault(dir: &TestDir, label: &str) -> (FilesystemVault, Box<dyn Secret>) {
+        let vault = FilesystemVault::new(dir.to_path_buf()).unwrap();
+        let secret = vault.secret_generate(attributes(false)).unwrap();
+        vault
+            .set_secret_label(secret.as_ref(), Some(label))
+            .unwrap();
+        vault
+            .set_secret_tags(secret.as_ref(), vec!["role=sink".into()])
+            .unwrap();
+        (vault, secret)
+    }
+
+    #[test]
+    fn seal_open() {
+        let dir = TestDir::new("seal_open");
+        let (vault, _) = labelled_vault(&dir, "identity");
+        vault
+            .secret_generate(SecretAttributes {
+                persistence: SecretPersistence::Ephemeral,
+                ..attributes(true)
+            })
+            .unwrap();
+        let backup = vault.collect_backup().unwrap();
+        // ephemeral secrets are left out
+        assert_eq!(backup.secrets.len(), 1);
+        assert_eq!(
+            backup.secrets[0].metadata.label.as_deref(),
+            Some("identity")
+        );
+
+        let bundle = backup.seal(test_passphrase("correct horse")).unwrap();
+        assert_eq!(
+            VaultBackup::open(&bundle, test_passphrase("correct horse")).unwrap(),
+            backup
+        );
+        let error = VaultBackup::open(&bundle, test_passphrase("battery staple")).unwrap_err();
+        assert_eq!(error.code(), Error::DecryptionFailed as u32);
+        let mut tampered = bundle.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(VaultBackup::open(&tampered, test_passphrase("correct horse")).is_err());
+
+        let encoded = backup.encode();
+        assert!(VaultBackup::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(VaultBackup::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn restore_into_another_vault() {
+        let source_dir = TestDir::new("restore_source");
+        let (source, secret) = labelled_vault(&source_dir, "identity");
+        let public_key = source.secret_public_key_get(secret.as_ref()).unwrap();
+        let bundle = source.backup(test_passphrase("pass")).unwrap();
+        drop(source);
+
+        let dir = TestDir::new("restore_target");
+        let target = FilesystemVault::new_encrypted(dir.clone(), test_passphrase("vault")).unwrap();
+        let report = target
+            .restore(&bundle, test_passphrase("pass"), ConflictPolicy::Abort)
+            .unwrap();
+        assert_eq!(
+            report.restored,
+            vec![("1.key".to_string(), "1.key".to_string())]
+        );
+        drop(target);
+
+        // the restored secret is persisted with its policy and metadata
+        let target =
+            FilesystemVault::new_encrypted(dir.to_path_buf(), test_passphrase("vault")).unwrap();
+        let restored = target.get_persistent_secret_by_label("identity").unwrap();
+        assert_eq!(
+            target.secret_public_key_get(restored.as_ref()).unwrap(),
+            public_key
+        );
+        assert!(target.secret_export(restored.as_ref()).is_err());
+        let metadata = target.get_secret_metadata(restored.as_ref()).unwrap();
+        assert_eq!(metadata.tags, vec!["role=sink".to_string()]);
+        // new secrets don't reuse restored ids
+        let secret = target.secret_generate(attributes(true)).unwrap();
+        assert_eq!(target.get_persistence_id(secret.as_ref()).unwrap(), "2.key");
+
+        let vault = DefaultVault::default();
+        let restored = restore_backup_into(&bundle, test_passphrase("pass"), &vault).unwrap();
+        assert_eq!(
+            vault.secret_public_key_get(restored[0].as_ref()).unwrap(),
+            public_key
+        );
+    }
+
+    #[test]
+    fn restore_conflicts() {
+        let source_dir = TestDir::new("conflicts_source");
+        let (source, _) = labelled_vault(&source_dir, "identity");
+        source.secret_generate(attributes(true)).unwrap();
+        let backup = source.collect_backup().unwrap();
+        drop(source);
+
+        // 1.key is taken and has a different label, 2.key is free but its label isn't
+        let dir = TestDir::new("conflicts_target");
+        let (mut target, _) = labelled_vault(&dir, "other");
+        let mut backup = backup;
+        backup.secrets[1].metadata.label = Some("other".into());
+
+        let error = target
+            .restore_backup(&backup, ConflictPolicy::Abort)
+            .unwrap_err();
+        assert_eq!(error.code(), Error::RestoreConflict as u32);
+        assert_eq!(target.list_persistent_secrets().unwrap().len(), 1);
+
+        let report = target
+            .restore_backup(&backup, ConflictPolicy::Skip)
+            .unwrap();
+        assert!(report.restored.is_empty());
+        assert_eq!(
+            report.skipped,
+            vec!["1.key".to_string(), "2.key".to_string()]
+        );
+
+        let report = target
+            .restore_backup(&backup, ConflictPolicy::KeepBoth)
+            .unwrap();
+        assert_eq!(
+            report.restored,
+            vec![
+                ("1.key".to_string(), "3.key".to_string()),
+                ("2.key".to_string(), "2.key".to_string())
+            ]
+        );
+        assert_eq!(report.unlabelled, vec!["2.key".to_string()]);
+        let restored = target.get_persistent_secret_by_label("identity").unwrap();
+        assert_eq!(
+            target.get_persistence_id(restored.as_ref()).unwrap(),
+            "3.key"
+        );
+        let restored = target.get_persistent_secret("2.key").unwrap();
+        assert_eq!(
+            target.get_secret_metadata(restored.as_ref()).unwrap().label,
+            None
+        );
+        drop(target);
+        assert_eq!(
+            FilesystemVault::new(dir.to_path_buf())
+                .unwrap()
+                .list_persistent_secrets()
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn failed_restores_are_rolled_back() {
+        let source_dir = TestDir::new("rollback_source");
+        let (source, _) = labelled_vault(&source_dir, "identity");
+        source.secret_generate(attributes(true)).unwrap();
+        let bundle = source.backup(test_passphrase("pass")).unwrap();
+        drop(source);
+
+        // the metadata file of the second secret can't be written
+        let dir = TestDir::new("rollback_target");
+        let target = FilesystemVault::new(dir.clone()).unwrap();
+        let blocker = dir.join(id_to_metadata_path(2));
+        std::fs::create_dir(&blocker).unwrap();
+        let error = target
+            .restore(&bundle, test_passphrase("pass"), ConflictPolicy::Abort)
+            .unwrap_err();
+        assert_eq!(error.code(), Error::IOError as u32);
+        assert!(target.list_persistent_secrets().unwrap().is_empty());
+        assert!(target.get_persistent_secret_by_label("identity").is_err());
+        assert!(!dir.join(id_to_path(1)).exists());
+        assert!(!dir.join(id_to_metadata_path(1)).exists());
+        assert!(!dir.join(id_to_path(2)).exists());
+
+        std::fs::remove_dir(&blocker).unwrap();
+        let report = target
+            .restore(&bundle, test_passphrase("pass"), ConflictPolicy::Abort)
+            .unwrap();
+        assert_eq!(report.restored.len(), 2);
+        drop(target);
+        assert_eq!(
+            FilesystemVault::new(dir.to_path_buf())
+                .unwrap()
+                .list_persistent_secrets()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn invalid_backups_are_refused() {
+        let dir = TestDir::new("invalid_backups");
+        let (mut vault, _) = labelled_vault(&dir, "identity");
+        let mut backup = vault.collect_backup().unwrap();
+        backup.secrets[0].persistence_id = "identity".into();
+        let error = vault
+            .restore_backup(&backup, ConflictPolicy::Skip)
+            .unwrap_err();
+        assert_eq!(error.code(), Error::InvalidBackup as u32);
+
+        let mut backup = vault.collect_backup().unwrap();
+        backup.secrets[0].attributes.persistence = SecretPersistence::Ephemeral;
+        assert!(vault.restore_backup(&backup, ConflictPolicy::Skip).is_err());
+
+        let mut backup = vault.collect_backup().unwrap();
+        backup.secrets.push(backup.secrets[0].clone());
+        assert!(vault
+            .restore_backup(&backup, ConflictPolicy::KeepBoth)
+            .is_err());
+    }
+}
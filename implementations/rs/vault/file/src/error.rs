@@ -10,6 +10,32 @@ pub enum Error {
     IOError,
     InvalidPersistenceId,
     EntryNotFound,
+    /// A key file is encrypted but the vault was opened without a storage key
+    StorageKeyRequired,
+    /// A key file is stored in plaintext in an encrypted vault, migrate the vault
+    UnencryptedSecret,
+    /// A key file failed to decrypt, the storage key is wrong or the file was modified
+    DecryptionFailed,
+    /// A key file has an unknown version or a malformed header
+    UnsupportedFormat,
+    /// The storage key is malformed or of the wrong kind for a key file
+    InvalidStorageKey,
+    /// The vault directory is in use by another vault
+    VaultLocked,
+    /// The secret is not persistent and has no metadata
+    SecretNotPersistent,
+    /// A label is empty, too long, contains control characters or looks like a persistence id
+    InvalidLabel,
+    /// Another secret in the vault already has the label
+    LabelInUse,
+    /// A metadata file is malformed
+    InvalidMetadata,
+    /// A backup bundle is malformed or holds an invalid secret
+    InvalidBackup,
+    /// A secret in a backup has the id or label of a secret in the vault
+    RestoreConflict,
+    /// The vault directory belongs to another user and is accessible by others
+    InsecurePermissions,
 }
 
 impl Error {
@@ -1,32 +1,65 @@
 use crate::error::*;
-use ockam_vault::types::{PublicKey, SecretAttributes, SecretKey, SecretPersistence};
+use ockam_vault::types::{
+    PersistentSecretInfo, PublicKey, SecretAttributes, SecretKey, SecretMetadata,
+    SecretPersistence, SecretType,
+};
 use ockam_vault::{
-    AsymmetricVault, HashVault, PersistentVault, Secret, SecretVault, SignerVault, SymmetricVault,
-    VerifierVault,
+    AsymmetricVault, HashVault, PersistentVault, RandomVault, Secret, SecretVault, SignerVault,
+    SymmetricVault, VerifierVault,
 };
 use ockam_vault_software::DefaultVault;
 use std::cmp::max;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
 use zeroize::Zeroize;
 
+pub use backup::{restore_backup_into, ConflictPolicy, RestoreReport, BACKUP_PASSPHRASE_ENV_VAR};
+pub use metadata::{FILENAME_METADATA_SUFFIX, MAX_LABEL_LENGTH};
 use ockam_common::error::OckamResult;
 pub use ockam_vault;
+use sealing::Sealer;
+pub use sealing::{KdfParams, StorageKey, KEK_ENV_VAR, PASSPHRASE_ENV_VAR};
+use storage::VaultLock;
+pub use storage::{QuarantinedFile, LOCK_FILE_NAME, QUARANTINE_DIR_NAME};
 
+mod backup;
 pub mod error;
+mod metadata;
+mod sealing;
+mod storage;
 
-const ATTRS_BYTE_LENGTH: usize = 6;
+/// Key files written before secrets carried a usage policy start with the six attribute
+/// bytes, the first of which is always zero. Newer files start with this version byte.
+const KEY_FILE_VERSION: u8 = 1;
+const LEGACY_ATTRS_BYTE_LENGTH: usize = 6;
+const ATTRS_BYTE_LENGTH: usize = 8;
 
 /// A FilesystemVault is an implementation of an Ockam Vault that wraps the software vault and uses
 /// the disk as a persistent store.
+///
+/// The vault holds an exclusive lock on its directory while it is alive, so only one
+/// vault, in this or another process, can use a directory at a time. Key files are
+/// only readable by the owner and are replaced atomically.
+///
+/// Every persistent secret has a metadata file next to its key file with an optional
+/// label, the creation time and tags. Metadata files are encrypted like key files.
+///
+/// Operations on secrets run in parallel, the files of an encrypted vault are sealed one
+/// at a time.
 #[derive(Debug)]
 pub struct FilesystemVault {
     v: DefaultVault,
     path: PathBuf,
-    map: BTreeMap<usize, Box<dyn Secret>>,
-    next_id: usize,
+    map: RwLock<BTreeMap<usize, Box<dyn Secret>>>,
+    metadata: RwLock<BTreeMap<usize, SecretMetadata>>,
+    next_id: AtomicUsize,
+    sealer: Option<Mutex<Sealer>>,
+    quarantined: Vec<QuarantinedFile>,
+    _lock: VaultLock,
 }
 
 pub const FILENAME_KEY_SUFFIX: &str = ".key";
@@ -36,7 +69,7 @@ pub const FILENAME_KEY_SUFFIX: &str = ".key";
 pub struct FilesystemVaultSecret(usize);
 
 impl FilesystemVaultSecret {
-    pub fn downcast_secret(context: &Box<dyn Secret>) -> OckamResult<&Self> {
+    pub fn downcast_secret(context: &dyn Secret) -> OckamResult<&Self> {
         context
             .downcast_ref::<FilesystemVaultSecret>()
             .map_err(|_| Error::SecretFromAnotherVault.into())
@@ -52,100 +85,255 @@ impl Secret for FilesystemVaultSecret {}
 impl FilesystemVault {
     fn get_entry_map<'a>(
         map: &'a BTreeMap<usize, Box<dyn Secret>>,
-        context: &'a Box<dyn Secret>,
-    ) -> OckamResult<&'a Box<dyn Secret>> {
+        context: &'a dyn Secret,
+    ) -> OckamResult<&'a dyn Secret> {
         let context = FilesystemVaultSecret::downcast_secret(context)?;
-        map.get(&context.0).ok_or(Error::InvalidSecret.into())
+        map.get(&context.0)
+            .map(|s| s.as_ref())
+            .ok_or(Error::InvalidSecret.into())
     }
 
     /// Creates a new FilesystemVault using the provided path on disk to store secrets.
     pub fn new(path: PathBuf) -> OckamResult<Self> {
-        let mut map = BTreeMap::<usize, Box<dyn Secret>>::new();
-        let mut next_id: usize = 0;
+        Self::open(path, None)
+    }
 
-        let create_path = path.clone();
-        fs::create_dir_all(create_path).or_else(|_| Err(Error::IOError.into()))?;
+    /// Creates a new FilesystemVault that keeps its secrets encrypted on disk. Every key
+    /// file is sealed with AES-GCM under a key derived from `storage_key`. Plaintext key
+    /// files are refused, `migrate` upgrades a vault written by `new`.
+    pub fn new_encrypted(path: PathBuf, storage_key: StorageKey) -> OckamResult<Self> {
+        Self::open(path, Some(Sealer::new(storage_key)))
+    }
 
-        let mut vault = DefaultVault::default();
-        let to_secret = |data: &[u8]| -> OckamResult<(SecretKey, SecretAttributes)> {
-            if data.len() < ATTRS_BYTE_LENGTH {
-                return Err(Error::InvalidSecret.into());
+    /// Encrypts the plaintext key files in the vault at `path` with `storage_key`.
+    /// Files that are already encrypted must open with `storage_key` and are left as
+    /// they are. Metadata files are encrypted along with their key files. Returns the
+    /// number of key files that were encrypted.
+    pub fn migrate(path: PathBuf, storage_key: StorageKey) -> OckamResult<usize> {
+        storage::make_private(&path)?;
+        let _lock = VaultLock::acquire(&path)?;
+        let mut sealer = Sealer::new(storage_key);
+        let mut migrated = 0;
+        for (id, file) in key_files(&path)? {
+            let mut data = fs::read(&file).map_err(|_| Error::IOError.into())?;
+            if sealing::is_sealed(&data) {
+                sealer.open(id, &data)?.zeroize();
+            } else {
+                parse_secret(&data)?;
+                let sealed = sealer.seal(id, &data)?;
+                storage::write_atomic(&file, &sealed)?;
+                migrated += 1;
             }
+            data.zeroize();
 
-            let mut attrs = [0u8; ATTRS_BYTE_LENGTH];
-            attrs.copy_from_slice(&data[0..ATTRS_BYTE_LENGTH]);
-            let attributes = SecretAttributes::try_from(attrs)?;
+            let file = path.join(id_to_metadata_path(id));
+            if !file.exists() {
+                continue;
+            }
+            let data = fs::read(&file).map_err(|_| Error::IOError.into())?;
+            if sealing::is_sealed(&data) {
+                sealer.open_metadata(id, &data)?;
+            } else {
+                metadata::decode(&data)?;
+                let sealed = sealer.seal_metadata(id, &data)?;
+                storage::write_atomic(&file, &sealed)?;
+            }
+        }
+        Ok(migrated)
+    }
 
-            Ok((
-                SecretKey::new(data[ATTRS_BYTE_LENGTH..].to_vec()),
-                attributes,
-            ))
-        };
-        let fs_path = path.clone();
-
-        path.read_dir()
-            .or_else(|_| Err(Error::IOError.into()))?
-            .filter(|r| {
-                // ignore directories within vault path
-                if let Ok(e) = r {
-                    match fs::metadata(e.path()) {
-                        Ok(md) => md.is_file(),
-                        Err(_) => false,
-                    }
-                } else {
-                    false
+    /// Key files that could not be loaded when the vault was opened. They were moved to
+    /// the `quarantine` directory inside the vault.
+    pub fn quarantined(&self) -> &[QuarantinedFile] {
+        &self.quarantined
+    }
+
+    fn open(path: PathBuf, mut sealer: Option<Sealer>) -> OckamResult<Self> {
+        let mut map = BTreeMap::<usize, Box<dyn Secret>>::new();
+        let mut next_id: usize = 0;
+
+        storage::create_vault_dir(&path)?;
+        let lock = VaultLock::acquire(&path)?;
+
+        let vault = DefaultVault::default();
+        let mut failed = vec![];
+        let mut upgraded = vec![];
+        let mut opened_sealed = false;
+        for (id, file) in key_files(&path)? {
+            // ids of corrupt files are not handed out again
+            next_id = max(next_id, id);
+            let mut data = fs::read(&file).map_err(|_| Error::IOError.into())?;
+            let plaintext = match (sealing::is_sealed(&data), sealer.as_mut()) {
+                (true, Some(sealer)) => {
+                    let opened = sealer.open(id, &data);
+                    opened_sealed |= opened.is_ok();
+                    opened
                 }
-            })
-            .for_each(|entry| {
-                if let Ok(entry) = entry {
-                    match fs::read(entry.path()) {
-                        Ok(data) => {
-                            let (secret, attrs) =
-                                &to_secret(data.as_slice()).unwrap_or_else(|_| {
-                                    panic!("failed to get secret {:?} from file", entry.file_name())
-                                });
-                            // Files are read in any order
-                            let fname = entry.file_name();
-                            let t: &std::path::Path = fname.as_os_str().as_ref();
-                            let mut item_id: Option<usize> = None;
-                            if let Some(stem) = t.file_stem() {
-                                if let Some(str) = stem.to_str() {
-                                    if let Ok(id) = str.parse::<usize>() {
-                                        item_id = Some(id);
-                                    }
-                                }
-                            }
-                            match item_id {
-                                Some(id) => {
-                                    let secret = vault.secret_import(secret.as_ref(), *attrs);
-                                    match secret {
-                                        Ok(secret) => {
-                                            map.insert(id, secret);
-                                            next_id = max(next_id, id);
-                                        }
-                                        Err(e) => eprintln!("{}", e),
-                                    }
-                                }
-                                None => eprintln!("invalid key file name: {:?}", entry),
-                            }
-                        }
-                        Err(e) => eprintln!("{}", e),
+                (true, None) => return Err(Error::StorageKeyRequired.into()),
+                (false, Some(_)) if parse_secret(&data).is_ok() => {
+                    return Err(Error::UnencryptedSecret.into())
+                }
+                (false, _) => Ok(data.clone()),
+            };
+            data.zeroize();
+            let imported = plaintext.and_then(|mut plaintext| {
+                let parsed = parse_secret(&plaintext);
+                plaintext.zeroize();
+                let (mut secret, attrs) = parsed?;
+                match with_key_length(&secret, attrs) {
+                    Some(attrs) => {
+                        let imported = vault.secret_import(secret.as_ref(), attrs)?;
+                        upgraded.push((id, secret, attrs));
+                        Ok(imported)
+                    }
+                    None => {
+                        let imported = vault.secret_import(secret.as_ref(), attrs);
+                        secret.zeroize();
+                        imported
                     }
                 }
             });
+            match imported {
+                Ok(secret) => {
+                    map.insert(id, secret);
+                }
+                Err(e) => failed.push((id, file, e)),
+            }
+        }
+
+        // With a wrong storage key no file opens, don't mistake that for corruption
+        let decryption_failed = failed.iter().any(|(_, _, e)| {
+            e.domain() == Error::ERROR_DOMAIN && e.code() == Error::DecryptionFailed as u32
+        });
+        if decryption_failed && !opened_sealed {
+            return Err(Error::DecryptionFailed.into());
+        }
 
-        Ok(Self {
+        let mut quarantined = vec![];
+        for (id, file, e) in failed {
+            quarantined.push(storage::quarantine(&file, e)?);
+            let metadata_file = path.join(id_to_metadata_path(id));
+            if metadata_file.exists() {
+                let e = Error::InvalidSecret.into();
+                quarantined.push(storage::quarantine(&metadata_file, e)?);
+            }
+        }
+
+        let mut metadata = BTreeMap::new();
+        for id in map.keys() {
+            let file = path.join(id_to_metadata_path(*id));
+            let loaded = match fs::read(&file) {
+                Ok(data) => Self::read_metadata(sealer.as_mut(), *id, &data),
+                Err(_) => Ok(metadata::default_metadata(&path.join(id_to_path(*id)))),
+            };
+            let loaded = match loaded {
+                Ok(m) => m,
+                Err(e) if e.code() == Error::UnencryptedSecret as u32 => return Err(e),
+                Err(e) => {
+                    quarantined.push(storage::quarantine(&file, e)?);
+                    metadata::default_metadata(&path.join(id_to_path(*id)))
+                }
+            };
+            metadata.insert(*id, loaded);
+        }
+
+        let filesystem_vault = Self {
             v: vault,
-            map,
-            path: fs_path,
-            next_id,
-        })
+            map: RwLock::new(map),
+            metadata: RwLock::new(metadata),
+            path,
+            next_id: AtomicUsize::new(next_id),
+            sealer: sealer.map(Mutex::new),
+            quarantined,
+            _lock: lock,
+        };
+        for (id, mut secret, attrs) in upgraded {
+            let written = filesystem_vault.write_key_file(id, secret.as_ref(), attrs);
+            secret.zeroize();
+            written?;
+        }
+        Ok(filesystem_vault)
     }
 
-    fn add_secret(&mut self, secret: Box<dyn Secret>) -> usize {
-        self.next_id += 1;
-        self.map.insert(self.next_id, secret);
-        self.next_id
+    fn add_secret(&self, secret: Box<dyn Secret>) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.map.write().unwrap().insert(id, secret);
+        id
+    }
+
+    fn write_secret(&self, id: usize, key: &[u8], attrs: SecretAttributes) -> OckamResult<()> {
+        if matches!(attrs.persistence, SecretPersistence::Persistent) {
+            self.write_key_file(id, key, attrs)?;
+            let metadata = SecretMetadata {
+                created_at: metadata::now(),
+                ..Default::default()
+            };
+            self.write_metadata(id, &metadata)?;
+            self.metadata.write().unwrap().insert(id, metadata);
+        }
+        Ok(())
+    }
+
+    fn write_key_file(&self, id: usize, key: &[u8], attrs: SecretAttributes) -> OckamResult<()> {
+        let mut bytes = vec![KEY_FILE_VERSION];
+        bytes.extend_from_slice(&attrs.to_bytes());
+        bytes.extend_from_slice(key.as_ref());
+        if let Some(sealer) = &self.sealer {
+            let sealed = sealer.lock().unwrap().seal(id, &bytes);
+            bytes.zeroize();
+            bytes = sealed?;
+        }
+
+        let written = storage::write_atomic(&self.path.join(id_to_path(id)), &bytes);
+        bytes.zeroize();
+        written
+    }
+
+    /// Read the key and attributes of persistent secret `id` back from its key file
+    fn read_key_file(&self, id: usize) -> OckamResult<(SecretKey, SecretAttributes)> {
+        let mut data =
+            fs::read(self.path.join(id_to_path(id))).map_err(|_| Error::IOError.into())?;
+        let plaintext = match (sealing::is_sealed(&data), &self.sealer) {
+            (true, Some(sealer)) => sealer.lock().unwrap().open(id, &data),
+            (true, None) => Err(Error::StorageKeyRequired.into()),
+            (false, Some(_)) => Err(Error::UnencryptedSecret.into()),
+            (false, None) => Ok(data.clone()),
+        };
+        data.zeroize();
+        let mut plaintext = plaintext?;
+        let parsed = parse_secret(&plaintext);
+        plaintext.zeroize();
+        parsed
+    }
+
+    fn write_metadata(&self, id: usize, metadata: &SecretMetadata) -> OckamResult<()> {
+        let mut bytes = metadata::encode(metadata);
+        if let Some(sealer) = &self.sealer {
+            bytes = sealer.lock().unwrap().seal_metadata(id, &bytes)?;
+        }
+        storage::write_atomic(&self.path.join(id_to_metadata_path(id)), &bytes)
+    }
+
+    fn read_metadata(
+        sealer: Option<&mut Sealer>,
+        id: usize,
+        data: &[u8],
+    ) -> OckamResult<SecretMetadata> {
+        match (sealing::is_sealed(data), sealer) {
+            (true, Some(sealer)) => metadata::decode(&sealer.open_metadata(id, data)?),
+            (true, None) => Err(Error::StorageKeyRequired.into()),
+            (false, Some(_)) => Err(Error::UnencryptedSecret.into()),
+            (false, None) => metadata::decode(data),
+        }
+    }
+
+    fn persistent_id(&self, secret: &dyn Secret) -> OckamResult<usize> {
+        let id = FilesystemVaultSecret::downcast_secret(secret)?.0;
+        if self.metadata.read().unwrap().contains_key(&id) {
+            Ok(id)
+        } else {
+            Err(Error::SecretNotPersistent.into())
+        }
     }
 }
 
@@ -153,83 +341,169 @@ fn id_to_path(id: usize) -> PathBuf {
     format!("{}.key", id.to_string()).into()
 }
 
-fn fs_write_secret(
-    path: PathBuf,
-    id: usize,
-    key: &[u8],
-    attrs: SecretAttributes,
-) -> OckamResult<()> {
-    if matches!(attrs.persistence, SecretPersistence::Persistent) {
-        let mut bytes = attrs.to_bytes().to_vec();
-        bytes.extend_from_slice(key.as_ref());
+fn id_to_metadata_path(id: usize) -> PathBuf {
+    format!("{}{}", id, FILENAME_METADATA_SUFFIX).into()
+}
+
+/// The key files in a vault directory with their ids. Files without the key file
+/// suffix, like the lock file, are skipped silently, key files that aren't named
+/// after an id are reported.
+fn key_files(path: &Path) -> OckamResult<Vec<(usize, PathBuf)>> {
+    let mut files = vec![];
+    for entry in path.read_dir().map_err(|_| Error::IOError.into())? {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        // ignore directories within vault path
+        match fs::metadata(entry.path()) {
+            Ok(md) if md.is_file() => {}
+            _ => continue,
+        }
+        let file_name = entry.file_name();
+        match file_name.to_str() {
+            Some(name) if name.ends_with(FILENAME_KEY_SUFFIX) => {}
+            _ => continue,
+        }
+        // Files are read in any order
+        let id = entry
+            .path()
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<usize>().ok());
+        // files named like key files without an id are not the vault's
+        if let Some(id) = id {
+            files.push((id, entry.path()));
+        }
+    }
+    Ok(files)
+}
+
+/// Returns `attrs` with the length of `secret` if they were stored with a length of 0
+/// before lengths were checked and that length is valid for them.
+fn with_key_length(secret: &SecretKey, attrs: SecretAttributes) -> Option<SecretAttributes> {
+    if attrs.length != 0 || secret.as_ref().is_empty() {
+        return None;
+    }
+    let attrs = SecretAttributes {
+        length: secret.as_ref().len(),
+        ..attrs
+    };
+    attrs.validate_secret(secret.as_ref()).ok().map(|_| attrs)
+}
 
-        fs::write(path.join(id_to_path(id)), bytes).or_else(|_| Err(Error::IOError.into()))?;
+/// Split the plaintext contents of a key file into the secret and its attributes
+fn parse_secret(data: &[u8]) -> OckamResult<(SecretKey, SecretAttributes)> {
+    match data.first() {
+        Some(0) if data.len() >= LEGACY_ATTRS_BYTE_LENGTH => {
+            let mut attrs = [0u8; LEGACY_ATTRS_BYTE_LENGTH];
+            attrs.copy_from_slice(&data[0..LEGACY_ATTRS_BYTE_LENGTH]);
+            let attributes = SecretAttributes::try_from(attrs)?;
+
+            Ok((
+                SecretKey::new(data[LEGACY_ATTRS_BYTE_LENGTH..].to_vec()),
+                attributes,
+            ))
+        }
+        Some(&KEY_FILE_VERSION) if data.len() > ATTRS_BYTE_LENGTH => {
+            let mut attrs = [0u8; ATTRS_BYTE_LENGTH];
+            attrs.copy_from_slice(&data[1..=ATTRS_BYTE_LENGTH]);
+            let attributes = SecretAttributes::try_from(attrs)?;
+
+            Ok((
+                SecretKey::new(data[ATTRS_BYTE_LENGTH + 1..].to_vec()),
+                attributes,
+            ))
+        }
+        _ => Err(Error::InvalidSecret.into()),
     }
-    return Ok(());
 }
 
 impl SecretVault for FilesystemVault {
     /// Create a new secret key
-    fn secret_generate(&mut self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+    fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+        if !matches!(attributes.persistence, SecretPersistence::Persistent) {
+            let ctx = self.v.secret_generate(attributes)?;
+            let id = self.add_secret(ctx);
+            return Ok(Box::new(FilesystemVaultSecret(id)));
+        }
+
+        // the key file needs the key bytes, so generate an exportable secret and
+        // re-import it with the requested policy when it isn't
+        let ctx = self.v.secret_generate(SecretAttributes {
+            exportable: true,
+            ..attributes
+        })?;
+        let secret = self.v.secret_export(ctx.as_ref())?;
+        let ctx = if attributes.exportable {
+            ctx
+        } else {
+            self.v.secret_destroy(ctx)?;
+            self.v.secret_import(secret.as_ref(), attributes)?
+        };
+
         // write the secret to disk using the context id
-        let ctx = self.v.secret_generate(attributes)?;
-        let secret = self.v.secret_export(&ctx)?;
         let id = self.add_secret(ctx);
-        fs_write_secret(self.path.clone(), id, secret.as_ref(), attributes)?;
+        self.write_secret(id, secret.as_ref(), attributes)?;
 
         Ok(Box::new(FilesystemVaultSecret(id)))
     }
 
     /// Import a secret key into the vault
     fn secret_import(
-        &mut self,
+        &self,
         secret: &[u8],
         attributes: SecretAttributes,
     ) -> OckamResult<Box<dyn Secret>> {
         // write the secret to disk using the context id
         let ctx = self.v.secret_import(secret, attributes)?;
         let id = self.add_secret(ctx);
-        fs_write_secret(self.path.clone(), id, &secret, attributes)?;
+        self.write_secret(id, secret, attributes)?;
 
         Ok(Box::new(FilesystemVaultSecret(id)))
     }
 
     /// Export a secret key from the vault
-    fn secret_export(&mut self, context: &Box<dyn Secret>) -> OckamResult<SecretKey> {
-        let context = Self::get_entry_map(&self.map, context)?;
+    fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, context)?;
         self.v.secret_export(context)
     }
 
     /// Get the attributes for a secret key
-    fn secret_attributes_get(
-        &mut self,
-        context: &Box<dyn Secret>,
-    ) -> OckamResult<SecretAttributes> {
-        let context = Self::get_entry_map(&self.map, context)?;
+    fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes> {
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, context)?;
         self.v.secret_attributes_get(context)
     }
 
     /// Return the associated public key given the secret key
-    fn secret_public_key_get(&mut self, context: &Box<dyn Secret>) -> OckamResult<PublicKey> {
-        let context = Self::get_entry_map(&self.map, context)?;
+    fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, context)?;
         self.v.secret_public_key_get(context)
     }
 
     /// Remove a secret key from the vault
-    fn secret_destroy(&mut self, context: Box<dyn Secret>) -> OckamResult<()> {
-        let id = FilesystemVaultSecret::downcast_secret(&context)?.0;
+    fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+        let id = FilesystemVaultSecret::downcast_secret(context.as_ref())?.0;
 
-        let path = self.path.join(id_to_path(id));
-        match fs::metadata(path.clone()) {
-            Ok(md) if md.is_file() => {
-                fs::remove_file(path).map_err(|_| Error::IOError.into())?;
+        for path in &[id_to_path(id), id_to_metadata_path(id)] {
+            let path = self.path.join(path);
+            match fs::metadata(path.clone()) {
+                Ok(md) if md.is_file() => {
+                    fs::remove_file(path).map_err(|_| Error::IOError.into())?;
+                }
+                _ => {}
             }
-            _ => {}
         }
+        self.metadata.write().unwrap().remove(&id);
 
-        let context = FilesystemVaultSecret::downcast_secret(&context)?;
+        let context = FilesystemVaultSecret::downcast_secret(context.as_ref())?;
         let context = self
             .map
+            .write()
+            .unwrap()
             .remove(&context.0)
             .ok_or(Error::EntryNotFound.into())?;
         self.v.secret_destroy(context)?;
@@ -243,12 +517,15 @@ impl AsymmetricVault for FilesystemVault {
     ///
     /// and the specified uncompressed public key
     fn ec_diffie_hellman(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         peer_public_key: &[u8],
     ) -> OckamResult<Box<dyn Secret>> {
-        let context = Self::get_entry_map(&self.map, context)?;
-        let ecdh = self.v.ec_diffie_hellman(context, peer_public_key)?;
+        let ecdh = {
+            let map = self.map.read().unwrap();
+            let context = Self::get_entry_map(&map, context)?;
+            self.v.ec_diffie_hellman(context, peer_public_key)?
+        };
         let id = self.add_secret(ecdh);
         // TODO: What if ecdh result is persistent?
 
@@ -259,40 +536,84 @@ impl AsymmetricVault for FilesystemVault {
 impl SymmetricVault for FilesystemVault {
     /// Encrypt a payload using AES-GCM
     fn aead_aes_gcm_encrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         plaintext: &[u8],
         nonce: &[u8],
         aad: &[u8],
     ) -> OckamResult<Vec<u8>> {
-        let context = Self::get_entry_map(&self.map, context)?;
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, context)?;
         self.v.aead_aes_gcm_encrypt(context, plaintext, nonce, aad)
     }
 
     /// Decrypt a payload using AES-GCM
     fn aead_aes_gcm_decrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         cipher_text: &[u8],
         nonce: &[u8],
         aad: &[u8],
     ) -> OckamResult<Vec<u8>> {
-        let context = Self::get_entry_map(&self.map, context)?;
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, context)?;
         self.v
             .aead_aes_gcm_decrypt(context, cipher_text, nonce, aad)
     }
+
+    /// Encrypt a payload using ChaCha20-Poly1305
+    fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, context)?;
+        self.v
+            .aead_chacha20_poly1305_encrypt(context, plaintext, nonce, aad)
+    }
+
+    /// Decrypt a payload using ChaCha20-Poly1305
+    fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, context)?;
+        self.v
+            .aead_chacha20_poly1305_decrypt(context, cipher_text, nonce, aad)
+    }
 }
 
 impl SignerVault for FilesystemVault {
-    fn sign(&mut self, secret_key: &Box<dyn Secret>, data: &[u8]) -> OckamResult<[u8; 64]> {
-        let context = Self::get_entry_map(&self.map, secret_key)?;
+    fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]> {
+        let map = self.map.read().unwrap();
+        let context = Self::get_entry_map(&map, secret_key)?;
         self.v.sign(context, data)
     }
 }
 
 impl VerifierVault for FilesystemVault {
-    fn verify(&mut self, signature: &[u8; 64], public_key: &[u8], data: &[u8]) -> OckamResult<()> {
-        self.v.verify(signature, public_key, data)
+    fn verify(
+        &self,
+        signature: &[u8; 64],
+        public_key: &[u8],
+        public_key_type: SecretType,
+        data: &[u8],
+    ) -> OckamResult<()> {
+        self.v.verify(signature, public_key, public_key_type, data)
+    }
+}
+
+impl RandomVault for FilesystemVault {
+    /// Fill `buffer` with random bytes
+    fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+        self.v.random_bytes_generate(buffer)
     }
 }
 
@@ -305,20 +626,24 @@ impl HashVault for FilesystemVault {
     ///
     /// and return the output key material of the specified length
     fn hkdf_sha256(
-        &mut self,
-        salt: &Box<dyn Secret>,
+        &self,
+        salt: &dyn Secret,
         info: &[u8],
-        ikm: Option<&Box<dyn Secret>>,
+        ikm: Option<&dyn Secret>,
         output_attributes: Vec<SecretAttributes>,
     ) -> OckamResult<Vec<Box<dyn Secret>>> {
-        let ikm = match ikm {
-            Some(secret) => Some(Self::get_entry_map(&self.map, secret)?),
-            None => None,
+        let secrets = {
+            let map = self.map.read().unwrap();
+            let ikm = match ikm {
+                Some(secret) => Some(Self::get_entry_map(&map, secret)?),
+                None => None,
+            };
+            let salt_context = Self::get_entry_map(&map, salt)?;
+            self.v
+                .hkdf_sha256(salt_context, info, ikm, output_attributes)?
         };
-        let salt_context = Self::get_entry_map(&self.map, salt)?;
 
-        self.v
-            .hkdf_sha256(salt_context, info, ikm, output_attributes)?
+        secrets
             .into_iter()
             .map(|secret| {
                 let id = self.add_secret(secret);
@@ -332,7 +657,7 @@ impl HashVault for FilesystemVault {
     }
 }
 impl PersistentVault for FilesystemVault {
-    fn get_persistence_id(&self, secret: &Box<dyn Secret>) -> OckamResult<String> {
+    fn get_persistence_id(&self, secret: &dyn Secret) -> OckamResult<String> {
         let id = FilesystemVaultSecret::downcast_secret(secret)?.0;
         Ok(format!("{}{}", id, FILENAME_KEY_SUFFIX))
     }
@@ -343,12 +668,92 @@ impl PersistentVault for FilesystemVault {
             .ok_or(Error::InvalidPersistenceId.into())?;
         let id: usize = id.parse().map_err(|_| Error::InvalidPersistenceId.into())?;
 
-        if self.map.contains_key(&id) {
+        if self.map.read().unwrap().contains_key(&id) {
             Ok(Box::new(FilesystemVaultSecret(id)))
         } else {
             Err(Error::InvalidPersistenceId.into())
         }
     }
+
+    fn get_persistent_secret_by_label(&self, label: &str) -> OckamResult<Box<dyn Secret>> {
+        self.metadata
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(_, m)| m.label.as_deref() == Some(label))
+            .map(|(id, _)| Box::new(FilesystemVaultSecret(*id)) as Box<dyn Secret>)
+            .ok_or_else(|| Error::EntryNotFound.into())
+    }
+
+    fn get_secret_metadata(&self, secret: &dyn Secret) -> OckamResult<SecretMetadata> {
+        let id = self.persistent_id(secret)?;
+        Ok(self.metadata.read().unwrap()[&id].clone())
+    }
+
+    fn set_secret_label(&self, secret: &dyn Secret, label: Option<&str>) -> OckamResult<()> {
+        let id = self.persistent_id(secret)?;
+        // held while the file is written, so two secrets can't take the same label
+        let mut all = self.metadata.write().unwrap();
+        if let Some(label) = label {
+            metadata::check_label(label)?;
+            let in_use = all
+                .iter()
+                .any(|(i, m)| *i != id && m.label.as_deref() == Some(label));
+            if in_use {
+                return Err(Error::LabelInUse.into());
+            }
+        }
+        let entry = all.get_mut(&id).ok_or(Error::SecretNotPersistent.into())?;
+        let updated = SecretMetadata {
+            label: label.map(str::to_string),
+            ..entry.clone()
+        };
+        self.write_metadata(id, &updated)?;
+        *entry = updated;
+        Ok(())
+    }
+
+    fn set_secret_tags(&self, secret: &dyn Secret, tags: Vec<String>) -> OckamResult<()> {
+        let id = self.persistent_id(secret)?;
+        for tag in &tags {
+            metadata::check_tag(tag)?;
+        }
+        if tags.len() > u16::MAX as usize {
+            return Err(Error::InvalidLabel.into());
+        }
+        let mut all = self.metadata.write().unwrap();
+        let entry = all.get_mut(&id).ok_or(Error::SecretNotPersistent.into())?;
+        let updated = SecretMetadata {
+            tags,
+            ..entry.clone()
+        };
+        self.write_metadata(id, &updated)?;
+        *entry = updated;
+        Ok(())
+    }
+
+    fn list_persistent_secrets(&self) -> OckamResult<Vec<PersistentSecretInfo>> {
+        let all = self.metadata.read().unwrap();
+        let map = self.map.read().unwrap();
+        let mut secrets = Vec::with_capacity(all.len());
+        for (id, metadata) in all.iter() {
+            let context = map.get(id).ok_or(Error::EntryNotFound.into())?;
+            let attributes = self.v.secret_attributes_get(context.as_ref())?;
+            let public_key = match attributes.stype {
+                SecretType::Curve25519 | SecretType::P256 | SecretType::Ed25519 => {
+                    Some(self.v.secret_public_key_get(context.as_ref())?)
+                }
+                _ => None,
+            };
+            secrets.push(PersistentSecretInfo {
+                persistence_id: format!("{}{}", id, FILENAME_KEY_SUFFIX),
+                attributes,
+                public_key,
+                metadata: metadata.clone(),
+            });
+        }
+        Ok(secrets)
+    }
 }
 
 impl Zeroize for FilesystemVault {
@@ -360,7 +765,9 @@ impl Zeroize for FilesystemVault {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ockam_vault::types::{SecretPersistence, SecretType, CURVE25519_SECRET_LENGTH};
+    use ockam_vault::types::{
+        SecretPersistence, SecretType, SecretUsage, CURVE25519_SECRET_LENGTH,
+    };
 
     #[test]
     fn persistence_test() {
@@ -368,34 +775,521 @@ mod tests {
         if path.exists() {
             std::fs::remove_dir_all(path.clone()).unwrap();
         }
-        let mut vault = FilesystemVault::new(path.clone()).unwrap();
+        let vault = FilesystemVault::new(path.clone()).unwrap();
         let atts = SecretAttributes {
             stype: SecretType::Curve25519,
             persistence: SecretPersistence::Persistent,
             length: CURVE25519_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         let sk1 = vault.secret_generate(atts).unwrap();
         let sk2 = vault.secret_generate(atts).unwrap();
         let sk3 = vault.secret_generate(atts).unwrap();
 
-        let sk_data1 = vault.secret_export(&sk1).unwrap();
-        let sk_data2 = vault.secret_export(&sk2).unwrap();
-        let sk_data3 = vault.secret_export(&sk3).unwrap();
+        let sk_data1 = vault.secret_export(sk1.as_ref()).unwrap();
+        let sk_data2 = vault.secret_export(sk2.as_ref()).unwrap();
+        let sk_data3 = vault.secret_export(sk3.as_ref()).unwrap();
 
-        let sk1_persistence_id = vault.get_persistence_id(&sk1).unwrap();
-        let sk2_persistence_id = vault.get_persistence_id(&sk2).unwrap();
-        let sk3_persistence_id = vault.get_persistence_id(&sk3).unwrap();
+        let sk1_persistence_id = vault.get_persistence_id(sk1.as_ref()).unwrap();
+        let sk2_persistence_id = vault.get_persistence_id(sk2.as_ref()).unwrap();
+        let sk3_persistence_id = vault.get_persistence_id(sk3.as_ref()).unwrap();
+        std::mem::drop(vault);
 
-        let mut vault2 = FilesystemVault::new(path).unwrap();
+        let vault2 = FilesystemVault::new(path).unwrap();
         let sk1 = vault2.get_persistent_secret(&sk1_persistence_id).unwrap();
         let sk2 = vault2.get_persistent_secret(&sk2_persistence_id).unwrap();
         let sk3 = vault2.get_persistent_secret(&sk3_persistence_id).unwrap();
-        let sk2_data_1 = vault2.secret_export(&sk1).unwrap();
-        let sk2_data_2 = vault2.secret_export(&sk2).unwrap();
-        let sk2_data_3 = vault2.secret_export(&sk3).unwrap();
+        let sk2_data_1 = vault2.secret_export(sk1.as_ref()).unwrap();
+        let sk2_data_2 = vault2.secret_export(sk2.as_ref()).unwrap();
+        let sk2_data_3 = vault2.secret_export(sk3.as_ref()).unwrap();
 
         assert_eq!(sk_data1, sk2_data_1);
         assert_eq!(sk_data2, sk2_data_2);
         assert_eq!(sk_data3, sk2_data_3);
     }
+
+    /// An empty directory in the system temp dir, removed again when it is dropped
+    pub(crate) struct TestDir(PathBuf);
+
+    impl TestDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("ockam_vault_file_{}", name));
+            if path.exists() {
+                std::fs::remove_dir_all(&path).unwrap();
+            }
+            Self(path)
+        }
+    }
+
+    impl std::ops::Deref for TestDir {
+        type Target = PathBuf;
+
+        fn deref(&self) -> &PathBuf {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TestDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_passphrase(passphrase: &str) -> StorageKey {
+        StorageKey::Passphrase(
+            passphrase.to_string(),
+            KdfParams {
+                log_n: 4,
+                r: 8,
+                p: 1,
+            },
+        )
+    }
+
+    fn persistent_attributes() -> SecretAttributes {
+        SecretAttributes {
+            stype: SecretType::Curve25519,
+            persistence: SecretPersistence::Persistent,
+            length: CURVE25519_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
+        }
+    }
+
+    #[test]
+    fn encrypted_persistence_test() {
+        let path = TestDir::new("encrypted_persistence_test");
+        let vault =
+            FilesystemVault::new_encrypted(path.clone(), test_passphrase("hunter2")).unwrap();
+        let sk = vault.secret_generate(persistent_attributes()).unwrap();
+        let sk_data = vault.secret_export(sk.as_ref()).unwrap();
+        let sk_persistence_id = vault.get_persistence_id(sk.as_ref()).unwrap();
+
+        // the secret is not on disk in the clear
+        let on_disk = std::fs::read(path.join(&sk_persistence_id)).unwrap();
+        assert!(!on_disk
+            .windows(sk_data.as_ref().len())
+            .any(|w| w == sk_data.as_ref()));
+        std::mem::drop(vault);
+
+        let vault2 =
+            FilesystemVault::new_encrypted(path.clone(), test_passphrase("hunter2")).unwrap();
+        let sk = vault2.get_persistent_secret(&sk_persistence_id).unwrap();
+        assert_eq!(vault2.secret_export(sk.as_ref()).unwrap(), sk_data);
+        std::mem::drop(vault2);
+
+        let error =
+            FilesystemVault::new_encrypted(path.clone(), test_passphrase("hunter3")).unwrap_err();
+        assert_eq!(error.code(), Error::DecryptionFailed as u32);
+        let error = FilesystemVault::new(path.clone()).unwrap_err();
+        assert_eq!(error.code(), Error::StorageKeyRequired as u32);
+    }
+
+    #[test]
+    fn migration_test() {
+        let path = TestDir::new("migration_test");
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let sk1 = vault.secret_generate(persistent_attributes()).unwrap();
+        let sk2 = vault.secret_generate(persistent_attributes()).unwrap();
+        let sk_data1 = vault.secret_export(sk1.as_ref()).unwrap();
+        let sk_data2 = vault.secret_export(sk2.as_ref()).unwrap();
+        let sk1_persistence_id = vault.get_persistence_id(sk1.as_ref()).unwrap();
+        let sk2_persistence_id = vault.get_persistence_id(sk2.as_ref()).unwrap();
+        std::mem::drop(vault);
+
+        let kek = || StorageKey::KeyEncryptionKey([9u8; 32]);
+        let error = FilesystemVault::new_encrypted(path.clone(), kek()).unwrap_err();
+        assert_eq!(error.code(), Error::UnencryptedSecret as u32);
+
+        assert_eq!(FilesystemVault::migrate(path.clone(), kek()).unwrap(), 2);
+        assert_eq!(FilesystemVault::migrate(path.clone(), kek()).unwrap(), 0);
+        assert!(FilesystemVault::migrate(path.clone(), test_passphrase("other")).is_err());
+
+        let vault2 = FilesystemVault::new_encrypted(path.clone(), kek()).unwrap();
+        let sk1 = vault2.get_persistent_secret(&sk1_persistence_id).unwrap();
+        let sk2 = vault2.get_persistent_secret(&sk2_persistence_id).unwrap();
+        assert_eq!(vault2.secret_export(sk1.as_ref()).unwrap(), sk_data1);
+        assert_eq!(vault2.secret_export(sk2.as_ref()).unwrap(), sk_data2);
+        std::mem::drop(vault2);
+
+        // files can't be swapped for each other
+        let file1 = path.join(&sk1_persistence_id);
+        let file2 = path.join(&sk2_persistence_id);
+        let data1 = std::fs::read(&file1).unwrap();
+        std::fs::write(&file2, data1).unwrap();
+        let vault3 = FilesystemVault::new_encrypted(path.clone(), kek()).unwrap();
+        assert!(vault3.get_persistent_secret(&sk1_persistence_id).is_ok());
+        assert!(vault3.get_persistent_secret(&sk2_persistence_id).is_err());
+        // the metadata file goes along with its key file
+        assert_eq!(vault3.quarantined().len(), 2);
+        assert_eq!(
+            vault3.quarantined()[0].error.code(),
+            Error::DecryptionFailed as u32
+        );
+        std::mem::drop(vault3);
+    }
+
+    #[test]
+    fn lock_test() {
+        let path = TestDir::new("lock_test");
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let error = FilesystemVault::new(path.clone()).unwrap_err();
+        assert_eq!(error.code(), Error::VaultLocked as u32);
+        let error = FilesystemVault::migrate(path.clone(), test_passphrase("p")).unwrap_err();
+        assert_eq!(error.code(), Error::VaultLocked as u32);
+        std::mem::drop(vault);
+        FilesystemVault::new(path.clone()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_files_are_quarantined() {
+        let path = TestDir::new("quarantine_test");
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let sk = vault.secret_generate(persistent_attributes()).unwrap();
+        let sk_persistence_id = vault.get_persistence_id(sk.as_ref()).unwrap();
+        std::mem::drop(vault);
+
+        // a truncated file and one with attributes that don't parse
+        std::fs::write(path.join("7.key"), [0u8, 2, 0]).unwrap();
+        std::fs::write(path.join("8.key"), [0xffu8; 38]).unwrap();
+
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        assert!(vault.get_persistent_secret(&sk_persistence_id).is_ok());
+        assert_eq!(vault.quarantined().len(), 2);
+        assert!(!path.join("7.key").exists());
+        assert!(path.join(QUARANTINE_DIR_NAME).join("7.key").exists());
+        assert!(path.join(QUARANTINE_DIR_NAME).join("8.key").exists());
+
+        // quarantined ids are not reused
+        let sk = vault.secret_generate(persistent_attributes()).unwrap();
+        assert_eq!(vault.get_persistence_id(sk.as_ref()).unwrap(), "9.key");
+    }
+
+    #[test]
+    fn non_exportable_persistent_secret() {
+        let path = TestDir::new("non_exportable_test");
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let atts = SecretAttributes {
+            exportable: false,
+            usage: SecretUsage::ECDH,
+            ..persistent_attributes()
+        };
+        let sk = vault.secret_generate(atts).unwrap();
+        let error = vault.secret_export(sk.as_ref()).unwrap_err();
+        assert_eq!(
+            error.code(),
+            ockam_vault_software::error::Error::SecretNotExportable as u32
+        );
+        let sk_persistence_id = vault.get_persistence_id(sk.as_ref()).unwrap();
+        let pk = vault.secret_public_key_get(sk.as_ref()).unwrap();
+        std::mem::drop(vault);
+
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let sk = vault.get_persistent_secret(&sk_persistence_id).unwrap();
+        assert_eq!(vault.secret_attributes_get(sk.as_ref()).unwrap(), atts);
+        assert!(vault.secret_export(sk.as_ref()).is_err());
+
+        let other = vault.secret_generate(persistent_attributes()).unwrap();
+        let other_pk = vault.secret_public_key_get(other.as_ref()).unwrap();
+        let shared1 = vault
+            .ec_diffie_hellman(sk.as_ref(), other_pk.as_ref())
+            .unwrap();
+        let shared2 = vault
+            .ec_diffie_hellman(other.as_ref(), pk.as_ref())
+            .unwrap();
+        // shared secrets don't leave the vault, compare keys derived from them
+        let output = SecretAttributes {
+            stype: SecretType::Buffer,
+            persistence: SecretPersistence::Ephemeral,
+            length: 32,
+            exportable: true,
+            usage: SecretUsage::AEAD,
+        };
+        let derive = |shared| {
+            let derived = vault.hkdf_sha256(shared, b"", None, vec![output]).unwrap();
+            vault.secret_export(derived[0].as_ref()).unwrap()
+        };
+        assert_eq!(derive(shared1.as_ref()), derive(shared2.as_ref()));
+        assert!(vault.sign(sk.as_ref(), b"data").is_err());
+    }
+
+    #[test]
+    fn legacy_key_files_are_read() {
+        let path = TestDir::new("legacy_key_file_test");
+        storage::create_vault_dir(&path).unwrap();
+        let legacy = SecretAttributes::try_from([0u8, 2, 0, 1, 0, 32]).unwrap();
+        assert_eq!(legacy, persistent_attributes());
+        // an already clamped Curve25519 key, so the vault exports it unchanged
+        let key = [0x40u8; CURVE25519_SECRET_LENGTH];
+        let mut data = vec![0u8, 2, 0, 1, 0, 32];
+        data.extend_from_slice(&key);
+        std::fs::write(path.join("1.key"), data).unwrap();
+
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        assert!(vault.quarantined().is_empty());
+        let sk = vault.get_persistent_secret("1.key").unwrap();
+        let atts = vault.secret_attributes_get(sk.as_ref()).unwrap();
+        assert!(atts.exportable);
+        assert_eq!(atts.usage, SecretUsage::ALL);
+        assert_eq!(vault.secret_export(sk.as_ref()).unwrap().as_ref(), &key);
+    }
+
+    #[test]
+    fn key_files_without_length_are_upgraded() {
+        let path = TestDir::new("zero_length_key_file_test");
+        storage::create_vault_dir(&path).unwrap();
+        // profile keys were stored as Curve25519 keys of length 0
+        let key = [0x40u8; CURVE25519_SECRET_LENGTH];
+        let mut data = vec![0u8, 2, 0, 1, 0, 0];
+        data.extend_from_slice(&key);
+        std::fs::write(path.join("1.key"), data).unwrap();
+
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        assert!(vault.quarantined().is_empty());
+        let sk = vault.get_persistent_secret("1.key").unwrap();
+        let atts = vault.secret_attributes_get(sk.as_ref()).unwrap();
+        assert_eq!(atts.length, CURVE25519_SECRET_LENGTH);
+        assert_eq!(atts.usage, SecretUsage::ALL);
+        assert_eq!(vault.secret_export(sk.as_ref()).unwrap().as_ref(), &key);
+        drop(vault);
+
+        // the key file is rewritten with the length
+        let data = std::fs::read(path.join("1.key")).unwrap();
+        let (stored, atts) = parse_secret(&data).unwrap();
+        assert_eq!(stored.as_ref(), &key);
+        assert_eq!(atts.length, CURVE25519_SECRET_LENGTH);
+        assert!(atts.exportable);
+    }
+
+    #[test]
+    fn labels_and_metadata() {
+        let path = TestDir::new("metadata_test");
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let sk1 = vault.secret_generate(persistent_attributes()).unwrap();
+        let sk2 = vault.secret_generate(persistent_attributes()).unwrap();
+        let pk1 = vault.secret_public_key_get(sk1.as_ref()).unwrap();
+        let ephemeral = vault
+            .secret_generate(SecretAttributes {
+                persistence: SecretPersistence::Ephemeral,
+                ..persistent_attributes()
+            })
+            .unwrap();
+
+        vault
+            .set_secret_label(sk1.as_ref(), Some("sink identity"))
+            .unwrap();
+        vault
+            .set_secret_tags(sk1.as_ref(), vec!["role=sink".to_string()])
+            .unwrap();
+        let error = vault
+            .set_secret_label(sk2.as_ref(), Some("sink identity"))
+            .unwrap_err();
+        assert_eq!(error.code(), Error::LabelInUse as u32);
+        let error = vault
+            .set_secret_label(sk2.as_ref(), Some("3.key"))
+            .unwrap_err();
+        assert_eq!(error.code(), Error::InvalidLabel as u32);
+        let error = vault
+            .set_secret_label(ephemeral.as_ref(), Some("temp"))
+            .unwrap_err();
+        assert_eq!(error.code(), Error::SecretNotPersistent as u32);
+        // relabeling keeps the label unique
+        vault
+            .set_secret_label(sk1.as_ref(), Some("sink identity"))
+            .unwrap();
+        std::mem::drop(vault);
+
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let sk1 = vault
+            .get_persistent_secret_by_label("sink identity")
+            .unwrap();
+        assert_eq!(vault.get_persistence_id(sk1.as_ref()).unwrap(), "1.key");
+        assert!(vault.get_persistent_secret_by_label("source").is_err());
+        let metadata = vault.get_secret_metadata(sk1.as_ref()).unwrap();
+        assert_eq!(metadata.tags, vec!["role=sink".to_string()]);
+        assert!(metadata.created_at > 0);
+
+        let list = vault.list_persistent_secrets().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].persistence_id, "1.key");
+        assert_eq!(list[0].attributes, persistent_attributes());
+        assert_eq!(list[0].public_key, Some(pk1));
+        assert_eq!(list[0].metadata, metadata);
+        assert_eq!(list[1].metadata.label, None);
+
+        let sk2 = vault.get_persistent_secret("2.key").unwrap();
+        vault.secret_destroy(sk2).unwrap();
+        assert!(!path.join(id_to_metadata_path(2)).exists());
+        vault.set_secret_label(sk1.as_ref(), None).unwrap();
+        assert!(vault
+            .get_persistent_secret_by_label("sink identity")
+            .is_err());
+        std::mem::drop(vault);
+
+        // key files written before metadata existed load without it
+        std::fs::remove_file(path.join(id_to_metadata_path(1))).unwrap();
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let list = vault.list_persistent_secrets().unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].metadata.label, None);
+        assert!(list[0].metadata.created_at > 0);
+        std::mem::drop(vault);
+    }
+
+    #[test]
+    fn encrypted_metadata() {
+        let path = TestDir::new("encrypted_metadata_test");
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let sk = vault.secret_generate(persistent_attributes()).unwrap();
+        vault
+            .set_secret_label(sk.as_ref(), Some("identity"))
+            .unwrap();
+        std::mem::drop(vault);
+
+        let kek = || StorageKey::KeyEncryptionKey([9u8; 32]);
+        assert_eq!(FilesystemVault::migrate(path.clone(), kek()).unwrap(), 1);
+        let on_disk = std::fs::read(path.join(id_to_metadata_path(1))).unwrap();
+        assert!(!on_disk.windows(8).any(|w| w == b"identity"));
+
+        let vault = FilesystemVault::new_encrypted(path.clone(), kek()).unwrap();
+        let sk = vault.get_persistent_secret_by_label("identity").unwrap();
+        vault
+            .set_secret_tags(sk.as_ref(), vec!["a".to_string()])
+            .unwrap();
+        std::mem::drop(vault);
+
+        // a corrupt metadata file loses the metadata, not the key
+        std::fs::write(path.join(id_to_metadata_path(1)), b"garbage").unwrap();
+        let error = FilesystemVault::new_encrypted(path.clone(), kek()).unwrap_err();
+        assert_eq!(error.code(), Error::UnencryptedSecret as u32);
+        let mut data = on_disk;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(path.join(id_to_metadata_path(1)), data).unwrap();
+        let vault = FilesystemVault::new_encrypted(path.clone(), kek()).unwrap();
+        assert!(vault.get_persistent_secret("1.key").is_ok());
+        assert!(vault.get_persistent_secret_by_label("identity").is_err());
+        assert_eq!(vault.quarantined().len(), 1);
+        std::mem::drop(vault);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = TestDir::new("permissions_test");
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        let sk = vault.secret_generate(persistent_attributes()).unwrap();
+        let file = path.join(vault.get_persistence_id(sk.as_ref()).unwrap());
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&file), 0o600);
+        assert_eq!(mode(&path), 0o700);
+        let metadata_file = path.join(id_to_metadata_path(1));
+        assert_eq!(mode(&metadata_file), 0o600);
+        // no temporary files are left behind
+        assert_eq!(key_files(&path).unwrap().len(), 1);
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 3);
+        std::mem::drop(vault);
+
+        // a directory of another user that others can access is not taken over
+        let lax = || std::fs::Permissions::from_mode(0o755);
+        if unsafe { libc::geteuid() } == 0 {
+            std::fs::set_permissions(&*path, lax()).unwrap();
+            std::os::unix::fs::chown(&*path, Some(65534), None).unwrap();
+            let error = FilesystemVault::new(path.clone()).unwrap_err();
+            assert_eq!(error.code(), Error::InsecurePermissions as u32);
+            assert_eq!(mode(&path), 0o755);
+            std::os::unix::fs::chown(&*path, Some(0), None).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn baseline_vault_dirs_are_made_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        let lax = || std::fs::Permissions::from_mode(0o755);
+        // vault directories used to be created with create_dir_all
+        let path = TestDir::new("baseline_permissions_test");
+        std::fs::create_dir_all(&*path).unwrap();
+        std::fs::set_permissions(&*path, lax()).unwrap();
+        let mut data = vec![0u8, 2, 0, 1, 0, 32];
+        data.extend_from_slice(&[0x40u8; CURVE25519_SECRET_LENGTH]);
+        std::fs::write(path.join(id_to_path(1)), &data).unwrap();
+
+        let vault = FilesystemVault::new(path.clone()).unwrap();
+        assert_eq!(mode(&path), 0o700);
+        assert!(vault.get_persistent_secret("1.key").is_ok());
+        std::mem::drop(vault);
+
+        // migrating one does the same
+        std::fs::set_permissions(&*path, lax()).unwrap();
+        let storage_key = StorageKey::KeyEncryptionKey([7u8; 32]);
+        assert_eq!(
+            FilesystemVault::migrate(path.clone(), storage_key).unwrap(),
+            1
+        );
+        assert_eq!(mode(&path), 0o700);
+    }
+
+    fn any_secret_type() -> impl proptest::strategy::Strategy<Value = SecretType> {
+        use proptest::prelude::*;
+        prop_oneof![
+            Just(SecretType::Buffer),
+            Just(SecretType::Aes),
+            Just(SecretType::Curve25519),
+            Just(SecretType::P256),
+            Just(SecretType::Ed25519),
+            Just(SecretType::ChaCha20Poly1305),
+        ]
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::test_runner::Config::with_cases(64))]
+
+        /// Keys the vault accepts come back from disk unchanged, keys it rejects
+        /// leave nothing behind
+        #[test]
+        fn imported_keys_survive_reopening(
+            stype in any_secret_type(),
+            length in proptest::prop_oneof![0usize..40, proptest::strategy::Just(32usize)],
+            secret in proptest::collection::vec(proptest::num::u8::ANY, 0..40),
+        ) {
+            let path = TestDir::new("import_property_test");
+            let atts = SecretAttributes {
+                stype,
+                persistence: SecretPersistence::Persistent,
+                length,
+                exportable: true,
+                usage: SecretUsage::ALL,
+            };
+            let vault = FilesystemVault::new(path.clone()).unwrap();
+            match vault.secret_import(&secret, atts) {
+                Ok(sk) => {
+                    let id = vault.get_persistence_id(sk.as_ref()).unwrap();
+                    let exported = vault.secret_export(sk.as_ref()).unwrap();
+                    std::mem::drop(vault);
+
+                    let vault = FilesystemVault::new(path.clone()).unwrap();
+                    proptest::prop_assert!(vault.quarantined().is_empty());
+                    let sk = vault.get_persistent_secret(&id).unwrap();
+                    proptest::prop_assert_eq!(vault.secret_attributes_get(sk.as_ref()).unwrap(), atts);
+                    proptest::prop_assert_eq!(vault.secret_export(sk.as_ref()).unwrap(), exported);
+                }
+                Err(error) => {
+                    proptest::prop_assert_eq!(error.domain(), ockam_vault::error::Error::ERROR_DOMAIN);
+                    proptest::prop_assert!(key_files(&path).unwrap().is_empty());
+                }
+            }
+        }
+    }
 }
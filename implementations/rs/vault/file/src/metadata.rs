@@ -0,0 +1,171 @@
+use crate::error::Error;
+use crate::FILENAME_KEY_SUFFIX;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::SecretMetadata;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Suffix of the file holding the metadata of a key file
+pub const FILENAME_METADATA_SUFFIX: &str = ".meta";
+/// Longest label or tag, in bytes
+pub const MAX_LABEL_LENGTH: usize = 255;
+const METADATA_VERSION: u8 = 1;
+
+/// Seconds since the UNIX epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Metadata for a key file that has none, written before metadata existed.
+/// The file's modification time stands in for the creation time.
+pub(crate) fn default_metadata(key_file: &Path) -> SecretMetadata {
+    let created_at = fs::metadata(key_file)
+        .and_then(|md| md.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    SecretMetadata {
+        created_at,
+        ..Default::default()
+    }
+}
+
+/// Labels must be printable and must not look like a persistence id, so a name
+/// given by a user resolves to at most one secret
+pub(crate) fn check_label(label: &str) -> OckamResult<()> {
+    if label.is_empty()
+        || label.ends_with(FILENAME_KEY_SUFFIX)
+        || label.chars().any(char::is_control)
+    {
+        return Err(Error::InvalidLabel.into());
+    }
+    check_length(label)
+}
+
+pub(crate) fn check_tag(tag: &str) -> OckamResult<()> {
+    check_length(tag)
+}
+
+fn check_length(s: &str) -> OckamResult<()> {
+    if s.len() > MAX_LABEL_LENGTH {
+        return Err(Error::InvalidLabel.into());
+    }
+    Ok(())
+}
+
+/// Encode metadata as version | created_at (u64) | label flag (u8) [| label] |
+/// tag count (u16) | tags, strings are prefixed with their u16 length
+pub(crate) fn encode(metadata: &SecretMetadata) -> Vec<u8> {
+    let mut out = vec![METADATA_VERSION];
+    out.extend_from_slice(&metadata.created_at.to_be_bytes());
+    match &metadata.label {
+        Some(label) => {
+            out.push(1);
+            encode_str(&mut out, label);
+        }
+        None => out.push(0),
+    }
+    out.extend_from_slice(&(metadata.tags.len() as u16).to_be_bytes());
+    for tag in &metadata.tags {
+        encode_str(&mut out, tag);
+    }
+    out
+}
+
+fn encode_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+pub(crate) fn decode(data: &[u8]) -> OckamResult<SecretMetadata> {
+    let mut reader = Reader(data);
+    if reader.take(1)?[0] != METADATA_VERSION {
+        return Err(Error::InvalidMetadata.into());
+    }
+    let created_at = u64::from_be_bytes(
+        <[u8; 8]>::try_from(reader.take(8)?).map_err(|_| Error::InvalidMetadata.into())?,
+    );
+    let label = match reader.take(1)?[0] {
+        0 => None,
+        1 => Some(reader.string()?),
+        _ => return Err(Error::InvalidMetadata.into()),
+    };
+    let count = reader.u16()?;
+    let mut tags = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        tags.push(reader.string()?);
+    }
+    if !reader.0.is_empty() {
+        return Err(Error::InvalidMetadata.into());
+    }
+    Ok(SecretMetadata {
+        label,
+        created_at,
+        tags,
+    })
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> OckamResult<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(Error::InvalidMetadata.into());
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> OckamResult<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn string(&mut self) -> OckamResult<String> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidMetadata.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let metadata = SecretMetadata {
+            label: Some("sink identity".to_string()),
+            created_at: 1_600_000_000,
+            tags: vec!["role=sink".to_string(), "".to_string()],
+        };
+        let encoded = encode(&metadata);
+        assert_eq!(decode(&encoded).unwrap(), metadata);
+        assert_eq!(
+            decode(&encode(&SecretMetadata::default())).unwrap(),
+            SecretMetadata::default()
+        );
+
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(decode(&trailing).is_err());
+        assert!(decode(&[]).is_err());
+    }
+
+    #[test]
+    fn labels() {
+        assert!(check_label("identity").is_ok());
+        assert!(check_label("").is_err());
+        assert!(check_label("1.key").is_err());
+        assert!(check_label("a\nb").is_err());
+        assert!(check_label(&"x".repeat(MAX_LABEL_LENGTH + 1)).is_err());
+    }
+}
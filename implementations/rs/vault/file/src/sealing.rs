@@ -0,0 +1,384 @@
+use crate::error::Error;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::{SecretAttributes, SecretPersistence, SecretType, SecretUsage};
+use ockam_vault::{SecretVault, SymmetricVault};
+use ockam_vault_software::DefaultVault;
+use rand::{thread_rng, RngCore};
+use std::collections::BTreeMap;
+use zeroize::Zeroize;
+
+/// Environment variable holding a hex encoded 32 byte key-encryption key
+pub const KEK_ENV_VAR: &str = "OCKAM_VAULT_KEK";
+/// Environment variable holding a passphrase the key-encryption key is derived from
+pub const PASSPHRASE_ENV_VAR: &str = "OCKAM_VAULT_PASSPHRASE";
+
+/// Version of the sealed key file format
+pub const FORMAT_VERSION: u8 = 1;
+
+const MAGIC: &[u8; 4] = b"OCKV";
+const KDF_NONE: u8 = 0;
+const KDF_SCRYPT: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+/// Appended to the associated data of metadata files so they can't pass for key files
+const METADATA_CONTEXT: &[u8] = b"metadata";
+/// Appended to the associated data of backup bundles
+const BACKUP_CONTEXT: &[u8] = b"backup";
+/// magic | version | kdf | log_n | r (be u32) | p (be u32) | salt | nonce
+const HEADER_LENGTH: usize = 4 + 1 + 1 + 1 + 4 + 4 + SALT_LENGTH + NONCE_LENGTH;
+/// Upper bounds for the scrypt cost read from a file, keep a forged header from
+/// making the vault spend minutes or gigabytes deriving a key
+const MAX_LOG_N: u8 = 20;
+const MAX_R: u32 = 32;
+const MAX_P: u32 = 16;
+
+/// scrypt cost parameters used to derive the key-encryption key from a passphrase
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct KdfParams {
+    /// log2 of the CPU/memory cost
+    pub log_n: u8,
+    /// Block size
+    pub r: u32,
+    /// Parallelization
+    pub p: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+/// The key an encrypted FilesystemVault seals its key files with
+pub enum StorageKey {
+    /// A passphrase, stretched with scrypt using a random salt per vault
+    Passphrase(String, KdfParams),
+    /// A 32 byte key-encryption key used as is
+    KeyEncryptionKey([u8; KEY_LENGTH]),
+}
+
+impl std::fmt::Debug for StorageKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StorageKey::Passphrase(_, params) => write!(f, "Passphrase({:?})", params),
+            StorageKey::KeyEncryptionKey(_) => write!(f, "KeyEncryptionKey"),
+        }
+    }
+}
+
+impl Drop for StorageKey {
+    fn drop(&mut self) {
+        match self {
+            StorageKey::Passphrase(p, _) => p.zeroize(),
+            StorageKey::KeyEncryptionKey(k) => k.zeroize(),
+        }
+    }
+}
+
+impl StorageKey {
+    /// A passphrase with the default scrypt cost
+    pub fn passphrase(passphrase: &str) -> Self {
+        StorageKey::Passphrase(passphrase.to_string(), KdfParams::default())
+    }
+
+    /// Read the storage key from `OCKAM_VAULT_KEK` or, if that isn't set,
+    /// `OCKAM_VAULT_PASSPHRASE`. Returns `None` when neither is set.
+    pub fn from_env() -> OckamResult<Option<Self>> {
+        Self::from_vars(
+            std::env::var(KEK_ENV_VAR).ok(),
+            std::env::var(PASSPHRASE_ENV_VAR).ok(),
+        )
+    }
+
+    fn from_vars(kek: Option<String>, passphrase: Option<String>) -> OckamResult<Option<Self>> {
+        if let Some(mut kek) = kek {
+            let decoded = hex::decode(kek.trim());
+            kek.zeroize();
+            let mut decoded = decoded.map_err(|_| Error::InvalidStorageKey.into())?;
+            if decoded.len() != KEY_LENGTH {
+                decoded.zeroize();
+                return Err(Error::InvalidStorageKey.into());
+            }
+            let mut key = [0u8; KEY_LENGTH];
+            key.copy_from_slice(&decoded);
+            decoded.zeroize();
+            return Ok(Some(StorageKey::KeyEncryptionKey(key)));
+        }
+        match passphrase {
+            Some(p) if p.is_empty() => Err(Error::InvalidStorageKey.into()),
+            Some(p) => Ok(Some(StorageKey::Passphrase(p, KdfParams::default()))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns true if `data` starts with the sealed file header
+pub(crate) fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Seals and opens key files.
+///
+/// A sealed file is a fixed size header followed by the AES-256-GCM encryption of
+/// the plaintext file contents, the attributes followed by the secret. The header
+/// and the key file id are the associated data, so files can't be swapped or have
+/// their header edited. Metadata files are sealed the same way under the id of their
+/// key file. Passphrase derived keys are cached per salt, all files
+/// written by one vault share a salt so scrypt runs once.
+#[derive(Debug)]
+pub(crate) struct Sealer {
+    key: StorageKey,
+    salt: [u8; SALT_LENGTH],
+    derived: BTreeMap<([u8; SALT_LENGTH], KdfParams), [u8; KEY_LENGTH]>,
+    vault: DefaultVault,
+}
+
+impl Drop for Sealer {
+    fn drop(&mut self) {
+        for k in self.derived.values_mut() {
+            k.zeroize();
+        }
+    }
+}
+
+impl Sealer {
+    pub fn new(key: StorageKey) -> Self {
+        let mut salt = [0u8; SALT_LENGTH];
+        thread_rng().fill_bytes(&mut salt);
+        Self {
+            key,
+            salt,
+            derived: BTreeMap::new(),
+            vault: DefaultVault::default(),
+        }
+    }
+
+    /// Encrypt the contents of key file `id`
+    pub fn seal(&mut self, id: usize, plaintext: &[u8]) -> OckamResult<Vec<u8>> {
+        self.seal_file(id, &[], plaintext)
+    }
+
+    /// Decrypt the contents of key file `id`
+    pub fn open(&mut self, id: usize, data: &[u8]) -> OckamResult<Vec<u8>> {
+        self.open_file(id, &[], data)
+    }
+
+    /// Encrypt the metadata file of key `id`
+    pub fn seal_metadata(&mut self, id: usize, plaintext: &[u8]) -> OckamResult<Vec<u8>> {
+        self.seal_file(id, METADATA_CONTEXT, plaintext)
+    }
+
+    /// Decrypt the metadata file of key `id`
+    pub fn open_metadata(&mut self, id: usize, data: &[u8]) -> OckamResult<Vec<u8>> {
+        self.open_file(id, METADATA_CONTEXT, data)
+    }
+
+    /// Encrypt a backup bundle
+    pub fn seal_backup(&mut self, plaintext: &[u8]) -> OckamResult<Vec<u8>> {
+        self.seal_file(0, BACKUP_CONTEXT, plaintext)
+    }
+
+    /// Decrypt a backup bundle
+    pub fn open_backup(&mut self, data: &[u8]) -> OckamResult<Vec<u8>> {
+        self.open_file(0, BACKUP_CONTEXT, data)
+    }
+
+    fn seal_file(&mut self, id: usize, context: &[u8], plaintext: &[u8]) -> OckamResult<Vec<u8>> {
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.extend_from_slice(MAGIC);
+        header.push(FORMAT_VERSION);
+        match &self.key {
+            StorageKey::Passphrase(_, params) => {
+                header.push(KDF_SCRYPT);
+                header.push(params.log_n);
+                header.extend_from_slice(&params.r.to_be_bytes());
+                header.extend_from_slice(&params.p.to_be_bytes());
+                header.extend_from_slice(&self.salt);
+            }
+            StorageKey::KeyEncryptionKey(_) => {
+                header.push(KDF_NONE);
+                header.extend_from_slice(&[0u8; 1 + 4 + 4 + SALT_LENGTH]);
+            }
+        }
+        let mut nonce = [0u8; NONCE_LENGTH];
+        thread_rng().fill_bytes(&mut nonce);
+        header.extend_from_slice(&nonce);
+
+        let (kdf, params, salt) = Self::parse_header(&header)?;
+        let key = self.file_key(kdf, params, salt)?;
+        let ciphertext = self.aead(key, &header, id, context, plaintext, true)?;
+        header.extend_from_slice(&ciphertext);
+        Ok(header)
+    }
+
+    fn open_file(&mut self, id: usize, context: &[u8], data: &[u8]) -> OckamResult<Vec<u8>> {
+        if data.len() < HEADER_LENGTH || !is_sealed(data) {
+            return Err(Error::UnsupportedFormat.into());
+        }
+        let (header, ciphertext) = data.split_at(HEADER_LENGTH);
+        let (kdf, params, salt) = Self::parse_header(header)?;
+        let key = self.file_key(kdf, params, salt)?;
+        self.aead(key, header, id, context, ciphertext, false)
+    }
+
+    fn parse_header(header: &[u8]) -> OckamResult<(u8, KdfParams, [u8; SALT_LENGTH])> {
+        if header[4] != FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat.into());
+        }
+        let mut r = [0u8; 4];
+        r.copy_from_slice(&header[7..11]);
+        let mut p = [0u8; 4];
+        p.copy_from_slice(&header[11..15]);
+        let params = KdfParams {
+            log_n: header[6],
+            r: u32::from_be_bytes(r),
+            p: u32::from_be_bytes(p),
+        };
+        let mut salt = [0u8; SALT_LENGTH];
+        salt.copy_from_slice(&header[15..15 + SALT_LENGTH]);
+        Ok((header[5], params, salt))
+    }
+
+    /// The AES key for a file, the file's kdf has to match the kind of storage key
+    fn file_key(
+        &mut self,
+        kdf: u8,
+        params: KdfParams,
+        salt: [u8; SALT_LENGTH],
+    ) -> OckamResult<[u8; KEY_LENGTH]> {
+        match (&self.key, kdf) {
+            (StorageKey::KeyEncryptionKey(k), KDF_NONE) => Ok(*k),
+            (StorageKey::Passphrase(passphrase, _), KDF_SCRYPT) => {
+                if let Some(k) = self.derived.get(&(salt, params)) {
+                    return Ok(*k);
+                }
+                if params.log_n > MAX_LOG_N || params.r > MAX_R || params.p > MAX_P {
+                    return Err(Error::UnsupportedFormat.into());
+                }
+                let scrypt_params = scrypt::ScryptParams::new(params.log_n, params.r, params.p)
+                    .map_err(|_| Error::UnsupportedFormat.into())?;
+                let mut k = [0u8; KEY_LENGTH];
+                scrypt::scrypt(passphrase.as_bytes(), &salt, &scrypt_params, &mut k)
+                    .map_err(|_| Error::InvalidStorageKey.into())?;
+                self.derived.insert((salt, params), k);
+                Ok(k)
+            }
+            _ => Err(Error::InvalidStorageKey.into()),
+        }
+    }
+
+    fn aead(
+        &mut self,
+        mut key: [u8; KEY_LENGTH],
+        header: &[u8],
+        id: usize,
+        context: &[u8],
+        data: &[u8],
+        encrypt: bool,
+    ) -> OckamResult<Vec<u8>> {
+        let attributes = SecretAttributes {
+            stype: SecretType::Aes,
+            persistence: SecretPersistence::Ephemeral,
+            length: KEY_LENGTH,
+            exportable: false,
+            usage: SecretUsage::AEAD,
+        };
+        let secret = self.vault.secret_import(&key, attributes);
+        key.zeroize();
+        let secret = secret?;
+        let nonce = &header[HEADER_LENGTH - NONCE_LENGTH..];
+        let mut aad = header.to_vec();
+        aad.extend_from_slice(&(id as u64).to_be_bytes());
+        aad.extend_from_slice(context);
+        let result = if encrypt {
+            self.vault
+                .aead_aes_gcm_encrypt(secret.as_ref(), data, nonce, &aad)
+        } else {
+            self.vault
+                .aead_aes_gcm_decrypt(secret.as_ref(), data, nonce, &aad)
+                .map_err(|_| Error::DecryptionFailed.into())
+        };
+        self.vault.secret_destroy(secret)?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_passphrase(passphrase: &str) -> StorageKey {
+        StorageKey::Passphrase(
+            passphrase.to_string(),
+            KdfParams {
+                log_n: 4,
+                r: 8,
+                p: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn seal_open() {
+        for key in [
+            test_passphrase("correct horse"),
+            StorageKey::KeyEncryptionKey([7u8; 32]),
+        ] {
+            let mut sealer = Sealer::new(key);
+            let sealed = sealer.seal(3, b"secret").unwrap();
+            assert!(is_sealed(&sealed));
+            assert_eq!(sealed.len(), HEADER_LENGTH + 6 + 16);
+            assert_eq!(sealer.open(3, &sealed).unwrap(), b"secret");
+            // bound to the file id
+            assert!(sealer.open(4, &sealed).is_err());
+            // and the header
+            let mut tampered = sealed.clone();
+            tampered[HEADER_LENGTH - 1] ^= 1;
+            assert!(sealer.open(3, &tampered).is_err());
+            // metadata and key files can't be mistaken for each other
+            assert!(sealer.open_metadata(3, &sealed).is_err());
+            let sealed = sealer.seal_metadata(3, b"label").unwrap();
+            assert_eq!(sealer.open_metadata(3, &sealed).unwrap(), b"label");
+            assert!(sealer.open(3, &sealed).is_err());
+            // neither can backups
+            let sealed = sealer.seal_backup(b"bundle").unwrap();
+            assert_eq!(sealer.open_backup(&sealed).unwrap(), b"bundle");
+            assert!(sealer.open(0, &sealed).is_err());
+            assert!(sealer.open_metadata(0, &sealed).is_err());
+        }
+    }
+
+    #[test]
+    fn wrong_key() {
+        let sealed = Sealer::new(test_passphrase("correct horse"))
+            .seal(1, b"secret")
+            .unwrap();
+        let mut sealer = Sealer::new(test_passphrase("battery staple"));
+        let error = sealer.open(1, &sealed).unwrap_err();
+        assert_eq!(error.code(), Error::DecryptionFailed as u32);
+        let mut sealer = Sealer::new(StorageKey::KeyEncryptionKey([7u8; 32]));
+        let error = sealer.open(1, &sealed).unwrap_err();
+        assert_eq!(error.code(), Error::InvalidStorageKey as u32);
+    }
+
+    #[test]
+    fn storage_key_from_vars() {
+        assert!(StorageKey::from_vars(None, None).unwrap().is_none());
+        let key = StorageKey::from_vars(Some(hex::encode([1u8; 32])), Some("ignored".into()))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(key, StorageKey::KeyEncryptionKey(ref k) if *k == [1u8; 32]));
+        let key = StorageKey::from_vars(None, Some("pass".into()))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(key, StorageKey::Passphrase(ref p, _) if p == "pass"));
+        assert!(StorageKey::from_vars(Some("abcd".into()), None).is_err());
+        assert!(StorageKey::from_vars(Some("xyz".into()), None).is_err());
+        assert!(StorageKey::from_vars(None, Some("".into())).is_err());
+    }
+}
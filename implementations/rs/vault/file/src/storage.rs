@@ -0,0 +1,177 @@
+use crate::error::Error;
+use fs2::FileExt;
+use ockam_common::error::{OckamError, OckamResult};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the lock file inside a vault directory
+pub const LOCK_FILE_NAME: &str = ".lock";
+/// Name of the directory corrupt key files are moved to
+pub const QUARANTINE_DIR_NAME: &str = "quarantine";
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// A key file that could not be loaded and was moved out of the way
+#[derive(Debug)]
+pub struct QuarantinedFile {
+    /// Where the file was moved to
+    pub path: PathBuf,
+    /// Why it could not be loaded
+    pub error: OckamError,
+}
+
+/// An exclusive advisory lock on a vault directory, held until dropped
+#[derive(Debug)]
+pub(crate) struct VaultLock {
+    file: File,
+}
+
+impl VaultLock {
+    /// Lock `dir`, fails with `VaultLocked` if another vault holds it
+    pub fn acquire(dir: &Path) -> OckamResult<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(dir.join(LOCK_FILE_NAME))
+            .map_err(|_| Error::IOError.into())?;
+        file.try_lock_exclusive()
+            .map_err(|_| Error::VaultLocked.into())?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Create the vault directory, only accessible by its owner. An existing directory
+/// must be private as well, see `make_private`.
+pub(crate) fn create_vault_dir(dir: &Path) -> OckamResult<()> {
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent).map_err(|_| Error::IOError.into())?;
+    }
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    match builder.create(dir) {
+        // the mode is masked by the umask
+        Ok(()) => restrict_permissions(dir, 0o700),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists && dir.is_dir() => make_private(dir),
+        Err(_) => Err(Error::IOError.into()),
+    }
+}
+
+/// Replace `path` with `data` so that a crash leaves either the old or the new
+/// contents. The data goes to a temporary file with 0600 permissions that is synced
+/// and renamed over `path`, then the directory is synced to persist the rename.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> OckamResult<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(TEMP_SUFFIX);
+    let temp = PathBuf::from(temp);
+
+    let result = write_synced(&temp, data).and_then(|_| {
+        fs::rename(&temp, path).map_err(|_| Error::IOError.into())?;
+        sync_dir(path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Move a key file that failed to load into the quarantine directory
+pub(crate) fn quarantine(path: &Path, error: OckamError) -> OckamResult<QuarantinedFile> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| Error::IOError.into())?
+        .join(QUARANTINE_DIR_NAME);
+    fs::create_dir_all(&dir).map_err(|_| Error::IOError.into())?;
+    let file_name = path.file_name().ok_or_else(|| Error::IOError.into())?;
+    let mut target = dir.join(file_name);
+    let mut n = 1;
+    while target.exists() {
+        let mut name = file_name.to_owned();
+        name.push(format!(".{}", n));
+        target = dir.join(name);
+        n += 1;
+    }
+    fs::rename(path, &target).map_err(|_| Error::IOError.into())?;
+    Ok(QuarantinedFile {
+        path: target,
+        error,
+    })
+}
+
+fn write_synced(path: &Path, data: &[u8]) -> OckamResult<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|_| Error::IOError.into())?;
+    // the mode only applies to new files, a stale temp file may have other permissions
+    restrict_permissions(path, 0o600)?;
+    file.write_all(data).map_err(|_| Error::IOError.into())?;
+    file.sync_all().map_err(|_| Error::IOError.into())
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> OckamResult<()> {
+    let dir = path.parent().ok_or_else(|| Error::IOError.into())?;
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|_| Error::IOError.into())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> OckamResult<()> {
+    Ok(())
+}
+
+/// Restrict an existing vault directory that others can access to its owner. Vault
+/// directories used to be created with the default permissions, so this is expected
+/// once after an upgrade and only warned about. A directory of another user that
+/// others can access is refused.
+#[cfg(unix)]
+pub(crate) fn make_private(dir: &Path) -> OckamResult<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let metadata = fs::metadata(dir).map_err(|_| Error::IOError.into())?;
+    let mode = metadata.permissions().mode() & 0o777;
+    if mode & 0o077 == 0 {
+        return Ok(());
+    }
+    // SAFETY: geteuid has no preconditions and can't fail
+    if metadata.uid() != unsafe { libc::geteuid() } {
+        return Err(Error::InsecurePermissions.into());
+    }
+    log::warn!(
+        "vault directory {} was accessible by other users (mode {:o}), restricting it to its owner",
+        dir.display(),
+        mode
+    );
+    restrict_permissions(dir, 0o700)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn make_private(_dir: &Path) -> OckamResult<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path, mode: u32) -> OckamResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|_| Error::IOError.into())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path, _mode: u32) -> OckamResult<()> {
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use ockam_common::error::OckamError;
+
+/// Represents the failures that can occur in
+/// an Ockam PKCS#11 Vault
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    None,
+    /// The PKCS#11 module could not be loaded or initialized
+    ModuleLoad,
+    /// No token with the label is present
+    TokenNotFound,
+    /// A session could not be opened or the PIN was rejected
+    Login,
+    /// A call into the PKCS#11 module failed
+    ModuleFunction,
+    SecretFromAnotherVault,
+    EntryNotFound,
+    InvalidKeyType,
+    InvalidPublicKey,
+    InvalidSignature,
+    AeadAesGcmDecrypt,
+    InvalidHkdfOutputType,
+    HkdfExpandError,
+    SecretNotExportable,
+    OperationNotAllowed,
+    /// The module doesn't implement CKM_HKDF_DERIVE, which HKDF runs on
+    HkdfNotSupported,
+    /// The secret is not stored on the token
+    SecretNotPersistent,
+}
+
+impl Error {
+    /// Error domain
+    pub const ERROR_DOMAIN: &'static str = "VAULT_PKCS11_ERROR_DOMAIN";
+}
+
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
+    }
+}
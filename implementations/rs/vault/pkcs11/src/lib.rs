@@ -0,0 +1,1189 @@
+use crate::error::*;
+use crate::module::*;
+use ockam_common::error::OckamResult;
+use ockam_vault::{
+    types::*, AsymmetricVault, HashVault, RandomVault, Secret, SecretVault, SignerVault,
+    SymmetricVault, VerifierVault,
+};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, RwLock};
+use zeroize::Zeroize;
+
+pub extern crate ockam_vault;
+
+pub mod error;
+mod module;
+
+/// Environment variable holding the user PIN of the token
+pub const PIN_ENV_VAR: &str = "OCKAM_PKCS11_PIN";
+
+const CKA_ID_LENGTH: usize = 16;
+
+/// The keys HKDF derives its outputs from, they stay on the token
+const HKDF_KEY_ATTRIBUTES: SecretAttributes = SecretAttributes {
+    stype: SecretType::Buffer,
+    persistence: SecretPersistence::Ephemeral,
+    length: 0,
+    exportable: false,
+    usage: SecretUsage::HKDF,
+};
+
+/// Pkcs11 vault secret
+#[derive(Debug, Copy, Clone)]
+pub struct Pkcs11VaultSecret(usize);
+
+impl Pkcs11VaultSecret {
+    pub fn downcast_secret(context: &dyn Secret) -> OckamResult<&Pkcs11VaultSecret> {
+        context
+            .downcast_ref::<Pkcs11VaultSecret>()
+            .map_err(|_| Error::SecretFromAnotherVault.into())
+    }
+}
+
+impl Zeroize for Pkcs11VaultSecret {
+    fn zeroize(&mut self) {}
+}
+
+impl Secret for Pkcs11VaultSecret {}
+
+#[derive(Clone, Debug)]
+struct Pkcs11Entry {
+    object: CK_OBJECT_HANDLE,
+    public_object: Option<CK_OBJECT_HANDLE>,
+    attributes: SecretAttributes,
+    public_key: Option<PublicKey>,
+}
+
+/// A vault that keeps its secrets on a PKCS#11 token, e.g. an HSM or SoftHSM.
+///
+/// P-256 keys sign with ECDSA and compute ECDH on the token, AES keys encrypt with
+/// AES-GCM on the token. Persistent secrets are token objects, ephemeral secrets are
+/// session objects that go away with the vault. HKDF derives its outputs on the token
+/// with the CKM_HKDF_DERIVE mechanism of PKCS#11 v3.0, modules without it can't run
+/// HKDF, see [`Pkcs11Vault::supports_hkdf`]. SHA-256 runs on the host, it doesn't
+/// involve secrets.
+///
+/// Usage policies are enforced by the vault and also set as the CKA_SIGN, CKA_DERIVE,
+/// CKA_ENCRYPT and CKA_DECRYPT attributes of the objects. A secret that isn't exportable
+/// is created sensitive and not extractable.
+///
+/// The vault has one session with the token, calls into the module are serialized
+/// on it.
+#[derive(Debug)]
+pub struct Pkcs11Vault {
+    module: Module,
+    hkdf: bool,
+    session: Mutex<CK_SESSION_HANDLE>,
+    entries: RwLock<BTreeMap<usize, Pkcs11Entry>>,
+    next_id: AtomicUsize,
+}
+
+fn module_error(rv: CK_RV) -> ockam_common::error::OckamError {
+    match rv {
+        CKR_ATTRIBUTE_SENSITIVE => Error::SecretNotExportable.into(),
+        _ => Error::ModuleFunction.into(),
+    }
+}
+
+fn hkdf_error(rv: CK_RV) -> ockam_common::error::OckamError {
+    match rv {
+        CKR_MECHANISM_INVALID => Error::HkdfNotSupported.into(),
+        _ => module_error(rv),
+    }
+}
+
+/// CKA_EC_POINT is an uncompressed point wrapped in a DER OCTET STRING
+fn ec_point_to_public_key(ec_point: &[u8]) -> OckamResult<PublicKey> {
+    match ec_point {
+        [0x04, 0x41, point @ ..] if point.len() == P256_PUBLIC_LENGTH => {
+            Ok(PublicKey::new(point.to_vec()))
+        }
+        // some modules leave out the wrapping
+        point if point.len() == P256_PUBLIC_LENGTH && point[0] == 0x04 => {
+            Ok(PublicKey::new(point.to_vec()))
+        }
+        _ => Err(Error::InvalidPublicKey.into()),
+    }
+}
+
+fn public_key_to_ec_point(public_key: &[u8]) -> OckamResult<Vec<u8>> {
+    if public_key.len() != P256_PUBLIC_LENGTH || public_key[0] != 0x04 {
+        return Err(Error::InvalidPublicKey.into());
+    }
+    let mut ec_point = vec![0x04, P256_PUBLIC_LENGTH as u8];
+    ec_point.extend_from_slice(public_key);
+    Ok(ec_point)
+}
+
+impl Pkcs11Vault {
+    /// Load the PKCS#11 module at `module_path` and log into the token with the label
+    pub fn open<P: AsRef<Path>>(module_path: P, token_label: &str, pin: &str) -> OckamResult<Self> {
+        let module = Module::load(module_path.as_ref()).map_err(|_| Error::ModuleLoad)?;
+        let slot = module
+            .find_token(token_label)
+            .map_err(|_| Error::ModuleLoad)?
+            .ok_or(Error::TokenNotFound)?;
+        let session = module.open_session(slot, pin).map_err(|_| Error::Login)?;
+        let hkdf = module.can_derive_with(slot, CKM_HKDF_DERIVE)
+            && module.can_derive_with(slot, CKM_EXTRACT_KEY_FROM_KEY);
+
+        Ok(Self {
+            module,
+            hkdf,
+            session: Mutex::new(session),
+            entries: RwLock::new(BTreeMap::new()),
+            next_id: AtomicUsize::new(0),
+        })
+    }
+
+    /// Whether the module derives HKDF outputs on the token, key exchanges need it.
+    /// SoftHSM2 doesn't implement CKM_HKDF_DERIVE.
+    pub fn supports_hkdf(&self) -> bool {
+        self.hkdf
+    }
+
+    /// The session, locked for the duration of the statement it is used in
+    fn session(&self) -> MutexGuard<'_, CK_SESSION_HANDLE> {
+        self.session.lock().unwrap()
+    }
+
+    fn add_entry(&self, entry: Pkcs11Entry) -> Box<dyn Secret> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.entries.write().unwrap().insert(id, entry);
+        Box::new(Pkcs11VaultSecret(id))
+    }
+
+    fn get_entry(&self, context: &dyn Secret) -> OckamResult<Pkcs11Entry> {
+        let id = Pkcs11VaultSecret::downcast_secret(context)?.0;
+        self.entries
+            .read()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| Error::EntryNotFound.into())
+    }
+
+    /// Get the entry of a secret whose usage policy allows `usage`
+    fn get_entry_for(&self, context: &dyn Secret, usage: SecretUsage) -> OckamResult<Pkcs11Entry> {
+        let entry = self.get_entry(context)?;
+        if !entry.attributes.usage.contains(usage) {
+            return Err(Error::OperationNotAllowed.into());
+        }
+        Ok(entry)
+    }
+
+    /// Attributes shared by every private and secret key the vault creates
+    fn key_template(
+        class: CK_ULONG,
+        key_type: CK_ULONG,
+        attributes: &SecretAttributes,
+    ) -> Template {
+        Template::new()
+            .ulong(CKA_CLASS, class)
+            .ulong(CKA_KEY_TYPE, key_type)
+            .bool(
+                CKA_TOKEN,
+                matches!(attributes.persistence, SecretPersistence::Persistent),
+            )
+            .bool(CKA_PRIVATE, true)
+            .bool(CKA_SENSITIVE, !attributes.exportable)
+            .bool(CKA_EXTRACTABLE, attributes.exportable)
+    }
+
+    fn public_key_template(attributes: &SecretAttributes, id: &[u8]) -> Template {
+        Template::new()
+            .ulong(CKA_CLASS, CKO_PUBLIC_KEY)
+            .ulong(CKA_KEY_TYPE, CKK_EC)
+            .bool(
+                CKA_TOKEN,
+                matches!(attributes.persistence, SecretPersistence::Persistent),
+            )
+            .bool(CKA_VERIFY, true)
+            .bytes(CKA_EC_PARAMS, P256_EC_PARAMS.to_vec())
+            .bytes(CKA_ID, id.to_vec())
+    }
+
+    fn private_key_template(attributes: &SecretAttributes, id: &[u8]) -> Template {
+        Self::key_template(CKO_PRIVATE_KEY, CKK_EC, attributes)
+            .bool(CKA_SIGN, attributes.usage.contains(SecretUsage::SIGN))
+            .bool(CKA_DERIVE, attributes.usage.contains(SecretUsage::ECDH))
+            .bytes(CKA_ID, id.to_vec())
+    }
+
+    fn secret_key_template(attributes: &SecretAttributes) -> OckamResult<Template> {
+        let aead = attributes.usage.contains(SecretUsage::AEAD);
+        let hkdf = attributes.usage.contains(SecretUsage::HKDF);
+        match attributes.stype {
+            SecretType::Aes => {
+                if attributes.length != AES128_SECRET_LENGTH
+                    && attributes.length != AES256_SECRET_LENGTH
+                {
+                    return Err(Error::InvalidKeyType.into());
+                }
+                Ok(Self::key_template(CKO_SECRET_KEY, CKK_AES, attributes)
+                    .bool(CKA_ENCRYPT, aead)
+                    .bool(CKA_DECRYPT, aead)
+                    .bool(CKA_DERIVE, hkdf))
+            }
+            SecretType::Buffer => {
+                Ok(
+                    Self::key_template(CKO_SECRET_KEY, CKK_GENERIC_SECRET, attributes)
+                        .bool(CKA_DERIVE, hkdf),
+                )
+            }
+            _ => Err(Error::InvalidKeyType.into()),
+        }
+    }
+
+    fn new_key_id(&self) -> OckamResult<[u8; CKA_ID_LENGTH]> {
+        let mut id = [0u8; CKA_ID_LENGTH];
+        self.random_bytes_generate(&mut id)?;
+        Ok(id)
+    }
+
+    /// Read a secret value from the token, this needs the secret to be exportable
+    fn read_value(&self, entry: &Pkcs11Entry) -> OckamResult<SecretKey> {
+        if !entry.attributes.exportable {
+            return Err(Error::SecretNotExportable.into());
+        }
+        let value = self
+            .module
+            .get_attribute(*self.session(), entry.object, CKA_VALUE)
+            .map_err(module_error)?;
+        Ok(SecretKey::new(value))
+    }
+
+    /// Find a persistent P-256 key on the token by its label
+    pub fn find_secret_by_label(&self, label: &str) -> OckamResult<Box<dyn Secret>> {
+        let template = Template::new()
+            .ulong(CKA_CLASS, CKO_PRIVATE_KEY)
+            .ulong(CKA_KEY_TYPE, CKK_EC)
+            .bool(CKA_TOKEN, true)
+            .bytes(CKA_LABEL, label.as_bytes().to_vec());
+        let object = *self
+            .module
+            .find_objects(*self.session(), template)
+            .map_err(module_error)?
+            .first()
+            .ok_or(Error::EntryNotFound)?;
+
+        let id = self
+            .module
+            .get_attribute(*self.session(), object, CKA_ID)
+            .map_err(module_error)?;
+        let template = Template::new()
+            .ulong(CKA_CLASS, CKO_PUBLIC_KEY)
+            .bool(CKA_TOKEN, true)
+            .bytes(CKA_ID, id);
+        let public_object = *self
+            .module
+            .find_objects(*self.session(), template)
+            .map_err(module_error)?
+            .first()
+            .ok_or(Error::EntryNotFound)?;
+        let ec_point = self
+            .module
+            .get_attribute(*self.session(), public_object, CKA_EC_POINT)
+            .map_err(module_error)?;
+        let public_key = ec_point_to_public_key(&ec_point)?;
+
+        let flag = |kind| {
+            self.module
+                .get_bool_attribute(*self.session(), object, kind)
+                .map_err(module_error)
+        };
+        let exportable = flag(CKA_EXTRACTABLE)? && !flag(CKA_SENSITIVE)?;
+        let mut usage = SecretUsage::NONE;
+        if flag(CKA_SIGN)? {
+            usage = usage | SecretUsage::SIGN;
+        }
+        if flag(CKA_DERIVE)? {
+            usage = usage | SecretUsage::ECDH;
+        }
+
+        Ok(self.add_entry(Pkcs11Entry {
+            object,
+            public_object: Some(public_object),
+            attributes: SecretAttributes {
+                stype: SecretType::P256,
+                persistence: SecretPersistence::Persistent,
+                length: P256_SECRET_LENGTH,
+                exportable,
+                usage,
+            },
+            public_key: Some(public_key),
+        }))
+    }
+
+    /// Set the label of a persistent secret, it can then be found with `find_secret_by_label`
+    pub fn set_secret_label(&self, context: &dyn Secret, label: &str) -> OckamResult<()> {
+        let entry = self.get_entry(context)?;
+        if !matches!(entry.attributes.persistence, SecretPersistence::Persistent) {
+            return Err(Error::SecretNotPersistent.into());
+        }
+        for object in std::iter::once(entry.object).chain(entry.public_object) {
+            let template = Template::new().bytes(CKA_LABEL, label.as_bytes().to_vec());
+            self.module
+                .set_attributes(*self.session(), object, template)
+                .map_err(module_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Zeroize for Pkcs11Vault {
+    fn zeroize(&mut self) {
+        // session objects are destroyed with the session, token objects stay on the token
+        let session = *self.session.get_mut().unwrap();
+        for (_, entry) in std::mem::take(self.entries.get_mut().unwrap()) {
+            if !matches!(entry.attributes.persistence, SecretPersistence::Persistent) {
+                for object in std::iter::once(entry.object).chain(entry.public_object) {
+                    let _ = self.module.destroy_object(session, object);
+                }
+            }
+        }
+        *self.next_id.get_mut() = 0;
+    }
+}
+
+impl Drop for Pkcs11Vault {
+    fn drop(&mut self) {
+        self.zeroize();
+        let _ = self.module.close_session(*self.session.get_mut().unwrap());
+    }
+}
+
+impl SecretVault for Pkcs11Vault {
+    fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+        let entry = match attributes.stype {
+            SecretType::P256 => {
+                let id = self.new_key_id()?;
+                let (public_object, object) = self
+                    .module
+                    .generate_key_pair(
+                        *self.session(),
+                        CK_MECHANISM::new(CKM_EC_KEY_PAIR_GEN),
+                        Self::public_key_template(&attributes, &id),
+                        Self::private_key_template(&attributes, &id),
+                    )
+                    .map_err(module_error)?;
+                let ec_point = self
+                    .module
+                    .get_attribute(*self.session(), public_object, CKA_EC_POINT)
+                    .map_err(module_error)?;
+                Pkcs11Entry {
+                    object,
+                    public_object: Some(public_object),
+                    attributes,
+                    public_key: Some(ec_point_to_public_key(&ec_point)?),
+                }
+            }
+            SecretType::Aes | SecretType::Buffer => {
+                let mechanism = if attributes.stype == SecretType::Aes {
+                    CKM_AES_KEY_GEN
+                } else {
+                    CKM_GENERIC_SECRET_KEY_GEN
+                };
+                let template = Self::secret_key_template(&attributes)?
+                    .ulong(CKA_VALUE_LEN, attributes.length as CK_ULONG);
+                let object = self
+                    .module
+                    .generate_key(*self.session(), CK_MECHANISM::new(mechanism), template)
+                    .map_err(module_error)?;
+                Pkcs11Entry {
+                    object,
+                    public_object: None,
+                    attributes,
+                    public_key: None,
+                }
+            }
+            _ => return Err(Error::InvalidKeyType.into()),
+        };
+
+        Ok(self.add_entry(entry))
+    }
+
+    fn secret_import(
+        &self,
+        secret: &[u8],
+        attributes: SecretAttributes,
+    ) -> OckamResult<Box<dyn Secret>> {
+        let entry = match attributes.stype {
+            SecretType::P256 => {
+                if secret.len() != P256_SECRET_LENGTH {
+                    return Err(Error::InvalidKeyType.into());
+                }
+                let sk = p256::SecretKey::from_slice(secret).map_err(|_| Error::InvalidKeyType)?;
+                let ap = sk.public_key().to_encoded_point(false);
+                let public_key = PublicKey::new(ap.as_bytes().to_vec());
+
+                let id = self.new_key_id()?;
+                let object = self
+                    .module
+                    .create_object(
+                        *self.session(),
+                        Self::private_key_template(&attributes, &id)
+                            .bytes(CKA_EC_PARAMS, P256_EC_PARAMS.to_vec())
+                            .bytes(CKA_VALUE, secret.to_vec()),
+                    )
+                    .map_err(module_error)?;
+                let public_object = self
+                    .module
+                    .create_object(
+                        *self.session(),
+                        Self::public_key_template(&attributes, &id)
+                            .bytes(CKA_EC_POINT, public_key_to_ec_point(public_key.as_ref())?),
+                    )
+                    .map_err(module_error)?;
+                Pkcs11Entry {
+                    object,
+                    public_object: Some(public_object),
+                    attributes,
+                    public_key: Some(public_key),
+                }
+            }
+            SecretType::Aes | SecretType::Buffer => {
+                if secret.len() != attributes.length {
+                    return Err(Error::InvalidKeyType.into());
+                }
+                let template =
+                    Self::secret_key_template(&attributes)?.bytes(CKA_VALUE, secret.to_vec());
+                let object = self
+                    .module
+                    .create_object(*self.session(), template)
+                    .map_err(module_error)?;
+                Pkcs11Entry {
+                    object,
+                    public_object: None,
+                    attributes,
+                    public_key: None,
+                }
+            }
+            _ => return Err(Error::InvalidKeyType.into()),
+        };
+
+        Ok(self.add_entry(entry))
+    }
+
+    fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+        let entry = self.get_entry(context)?;
+        self.read_value(&entry)
+    }
+
+    fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes> {
+        Ok(self.get_entry(context)?.attributes)
+    }
+
+    fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
+        self.get_entry(context)?
+            .public_key
+            .clone()
+            .ok_or_else(|| Error::InvalidKeyType.into())
+    }
+
+    fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+        let id = Pkcs11VaultSecret::downcast_secret(context.as_ref())?.0;
+        let entry = self
+            .entries
+            .write()
+            .unwrap()
+            .remove(&id)
+            .ok_or(Error::EntryNotFound)?;
+        for object in std::iter::once(entry.object).chain(entry.public_object) {
+            self.module
+                .destroy_object(*self.session(), object)
+                .map_err(module_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl SignerVault for Pkcs11Vault {
+    /// P-256 keys sign the SHA-256 digest of `data` with ECDSA, the signature is r || s
+    fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]> {
+        let entry = self.get_entry_for(secret_key, SecretUsage::SIGN)?;
+        if entry.attributes.stype != SecretType::P256 {
+            return Err(Error::InvalidKeyType.into());
+        }
+        let digest = self.sha256(data)?;
+        let mut signature = [0u8; 64];
+        let len = self
+            .module
+            .sign(
+                *self.session(),
+                CK_MECHANISM::new(CKM_ECDSA),
+                entry.object,
+                &digest,
+                &mut signature,
+            )
+            .map_err(module_error)?;
+        if len != signature.len() {
+            return Err(Error::InvalidSignature.into());
+        }
+        Ok(signature)
+    }
+}
+
+impl VerifierVault for Pkcs11Vault {
+    fn verify(
+        &self,
+        signature: &[u8; 64],
+        public_key: &[u8],
+        public_key_type: SecretType,
+        data: &[u8],
+    ) -> OckamResult<()> {
+        if public_key_type != SecretType::P256 {
+            return Err(Error::InvalidKeyType.into());
+        }
+        let template = Template::new()
+            .ulong(CKA_CLASS, CKO_PUBLIC_KEY)
+            .ulong(CKA_KEY_TYPE, CKK_EC)
+            .bool(CKA_TOKEN, false)
+            .bool(CKA_VERIFY, true)
+            .bytes(CKA_EC_PARAMS, P256_EC_PARAMS.to_vec())
+            .bytes(CKA_EC_POINT, public_key_to_ec_point(public_key)?);
+        let object = self
+            .module
+            .create_object(*self.session(), template)
+            .map_err(|_| Error::InvalidPublicKey)?;
+
+        let digest = self.sha256(data)?;
+        let verified = self.module.verify(
+            *self.session(),
+            CK_MECHANISM::new(CKM_ECDSA),
+            object,
+            &digest,
+            signature,
+        );
+        self.module
+            .destroy_object(*self.session(), object)
+            .map_err(module_error)?;
+        verified.map_err(|rv| match rv {
+            CKR_SIGNATURE_INVALID | CKR_SIGNATURE_LEN_RANGE => Error::InvalidSignature.into(),
+            _ => module_error(rv),
+        })
+    }
+}
+
+impl AsymmetricVault for Pkcs11Vault {
+    fn ec_diffie_hellman(
+        &self,
+        context: &dyn Secret,
+        peer_public_key: &[u8],
+    ) -> OckamResult<Box<dyn Secret>> {
+        let entry = self.get_entry_for(context, SecretUsage::ECDH)?;
+        if entry.attributes.stype != SecretType::P256 {
+            return Err(Error::InvalidKeyType.into());
+        }
+        if peer_public_key.len() != P256_PUBLIC_LENGTH {
+            return Err(Error::InvalidPublicKey.into());
+        }
+
+        // the shared secret is only used as HKDF input and never leaves the token
+        let attributes = SecretAttributes {
+            stype: SecretType::Buffer,
+            persistence: SecretPersistence::Ephemeral,
+            length: 32,
+            exportable: false,
+            usage: SecretUsage::HKDF,
+        };
+        let template = Self::secret_key_template(&attributes)?
+            .ulong(CKA_VALUE_LEN, attributes.length as CK_ULONG);
+        let mut params = CK_ECDH1_DERIVE_PARAMS::new(peer_public_key);
+        let object = self
+            .module
+            .derive_key(
+                *self.session(),
+                CK_MECHANISM::with_parameter(CKM_ECDH1_DERIVE, &mut params),
+                entry.object,
+                template,
+            )
+            .map_err(|_| Error::InvalidPublicKey)?;
+
+        Ok(self.add_entry(Pkcs11Entry {
+            object,
+            public_object: None,
+            attributes,
+            public_key: None,
+        }))
+    }
+}
+
+impl HashVault for Pkcs11Vault {
+    fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]> {
+        let digest = Sha256::digest(data);
+        let mut output = [0u8; 32];
+        output.copy_from_slice(&digest[..]);
+        Ok(output)
+    }
+
+    fn hkdf_sha256(
+        &self,
+        salt: &dyn Secret,
+        info: &[u8],
+        ikm: Option<&dyn Secret>,
+        output_attributes: Vec<SecretAttributes>,
+    ) -> OckamResult<Vec<Box<dyn Secret>>> {
+        let salt = self.get_entry_for(salt, SecretUsage::HKDF)?;
+        let ikm = match ikm {
+            Some(ikm) => {
+                let ikm = self.get_entry_for(ikm, SecretUsage::HKDF)?;
+                if ikm.attributes.stype != SecretType::Buffer {
+                    return Err(Error::InvalidKeyType.into());
+                }
+                Some(ikm)
+            }
+            None => None,
+        };
+        for attributes in output_attributes.iter() {
+            if attributes.stype != SecretType::Aes && attributes.stype != SecretType::Buffer {
+                return Err(Error::InvalidHkdfOutputType.into());
+            }
+            if attributes.length > 32 {
+                return Err(Error::InvalidHkdfOutputType.into());
+            }
+        }
+        if !self.hkdf {
+            return Err(Error::HkdfNotSupported.into());
+        }
+        if output_attributes.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // CKM_HKDF_DERIVE takes the ikm as its base key, no ikm is an empty session key
+        let ikm_object = match &ikm {
+            Some(ikm) => ikm.object,
+            None => self
+                .module
+                .create_object(
+                    *self.session(),
+                    Self::key_template(CKO_SECRET_KEY, CKK_GENERIC_SECRET, &HKDF_KEY_ATTRIBUTES)
+                        .bool(CKA_DERIVE, true)
+                        .bytes(CKA_VALUE, vec![]),
+                )
+                .map_err(module_error)?,
+        };
+        // every output takes 32 bytes of the output key material, like the software vault
+        let okm_attributes = SecretAttributes {
+            length: output_attributes.len() * 32,
+            ..HKDF_KEY_ATTRIBUTES
+        };
+        let mut params = CK_HKDF_PARAMS::new(salt.object, info);
+        let okm = self
+            .module
+            .derive_key(
+                *self.session(),
+                CK_MECHANISM::with_parameter(CKM_HKDF_DERIVE, &mut params),
+                ikm_object,
+                Self::secret_key_template(&okm_attributes)?
+                    .ulong(CKA_VALUE_LEN, okm_attributes.length as CK_ULONG),
+            )
+            .map_err(hkdf_error);
+        if ikm.is_none() {
+            self.module
+                .destroy_object(*self.session(), ikm_object)
+                .map_err(module_error)?;
+        }
+        let okm = okm?;
+
+        let mut secrets = Vec::<Box<dyn Secret>>::new();
+        let mut result = Ok(());
+        for (index, attributes) in output_attributes.into_iter().enumerate() {
+            let mut bit_index = (index * 32 * 8) as CK_ULONG;
+            let object = Self::secret_key_template(&attributes).and_then(|template| {
+                self.module
+                    .derive_key(
+                        *self.session(),
+                        CK_MECHANISM::with_parameter(CKM_EXTRACT_KEY_FROM_KEY, &mut bit_index),
+                        okm,
+                        template.ulong(CKA_VALUE_LEN, attributes.length as CK_ULONG),
+                    )
+                    .map_err(hkdf_error)
+            });
+            match object {
+                Ok(object) => secrets.push(self.add_entry(Pkcs11Entry {
+                    object,
+                    public_object: None,
+                    attributes,
+                    public_key: None,
+                })),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.module
+            .destroy_object(*self.session(), okm)
+            .map_err(module_error)?;
+        result?;
+
+        Ok(secrets)
+    }
+}
+
+impl SymmetricVault for Pkcs11Vault {
+    fn aead_aes_gcm_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let entry = self.get_entry_for(context, SecretUsage::AEAD)?;
+        if entry.attributes.stype != SecretType::Aes {
+            return Err(Error::InvalidKeyType.into());
+        }
+        let mut params = CK_GCM_PARAMS::new(nonce, aad);
+        self.module
+            .encrypt(
+                *self.session(),
+                CK_MECHANISM::with_parameter(CKM_AES_GCM, &mut params),
+                entry.object,
+                plaintext,
+                plaintext.len() + 16,
+            )
+            .map_err(module_error)
+    }
+
+    fn aead_aes_gcm_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let entry = self.get_entry_for(context, SecretUsage::AEAD)?;
+        if entry.attributes.stype != SecretType::Aes {
+            return Err(Error::InvalidKeyType.into());
+        }
+        let mut params = CK_GCM_PARAMS::new(nonce, aad);
+        self.module
+            .decrypt(
+                *self.session(),
+                CK_MECHANISM::with_parameter(CKM_AES_GCM, &mut params),
+                entry.object,
+                cipher_text,
+                cipher_text.len(),
+            )
+            .map_err(|rv| match rv {
+                CKR_ENCRYPTED_DATA_INVALID | CKR_ENCRYPTED_DATA_LEN_RANGE => {
+                    Error::AeadAesGcmDecrypt.into()
+                }
+                _ => module_error(rv),
+            })
+    }
+
+    /// The vault holds no ChaCha20-Poly1305 keys
+    fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        _plaintext: &[u8],
+        _nonce: &[u8],
+        _aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        self.get_entry(context)?;
+        Err(Error::InvalidKeyType.into())
+    }
+
+    /// The vault holds no ChaCha20-Poly1305 keys
+    fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
+        _cipher_text: &[u8],
+        _nonce: &[u8],
+        _aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        self.get_entry(context)?;
+        Err(Error::InvalidKeyType.into())
+    }
+}
+
+impl RandomVault for Pkcs11Vault {
+    fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+        self.module
+            .generate_random(*self.session(), buffer)
+            .map_err(module_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam_kex::{CipherSuite, KeyExchanger, NewKeyExchanger};
+    use ockam_kex_xx::XXNewKeyExchanger;
+    use ockam_vault_software::DefaultVault;
+    use std::process::Command;
+    use std::sync::{Arc, Once};
+
+    /// Points at a SoftHSM2 module when it isn't installed in a usual place
+    const MODULE_ENV_VAR: &str = "OCKAM_PKCS11_TEST_MODULE";
+    const SOFTHSM_MODULES: [&str; 3] = [
+        "/usr/lib/softhsm/libsofthsm2.so",
+        "/usr/lib/x86_64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/local/lib/softhsm/libsofthsm2.so",
+    ];
+    const TEST_TOKEN: &str = "ockam-test";
+    const TEST_PIN: &str = "1234";
+
+    /// Open a vault on a SoftHSM2 token that is set up once per test run. The tests
+    /// only run with the `softhsm` feature, which requires SoftHSM2 to be installed.
+    fn test_vault() -> Pkcs11Vault {
+        static SETUP: Once = Once::new();
+
+        let module = std::env::var(MODULE_ENV_VAR).ok().or_else(|| {
+            SOFTHSM_MODULES
+                .iter()
+                .find(|path| Path::new(path).exists())
+                .map(|path| path.to_string())
+        });
+        let module = module.unwrap_or_else(|| {
+            panic!(
+                "the softhsm feature requires SoftHSM2, install it or set {}",
+                MODULE_ENV_VAR
+            )
+        });
+
+        // SoftHSM2 reads its configuration once per process, every test shares the
+        // token. The directory is replaced by the next run instead of piling up.
+        SETUP.call_once(|| {
+            let dir = std::env::temp_dir().join("ockam_softhsm_test");
+            let _ = std::fs::remove_dir_all(&dir);
+            let tokens = dir.join("tokens");
+            std::fs::create_dir_all(&tokens).unwrap();
+            let conf = dir.join("softhsm2.conf");
+            std::fs::write(
+                &conf,
+                format!("directories.tokendir = {}\n", tokens.display()),
+            )
+            .unwrap();
+            std::env::set_var("SOFTHSM2_CONF", &conf);
+
+            let status = Command::new("softhsm2-util")
+                .args(["--init-token", "--free", "--label", TEST_TOKEN])
+                .args(["--pin", TEST_PIN, "--so-pin", TEST_PIN])
+                .status()
+                .expect("softhsm2-util sets up the test token");
+            assert!(status.success());
+        });
+
+        Pkcs11Vault::open(module, TEST_TOKEN, TEST_PIN).unwrap()
+    }
+
+    fn p256_attributes(persistence: SecretPersistence) -> SecretAttributes {
+        SecretAttributes {
+            stype: SecretType::P256,
+            persistence,
+            length: P256_SECRET_LENGTH,
+            exportable: false,
+            usage: SecretUsage::SIGN | SecretUsage::ECDH,
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "softhsm"), ignore)]
+    fn sign_and_verify() {
+        let vault = test_vault();
+        let sk = vault
+            .secret_generate(p256_attributes(SecretPersistence::Ephemeral))
+            .unwrap();
+        let pk = vault.secret_public_key_get(sk.as_ref()).unwrap();
+        assert_eq!(pk.as_ref().len(), P256_PUBLIC_LENGTH);
+
+        let signature = vault.sign(sk.as_ref(), b"hello world").unwrap();
+        vault
+            .verify(&signature, pk.as_ref(), SecretType::P256, b"hello world")
+            .unwrap();
+        let error = vault
+            .verify(&signature, pk.as_ref(), SecretType::P256, b"hello there")
+            .unwrap_err();
+        assert_eq!(error.code(), Error::InvalidSignature as u32);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "softhsm"), ignore)]
+    fn ecdh_and_import_match_the_software_vault() {
+        let vault = test_vault();
+        let software = DefaultVault::default();
+        let attributes = SecretAttributes {
+            exportable: true,
+            ..p256_attributes(SecretPersistence::Ephemeral)
+        };
+        let sk = vault.secret_generate(attributes).unwrap();
+        let pk = vault.secret_public_key_get(sk.as_ref()).unwrap();
+        let software_sk = software.secret_generate(attributes).unwrap();
+        let software_pk = software
+            .secret_public_key_get(software_sk.as_ref())
+            .unwrap();
+
+        // shared secrets stay on the token, hkdf_matches_the_software_vault compares them
+        let shared = vault
+            .ec_diffie_hellman(sk.as_ref(), software_pk.as_ref())
+            .unwrap();
+        let error = vault.secret_export(shared.as_ref()).unwrap_err();
+        assert_eq!(error.code(), Error::SecretNotExportable as u32);
+        let error = vault
+            .ec_diffie_hellman(sk.as_ref(), &software_pk.as_ref()[1..])
+            .unwrap_err();
+        assert_eq!(error.code(), Error::InvalidPublicKey as u32);
+
+        // imported keys have the same public key in both vaults
+        let exported = vault.secret_export(sk.as_ref()).unwrap();
+        let imported = software
+            .secret_import(exported.as_ref(), attributes)
+            .unwrap();
+        assert_eq!(
+            software.secret_public_key_get(imported.as_ref()).unwrap(),
+            pk
+        );
+        let exported = software.secret_export(software_sk.as_ref()).unwrap();
+        let imported = vault.secret_import(exported.as_ref(), attributes).unwrap();
+        assert_eq!(
+            vault.secret_public_key_get(imported.as_ref()).unwrap(),
+            software_pk
+        );
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "softhsm"), ignore)]
+    fn aes_gcm_matches_the_software_vault() {
+        let vault = test_vault();
+        let software = DefaultVault::default();
+        let attributes = SecretAttributes {
+            stype: SecretType::Aes,
+            persistence: SecretPersistence::Ephemeral,
+            length: AES128_SECRET_LENGTH,
+            exportable: false,
+            usage: SecretUsage::AEAD,
+        };
+        let key = vault.secret_import(&[5u8; 16], attributes).unwrap();
+        let software_key = software.secret_import(&[5u8; 16], attributes).unwrap();
+
+        let nonce = [3u8; 12];
+        let ciphertext = vault
+            .aead_aes_gcm_encrypt(key.as_ref(), b"plaintext", &nonce, b"aad")
+            .unwrap();
+        let plaintext = software
+            .aead_aes_gcm_decrypt(software_key.as_ref(), &ciphertext, &nonce, b"aad")
+            .unwrap();
+        assert_eq!(plaintext, b"plaintext");
+
+        let ciphertext = software
+            .aead_aes_gcm_encrypt(software_key.as_ref(), b"reply", &nonce, b"aad")
+            .unwrap();
+        let plaintext = vault
+            .aead_aes_gcm_decrypt(key.as_ref(), &ciphertext, &nonce, b"aad")
+            .unwrap();
+        assert_eq!(plaintext, b"reply");
+
+        let error = vault
+            .aead_aes_gcm_decrypt(key.as_ref(), &ciphertext, &nonce, b"other")
+            .unwrap_err();
+        assert_eq!(error.code(), Error::AeadAesGcmDecrypt as u32);
+    }
+
+    fn buffer(length: usize) -> SecretAttributes {
+        SecretAttributes {
+            stype: SecretType::Buffer,
+            persistence: SecretPersistence::Ephemeral,
+            length,
+            exportable: true,
+            usage: SecretUsage::HKDF,
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "softhsm"), ignore)]
+    fn hkdf_needs_the_mechanism() {
+        let vault = test_vault();
+        let salt = vault.secret_import(&[1u8; 32], buffer(32)).unwrap();
+        let result = vault.hkdf_sha256(salt.as_ref(), b"", None, vec![buffer(32)]);
+        match result {
+            Ok(_) => assert!(vault.supports_hkdf()),
+            Err(e) => {
+                assert!(!vault.supports_hkdf());
+                assert_eq!(e.code(), Error::HkdfNotSupported as u32);
+            }
+        }
+    }
+
+    /// Derive the outputs from a fixed salt and ikm, returns the first one exported and all handles
+    fn derive<V: HashVault + SecretVault>(
+        vault: &V,
+        outputs: &[SecretAttributes],
+    ) -> (SecretKey, Vec<Box<dyn Secret>>) {
+        let salt = vault.secret_import(&[1u8; 32], buffer(32)).unwrap();
+        let ikm = vault.secret_import(&[2u8; 16], buffer(16)).unwrap();
+        let okm = vault
+            .hkdf_sha256(salt.as_ref(), b"info", Some(ikm.as_ref()), outputs.to_vec())
+            .unwrap();
+        (vault.secret_export(okm[0].as_ref()).unwrap(), okm)
+    }
+
+    #[test]
+    #[ignore = "SoftHSM2 doesn't implement CKM_HKDF_DERIVE, run against a module that does"]
+    fn hkdf_matches_the_software_vault() {
+        let vault = test_vault();
+        let software = DefaultVault::default();
+        let outputs = vec![
+            buffer(32),
+            SecretAttributes {
+                stype: SecretType::Aes,
+                persistence: SecretPersistence::Ephemeral,
+                length: AES128_SECRET_LENGTH,
+                exportable: false,
+                usage: SecretUsage::AEAD,
+            },
+        ];
+
+        let keys = [derive(&vault, &outputs), derive(&software, &outputs)];
+        assert_eq!(keys[0].0, keys[1].0);
+        let nonce = [3u8; 12];
+        let ciphertext = vault
+            .aead_aes_gcm_encrypt(keys[0].1[1].as_ref(), b"plaintext", &nonce, b"aad")
+            .unwrap();
+        let plaintext = software
+            .aead_aes_gcm_decrypt(keys[1].1[1].as_ref(), &ciphertext, &nonce, b"aad")
+            .unwrap();
+        assert_eq!(plaintext, b"plaintext");
+
+        // the software vault doesn't export shared secrets, compare keys derived from them
+        let attributes = p256_attributes(SecretPersistence::Ephemeral);
+        let sk = vault.secret_generate(attributes).unwrap();
+        let pk = vault.secret_public_key_get(sk.as_ref()).unwrap();
+        let software_sk = software.secret_generate(attributes).unwrap();
+        let software_pk = software
+            .secret_public_key_get(software_sk.as_ref())
+            .unwrap();
+        let shared = vault
+            .ec_diffie_hellman(sk.as_ref(), software_pk.as_ref())
+            .unwrap();
+        let software_shared = software
+            .ec_diffie_hellman(software_sk.as_ref(), pk.as_ref())
+            .unwrap();
+        let salt = vault.secret_import(&[0u8; 32], buffer(32)).unwrap();
+        let derived = vault
+            .hkdf_sha256(salt.as_ref(), b"", Some(shared.as_ref()), vec![buffer(32)])
+            .unwrap();
+        let software_salt = software.secret_import(&[0u8; 32], buffer(32)).unwrap();
+        let software_derived = software
+            .hkdf_sha256(
+                software_salt.as_ref(),
+                b"",
+                Some(software_shared.as_ref()),
+                vec![buffer(32)],
+            )
+            .unwrap();
+        assert_eq!(
+            vault.secret_export(derived[0].as_ref()).unwrap(),
+            software
+                .secret_export(software_derived[0].as_ref())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    #[ignore = "SoftHSM2 doesn't implement CKM_HKDF_DERIVE, run against a module that does"]
+    fn xx_handshake_on_the_token() {
+        let initiator_vault = Arc::new(test_vault());
+        let responder_vault = Arc::new(test_vault());
+        let exchangers = XXNewKeyExchanger::new(
+            CipherSuite::P256Aes128GcmSha256,
+            initiator_vault.clone(),
+            responder_vault.clone(),
+        );
+        let mut initiator = exchangers.initiator(None);
+        let mut responder = exchangers.responder(None);
+
+        let m1 = initiator.process(&[]).unwrap();
+        responder.process(&m1).unwrap();
+        let m2 = responder.process(&[]).unwrap();
+        initiator.process(&m2).unwrap();
+        let m3 = initiator.process(&[]).unwrap();
+        responder.process(&m3).unwrap();
+        assert!(initiator.is_complete() && responder.is_complete());
+
+        let initiator = Box::new(initiator).finalize().unwrap();
+        let responder = Box::new(responder).finalize().unwrap();
+        assert_eq!(initiator.h, responder.h);
+        assert_eq!(
+            initiator.remote_static_public_key,
+            responder_vault
+                .secret_public_key_get(responder.local_static_secret.as_ref().as_ref())
+                .unwrap()
+        );
+
+        let nonce = [0u8; 12];
+        let ciphertext = initiator_vault
+            .aead_aes_gcm_encrypt(initiator.encrypt_key.as_ref(), b"hello", &nonce, &[])
+            .unwrap();
+        let plaintext = responder_vault
+            .aead_aes_gcm_decrypt(responder.decrypt_key.as_ref(), &ciphertext, &nonce, &[])
+            .unwrap();
+        assert_eq!(plaintext, b"hello");
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "softhsm"), ignore)]
+    fn usage_policy() {
+        let vault = test_vault();
+        let sk = vault
+            .secret_generate(SecretAttributes {
+                usage: SecretUsage::ECDH,
+                ..p256_attributes(SecretPersistence::Ephemeral)
+            })
+            .unwrap();
+        let error = vault.secret_export(sk.as_ref()).unwrap_err();
+        assert_eq!(error.code(), Error::SecretNotExportable as u32);
+        let error = vault.sign(sk.as_ref(), b"data").unwrap_err();
+        assert_eq!(error.code(), Error::OperationNotAllowed as u32);
+
+        let key = vault
+            .secret_generate(SecretAttributes {
+                stype: SecretType::Aes,
+                persistence: SecretPersistence::Ephemeral,
+                length: AES256_SECRET_LENGTH,
+                exportable: false,
+                usage: SecretUsage::AEAD | SecretUsage::HKDF,
+            })
+            .unwrap();
+        assert!(vault
+            .aead_aes_gcm_encrypt(key.as_ref(), b"data", &[0u8; 12], b"")
+            .is_ok());
+        let error = vault
+            .hkdf_sha256(sk.as_ref(), b"", None, vec![buffer(32)])
+            .unwrap_err();
+        assert_eq!(error.code(), Error::OperationNotAllowed as u32);
+        let error = vault
+            .hkdf_sha256(key.as_ref(), b"", Some(key.as_ref()), vec![buffer(32)])
+            .unwrap_err();
+        assert_eq!(error.code(), Error::InvalidKeyType as u32);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "softhsm"), ignore)]
+    fn persistent_keys_are_found_by_label() {
+        let vault = test_vault();
+        let sk = vault
+            .secret_generate(p256_attributes(SecretPersistence::Persistent))
+            .unwrap();
+        let pk = vault.secret_public_key_get(sk.as_ref()).unwrap();
+        vault.set_secret_label(sk.as_ref(), "identity").unwrap();
+
+        let other = test_vault();
+        let found = other.find_secret_by_label("identity").unwrap();
+        assert_eq!(other.secret_public_key_get(found.as_ref()).unwrap(), pk);
+        assert_eq!(
+            other.secret_attributes_get(found.as_ref()).unwrap(),
+            p256_attributes(SecretPersistence::Persistent)
+        );
+
+        other.secret_destroy(found).unwrap();
+        assert!(vault.find_secret_by_label("identity").is_err());
+        assert!(vault.find_secret_by_label("missing").is_err());
+    }
+}
@@ -0,0 +1,915 @@
+//! The parts of the PKCS#11 v2.40 interface the vault uses, loaded at runtime from a module,
+//! and the HKDF mechanism of PKCS#11 v3.0
+#![allow(non_camel_case_types)]
+
+use libloading::Library;
+use std::os::raw::{c_ulong, c_void};
+use std::path::Path;
+use std::ptr;
+use zeroize::Zeroize;
+
+pub(crate) type CK_ULONG = c_ulong;
+pub(crate) type CK_BBOOL = u8;
+pub(crate) type CK_RV = CK_ULONG;
+pub(crate) type CK_SLOT_ID = CK_ULONG;
+pub(crate) type CK_SESSION_HANDLE = CK_ULONG;
+pub(crate) type CK_OBJECT_HANDLE = CK_ULONG;
+
+pub(crate) const CKR_OK: CK_RV = 0x0;
+pub(crate) const CKR_ATTRIBUTE_SENSITIVE: CK_RV = 0x11;
+pub(crate) const CKR_ENCRYPTED_DATA_INVALID: CK_RV = 0x40;
+pub(crate) const CKR_ENCRYPTED_DATA_LEN_RANGE: CK_RV = 0x41;
+pub(crate) const CKR_FUNCTION_NOT_SUPPORTED: CK_RV = 0x54;
+pub(crate) const CKR_MECHANISM_INVALID: CK_RV = 0x70;
+pub(crate) const CKR_SIGNATURE_INVALID: CK_RV = 0xC0;
+pub(crate) const CKR_SIGNATURE_LEN_RANGE: CK_RV = 0xC1;
+pub(crate) const CKR_USER_ALREADY_LOGGED_IN: CK_RV = 0x100;
+pub(crate) const CKR_CRYPTOKI_ALREADY_INITIALIZED: CK_RV = 0x191;
+
+const CKF_RW_SESSION: CK_ULONG = 0x2;
+const CKF_SERIAL_SESSION: CK_ULONG = 0x4;
+const CKF_OS_LOCKING_OK: CK_ULONG = 0x2;
+const CKF_DERIVE: CK_ULONG = 0x80000;
+const CKU_USER: CK_ULONG = 1;
+
+pub(crate) const CKO_PUBLIC_KEY: CK_ULONG = 2;
+pub(crate) const CKO_PRIVATE_KEY: CK_ULONG = 3;
+pub(crate) const CKO_SECRET_KEY: CK_ULONG = 4;
+
+pub(crate) const CKK_EC: CK_ULONG = 0x3;
+pub(crate) const CKK_GENERIC_SECRET: CK_ULONG = 0x10;
+pub(crate) const CKK_AES: CK_ULONG = 0x1F;
+
+pub(crate) const CKA_CLASS: CK_ULONG = 0x0;
+pub(crate) const CKA_TOKEN: CK_ULONG = 0x1;
+pub(crate) const CKA_PRIVATE: CK_ULONG = 0x2;
+pub(crate) const CKA_LABEL: CK_ULONG = 0x3;
+pub(crate) const CKA_VALUE: CK_ULONG = 0x11;
+pub(crate) const CKA_KEY_TYPE: CK_ULONG = 0x100;
+pub(crate) const CKA_ID: CK_ULONG = 0x102;
+pub(crate) const CKA_SENSITIVE: CK_ULONG = 0x103;
+pub(crate) const CKA_ENCRYPT: CK_ULONG = 0x104;
+pub(crate) const CKA_DECRYPT: CK_ULONG = 0x105;
+pub(crate) const CKA_SIGN: CK_ULONG = 0x108;
+pub(crate) const CKA_VERIFY: CK_ULONG = 0x10A;
+pub(crate) const CKA_DERIVE: CK_ULONG = 0x10C;
+pub(crate) const CKA_VALUE_LEN: CK_ULONG = 0x161;
+pub(crate) const CKA_EXTRACTABLE: CK_ULONG = 0x162;
+pub(crate) const CKA_EC_PARAMS: CK_ULONG = 0x180;
+pub(crate) const CKA_EC_POINT: CK_ULONG = 0x181;
+
+pub(crate) const CKM_SHA256: CK_ULONG = 0x250;
+pub(crate) const CKM_GENERIC_SECRET_KEY_GEN: CK_ULONG = 0x350;
+pub(crate) const CKM_EXTRACT_KEY_FROM_KEY: CK_ULONG = 0x365;
+pub(crate) const CKM_EC_KEY_PAIR_GEN: CK_ULONG = 0x1040;
+pub(crate) const CKM_ECDSA: CK_ULONG = 0x1041;
+pub(crate) const CKM_ECDH1_DERIVE: CK_ULONG = 0x1050;
+pub(crate) const CKM_AES_KEY_GEN: CK_ULONG = 0x1080;
+pub(crate) const CKM_AES_GCM: CK_ULONG = 0x1087;
+pub(crate) const CKM_HKDF_DERIVE: CK_ULONG = 0x402A;
+
+pub(crate) const CKD_NULL: CK_ULONG = 0x1;
+
+const CKF_HKDF_SALT_KEY: CK_ULONG = 0x4;
+
+/// DER encoding of the prime256v1 named curve OID
+pub(crate) const P256_EC_PARAMS: [u8; 10] =
+    [0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CK_VERSION {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+struct CK_ATTRIBUTE {
+    kind: CK_ULONG,
+    value: *mut c_void,
+    value_len: CK_ULONG,
+}
+
+#[repr(C)]
+pub(crate) struct CK_MECHANISM {
+    mechanism: CK_ULONG,
+    parameter: *mut c_void,
+    parameter_len: CK_ULONG,
+}
+
+impl CK_MECHANISM {
+    /// A mechanism without parameters
+    pub(crate) fn new(mechanism: CK_ULONG) -> Self {
+        Self {
+            mechanism,
+            parameter: ptr::null_mut(),
+            parameter_len: 0,
+        }
+    }
+
+    /// A mechanism with parameters, which have to outlive it
+    pub(crate) fn with_parameter<T>(mechanism: CK_ULONG, parameter: &mut T) -> Self {
+        Self {
+            mechanism,
+            parameter: parameter as *mut T as *mut c_void,
+            parameter_len: std::mem::size_of::<T>() as CK_ULONG,
+        }
+    }
+}
+
+#[repr(C)]
+pub(crate) struct CK_ECDH1_DERIVE_PARAMS {
+    kdf: CK_ULONG,
+    shared_data_len: CK_ULONG,
+    shared_data: *mut u8,
+    public_data_len: CK_ULONG,
+    public_data: *mut u8,
+}
+
+impl CK_ECDH1_DERIVE_PARAMS {
+    /// Raw ECDH with the peer's uncompressed point, which has to outlive the parameters
+    pub(crate) fn new(public_data: &[u8]) -> Self {
+        Self {
+            kdf: CKD_NULL,
+            shared_data_len: 0,
+            shared_data: ptr::null_mut(),
+            public_data_len: public_data.len() as CK_ULONG,
+            public_data: public_data.as_ptr() as *mut u8,
+        }
+    }
+}
+
+#[repr(C)]
+pub(crate) struct CK_HKDF_PARAMS {
+    extract: CK_BBOOL,
+    expand: CK_BBOOL,
+    prf_hash_mechanism: CK_ULONG,
+    salt_type: CK_ULONG,
+    salt: *mut u8,
+    salt_len: CK_ULONG,
+    salt_key: CK_OBJECT_HANDLE,
+    info: *mut u8,
+    info_len: CK_ULONG,
+}
+
+impl CK_HKDF_PARAMS {
+    /// HKDF-SHA256 extract and expand with the salt in a key, `info` has to outlive the
+    /// parameters
+    pub(crate) fn new(salt_key: CK_OBJECT_HANDLE, info: &[u8]) -> Self {
+        Self {
+            extract: 1,
+            expand: 1,
+            prf_hash_mechanism: CKM_SHA256,
+            salt_type: CKF_HKDF_SALT_KEY,
+            salt: ptr::null_mut(),
+            salt_len: 0,
+            salt_key,
+            info: info.as_ptr() as *mut u8,
+            info_len: info.len() as CK_ULONG,
+        }
+    }
+}
+
+#[repr(C)]
+pub(crate) struct CK_GCM_PARAMS {
+    iv: *mut u8,
+    iv_len: CK_ULONG,
+    iv_bits: CK_ULONG,
+    aad: *mut u8,
+    aad_len: CK_ULONG,
+    tag_bits: CK_ULONG,
+}
+
+impl CK_GCM_PARAMS {
+    /// GCM with a 128 bit tag, `iv` and `aad` have to outlive the parameters
+    pub(crate) fn new(iv: &[u8], aad: &[u8]) -> Self {
+        Self {
+            iv: iv.as_ptr() as *mut u8,
+            iv_len: iv.len() as CK_ULONG,
+            iv_bits: (iv.len() * 8) as CK_ULONG,
+            aad: aad.as_ptr() as *mut u8,
+            aad_len: aad.len() as CK_ULONG,
+            tag_bits: 128,
+        }
+    }
+}
+
+#[repr(C)]
+struct CK_C_INITIALIZE_ARGS {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: CK_ULONG,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct CK_TOKEN_INFO {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    flags: CK_ULONG,
+    counters: [CK_ULONG; 10],
+    hardware_version: CK_VERSION,
+    firmware_version: CK_VERSION,
+    utc_time: [u8; 16],
+}
+
+#[repr(C)]
+struct CK_MECHANISM_INFO {
+    min_key_size: CK_ULONG,
+    max_key_size: CK_ULONG,
+    flags: CK_ULONG,
+}
+
+type Unused = Option<unsafe extern "C" fn()>;
+
+/// CK_FUNCTION_LIST, functions the vault doesn't call are left untyped
+#[repr(C)]
+struct CK_FUNCTION_LIST {
+    version: CK_VERSION,
+    initialize: Option<unsafe extern "C" fn(*mut c_void) -> CK_RV>,
+    finalize: Unused,
+    get_info: Unused,
+    get_function_list: Unused,
+    get_slot_list: Option<unsafe extern "C" fn(u8, *mut CK_SLOT_ID, *mut CK_ULONG) -> CK_RV>,
+    get_slot_info: Unused,
+    get_token_info: Option<unsafe extern "C" fn(CK_SLOT_ID, *mut CK_TOKEN_INFO) -> CK_RV>,
+    get_mechanism_list: Unused,
+    get_mechanism_info:
+        Option<unsafe extern "C" fn(CK_SLOT_ID, CK_ULONG, *mut CK_MECHANISM_INFO) -> CK_RV>,
+    init_token: Unused,
+    init_pin: Unused,
+    set_pin: Unused,
+    open_session: Option<
+        unsafe extern "C" fn(
+            CK_SLOT_ID,
+            CK_ULONG,
+            *mut c_void,
+            *mut c_void,
+            *mut CK_SESSION_HANDLE,
+        ) -> CK_RV,
+    >,
+    close_session: Option<unsafe extern "C" fn(CK_SESSION_HANDLE) -> CK_RV>,
+    close_all_sessions: Unused,
+    get_session_info: Unused,
+    get_operation_state: Unused,
+    set_operation_state: Unused,
+    login: Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_ULONG, *mut u8, CK_ULONG) -> CK_RV>,
+    logout: Unused,
+    create_object: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            *mut CK_ATTRIBUTE,
+            CK_ULONG,
+            *mut CK_OBJECT_HANDLE,
+        ) -> CK_RV,
+    >,
+    copy_object: Unused,
+    destroy_object: Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_OBJECT_HANDLE) -> CK_RV>,
+    get_object_size: Unused,
+    get_attribute_value: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_OBJECT_HANDLE,
+            *mut CK_ATTRIBUTE,
+            CK_ULONG,
+        ) -> CK_RV,
+    >,
+    set_attribute_value: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_OBJECT_HANDLE,
+            *mut CK_ATTRIBUTE,
+            CK_ULONG,
+        ) -> CK_RV,
+    >,
+    find_objects_init:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, *mut CK_ATTRIBUTE, CK_ULONG) -> CK_RV>,
+    find_objects: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            *mut CK_OBJECT_HANDLE,
+            CK_ULONG,
+            *mut CK_ULONG,
+        ) -> CK_RV,
+    >,
+    find_objects_final: Option<unsafe extern "C" fn(CK_SESSION_HANDLE) -> CK_RV>,
+    encrypt_init: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut CK_MECHANISM, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    encrypt: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut u8, CK_ULONG, *mut u8, *mut CK_ULONG) -> CK_RV,
+    >,
+    encrypt_update: Unused,
+    encrypt_final: Unused,
+    decrypt_init: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut CK_MECHANISM, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    decrypt: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut u8, CK_ULONG, *mut u8, *mut CK_ULONG) -> CK_RV,
+    >,
+    decrypt_update: Unused,
+    decrypt_final: Unused,
+    digest_init: Unused,
+    digest: Unused,
+    digest_update: Unused,
+    digest_key: Unused,
+    digest_final: Unused,
+    sign_init: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut CK_MECHANISM, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    sign: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut u8, CK_ULONG, *mut u8, *mut CK_ULONG) -> CK_RV,
+    >,
+    sign_update: Unused,
+    sign_final: Unused,
+    sign_recover_init: Unused,
+    sign_recover: Unused,
+    verify_init: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut CK_MECHANISM, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    verify: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, *mut u8, CK_ULONG, *mut u8, CK_ULONG) -> CK_RV,
+    >,
+    verify_update: Unused,
+    verify_final: Unused,
+    verify_recover_init: Unused,
+    verify_recover: Unused,
+    digest_encrypt_update: Unused,
+    decrypt_digest_update: Unused,
+    sign_encrypt_update: Unused,
+    decrypt_verify_update: Unused,
+    generate_key: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            *mut CK_MECHANISM,
+            *mut CK_ATTRIBUTE,
+            CK_ULONG,
+            *mut CK_OBJECT_HANDLE,
+        ) -> CK_RV,
+    >,
+    generate_key_pair: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            *mut CK_MECHANISM,
+            *mut CK_ATTRIBUTE,
+            CK_ULONG,
+            *mut CK_ATTRIBUTE,
+            CK_ULONG,
+            *mut CK_OBJECT_HANDLE,
+            *mut CK_OBJECT_HANDLE,
+        ) -> CK_RV,
+    >,
+    wrap_key: Unused,
+    unwrap_key: Unused,
+    derive_key: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            *mut CK_MECHANISM,
+            CK_OBJECT_HANDLE,
+            *mut CK_ATTRIBUTE,
+            CK_ULONG,
+            *mut CK_OBJECT_HANDLE,
+        ) -> CK_RV,
+    >,
+    seed_random: Unused,
+    generate_random: Option<unsafe extern "C" fn(CK_SESSION_HANDLE, *mut u8, CK_ULONG) -> CK_RV>,
+    get_function_status: Unused,
+    cancel_function: Unused,
+    wait_for_slot_event: Unused,
+}
+
+/// Attributes for creating, generating or finding objects. Values are zeroized on drop
+/// since they can hold key material.
+#[derive(Default)]
+pub(crate) struct Template {
+    attributes: Vec<CK_ATTRIBUTE>,
+    values: Vec<Vec<u8>>,
+}
+
+impl Template {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn bytes(mut self, kind: CK_ULONG, mut value: Vec<u8>) -> Self {
+        // moving the vector into `values` doesn't move its buffer
+        self.attributes.push(CK_ATTRIBUTE {
+            kind,
+            value: value.as_mut_ptr() as *mut c_void,
+            value_len: value.len() as CK_ULONG,
+        });
+        self.values.push(value);
+        self
+    }
+
+    pub(crate) fn ulong(self, kind: CK_ULONG, value: CK_ULONG) -> Self {
+        self.bytes(kind, value.to_ne_bytes().to_vec())
+    }
+
+    pub(crate) fn bool(self, kind: CK_ULONG, value: bool) -> Self {
+        self.bytes(kind, vec![value as u8])
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut CK_ATTRIBUTE {
+        self.attributes.as_mut_ptr()
+    }
+
+    fn len(&self) -> CK_ULONG {
+        self.attributes.len() as CK_ULONG
+    }
+}
+
+impl Drop for Template {
+    fn drop(&mut self) {
+        for value in self.values.iter_mut() {
+            value.zeroize();
+        }
+    }
+}
+
+macro_rules! call {
+    ($module:expr, $function:ident, $($arg:expr),*) => {
+        match (*$module.functions).$function {
+            Some(f) => check(f($($arg),*)),
+            None => Err(CKR_FUNCTION_NOT_SUPPORTED),
+        }
+    };
+}
+
+fn check(rv: CK_RV) -> Result<(), CK_RV> {
+    if rv == CKR_OK {
+        Ok(())
+    } else {
+        Err(rv)
+    }
+}
+
+/// A loaded PKCS#11 module. Every call returns the module's CK_RV on failure.
+pub(crate) struct Module {
+    functions: *const CK_FUNCTION_LIST,
+    // keeps `functions` valid
+    _library: Library,
+}
+
+// Modules are initialized with CKF_OS_LOCKING_OK, so they may be called from any thread
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+impl std::fmt::Debug for Module {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Module")
+    }
+}
+
+impl Module {
+    /// Load and initialize the module at `path`. Modules are never finalized, other
+    /// vaults in the process may share them.
+    pub(crate) fn load(path: &Path) -> Result<Self, ()> {
+        unsafe {
+            let library = Library::new(path).map_err(|_| ())?;
+            let get_function_list: libloading::Symbol<
+                unsafe extern "C" fn(*mut *const CK_FUNCTION_LIST) -> CK_RV,
+            > = library.get(b"C_GetFunctionList\0").map_err(|_| ())?;
+            let mut functions = ptr::null();
+            if get_function_list(&mut functions) != CKR_OK || functions.is_null() {
+                return Err(());
+            }
+
+            let module = Self {
+                functions,
+                _library: library,
+            };
+            let mut args = CK_C_INITIALIZE_ARGS {
+                create_mutex: ptr::null_mut(),
+                destroy_mutex: ptr::null_mut(),
+                lock_mutex: ptr::null_mut(),
+                unlock_mutex: ptr::null_mut(),
+                flags: CKF_OS_LOCKING_OK,
+                reserved: ptr::null_mut(),
+            };
+            match call!(
+                module,
+                initialize,
+                &mut args as *mut CK_C_INITIALIZE_ARGS as *mut c_void
+            ) {
+                Ok(()) | Err(CKR_CRYPTOKI_ALREADY_INITIALIZED) => Ok(module),
+                Err(_) => Err(()),
+            }
+        }
+    }
+
+    /// Find the slot of the token with the given label
+    pub(crate) fn find_token(&self, label: &str) -> Result<Option<CK_SLOT_ID>, CK_RV> {
+        unsafe {
+            let mut count: CK_ULONG = 0;
+            call!(self, get_slot_list, 1, ptr::null_mut(), &mut count)?;
+            let mut slots = vec![0 as CK_SLOT_ID; count as usize];
+            call!(self, get_slot_list, 1, slots.as_mut_ptr(), &mut count)?;
+            slots.truncate(count as usize);
+
+            for slot in slots {
+                let mut info: CK_TOKEN_INFO = std::mem::zeroed();
+                call!(self, get_token_info, slot, &mut info)?;
+                // labels are padded with blanks
+                let token_label = String::from_utf8_lossy(&info.label);
+                if token_label.trim_end() == label {
+                    return Ok(Some(slot));
+                }
+            }
+            Ok(None)
+        }
+    }
+
+    /// Open a read/write session on `slot` and log the user in
+    /// Whether the token in `slot` can derive keys with `mechanism`
+    pub(crate) fn can_derive_with(&self, slot: CK_SLOT_ID, mechanism: CK_ULONG) -> bool {
+        let mut info = CK_MECHANISM_INFO {
+            min_key_size: 0,
+            max_key_size: 0,
+            flags: 0,
+        };
+        let result = unsafe { call!(self, get_mechanism_info, slot, mechanism, &mut info) };
+        result.is_ok() && info.flags & CKF_DERIVE != 0
+    }
+
+    pub(crate) fn open_session(
+        &self,
+        slot: CK_SLOT_ID,
+        pin: &str,
+    ) -> Result<CK_SESSION_HANDLE, CK_RV> {
+        unsafe {
+            let mut session = 0;
+            call!(
+                self,
+                open_session,
+                slot,
+                CKF_SERIAL_SESSION | CKF_RW_SESSION,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut session
+            )?;
+            // the login state is shared by every session of the process
+            match call!(
+                self,
+                login,
+                session,
+                CKU_USER,
+                pin.as_ptr() as *mut u8,
+                pin.len() as CK_ULONG
+            ) {
+                Ok(()) | Err(CKR_USER_ALREADY_LOGGED_IN) => Ok(session),
+                Err(rv) => {
+                    let _ = call!(self, close_session, session);
+                    Err(rv)
+                }
+            }
+        }
+    }
+
+    pub(crate) fn close_session(&self, session: CK_SESSION_HANDLE) -> Result<(), CK_RV> {
+        unsafe { call!(self, close_session, session) }
+    }
+
+    pub(crate) fn create_object(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut template: Template,
+    ) -> Result<CK_OBJECT_HANDLE, CK_RV> {
+        let mut object = 0;
+        unsafe {
+            call!(
+                self,
+                create_object,
+                session,
+                template.as_mut_ptr(),
+                template.len(),
+                &mut object
+            )?;
+        }
+        Ok(object)
+    }
+
+    pub(crate) fn destroy_object(
+        &self,
+        session: CK_SESSION_HANDLE,
+        object: CK_OBJECT_HANDLE,
+    ) -> Result<(), CK_RV> {
+        unsafe { call!(self, destroy_object, session, object) }
+    }
+
+    /// Read the value of a single attribute
+    pub(crate) fn get_attribute(
+        &self,
+        session: CK_SESSION_HANDLE,
+        object: CK_OBJECT_HANDLE,
+        kind: CK_ULONG,
+    ) -> Result<Vec<u8>, CK_RV> {
+        let mut attribute = CK_ATTRIBUTE {
+            kind,
+            value: ptr::null_mut(),
+            value_len: 0,
+        };
+        unsafe {
+            call!(
+                self,
+                get_attribute_value,
+                session,
+                object,
+                &mut attribute,
+                1
+            )?;
+            let mut value = vec![0u8; attribute.value_len as usize];
+            attribute.value = value.as_mut_ptr() as *mut c_void;
+            call!(
+                self,
+                get_attribute_value,
+                session,
+                object,
+                &mut attribute,
+                1
+            )?;
+            value.truncate(attribute.value_len as usize);
+            Ok(value)
+        }
+    }
+
+    pub(crate) fn get_bool_attribute(
+        &self,
+        session: CK_SESSION_HANDLE,
+        object: CK_OBJECT_HANDLE,
+        kind: CK_ULONG,
+    ) -> Result<bool, CK_RV> {
+        let value = self.get_attribute(session, object, kind)?;
+        Ok(value.iter().any(|b| *b != 0))
+    }
+
+    pub(crate) fn set_attributes(
+        &self,
+        session: CK_SESSION_HANDLE,
+        object: CK_OBJECT_HANDLE,
+        mut template: Template,
+    ) -> Result<(), CK_RV> {
+        unsafe {
+            call!(
+                self,
+                set_attribute_value,
+                session,
+                object,
+                template.as_mut_ptr(),
+                template.len()
+            )
+        }
+    }
+
+    /// Find every object matching the template
+    pub(crate) fn find_objects(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut template: Template,
+    ) -> Result<Vec<CK_OBJECT_HANDLE>, CK_RV> {
+        let mut objects = Vec::new();
+        unsafe {
+            call!(
+                self,
+                find_objects_init,
+                session,
+                template.as_mut_ptr(),
+                template.len()
+            )?;
+            let mut found = Ok(());
+            loop {
+                let mut batch = [0 as CK_OBJECT_HANDLE; 16];
+                let mut count: CK_ULONG = 0;
+                if let Err(rv) = call!(
+                    self,
+                    find_objects,
+                    session,
+                    batch.as_mut_ptr(),
+                    batch.len() as CK_ULONG,
+                    &mut count
+                ) {
+                    found = Err(rv);
+                    break;
+                }
+                if count == 0 {
+                    break;
+                }
+                objects.extend_from_slice(&batch[..count as usize]);
+            }
+            call!(self, find_objects_final, session)?;
+            found?;
+        }
+        Ok(objects)
+    }
+
+    pub(crate) fn generate_key(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut mechanism: CK_MECHANISM,
+        mut template: Template,
+    ) -> Result<CK_OBJECT_HANDLE, CK_RV> {
+        let mut object = 0;
+        unsafe {
+            call!(
+                self,
+                generate_key,
+                session,
+                &mut mechanism,
+                template.as_mut_ptr(),
+                template.len(),
+                &mut object
+            )?;
+        }
+        Ok(object)
+    }
+
+    /// Generate a key pair, returns the public then the private key
+    pub(crate) fn generate_key_pair(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut mechanism: CK_MECHANISM,
+        mut public_template: Template,
+        mut private_template: Template,
+    ) -> Result<(CK_OBJECT_HANDLE, CK_OBJECT_HANDLE), CK_RV> {
+        let mut public = 0;
+        let mut private = 0;
+        unsafe {
+            call!(
+                self,
+                generate_key_pair,
+                session,
+                &mut mechanism,
+                public_template.as_mut_ptr(),
+                public_template.len(),
+                private_template.as_mut_ptr(),
+                private_template.len(),
+                &mut public,
+                &mut private
+            )?;
+        }
+        Ok((public, private))
+    }
+
+    pub(crate) fn derive_key(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut mechanism: CK_MECHANISM,
+        base_key: CK_OBJECT_HANDLE,
+        mut template: Template,
+    ) -> Result<CK_OBJECT_HANDLE, CK_RV> {
+        let mut object = 0;
+        unsafe {
+            call!(
+                self,
+                derive_key,
+                session,
+                &mut mechanism,
+                base_key,
+                template.as_mut_ptr(),
+                template.len(),
+                &mut object
+            )?;
+        }
+        Ok(object)
+    }
+
+    /// Encrypt `data` in a single part, the output is at most `output_len` bytes
+    pub(crate) fn encrypt(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut mechanism: CK_MECHANISM,
+        key: CK_OBJECT_HANDLE,
+        data: &[u8],
+        output_len: usize,
+    ) -> Result<Vec<u8>, CK_RV> {
+        let mut output = vec![0u8; output_len];
+        let mut len = output_len as CK_ULONG;
+        unsafe {
+            call!(self, encrypt_init, session, &mut mechanism, key)?;
+            call!(
+                self,
+                encrypt,
+                session,
+                data.as_ptr() as *mut u8,
+                data.len() as CK_ULONG,
+                output.as_mut_ptr(),
+                &mut len
+            )?;
+        }
+        output.truncate(len as usize);
+        Ok(output)
+    }
+
+    /// Decrypt `data` in a single part, the output is at most `output_len` bytes
+    pub(crate) fn decrypt(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut mechanism: CK_MECHANISM,
+        key: CK_OBJECT_HANDLE,
+        data: &[u8],
+        output_len: usize,
+    ) -> Result<Vec<u8>, CK_RV> {
+        let mut output = vec![0u8; output_len];
+        let mut len = output_len as CK_ULONG;
+        unsafe {
+            call!(self, decrypt_init, session, &mut mechanism, key)?;
+            call!(
+                self,
+                decrypt,
+                session,
+                data.as_ptr() as *mut u8,
+                data.len() as CK_ULONG,
+                output.as_mut_ptr(),
+                &mut len
+            )?;
+        }
+        output.truncate(len as usize);
+        Ok(output)
+    }
+
+    pub(crate) fn sign(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut mechanism: CK_MECHANISM,
+        key: CK_OBJECT_HANDLE,
+        data: &[u8],
+        signature: &mut [u8],
+    ) -> Result<usize, CK_RV> {
+        let mut len = signature.len() as CK_ULONG;
+        unsafe {
+            call!(self, sign_init, session, &mut mechanism, key)?;
+            call!(
+                self,
+                sign,
+                session,
+                data.as_ptr() as *mut u8,
+                data.len() as CK_ULONG,
+                signature.as_mut_ptr(),
+                &mut len
+            )?;
+        }
+        Ok(len as usize)
+    }
+
+    pub(crate) fn verify(
+        &self,
+        session: CK_SESSION_HANDLE,
+        mut mechanism: CK_MECHANISM,
+        key: CK_OBJECT_HANDLE,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CK_RV> {
+        unsafe {
+            call!(self, verify_init, session, &mut mechanism, key)?;
+            call!(
+                self,
+                verify,
+                session,
+                data.as_ptr() as *mut u8,
+                data.len() as CK_ULONG,
+                signature.as_ptr() as *mut u8,
+                signature.len() as CK_ULONG
+            )
+        }
+    }
+
+    pub(crate) fn generate_random(
+        &self,
+        session: CK_SESSION_HANDLE,
+        buffer: &mut [u8],
+    ) -> Result<(), CK_RV> {
+        unsafe {
+            call!(
+                self,
+                generate_random,
+                session,
+                buffer.as_mut_ptr(),
+                buffer.len() as CK_ULONG
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn function_list_layout() {
+        // the version is padded to a pointer, followed by 68 function pointers
+        assert_eq!(size_of::<CK_FUNCTION_LIST>(), size_of::<usize>() * 69);
+        // 96 bytes of text, 11 counters and flags, two versions, the time and trailing padding
+        assert_eq!(
+            size_of::<CK_TOKEN_INFO>(),
+            96 + size_of::<CK_ULONG>() * 11 + 4 + 16 + 4
+        );
+        // the two flags are padded to a CK_ULONG, followed by seven CK_ULONGs and pointers
+        assert_eq!(size_of::<CK_HKDF_PARAMS>(), size_of::<[CK_ULONG; 8]>());
+    }
+}
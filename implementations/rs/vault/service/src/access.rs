@@ -0,0 +1,52 @@
+use ockam_vault::types::PublicKey;
+
+/// Decides what the peers of a vault service may do beyond using the secrets they
+/// created themselves. Peers are identified by the static public key of the secure
+/// channel their requests arrive through.
+pub trait AccessPolicy: Send {
+    /// Whether `peer` may use the persistent secret labeled `label`
+    fn check_lookup(&self, peer: &PublicKey, label: &str) -> bool;
+
+    /// Whether `peer` may export the secrets it holds handles to
+    fn check_export(&self, peer: &PublicKey) -> bool;
+}
+
+/// Grants peers access to labeled persistent secrets and export one by one, everything
+/// else is refused. The default list refuses every lookup and export.
+#[derive(Clone, Debug, Default)]
+pub struct AccessList {
+    lookups: Vec<(PublicKey, String)>,
+    exporters: Vec<PublicKey>,
+}
+
+impl AccessList {
+    /// Create a list that refuses everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let `peer` use the persistent secret labeled `label`
+    pub fn allow_lookup(&mut self, peer: PublicKey, label: &str) {
+        let entry = (peer, label.to_string());
+        if !self.lookups.contains(&entry) {
+            self.lookups.push(entry);
+        }
+    }
+
+    /// Let `peer` export the secrets it holds handles to
+    pub fn allow_export(&mut self, peer: PublicKey) {
+        if !self.exporters.contains(&peer) {
+            self.exporters.push(peer);
+        }
+    }
+}
+
+impl AccessPolicy for AccessList {
+    fn check_lookup(&self, peer: &PublicKey, label: &str) -> bool {
+        self.lookups.iter().any(|(p, l)| p == peer && l == label)
+    }
+
+    fn check_export(&self, peer: &PublicKey) -> bool {
+        self.exporters.contains(peer)
+    }
+}
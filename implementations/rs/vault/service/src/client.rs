@@ -0,0 +1,278 @@
+use crate::error::Error;
+use crate::protocol::{decode, encode, Operation, Reply, VAULT_ERROR_DOMAINS};
+use ockam::message::{Route, RouterAddress};
+use ockam::service::ServiceClient;
+use ockam::system::commands::OckamCommand;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::{PublicKey, SecretAttributes, SecretKey};
+use ockam_vault::{AsymmetricVault, HashVault, Secret, SecretVault, SignerVault};
+use std::convert::TryFrom;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use zeroize::Zeroize;
+
+/// A secret held by the vault service, only its handle is known to the client. The
+/// service releases the handle once it is dropped, which destroys the secret unless it
+/// was looked up by label.
+#[derive(Debug, Zeroize)]
+pub struct RemoteSecret {
+    handle: u128,
+    #[zeroize(skip)]
+    client: Weak<ServiceClient>,
+    #[zeroize(skip)]
+    destroyed: bool,
+}
+
+impl RemoteSecret {
+    pub(crate) fn boxed(handle: u128, client: Weak<ServiceClient>) -> Box<dyn Secret> {
+        Box::new(Self {
+            handle,
+            client,
+            destroyed: false,
+        })
+    }
+
+    pub fn downcast_secret(context: &dyn Secret) -> OckamResult<&Self> {
+        context
+            .downcast_ref::<RemoteSecret>()
+            .map_err(|_| Error::SecretFromAnotherVault.into())
+    }
+
+    /// Handle of the secret in the service
+    pub fn handle(&self) -> u128 {
+        self.handle
+    }
+}
+
+impl Drop for RemoteSecret {
+    fn drop(&mut self) {
+        if self.destroyed {
+            return;
+        }
+        // without the client there is no channel left to send the request through
+        if let Some(client) = self.client.upgrade() {
+            if let Ok(request) = encode(&Operation::SecretRelease {
+                handle: self.handle,
+            }) {
+                let _ = client.send(request);
+            }
+        }
+    }
+}
+
+impl Secret for RemoteSecret {}
+
+/// A vault whose operations run in a `VaultWorker` on another node, reached through
+/// a secure channel. Every call sends a request and blocks until the response
+/// arrives, so the client must not be used on the thread that polls the router of
+/// its node.
+///
+/// The client registers its address with the router, so the node can run other
+/// workers next to it. Messages to the client that aren't responses are dropped.
+#[derive(Debug)]
+pub struct VaultClient {
+    client: Arc<ServiceClient>,
+}
+
+impl VaultClient {
+    /// Create a client at `addr` that opens a secure channel along `onward_route` and
+    /// reaches the service at `service_addr` through it. Calls wait for the channel,
+    /// the service refuses requests that don't come through one.
+    pub fn new(
+        addr: RouterAddress,
+        onward_route: Route,
+        service_addr: RouterAddress,
+        router_tx: Sender<OckamCommand>,
+        channel_tx: Sender<OckamCommand>,
+    ) -> Self {
+        let mut client =
+            ServiceClient::through_channel(addr, onward_route, service_addr, router_tx, channel_tx);
+        client.set_error_domains(VAULT_ERROR_DOMAINS);
+        Self {
+            client: Arc::new(client),
+        }
+    }
+
+    /// Set how long a call waits for the channel and the response
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.client.set_timeout(timeout);
+    }
+
+    pub fn sender(&self) -> Sender<OckamCommand> {
+        self.client.sender()
+    }
+
+    /// Get a handle to the persistent secret labeled `label` in the service's vault.
+    /// Several clients can use the same secret this way, the access policy of the
+    /// service decides who may. Dropping the handle leaves the secret in the vault and
+    /// it can't be destroyed through the handle.
+    pub fn get_persistent_secret_by_label(&self, label: &str) -> OckamResult<Box<dyn Secret>> {
+        let reply = self.call(Operation::SecretGetByLabel {
+            label: label.to_string(),
+        })?;
+        Ok(self.secret(reply.handle()?))
+    }
+
+    /// Send `operation` to the service and wait for its reply
+    fn call(&self, operation: Operation) -> OckamResult<Reply> {
+        decode(&self.client.call(encode(&operation)?)?)
+    }
+
+    /// A secret the service created with `handle`
+    fn secret(&self, handle: u128) -> Box<dyn Secret> {
+        RemoteSecret::boxed(handle, Arc::downgrade(&self.client))
+    }
+
+    fn handle(context: &dyn Secret) -> OckamResult<u128> {
+        Ok(RemoteSecret::downcast_secret(context)?.handle)
+    }
+}
+
+impl Reply {
+    fn handle(self) -> OckamResult<u128> {
+        match self {
+            Reply::Handle(handle) => Ok(handle),
+            _ => Err(Error::InvalidResponse.into()),
+        }
+    }
+
+    fn bytes(self) -> OckamResult<Vec<u8>> {
+        match self {
+            Reply::Bytes(bytes) => Ok(bytes),
+            _ => Err(Error::InvalidResponse.into()),
+        }
+    }
+}
+
+impl Zeroize for VaultClient {
+    fn zeroize(&mut self) {}
+}
+
+impl SecretVault for VaultClient {
+    fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+        let reply = self.call(Operation::SecretGenerate {
+            attributes: attributes.to_bytes(),
+        })?;
+        Ok(self.secret(reply.handle()?))
+    }
+
+    fn secret_import(
+        &self,
+        secret: &[u8],
+        attributes: SecretAttributes,
+    ) -> OckamResult<Box<dyn Secret>> {
+        let reply = self.call(Operation::SecretImport {
+            secret: secret.to_vec(),
+            attributes: attributes.to_bytes(),
+        })?;
+        Ok(self.secret(reply.handle()?))
+    }
+
+    fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+        let reply = self.call(Operation::SecretExport {
+            handle: Self::handle(context)?,
+        })?;
+        Ok(SecretKey::new(reply.bytes()?))
+    }
+
+    fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes> {
+        let reply = self.call(Operation::SecretAttributesGet {
+            handle: Self::handle(context)?,
+        })?;
+        match reply {
+            Reply::Attributes(attributes) => SecretAttributes::try_from(attributes),
+            _ => Err(Error::InvalidResponse.into()),
+        }
+    }
+
+    fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
+        let reply = self.call(Operation::SecretPublicKeyGet {
+            handle: Self::handle(context)?,
+        })?;
+        Ok(PublicKey::new(reply.bytes()?))
+    }
+
+    fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+        let mut context = context
+            .downcast::<RemoteSecret>()
+            .map_err(|_| Error::SecretFromAnotherVault)?;
+        match self.call(Operation::SecretDestroy {
+            handle: context.handle,
+        })? {
+            Reply::Done => {
+                context.destroyed = true;
+                Ok(())
+            }
+            _ => Err(Error::InvalidResponse.into()),
+        }
+    }
+}
+
+impl SignerVault for VaultClient {
+    fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]> {
+        let reply = self.call(Operation::Sign {
+            handle: Self::handle(secret_key)?,
+            data: data.to_vec(),
+        })?;
+        let signature = reply.bytes()?;
+        if signature.len() != 64 {
+            return Err(Error::InvalidResponse.into());
+        }
+        let mut output = [0u8; 64];
+        output.copy_from_slice(&signature);
+        Ok(output)
+    }
+}
+
+impl AsymmetricVault for VaultClient {
+    fn ec_diffie_hellman(
+        &self,
+        context: &dyn Secret,
+        peer_public_key: &[u8],
+    ) -> OckamResult<Box<dyn Secret>> {
+        let reply = self.call(Operation::EcDiffieHellman {
+            handle: Self::handle(context)?,
+            peer_public_key: peer_public_key.to_vec(),
+        })?;
+        Ok(self.secret(reply.handle()?))
+    }
+}
+
+impl HashVault for VaultClient {
+    fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]> {
+        let reply = self.call(Operation::Sha256 {
+            data: data.to_vec(),
+        })?;
+        let digest = reply.bytes()?;
+        if digest.len() != 32 {
+            return Err(Error::InvalidResponse.into());
+        }
+        let mut output = [0u8; 32];
+        output.copy_from_slice(&digest);
+        Ok(output)
+    }
+
+    fn hkdf_sha256(
+        &self,
+        salt: &dyn Secret,
+        info: &[u8],
+        ikm: Option<&dyn Secret>,
+        output_attributes: Vec<SecretAttributes>,
+    ) -> OckamResult<Vec<Box<dyn Secret>>> {
+        let ikm = match ikm {
+            Some(ikm) => Some(Self::handle(ikm)?),
+            None => None,
+        };
+        let reply = self.call(Operation::HkdfSha256 {
+            salt: Self::handle(salt)?,
+            info: info.to_vec(),
+            ikm,
+            output_attributes: output_attributes.iter().map(|a| a.to_bytes()).collect(),
+        })?;
+        match reply {
+            Reply::Handles(handles) => Ok(handles.into_iter().map(|h| self.secret(h)).collect()),
+            _ => Err(Error::InvalidResponse.into()),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use ockam_common::error::OckamError;
+
+/// Represents the failures that can occur in
+/// an Ockam Vault Service
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    None,
+    /// A request or response could not be encoded or decoded
+    BareError,
+    /// The secret was not created by this client
+    SecretFromAnotherVault,
+    /// The service doesn't hold a secret with this handle, it was destroyed or the
+    /// service restarted
+    UnknownHandle,
+    /// The response doesn't match the request
+    InvalidResponse,
+    /// The access policy of the service doesn't allow the request
+    AccessDenied,
+    /// The request arrived through a channel the service's channel manager doesn't
+    /// know, it was closed
+    UnknownChannel,
+    /// The vault of the service can't look up persistent secrets
+    LookupUnsupported,
+}
+
+impl Error {
+    /// Error domain
+    pub const ERROR_DOMAIN: &'static str = "VAULT_SERVICE_ERROR_DOMAIN";
+}
+
+impl From<Error> for OckamError {
+    fn from(err: Error) -> Self {
+        OckamError::new(err as u32, Error::ERROR_DOMAIN)
+    }
+}
@@ -0,0 +1,580 @@
+//! A vault service lets several nodes use the keys of a vault on another node. The
+//! `VaultWorker` runs on the node with the vault and answers requests from
+//! `VaultClient`s, which implement the vault traits on the application nodes.
+//! Requests and responses are BARE encoded message bodies sent through a secure
+//! channel, secrets never leave the service unless they are exported and are
+//! referred to by handles otherwise.
+//!
+//! Handles belong to the channel they were handed out through. Clients share a key
+//! by looking up a labeled persistent secret of the service's vault, after a
+//! reconnect a client looks it up again. An `AccessPolicy` decides which clients may
+//! look up which secrets and who may export.
+
+use ockam_vault::{AsymmetricVault, HashVault, SecretVault, SignerVault};
+
+pub use access::{AccessList, AccessPolicy};
+pub use client::{RemoteSecret, VaultClient};
+pub use ockam::service::DEFAULT_TIMEOUT;
+pub use ockam_vault;
+pub use worker::VaultWorker;
+
+mod access;
+mod client;
+pub mod error;
+mod protocol;
+mod worker;
+
+/// Vault with the functionality a vault service offers
+pub trait ServiceVault:
+    SecretVault + SignerVault + AsymmetricVault + HashVault + Send + Sync
+{
+}
+
+impl<D> ServiceVault for D where
+    D: SecretVault + SignerVault + AsymmetricVault + HashVault + Send + Sync
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ockam::message::{AddressType, Message, MessageType, Route, RouterAddress};
+    use ockam::secure_channel::{AcceptAll, ChannelManager, ChannelPeers};
+    use ockam::service::error::Error as ServiceError;
+    use ockam::service::ServiceClient;
+    use ockam::system::commands::{OckamCommand, RouterCommand, WorkerCommand};
+    use ockam_common::error::OckamError;
+    use ockam_kex::CipherSuite;
+    use ockam_kex_xx::{XXInitiator, XXNewKeyExchanger, XXResponder};
+    use ockam_router::local::{LocalNetwork, LocalNode};
+    use ockam_vault::types::{
+        PublicKey, SecretAttributes, SecretPersistence, SecretType, SecretUsage,
+        CURVE25519_SECRET_LENGTH,
+    };
+    use ockam_vault::{PersistentVault, Secret, VerifierVault};
+    use ockam_vault_file::FilesystemVault;
+    use ockam_vault_software::DefaultVault;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::{Arc, Mutex, Weak};
+    use std::time::{Duration, Instant};
+
+    const SERVICE: &str = "5e5e5e5e";
+    const CLIENT: &str = "c1c1c1c1";
+
+    type XXChannelManager = ChannelManager<XXInitiator, XXResponder, XXNewKeyExchanger>;
+
+    /// A node with the channels of its channel manager and the identity key its
+    /// channels authenticate with
+    struct TestNode {
+        node: LocalNode,
+        channel_tx: Sender<OckamCommand>,
+        channel_rx: Receiver<OckamCommand>,
+        vault: Arc<DefaultVault>,
+        identity: Arc<Box<dyn Secret>>,
+        public_key: PublicKey,
+        idle_timeout: Option<Duration>,
+    }
+
+    impl TestNode {
+        fn new(address: &str) -> Self {
+            let (channel_tx, channel_rx) = channel();
+            let vault = Arc::new(DefaultVault::default());
+            let identity = vault
+                .secret_generate(SecretAttributes {
+                    stype: SecretType::Curve25519,
+                    persistence: SecretPersistence::Persistent,
+                    length: CURVE25519_SECRET_LENGTH,
+                    exportable: false,
+                    usage: SecretUsage::ECDH,
+                })
+                .unwrap();
+            let public_key = vault.secret_public_key_get(identity.as_ref()).unwrap();
+            Self {
+                node: LocalNode::new(address),
+                channel_tx,
+                channel_rx,
+                vault,
+                identity: Arc::new(identity),
+                public_key,
+                idle_timeout: None,
+            }
+        }
+    }
+
+    /// The vault worker of a started network, it is created on the polling thread
+    type SharedWorker = Arc<Mutex<Option<VaultWorker>>>;
+
+    /// Poll the nodes, their channel managers if `secure_channel` is set and the vault
+    /// worker until the network is dropped. The worker is made from the channel peers
+    /// of the first node.
+    fn start<W>(
+        nodes: Vec<TestNode>,
+        make_worker: W,
+        secure_channel: bool,
+    ) -> (LocalNetwork, SharedWorker)
+    where
+        W: FnOnce(ChannelPeers) -> VaultWorker + Send + 'static,
+    {
+        let mut channels = vec![];
+        let mut local_nodes = vec![];
+        for node in nodes {
+            channels.push((
+                node.channel_rx,
+                node.channel_tx,
+                node.node.router_tx.clone(),
+                node.vault,
+                node.identity,
+                node.idle_timeout,
+            ));
+            local_nodes.push(node.node);
+        }
+        let worker: SharedWorker = Arc::new(Mutex::new(None));
+        let polled_worker = worker.clone();
+        let network = LocalNetwork::start(local_nodes, move || {
+            let mut managers = vec![];
+            let mut peers = ChannelPeers::default();
+            if secure_channel {
+                for (channel_rx, channel_tx, router_tx, vault, identity, idle_timeout) in channels {
+                    let new_key_exchanger = XXNewKeyExchanger::new(
+                        CipherSuite::Curve25519AesGcmSha256,
+                        vault.clone(),
+                        vault.clone(),
+                    );
+                    let mut manager = XXChannelManager::new(
+                        channel_rx,
+                        channel_tx,
+                        router_tx,
+                        vault,
+                        new_key_exchanger,
+                        Some(identity.clone()),
+                        Some(identity),
+                        Box::new(AcceptAll),
+                    )
+                    .unwrap();
+                    manager.set_idle_timeout(idle_timeout);
+                    if managers.is_empty() {
+                        peers = manager.channel_peers();
+                    }
+                    managers.push(manager);
+                }
+            }
+            *polled_worker.lock().unwrap() = Some(make_worker(peers));
+            move || {
+                for manager in managers.iter_mut() {
+                    manager.poll().unwrap();
+                }
+                polled_worker.lock().unwrap().as_mut().unwrap().poll();
+            }
+        });
+        (network, worker)
+    }
+
+    /// A worker on `node` that lets the nodes in `exporters` export their secrets
+    fn worker(
+        node: &TestNode,
+        exporters: &[&TestNode],
+    ) -> impl FnOnce(ChannelPeers) -> VaultWorker {
+        let router_tx = node.node.router_tx.clone();
+        let mut access = AccessList::new();
+        for exporter in exporters {
+            access.allow_export(exporter.public_key.clone());
+        }
+        move |peers| {
+            let mut worker = VaultWorker::new(
+                RouterAddress::worker_router_address_from_str(SERVICE).unwrap(),
+                router_tx,
+                Arc::new(DefaultVault::default()),
+                peers,
+            );
+            worker.set_access_policy(Box::new(access));
+            worker
+        }
+    }
+
+    /// A client at `addr` on `application` with a channel to the service
+    fn client(addr: &str, application: &TestNode, service: &TestNode) -> VaultClient {
+        VaultClient::new(
+            RouterAddress::worker_router_address_from_str(addr).unwrap(),
+            Route {
+                addresses: vec![service.node.address.clone()],
+            },
+            RouterAddress::worker_router_address_from_str(SERVICE).unwrap(),
+            application.node.router_tx.clone(),
+            application.channel_tx.clone(),
+        )
+    }
+
+    fn assert_error(error: OckamError, expected: error::Error) {
+        assert_eq!(error.code(), expected as u32);
+        assert_eq!(error.domain(), error::Error::ERROR_DOMAIN);
+    }
+
+    /// Use a remote key the way a key exchange would and compare with a local vault
+    fn exercise(client: &VaultClient) {
+        let local = DefaultVault::default();
+        let attributes = SecretAttributes {
+            stype: SecretType::Curve25519,
+            persistence: SecretPersistence::Ephemeral,
+            length: CURVE25519_SECRET_LENGTH,
+            exportable: false,
+            usage: SecretUsage::SIGN | SecretUsage::ECDH,
+        };
+
+        assert_eq!(
+            client.sha256(b"vault service").unwrap(),
+            local.sha256(b"vault service").unwrap()
+        );
+
+        let remote_key = client.secret_generate(attributes).unwrap();
+        assert_eq!(
+            client.secret_attributes_get(remote_key.as_ref()).unwrap(),
+            attributes
+        );
+        let remote_public = client.secret_public_key_get(remote_key.as_ref()).unwrap();
+
+        let signature = client.sign(remote_key.as_ref(), b"hello").unwrap();
+        local
+            .verify(
+                &signature,
+                remote_public.as_ref(),
+                SecretType::Curve25519,
+                b"hello",
+            )
+            .unwrap();
+
+        // the key stays in the service
+        let error = client.secret_export(remote_key.as_ref()).unwrap_err();
+        assert_eq!(
+            error.code(),
+            ockam_vault_software::error::Error::SecretNotExportable as u32
+        );
+        assert_eq!(
+            error.domain(),
+            ockam_vault_software::error::Error::ERROR_DOMAIN
+        );
+
+        // both sides of a key agreement derive the same key
+        let local_key = local.secret_generate(attributes).unwrap();
+        let local_public = local.secret_public_key_get(local_key.as_ref()).unwrap();
+        let remote_shared = client
+            .ec_diffie_hellman(remote_key.as_ref(), local_public.as_ref())
+            .unwrap();
+        let local_shared = local
+            .ec_diffie_hellman(local_key.as_ref(), remote_public.as_ref())
+            .unwrap();
+        let output = vec![SecretAttributes {
+            stype: SecretType::Buffer,
+            persistence: SecretPersistence::Ephemeral,
+            length: 32,
+            exportable: true,
+            usage: SecretUsage::AEAD,
+        }];
+        let remote_derived = client
+            .hkdf_sha256(remote_shared.as_ref(), b"info", None, output.clone())
+            .unwrap();
+        let local_derived = local
+            .hkdf_sha256(local_shared.as_ref(), b"info", None, output)
+            .unwrap();
+        assert_eq!(
+            client.secret_export(remote_derived[0].as_ref()).unwrap(),
+            local.secret_export(local_derived[0].as_ref()).unwrap()
+        );
+
+        // a destroyed key is gone from the service
+        let handle = RemoteSecret::downcast_secret(remote_key.as_ref())
+            .unwrap()
+            .handle();
+        client.secret_destroy(remote_key).unwrap();
+        assert_unknown(client, handle);
+
+        // and so is a dropped one
+        let handle = RemoteSecret::downcast_secret(remote_shared.as_ref())
+            .unwrap()
+            .handle();
+        std::mem::drop(remote_shared);
+        assert_unknown(client, handle);
+    }
+
+    /// Check that the service doesn't know `handle`, through a secret that doesn't
+    /// destroy it when it is dropped
+    fn assert_unknown(client: &VaultClient, handle: u128) {
+        let secret = RemoteSecret::boxed(handle, Weak::new());
+        let error = client.secret_attributes_get(secret.as_ref()).unwrap_err();
+        assert_error(error, error::Error::UnknownHandle);
+    }
+
+    #[test]
+    fn plain_routes_are_refused() {
+        let service = TestNode::new("127.0.0.1:4100");
+        let application = TestNode::new("127.0.0.1:4200");
+        let worker = worker(&service, &[]);
+        let client = ServiceClient::new(
+            RouterAddress::worker_router_address_from_str(CLIENT).unwrap(),
+            service.node.route_to(SERVICE),
+            application.node.router_tx.clone(),
+        );
+        let (_network, _) = start(vec![service, application], worker, false);
+
+        let request = protocol::encode(&protocol::Operation::Sha256 { data: vec![] }).unwrap();
+        let error = client.call(request).unwrap_err();
+        assert_eq!(error.code(), ServiceError::ChannelRequired as u32);
+        assert_eq!(error.domain(), ServiceError::ERROR_DOMAIN);
+    }
+
+    #[test]
+    fn handles_are_scoped_to_their_channel() {
+        let service = TestNode::new("127.0.0.1:4100");
+        let application = TestNode::new("127.0.0.1:4200");
+        let worker = worker(&service, &[&application]);
+        let alice = client(CLIENT, &application, &service);
+        let bob = client("c2c2c2c2", &application, &service);
+        let (_network, _) = start(vec![service, application], worker, true);
+
+        let key = alice
+            .secret_generate(SecretAttributes {
+                stype: SecretType::Buffer,
+                persistence: SecretPersistence::Ephemeral,
+                length: 32,
+                exportable: true,
+                usage: SecretUsage::AEAD,
+            })
+            .unwrap();
+        let handle = RemoteSecret::downcast_secret(key.as_ref())
+            .unwrap()
+            .handle();
+        let error = bob
+            .secret_export(RemoteSecret::boxed(handle, Weak::new()).as_ref())
+            .unwrap_err();
+        assert_error(error, error::Error::UnknownHandle);
+        assert!(alice.secret_export(key.as_ref()).is_ok());
+    }
+
+    #[test]
+    fn application_worker_next_to_client() {
+        let service = TestNode::new("127.0.0.1:4100");
+        let application = TestNode::new("127.0.0.1:4200");
+        let (app_tx, app_rx) = channel();
+        application
+            .node
+            .router_tx
+            .send(OckamCommand::Router(RouterCommand::Register(
+                AddressType::Worker,
+                app_tx,
+            )))
+            .unwrap();
+        let worker = worker(&service, &[&application]);
+        let client = client(CLIENT, &application, &service);
+        let to_app = application.node.route_to("a0a0a0a0");
+        let service_tx = service.node.router_tx.clone();
+        let (_network, _) = start(vec![service, application], worker, true);
+
+        exercise(&client);
+        service_tx
+            .send(OckamCommand::Router(RouterCommand::SendMessage(Message {
+                onward_route: to_app,
+                return_route: Route { addresses: vec![] },
+                message_type: MessageType::Payload,
+                message_body: b"hello".to_vec(),
+            })))
+            .unwrap();
+        match app_rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            OckamCommand::Worker(WorkerCommand::ReceiveMessage(m)) => {
+                assert_eq!(m.message_body, b"hello".to_vec())
+            }
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+        exercise(&client);
+    }
+
+    #[test]
+    fn through_secure_channel() {
+        let service = TestNode::new("127.0.0.1:4100");
+        let application = TestNode::new("127.0.0.1:4200");
+        let worker = worker(&service, &[&application]);
+        let client = client(CLIENT, &application, &service);
+        let (_network, _) = start(vec![service, application], worker, true);
+
+        exercise(&client);
+    }
+
+    #[test]
+    fn persistent_secrets_are_shared_by_label() {
+        let dir = std::env::temp_dir().join(format!("ockam_vault_service_{}", std::process::id()));
+        let vault = Arc::new(FilesystemVault::new(dir.clone()).unwrap());
+        let identity = vault
+            .secret_generate(SecretAttributes {
+                stype: SecretType::Curve25519,
+                persistence: SecretPersistence::Persistent,
+                length: CURVE25519_SECRET_LENGTH,
+                exportable: true,
+                usage: SecretUsage::SIGN,
+            })
+            .unwrap();
+        vault
+            .set_secret_label(identity.as_ref(), Some("identity"))
+            .unwrap();
+        let identity_public = vault.secret_public_key_get(identity.as_ref()).unwrap();
+
+        let service = TestNode::new("127.0.0.1:4100");
+        let first = TestNode::new("127.0.0.1:4200");
+        let second = TestNode::new("127.0.0.1:4300");
+        let stranger = TestNode::new("127.0.0.1:4400");
+        let mut access = AccessList::new();
+        access.allow_lookup(first.public_key.clone(), "identity");
+        access.allow_lookup(second.public_key.clone(), "identity");
+        let router_tx = service.node.router_tx.clone();
+        let service_vault = vault.clone();
+        let worker = move |peers| {
+            let mut worker = VaultWorker::with_persistent_vault(
+                RouterAddress::worker_router_address_from_str(SERVICE).unwrap(),
+                router_tx,
+                service_vault,
+                peers,
+            );
+            worker.set_access_policy(Box::new(access));
+            worker
+        };
+        let first_client = client(CLIENT, &first, &service);
+        let second_client = client(CLIENT, &second, &service);
+        let stranger_client = client(CLIENT, &stranger, &service);
+        let (network, _) = start(vec![service, first, second, stranger], worker, true);
+
+        let first_key = first_client
+            .get_persistent_secret_by_label("identity")
+            .unwrap();
+        let second_key = second_client
+            .get_persistent_secret_by_label("identity")
+            .unwrap();
+        for (client, key) in [(&first_client, &first_key), (&second_client, &second_key)] {
+            assert_eq!(
+                client.secret_public_key_get(key.as_ref()).unwrap(),
+                identity_public
+            );
+            let signature = client.sign(key.as_ref(), b"hello").unwrap();
+            DefaultVault::default()
+                .verify(
+                    &signature,
+                    identity_public.as_ref(),
+                    SecretType::Curve25519,
+                    b"hello",
+                )
+                .unwrap();
+        }
+
+        let error = stranger_client
+            .get_persistent_secret_by_label("identity")
+            .unwrap_err();
+        assert_error(error, error::Error::AccessDenied);
+        // the key is exportable, but not over the network
+        let error = first_client.secret_export(first_key.as_ref()).unwrap_err();
+        assert_error(error, error::Error::AccessDenied);
+        let error = first_client.secret_destroy(first_key).unwrap_err();
+        assert_error(error, error::Error::AccessDenied);
+
+        // the failed destroy dropped the handle, which leaves the key in the vault
+        assert!(second_client.sign(second_key.as_ref(), b"hello").is_ok());
+        std::mem::drop(network);
+        assert!(vault.get_persistent_secret_by_label("identity").is_ok());
+        std::mem::drop(vault);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn released_persistent_secrets_are_destroyed() {
+        let dir = std::env::temp_dir().join(format!(
+            "ockam_vault_service_release_{}",
+            std::process::id()
+        ));
+        let vault = Arc::new(FilesystemVault::new(dir.clone()).unwrap());
+        let service = TestNode::new("127.0.0.1:4100");
+        let application = TestNode::new("127.0.0.1:4200");
+        let router_tx = service.node.router_tx.clone();
+        let service_vault = vault.clone();
+        let worker = move |peers| {
+            VaultWorker::with_persistent_vault(
+                RouterAddress::worker_router_address_from_str(SERVICE).unwrap(),
+                router_tx,
+                service_vault,
+                peers,
+            )
+        };
+        let client = client(CLIENT, &application, &service);
+        let (network, _) = start(vec![service, application], worker, true);
+
+        let files = || std::fs::read_dir(&dir).unwrap().count();
+        let vault_files = files();
+        let key = client
+            .secret_generate(SecretAttributes {
+                stype: SecretType::Buffer,
+                persistence: SecretPersistence::Persistent,
+                length: 32,
+                exportable: false,
+                usage: SecretUsage::AEAD,
+            })
+            .unwrap();
+        assert!(files() > vault_files);
+
+        // nothing can find the key again once its handle is gone
+        std::mem::drop(key);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while files() != vault_files {
+            assert!(Instant::now() < deadline, "key was not destroyed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::mem::drop(network);
+        std::mem::drop(vault);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn handles_are_released_when_their_channel_closes() {
+        let mut service = TestNode::new("127.0.0.1:4100");
+        service.idle_timeout = Some(Duration::from_millis(500));
+        let application = TestNode::new("127.0.0.1:4200");
+        let worker = worker(&service, &[]);
+        let client = client(CLIENT, &application, &service);
+        let (_network, worker) = start(vec![service, application], worker, true);
+
+        let _key = client
+            .secret_generate(SecretAttributes {
+                stype: SecretType::Buffer,
+                persistence: SecretPersistence::Ephemeral,
+                length: 32,
+                exportable: false,
+                usage: SecretUsage::AEAD,
+            })
+            .unwrap();
+        let handle_count = || worker.lock().unwrap().as_ref().unwrap().handle_count();
+        assert_eq!(handle_count(), 1);
+
+        // the idle timeout closes the channel, the client keeps its handle
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle_count() != 0 {
+            assert!(Instant::now() < deadline, "handle was not released");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn no_service() {
+        let service = TestNode::new("127.0.0.1:4100");
+        let application = TestNode::new("127.0.0.1:4200");
+        let mut client = client(CLIENT, &application, &service);
+        client.set_timeout(Duration::from_millis(100));
+        let (router_tx, _router_rx) = channel();
+        let worker = move |peers| {
+            VaultWorker::new(
+                RouterAddress::worker_router_address_from_str(SERVICE).unwrap(),
+                router_tx,
+                Arc::new(DefaultVault::default()),
+                peers,
+            )
+        };
+        // the service node has no worker or channel manager, requests go nowhere
+        let (_network, _) = start(vec![service, application], worker, false);
+
+        let error = client.sha256(b"").unwrap_err();
+        assert_eq!(error.code(), ServiceError::Timeout as u32);
+        assert_eq!(error.domain(), ServiceError::ERROR_DOMAIN);
+    }
+}
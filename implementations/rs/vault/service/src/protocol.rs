@@ -0,0 +1,81 @@
+use crate::error::Error;
+use ockam_common::error::OckamResult;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Error domains of the vaults a service may run, errors from them keep their domain
+/// on the client
+pub(crate) const VAULT_ERROR_DOMAINS: &[&str] = &[
+    Error::ERROR_DOMAIN,
+    ockam_vault::error::Error::ERROR_DOMAIN,
+    "VAULT_SOFTWARE_ERROR_DOMAIN",
+    "VAULT_FILESYSTEM_ERROR_DOMAIN",
+    "VAULT_PKCS11_ERROR_DOMAIN",
+    "VAULT_AUDIT_ERROR_DOMAIN",
+];
+
+/// A vault operation, secrets are referenced by the random handle the service gave
+/// them and attributes are in the `SecretAttributes::to_bytes` format
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum Operation {
+    SecretGenerate {
+        attributes: [u8; 8],
+    },
+    SecretImport {
+        secret: Vec<u8>,
+        attributes: [u8; 8],
+    },
+    SecretExport {
+        handle: u128,
+    },
+    SecretAttributesGet {
+        handle: u128,
+    },
+    SecretPublicKeyGet {
+        handle: u128,
+    },
+    SecretDestroy {
+        handle: u128,
+    },
+    SecretRelease {
+        handle: u128,
+    },
+    SecretGetByLabel {
+        label: String,
+    },
+    Sign {
+        handle: u128,
+        data: Vec<u8>,
+    },
+    EcDiffieHellman {
+        handle: u128,
+        peer_public_key: Vec<u8>,
+    },
+    Sha256 {
+        data: Vec<u8>,
+    },
+    HkdfSha256 {
+        salt: u128,
+        info: Vec<u8>,
+        ikm: Option<u128>,
+        output_attributes: Vec<[u8; 8]>,
+    },
+}
+
+/// Result of an operation, errors are returned by the service
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum Reply {
+    Done,
+    Handle(u128),
+    Handles(Vec<u128>),
+    Bytes(Vec<u8>),
+    Attributes([u8; 8]),
+}
+
+pub(crate) fn encode<T: Serialize>(body: &T) -> OckamResult<Vec<u8>> {
+    serde_bare::to_vec(body).map_err(|_| Error::BareError.into())
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(body: &[u8]) -> OckamResult<T> {
+    serde_bare::from_slice(body).map_err(|_| Error::BareError.into())
+}
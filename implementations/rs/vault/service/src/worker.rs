@@ -0,0 +1,301 @@
+use crate::access::{AccessList, AccessPolicy};
+use crate::error::Error;
+use crate::protocol::{decode, encode, Operation, Reply};
+use crate::ServiceVault;
+use ockam::message::{Route, RouterAddress};
+use ockam::secure_channel::ChannelPeers;
+use ockam::service::ServiceWorker;
+use ockam::system::commands::OckamCommand;
+use ockam_common::error::OckamResult;
+use ockam_vault::types::{PublicKey, SecretAttributes};
+use ockam_vault::{PersistentVault, Secret};
+use rand::rngs::OsRng;
+use rand::Rng;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use zeroize::Zeroize;
+
+/// A worker that runs vault operations requested by `VaultClient`s and replies on the
+/// return route of the request. Secrets stay in the vault, clients refer to them by a
+/// random handle that only the secure channel the secret was created through can use.
+/// The handles of a channel are released once the channel closes, the secrets it
+/// created are destroyed then.
+///
+/// Requests that didn't arrive through a secure channel are refused, use a trust
+/// policy for the channels that only admits the application nodes. Clients may look
+/// up labeled persistent secrets and export secrets only as far as the access policy
+/// allows, by default neither is allowed.
+pub struct VaultWorker {
+    worker: ServiceWorker,
+    secrets: Secrets,
+}
+
+impl std::fmt::Debug for VaultWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("VaultWorker")
+            .field("addr", self.worker.address())
+            .field("secrets", &self.secrets.secrets.len())
+            .finish()
+    }
+}
+
+/// A secret the worker handed out
+struct Entry {
+    /// The channel the handle was given out through
+    channel: RouterAddress,
+    secret: Box<dyn Secret>,
+    /// Looked up persistent secrets are shared with other channels and are never
+    /// destroyed through a handle
+    shared: bool,
+}
+
+/// The vault and the secrets the worker handed out
+struct Secrets {
+    vault: Arc<dyn ServiceVault>,
+    persistent_vault: Option<Arc<dyn PersistentVault + Send + Sync>>,
+    peers: ChannelPeers,
+    access_policy: Box<dyn AccessPolicy>,
+    secrets: HashMap<u128, Entry>,
+}
+
+impl VaultWorker {
+    /// Create the worker at `addr` and register it with the router. `peers` must come
+    /// from the channel manager of this node, it tells the worker who is at the other
+    /// end of a channel and when the channel is closed.
+    pub fn new(
+        addr: RouterAddress,
+        router_tx: Sender<OckamCommand>,
+        vault: Arc<dyn ServiceVault>,
+        peers: ChannelPeers,
+    ) -> Self {
+        Self::create(addr, router_tx, vault, None, peers)
+    }
+
+    /// Create a worker whose clients can also look up the labeled persistent secrets of
+    /// `vault`, as far as the access policy allows
+    pub fn with_persistent_vault<V: ServiceVault + PersistentVault + 'static>(
+        addr: RouterAddress,
+        router_tx: Sender<OckamCommand>,
+        vault: Arc<V>,
+        peers: ChannelPeers,
+    ) -> Self {
+        Self::create(addr, router_tx, vault.clone(), Some(vault), peers)
+    }
+
+    fn create(
+        addr: RouterAddress,
+        router_tx: Sender<OckamCommand>,
+        vault: Arc<dyn ServiceVault>,
+        persistent_vault: Option<Arc<dyn PersistentVault + Send + Sync>>,
+        peers: ChannelPeers,
+    ) -> Self {
+        let mut worker = ServiceWorker::new(addr, router_tx);
+        worker.set_channel_required(true);
+        Self {
+            worker,
+            secrets: Secrets {
+                vault,
+                persistent_vault,
+                peers,
+                access_policy: Box::new(AccessList::new()),
+                secrets: HashMap::new(),
+            },
+        }
+    }
+
+    /// Set what clients may do beyond using the secrets they created
+    pub fn set_access_policy(&mut self, access_policy: Box<dyn AccessPolicy>) {
+        self.secrets.access_policy = access_policy;
+    }
+
+    pub fn sender(&self) -> Sender<OckamCommand> {
+        self.worker.sender()
+    }
+
+    /// Answer the requests that arrived since the last poll and release the handles of
+    /// closed channels
+    pub fn poll(&mut self) -> bool {
+        let secrets = &mut self.secrets;
+        let keep_going = self
+            .worker
+            .poll(|route, body| secrets.receive_request(route, body));
+        self.secrets.release_closed_channels();
+        keep_going
+    }
+
+    /// Number of handles the worker has handed out and not released yet
+    #[cfg(test)]
+    pub(crate) fn handle_count(&self) -> usize {
+        self.secrets.secrets.len()
+    }
+}
+
+impl Secrets {
+    fn receive_request(&mut self, route: &Route, body: Vec<u8>) -> OckamResult<Vec<u8>> {
+        // the service worker only lets requests through a channel in
+        let channel = route.addresses[0].clone();
+        let peer = self
+            .peers
+            .peer(&channel.address)
+            .ok_or(Error::UnknownChannel)?;
+        let operation: Operation = decode(&body)?;
+        encode(&self.run(&channel, &peer, operation)?)
+    }
+
+    /// The secret behind `handle`, secrets of other channels are unknown
+    fn secret(&self, channel: &RouterAddress, handle: u128) -> OckamResult<&dyn Secret> {
+        match self.secrets.get(&handle) {
+            Some(entry) if entry.channel == *channel => Ok(entry.secret.as_ref()),
+            _ => Err(Error::UnknownHandle.into()),
+        }
+    }
+
+    fn insert(&mut self, channel: &RouterAddress, secret: Box<dyn Secret>, shared: bool) -> u128 {
+        let mut handle: u128 = OsRng.gen();
+        while self.secrets.contains_key(&handle) {
+            handle = OsRng.gen();
+        }
+        self.secrets.insert(
+            handle,
+            Entry {
+                channel: channel.clone(),
+                secret,
+                shared,
+            },
+        );
+        handle
+    }
+
+    /// Forget a handle. Secrets the client created can't be reached through any other
+    /// handle, not even persistent ones since clients can't label them, and are
+    /// destroyed. Looked up secrets stay in the vault.
+    fn release(&self, entry: Entry) -> OckamResult<()> {
+        if entry.shared {
+            return Ok(());
+        }
+        self.vault.secret_destroy(entry.secret)
+    }
+
+    fn release_closed_channels(&mut self) {
+        let peers = &self.peers;
+        let closed: Vec<u128> = self
+            .secrets
+            .iter()
+            .filter(|(_, entry)| peers.peer(&entry.channel.address).is_none())
+            .map(|(handle, _)| *handle)
+            .collect();
+        for handle in closed {
+            let entry = self.secrets.remove(&handle).unwrap();
+            // nobody is left to report a failure to, the entry is gone either way
+            let _ = self.release(entry);
+        }
+    }
+
+    fn run(
+        &mut self,
+        channel: &RouterAddress,
+        peer: &PublicKey,
+        operation: Operation,
+    ) -> OckamResult<Reply> {
+        let vault = self.vault.clone();
+        Ok(match operation {
+            Operation::SecretGenerate { attributes } => {
+                let secret = vault.secret_generate(SecretAttributes::try_from(attributes)?)?;
+                Reply::Handle(self.insert(channel, secret, false))
+            }
+            Operation::SecretImport {
+                mut secret,
+                attributes,
+            } => {
+                let imported =
+                    vault.secret_import(&secret, SecretAttributes::try_from(attributes)?);
+                secret.zeroize();
+                Reply::Handle(self.insert(channel, imported?, false))
+            }
+            Operation::SecretExport { handle } => {
+                let secret = self.secret(channel, handle)?;
+                if !self.access_policy.check_export(peer) {
+                    return Err(Error::AccessDenied.into());
+                }
+                let key = vault.secret_export(secret)?;
+                Reply::Bytes(key.as_ref().to_vec())
+            }
+            Operation::SecretAttributesGet { handle } => {
+                let attributes = vault.secret_attributes_get(self.secret(channel, handle)?)?;
+                Reply::Attributes(attributes.to_bytes())
+            }
+            Operation::SecretPublicKeyGet { handle } => {
+                let public_key = vault.secret_public_key_get(self.secret(channel, handle)?)?;
+                Reply::Bytes(public_key.as_ref().to_vec())
+            }
+            Operation::SecretDestroy { handle } => {
+                self.secret(channel, handle)?;
+                if self.secrets[&handle].shared {
+                    return Err(Error::AccessDenied.into());
+                }
+                let entry = self.secrets.remove(&handle).unwrap();
+                vault.secret_destroy(entry.secret)?;
+                Reply::Done
+            }
+            Operation::SecretRelease { handle } => {
+                self.secret(channel, handle)?;
+                let entry = self.secrets.remove(&handle).unwrap();
+                self.release(entry)?;
+                Reply::Done
+            }
+            Operation::SecretGetByLabel { label } => {
+                let persistent_vault = match &self.persistent_vault {
+                    Some(v) => v.clone(),
+                    None => return Err(Error::LookupUnsupported.into()),
+                };
+                if !self.access_policy.check_lookup(peer, &label) {
+                    return Err(Error::AccessDenied.into());
+                }
+                let secret = persistent_vault.get_persistent_secret_by_label(&label)?;
+                Reply::Handle(self.insert(channel, secret, true))
+            }
+            Operation::Sign { handle, data } => {
+                let signature = vault.sign(self.secret(channel, handle)?, &data)?;
+                Reply::Bytes(signature.to_vec())
+            }
+            Operation::EcDiffieHellman {
+                handle,
+                peer_public_key,
+            } => {
+                let shared =
+                    vault.ec_diffie_hellman(self.secret(channel, handle)?, &peer_public_key)?;
+                Reply::Handle(self.insert(channel, shared, false))
+            }
+            Operation::Sha256 { data } => Reply::Bytes(vault.sha256(&data)?.to_vec()),
+            Operation::HkdfSha256 {
+                salt,
+                info,
+                ikm,
+                output_attributes,
+            } => {
+                let output_attributes = output_attributes
+                    .into_iter()
+                    .map(SecretAttributes::try_from)
+                    .collect::<OckamResult<Vec<_>>>()?;
+                let ikm = match ikm {
+                    Some(handle) => Some(self.secret(channel, handle)?),
+                    None => None,
+                };
+                let outputs = vault.hkdf_sha256(
+                    self.secret(channel, salt)?,
+                    &info,
+                    ikm,
+                    output_attributes,
+                )?;
+                Reply::Handles(
+                    outputs
+                        .into_iter()
+                        .map(|s| self.insert(channel, s, false))
+                        .collect(),
+                )
+            }
+        })
+    }
+}
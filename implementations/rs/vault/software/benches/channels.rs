@@ -0,0 +1,103 @@
+//! Encryption throughput of several channels sharing one vault.
+//!
+//! Every channel runs on its own thread with its own AES-GCM key and encrypts 1 KB
+//! messages. The shared `DefaultVault` is compared with the same vault behind a
+//! `Mutex`, which is how vaults were shared before they synchronized internally.
+//!
+//! Run with `cargo bench -p ockam-vault-software`.
+
+use ockam_vault_software::ockam_vault::types::{
+    SecretAttributes, SecretPersistence, SecretType, SecretUsage, AES128_SECRET_LENGTH,
+};
+use ockam_vault_software::ockam_vault::{Secret, SecretVault, SymmetricVault};
+use ockam_vault_software::DefaultVault;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+const MESSAGE_SIZE: usize = 1024;
+const MESSAGES_PER_CHANNEL: usize = 20_000;
+
+fn channel_key(vault: &DefaultVault, channel: usize) -> Box<dyn Secret> {
+    let attributes = SecretAttributes {
+        stype: SecretType::Aes,
+        persistence: SecretPersistence::Ephemeral,
+        length: AES128_SECRET_LENGTH,
+        exportable: false,
+        usage: SecretUsage::AEAD,
+    };
+    vault
+        .secret_import(&[channel as u8; AES128_SECRET_LENGTH], attributes)
+        .unwrap()
+}
+
+fn encrypt_messages<F>(encrypt: F)
+where
+    F: Fn(&[u8], &[u8]),
+{
+    let message = [0x5au8; MESSAGE_SIZE];
+    for n in 0..MESSAGES_PER_CHANNEL as u64 {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&n.to_be_bytes());
+        encrypt(&message, &nonce);
+    }
+}
+
+/// Messages per second with every channel calling the vault directly
+fn shared(channels: usize) -> f64 {
+    let vault = Arc::new(DefaultVault::default());
+    let start = Instant::now();
+    let threads: Vec<_> = (0..channels)
+        .map(|channel| {
+            let vault = vault.clone();
+            let key = channel_key(&vault, channel);
+            thread::spawn(move || {
+                encrypt_messages(|message, nonce| {
+                    vault
+                        .aead_aes_gcm_encrypt(key.as_ref(), message, nonce, &[])
+                        .unwrap();
+                })
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+    (channels * MESSAGES_PER_CHANNEL) as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Messages per second with the vault locked for every call
+fn locked(channels: usize) -> f64 {
+    let vault = Arc::new(Mutex::new(DefaultVault::default()));
+    let start = Instant::now();
+    let threads: Vec<_> = (0..channels)
+        .map(|channel| {
+            let vault = vault.clone();
+            let key = channel_key(&vault.lock().unwrap(), channel);
+            thread::spawn(move || {
+                encrypt_messages(|message, nonce| {
+                    vault
+                        .lock()
+                        .unwrap()
+                        .aead_aes_gcm_encrypt(key.as_ref(), message, nonce, &[])
+                        .unwrap();
+                })
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+    (channels * MESSAGES_PER_CHANNEL) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>16} {:>16}",
+        "channels", "shared msg/s", "mutex msg/s"
+    );
+    for &channels in &[1, 2, 4, 8] {
+        println!(
+            "{:>8} {:>16.0} {:>16.0}",
+            channels,
+            shared(channels),
+            locked(channels)
+        );
+    }
+}
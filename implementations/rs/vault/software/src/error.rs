@@ -18,6 +18,12 @@ pub enum Error {
     AeadAesGcmDecrypt,
     InvalidSignature,
     HkdfExpandError,
+    InvalidChaChaPolyKey,
+    AeadChaChaPolyEncrypt,
+    AeadChaChaPolyDecrypt,
+    RandomBytesGenerate,
+    SecretNotExportable,
+    OperationNotAllowed,
 }
 
 impl Error {
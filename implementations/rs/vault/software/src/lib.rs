@@ -2,18 +2,19 @@ use crate::error::*;
 use crate::xeddsa::*;
 use aead::{generic_array::GenericArray, Aead, NewAead, Payload};
 use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use chacha20poly1305::ChaCha20Poly1305;
 use ockam_common::error::OckamResult;
 use ockam_vault::{
-    types::*, AsymmetricVault, HashVault, Secret, SecretVault, SignerVault, SymmetricVault,
-    VerifierVault,
-};
-use p256::{
-    elliptic_curve::{sec1::FromEncodedPoint, Group},
-    AffinePoint, ProjectivePoint, Scalar,
+    types::*, AsymmetricVault, HashVault, RandomVault, Secret, SecretVault, SignerVault,
+    SymmetricVault, VerifierVault,
 };
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
 use rand::{prelude::*, rngs::OsRng};
 use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 use zeroize::Zeroize;
 
 pub extern crate ockam_vault;
@@ -32,7 +33,7 @@ extern crate arrayref;
 pub struct DefaultVaultSecret(usize);
 
 impl DefaultVaultSecret {
-    pub fn downcast_secret(context: &Box<dyn Secret>) -> OckamResult<&DefaultVaultSecret> {
+    pub fn downcast_secret(context: &dyn Secret) -> OckamResult<&DefaultVaultSecret> {
         context
             .downcast_ref::<DefaultVaultSecret>()
             .map_err(|_| Error::SecretFromAnotherVault.into())
@@ -46,8 +47,9 @@ impl Zeroize for DefaultVaultSecret {
 impl Secret for DefaultVaultSecret {}
 
 /// A pure rust implementation of a vault.
-/// Is not thread-safe i.e. if multiple threads
-/// add values to the vault there may be collisions
+/// The entries are behind a read-write lock that is only held to look up, add or
+/// remove an entry, cryptographic operations run outside of it, so threads using
+/// different secrets don't wait on each other.
 /// This is mostly for testing purposes anyway
 /// and shouldn't be used for production
 ///
@@ -57,28 +59,57 @@ impl Secret for DefaultVaultSecret {}
 /// ```
 #[derive(Debug)]
 pub struct DefaultVault {
-    entries: BTreeMap<usize, VaultEntry>,
-    next_id: usize,
+    entries: RwLock<BTreeMap<usize, Arc<VaultEntry>>>,
+    next_id: AtomicUsize,
 }
 
 impl Default for DefaultVault {
     fn default() -> Self {
         Self {
-            entries: BTreeMap::new(),
-            next_id: 0,
+            entries: RwLock::new(BTreeMap::new()),
+            next_id: AtomicUsize::new(0),
         }
     }
 }
 
 impl DefaultVault {
-    fn get_entry(&self, context: &Box<dyn Secret>) -> OckamResult<&VaultEntry> {
+    fn get_entry(&self, context: &dyn Secret) -> OckamResult<Arc<VaultEntry>> {
         let id = DefaultVaultSecret::downcast_secret(context)?.0;
 
         self.entries
+            .read()
+            .unwrap()
             .get(&id)
+            .cloned()
             .ok_or_else(|| Error::EntryNotFound.into())
     }
 
+    fn add_entry(&self, key: SecretKey, attributes: SecretAttributes) -> Box<dyn Secret> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.entries.write().unwrap().insert(
+            id,
+            Arc::new(VaultEntry {
+                id,
+                key_attributes: attributes,
+                key,
+            }),
+        );
+        Box::new(DefaultVaultSecret(id))
+    }
+
+    /// Get an entry whose usage policy allows `usage`
+    fn get_entry_for(
+        &self,
+        context: &dyn Secret,
+        usage: SecretUsage,
+    ) -> OckamResult<Arc<VaultEntry>> {
+        let entry = self.get_entry(context)?;
+        if !entry.key_attributes.usage.contains(usage) {
+            return Err(Error::OperationNotAllowed.into());
+        }
+        Ok(entry)
+    }
+
     fn ecdh_internal(vault_entry: &VaultEntry, peer_public_key: &[u8]) -> OckamResult<Vec<u8>> {
         let key = vault_entry.key.as_ref();
         match vault_entry.key_attributes.stype {
@@ -100,32 +131,24 @@ impl DefaultVault {
                 if peer_public_key.len() == P256_PUBLIC_LENGTH
                     && key.len() == P256_SECRET_LENGTH =>
             {
-                let pk_t = p256::elliptic_curve::sec1::EncodedPoint::from_bytes(peer_public_key)
-                    .or_else(|_| Err(Error::InvalidPublicKey.into()))?;
-                let o_p_t: Option<AffinePoint> = AffinePoint::from_encoded_point(&pk_t).into();
-                let o_p_t = o_p_t.ok_or(Error::InvalidPublicKey.into())?;
-                let sk = Scalar::from_bytes_reduced(p256::FieldBytes::from_slice(key));
-                let pk_t = ProjectivePoint::from(o_p_t);
-                let secret = pk_t * sk;
-                let is_identity: bool = secret.is_identity().into();
-                if is_identity {
-                    return Err(Error::Ecdh.into());
-                }
-                let ap = p256::elliptic_curve::sec1::EncodedPoint::from(secret.to_affine());
-                Ok(ap.x().as_slice().to_vec())
+                let pk_t = p256::PublicKey::from_sec1_bytes(peer_public_key)
+                    .map_err(|_| Error::InvalidPublicKey.into())?;
+                let sk = p256::SecretKey::from_slice(key).map_err(|_| Error::Ecdh.into())?;
+                let secret = p256::ecdh::diffie_hellman(sk.to_nonzero_scalar(), pk_t.as_affine());
+                Ok(secret.raw_secret_bytes().to_vec())
             }
             _ => Err(Error::UnknownEcdhKeyType.into()),
         }
     }
 
     fn hkdf_sha256_internal(
-        &mut self,
-        salt: &Box<dyn Secret>,
+        &self,
+        salt: &dyn Secret,
         info: &[u8],
         ikm: &[u8],
         output_attributes: Vec<SecretAttributes>,
     ) -> OckamResult<Vec<Box<dyn Secret>>> {
-        let salt = self.get_entry(salt)?;
+        let salt = self.get_entry_for(salt, SecretUsage::HKDF)?;
 
         // FIXME: Doesn't work for secrets with size more than 32 bytes
         let okm_len = output_attributes.len() * 32;
@@ -147,6 +170,10 @@ impl DefaultVault {
                 if length != AES256_SECRET_LENGTH && length != AES128_SECRET_LENGTH {
                     return Err(Error::InvalidAesKeyLength.into());
                 }
+            } else if attributes.stype == SecretType::ChaCha20Poly1305 {
+                if length != CHACHA20POLY1305_SECRET_LENGTH {
+                    return Err(Error::InvalidChaChaPolyKey.into());
+                }
             } else if attributes.stype != SecretType::Buffer {
                 return Err(Error::InvalidHkdfOutputType.into());
             }
@@ -161,17 +188,15 @@ impl DefaultVault {
     }
 
     pub fn get_ids(&self) -> Vec<usize> {
-        self.entries.keys().map(|i| *i).collect()
+        self.entries.read().unwrap().keys().copied().collect()
     }
 }
 
 impl Zeroize for DefaultVault {
     fn zeroize(&mut self) {
-        for (_, v) in self.entries.iter_mut() {
-            v.zeroize();
-        }
-        self.entries.clear();
-        self.next_id = 0;
+        // entries zeroize their key when they are dropped
+        self.entries.get_mut().unwrap().clear();
+        *self.next_id.get_mut() = 0;
     }
 }
 
@@ -194,6 +219,17 @@ zdrop_impl!(VaultEntry);
 
 macro_rules! encrypt_op_impl {
     ($a:expr,$aad:expr,$nonce:expr,$text:expr,$type:ident,$op:ident) => {{
+        encrypt_op_impl!(
+            $a,
+            $aad,
+            $nonce,
+            $text,
+            $type,
+            $op,
+            Error::AeadAesGcmEncrypt
+        )
+    }};
+    ($a:expr,$aad:expr,$nonce:expr,$text:expr,$type:ident,$op:ident,$err:expr) => {{
         let key = GenericArray::from_slice($a.as_ref());
         let cipher = $type::new(key);
         let nonce = GenericArray::from_slice($nonce.as_ref());
@@ -201,9 +237,7 @@ macro_rules! encrypt_op_impl {
             aad: $aad.as_ref(),
             msg: $text.as_ref(),
         };
-        let output = cipher
-            .$op(nonce, payload)
-            .or_else(|_| Err(Error::AeadAesGcmEncrypt.into()))?;
+        let output = cipher.$op(nonce, payload).or_else(|_| Err($err.into()))?;
         Ok(output)
     }};
 }
@@ -225,8 +259,31 @@ macro_rules! encrypt_impl {
     }};
 }
 
+macro_rules! chacha_impl {
+    ($entry:expr, $aad:expr, $nonce: expr, $text:expr, $op:ident, $err:expr) => {{
+        if $entry.key_attributes.stype != SecretType::ChaCha20Poly1305
+            || $entry.key.as_ref().len() != CHACHA20POLY1305_SECRET_LENGTH
+        {
+            return Err(Error::InvalidChaChaPolyKey.into());
+        }
+        if $nonce.len() != 12 {
+            return Err($err.into());
+        }
+        encrypt_op_impl!(
+            $entry.key.as_ref(),
+            $aad,
+            $nonce,
+            $text,
+            ChaCha20Poly1305,
+            $op,
+            $err
+        )
+    }};
+}
+
 impl SecretVault for DefaultVault {
-    fn secret_generate(&mut self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+    fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+        attributes.validate()?;
         let mut rng = OsRng {};
         let length = attributes.length;
         let key = match attributes.stype {
@@ -235,68 +292,75 @@ impl SecretVault for DefaultVault {
                 SecretKey::new(sk.to_bytes().to_vec())
             }
             SecretType::Aes => {
-                if length != AES256_SECRET_LENGTH && length != AES128_SECRET_LENGTH {
-                    return Err(Error::InvalidAesKeyLength.into());
-                };
                 let mut key = vec![0u8; length];
                 rng.fill_bytes(&mut key);
                 SecretKey::new(key)
             }
             SecretType::P256 => {
-                let key = p256::SecretKey::random(&mut rng);
-                let mut value = [0u8; 32];
-                value.copy_from_slice(&key.secret_scalar().to_bytes());
-                SecretKey::new(value.to_vec())
+                // the rng is too old for p256, sample a scalar in [1, n - 1] like it does
+                let mut value = [0u8; P256_SECRET_LENGTH];
+                loop {
+                    rng.fill_bytes(&mut value);
+                    if p256::SecretKey::from_slice(&value).is_ok() {
+                        break;
+                    }
+                }
+                let key = SecretKey::new(value.to_vec());
+                value.zeroize();
+                key
             }
             SecretType::Buffer => {
                 let mut key = vec![0u8; attributes.length];
                 rng.fill_bytes(key.as_mut_slice());
                 SecretKey::new(key)
             }
+            SecretType::Ed25519 => {
+                let sk = ed25519_dalek::SecretKey::generate(&mut rng);
+                SecretKey::new(sk.to_bytes().to_vec())
+            }
+            SecretType::ChaCha20Poly1305 => {
+                let mut key = vec![0u8; length];
+                rng.fill_bytes(&mut key);
+                SecretKey::new(key)
+            }
         };
-        self.next_id += 1;
-        self.entries.insert(
-            self.next_id,
-            VaultEntry {
-                id: self.next_id,
-                key_attributes: attributes,
-                key,
-            },
-        );
-
-        Ok(Box::new(DefaultVaultSecret(self.next_id)))
+        Ok(self.add_entry(key, attributes))
     }
 
     fn secret_import(
-        &mut self,
+        &self,
         secret: &[u8],
         attributes: SecretAttributes,
     ) -> OckamResult<Box<dyn Secret>> {
-        // FIXME: Should we check secrets here?
-        self.next_id += 1;
-        self.entries.insert(
-            self.next_id,
-            VaultEntry {
-                id: self.next_id,
-                key_attributes: attributes,
-                key: SecretKey::new(secret.to_vec()),
-            },
-        );
-        Ok(Box::new(DefaultVaultSecret(self.next_id)))
+        attributes.validate_secret(secret)?;
+        let key = match attributes.stype {
+            // store the clamped scalar (RFC 7748), it is the key the vault uses and exports
+            SecretType::Curve25519 => {
+                let sk = x25519_dalek::StaticSecret::from(*array_ref!(
+                    secret,
+                    0,
+                    CURVE25519_SECRET_LENGTH
+                ));
+                SecretKey::new(sk.to_bytes().to_vec())
+            }
+            _ => SecretKey::new(secret.to_vec()),
+        };
+        Ok(self.add_entry(key, attributes))
     }
 
-    fn secret_export(&mut self, context: &Box<dyn Secret>) -> OckamResult<SecretKey> {
-        self.get_entry(context).map(|i| i.key.clone())
+    fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+        let entry = self.get_entry(context)?;
+        if !entry.key_attributes.exportable {
+            return Err(Error::SecretNotExportable.into());
+        }
+        Ok(entry.key.clone())
     }
 
-    fn secret_attributes_get(
-        &mut self,
-        context: &Box<dyn Secret>,
-    ) -> OckamResult<SecretAttributes> {
+    fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes> {
         self.get_entry(context).map(|i| i.key_attributes)
     }
 
-    fn secret_public_key_get(&mut self, context: &Box<dyn Secret>) -> OckamResult<PublicKey> {
+    fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
         let entry = self.get_entry(context)?;
 
         if entry.key.as_ref().len() != CURVE25519_SECRET_LENGTH {
@@ -314,25 +378,37 @@ impl SecretVault for DefaultVault {
                 Ok(PublicKey::new(pk.to_bytes().to_vec()))
             }
             SecretType::P256 => {
-                let sk =
-                    Scalar::from_bytes_reduced(p256::FieldBytes::from_slice(entry.key.as_ref()));
-                let pp = ProjectivePoint::generator() * sk;
-                let ap = p256::elliptic_curve::sec1::EncodedPoint::from(pp.to_affine());
+                let sk = p256::SecretKey::from_slice(entry.key.as_ref())
+                    .map_err(|_| Error::InvalidPrivateKeyLen.into())?;
+                let ap = sk.public_key().to_encoded_point(false);
                 Ok(PublicKey::new(ap.as_bytes().to_vec()))
             }
+            SecretType::Ed25519 => {
+                let sk = ed25519_dalek::SecretKey::from_bytes(entry.key.as_ref())
+                    .map_err(|_| Error::InvalidPrivateKeyLen.into())?;
+                let pk = ed25519_dalek::PublicKey::from(&sk);
+                Ok(PublicKey::new(pk.to_bytes().to_vec()))
+            }
             _ => Err(Error::InvalidKeyType.into()),
         }
     }
 
-    fn secret_destroy(&mut self, context: Box<dyn Secret>) -> OckamResult<()> {
-        let id = DefaultVaultSecret::downcast_secret(&context)?.0;
-        if let Some(mut k) = self.entries.remove(&id) {
-            k.key.zeroize();
-        }
+    fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+        let id = DefaultVaultSecret::downcast_secret(context.as_ref())?.0;
+        // the key is zeroized once operations still using the entry are done with it
+        self.entries.write().unwrap().remove(&id);
         Ok(())
     }
 }
 
+impl RandomVault for DefaultVault {
+    fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+        OsRng {}
+            .try_fill_bytes(buffer)
+            .map_err(|_| Error::RandomBytesGenerate.into())
+    }
+}
+
 impl HashVault for DefaultVault {
     fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]> {
         let digest = Sha256::digest(data);
@@ -340,15 +416,15 @@ impl HashVault for DefaultVault {
     }
 
     fn hkdf_sha256(
-        &mut self,
-        salt: &Box<dyn Secret>,
+        &self,
+        salt: &dyn Secret,
         info: &[u8],
-        ikm: Option<&Box<dyn Secret>>,
+        ikm: Option<&dyn Secret>,
         output_attributes: Vec<SecretAttributes>,
     ) -> OckamResult<Vec<Box<dyn Secret>>> {
         let ikm_slice = match ikm {
             Some(ikm) => {
-                let ikm = self.get_entry(ikm)?;
+                let ikm = self.get_entry_for(ikm, SecretUsage::HKDF)?;
                 if ikm.key_attributes.stype == SecretType::Buffer {
                     Ok(ikm.key.as_ref().to_vec())
                 } else {
@@ -364,18 +440,21 @@ impl HashVault for DefaultVault {
 
 impl AsymmetricVault for DefaultVault {
     fn ec_diffie_hellman(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         peer_public_key: &[u8],
     ) -> OckamResult<Box<dyn Secret>> {
-        let entry = self.get_entry(context)?;
+        let entry = self.get_entry_for(context, SecretUsage::ECDH)?;
 
-        let dh = Self::ecdh_internal(entry, peer_public_key)?;
+        let dh = Self::ecdh_internal(&entry, peer_public_key)?;
 
+        // The shared secret only feeds key derivation and never leaves the vault
         let attributes = SecretAttributes {
             stype: SecretType::Buffer,
             persistence: SecretPersistence::Ephemeral,
             length: dh.len(),
+            exportable: false,
+            usage: SecretUsage::HKDF,
         };
         self.secret_import(&dh, attributes)
     }
@@ -383,13 +462,13 @@ impl AsymmetricVault for DefaultVault {
 
 impl SymmetricVault for DefaultVault {
     fn aead_aes_gcm_encrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         plaintext: &[u8],
         nonce: &[u8],
         aad: &[u8],
     ) -> OckamResult<Vec<u8>> {
-        let entry = self.get_entry(context)?;
+        let entry = self.get_entry_for(context, SecretUsage::AEAD)?;
 
         encrypt_impl!(
             entry,
@@ -402,13 +481,13 @@ impl SymmetricVault for DefaultVault {
     }
 
     fn aead_aes_gcm_decrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         cipher_text: &[u8],
         nonce: &[u8],
         aad: &[u8],
     ) -> OckamResult<Vec<u8>> {
-        let entry = self.get_entry(context)?;
+        let entry = self.get_entry_for(context, SecretUsage::AEAD)?;
         encrypt_impl!(
             entry,
             aad,
@@ -418,11 +497,47 @@ impl SymmetricVault for DefaultVault {
             Error::AeadAesGcmDecrypt
         )
     }
+
+    fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let entry = self.get_entry_for(context, SecretUsage::AEAD)?;
+        chacha_impl!(
+            entry,
+            aad,
+            nonce,
+            plaintext,
+            encrypt,
+            Error::AeadChaChaPolyEncrypt
+        )
+    }
+
+    fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        let entry = self.get_entry_for(context, SecretUsage::AEAD)?;
+        chacha_impl!(
+            entry,
+            aad,
+            nonce,
+            cipher_text,
+            decrypt,
+            Error::AeadChaChaPolyDecrypt
+        )
+    }
 }
 
 impl SignerVault for DefaultVault {
-    fn sign(&mut self, secret_key: &Box<dyn Secret>, data: &[u8]) -> OckamResult<[u8; 64]> {
-        let entry = self.get_entry(secret_key)?;
+    fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]> {
+        let entry = self.get_entry_for(secret_key, SecretUsage::SIGN)?;
         let key = entry.key.as_ref();
         match entry.key_attributes.stype {
             SecretType::Curve25519 if key.len() == CURVE25519_SECRET_LENGTH => {
@@ -434,38 +549,67 @@ impl SignerVault for DefaultVault {
                         .sign(data.as_ref(), &nonce);
                 Ok(sig)
             }
-            // SecretKey::P256(k) => {
-            //     let sign_key = SigningKey::new(&k)?;
-            //     let sig = sign_key.sign(data.as_ref());
-            //     Ok(*array_ref![sig.as_ref(), 0, 64])
-            // }
+            SecretType::Ed25519 if key.len() == ED25519_SECRET_LENGTH => {
+                let sk = ed25519_dalek::SecretKey::from_bytes(key)
+                    .map_err(|_| Error::InvalidPrivateKeyLen.into())?;
+                let pk = ed25519_dalek::PublicKey::from(&sk);
+                let sig = ed25519_dalek::ExpandedSecretKey::from(&sk).sign(data, &pk);
+                Ok(sig.to_bytes())
+            }
+            SecretType::P256 if key.len() == P256_SECRET_LENGTH => {
+                // ECDSA with SHA-256 and the per message secret of RFC 6979
+                let sk = p256::ecdsa::SigningKey::from_slice(key)
+                    .map_err(|_| Error::InvalidPrivateKeyLen.into())?;
+                let sig: p256::ecdsa::Signature = sk.sign(data);
+                // the fixed size big endian r || s encoding that PKCS#11 tokens produce
+                let mut signature = [0u8; 64];
+                signature.copy_from_slice(&sig.to_bytes());
+                Ok(signature)
+            }
             _ => Err(Error::InvalidKeyType.into()),
         }
     }
 }
 
 impl VerifierVault for DefaultVault {
-    fn verify(&mut self, signature: &[u8; 64], public_key: &[u8], data: &[u8]) -> OckamResult<()> {
-        // FIXME
-        if public_key.len() == CURVE25519_PUBLIC_LENGTH {
-            if x25519_dalek::PublicKey::from(*array_ref!(public_key, 0, CURVE25519_PUBLIC_LENGTH))
-                .verify(data.as_ref(), &signature)
-            {
-                Ok(())
-            } else {
-                Err(Error::InvalidSignature.into())
+    fn verify(
+        &self,
+        signature: &[u8; 64],
+        public_key: &[u8],
+        public_key_type: SecretType,
+        data: &[u8],
+    ) -> OckamResult<()> {
+        let valid = match public_key_type {
+            SecretType::Curve25519 if public_key.len() == CURVE25519_PUBLIC_LENGTH => {
+                x25519_dalek::PublicKey::from(*array_ref!(public_key, 0, CURVE25519_PUBLIC_LENGTH))
+                    .verify(data.as_ref(), signature)
+            }
+            SecretType::Ed25519 if public_key.len() == ED25519_PUBLIC_LENGTH => {
+                let pk = ed25519_dalek::PublicKey::from_bytes(public_key)
+                    .map_err(|_| Error::InvalidPublicKey.into())?;
+                let sig = ed25519_dalek::Signature::from_bytes(signature)
+                    .map_err(|_| Error::InvalidSignature.into())?;
+                pk.verify_strict(data, &sig).is_ok()
+            }
+            SecretType::P256 if public_key.len() == P256_PUBLIC_LENGTH => {
+                let pk = p256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+                    .map_err(|_| Error::InvalidPublicKey.into())?;
+                // r and s out of [1, n - 1] don't make a signature
+                match p256::ecdsa::Signature::from_slice(signature) {
+                    Ok(sig) => pk.verify(data, &sig).is_ok(),
+                    Err(_) => false,
+                }
             }
+            SecretType::Curve25519 | SecretType::Ed25519 | SecretType::P256 => {
+                return Err(Error::InvalidPublicKey.into())
+            }
+            _ => return Err(Error::InvalidKeyType.into()),
+        };
+        if valid {
+            Ok(())
         } else {
-            Err(Error::InvalidPublicKey.into())
+            Err(Error::InvalidSignature.into())
         }
-        // match public_key {
-        // PublicKey::P256(k) => {
-        //     let key = VerifyKey::new(&k)?;
-        //     let sig = Signature::try_from(&signature[..])?;
-        //     key.verify(data.as_ref(), &sig)?;
-        //     Ok(())
-        // }
-        // }
     }
 }
 
@@ -476,50 +620,54 @@ mod tests {
     #[test]
     fn new_vault() {
         let vault = DefaultVault::default();
-        assert_eq!(vault.next_id, 0);
-        assert_eq!(vault.entries.len(), 0);
+        assert_eq!(vault.next_id.load(Ordering::Relaxed), 0);
+        assert_eq!(vault.entries.read().unwrap().len(), 0);
     }
 
     #[test]
     fn new_public_keys() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
         let mut attributes = SecretAttributes {
             stype: SecretType::P256,
             persistence: SecretPersistence::Ephemeral,
             length: P256_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
 
         let res = vault.secret_generate(attributes);
         assert!(res.is_ok());
         let p256_ctx_1 = res.unwrap();
 
-        let res = vault.secret_public_key_get(&p256_ctx_1);
+        let res = vault.secret_public_key_get(p256_ctx_1.as_ref());
         assert!(res.is_ok());
         let pk_1 = res.unwrap();
         assert_eq!(pk_1.as_ref().len(), P256_PUBLIC_LENGTH);
-        assert_eq!(vault.entries.len(), 1);
-        assert_eq!(vault.next_id, 1);
+        assert_eq!(vault.entries.read().unwrap().len(), 1);
+        assert_eq!(vault.next_id.load(Ordering::Relaxed), 1);
 
         attributes.stype = SecretType::Curve25519;
 
         let res = vault.secret_generate(attributes);
         assert!(res.is_ok());
         let c25519_ctx_1 = res.unwrap();
-        let res = vault.secret_public_key_get(&c25519_ctx_1);
+        let res = vault.secret_public_key_get(c25519_ctx_1.as_ref());
         assert!(res.is_ok());
         let pk_1 = res.unwrap();
         assert_eq!(pk_1.as_ref().len(), CURVE25519_PUBLIC_LENGTH);
-        assert_eq!(vault.entries.len(), 2);
-        assert_eq!(vault.next_id, 2);
+        assert_eq!(vault.entries.read().unwrap().len(), 2);
+        assert_eq!(vault.next_id.load(Ordering::Relaxed), 2);
     }
 
     #[test]
     fn new_secret_keys() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
         let mut attributes = SecretAttributes {
             stype: SecretType::P256,
             persistence: SecretPersistence::Ephemeral,
             length: P256_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         let types = [
             (SecretType::Curve25519, 32),
@@ -527,6 +675,7 @@ mod tests {
             (SecretType::Aes, 32),
             (SecretType::Aes, 16),
             (SecretType::Buffer, 24),
+            (SecretType::ChaCha20Poly1305, 32),
         ];
         for (t, s) in &types {
             attributes.stype = *t;
@@ -534,13 +683,87 @@ mod tests {
             let res = vault.secret_generate(attributes);
             assert!(res.is_ok());
             let sk_ctx = res.unwrap();
-            let sk = vault.secret_export(&sk_ctx).unwrap();
+            let sk = vault.secret_export(sk_ctx.as_ref()).unwrap();
             assert_eq!(sk.as_ref().len(), *s);
             vault.secret_destroy(sk_ctx).unwrap();
-            assert_eq!(vault.entries.len(), 0);
+            assert_eq!(vault.entries.read().unwrap().len(), 0);
         }
     }
 
+    #[test]
+    fn usage_policy() {
+        let vault = DefaultVault::default();
+        let attributes = SecretAttributes {
+            stype: SecretType::Curve25519,
+            persistence: SecretPersistence::Ephemeral,
+            length: CURVE25519_SECRET_LENGTH,
+            exportable: false,
+            usage: SecretUsage::ECDH,
+        };
+        let sk = vault.secret_generate(attributes).unwrap();
+        let pk = vault.secret_public_key_get(sk.as_ref()).unwrap();
+        assert_eq!(
+            vault.secret_attributes_get(sk.as_ref()).unwrap(),
+            attributes
+        );
+
+        let err = vault.secret_export(sk.as_ref()).unwrap_err();
+        assert_eq!(err.domain(), Error::ERROR_DOMAIN);
+        assert_eq!(err.code(), Error::SecretNotExportable as u32);
+        let err = vault.sign(sk.as_ref(), b"data").unwrap_err();
+        assert_eq!(err.code(), Error::OperationNotAllowed as u32);
+
+        // the shared secret can only be used for key derivation
+        let dh = vault.ec_diffie_hellman(sk.as_ref(), pk.as_ref()).unwrap();
+        let dh_attributes = vault.secret_attributes_get(dh.as_ref()).unwrap();
+        assert_eq!(dh_attributes.usage, SecretUsage::HKDF);
+        let err = vault.secret_export(dh.as_ref()).unwrap_err();
+        assert_eq!(err.code(), Error::SecretNotExportable as u32);
+        let aes_attributes = SecretAttributes {
+            stype: SecretType::Aes,
+            persistence: SecretPersistence::Ephemeral,
+            length: AES256_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::AEAD,
+        };
+        let err = vault
+            .aead_aes_gcm_encrypt(dh.as_ref(), b"data", &[0u8; 12], &[])
+            .unwrap_err();
+        assert_eq!(err.code(), Error::OperationNotAllowed as u32);
+        let mut keys = vault
+            .hkdf_sha256(dh.as_ref(), b"", None, vec![aes_attributes])
+            .unwrap();
+        let key = keys.pop().unwrap();
+        assert!(vault
+            .aead_aes_gcm_encrypt(key.as_ref(), b"data", &[0u8; 12], &[])
+            .is_ok());
+
+        // AEAD keys are neither HKDF salt nor input key material
+        let err = vault
+            .hkdf_sha256(key.as_ref(), b"", None, vec![aes_attributes])
+            .unwrap_err();
+        assert_eq!(err.code(), Error::OperationNotAllowed as u32);
+        let err = vault
+            .hkdf_sha256(dh.as_ref(), b"", Some(key.as_ref()), vec![aes_attributes])
+            .unwrap_err();
+        assert_eq!(err.code(), Error::OperationNotAllowed as u32);
+        // masks combine with |, a key needs every bit an operation asks for
+        assert!(SecretUsage::ALL.contains(SecretUsage::SIGN | SecretUsage::HKDF));
+        assert!(!(SecretUsage::ECDH | SecretUsage::AEAD).contains(SecretUsage::SIGN));
+    }
+
+    #[test]
+    fn random_bytes() {
+        let vault = DefaultVault::default();
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        vault.random_bytes_generate(&mut a).unwrap();
+        vault.random_bytes_generate(&mut b).unwrap();
+        assert_ne!(a, [0u8; 32]);
+        assert_ne!(a, b);
+        vault.random_bytes_generate(&mut []).unwrap();
+    }
+
     #[test]
     fn sha256() {
         let vault = DefaultVault::default();
@@ -555,13 +778,15 @@ mod tests {
 
     #[test]
     fn hkdf() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
 
         let salt_value = b"hkdf_test";
         let attributes = SecretAttributes {
             stype: SecretType::Buffer,
             persistence: SecretPersistence::Ephemeral,
             length: salt_value.len(),
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         let salt = vault.secret_import(&salt_value[..], attributes).unwrap();
 
@@ -570,6 +795,8 @@ mod tests {
             stype: SecretType::Buffer,
             persistence: SecretPersistence::Ephemeral,
             length: ikm_value.len(),
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         let ikm = vault.secret_import(&ikm_value[..], attributes).unwrap();
 
@@ -577,13 +804,15 @@ mod tests {
             stype: SecretType::Buffer,
             persistence: SecretPersistence::Ephemeral,
             length: 24,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
 
-        let res = vault.hkdf_sha256(&salt, b"", Some(&ikm), vec![attributes]);
+        let res = vault.hkdf_sha256(salt.as_ref(), b"", Some(ikm.as_ref()), vec![attributes]);
         assert!(res.is_ok());
         let digest = res.unwrap();
         assert_eq!(digest.len(), 1);
-        let digest = vault.secret_export(&digest[0]).unwrap();
+        let digest = vault.secret_export(digest[0].as_ref()).unwrap();
         assert_eq!(
             hex::encode(digest.as_ref()),
             "921ab9f260544b71941dbac2ca2d42c417aa07b53e055a8f"
@@ -592,65 +821,96 @@ mod tests {
 
     #[test]
     fn ec_diffie_hellman_p256() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
         let attributes = SecretAttributes {
             stype: SecretType::P256,
             persistence: SecretPersistence::Ephemeral,
             length: P256_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         let sk_ctx_1 = vault.secret_generate(attributes).unwrap();
         let sk_ctx_2 = vault.secret_generate(attributes).unwrap();
-        let pk_1 = vault.secret_public_key_get(&sk_ctx_1).unwrap();
-        let pk_2 = vault.secret_public_key_get(&sk_ctx_2).unwrap();
+        let _pk_1 = vault.secret_public_key_get(sk_ctx_1.as_ref()).unwrap();
+        let pk_2 = vault.secret_public_key_get(sk_ctx_2.as_ref()).unwrap();
 
-        let res = vault.ec_diffie_hellman(&sk_ctx_1, pk_2.as_ref());
+        let res = vault.ec_diffie_hellman(sk_ctx_1.as_ref(), pk_2.as_ref());
         assert!(res.is_ok());
-        let ss = res.unwrap();
+        let _ss = res.unwrap();
         // TODO: Check result against test vector
     }
 
     #[test]
     fn ec_diffie_hellman_curve25519() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
         let attributes = SecretAttributes {
             stype: SecretType::Curve25519,
             persistence: SecretPersistence::Ephemeral,
             length: CURVE25519_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
-        let sk_ctx_1 = vault.secret_generate(attributes).unwrap();
-        let sk_ctx_2 = vault.secret_generate(attributes).unwrap();
-        let pk_1 = vault.secret_public_key_get(&sk_ctx_1).unwrap();
-        let pk_2 = vault.secret_public_key_get(&sk_ctx_2).unwrap();
+        let sk_ctx_1 = vault
+            .secret_import(
+                &hex::decode("889607adbd3f237f1125b954a7f35a3d8c49b72eb18b14abaf29abca9237ba72")
+                    .unwrap(),
+                attributes,
+            )
+            .unwrap();
+        let pk_2 = hex::decode("f4dc26c1fd3c7f14123d78a28cbce62414521fba14cf700e5877171477b3e25f")
+            .unwrap();
 
-        let res = vault.ec_diffie_hellman(&sk_ctx_1, pk_2.as_ref());
-        assert!(res.is_ok());
-        let ss = res.unwrap();
-        // TODO: Check result against test vector
+        // the shared secret can't be exported, check a key derived from it
+        let ss = vault.ec_diffie_hellman(sk_ctx_1.as_ref(), &pk_2).unwrap();
+        let salt = vault
+            .secret_import(
+                &[0u8; 32],
+                SecretAttributes {
+                    stype: SecretType::Buffer,
+                    length: 32,
+                    ..attributes
+                },
+            )
+            .unwrap();
+        let output = SecretAttributes {
+            stype: SecretType::Buffer,
+            length: 32,
+            ..attributes
+        };
+        let derived = vault
+            .hkdf_sha256(salt.as_ref(), b"", Some(ss.as_ref()), vec![output])
+            .unwrap();
+        assert_eq!(
+            hex::encode(vault.secret_export(derived[0].as_ref()).unwrap().as_ref()),
+            "0b0d832af6c8d0ba9507f241a21f18c1393f0a6e8c3f610c9a86ec478440daea"
+        );
     }
 
     #[test]
     fn ec_diffie_hellman_different_keys() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
         let mut attributes = SecretAttributes {
             stype: SecretType::Curve25519,
             persistence: SecretPersistence::Ephemeral,
             length: CURVE25519_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
         let sk_ctx_1 = vault.secret_generate(attributes).unwrap();
-        let pk_1 = vault.secret_public_key_get(&sk_ctx_1).unwrap();
+        let pk_1 = vault.secret_public_key_get(sk_ctx_1.as_ref()).unwrap();
         attributes.stype = SecretType::P256;
         let sk_ctx_2 = vault.secret_generate(attributes).unwrap();
-        let pk_2 = vault.secret_public_key_get(&sk_ctx_2).unwrap();
+        let pk_2 = vault.secret_public_key_get(sk_ctx_2.as_ref()).unwrap();
 
-        let res = vault.ec_diffie_hellman(&sk_ctx_1, pk_2.as_ref());
+        let res = vault.ec_diffie_hellman(sk_ctx_1.as_ref(), pk_2.as_ref());
         assert!(res.is_err());
-        let res = vault.ec_diffie_hellman(&sk_ctx_2, pk_1.as_ref());
+        let res = vault.ec_diffie_hellman(sk_ctx_2.as_ref(), pk_1.as_ref());
         assert!(res.is_err());
     }
 
     #[test]
     fn encryption() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
         let message = b"Ockam Test Message";
         let nonce = b"TestingNonce";
         let aad = b"Extra payload data";
@@ -658,38 +918,504 @@ mod tests {
             stype: SecretType::Aes,
             persistence: SecretPersistence::Ephemeral,
             length: AES128_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
         };
 
         let ctx = &vault.secret_generate(attributes).unwrap();
-        let res = vault.aead_aes_gcm_encrypt(ctx, message.as_ref(), nonce.as_ref(), aad.as_ref());
+        let res = vault.aead_aes_gcm_encrypt(
+            ctx.as_ref(),
+            message.as_ref(),
+            nonce.as_ref(),
+            aad.as_ref(),
+        );
         assert!(res.is_ok());
         let mut ciphertext = res.unwrap();
-        let res =
-            vault.aead_aes_gcm_decrypt(ctx, ciphertext.as_slice(), nonce.as_ref(), aad.as_ref());
+        let res = vault.aead_aes_gcm_decrypt(
+            ctx.as_ref(),
+            ciphertext.as_slice(),
+            nonce.as_ref(),
+            aad.as_ref(),
+        );
         assert!(res.is_ok());
         let plaintext = res.unwrap();
         assert_eq!(plaintext, message.to_vec());
         ciphertext[0] ^= ciphertext[1];
-        let res =
-            vault.aead_aes_gcm_decrypt(ctx, ciphertext.as_slice(), nonce.as_ref(), aad.as_ref());
+        let res = vault.aead_aes_gcm_decrypt(
+            ctx.as_ref(),
+            ciphertext.as_slice(),
+            nonce.as_ref(),
+            aad.as_ref(),
+        );
         assert!(res.is_err());
     }
 
+    #[test]
+    fn chacha20_poly1305() {
+        // RFC 8439 section 2.8.2
+        let vault = DefaultVault::default();
+        let key = hex::decode("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")
+            .unwrap();
+        let nonce = hex::decode("070000004041424344454647").unwrap();
+        let aad = hex::decode("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let attributes = SecretAttributes {
+            stype: SecretType::ChaCha20Poly1305,
+            persistence: SecretPersistence::Ephemeral,
+            length: CHACHA20POLY1305_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
+        };
+        let ctx = &vault.secret_import(&key, attributes).unwrap();
+
+        let mut ciphertext = vault
+            .aead_chacha20_poly1305_encrypt(ctx.as_ref(), plaintext.as_ref(), &nonce, &aad)
+            .unwrap();
+        assert_eq!(
+            hex::encode(&ciphertext),
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691"
+        );
+        let decrypted = vault
+            .aead_chacha20_poly1305_decrypt(ctx.as_ref(), &ciphertext, &nonce, &aad)
+            .unwrap();
+        assert_eq!(decrypted, plaintext.to_vec());
+
+        ciphertext[0] ^= 1;
+        assert!(vault
+            .aead_chacha20_poly1305_decrypt(ctx.as_ref(), &ciphertext, &nonce, &aad)
+            .is_err());
+        assert!(vault
+            .aead_chacha20_poly1305_encrypt(ctx.as_ref(), plaintext.as_ref(), &nonce[1..], &aad)
+            .is_err());
+
+        let aes = &vault
+            .secret_generate(SecretAttributes {
+                stype: SecretType::Aes,
+                persistence: SecretPersistence::Ephemeral,
+                length: AES256_SECRET_LENGTH,
+                exportable: true,
+                usage: SecretUsage::ALL,
+            })
+            .unwrap();
+        assert!(vault
+            .aead_chacha20_poly1305_encrypt(aes.as_ref(), plaintext.as_ref(), &nonce, &aad)
+            .is_err());
+        assert!(vault
+            .aead_aes_gcm_encrypt(ctx.as_ref(), plaintext.as_ref(), &nonce, &aad)
+            .is_err());
+    }
+
     #[test]
     fn sign() {
-        let mut vault = DefaultVault::default();
+        let vault = DefaultVault::default();
         let secret = vault
             .secret_generate(SecretAttributes {
                 persistence: SecretPersistence::Ephemeral,
                 stype: SecretType::Curve25519,
                 length: CURVE25519_SECRET_LENGTH,
+                exportable: true,
+                usage: SecretUsage::ALL,
             })
             .unwrap();
-        let res = vault.sign(&secret, b"hello world!");
+        let res = vault.sign(secret.as_ref(), b"hello world!");
         assert!(res.is_ok());
-        let pubkey = vault.secret_public_key_get(&secret).unwrap();
+        let pubkey = vault.secret_public_key_get(secret.as_ref()).unwrap();
         let signature = res.unwrap();
-        let res = vault.verify(&signature, pubkey.as_ref(), b"hello world!");
+        let res = vault.verify(
+            &signature,
+            pubkey.as_ref(),
+            SecretType::Curve25519,
+            b"hello world!",
+        );
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn sign_p256_rfc_6979_vectors() {
+        // RFC 6979 A.2.5, P-256 with SHA-256
+        let vault = DefaultVault::default();
+        let secret = vault
+            .secret_import(
+                &hex::decode("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721")
+                    .unwrap(),
+                SecretAttributes {
+                    persistence: SecretPersistence::Ephemeral,
+                    stype: SecretType::P256,
+                    length: P256_SECRET_LENGTH,
+                    exportable: true,
+                    usage: SecretUsage::ALL,
+                },
+            )
+            .unwrap();
+        let pubkey = vault.secret_public_key_get(secret.as_ref()).unwrap();
+        assert_eq!(
+            hex::encode(pubkey.as_ref()),
+            "0460fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6\
+             7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299"
+        );
+
+        let vectors = [
+            (
+                "sample",
+                "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716\
+                 f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8",
+            ),
+            (
+                "test",
+                "f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367\
+                 019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083",
+            ),
+        ];
+        for (msg, expected) in vectors.iter() {
+            let signature = vault.sign(secret.as_ref(), msg.as_bytes()).unwrap();
+            assert_eq!(hex::encode(&signature[..]), *expected);
+            assert!(vault
+                .verify(
+                    &signature,
+                    pubkey.as_ref(),
+                    SecretType::P256,
+                    msg.as_bytes()
+                )
+                .is_ok());
+        }
+
+        // r and s must both be in [1, n - 1]
+        let signature = vault.sign(secret.as_ref(), b"sample").unwrap();
+        let mut zero_r = signature;
+        zero_r[..32].copy_from_slice(&[0u8; 32]);
+        let mut large_s = signature;
+        large_s[32..].copy_from_slice(&[0xffu8; 32]);
+        for forged in [zero_r, large_s].iter() {
+            assert!(vault
+                .verify(forged, pubkey.as_ref(), SecretType::P256, b"sample")
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn sign_p256() {
+        let vault = DefaultVault::default();
+        let secret = vault
+            .secret_generate(SecretAttributes {
+                persistence: SecretPersistence::Ephemeral,
+                stype: SecretType::P256,
+                length: P256_SECRET_LENGTH,
+                exportable: true,
+                usage: SecretUsage::ALL,
+            })
+            .unwrap();
+        let signature = vault.sign(secret.as_ref(), b"hello world!").unwrap();
+        let pubkey = vault.secret_public_key_get(secret.as_ref()).unwrap();
+        assert!(vault
+            .verify(
+                &signature,
+                pubkey.as_ref(),
+                SecretType::P256,
+                b"hello world!"
+            )
+            .is_ok());
+        assert!(vault
+            .verify(
+                &signature,
+                pubkey.as_ref(),
+                SecretType::P256,
+                b"hello world?"
+            )
+            .is_err());
+        assert!(vault
+            .verify(
+                &signature,
+                &pubkey.as_ref()[..33],
+                SecretType::P256,
+                b"hello world!"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn ed25519_rfc8032_vectors() {
+        // RFC 8032 section 7.1, tests 1 to 3
+        let vectors = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+            (
+                "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+                "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+                "af82",
+                "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+            ),
+        ];
+        let vault = DefaultVault::default();
+        let attributes = SecretAttributes {
+            stype: SecretType::Ed25519,
+            persistence: SecretPersistence::Ephemeral,
+            length: ED25519_SECRET_LENGTH,
+            exportable: true,
+            usage: SecretUsage::ALL,
+        };
+        for (secret, public, message, signature) in vectors.iter() {
+            let secret = vault
+                .secret_import(&hex::decode(secret).unwrap(), attributes)
+                .unwrap();
+            let public = hex::decode(public).unwrap();
+            let message = hex::decode(message).unwrap();
+            let pubkey = vault.secret_public_key_get(secret.as_ref()).unwrap();
+            assert_eq!(pubkey.as_ref(), public.as_slice());
+
+            let sig = vault.sign(secret.as_ref(), &message).unwrap();
+            assert_eq!(hex::encode(sig.as_ref()), *signature);
+            assert!(vault
+                .verify(&sig, &public, SecretType::Ed25519, &message)
+                .is_ok());
+
+            let mut forged = sig;
+            forged[0] ^= 1;
+            assert!(vault
+                .verify(&forged, &public, SecretType::Ed25519, &message)
+                .is_err());
+            // the same bytes as a Curve25519 key don't verify
+            assert!(vault
+                .verify(&sig, &public, SecretType::Curve25519, &message)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn ed25519_generate() {
+        let vault = DefaultVault::default();
+        let secret = vault
+            .secret_generate(SecretAttributes {
+                stype: SecretType::Ed25519,
+                persistence: SecretPersistence::Ephemeral,
+                length: ED25519_SECRET_LENGTH,
+                exportable: true,
+                usage: SecretUsage::ALL,
+            })
+            .unwrap();
+        assert_eq!(
+            vault.secret_export(secret.as_ref()).unwrap().as_ref().len(),
+            ED25519_SECRET_LENGTH
+        );
+        let pubkey = vault.secret_public_key_get(secret.as_ref()).unwrap();
+        let sig = vault.sign(secret.as_ref(), b"hello world!").unwrap();
+        assert!(vault
+            .verify(&sig, pubkey.as_ref(), SecretType::Ed25519, b"hello world!")
+            .is_ok());
+        assert!(vault
+            .ec_diffie_hellman(secret.as_ref(), pubkey.as_ref())
+            .is_err());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let vault = std::sync::Arc::new(DefaultVault::default());
+        let attributes = SecretAttributes {
+            stype: SecretType::Aes,
+            persistence: SecretPersistence::Ephemeral,
+            length: AES128_SECRET_LENGTH,
+            exportable: false,
+            usage: SecretUsage::AEAD,
+        };
+        let threads: Vec<_> = (0..4u8)
+            .map(|i| {
+                let vault = vault.clone();
+                std::thread::spawn(move || {
+                    let key = vault.secret_generate(attributes).unwrap();
+                    for n in 0..50u8 {
+                        let nonce = [n; 12];
+                        let ciphertext = vault
+                            .aead_aes_gcm_encrypt(key.as_ref(), &[i; 32], &nonce, &[])
+                            .unwrap();
+                        let plaintext = vault
+                            .aead_aes_gcm_decrypt(key.as_ref(), &ciphertext, &nonce, &[])
+                            .unwrap();
+                        assert_eq!(plaintext, [i; 32]);
+                    }
+                    vault.secret_destroy(key).unwrap();
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert_eq!(vault.next_id.load(Ordering::Relaxed), 4);
+        assert!(vault.entries.read().unwrap().is_empty());
+    }
+
+    fn attributes(stype: SecretType, length: usize) -> SecretAttributes {
+        SecretAttributes {
+            stype,
+            persistence: SecretPersistence::Ephemeral,
+            length,
+            exportable: true,
+            usage: SecretUsage::ALL,
+        }
+    }
+
+    fn assert_vault_error(
+        error: ockam_common::error::OckamError,
+        expected: ockam_vault::error::Error,
+    ) {
+        assert_eq!(error.domain(), ockam_vault::error::Error::ERROR_DOMAIN);
+        assert_eq!(error.code(), expected as u32);
+    }
+
+    #[test]
+    fn secret_import_checks_p256_scalar() {
+        let vault = DefaultVault::default();
+        let atts = attributes(SecretType::P256, P256_SECRET_LENGTH);
+        let mut order =
+            hex::decode("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551")
+                .unwrap();
+
+        let error = vault.secret_import(&order, atts).unwrap_err();
+        assert_vault_error(error, ockam_vault::error::Error::InvalidSecretValue);
+        let error = vault.secret_import(&[0u8; 32], atts).unwrap_err();
+        assert_vault_error(error, ockam_vault::error::Error::InvalidSecretValue);
+        let error = vault.secret_import(&[0xffu8; 32], atts).unwrap_err();
+        assert_vault_error(error, ockam_vault::error::Error::InvalidSecretValue);
+
+        order[31] -= 1;
+        let key = vault.secret_import(&order, atts).unwrap();
+        assert!(vault.secret_public_key_get(key.as_ref()).is_ok());
+        let error = vault.secret_import(&order[1..], atts).unwrap_err();
+        assert_vault_error(error, ockam_vault::error::Error::InvalidSecretLength);
+    }
+
+    #[test]
+    fn secret_import_clamps_curve25519() {
+        let vault = DefaultVault::default();
+        let atts = attributes(SecretType::Curve25519, CURVE25519_SECRET_LENGTH);
+        let key = vault.secret_import(&[0xffu8; 32], atts).unwrap();
+        let exported = vault.secret_export(key.as_ref()).unwrap();
+        let mut clamped = [0xffu8; 32];
+        clamped[0] = 0xf8;
+        clamped[31] = 0x7f;
+        assert_eq!(exported.as_ref(), &clamped);
+
+        // clamping doesn't change the key the peer sees
+        let unclamped = x25519_dalek::StaticSecret::from([0xffu8; 32]);
+        assert_eq!(
+            vault.secret_public_key_get(key.as_ref()).unwrap().as_ref(),
+            x25519_dalek::PublicKey::from(&unclamped).as_bytes()
+        );
+    }
+
+    #[test]
+    fn secret_lengths_follow_type() {
+        let vault = DefaultVault::default();
+        for length in &[0, 24, 31, 33, 64] {
+            let error = vault
+                .secret_generate(attributes(SecretType::Aes, *length))
+                .unwrap_err();
+            assert_vault_error(error, ockam_vault::error::Error::InvalidSecretLength);
+        }
+        let error = vault
+            .secret_generate(attributes(SecretType::Curve25519, 0))
+            .unwrap_err();
+        assert_vault_error(error, ockam_vault::error::Error::InvalidSecretLength);
+        let error = vault
+            .secret_import(
+                &[1u8; 16],
+                attributes(SecretType::Aes, AES256_SECRET_LENGTH),
+            )
+            .unwrap_err();
+        assert_vault_error(error, ockam_vault::error::Error::InvalidSecretLength);
+        assert!(vault
+            .secret_import(&[1u8; 5], attributes(SecretType::Buffer, 5))
+            .is_ok());
+    }
+
+    fn any_secret_type() -> impl proptest::strategy::Strategy<Value = SecretType> {
+        use proptest::prelude::*;
+        prop_oneof![
+            Just(SecretType::Buffer),
+            Just(SecretType::Aes),
+            Just(SecretType::Curve25519),
+            Just(SecretType::P256),
+            Just(SecretType::Ed25519),
+            Just(SecretType::ChaCha20Poly1305),
+        ]
+    }
+
+    /// What the attributes allow, worked out without the vault
+    fn expected_valid(stype: SecretType, length: usize, secret: &[u8]) -> bool {
+        let length_ok = match stype {
+            SecretType::Buffer => true,
+            SecretType::Aes => length == 16 || length == 32,
+            _ => length == 32,
+        };
+        length_ok
+            && secret.len() == length
+            && (!matches!(stype, SecretType::P256) || p256::SecretKey::from_slice(secret).is_ok())
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn secret_import_accepts_only_valid_keys(
+            stype in any_secret_type(),
+            length in proptest::prop_oneof![0usize..40, proptest::strategy::Just(32usize)],
+            secret in proptest::collection::vec(proptest::num::u8::ANY, 0..40),
+            // most random 32 byte strings are P-256 scalars, also try ones near the order
+            high in proptest::bool::ANY,
+        ) {
+            let mut secret = secret;
+            if high && !secret.is_empty() {
+                secret[0] = 0xff;
+            }
+            let vault = DefaultVault::default();
+            let atts = attributes(stype, length);
+            let valid = expected_valid(stype, length, &secret);
+            match vault.secret_import(&secret, atts) {
+                Ok(key) => {
+                    proptest::prop_assert!(valid);
+                    proptest::prop_assert_eq!(vault.secret_attributes_get(key.as_ref()).unwrap(), atts);
+                    let exported = vault.secret_export(key.as_ref()).unwrap();
+                    if matches!(stype, SecretType::Curve25519) {
+                        // clamping is idempotent
+                        let again = vault.secret_import(exported.as_ref(), atts).unwrap();
+                        proptest::prop_assert_eq!(vault.secret_export(again.as_ref()).unwrap(), exported);
+                    } else {
+                        proptest::prop_assert_eq!(exported.as_ref(), &secret[..]);
+                    }
+                    if matches!(stype, SecretType::Curve25519 | SecretType::P256 | SecretType::Ed25519) {
+                        proptest::prop_assert!(vault.secret_public_key_get(key.as_ref()).is_ok());
+                    }
+                }
+                Err(error) => {
+                    proptest::prop_assert!(!valid);
+                    proptest::prop_assert_eq!(error.domain(), ockam_vault::error::Error::ERROR_DOMAIN);
+                    proptest::prop_assert!(
+                        error.code() == ockam_vault::error::Error::InvalidSecretLength as u32
+                            || error.code() == ockam_vault::error::Error::InvalidSecretValue as u32
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn secret_generate_checks_length(stype in any_secret_type(), length in 0usize..40) {
+            let vault = DefaultVault::default();
+            let atts = attributes(stype, length);
+            match vault.secret_generate(atts) {
+                Ok(key) => {
+                    let exported = vault.secret_export(key.as_ref()).unwrap();
+                    proptest::prop_assert_eq!(exported.as_ref().len(), length);
+                    proptest::prop_assert!(expected_valid(stype, length, exported.as_ref()));
+                }
+                Err(error) => {
+                    proptest::prop_assert!(!expected_valid(stype, length, &vec![1u8; length]));
+                    proptest::prop_assert_eq!(error.code(), ockam_vault::error::Error::InvalidSecretLength as u32);
+                }
+            }
+        }
+    }
 }
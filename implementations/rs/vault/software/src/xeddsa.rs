@@ -88,7 +88,14 @@ impl XEddsaSigner for XSecretKey {
 impl XEddsaVerifier for XPublicKey {
     fn verify(&self, msg: &[u8], sig: &[u8; 64]) -> bool {
         let pt = MontgomeryPoint(self.to_bytes());
-        let pk = EPublicKey::from_bytes(&pt.to_edwards(0).unwrap().compress().to_bytes()).unwrap();
+        // not every u-coordinate is on the curve
+        let pk = match pt
+            .to_edwards(0)
+            .and_then(|p| EPublicKey::from_bytes(&p.compress().to_bytes()).ok())
+        {
+            Some(pk) => pk,
+            None => return false,
+        };
         let sig = Signature::new(*sig);
         pk.verify(msg, &sig).is_ok()
     }
@@ -0,0 +1,222 @@
+use crate::types::*;
+use crate::{
+    AsymmetricVault, HashVault, RandomVault, Secret, SecretVault, SignerVault, SymmetricVault,
+};
+use async_trait::async_trait;
+use ockam_common::error::OckamResult;
+
+/// Async counterpart of [`SecretVault`](crate::SecretVault)
+#[async_trait]
+pub trait AsyncSecretVault: Send + Sync {
+    /// Create a new secret key
+    async fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>>;
+    /// Import a secret key into the vault
+    async fn secret_import(
+        &self,
+        secret: &[u8],
+        attributes: SecretAttributes,
+    ) -> OckamResult<Box<dyn Secret>>;
+    /// Export a secret key from the vault
+    async fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey>;
+    /// Get the attributes for a secret key
+    async fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes>;
+    /// Return the associated public key given the secret key
+    async fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey>;
+    /// Remove a secret key from the vault
+    async fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()>;
+}
+
+/// Async counterpart of [`SignerVault`](crate::SignerVault)
+#[async_trait]
+pub trait AsyncSignerVault: Send + Sync {
+    /// Generate a signature. Curve25519 keys sign with XEdDSA, Ed25519 keys with RFC 8032
+    async fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]>;
+}
+
+/// Async counterpart of [`SymmetricVault`](crate::SymmetricVault)
+#[async_trait]
+pub trait AsyncSymmetricVault: Send + Sync {
+    /// Encrypt a payload using AES-GCM
+    async fn aead_aes_gcm_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>>;
+    /// Decrypt a payload using AES-GCM
+    async fn aead_aes_gcm_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>>;
+    /// Encrypt a payload using ChaCha20-Poly1305
+    async fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>>;
+    /// Decrypt a payload using ChaCha20-Poly1305
+    async fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>>;
+}
+
+/// Async counterpart of [`AsymmetricVault`](crate::AsymmetricVault)
+#[async_trait]
+pub trait AsyncAsymmetricVault: Send + Sync {
+    /// Compute Elliptic-Curve Diffie-Hellman using this secret key
+    /// and the specified uncompressed public key
+    async fn ec_diffie_hellman(
+        &self,
+        context: &dyn Secret,
+        peer_public_key: &[u8],
+    ) -> OckamResult<Box<dyn Secret>>;
+}
+
+/// Async counterpart of [`HashVault`](crate::HashVault)
+#[async_trait]
+pub trait AsyncHashVault: Send + Sync {
+    /// Compute the SHA-256 digest given input `data`
+    async fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]>;
+    /// Compute the HKDF-SHA256 using the specified salt and input key material
+    /// and return the output key material of the specified length
+    async fn hkdf_sha256(
+        &self,
+        salt: &dyn Secret,
+        info: &[u8],
+        ikm: Option<&dyn Secret>,
+        output_attributes: Vec<SecretAttributes>,
+    ) -> OckamResult<Vec<Box<dyn Secret>>>;
+}
+
+/// Async counterpart of [`RandomVault`](crate::RandomVault)
+#[async_trait]
+pub trait AsyncRandomVault: Send + Sync {
+    /// Fill `buffer` with random bytes from the vault's entropy source
+    async fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()>;
+}
+
+#[async_trait]
+impl<V: SecretVault + Send + Sync + ?Sized> AsyncSecretVault for V {
+    async fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>> {
+        SecretVault::secret_generate(self, attributes)
+    }
+
+    async fn secret_import(
+        &self,
+        secret: &[u8],
+        attributes: SecretAttributes,
+    ) -> OckamResult<Box<dyn Secret>> {
+        SecretVault::secret_import(self, secret, attributes)
+    }
+
+    async fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey> {
+        SecretVault::secret_export(self, context)
+    }
+
+    async fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes> {
+        SecretVault::secret_attributes_get(self, context)
+    }
+
+    async fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey> {
+        SecretVault::secret_public_key_get(self, context)
+    }
+
+    async fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()> {
+        SecretVault::secret_destroy(self, context)
+    }
+}
+
+#[async_trait]
+impl<V: SignerVault + Send + Sync + ?Sized> AsyncSignerVault for V {
+    async fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]> {
+        SignerVault::sign(self, secret_key, data)
+    }
+}
+
+#[async_trait]
+impl<V: SymmetricVault + Send + Sync + ?Sized> AsyncSymmetricVault for V {
+    async fn aead_aes_gcm_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        SymmetricVault::aead_aes_gcm_encrypt(self, context, plaintext, nonce, aad)
+    }
+
+    async fn aead_aes_gcm_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        SymmetricVault::aead_aes_gcm_decrypt(self, context, cipher_text, nonce, aad)
+    }
+
+    async fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        SymmetricVault::aead_chacha20_poly1305_encrypt(self, context, plaintext, nonce, aad)
+    }
+
+    async fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>> {
+        SymmetricVault::aead_chacha20_poly1305_decrypt(self, context, cipher_text, nonce, aad)
+    }
+}
+
+#[async_trait]
+impl<V: AsymmetricVault + Send + Sync + ?Sized> AsyncAsymmetricVault for V {
+    async fn ec_diffie_hellman(
+        &self,
+        context: &dyn Secret,
+        peer_public_key: &[u8],
+    ) -> OckamResult<Box<dyn Secret>> {
+        AsymmetricVault::ec_diffie_hellman(self, context, peer_public_key)
+    }
+}
+
+#[async_trait]
+impl<V: HashVault + Send + Sync + ?Sized> AsyncHashVault for V {
+    async fn sha256(&self, data: &[u8]) -> OckamResult<[u8; 32]> {
+        HashVault::sha256(self, data)
+    }
+
+    async fn hkdf_sha256(
+        &self,
+        salt: &dyn Secret,
+        info: &[u8],
+        ikm: Option<&dyn Secret>,
+        output_attributes: Vec<SecretAttributes>,
+    ) -> OckamResult<Vec<Box<dyn Secret>>> {
+        HashVault::hkdf_sha256(self, salt, info, ikm, output_attributes)
+    }
+}
+
+#[async_trait]
+impl<V: RandomVault + Send + Sync + ?Sized> AsyncRandomVault for V {
+    async fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()> {
+        RandomVault::random_bytes_generate(self, buffer)
+    }
+}
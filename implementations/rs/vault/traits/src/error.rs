@@ -10,6 +10,12 @@ pub enum Error {
     UnknownSecretTypeValue,
     /// An unknown secret persistence value was supplied
     UnknownSecretPersistenceValue,
+    /// An unknown secret usage bit was supplied
+    UnknownSecretUsageValue,
+    /// The secret length doesn't match the attributes or isn't allowed for the secret type
+    InvalidSecretLength,
+    /// The secret isn't a valid key of its type
+    InvalidSecretValue,
 }
 
 impl Error {
@@ -19,6 +19,9 @@
 //!
 //! Vault represents a location where cryptographic keys live such
 //! as secure enclaves, TPMs, HSMs, Keyrings, files, memory, etc.
+//!
+//! Vault functions take `&self`. Vaults synchronize internally, so one vault can be
+//! shared between threads as an `Arc` and used by several of them at once.
 
 #![cfg_attr(feature = "nightly", feature(doc_cfg))]
 
@@ -38,6 +41,9 @@ use zeroize::Zeroize;
 /// Internal macros
 #[macro_use]
 mod macros;
+/// Async vault traits for slow or remote backends, every vault implementing the
+/// sync traits implements them as well
+pub mod asynchronous;
 /// Represents the errors that occur within a vault
 pub mod error;
 /// The various enumerations of options
@@ -55,50 +61,71 @@ downcast!(dyn Secret);
 /// Vault trait with secret management functionality
 pub trait SecretVault: Zeroize {
     /// Create a new secret key
-    fn secret_generate(&mut self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>>;
+    fn secret_generate(&self, attributes: SecretAttributes) -> OckamResult<Box<dyn Secret>>;
     /// Import a secret key into the vault
     fn secret_import(
-        &mut self,
+        &self,
         secret: &[u8],
         attributes: SecretAttributes,
     ) -> OckamResult<Box<dyn Secret>>;
     /// Export a secret key from the vault
-    fn secret_export(&mut self, context: &Box<dyn Secret>) -> OckamResult<SecretKey>;
+    fn secret_export(&self, context: &dyn Secret) -> OckamResult<SecretKey>;
     /// Get the attributes for a secret key
-    fn secret_attributes_get(&mut self, context: &Box<dyn Secret>)
-        -> OckamResult<SecretAttributes>;
+    fn secret_attributes_get(&self, context: &dyn Secret) -> OckamResult<SecretAttributes>;
     /// Return the associated public key given the secret key
-    fn secret_public_key_get(&mut self, context: &Box<dyn Secret>) -> OckamResult<PublicKey>;
+    fn secret_public_key_get(&self, context: &dyn Secret) -> OckamResult<PublicKey>;
     /// Remove a secret key from the vault
-    fn secret_destroy(&mut self, context: Box<dyn Secret>) -> OckamResult<()>;
+    fn secret_destroy(&self, context: Box<dyn Secret>) -> OckamResult<()>;
 }
 
 /// Trait with sign functionality
 pub trait SignerVault: Zeroize {
-    /// Generate a signature
-    fn sign(&mut self, secret_key: &Box<dyn Secret>, data: &[u8]) -> OckamResult<[u8; 64]>;
+    /// Generate a signature. Curve25519 keys sign with XEdDSA, Ed25519 keys with RFC 8032
+    fn sign(&self, secret_key: &dyn Secret, data: &[u8]) -> OckamResult<[u8; 64]>;
 }
 
 /// Trait with verify functionality
 pub trait VerifierVault: Zeroize {
-    /// Verify a signature
-    fn verify(&mut self, signature: &[u8; 64], public_key: &[u8], data: &[u8]) -> OckamResult<()>;
+    /// Verify a signature made by the key of type `public_key_type` that `public_key` belongs to
+    fn verify(
+        &self,
+        signature: &[u8; 64],
+        public_key: &[u8],
+        public_key_type: SecretType,
+        data: &[u8],
+    ) -> OckamResult<()>;
 }
 
 /// Trait with symmetric encryption
 pub trait SymmetricVault: Zeroize {
     /// Encrypt a payload using AES-GCM
     fn aead_aes_gcm_encrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         plaintext: &[u8],
         nonce: &[u8],
         aad: &[u8],
     ) -> OckamResult<Vec<u8>>;
     /// Decrypt a payload using AES-GCM
     fn aead_aes_gcm_decrypt(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
+        cipher_text: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>>;
+    /// Encrypt a payload using ChaCha20-Poly1305
+    fn aead_chacha20_poly1305_encrypt(
+        &self,
+        context: &dyn Secret,
+        plaintext: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> OckamResult<Vec<u8>>;
+    /// Decrypt a payload using ChaCha20-Poly1305
+    fn aead_chacha20_poly1305_decrypt(
+        &self,
+        context: &dyn Secret,
         cipher_text: &[u8],
         nonce: &[u8],
         aad: &[u8],
@@ -110,8 +137,8 @@ pub trait AsymmetricVault: Zeroize {
     /// Compute Elliptic-Curve Diffie-Hellman using this secret key
     /// and the specified uncompressed public key
     fn ec_diffie_hellman(
-        &mut self,
-        context: &Box<dyn Secret>,
+        &self,
+        context: &dyn Secret,
         peer_public_key: &[u8],
     ) -> OckamResult<Box<dyn Secret>>;
 }
@@ -123,19 +150,40 @@ pub trait HashVault: Zeroize {
     /// Compute the HKDF-SHA256 using the specified salt and input key material
     /// and return the output key material of the specified length
     fn hkdf_sha256(
-        &mut self,
-        salt: &Box<dyn Secret>,
+        &self,
+        salt: &dyn Secret,
         info: &[u8],
-        ikm: Option<&Box<dyn Secret>>,
+        ikm: Option<&dyn Secret>,
         output_attributes: Vec<SecretAttributes>,
     ) -> OckamResult<Vec<Box<dyn Secret>>>;
 }
 
+/// Vault with a source of randomness
+pub trait RandomVault: Zeroize {
+    /// Fill `buffer` with random bytes from the vault's entropy source
+    fn random_bytes_generate(&self, buffer: &mut [u8]) -> OckamResult<()>;
+}
+
 /// Trait for vault with persistence capabilities
 pub trait PersistentVault: Zeroize {
     /// Returns some String id that can be then used to retrieve secret from storage
-    fn get_persistence_id(&self, secret: &Box<dyn Secret>) -> OckamResult<String>;
+    fn get_persistence_id(&self, secret: &dyn Secret) -> OckamResult<String>;
 
     /// Returns persistent secret using id
     fn get_persistent_secret(&self, persistence_id: &str) -> OckamResult<Box<dyn Secret>>;
+
+    /// Returns persistent secret using its label
+    fn get_persistent_secret_by_label(&self, label: &str) -> OckamResult<Box<dyn Secret>>;
+
+    /// Returns label, creation time and tags of a persistent secret
+    fn get_secret_metadata(&self, secret: &dyn Secret) -> OckamResult<SecretMetadata>;
+
+    /// Sets or removes the label of a persistent secret, labels are unique within a vault
+    fn set_secret_label(&self, secret: &dyn Secret, label: Option<&str>) -> OckamResult<()>;
+
+    /// Replaces the tags of a persistent secret
+    fn set_secret_tags(&self, secret: &dyn Secret, tags: Vec<String>) -> OckamResult<()>;
+
+    /// Returns every persistent secret in the vault with its attributes, public key and metadata
+    fn list_persistent_secrets(&self) -> OckamResult<Vec<PersistentSecretInfo>>;
 }
@@ -10,10 +10,22 @@ pub const CURVE25519_PUBLIC_LENGTH: usize = 32;
 pub const P256_SECRET_LENGTH: usize = 32;
 /// P256 public key length
 pub const P256_PUBLIC_LENGTH: usize = 65;
+/// Ed25519 private key length
+pub const ED25519_SECRET_LENGTH: usize = 32;
+/// Ed25519 public key length
+pub const ED25519_PUBLIC_LENGTH: usize = 32;
 /// AES256 private key length
 pub const AES256_SECRET_LENGTH: usize = 32;
 /// AES128 private key length
 pub const AES128_SECRET_LENGTH: usize = 16;
+/// ChaCha20-Poly1305 key length
+pub const CHACHA20POLY1305_SECRET_LENGTH: usize = 32;
+
+/// Order of the NIST P-256 group, big-endian. Private keys are scalars in [1, n).
+const P256_ORDER: [u8; P256_SECRET_LENGTH] = [
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xbc, 0xe6, 0xfa, 0xad, 0xa7, 0x17, 0x9e, 0x84, 0xf3, 0xb9, 0xca, 0xc2, 0xfc, 0x63, 0x25, 0x51,
+];
 
 cfg_if! {
     if #[cfg(feature = "heapless")] {
@@ -82,6 +94,10 @@ pub enum SecretType {
     Curve25519,
     /// NIST P-256 (secp256r1, prime256v1) secret key
     P256,
+    /// Ed25519 signing key, the RFC 8032 32 byte seed
+    Ed25519,
+    /// ChaCha20-Poly1305 key
+    ChaCha20Poly1305,
 }
 
 impl SecretType {
@@ -92,6 +108,8 @@ impl SecretType {
             SecretType::Aes => 1,
             SecretType::Curve25519 => 2,
             SecretType::P256 => 3,
+            SecretType::Ed25519 => 4,
+            SecretType::ChaCha20Poly1305 => 5,
         }
     }
 
@@ -102,6 +120,8 @@ impl SecretType {
             1 => Ok(SecretType::Aes),
             2 => Ok(SecretType::Curve25519),
             3 => Ok(SecretType::P256),
+            4 => Ok(SecretType::Ed25519),
+            5 => Ok(SecretType::ChaCha20Poly1305),
             _ => Err(Error::UnknownSecretTypeValue.into()),
         }
     }
@@ -177,6 +197,51 @@ try_from_int_impl!(SecretPersistence, u32);
 try_from_int_impl!(SecretPersistence, u64);
 try_from_int_impl!(SecretPersistence, u128);
 
+/// Operations a secret may be used for, combine them with `|`
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub struct SecretUsage(u8);
+
+impl SecretUsage {
+    /// No operation
+    pub const NONE: Self = Self(0);
+    /// Signing
+    pub const SIGN: Self = Self(1);
+    /// Elliptic-Curve Diffie-Hellman
+    pub const ECDH: Self = Self(1 << 1);
+    /// AEAD encryption and decryption
+    pub const AEAD: Self = Self(1 << 2);
+    /// Salt or input key material of HKDF
+    pub const HKDF: Self = Self(1 << 3);
+    /// Every operation
+    pub const ALL: Self = Self(0b1111);
+
+    /// True if every operation in `other` is allowed
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Convert to a bit mask
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// Try to convert from a bit mask, unknown bits are an error
+    pub fn from_bits(bits: u8) -> OckamResult<Self> {
+        if bits & !Self::ALL.0 != 0 {
+            return Err(Error::UnknownSecretUsageValue.into());
+        }
+        Ok(Self(bits))
+    }
+}
+
+impl std::ops::BitOr for SecretUsage {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 /// Attributes for a specific vault secret
 #[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
 pub struct SecretAttributes {
@@ -186,19 +251,65 @@ pub struct SecretAttributes {
     pub persistence: SecretPersistence,
     /// The purpose of the secret key
     pub length: usize,
+    /// Whether `SecretVault::secret_export` may return the key
+    pub exportable: bool,
+    /// The operations the key may be used for
+    pub usage: SecretUsage,
 }
 
 impl SecretAttributes {
     /// Convert attributes to byte values
-    pub fn to_bytes(&self) -> [u8; 6] {
-        let mut output = [0u8; 6];
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut output = [0u8; 8];
         output[..2].copy_from_slice((self.stype.to_usize() as u16).to_be_bytes().as_ref());
         output[2..4].copy_from_slice((self.persistence.to_usize() as u16).to_be_bytes().as_ref());
-        output[4..].copy_from_slice((self.length as u16).to_be_bytes().as_ref());
+        output[4..6].copy_from_slice((self.length as u16).to_be_bytes().as_ref());
+        output[6] = self.exportable as u8;
+        output[7] = self.usage.bits();
         output
     }
 }
 
+impl SecretAttributes {
+    /// Check that `length` is allowed for the secret type. Buffers may have any length,
+    /// AES keys are 16 or 32 bytes and the other types have a fixed length.
+    pub fn validate(&self) -> OckamResult<()> {
+        let valid = match self.stype {
+            SecretType::Buffer => true,
+            SecretType::Aes => {
+                self.length == AES128_SECRET_LENGTH || self.length == AES256_SECRET_LENGTH
+            }
+            SecretType::Curve25519 => self.length == CURVE25519_SECRET_LENGTH,
+            SecretType::P256 => self.length == P256_SECRET_LENGTH,
+            SecretType::Ed25519 => self.length == ED25519_SECRET_LENGTH,
+            SecretType::ChaCha20Poly1305 => self.length == CHACHA20POLY1305_SECRET_LENGTH,
+        };
+        if !valid {
+            return Err(Error::InvalidSecretLength.into());
+        }
+        Ok(())
+    }
+
+    /// Check that `secret` is a key these attributes describe. Its length has to be
+    /// `length` and a P-256 key has to be a scalar in [1, n). Every 32 byte string is
+    /// a Curve25519 key once it is clamped, and an Ed25519 seed.
+    pub fn validate_secret(&self, secret: &[u8]) -> OckamResult<()> {
+        self.validate()?;
+        if secret.len() != self.length {
+            return Err(Error::InvalidSecretLength.into());
+        }
+        if let SecretType::P256 = self.stype {
+            // big-endian, so comparing the bytes compares the numbers
+            if secret.iter().all(|b| *b == 0) || secret >= &P256_ORDER[..] {
+                return Err(Error::InvalidSecretValue.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Attributes written before secrets had a usage policy, they are exportable and
+/// allow every operation
 impl std::convert::TryFrom<[u8; 6]> for SecretAttributes {
     type Error = OckamError;
 
@@ -211,8 +322,49 @@ impl std::convert::TryFrom<[u8; 6]> for SecretAttributes {
             stype: xtype,
             persistence,
             length: len,
+            exportable: true,
+            usage: SecretUsage::ALL,
         })
     }
 }
 
+impl std::convert::TryFrom<[u8; 8]> for SecretAttributes {
+    type Error = OckamError;
+
+    fn try_from(bytes: [u8; 8]) -> Result<Self, Self::Error> {
+        let mut attributes = Self::try_from(*array_ref![bytes, 0, 6])?;
+        attributes.exportable = match bytes[6] {
+            0 => false,
+            1 => true,
+            _ => return Err(Error::UnknownSecretUsageValue.into()),
+        };
+        attributes.usage = SecretUsage::from_bits(bytes[7])?;
+        Ok(attributes)
+    }
+}
+
+/// Descriptive data a persistent vault keeps alongside a secret
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SecretMetadata {
+    /// Human-readable name, unique within a vault
+    pub label: Option<String>,
+    /// When the secret was created, in seconds since the UNIX epoch
+    pub created_at: u64,
+    /// Free-form tags
+    pub tags: Vec<String>,
+}
+
+/// A persistent secret as returned by listing a vault
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PersistentSecretInfo {
+    /// Id to retrieve the secret with
+    pub persistence_id: String,
+    /// Attributes of the secret
+    pub attributes: SecretAttributes,
+    /// Public key, for secret types that have one
+    pub public_key: Option<PublicKey>,
+    /// Label, creation time and tags
+    pub metadata: SecretMetadata,
+}
+
 zdrop_impl!(SecretKey);